@@ -1,50 +1,87 @@
-use crate::{clock::Hvc, transaction::Transaction};
+use crate::{
+    amount::Amount,
+    clock::Hvc,
+    error::StateError,
+    id::{AccountId, TxId},
+    transaction::Transaction,
+};
 use crypto::hash::Hash;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::RwLock;
 use std::time::{Duration, SystemTime};
 
 /// Basic representation of an account
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Account {
-    pub id: Hash,
-    pub balance: u128,
+    pub id: AccountId,
+    pub balance: Amount,
     pub hvc: Hvc,
-    pub last_tx_id: Hash,
+    pub last_tx_id: TxId,
     pub created: Duration,
+    /// Sequence number of the latest transaction sent from the account
+    pub sequence: u64,
 }
 
 impl Account {
     /// Create account
-    pub fn create(account_id: &Hash, tx_id: &Hash) -> Self {
+    pub fn create(account_id: &AccountId, tx_id: &TxId) -> Self {
         Self {
             id: *account_id,
-            balance: 0,
+            balance: Amount::ZERO,
             hvc: Hvc::new(),
             last_tx_id: *tx_id,
             created: SystemTime::now()
                 .duration_since(SystemTime::UNIX_EPOCH)
                 .unwrap(),
+            sequence: 0,
         }
     }
 
-    /// Increase account balance
-    pub fn increase_balance(&mut self, balance: u128) -> &mut Self {
-        self.balance += balance;
-        self
+    /// Sequence number the next transaction from the account must carry
+    pub fn next_sequence(&self) -> u64 {
+        self.sequence + 1
     }
 
-    /// Decrease account balance
-    pub fn decrease_balance(&mut self, balance: u128) -> &mut Self {
-        self.balance -= balance;
-        self
+    /// Increase account balance, leaving it as is if it would overflow
+    pub fn increase_balance(&mut self, amount: Amount) -> Result<&mut Self, StateError> {
+        self.balance = self
+            .balance
+            .checked_add(amount)
+            .ok_or(StateError::BalanceOverflow {
+                account: self.id,
+                balance: self.balance,
+                amount,
+            })?;
+        Ok(self)
+    }
+
+    /// Decrease account balance, leaving it as is if it does not hold
+    /// `amount`
+    pub fn decrease_balance(&mut self, amount: Amount) -> Result<&mut Self, StateError> {
+        self.balance = self
+            .balance
+            .checked_sub(amount)
+            .ok_or(StateError::BalanceUnderflow {
+                account: self.id,
+                balance: self.balance,
+                amount,
+            })?;
+        Ok(self)
     }
 
     /// Update last transaction ID
-    pub fn update_last_tx(&mut self, tx_id: &Hash) -> &mut Self {
+    pub fn update_last_tx(&mut self, tx_id: &TxId) -> &mut Self {
         self.last_tx_id = *tx_id;
         self
     }
 
+    /// Update the sequence number of the latest transaction sent
+    pub fn update_sequence(&mut self, sequence: u64) -> &mut Self {
+        self.sequence = sequence;
+        self
+    }
+
     /// Update HVC
     pub fn update_hvc(&mut self) -> &mut Self {
         self.hvc.order().increment();
@@ -74,3 +111,112 @@ impl AccountStateChoice {
         }
     }
 }
+
+/// Latest accepted sequence number of each account, as seen by an engine.
+///
+/// Lets an engine refuse replayed or out-of-order transactions even once
+/// their conflict sets were pruned.
+#[derive(Debug, Default)]
+pub struct SequenceTracker {
+    latest: RwLock<HashMap<AccountId, u64>>,
+}
+
+impl SequenceTracker {
+    /// Whether `tx` carries the next sequence of its origin. Accounts the
+    /// engine has not seen a transaction from yet accept any sequence.
+    pub fn is_next(&self, tx: &Transaction) -> bool {
+        self.latest
+            .read()
+            .unwrap()
+            .get(&tx.origin)
+            .is_none_or(|latest| tx.sequence == latest + 1)
+    }
+
+    /// Record an accepted transaction
+    pub fn advance(&self, tx: &Transaction) {
+        let mut latest = self.latest.write().unwrap();
+        let sequence = latest.entry(tx.origin).or_insert(tx.sequence);
+        *sequence = (*sequence).max(tx.sequence);
+    }
+
+    pub fn snapshot(&self) -> HashMap<AccountId, u64> {
+        self.latest.read().unwrap().clone()
+    }
+
+    /// Merge sequences handed over by another engine
+    pub fn merge(&self, sequences: &HashMap<AccountId, u64>) {
+        let mut latest = self.latest.write().unwrap();
+        for (origin, sequence) in sequences {
+            let entry = latest.entry(*origin).or_insert(*sequence);
+            *entry = (*entry).max(*sequence);
+        }
+    }
+}
+
+#[test]
+fn test_sequence_tracker_rejects_replays() {
+    use crate::{transaction::TransactionType, ConsensusError};
+
+    let mut origin = Account::create(&Hash::new("A".as_bytes()).into(), &Hash::default().into());
+    let destination = Account::create(&Hash::new("B".as_bytes()).into(), &Hash::default().into());
+    let mut tx = Transaction::new(
+        Hash::default().into(),
+        origin.clone(),
+        destination.id,
+        Amount::ZERO,
+        TransactionType::Transfer,
+        vec![],
+    );
+    tx.calculate_tx_id().unwrap();
+    assert!(tx.check_sequence(&origin).is_ok());
+
+    let tracker = SequenceTracker::default();
+    assert!(tracker.is_next(&tx));
+    tracker.advance(&tx);
+    let delta = tx.apply(&origin, &destination).unwrap();
+    origin = delta.get(&origin.id).unwrap().clone();
+    assert_eq!(origin.sequence, 1);
+
+    // The same transaction cannot be applied twice
+    assert!(!tracker.is_next(&tx));
+    assert!(matches!(
+        tx.check_sequence(&origin),
+        Err(ConsensusError::InvalidSequence {
+            expected: 2,
+            got: 1,
+            ..
+        })
+    ));
+
+    let next = Transaction::new(
+        tx.get_tx_id(),
+        origin.clone(),
+        destination.id,
+        Amount::ZERO,
+        TransactionType::Transfer,
+        vec![],
+    );
+    assert!(tracker.is_next(&next));
+    assert!(next.check_sequence(&origin).is_ok());
+}
+
+#[test]
+fn test_balance_arithmetic_is_checked() {
+    let mut account = Account::create(&Hash::new("A".as_bytes()).into(), &Hash::default().into());
+    account.increase_balance(Amount::new(10)).unwrap();
+    assert_eq!(
+        account.decrease_balance(Amount::new(11)).unwrap_err(),
+        StateError::BalanceUnderflow {
+            account: account.id,
+            balance: Amount::new(10),
+            amount: Amount::new(11),
+        }
+    );
+    assert!(matches!(
+        account.increase_balance(Amount::MAX),
+        Err(StateError::BalanceOverflow { .. })
+    ));
+    assert_eq!(account.balance, Amount::new(10));
+    account.decrease_balance(Amount::new(10)).unwrap();
+    assert_eq!(account.balance, Amount::ZERO);
+}
@@ -0,0 +1,163 @@
+//! Amounts of DAG.
+//!
+//! Balances, transfers and fees are counted in base units, [`Amount::UNIT`]
+//! of them making one DAG. An [`Amount`] only offers checked and saturating
+//! arithmetic, so that an overflow is handled where it happens instead of
+//! wrapping or panicking. It is displayed and parsed in DAG, e.g.
+//! `12.5 DAG`, and serialized as its number of base units.
+
+use crate::error::AmountError;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Amount of DAG, in base units
+#[derive(
+    Clone, Copy, Debug, Default, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize,
+)]
+#[serde(transparent)]
+pub struct Amount(u128);
+
+impl Amount {
+    pub const ZERO: Self = Self(0);
+    pub const MAX: Self = Self(u128::MAX);
+    /// Digits of the fractional part of a DAG
+    pub const DECIMALS: u32 = 9;
+    /// One DAG
+    pub const UNIT: Self = Self(10u128.pow(Self::DECIMALS));
+    pub const SYMBOL: &'static str = "DAG";
+
+    /// Amount of `base_units`
+    pub const fn new(base_units: u128) -> Self {
+        Self(base_units)
+    }
+
+    /// Amount of `dag` whole DAG, if it fits
+    pub fn from_dag(dag: u128) -> Option<Self> {
+        dag.checked_mul(Self::UNIT.0).map(Self)
+    }
+
+    pub const fn base_units(self) -> u128 {
+        self.0
+    }
+
+    pub const fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        self.0.checked_add(other.0).map(Self)
+    }
+
+    pub fn checked_sub(self, other: Self) -> Option<Self> {
+        self.0.checked_sub(other.0).map(Self)
+    }
+
+    pub fn checked_mul(self, factor: u128) -> Option<Self> {
+        self.0.checked_mul(factor).map(Self)
+    }
+
+    pub fn saturating_add(self, other: Self) -> Self {
+        Self(self.0.saturating_add(other.0))
+    }
+
+    pub fn saturating_sub(self, other: Self) -> Self {
+        Self(self.0.saturating_sub(other.0))
+    }
+}
+
+impl From<u128> for Amount {
+    fn from(base_units: u128) -> Self {
+        Self(base_units)
+    }
+}
+
+impl From<Amount> for u128 {
+    fn from(amount: Amount) -> Self {
+        amount.0
+    }
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let whole = self.0 / Self::UNIT.0;
+        let fraction = self.0 % Self::UNIT.0;
+        if fraction == 0 {
+            return write!(f, "{} {}", whole, Self::SYMBOL);
+        }
+        let fraction = format!("{:0width$}", fraction, width = Self::DECIMALS as usize);
+        write!(
+            f,
+            "{}.{} {}",
+            whole,
+            fraction.trim_end_matches('0'),
+            Self::SYMBOL
+        )
+    }
+}
+
+impl FromStr for Amount {
+    type Err = AmountError;
+
+    /// Parse an amount of DAG, the symbol being optional
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || AmountError::Invalid(s.to_string());
+        let number = s.trim();
+        let number = number
+            .strip_suffix(Self::SYMBOL)
+            .map_or(number, str::trim_end);
+        let (whole, fraction) = number.split_once('.').unwrap_or((number, ""));
+        let is_digits = |part: &str| part.bytes().all(|byte| byte.is_ascii_digit());
+        if whole.is_empty() || !is_digits(whole) || !is_digits(fraction) {
+            return Err(invalid());
+        }
+        if fraction.len() > Self::DECIMALS as usize {
+            return Err(AmountError::TooPrecise(Self::DECIMALS));
+        }
+        let whole = whole.parse::<u128>().map_err(|_| AmountError::Overflow)?;
+        let fraction = match fraction {
+            "" => 0,
+            fraction => {
+                let digits = fraction.parse::<u128>().map_err(|_| invalid())?;
+                digits * 10u128.pow(Self::DECIMALS - fraction.len() as u32)
+            }
+        };
+        Self::from_dag(whole)
+            .and_then(|amount| amount.checked_add(Self(fraction)))
+            .ok_or(AmountError::Overflow)
+    }
+}
+
+#[test]
+fn test_amount_display_round_trips() {
+    let amount = "12.5 DAG".parse::<Amount>().unwrap();
+    assert_eq!(amount, Amount::new(12_500_000_000));
+    assert_eq!(amount.to_string(), "12.5 DAG");
+    assert_eq!(Amount::UNIT.to_string(), "1 DAG");
+    assert_eq!(Amount::new(1).to_string(), "0.000000001 DAG");
+    assert_eq!("3".parse::<Amount>().unwrap(), Amount::from_dag(3).unwrap());
+    assert_eq!(
+        Amount::MAX.to_string().parse::<Amount>().unwrap(),
+        Amount::MAX
+    );
+
+    assert_eq!(
+        "0.0000000001".parse::<Amount>(),
+        Err(AmountError::TooPrecise(9))
+    );
+    assert!(matches!(
+        "-1 DAG".parse::<Amount>(),
+        Err(AmountError::Invalid(_))
+    ));
+    assert_eq!(
+        format!("{} DAG", u128::MAX / Amount::UNIT.base_units() + 1).parse::<Amount>(),
+        Err(AmountError::Overflow)
+    );
+
+    assert_eq!(Amount::MAX.checked_add(Amount::new(1)), None);
+    assert_eq!(Amount::ZERO.saturating_sub(Amount::UNIT), Amount::ZERO);
+    assert_eq!(
+        bincode::serialize(&amount).unwrap(),
+        bincode::serialize(&12_500_000_000u128).unwrap()
+    );
+}
@@ -0,0 +1,258 @@
+use crate::{account::AccountStateChoice, id::TxId, ConsensusError, ConsensusStatus};
+use crypto::hash::Hash;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime};
+use storage::Storage;
+
+const AUDIT_HEAD_KEY: &[u8] = b"audit:head";
+const AUDIT_ENTRY_TAG: &[u8] = b"audit:entry:";
+
+/// Consensus decision taken by the local node
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum Decision {
+    /// A peer queried our preference for an account state
+    QueryReceived { account_state: Hash, tx: TxId },
+    /// We answered a query with our current choice
+    AnswerGiven {
+        account_state: Hash,
+        choice: TxId,
+        preferred: bool,
+    },
+    /// A round on a transaction reached a final outcome
+    Finalized { tx: TxId, accepted: bool },
+}
+
+/// Entry of the audit log, chained to the previous one by hash
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct AuditEntry {
+    pub index: u64,
+    /// Hash of the previous entry, default for the first one
+    pub previous: Hash,
+    pub decision: Decision,
+    pub timestamp: Duration,
+    pub hash: Hash,
+}
+
+impl AuditEntry {
+    fn new(index: u64, previous: Hash, decision: Decision) -> Result<Self, ConsensusError> {
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap();
+        let hash = entry_hash(index, &previous, &decision, timestamp)?;
+        Ok(Self {
+            index,
+            previous,
+            decision,
+            timestamp,
+            hash,
+        })
+    }
+}
+
+/// Tamper-evident log of the consensus decisions of the node.
+///
+/// Every entry commits to the one before it, so altering, dropping or
+/// reordering past entries breaks the chain from that point on.
+pub struct AuditLog<S: Storage> {
+    storage: S,
+    /// Index and hash of the latest entry
+    head: Option<(u64, Hash)>,
+}
+
+impl<S: Storage> AuditLog<S> {
+    /// Initialize an AuditLog, resuming from the latest entry in storage
+    pub fn new(storage: S) -> Self {
+        let head = storage
+            .get(Hash::new(AUDIT_HEAD_KEY))
+            .ok()
+            .and_then(|bytes| bincode::deserialize(&bytes).ok());
+        Self { storage, head }
+    }
+
+    /// Number of entries in the log
+    pub fn len(&self) -> u64 {
+        self.head.map_or(0, |(index, _)| index + 1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head.is_none()
+    }
+
+    /// Append a decision to the log
+    pub fn append(&mut self, decision: Decision) -> Result<AuditEntry, ConsensusError> {
+        let (index, previous) = match self.head {
+            Some((index, hash)) => (index + 1, hash),
+            None => (0, Hash::default()),
+        };
+        let entry = AuditEntry::new(index, previous, decision)?;
+        self.storage.insert(entry_key(index), serialize(&entry)?)?;
+        self.storage
+            .insert(Hash::new(AUDIT_HEAD_KEY), serialize(&(index, entry.hash))?)?;
+        self.storage.flush()?;
+        self.head = Some((index, entry.hash));
+        Ok(entry)
+    }
+
+    pub fn record_query(
+        &mut self,
+        state: &AccountStateChoice,
+    ) -> Result<AuditEntry, ConsensusError> {
+        self.append(Decision::QueryReceived {
+            account_state: state.account_state_id,
+            tx: state.tx.get_tx_id(),
+        })
+    }
+
+    /// Record the answer to a query, as returned by `Consensus::on_query`
+    pub fn record_answer(
+        &mut self,
+        state: &AccountStateChoice,
+        (choice, preferred): (TxId, bool),
+    ) -> Result<AuditEntry, ConsensusError> {
+        self.append(Decision::AnswerGiven {
+            account_state: state.account_state_id,
+            choice,
+            preferred,
+        })
+    }
+
+    /// Record the outcome of a round, ignoring rounds still in progress
+    pub fn record_finalization(
+        &mut self,
+        tx: TxId,
+        status: &ConsensusStatus,
+    ) -> Result<Option<AuditEntry>, ConsensusError> {
+        let accepted = match status {
+            ConsensusStatus::Accept(_) | ConsensusStatus::Checkpointed(_) => true,
+            ConsensusStatus::Reject => false,
+            ConsensusStatus::InProgress
+            | ConsensusStatus::Draining
+            | ConsensusStatus::Overloaded
+            | ConsensusStatus::TimedOut(_) => return Ok(None),
+        };
+        self.append(Decision::Finalized { tx, accepted }).map(Some)
+    }
+
+    /// Load every entry of the log, oldest first
+    pub fn export(&self) -> Result<Vec<AuditEntry>, ConsensusError> {
+        (0..self.len())
+            .map(|index| deserialize(&self.storage.get(entry_key(index))?))
+            .collect()
+    }
+
+    /// Check the chain of the stored entries up to the latest one
+    pub fn verify(&self) -> Result<(), ConsensusError> {
+        let entries = self.export()?;
+        verify_chain(&entries)?;
+        match (entries.last(), self.head) {
+            (Some(last), Some((_, head))) if last.hash != head => {
+                Err(ConsensusError::AuditChainBroken(last.index))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Check that exported entries form an unbroken chain, returning the index
+/// of the first entry that does not
+pub fn verify_chain(entries: &[AuditEntry]) -> Result<(), ConsensusError> {
+    let mut previous = Hash::default();
+    for (index, entry) in entries.iter().enumerate() {
+        let hash = entry_hash(
+            entry.index,
+            &entry.previous,
+            &entry.decision,
+            entry.timestamp,
+        )?;
+        if entry.index != index as u64 || entry.previous != previous || entry.hash != hash {
+            return Err(ConsensusError::AuditChainBroken(index as u64));
+        }
+        previous = entry.hash;
+    }
+    Ok(())
+}
+
+fn entry_hash(
+    index: u64,
+    previous: &Hash,
+    decision: &Decision,
+    timestamp: Duration,
+) -> Result<Hash, ConsensusError> {
+    Hash::serialize(&(index, previous, decision, timestamp))
+        .map_err(|e| ConsensusError::SerializationError(e.to_string()))
+}
+
+fn entry_key(index: u64) -> Hash {
+    let mut key = AUDIT_ENTRY_TAG.to_vec();
+    key.extend_from_slice(&index.to_be_bytes());
+    Hash::new(&key)
+}
+
+fn serialize<T: Serialize>(value: &T) -> Result<Vec<u8>, ConsensusError> {
+    bincode::serialize(value).map_err(|e| ConsensusError::SerializationError(e.to_string()))
+}
+
+fn deserialize<T: serde::de::DeserializeOwned>(bytes: &[u8]) -> Result<T, ConsensusError> {
+    bincode::deserialize(bytes).map_err(|e| ConsensusError::SerializationError(e.to_string()))
+}
+
+#[test]
+fn test_audit_log_chains_decisions() {
+    use crate::{
+        account::Account,
+        amount::Amount,
+        transaction::{Transaction, TransactionType},
+    };
+    use storage::memory::MemoryStorage;
+
+    let origin = Account::create(&Hash::new("A".as_bytes()).into(), &Hash::default().into());
+    let mut tx = Transaction::new(
+        Hash::default().into(),
+        origin,
+        Hash::new("B".as_bytes()).into(),
+        Amount::new(1),
+        TransactionType::Transfer,
+        vec![],
+    );
+    tx.calculate_tx_id().unwrap();
+    let state = AccountStateChoice::new(Hash::default(), &tx);
+
+    let mut log = AuditLog::new(MemoryStorage::new(None).unwrap());
+    log.record_query(&state).unwrap();
+    log.record_answer(&state, (tx.get_tx_id(), true)).unwrap();
+    assert!(log
+        .record_finalization(tx.get_tx_id(), &ConsensusStatus::InProgress)
+        .unwrap()
+        .is_none());
+    log.record_finalization(tx.get_tx_id(), &ConsensusStatus::Accept(tx.get_tx_id()))
+        .unwrap();
+    assert_eq!(log.len(), 3);
+    log.verify().unwrap();
+
+    // Resuming from storage keeps extending the same chain
+    let mut log = AuditLog::new(log.storage);
+    let entry = log
+        .append(Decision::Finalized {
+            tx: Hash::default().into(),
+            accepted: false,
+        })
+        .unwrap();
+    assert_eq!(entry.index, 3);
+    let mut entries = log.export().unwrap();
+    verify_chain(&entries).unwrap();
+
+    entries[1].decision = Decision::AnswerGiven {
+        account_state: Hash::default(),
+        choice: Hash::default().into(),
+        preferred: false,
+    };
+    assert!(matches!(
+        verify_chain(&entries),
+        Err(ConsensusError::AuditChainBroken(1))
+    ));
+    let _ = entries.remove(1);
+    assert!(matches!(
+        verify_chain(&entries),
+        Err(ConsensusError::AuditChainBroken(1))
+    ));
+}
@@ -0,0 +1,141 @@
+//! Accounting of the memory taken by in-flight consensus state.
+//!
+//! The conflict sets of the engine, the pending transactions of the mempool
+//! and the outboxes of the node all grow with load, each under its own bound
+//! if any. They report the bytes they hold to a shared [`MemoryBudget`], so
+//! that together they stay under a single cap: once it is reached,
+//! submissions are refused and the lowest priority work is shed until usage
+//! falls back under it.
+
+use crate::{config::ConsensusConfig, error::ConsensusError};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Kinds of state accounted for in a [`MemoryBudget`]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub enum Resource {
+    /// Transactions competing for each account state, in the engine
+    ConflictSets,
+    /// Transactions waiting in the mempool
+    Mempool,
+    /// Envelopes waiting to be sent to each next hop
+    Outboxes,
+}
+
+impl Resource {
+    const ALL: [Resource; 3] = [
+        Resource::ConflictSets,
+        Resource::Mempool,
+        Resource::Outboxes,
+    ];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Cap on the bytes held by every [`Resource`] together. Clones share the
+/// same accounting.
+#[derive(Clone, Debug)]
+pub struct MemoryBudget {
+    limit: usize,
+    used: Arc<[AtomicUsize; 3]>,
+}
+
+impl Default for MemoryBudget {
+    fn default() -> Self {
+        Self::from_config(&ConsensusConfig::default())
+    }
+}
+
+impl MemoryBudget {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            used: Arc::new(Default::default()),
+        }
+    }
+
+    pub fn from_config(config: &ConsensusConfig) -> Self {
+        Self::new(config.memory_budget())
+    }
+
+    /// A budget that is never exhausted, for components not sharing one
+    pub fn unlimited() -> Self {
+        Self::new(usize::MAX)
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Bytes held by every resource
+    pub fn used(&self) -> usize {
+        Resource::ALL.iter().map(|r| self.used_by(*r)).sum()
+    }
+
+    pub fn used_by(&self, resource: Resource) -> usize {
+        self.used[resource.index()].load(Ordering::SeqCst)
+    }
+
+    /// Report the bytes `resource` holds now
+    pub fn set_used(&self, resource: Resource, bytes: usize) {
+        self.used[resource.index()].store(bytes, Ordering::SeqCst);
+    }
+
+    /// Whether `bytes` more can be taken up without going over the limit
+    pub fn admits(&self, bytes: usize) -> bool {
+        self.used().saturating_add(bytes) <= self.limit
+    }
+
+    /// Whether usage went over the limit
+    pub fn is_exhausted(&self) -> bool {
+        self.used() > self.limit
+    }
+
+    /// Bytes to free to get back under the limit
+    pub fn excess(&self) -> usize {
+        self.used().saturating_sub(self.limit)
+    }
+
+    /// Check that `bytes` more for `resource` fit in the budget
+    pub fn check(&self, resource: Resource, bytes: usize) -> Result<(), ConsensusError> {
+        if self.admits(bytes) {
+            return Ok(());
+        }
+        Err(ConsensusError::ResourceExhausted {
+            resource,
+            used: self.used(),
+            limit: self.limit,
+        })
+    }
+}
+
+#[test]
+fn test_memory_budget() {
+    let budget = MemoryBudget::new(1000);
+    let shared = budget.clone();
+    budget.set_used(Resource::Mempool, 600);
+    shared.set_used(Resource::Outboxes, 300);
+    assert_eq!(budget.used(), 900);
+    assert_eq!(shared.used_by(Resource::Mempool), 600);
+    assert!(budget.admits(100));
+    assert!(!budget.is_exhausted());
+    assert!(matches!(
+        budget.check(Resource::ConflictSets, 101),
+        Err(ConsensusError::ResourceExhausted {
+            resource: Resource::ConflictSets,
+            used: 900,
+            limit: 1000,
+        })
+    ));
+
+    // Usage reported past the limit must be shed
+    shared.set_used(Resource::ConflictSets, 250);
+    assert!(budget.is_exhausted());
+    assert_eq!(budget.excess(), 150);
+    budget.set_used(Resource::Outboxes, 0);
+    assert!(!budget.is_exhausted());
+    assert_eq!(budget.excess(), 0);
+}
@@ -0,0 +1,518 @@
+use crate::{
+    account::Account,
+    config::ConsensusConfig,
+    id::{AccountId, TxId},
+    reconcile::StakeTable,
+    state::{BalanceProof, DomainRoots, StateTrie},
+    tree::HashTreeNode,
+    Consensus, ConsensusError, ConsensusStatus,
+};
+use crypto::{
+    hash::Hash,
+    signature::{PrivateKey, PublicKey, Signature},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::time::{Duration, SystemTime};
+use storage::Storage;
+
+const LATEST_CHECKPOINT_KEY: &[u8] = b"checkpoint:latest";
+const ACCOUNTS_KEY_TAG: &[u8] = b"checkpoint:accounts:";
+const DOMAINS_KEY_TAG: &[u8] = b"checkpoint:domains:";
+
+/// Snapshot of finalized account state
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Checkpoint {
+    pub id: Hash,
+    pub height: u64,
+    pub previous: Hash,
+    /// Root of the state at the checkpoint, see [`StateTrie::root`]
+    pub state_root: Hash,
+    /// Last transaction accepted before the checkpoint
+    pub anchor: TxId,
+    /// Number of transactions finalized since the previous checkpoint
+    pub finalized: usize,
+    pub timestamp: Duration,
+}
+
+impl Checkpoint {
+    /// Initialize the checkpoint following `previous`, the first one if
+    /// there is none
+    pub fn new(
+        previous: Option<&Checkpoint>,
+        state_root: Hash,
+        anchor: TxId,
+        finalized: usize,
+    ) -> Result<Self, ConsensusError> {
+        let (height, previous) = match previous {
+            Some(previous) => (previous.height + 1, previous.id),
+            None => (0, Hash::default()),
+        };
+        Ok(Self {
+            id: checkpoint_id(height, &previous, &state_root, &anchor)?,
+            height,
+            previous,
+            state_root,
+            anchor,
+            finalized,
+            timestamp: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap(),
+        })
+    }
+
+    /// Check that the ID of the checkpoint commits to its contents
+    pub fn verify_id(&self) -> bool {
+        checkpoint_id(self.height, &self.previous, &self.state_root, &self.anchor)
+            .is_ok_and(|id| id == self.id)
+    }
+
+    /// Check that a committee co-signed the checkpoint, its threshold
+    /// signature shares of the ID being combined into `signature`, see
+    /// [`crypto::signature::combine_shares`]
+    pub fn verify_committee_signature(&self, group_key: &PublicKey, signature: &Signature) -> bool {
+        self.verify_id() && signature.verify(group_key, self.id)
+    }
+}
+
+/// Checkpoint vouched for by validators.
+///
+/// Their signatures of the checkpoint ID are aggregated into one, so that a
+/// light client checks them all at once and learns which stake stands behind
+/// the checkpoint without following consensus itself.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct CheckpointCertificate {
+    pub checkpoint: Checkpoint,
+    /// Hashes of the public keys of the signers, see [`StakeTable`]
+    pub signers: Vec<Hash>,
+    pub signature: Option<Signature>,
+}
+
+impl CheckpointCertificate {
+    /// Initialize a certificate nobody signed yet
+    pub fn new(checkpoint: Checkpoint) -> Self {
+        Self {
+            checkpoint,
+            signers: vec![],
+            signature: None,
+        }
+    }
+
+    /// Add our signature to the aggregate, unless we signed already
+    pub fn sign(&mut self, private_key: &PrivateKey) -> Result<&mut Self, ConsensusError> {
+        let signer = Hash::new(&private_key.public_key().to_bytes());
+        if self.signers.contains(&signer) {
+            return Ok(self);
+        }
+        let mut signatures = vec![Signature::sign(private_key, self.checkpoint.id)];
+        signatures.extend(self.signature);
+        self.signature = Some(
+            Signature::aggregate(&signatures)
+                .map_err(|e| ConsensusError::SignatureError(e.to_string()))?,
+        );
+        self.signers.push(signer);
+        Ok(self)
+    }
+
+    /// Stake of the validators in `stake` certifying the checkpoint. Nothing
+    /// is certified if the checkpoint ID doesn't commit to its contents, if
+    /// the aggregated signature is invalid, or if a signer is unknown.
+    pub fn certified(&self, stake: &StakeTable) -> u128 {
+        let signature = match self.signature {
+            Some(signature) if self.checkpoint.verify_id() => signature,
+            _ => return 0,
+        };
+        let mut signers = self.signers.clone();
+        signers.sort();
+        signers.dedup();
+        if signers.len() != self.signers.len() {
+            return 0;
+        }
+        let validators = match signers
+            .iter()
+            .map(|signer| stake.get(signer))
+            .collect::<Option<Vec<_>>>()
+        {
+            Some(validators) => validators,
+            None => return 0,
+        };
+        let public_keys = validators
+            .iter()
+            .map(|(public_key, _)| *public_key)
+            .collect::<Vec<_>>();
+        if signature.verify_aggregate(&public_keys, self.checkpoint.id) {
+            validators.iter().map(|(_, stake)| stake).sum()
+        } else {
+            0
+        }
+    }
+}
+
+/// Takes a checkpoint every `interval` accepted transactions.
+///
+/// A checkpoint persists the account state to storage and prunes the
+/// finalized vertices below it from the consensus tree, along with the
+/// conflict sets they resolved, so memory stays bounded.
+pub struct Checkpointer<S: Storage> {
+    interval: usize,
+    storage: S,
+    finalized: Vec<TxId>,
+    latest: Option<Checkpoint>,
+}
+
+impl<S: Storage> Checkpointer<S> {
+    /// Initialize a Checkpointer, resuming from the latest checkpoint in storage
+    pub fn new(storage: S, interval: usize) -> Self {
+        let latest = storage
+            .get(Hash::new(LATEST_CHECKPOINT_KEY))
+            .ok()
+            .and_then(|bytes| bincode::deserialize::<Hash>(&bytes).ok())
+            .and_then(|id| storage.get(id).ok())
+            .and_then(|bytes| bincode::deserialize(&bytes).ok());
+        Self {
+            interval: interval.max(1),
+            storage,
+            finalized: vec![],
+            latest,
+        }
+    }
+
+    /// Initialize a Checkpointer from consensus parameters
+    pub fn from_config(storage: S, config: &ConsensusConfig) -> Self {
+        Self::new(storage, config.checkpoint_interval)
+    }
+
+    /// Latest checkpoint taken
+    pub fn latest(&self) -> Option<&Checkpoint> {
+        self.latest.as_ref()
+    }
+
+    /// Number of transactions accepted since the latest checkpoint
+    pub fn pending(&self) -> usize {
+        self.finalized.len()
+    }
+
+    /// Record an accepted transaction, taking a checkpoint once enough of
+    /// them have been accepted
+    pub fn on_accepted<C: Consensus>(
+        &mut self,
+        tx_id: TxId,
+        engine: &C,
+        state: &StateTrie,
+        tree: &mut HashTreeNode,
+    ) -> Result<Option<ConsensusStatus>, ConsensusError> {
+        self.finalized.push(tx_id);
+        if self.finalized.len() < self.interval {
+            return Ok(None);
+        }
+        let checkpoint = self.checkpoint(engine, state, tree)?;
+        Ok(Some(ConsensusStatus::Checkpointed(checkpoint.id)))
+    }
+
+    /// Take a checkpoint right away
+    pub fn checkpoint<C: Consensus>(
+        &mut self,
+        engine: &C,
+        state: &StateTrie,
+        tree: &mut HashTreeNode,
+    ) -> Result<Checkpoint, ConsensusError> {
+        let previous = self.latest.as_ref().map(|latest| latest.id);
+        let anchor = self
+            .finalized
+            .last()
+            .copied()
+            .unwrap_or_else(|| previous.unwrap_or_default().into());
+        let checkpoint = Checkpoint::new(
+            self.latest.as_ref(),
+            state.root(),
+            anchor,
+            self.finalized.len(),
+        )?;
+        let id = checkpoint.id;
+
+        let accounts = state.accounts().cloned().collect::<Vec<_>>();
+        self.storage
+            .insert(accounts_key(&id), serialize(&accounts)?)?;
+        self.storage
+            .insert(domains_key(&id), serialize(state.domains())?)?;
+        self.storage.insert(id, serialize(&checkpoint)?)?;
+        self.storage
+            .insert(Hash::new(LATEST_CHECKPOINT_KEY), serialize(&id)?)?;
+        self.storage.flush()?;
+
+        let finalized = std::mem::take(&mut self.finalized)
+            .into_iter()
+            .collect::<HashSet<_>>();
+        prune_tree(tree, &finalized, &anchor);
+        engine.prune(&finalized);
+
+        log::info!(
+            "Checkpoint {:?} at height {} ({} transactions finalized)",
+            id,
+            checkpoint.height,
+            checkpoint.finalized
+        );
+        self.latest = Some(checkpoint.clone());
+        Ok(checkpoint)
+    }
+
+    /// Load a checkpoint from storage
+    pub fn load(&self, id: &Hash) -> Result<Checkpoint, ConsensusError> {
+        deserialize(&self.storage.get(*id)?)
+    }
+
+    /// Load the account state snapshot of a checkpoint from storage
+    pub fn load_accounts(&self, checkpoint: &Checkpoint) -> Result<Vec<Account>, ConsensusError> {
+        deserialize(&self.storage.get(accounts_key(&checkpoint.id))?)
+    }
+
+    /// Load the roots of the domains of a checkpoint from storage, only the
+    /// accounts of which are snapshot
+    fn load_domains(&self, checkpoint: &Checkpoint) -> Result<DomainRoots, ConsensusError> {
+        deserialize(&self.storage.get(domains_key(&checkpoint.id))?)
+    }
+
+    /// Stored checkpoints from `height` up to the latest, in order
+    pub fn history(&self, height: u64) -> Result<Vec<Checkpoint>, ConsensusError> {
+        let mut history = vec![];
+        let mut next = self.latest.clone();
+        while let Some(checkpoint) = next.take() {
+            if checkpoint.height < height {
+                break;
+            }
+            if checkpoint.height > 0 {
+                next = Some(self.load(&checkpoint.previous)?);
+            }
+            history.push(checkpoint);
+        }
+        history.reverse();
+        Ok(history)
+    }
+
+    /// Prove the balance of an account at a stored checkpoint.
+    /// Returns `None` if the account did not exist at the checkpoint.
+    pub fn prove_balance(
+        &self,
+        checkpoint_id: &Hash,
+        account_id: &AccountId,
+    ) -> Result<Option<BalanceProof>, ConsensusError> {
+        let checkpoint = self.load(checkpoint_id)?;
+        let state = StateTrie::from_accounts(self.load_accounts(&checkpoint)?);
+        let domains = self.load_domains(&checkpoint)?;
+        Ok(state.prove_balance_under(&checkpoint, &domains, account_id))
+    }
+}
+
+/// Remove finalized vertices from the tree, keeping the ones that still
+/// have pending children and the checkpoint anchor, which new transactions
+/// reference as their parent.
+fn prune_tree(tree: &mut HashTreeNode, finalized: &HashSet<TxId>, anchor: &TxId) {
+    let referenced = tree
+        .iter()
+        .filter(|(vertex, _)| !finalized.contains(vertex))
+        .flat_map(|(_, (parents, _))| parents.iter().copied())
+        .collect::<HashSet<_>>();
+    tree.retain(|vertex, _| {
+        !finalized.contains(vertex) || vertex == anchor || referenced.contains(vertex)
+    });
+}
+
+fn checkpoint_id(
+    height: u64,
+    previous: &Hash,
+    state_root: &Hash,
+    anchor: &TxId,
+) -> Result<Hash, ConsensusError> {
+    Hash::serialize(&(height, previous, state_root, anchor))
+        .map_err(|e| ConsensusError::SerializationError(e.to_string()))
+}
+
+fn accounts_key(checkpoint_id: &Hash) -> Hash {
+    let mut key = ACCOUNTS_KEY_TAG.to_vec();
+    key.extend_from_slice(&checkpoint_id.0);
+    Hash::new(&key)
+}
+
+fn domains_key(checkpoint_id: &Hash) -> Hash {
+    let mut key = DOMAINS_KEY_TAG.to_vec();
+    key.extend_from_slice(&checkpoint_id.0);
+    Hash::new(&key)
+}
+
+fn serialize<T: Serialize>(value: &T) -> Result<Vec<u8>, ConsensusError> {
+    bincode::serialize(value).map_err(|e| ConsensusError::SerializationError(e.to_string()))
+}
+
+fn deserialize<T: serde::de::DeserializeOwned>(bytes: &[u8]) -> Result<T, ConsensusError> {
+    bincode::deserialize(bytes).map_err(|e| ConsensusError::SerializationError(e.to_string()))
+}
+
+#[cfg(test)]
+fn accepted_tx(parent: TxId, name: &str) -> crate::transaction::Transaction {
+    use crate::{
+        amount::Amount,
+        transaction::{Transaction, TransactionType},
+    };
+
+    let origin = Account::create(&Hash::new(name.as_bytes()).into(), &Hash::default().into());
+    let mut tx = Transaction::new(
+        parent,
+        origin,
+        Hash::new("destination".as_bytes()).into(),
+        Amount::new(1),
+        TransactionType::Transfer,
+        vec![],
+    );
+    tx.calculate_tx_id().unwrap();
+    tx
+}
+
+#[test]
+fn test_checkpoint_prunes_finalized_state() {
+    use crate::{account::AccountStateChoice, dag_consensus::DagConsensus, tree::TreeNode};
+    use storage::memory::MemoryStorage;
+
+    let engine = DagConsensus::new(ConsensusConfig::default());
+    let mut checkpointer = Checkpointer::new(MemoryStorage::new(None).unwrap(), 2);
+    let mut state = StateTrie::new();
+    state.insert(&Account::create(
+        &Hash::new("A".as_bytes()).into(),
+        &Hash::default().into(),
+    ));
+    let mut tree = HashTreeNode::new();
+
+    let first = accepted_tx(TxId::default(), "first");
+    let second = accepted_tx(first.get_tx_id(), "second");
+    let pending = accepted_tx(second.get_tx_id(), "pending");
+    for tx in [&first, &second, &pending] {
+        let _ = tree.insert(
+            tx.get_tx_id(),
+            (tx.parents().to_vec(), TreeNode::new(tx.get_tx_id())),
+        );
+        engine.query(&AccountStateChoice::new(tx.parent().into(), tx));
+    }
+
+    let status = checkpointer
+        .on_accepted(first.get_tx_id(), &engine, &state, &mut tree)
+        .unwrap();
+    assert!(status.is_none());
+    let status = checkpointer
+        .on_accepted(second.get_tx_id(), &engine, &state, &mut tree)
+        .unwrap();
+    let checkpoint = checkpointer.latest().unwrap().clone();
+    assert_eq!(status, Some(ConsensusStatus::Checkpointed(checkpoint.id)));
+    assert_eq!(checkpoint.anchor, second.get_tx_id());
+    assert_eq!(checkpoint.state_root, state.root());
+
+    assert!(!tree.contains_key(&first.get_tx_id()));
+    assert!(tree.contains_key(&second.get_tx_id()));
+    assert!(tree.contains_key(&pending.get_tx_id()));
+    let conflicts = engine.conflict_set();
+    assert!(!conflicts.contains_key(first.parent().as_hash()));
+    assert!(conflicts.contains_key(pending.parent().as_hash()));
+
+    assert_eq!(checkpointer.load(&checkpoint.id).unwrap(), checkpoint);
+    assert_eq!(checkpointer.load_accounts(&checkpoint).unwrap().len(), 1);
+}
+
+#[test]
+fn test_checkpointer_resumes_from_storage() {
+    use crate::dag_consensus::DagConsensus;
+    use storage::memory::MemoryStorage;
+
+    let engine = DagConsensus::new(ConsensusConfig::default());
+    let mut checkpointer = Checkpointer::new(MemoryStorage::new(None).unwrap(), 1);
+    let checkpoint = checkpointer
+        .checkpoint(&engine, &StateTrie::new(), &mut HashTreeNode::new())
+        .unwrap();
+
+    let resumed = Checkpointer::new(checkpointer.storage, 1);
+    assert_eq!(resumed.latest(), Some(&checkpoint));
+}
+
+#[test]
+fn test_balance_proof_at_checkpoint() {
+    use crate::{amount::Amount, dag_consensus::DagConsensus};
+    use storage::memory::MemoryStorage;
+
+    let engine = DagConsensus::new(ConsensusConfig::default());
+    let mut checkpointer = Checkpointer::new(MemoryStorage::new(None).unwrap(), 1);
+    let mut state = StateTrie::new();
+    let mut account = Account::create(&Hash::new("A".as_bytes()).into(), &Hash::default().into());
+    account.increase_balance(Amount::new(42)).unwrap();
+    state.insert(&account);
+    state.insert(&Account::create(
+        &Hash::new("B".as_bytes()).into(),
+        &Hash::default().into(),
+    ));
+    let checkpoint = checkpointer
+        .checkpoint(&engine, &state, &mut HashTreeNode::new())
+        .unwrap();
+
+    let proof = checkpointer
+        .prove_balance(&checkpoint.id, &account.id)
+        .unwrap()
+        .unwrap();
+    assert_eq!(proof.balance, Amount::new(42));
+    assert!(proof.verify(&checkpoint));
+
+    let mut inflated = proof.clone();
+    inflated.balance = Amount::new(1_000);
+    assert!(!inflated.verify(&checkpoint));
+    let mut forged = checkpoint.clone();
+    forged.state_root = Hash::default();
+    assert!(!proof.verify(&forged));
+    assert!(checkpointer
+        .prove_balance(&checkpoint.id, &Hash::new("C".as_bytes()).into())
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn test_checkpoint_certificate() {
+    use crate::dag_consensus::DagConsensus;
+    use storage::memory::MemoryStorage;
+
+    let engine = DagConsensus::new(ConsensusConfig::default());
+    let mut checkpointer = Checkpointer::new(MemoryStorage::new(None).unwrap(), 1);
+    let mut tree = HashTreeNode::new();
+    let first = checkpointer
+        .checkpoint(&engine, &StateTrie::new(), &mut tree)
+        .unwrap();
+    let second = checkpointer
+        .checkpoint(&engine, &StateTrie::new(), &mut tree)
+        .unwrap();
+    assert_eq!(
+        checkpointer.history(0).unwrap(),
+        vec![first, second.clone()]
+    );
+    assert_eq!(checkpointer.history(1).unwrap(), vec![second.clone()]);
+
+    let (alice, bob, mallory) = (
+        PrivateKey::generate(),
+        PrivateKey::generate(),
+        PrivateKey::generate(),
+    );
+    let mut stake = StakeTable::new();
+    let _ = stake
+        .insert(alice.public_key(), 30)
+        .insert(bob.public_key(), 70);
+
+    let mut certificate = CheckpointCertificate::new(second);
+    assert_eq!(certificate.certified(&stake), 0);
+    let _ = certificate.sign(&alice).unwrap().sign(&bob).unwrap();
+    let _ = certificate.sign(&alice).unwrap();
+    assert_eq!(certificate.signers.len(), 2);
+    assert_eq!(certificate.certified(&stake), 100);
+
+    // Unknown signers, repeated signers and forged checkpoints certify nothing
+    let mut unknown = certificate.clone();
+    let _ = unknown.sign(&mallory).unwrap();
+    assert_eq!(unknown.certified(&stake), 0);
+    let mut repeated = certificate.clone();
+    repeated.signers = vec![repeated.signers[0]; 2];
+    assert_eq!(repeated.certified(&stake), 0);
+    let mut forged = certificate;
+    forged.checkpoint.state_root = Hash::new("forged".as_bytes());
+    assert_eq!(forged.certified(&stake), 0);
+}
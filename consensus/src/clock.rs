@@ -21,6 +21,20 @@ impl Hvc {
         &mut self.hierarchical_order
     }
 
+    /// Rebuild a clock from its entries and logical clock, see
+    /// [`Hvc::parts`]
+    pub(crate) fn from_parts(vector: HashMap<Hash, u64>, order: u64) -> Self {
+        Self {
+            vector,
+            hierarchical_order: LogicalClock(order),
+        }
+    }
+
+    /// Entries of the clock and its logical clock
+    pub(crate) fn parts(&self) -> (&HashMap<Hash, u64>, u64) {
+        (&self.vector, self.hierarchical_order.0)
+    }
+
     pub fn increment(&mut self, node_id: Hash) {
         self.vector
             .entry(node_id)
@@ -41,7 +55,7 @@ impl Hvc {
         }
 
         for (key, &other_clock) in other.vector.iter() {
-            let self_clock = *self.vector.get(&key).unwrap_or(&0);
+            let self_clock = *self.vector.get(key).unwrap_or(&0);
             match self_clock.cmp(&other_clock) {
                 Ordering::Greater => return false,
                 Ordering::Less => return true,
@@ -1,37 +1,170 @@
+use crate::{
+    error::ConfigError,
+    tip_selection::{TipSelection, DEFAULT_TIP_PARENTS, MAX_TIP_PARENTS},
+};
 use serde::{Deserialize, Serialize};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, RwLock};
 use structopt::StructOpt;
 
-/// Consensus parameters
+/// Consensus parameters.
+///
+/// Built through [`ConsensusConfig::builder`], which validates them.
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize, StructOpt)]
+#[non_exhaustive]
 pub struct ConsensusConfig {
     #[structopt(short, long, default_value = "0.6")]
-    pub alpha: f64,
+    pub(crate) alpha: f64,
     #[structopt(short, long, default_value = "2")]
-    pub beta: u64,
+    pub(crate) beta: u64,
     #[structopt(short, long, default_value = "2")]
-    pub beta2: u64,
+    pub(crate) beta2: u64,
     #[structopt(short, long, default_value = "10")]
-    pub k: u64,
+    pub(crate) k: u64,
     #[structopt(skip)]
-    pub quantum: bool,
+    pub(crate) quantum: bool,
+    /// Finalize transactions instantly on this node alone, for local
+    /// development
+    #[structopt(long)]
+    pub(crate) dev: bool,
     #[structopt(short, long, default_value = "40")]
-    pub max_batch_size: usize,
+    pub(crate) max_batch_size: usize,
     #[structopt(short, long, default_value = "10")]
-    pub max_batch_interval: f32,
+    pub(crate) max_batch_interval: f32,
+    #[structopt(long, default_value = "30")]
+    pub(crate) submission_window: f32,
+    #[structopt(long, default_value = "10000")]
+    pub(crate) mempool_capacity: usize,
+    #[structopt(long, default_value = "67108864")]
+    pub(crate) mempool_max_bytes: usize,
+    /// Bytes all in-flight state may take together, see
+    /// [`MemoryBudget`](crate::budget::MemoryBudget)
+    #[structopt(long, default_value = "268435456")]
+    pub(crate) memory_budget: usize,
+    #[structopt(long, default_value = "1000")]
+    pub(crate) checkpoint_interval: usize,
+    /// Seconds a tip goes unreferenced before it is revalidated
+    #[structopt(long, default_value = "30")]
+    pub(crate) tip_revalidation_interval: f32,
+    /// Seconds a tip goes unreferenced before it expires
+    #[structopt(long, default_value = "300")]
+    pub(crate) tip_max_age: f32,
+    /// How the parents of new transactions are picked among the tips:
+    /// uniform or weighted-walk
+    #[structopt(long, default_value = "uniform")]
+    pub(crate) tip_selection: TipSelection,
+    /// Parents new transactions build on, their parent included
+    #[structopt(long, default_value = "2")]
+    pub(crate) tip_parents: usize,
+    /// Seconds to wait for the responses of a round's queries before
+    /// resampling peers
+    #[structopt(long, default_value = "10")]
+    pub(crate) round_timeout: f32,
+    /// Queries sent for a round before it times out
+    #[structopt(long, default_value = "3")]
+    pub(crate) max_round_attempts: usize,
+    /// Drop transactions whose rounds time out, instead of queueing them
+    /// again
+    #[structopt(long)]
+    pub(crate) drop_timed_out: bool,
 }
 
 impl ConsensusConfig {
+    /// Initialize a builder starting from the default parameters
+    pub fn builder() -> ConsensusConfigBuilder {
+        ConsensusConfigBuilder::default()
+    }
+
     /// Initialize config with specific params
-    pub fn new(alpha: f64, beta: u64, beta2: u64, k: u64) -> Self {
-        Self {
-            alpha,
-            beta,
-            beta2,
-            k,
-            quantum: false,
-            max_batch_size: 40,
-            max_batch_interval: 2.0,
-        }
+    pub fn new(alpha: f64, beta: u64, beta2: u64, k: u64) -> Result<Self, ConfigError> {
+        Self::builder()
+            .alpha(alpha)
+            .beta(beta)
+            .beta2(beta2)
+            .k(k)
+            .build()
+    }
+
+    pub fn alpha(&self) -> f64 {
+        self.alpha
+    }
+
+    pub fn beta(&self) -> u64 {
+        self.beta
+    }
+
+    pub fn beta2(&self) -> u64 {
+        self.beta2
+    }
+
+    pub fn k(&self) -> u64 {
+        self.k
+    }
+
+    pub fn is_quantum(&self) -> bool {
+        self.quantum
+    }
+
+    /// Whether the single-node development engine runs, see
+    /// [`DevConsensus`](crate::dev::DevConsensus)
+    pub fn is_dev(&self) -> bool {
+        self.dev
+    }
+
+    pub fn max_batch_size(&self) -> usize {
+        self.max_batch_size
+    }
+
+    pub fn max_batch_interval(&self) -> f32 {
+        self.max_batch_interval
+    }
+
+    pub fn submission_window(&self) -> f32 {
+        self.submission_window
+    }
+
+    pub fn mempool_capacity(&self) -> usize {
+        self.mempool_capacity
+    }
+
+    pub fn mempool_max_bytes(&self) -> usize {
+        self.mempool_max_bytes
+    }
+
+    pub fn memory_budget(&self) -> usize {
+        self.memory_budget
+    }
+
+    pub fn checkpoint_interval(&self) -> usize {
+        self.checkpoint_interval
+    }
+
+    pub fn tip_revalidation_interval(&self) -> f32 {
+        self.tip_revalidation_interval
+    }
+
+    pub fn tip_max_age(&self) -> f32 {
+        self.tip_max_age
+    }
+
+    pub fn tip_selection(&self) -> TipSelection {
+        self.tip_selection
+    }
+
+    pub fn tip_parents(&self) -> usize {
+        self.tip_parents
+    }
+
+    pub fn round_timeout(&self) -> f32 {
+        self.round_timeout
+    }
+
+    pub fn max_round_attempts(&self) -> usize {
+        self.max_round_attempts
+    }
+
+    pub fn drop_timed_out(&self) -> bool {
+        self.drop_timed_out
     }
 
     /// Change consensus to Quantum by default
@@ -39,6 +172,58 @@ impl ConsensusConfig {
         self.quantum = true;
     }
 
+    /// Check that the parameters are usable, e.g. after parsing them from
+    /// the command line
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.alpha.is_nan() || self.alpha <= 0.5 || self.alpha > 1.0 {
+            return Err(ConfigError::InvalidAlpha(self.alpha));
+        }
+        if self.k < 1 {
+            return Err(ConfigError::InvalidSampleSize);
+        }
+        if self.beta < 1 || self.beta2 < 1 {
+            return Err(ConfigError::InvalidBeta {
+                beta: self.beta,
+                beta2: self.beta2,
+            });
+        }
+        if self.max_batch_interval.is_nan() || self.max_batch_interval <= 0.0 {
+            return Err(ConfigError::InvalidBatchInterval(self.max_batch_interval));
+        }
+        if !self.submission_window.is_finite() || self.submission_window < 0.0 {
+            return Err(ConfigError::InvalidSubmissionWindow(self.submission_window));
+        }
+        if self.memory_budget < self.mempool_max_bytes {
+            return Err(ConfigError::InvalidMemoryBudget {
+                budget: self.memory_budget,
+                mempool_max_bytes: self.mempool_max_bytes,
+            });
+        }
+        if self.tip_revalidation_interval.is_nan()
+            || self.tip_revalidation_interval <= 0.0
+            || self.tip_max_age.is_nan()
+            || self.tip_max_age < self.tip_revalidation_interval
+        {
+            return Err(ConfigError::InvalidTipAge {
+                revalidation_interval: self.tip_revalidation_interval,
+                max_age: self.tip_max_age,
+            });
+        }
+        if self.tip_parents < 1 || self.tip_parents > MAX_TIP_PARENTS {
+            return Err(ConfigError::InvalidTipParents {
+                parents: self.tip_parents,
+                max: MAX_TIP_PARENTS,
+            });
+        }
+        if self.round_timeout.is_nan() || self.round_timeout <= 0.0 || self.max_round_attempts < 1 {
+            return Err(ConfigError::InvalidRoundDeadline {
+                timeout: self.round_timeout,
+                attempts: self.max_round_attempts,
+            });
+        }
+        Ok(())
+    }
+
     /// Check threshold for coefficients
     pub fn threshold(&self, param: u64) -> bool {
         param as f64 > self.alpha * self.k as f64
@@ -53,8 +238,319 @@ impl Default for ConsensusConfig {
             beta2: 2,
             k: 10,
             quantum: false,
+            dev: false,
             max_batch_size: 40,
             max_batch_interval: 2.0,
+            submission_window: 30.0,
+            mempool_capacity: 10000,
+            mempool_max_bytes: 64 * 1024 * 1024,
+            memory_budget: 256 * 1024 * 1024,
+            checkpoint_interval: 1000,
+            tip_revalidation_interval: 30.0,
+            tip_max_age: 300.0,
+            tip_selection: TipSelection::default(),
+            tip_parents: DEFAULT_TIP_PARENTS,
+            round_timeout: 10.0,
+            max_round_attempts: 3,
+            drop_timed_out: false,
         }
     }
 }
+
+/// Builder of validated [`ConsensusConfig`]s
+#[derive(Clone, Debug, Default)]
+pub struct ConsensusConfigBuilder {
+    config: ConsensusConfig,
+}
+
+impl ConsensusConfigBuilder {
+    /// Quorum fraction of a sample needed to accept, in (0.5, 1]
+    pub fn alpha(mut self, alpha: f64) -> Self {
+        self.config.alpha = alpha;
+        self
+    }
+
+    pub fn beta(mut self, beta: u64) -> Self {
+        self.config.beta = beta;
+        self
+    }
+
+    pub fn beta2(mut self, beta2: u64) -> Self {
+        self.config.beta2 = beta2;
+        self
+    }
+
+    /// Sample size of each query, at least 1
+    pub fn k(mut self, k: u64) -> Self {
+        self.config.k = k;
+        self
+    }
+
+    pub fn quantum(mut self, quantum: bool) -> Self {
+        self.config.quantum = quantum;
+        self
+    }
+
+    pub fn dev(mut self, dev: bool) -> Self {
+        self.config.dev = dev;
+        self
+    }
+
+    pub fn max_batch_size(mut self, max_batch_size: usize) -> Self {
+        self.config.max_batch_size = max_batch_size;
+        self
+    }
+
+    /// Interval between batched queries in seconds, must be positive
+    pub fn max_batch_interval(mut self, max_batch_interval: f32) -> Self {
+        self.config.max_batch_interval = max_batch_interval;
+        self
+    }
+
+    pub fn submission_window(mut self, submission_window: f32) -> Self {
+        self.config.submission_window = submission_window;
+        self
+    }
+
+    pub fn mempool_capacity(mut self, mempool_capacity: usize) -> Self {
+        self.config.mempool_capacity = mempool_capacity;
+        self
+    }
+
+    pub fn mempool_max_bytes(mut self, mempool_max_bytes: usize) -> Self {
+        self.config.mempool_max_bytes = mempool_max_bytes;
+        self
+    }
+
+    /// Bytes all in-flight state may take together, at least the mempool
+    /// cap
+    pub fn memory_budget(mut self, memory_budget: usize) -> Self {
+        self.config.memory_budget = memory_budget;
+        self
+    }
+
+    pub fn checkpoint_interval(mut self, checkpoint_interval: usize) -> Self {
+        self.config.checkpoint_interval = checkpoint_interval;
+        self
+    }
+
+    /// Seconds before an unreferenced tip is revalidated, must be positive
+    pub fn tip_revalidation_interval(mut self, tip_revalidation_interval: f32) -> Self {
+        self.config.tip_revalidation_interval = tip_revalidation_interval;
+        self
+    }
+
+    /// Seconds before an unreferenced tip expires, at least the revalidation
+    /// interval
+    pub fn tip_max_age(mut self, tip_max_age: f32) -> Self {
+        self.config.tip_max_age = tip_max_age;
+        self
+    }
+
+    pub fn tip_selection(mut self, tip_selection: TipSelection) -> Self {
+        self.config.tip_selection = tip_selection;
+        self
+    }
+
+    /// Parents new transactions build on, from 1 up to
+    /// [`MAX_TIP_PARENTS`]
+    pub fn tip_parents(mut self, tip_parents: usize) -> Self {
+        self.config.tip_parents = tip_parents;
+        self
+    }
+
+    /// Seconds to wait for the responses of a round, must be positive
+    pub fn round_timeout(mut self, round_timeout: f32) -> Self {
+        self.config.round_timeout = round_timeout;
+        self
+    }
+
+    /// Queries sent for a round before it times out, at least 1
+    pub fn max_round_attempts(mut self, max_round_attempts: usize) -> Self {
+        self.config.max_round_attempts = max_round_attempts;
+        self
+    }
+
+    pub fn drop_timed_out(mut self, drop_timed_out: bool) -> Self {
+        self.config.drop_timed_out = drop_timed_out;
+        self
+    }
+
+    /// Validate the parameters and build the config
+    pub fn build(self) -> Result<ConsensusConfig, ConfigError> {
+        self.config.validate()?;
+        Ok(self.config)
+    }
+}
+
+impl From<ConsensusConfig> for ConsensusConfigBuilder {
+    #[inline]
+    fn from(config: ConsensusConfig) -> Self {
+        Self { config }
+    }
+}
+
+/// Consensus parameters shared with running engines and tunable at runtime,
+/// e.g. to sample fewer peers under load. Clones share the same parameters.
+#[derive(Clone, Debug)]
+pub struct ConsensusParamsHandle {
+    config: Arc<RwLock<ConsensusConfig>>,
+    /// Told about every change
+    subscribers: Arc<Mutex<Vec<Sender<ConsensusConfig>>>>,
+}
+
+impl Default for ConsensusParamsHandle {
+    fn default() -> Self {
+        Self::new(ConsensusConfig::default())
+    }
+}
+
+impl ConsensusParamsHandle {
+    pub fn new(config: ConsensusConfig) -> Self {
+        Self {
+            config: Arc::new(RwLock::new(config)),
+            subscribers: Default::default(),
+        }
+    }
+
+    /// Parameters in effect
+    pub fn get(&self) -> ConsensusConfig {
+        self.config.read().unwrap().clone()
+    }
+
+    /// Receive the parameters every time they change
+    pub fn subscribe(&self) -> Receiver<ConsensusConfig> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    /// Change the parameters through a builder starting from the current
+    /// ones. Invalid parameters are refused, leaving the current ones in
+    /// effect.
+    pub fn update<F>(&self, change: F) -> Result<ConsensusConfig, ConfigError>
+    where
+        F: FnOnce(ConsensusConfigBuilder) -> ConsensusConfigBuilder,
+    {
+        let updated = {
+            let mut config = self.config.write().unwrap();
+            let updated = change(ConsensusConfigBuilder::from(config.clone())).build()?;
+            if updated == *config {
+                return Ok(updated);
+            }
+            *config = updated.clone();
+            updated
+        };
+        log::info!("Consensus parameters changed to {:?}", updated);
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|subscriber| subscriber.send(updated.clone()).is_ok());
+        Ok(updated)
+    }
+
+    pub fn set_k(&self, k: u64) -> Result<(), ConfigError> {
+        self.update(|builder| builder.k(k)).map(|_| ())
+    }
+
+    pub fn set_alpha(&self, alpha: f64) -> Result<(), ConfigError> {
+        self.update(|builder| builder.alpha(alpha)).map(|_| ())
+    }
+
+    pub fn set_max_batch_size(&self, max_batch_size: usize) -> Result<(), ConfigError> {
+        self.update(|builder| builder.max_batch_size(max_batch_size))
+            .map(|_| ())
+    }
+}
+
+#[test]
+fn test_consensus_config_validation() {
+    assert!(ConsensusConfig::builder().build().is_ok());
+    assert_eq!(
+        ConsensusConfig::builder().alpha(0.5).build(),
+        Err(ConfigError::InvalidAlpha(0.5))
+    );
+    assert!(ConsensusConfig::builder().alpha(1.0).build().is_ok());
+    assert!(ConsensusConfig::builder().alpha(f64::NAN).build().is_err());
+    assert_eq!(
+        ConsensusConfig::new(0.8, 2, 2, 0),
+        Err(ConfigError::InvalidSampleSize)
+    );
+    assert_eq!(
+        ConsensusConfig::builder().beta(0).build(),
+        Err(ConfigError::InvalidBeta { beta: 0, beta2: 2 })
+    );
+    assert_eq!(
+        ConsensusConfig::builder().max_batch_interval(0.0).build(),
+        Err(ConfigError::InvalidBatchInterval(0.0))
+    );
+    assert_eq!(
+        ConsensusConfig::builder().submission_window(-1.0).build(),
+        Err(ConfigError::InvalidSubmissionWindow(-1.0))
+    );
+    assert!(ConsensusConfig::builder()
+        .submission_window(f32::NAN)
+        .build()
+        .is_err());
+    assert!(ConsensusConfig::builder()
+        .submission_window(0.0)
+        .build()
+        .is_ok());
+    assert_eq!(
+        ConsensusConfig::builder().memory_budget(1024).build(),
+        Err(ConfigError::InvalidMemoryBudget {
+            budget: 1024,
+            mempool_max_bytes: 64 * 1024 * 1024
+        })
+    );
+    assert_eq!(
+        ConsensusConfig::builder().tip_max_age(10.0).build(),
+        Err(ConfigError::InvalidTipAge {
+            revalidation_interval: 30.0,
+            max_age: 10.0
+        })
+    );
+    assert_eq!(
+        ConsensusConfig::builder().tip_parents(0).build(),
+        Err(ConfigError::InvalidTipParents {
+            parents: 0,
+            max: MAX_TIP_PARENTS
+        })
+    );
+    assert_eq!(
+        ConsensusConfig::builder().max_round_attempts(0).build(),
+        Err(ConfigError::InvalidRoundDeadline {
+            timeout: 10.0,
+            attempts: 0
+        })
+    );
+
+    let config = ConsensusConfig::builder()
+        .k(20)
+        .quantum(true)
+        .build()
+        .unwrap();
+    assert_eq!(config.k(), 20);
+    assert!(config.is_quantum());
+}
+
+#[test]
+fn test_consensus_params_handle() {
+    let handle = ConsensusParamsHandle::default();
+    let engine = handle.clone();
+    let changes = handle.subscribe();
+
+    handle.set_k(20).unwrap();
+    assert_eq!(engine.get().k(), 20);
+    assert_eq!(changes.try_recv().unwrap().k(), 20);
+
+    // Invalid or unchanged parameters notify nobody
+    assert_eq!(handle.set_alpha(0.4), Err(ConfigError::InvalidAlpha(0.4)));
+    handle.set_k(20).unwrap();
+    assert!(changes.try_recv().is_err());
+    assert_eq!(engine.get().alpha(), ConsensusConfig::default().alpha());
+
+    handle.set_max_batch_size(10).unwrap();
+    let changed = changes.try_recv().unwrap();
+    assert_eq!((changed.k(), changed.max_batch_size()), (20, 10));
+}
@@ -0,0 +1,226 @@
+//! Parent and child links between transactions.
+//!
+//! A transaction names its parents, its parent followed by the tips it
+//! references, but nothing says which transactions build on it. The [`Dag`]
+//! indexes both directions, so that tip selection and explorers can walk
+//! the graph either way. Transactions may be added before their parents:
+//! they are linked to them once the parents are added too.
+
+use crate::{id::TxId, transaction::Transaction, ConsensusError};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+#[derive(Clone, Debug, Default)]
+struct DagVertex {
+    parents: Vec<TxId>,
+    children: Vec<TxId>,
+}
+
+/// Transactions indexed by their parents and children
+#[derive(Clone, Debug, Default)]
+pub struct Dag {
+    vertices: HashMap<TxId, DagVertex>,
+    /// Vertices in the order they were added, to iterate deterministically
+    order: Vec<TxId>,
+    /// Children of the parents not added yet
+    waiting: HashMap<TxId, Vec<TxId>>,
+}
+
+impl Dag {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.vertices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+
+    pub fn contains(&self, tx_id: &TxId) -> bool {
+        self.vertices.contains_key(tx_id)
+    }
+
+    /// Link a transaction to its parents and to its children added so far.
+    /// Returns false if it was added already.
+    pub fn add_vertex(&mut self, tx: &Transaction) -> Result<bool, ConsensusError> {
+        let tx_id = tx
+            .try_get_tx_id()
+            .ok_or(ConsensusError::MissingTransactionId)?;
+        if self.contains(&tx_id) {
+            return Ok(false);
+        }
+        let mut parents = vec![];
+        for parent in tx.parents() {
+            if *parent == tx_id || parents.contains(parent) {
+                continue;
+            }
+            parents.push(*parent);
+            match self.vertices.get_mut(parent) {
+                Some(vertex) => vertex.children.push(tx_id),
+                None => self.waiting.entry(*parent).or_default().push(tx_id),
+            }
+        }
+        let vertex = DagVertex {
+            parents,
+            children: self.waiting.remove(&tx_id).unwrap_or_default(),
+        };
+        let _ = self.vertices.insert(tx_id, vertex);
+        self.order.push(tx_id);
+        Ok(true)
+    }
+
+    /// Parents of a transaction, its parent first, whether they were added
+    /// or not. None if the transaction was not added.
+    pub fn parents_of(&self, tx_id: &TxId) -> Option<&[TxId]> {
+        self.vertices
+            .get(tx_id)
+            .map(|vertex| vertex.parents.as_slice())
+    }
+
+    /// Transactions added with `tx_id` among their parents, in the order
+    /// they were added
+    pub fn children_of(&self, tx_id: &TxId) -> Vec<TxId> {
+        match self.vertices.get(tx_id) {
+            Some(vertex) => vertex.children.clone(),
+            None => self.waiting.get(tx_id).cloned().unwrap_or_default(),
+        }
+    }
+
+    /// Transactions at most `depth` parent links away from `tx_id`, nearest
+    /// first. Parents that were not added end the walk.
+    pub fn ancestors(&self, tx_id: &TxId, depth: usize) -> Vec<TxId> {
+        let mut seen = HashSet::from([*tx_id]);
+        let mut ancestors = vec![];
+        let mut level = vec![*tx_id];
+        for _ in 0..depth {
+            let mut next = vec![];
+            for parent in level
+                .iter()
+                .filter_map(|tx_id| self.parents_of(tx_id))
+                .flatten()
+            {
+                if self.contains(parent) && seen.insert(*parent) {
+                    ancestors.push(*parent);
+                    next.push(*parent);
+                }
+            }
+            if next.is_empty() {
+                break;
+            }
+            level = next;
+        }
+        ancestors
+    }
+
+    /// Transactions confirming `tx_id` by building on it, directly or not,
+    /// plus one for itself
+    pub fn cumulative_weight(&self, tx_id: &TxId) -> usize {
+        let mut seen = HashSet::from([*tx_id]);
+        let mut queue = self.children_of(tx_id);
+        while let Some(child) = queue.pop() {
+            if seen.insert(child) {
+                queue.extend(self.children_of(&child));
+            }
+        }
+        seen.len()
+    }
+
+    /// Transactions no added transaction builds on yet
+    pub fn tips(&self) -> Vec<TxId> {
+        self.order
+            .iter()
+            .filter(|tx_id| self.vertices[*tx_id].children.is_empty())
+            .copied()
+            .collect()
+    }
+
+    /// Every transaction after all of its parents, ties in the order they
+    /// were added
+    pub fn topological(&self) -> impl Iterator<Item = TxId> + '_ {
+        let mut unvisited = self
+            .order
+            .iter()
+            .map(|tx_id| {
+                let parents = self.vertices[tx_id]
+                    .parents
+                    .iter()
+                    .filter(|parent| self.contains(parent))
+                    .count();
+                (*tx_id, parents)
+            })
+            .collect::<HashMap<_, _>>();
+        let mut ready = self
+            .order
+            .iter()
+            .filter(|tx_id| unvisited[*tx_id] == 0)
+            .copied()
+            .collect::<VecDeque<_>>();
+        std::iter::from_fn(move || {
+            let tx_id = ready.pop_front()?;
+            for child in &self.vertices[&tx_id].children {
+                let parents = unvisited.get_mut(child).unwrap();
+                *parents -= 1;
+                if *parents == 0 {
+                    ready.push_back(*child);
+                }
+            }
+            Some(tx_id)
+        })
+    }
+}
+
+#[test]
+fn test_dag_links_parents_and_children() {
+    use crate::{account::Account, amount::Amount, transaction::TransactionType};
+    use crypto::hash::Hash;
+
+    let origin = Account::create(&Hash::new("A".as_bytes()).into(), &TxId::default());
+    let tx = |parent: TxId, tips: Vec<TxId>, amount| {
+        let mut tx = Transaction::new(
+            parent,
+            origin.clone(),
+            Hash::new("B".as_bytes()).into(),
+            Amount::new(amount),
+            TransactionType::Transfer,
+            vec![],
+        );
+        let _ = tx.set_parents(std::iter::once(parent).chain(tips)).unwrap();
+        tx.calculate_tx_id().unwrap();
+        tx
+    };
+    let root = tx(TxId::default(), vec![], 1);
+    let left = tx(root.get_tx_id(), vec![], 2);
+    let right = tx(root.get_tx_id(), vec![], 3);
+    let merge = tx(left.get_tx_id(), vec![right.get_tx_id()], 4);
+    let [root_id, left_id, right_id, merge_id] =
+        [&root, &left, &right, &merge].map(Transaction::get_tx_id);
+
+    let mut dag = Dag::new();
+    // Added before one of its parents, and linked to it later on
+    assert!(dag.add_vertex(&merge).unwrap());
+    assert!(dag.add_vertex(&root).unwrap());
+    assert!(dag.add_vertex(&left).unwrap());
+    assert!(dag.add_vertex(&right).unwrap());
+    assert!(!dag.add_vertex(&right).unwrap());
+    assert_eq!(dag.len(), 4);
+
+    assert_eq!(dag.parents_of(&merge_id).unwrap(), &[left_id, right_id]);
+    assert_eq!(dag.children_of(&root_id), vec![left_id, right_id]);
+    assert_eq!(dag.children_of(&left_id), vec![merge_id]);
+    assert_eq!(dag.children_of(&right_id), vec![merge_id]);
+    assert_eq!(dag.tips(), vec![merge_id]);
+    assert_eq!(dag.cumulative_weight(&root_id), 4);
+    assert_eq!(dag.cumulative_weight(&merge_id), 1);
+
+    assert_eq!(dag.ancestors(&merge_id, 1), vec![left_id, right_id]);
+    assert_eq!(
+        dag.ancestors(&merge_id, 5),
+        vec![left_id, right_id, root_id]
+    );
+    assert!(dag.ancestors(&root_id, 5).is_empty());
+
+    let order = dag.topological().collect::<Vec<_>>();
+    assert_eq!(order, vec![root_id, left_id, right_id, merge_id]);
+}
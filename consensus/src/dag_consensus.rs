@@ -1,19 +1,294 @@
 use crate::{
-    account::AccountStateChoice,
-    config::ConsensusConfig,
+    account::{AccountStateChoice, SequenceTracker},
+    budget::{MemoryBudget, Resource},
+    config::{ConsensusConfig, ConsensusParamsHandle},
+    decision,
+    drain::{self, EngineState},
+    id::TxId,
+    inspect::{CandidateReport, ConflictReport},
     network::{CommonConsensusNetwork, ConsensusNetwork},
+    time::{Clock, SystemClock},
     transaction::Transaction,
     tree::HashTreeNode,
     AccountConflictSet, Consensus, ConsensusStatus,
 };
 use crypto::hash::Hash;
+use metrics::Metrics;
 use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+/// A round in flight
+struct Round {
+    started: Instant,
+    /// When the responses of the latest queries are due
+    deadline: Instant,
+    /// Queries sent so far
+    attempts: usize,
+    /// What to query again when the responses are overdue, for rounds
+    /// resolved once they arrive
+    query: Option<(AccountStateChoice, Transaction, usize)>,
+    /// Set once the round timed out, until responses arriving late are
+    /// no longer expected
+    timed_out: bool,
+}
 
 pub struct DagConsensus {
     conflict_set: Arc<RwLock<AccountConflictSet>>,
-    choice: Arc<RwLock<HashMap<Hash, Hash>>>,
-    config: ConsensusConfig,
+    choice: Arc<RwLock<HashMap<Hash, TxId>>>,
+    sequences: Arc<SequenceTracker>,
+    /// Rounds in flight, to measure acceptance latency and time them out
+    rounds: Arc<RwLock<HashMap<TxId, Round>>>,
+    /// Rounds that reached the quorum and rounds resolved, per candidate
+    progress: Arc<RwLock<HashMap<TxId, (u64, u64)>>>,
+    metrics: Arc<Metrics>,
+    clock: Arc<dyn Clock>,
+    budget: MemoryBudget,
+    /// Read at the start of every round, so that changes apply to the
+    /// rounds started afterwards
+    params: ConsensusParamsHandle,
+}
+
+/// Estimated memory taken up by a conflict set
+fn conflict_set_size(conflict_set: &AccountConflictSet) -> usize {
+    conflict_set
+        .values()
+        .map(|set| std::mem::size_of::<Hash>() + set.len() * std::mem::size_of::<TxId>())
+        .sum()
+}
+
+impl DagConsensus {
+    /// Set the metrics updated by the engine
+    pub fn set_metrics(&mut self, metrics: Arc<Metrics>) -> &mut Self {
+        self.metrics = metrics;
+        self
+    }
+
+    pub fn metrics(&self) -> &Arc<Metrics> {
+        &self.metrics
+    }
+
+    /// Time rounds with `clock`, e.g. a manual one in tests
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) -> &mut Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Use parameters tuned through `params` from now on
+    pub fn set_params(&mut self, params: ConsensusParamsHandle) -> &mut Self {
+        self.params = params;
+        self
+    }
+
+    pub fn params(&self) -> &ConsensusParamsHandle {
+        &self.params
+    }
+
+    /// Count the conflict sets against `budget`, shared with the rest of the
+    /// in-flight state. They aren't shed once it is exhausted, as dropping
+    /// transactions peers query us about would stall their rounds:
+    /// submissions are refused instead.
+    pub fn set_budget(&mut self, budget: MemoryBudget) -> &mut Self {
+        self.budget = budget;
+        self.report_usage(&self.conflict_set.read().unwrap());
+        self
+    }
+
+    pub fn budget(&self) -> &MemoryBudget {
+        &self.budget
+    }
+
+    fn report_usage(&self, conflict_set: &AccountConflictSet) {
+        self.budget
+            .set_used(Resource::ConflictSets, conflict_set_size(conflict_set));
+    }
+
+    fn start_round(&self, tx_id: &TxId, query: Option<(AccountStateChoice, Transaction, usize)>) {
+        let now = self.clock.now();
+        let timeout = Duration::from_secs_f32(self.params.get().round_timeout);
+        let mut rounds = self.rounds.write().unwrap();
+        match rounds.get_mut(tx_id) {
+            Some(round) if !round.timed_out => {}
+            _ => {
+                let _ = rounds.insert(
+                    *tx_id,
+                    Round {
+                        started: now,
+                        deadline: now + timeout,
+                        attempts: 1,
+                        query,
+                        timed_out: false,
+                    },
+                );
+            }
+        }
+    }
+
+    /// Transactions of timed out rounds leave the conflict sets, so that
+    /// they can be queued again
+    fn expire_overdue<T, N>(
+        &self,
+        network: &mut T,
+        common_network: &mut N,
+    ) -> Vec<(AccountStateChoice, ConsensusStatus)>
+    where
+        T: ConsensusNetwork,
+        N: CommonConsensusNetwork,
+    {
+        let config = self.params.get();
+        let now = self.clock.now();
+        let timeout = Duration::from_secs_f32(config.round_timeout);
+        let mut resampled = vec![];
+        let mut timed_out = vec![];
+        self.rounds.write().unwrap().retain(|tx_id, round| {
+            if round.deadline > now {
+                return true;
+            }
+            if round.timed_out {
+                return false;
+            }
+            let query = match &round.query {
+                Some(query) => query,
+                None => return true,
+            };
+            round.deadline = now + timeout;
+            if round.attempts < config.max_round_attempts {
+                round.attempts += 1;
+                resampled.push(query.clone());
+            } else {
+                log::warn!("Round of {:?} timed out", tx_id);
+                timed_out.push(round.query.take().unwrap().0);
+                round.timed_out = true;
+            }
+            true
+        });
+
+        for (state, tx, count) in resampled {
+            let _span = crate::round_span(&tx.get_tx_id()).entered();
+            tracing::debug!("Resampling peers");
+            network.send_dag_queries_batched(
+                config.k,
+                &tx,
+                &state,
+                common_network,
+                config.max_batch_size,
+                config.max_batch_interval,
+                count,
+            );
+        }
+        if !timed_out.is_empty() {
+            let mut conflict_set = self.conflict_set.write().unwrap();
+            for state in &timed_out {
+                if let Some(set) = conflict_set.get_mut(&state.account_state_id) {
+                    let _ = set.remove(&state.tx.get_tx_id());
+                    if set.is_empty() {
+                        let _ = conflict_set.remove(&state.account_state_id);
+                    }
+                }
+            }
+            self.report_usage(&conflict_set);
+            self.forget_progress(&conflict_set);
+        }
+        timed_out
+            .into_iter()
+            .map(|state| {
+                self.metrics.round_timed_out();
+                let status = ConsensusStatus::TimedOut(state.tx.get_tx_id().into());
+                (state, status)
+            })
+            .collect()
+    }
+
+    /// Count a resolved round of `tx_id` towards its progress
+    fn count_round(&self, tx_id: TxId, quorum: bool) {
+        let mut progress = self.progress.write().unwrap();
+        let (confidence, rounds) = progress.entry(tx_id).or_default();
+        *confidence += quorum as u64;
+        *rounds += 1;
+    }
+
+    /// Drop the progress of candidates no longer in `conflict_set`
+    fn forget_progress(&self, conflict_set: &AccountConflictSet) {
+        self.progress
+            .write()
+            .unwrap()
+            .retain(|tx_id, _| conflict_set.values().any(|set| set.contains(tx_id)));
+    }
+
+    fn record_round(&self, state: &AccountStateChoice, status: &ConsensusStatus) {
+        let tx_id = state.tx.get_tx_id();
+        let started = self
+            .rounds
+            .write()
+            .unwrap()
+            .remove(&tx_id)
+            .map(|round| round.started);
+        match status {
+            ConsensusStatus::Accept(accepted) => {
+                if *accepted == tx_id {
+                    self.sequences.advance(&state.tx);
+                }
+                let now = self.clock.now();
+                self.metrics.round_accepted(
+                    started
+                        .map(|at| now.saturating_duration_since(at))
+                        .unwrap_or_default(),
+                )
+            }
+            ConsensusStatus::Reject => self.metrics.round_rejected(),
+            _ => {}
+        }
+    }
+
+    fn resolve_round(
+        &self,
+        acceptance: usize,
+        state: &AccountStateChoice,
+        tree: &mut HashTreeNode,
+    ) -> ConsensusStatus {
+        let config = self.params.get();
+        tracing::debug!(acceptance, "Resolving round");
+        self.decide(&config, acceptance as u64, state, tree)
+    }
+
+    fn run_round<T, N>(
+        &self,
+        state: &AccountStateChoice,
+        network: &mut T,
+        common_network: &mut N,
+        tree: Option<&mut HashTreeNode>,
+    ) -> ConsensusStatus
+    where
+        T: ConsensusNetwork,
+        N: CommonConsensusNetwork,
+    {
+        let config = self.params.get();
+        self.query(state);
+        let tree = tree.unwrap();
+
+        let p = network.dag_query(config.k, state, common_network);
+        tracing::debug!(acceptance = p, "Queried the sample");
+        self.decide(&config, p, state, tree)
+    }
+
+    /// Count the round towards the progress of the candidate, and resolve it
+    /// through the network-free core
+    fn decide(
+        &self,
+        config: &ConsensusConfig,
+        acceptance: u64,
+        state: &AccountStateChoice,
+        tree: &mut HashTreeNode,
+    ) -> ConsensusStatus {
+        self.count_round(state.tx.get_tx_id(), config.threshold(acceptance));
+        decision::decide(
+            config,
+            &mut self.choice.write().unwrap(),
+            tree,
+            state,
+            acceptance,
+        )
+    }
 }
 
 impl Consensus for DagConsensus {
@@ -24,7 +299,13 @@ impl Consensus for DagConsensus {
         Self {
             conflict_set: Arc::new(RwLock::new(HashMap::new())),
             choice: Arc::new(RwLock::new(HashMap::new())),
-            config,
+            sequences: Arc::new(SequenceTracker::default()),
+            rounds: Arc::new(RwLock::new(HashMap::new())),
+            progress: Arc::new(RwLock::new(HashMap::new())),
+            metrics: Arc::new(Metrics::new()),
+            clock: Arc::new(SystemClock),
+            budget: MemoryBudget::unlimited(),
+            params: ConsensusParamsHandle::new(config),
         }
     }
 
@@ -37,10 +318,11 @@ impl Consensus for DagConsensus {
             if let Some(set) = conflict_set.get_mut(&state.account_state_id) {
                 set.insert(state.tx.get_tx_id());
             } else {
-                let mut set: HashSet<Hash> = HashSet::new();
+                let mut set: HashSet<TxId> = HashSet::new();
                 set.insert(state.tx.get_tx_id());
                 conflict_set.insert(state.account_state_id, set);
             }
+            self.report_usage(&conflict_set);
         }
         self
     }
@@ -56,14 +338,17 @@ impl Consensus for DagConsensus {
         T: ConsensusNetwork,
         N: CommonConsensusNetwork,
     {
+        let _span = crate::round_span(&tx.get_tx_id()).entered();
+        let config = self.params.get();
         self.query(state);
+        self.start_round(&tx.get_tx_id(), Some((state.clone(), tx.clone(), count)));
         network.send_dag_queries_batched(
-            self.config.k,
+            config.k,
             tx,
-            &state,
+            state,
             common_network,
-            self.config.max_batch_size,
-            self.config.max_batch_interval,
+            config.max_batch_size,
+            config.max_batch_interval,
             count,
         );
     }
@@ -74,53 +359,19 @@ impl Consensus for DagConsensus {
         state: &AccountStateChoice,
         tree: &mut HashTreeNode,
     ) -> ConsensusStatus {
-        log::info!("ACCEPTANCE: {}", acceptance as u64);
-        if self.config.threshold(acceptance as u64) {
-            log::info!("PRINT: fire_consensus: #5");
-            {
-                let mut store = self.choice.write().unwrap();
-                if store.get(&state.account_state_id).is_some() {
-                    log::error!("REJECT: account state doesn't exist");
-                    return ConsensusStatus::Reject;
-                }
-                store.insert(state.account_state_id, state.tx.get_tx_id());
-            }
-
-            let mut parent_hash = state.tx.parent;
-            log::info!(
-                "PRINT: fire_consensus: #6 {:#?} {:#?}",
-                tree.get(&parent_hash),
-                parent_hash
-            );
-            while let Some(path) = tree.get(&parent_hash) {
-                log::info!("PRINT:fire_consensus: #7 parent_hash {:#?}", path.0);
-                parent_hash = path.0;
-                let mut node = path.clone().1;
-                if let Some(preferred_confidence) = tree.get(&node.preferred) {
-                    let preferred_confidence = preferred_confidence.clone().1;
-                    if node.confidence > preferred_confidence.confidence {
-                        node.preferred = node.node;
-                    }
-                    if node.node != node.last {
-                        node.last = node.node;
-                        node.count = 0;
-                    } else {
-                        node.count += 1;
-                    }
-                }
-                let updated_node = (parent_hash, node.clone());
-                *tree.entry(parent_hash).or_insert(updated_node) = updated_node.clone();
-                if node.confidence > self.config.beta {
-                    return ConsensusStatus::Accept(node.node);
-                }
-                if node.count > self.config.beta2 {
-                    return ConsensusStatus::Accept(state.tx.get_tx_id());
-                }
-            }
-            log::error!("REJECT: Reached threshold but not accepted")
+        let tx_id = state.tx.get_tx_id();
+        let _span = crate::round_span(&tx_id).entered();
+        let timed_out = matches!(
+            self.rounds.read().unwrap().get(&tx_id),
+            Some(round) if round.timed_out
+        );
+        if timed_out {
+            let _ = self.rounds.write().unwrap().remove(&tx_id);
+            return ConsensusStatus::TimedOut(tx_id.into());
         }
-        log::error!("REJECT: Request not accepted");
-        ConsensusStatus::Reject
+        let status = self.resolve_round(acceptance, state, tree);
+        self.record_round(state, &status);
+        status
     }
 
     fn fire_consensus<T, N>(
@@ -134,77 +385,45 @@ impl Consensus for DagConsensus {
         T: ConsensusNetwork,
         N: CommonConsensusNetwork,
     {
-        self.query(state);
-        let tree = tree.unwrap();
+        let _span = crate::round_span(&state.tx.get_tx_id()).entered();
+        self.start_round(&state.tx.get_tx_id(), None);
+        let status = self.run_round(state, network, common_network, tree);
+        self.record_round(state, &status);
+        status
+    }
 
-        log::info!("PRINT: fire_consensus: #3");
-        let p = network.dag_query(self.config.k, &state, common_network);
-        log::info!("PRINT: fire_consensus: #4 {:?}", p);
-        if self.config.threshold(p) {
-            log::info!("PRINT: fire_consensus: #5");
-            {
-                let mut store = self.choice.write().unwrap();
-                if store.get(&state.account_state_id).is_some() {
-                    return ConsensusStatus::Reject;
-                }
-                store.insert(state.account_state_id, state.tx.get_tx_id());
-            }
+    fn expire_rounds<T, N>(
+        &self,
+        network: &mut T,
+        common_network: &mut N,
+    ) -> Vec<(AccountStateChoice, ConsensusStatus)>
+    where
+        T: ConsensusNetwork,
+        N: CommonConsensusNetwork,
+    {
+        self.expire_overdue(network, common_network)
+    }
 
-            let mut parent_hash = state.tx.parent;
-            // Fetch Tree
-            log::info!(
-                "PRINT: fire_consensus: #6 {:#?} {:#?}",
-                tree.get(&parent_hash),
-                parent_hash
+    fn on_query(&self, state: &AccountStateChoice) -> (TxId, bool) {
+        let _span = crate::round_span(&state.tx.get_tx_id()).entered();
+        if !self.sequences.is_next(&state.tx) {
+            log::warn!(
+                "Replayed or out-of-order transaction {:?}",
+                state.tx.get_tx_id()
             );
-            while let Some(path) = tree.get(&parent_hash) {
-                log::info!("PRINT:fire_consensus: #7 parent_hash {:#?}", path.0);
-                parent_hash = path.0;
-                let mut node = path.clone().1;
-                if let Some(preferred_confidence) = tree.get(&node.preferred) {
-                    let preferred_confidence = preferred_confidence.clone().1;
-                    // Compare Confidence Tree
-                    if node.confidence > preferred_confidence.confidence {
-                        node.preferred = node.node;
-                    }
-                    if node.node != node.last {
-                        node.last = node.node;
-                        node.count = 0;
-                    } else {
-                        node.count += 1;
-                    }
-                }
-                // Update Tree Node state
-                let updated_node = (parent_hash, node.clone());
-                *tree.entry(parent_hash).or_insert(updated_node) = updated_node.clone();
-                // Check early commitment
-                if node.confidence > self.config.beta {
-                    return ConsensusStatus::Accept(node.node);
-                }
-                // Check consecutive counter commitment
-                if node.count > self.config.beta2 {
-                    return ConsensusStatus::Accept(state.tx.get_tx_id());
-                }
-            }
+            return (state.tx.get_tx_id(), false);
         }
-        // If request was not accepted return Reject
-        ConsensusStatus::Reject
-    }
-
-    fn on_query(&self, state: &AccountStateChoice) -> (Hash, bool) {
-        log::info!("PRINT: on_query: {:?}", state);
         let exists = if let Some(set) = self
             .conflict_set
             .write()
             .unwrap()
             .get(&state.account_state_id)
         {
-            log::info!("PRINT: on_query: is_some {:?}", state);
             set.get(&state.tx.get_tx_id()).is_some()
         } else {
             false
         };
-        log::info!("PRINT: on_query: exists {:?}", exists);
+        tracing::trace!(exists, "Answering query");
         if let Some(choice) = self.choice.write().unwrap().get(&state.account_state_id) {
             return (*choice, exists);
         }
@@ -212,6 +431,258 @@ impl Consensus for DagConsensus {
     }
 
     fn target_count(&self) -> usize {
-        self.config.k as usize
+        self.params.get().k as usize
+    }
+
+    fn conflict_set(&self) -> AccountConflictSet {
+        self.conflict_set.read().unwrap().clone()
+    }
+
+    fn conflicts_for(&self, account_state_id: &Hash) -> Option<ConflictReport> {
+        let set = self
+            .conflict_set
+            .read()
+            .unwrap()
+            .get(account_state_id)?
+            .clone();
+        let progress = self.progress.read().unwrap();
+        let rounds = self.rounds.read().unwrap();
+        let mut candidates = set
+            .into_iter()
+            .map(|tx_id| {
+                let (confidence, resolved) = progress.get(&tx_id).copied().unwrap_or_default();
+                let attempts = rounds
+                    .get(&tx_id)
+                    .filter(|round| !round.timed_out)
+                    .map_or(0, |round| round.attempts);
+                CandidateReport {
+                    tx_id,
+                    confidence,
+                    rounds: resolved,
+                    attempts,
+                }
+            })
+            .collect::<Vec<_>>();
+        candidates.sort_by_key(|candidate| candidate.tx_id);
+        Some(ConflictReport {
+            account_state: *account_state_id,
+            choice: self.choice.read().unwrap().get(account_state_id).copied(),
+            candidates,
+        })
+    }
+
+    fn prune(&self, finalized: &HashSet<TxId>) {
+        {
+            let mut conflict_set = self.conflict_set.write().unwrap();
+            drain::prune_conflict_set(&mut conflict_set, finalized);
+            self.report_usage(&conflict_set);
+            self.forget_progress(&conflict_set);
+        }
+        self.rounds
+            .write()
+            .unwrap()
+            .retain(|tx_id, _| !finalized.contains(tx_id));
+    }
+
+    fn export_state(&self) -> EngineState {
+        EngineState::export(&self.conflict_set, &self.choice, &self.sequences)
     }
+
+    fn import_state(&self, state: &EngineState) {
+        state.merge_into(&self.conflict_set, &self.choice, &self.sequences);
+        self.report_usage(&self.conflict_set.read().unwrap());
+    }
+}
+
+#[test]
+fn test_params_tuned_at_runtime() {
+    let params = ConsensusParamsHandle::default();
+    let mut engine = DagConsensus::new(ConsensusConfig::default());
+    let _ = engine.set_params(params.clone());
+    assert_eq!(engine.target_count(), 10);
+    params.set_k(4).unwrap();
+    assert_eq!(engine.target_count(), 4);
+}
+
+#[cfg(test)]
+#[derive(Default)]
+struct PendingNetwork {
+    batches: usize,
+}
+
+#[cfg(test)]
+impl CommonConsensusNetwork for PendingNetwork {
+    fn get_nodes_except_one(&self, _k: u64, _node_id: crate::NodeId) -> Vec<crate::NodeId> {
+        vec![]
+    }
+}
+
+/// Queries whose responses never arrive
+#[cfg(test)]
+impl ConsensusNetwork for PendingNetwork {
+    fn get_sample_network<T: CommonConsensusNetwork>(
+        &self,
+        _k: u64,
+        _current_node: crate::NodeId,
+        _network: &T,
+    ) -> Vec<crate::NodeId> {
+        vec![]
+    }
+
+    fn request_consensus(&mut self, _node_id: crate::NodeId, data: &AccountStateChoice) -> TxId {
+        data.tx.get_tx_id()
+    }
+
+    fn request_dag_consensus(&self, _node_id: crate::NodeId, _data: &AccountStateChoice) -> bool {
+        false
+    }
+
+    fn send_dag_consensus_request(
+        &mut self,
+        _node_id: crate::NodeId,
+        _data: &AccountStateChoice,
+        _tx: &Transaction,
+        _count: usize,
+    ) {
+    }
+
+    fn add_outgoing_dag_consensus_request(
+        &mut self,
+        _node_id: crate::NodeId,
+        _data: &AccountStateChoice,
+        _tx: &Transaction,
+        _count: usize,
+    ) {
+    }
+
+    fn accept_incoming_consensus_response(
+        &mut self,
+        _node_id: crate::NodeId,
+        _data: TxId,
+        _accepted: bool,
+    ) -> (usize, usize) {
+        (0, 0)
+    }
+
+    fn remove_outgoing_dag_transaction(&mut self, _tx_id: TxId) -> Option<Transaction> {
+        None
+    }
+
+    fn get_node_id(&self) -> crate::NodeId {
+        crate::NodeId::default()
+    }
+
+    fn add_transaction_to_batch<N: CommonConsensusNetwork>(
+        &mut self,
+        _k: u64,
+        _tx: &Transaction,
+        _data: &AccountStateChoice,
+        _network: &N,
+        _max_batch_size: usize,
+        _max_batch_interval: f32,
+        _count: usize,
+    ) {
+        self.batches += 1;
+    }
+}
+
+#[test]
+fn test_unanswered_rounds_time_out() {
+    use crate::{
+        account::Account, amount::Amount, mempool::Mempool, time::ManualClock,
+        transaction::TransactionType,
+    };
+
+    let config = ConsensusConfig::builder()
+        .round_timeout(1.0)
+        .max_round_attempts(2)
+        .build()
+        .unwrap();
+    let clock = Arc::new(ManualClock::new());
+    let mut engine = DagConsensus::new(config.clone());
+    let _ = engine.set_clock(clock.clone());
+    let mut mempool = Mempool::from_config(&config);
+    let mut network = PendingNetwork::default();
+    let origin = Account::create(&Hash::new("A".as_bytes()).into(), &Hash::default().into());
+    let mut tx = Transaction::new(
+        Hash::default().into(),
+        origin,
+        Hash::new("B".as_bytes()).into(),
+        Amount::new(1),
+        TransactionType::Transfer,
+        vec![],
+    );
+    tx.calculate_tx_id().unwrap();
+    let state = AccountStateChoice::new(Hash::default(), &tx);
+
+    engine.send_consensus_requests(&state, &tx, &mut network, &mut PendingNetwork::default(), 0);
+    assert_eq!(network.batches, 1);
+    let mut expire = |engine: &DagConsensus, mempool: &mut Mempool| {
+        mempool.expire_rounds(engine, &mut network, &mut PendingNetwork::default())
+    };
+    assert!(expire(&engine, &mut mempool).is_empty());
+
+    // Overdue responses are queried from a fresh sample
+    clock.advance(Duration::from_secs(1));
+    assert!(expire(&engine, &mut mempool).is_empty());
+    // Then the round times out, and its transaction is queued again
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(
+        expire(&engine, &mut mempool),
+        vec![(
+            tx.get_tx_id(),
+            ConsensusStatus::TimedOut(tx.get_tx_id().into())
+        )]
+    );
+    assert_eq!(network.batches, 2);
+    assert!(engine.conflict_set().is_empty());
+    assert!(mempool.contains(&tx.get_tx_id()));
+    assert_eq!(engine.metrics().snapshot().timed_out, 1);
+
+    // Responses arriving late don't resolve the round
+    let status = engine.complete_dag_consensus(10, &state, &mut HashMap::new());
+    assert_eq!(status, ConsensusStatus::TimedOut(tx.get_tx_id().into()));
+}
+
+#[test]
+fn test_conflicts_report_round_progress() {
+    use crate::{account::Account, amount::Amount, transaction::TransactionType};
+
+    let engine = DagConsensus::new(ConsensusConfig::default());
+    let mut network = PendingNetwork::default();
+    let account_state = Hash::new("state".as_bytes());
+    let spend = |to: &str| {
+        let origin = Account::create(&Hash::new("A".as_bytes()).into(), &Hash::default().into());
+        let mut tx = Transaction::new(
+            Hash::default().into(),
+            origin,
+            Hash::new(to.as_bytes()).into(),
+            Amount::new(1),
+            TransactionType::Transfer,
+            vec![],
+        );
+        tx.calculate_tx_id().unwrap();
+        (AccountStateChoice::new(account_state, &tx), tx)
+    };
+    let (first, first_tx) = spend("B");
+    let (second, second_tx) = spend("C");
+    assert!(engine.conflicts_for(&account_state).is_none());
+
+    for (state, tx) in [(&first, &first_tx), (&second, &second_tx)] {
+        engine.send_consensus_requests(state, tx, &mut network, &mut PendingNetwork::default(), 0);
+    }
+    let _ = engine.complete_dag_consensus(10, &first, &mut HashMap::new());
+    let _ = engine.complete_dag_consensus(0, &second, &mut HashMap::new());
+
+    let report = engine.conflicts_for(&account_state).unwrap();
+    assert_eq!(report.choice, Some(first_tx.get_tx_id()));
+    assert_eq!(report.candidates.len(), 2);
+    let first = report.candidate(&first_tx.get_tx_id()).unwrap();
+    assert_eq!((first.confidence, first.rounds), (1, 1));
+    let second = report.candidate(&second_tx.get_tx_id()).unwrap();
+    assert_eq!((second.confidence, second.rounds), (0, 1));
+
+    engine.prune(&[first_tx.get_tx_id()].into());
+    assert!(engine.conflicts_for(&account_state).is_none());
+    assert!(engine.progress.read().unwrap().is_empty());
 }
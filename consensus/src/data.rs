@@ -0,0 +1,239 @@
+//! Key/value entries accounts store in their own state, e.g. to anchor the
+//! hash of a document on the DAG.
+//!
+//! An account writes an entry with a `StoreData` transaction carrying a
+//! [`DataEntry`] as its payload. Entries are size-limited and paid for by
+//! the byte: the fee of the transaction must cover [`DataEntry::rent`],
+//! and is charged to the origin once the entry is written. Writing a key
+//! again replaces its value. A [`DataStore`] keeps the entries through a
+//! [`Storage`], so that they can be served after restarts of the node.
+
+use crate::{
+    amount::Amount,
+    id::{AccountId, TxId},
+    ConsensusError,
+};
+use crypto::{hash::Hash, merkle::MerkleTree};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use storage::{Storage, TypedStore};
+
+/// Longest key of an entry, in bytes
+pub const MAX_DATA_KEY_LEN: usize = 64;
+/// Longest value of an entry, in bytes
+pub const MAX_DATA_VALUE_LEN: usize = 1024;
+/// Rent of an entry per byte of its key and value
+pub const DATA_RENT_PER_BYTE: Amount = Amount::new(1_000);
+
+/// Keyspace of the entries
+const DATA_TREE: &str = "data";
+/// Schema version of the stored entries
+const DATA_VERSION: u32 = 1;
+
+/// Payload of a `StoreData` transaction
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct DataEntry {
+    pub key: String,
+    pub value: Vec<u8>,
+}
+
+impl DataEntry {
+    pub fn new(key: impl Into<String>, value: Vec<u8>) -> Self {
+        Self {
+            key: key.into(),
+            value,
+        }
+    }
+
+    /// Build the payload of a `StoreData` transaction
+    pub fn to_payload(&self) -> Result<Vec<u8>, ConsensusError> {
+        bincode::serialize(self).map_err(|e| ConsensusError::SerializationError(e.to_string()))
+    }
+
+    /// Read the entry in the payload of a `StoreData` transaction, checking
+    /// its size
+    pub fn from_payload(payload: &[u8]) -> Result<Self, ConsensusError> {
+        let entry = bincode::deserialize::<Self>(payload)
+            .map_err(|e| ConsensusError::SerializationError(e.to_string()))?;
+        entry.check()?;
+        Ok(entry)
+    }
+
+    /// Check that the key is not empty, and that neither the key nor the
+    /// value is too long
+    pub fn check(&self) -> Result<(), ConsensusError> {
+        if self.key.is_empty() || self.key.len() > MAX_DATA_KEY_LEN {
+            return Err(ConsensusError::InvalidDataKey {
+                len: self.key.len(),
+                max: MAX_DATA_KEY_LEN,
+            });
+        }
+        if self.value.len() > MAX_DATA_VALUE_LEN {
+            return Err(ConsensusError::DataValueTooLong {
+                len: self.value.len(),
+                max: MAX_DATA_VALUE_LEN,
+            });
+        }
+        Ok(())
+    }
+
+    /// Bytes paid for
+    pub fn size(&self) -> usize {
+        self.key.len() + self.value.len()
+    }
+
+    /// Least fee of the transaction storing the entry
+    pub fn rent(&self) -> Amount {
+        DATA_RENT_PER_BYTE
+            .checked_mul(self.size() as u128)
+            .unwrap_or(Amount::MAX)
+    }
+}
+
+/// Entry written by an account, along with the transaction that wrote it
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct StoredData {
+    pub account_id: AccountId,
+    pub key: String,
+    pub value: Vec<u8>,
+    pub tx_id: TxId,
+}
+
+impl StoredData {
+    /// Key of the entry in a [`DataStore`]
+    fn storage_key(account_id: &AccountId, key: &str) -> Hash {
+        let mut bytes = account_id.as_ref().to_vec();
+        bytes.extend_from_slice(key.as_bytes());
+        Hash::new(&bytes)
+    }
+}
+
+/// Entries of the accounts, by account and key
+#[derive(Clone, Debug, Default)]
+pub struct DataBook {
+    entries: BTreeMap<(AccountId, String), StoredData>,
+}
+
+impl DataBook {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, account_id: &AccountId, key: &str) -> Option<&StoredData> {
+        self.entries.get(&(*account_id, key.to_string()))
+    }
+
+    /// Entries of an account, sorted by key
+    pub fn of_account<'a>(
+        &'a self,
+        account_id: &'a AccountId,
+    ) -> impl Iterator<Item = &'a StoredData> + 'a {
+        self.entries
+            .range((*account_id, String::new())..)
+            .take_while(move |((id, _), _)| id == account_id)
+            .map(|(_, data)| data)
+    }
+
+    /// Write an entry, replacing the previous value of its key
+    pub fn insert(&mut self, data: StoredData) {
+        let _ = self
+            .entries
+            .insert((data.account_id, data.key.clone()), data);
+    }
+
+    /// Root of a Merkle tree over the entries, sorted by account and key
+    pub fn root(&self) -> Hash {
+        let leaves = self.entries.values().map(entry_leaf).collect::<Vec<_>>();
+        MerkleTree::new(&leaves).root()
+    }
+}
+
+/// Tree leaf of an entry. Key and value are prefixed with their length, so
+/// that no entry hashes like another.
+fn entry_leaf(data: &StoredData) -> Hash {
+    let mut bytes = data.account_id.as_ref().to_vec();
+    bytes.extend_from_slice(&(data.key.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(data.key.as_bytes());
+    bytes.extend_from_slice(&(data.value.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(&data.value);
+    bytes.extend_from_slice(data.tx_id.as_ref());
+    Hash::new(&bytes)
+}
+
+/// Entries persisted through a [`Storage`], by account and key
+pub struct DataStore<S: Storage> {
+    entries: TypedStore<S, StoredData>,
+}
+
+impl<S: Storage> DataStore<S> {
+    /// Keep the entries in their own keyspace of `storage`
+    pub fn open(storage: &S) -> Result<Self, ConsensusError> {
+        Ok(Self {
+            entries: TypedStore::open(storage, DATA_TREE, DATA_VERSION)?,
+        })
+    }
+
+    /// Store an entry, replacing the previous value of its key
+    pub fn record(&mut self, data: &StoredData) -> Result<(), ConsensusError> {
+        let key = StoredData::storage_key(&data.account_id, &data.key);
+        Ok(self.entries.put(&key, data)?)
+    }
+
+    pub fn get(
+        &self,
+        account_id: &AccountId,
+        key: &str,
+    ) -> Result<Option<StoredData>, ConsensusError> {
+        Ok(self
+            .entries
+            .get(&StoredData::storage_key(account_id, key))?)
+    }
+
+    pub fn flush(&mut self) -> Result<(), ConsensusError> {
+        Ok(self.entries.flush()?)
+    }
+}
+
+#[test]
+fn test_data_entries_survive_the_store() {
+    use storage::memory::MemoryStorage;
+
+    let account_id = AccountId::from(Hash::new("A".as_bytes()));
+    let entry = DataEntry::new("doc", Hash::new("document".as_bytes()).0.to_vec());
+    assert_eq!(
+        DataEntry::from_payload(&entry.to_payload().unwrap()).unwrap(),
+        entry
+    );
+    assert_eq!(entry.rent(), Amount::new(35_000));
+    assert!(matches!(
+        DataEntry::new("", vec![]).check(),
+        Err(ConsensusError::InvalidDataKey { len: 0, .. })
+    ));
+    assert!(matches!(
+        DataEntry::new("doc", vec![0; MAX_DATA_VALUE_LEN + 1]).check(),
+        Err(ConsensusError::DataValueTooLong { .. })
+    ));
+
+    let data = StoredData {
+        account_id,
+        key: entry.key.clone(),
+        value: entry.value.clone(),
+        tx_id: TxId::from(Hash::new("tx".as_bytes())),
+    };
+    let storage = MemoryStorage::new(None).unwrap();
+    let mut store = DataStore::open(&storage).unwrap();
+    assert_eq!(store.get(&account_id, "doc").unwrap(), None);
+    store.record(&data).unwrap();
+    store.flush().unwrap();
+    let reopened = DataStore::open(&storage).unwrap();
+    assert_eq!(reopened.get(&account_id, "doc").unwrap(), Some(data));
+    assert_eq!(reopened.get(&account_id, "other").unwrap(), None);
+}
@@ -0,0 +1,235 @@
+//! Consensus decisions, without a network.
+//!
+//! [`ConsensusCore`] is the decision logic of [`DagConsensus`] as a plain
+//! state machine: candidates join the conflict set of their account state,
+//! and each round's acceptance either reaches the threshold and updates the
+//! confidence of the candidate's ancestors, or leaves nothing decided. It
+//! neither locks, times nor samples anything, so it can be driven through
+//! millions of simulated rounds, and the engines resolve their rounds
+//! through the same decision.
+//!
+//! [`DagConsensus`]: crate::dag_consensus::DagConsensus
+
+use crate::{
+    account::AccountStateChoice,
+    config::ConsensusConfig,
+    id::TxId,
+    tree::{HashTreeNode, TreeNode},
+    AccountConflictSet, ConsensusStatus,
+};
+use crypto::hash::Hash;
+use std::collections::{HashMap, HashSet};
+
+/// A candidate accepted for an account state
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Decision {
+    pub account_state: Hash,
+    pub accepted: TxId,
+}
+
+/// Network-free consensus state of a node
+#[derive(Clone, Debug)]
+pub struct ConsensusCore {
+    config: ConsensusConfig,
+    conflict_set: AccountConflictSet,
+    choices: HashMap<Hash, TxId>,
+    tree: HashTreeNode,
+}
+
+impl ConsensusCore {
+    pub fn new(config: ConsensusConfig) -> Self {
+        Self {
+            config,
+            conflict_set: HashMap::new(),
+            choices: HashMap::new(),
+            tree: HashTreeNode::new(),
+        }
+    }
+
+    pub fn config(&self) -> &ConsensusConfig {
+        &self.config
+    }
+
+    /// Add a transaction to the confidence tree, under its parents
+    pub fn add_vertex(&mut self, tx_id: TxId, parents: Vec<TxId>) {
+        let _ = self
+            .tree
+            .entry(tx_id)
+            .or_insert_with(|| (parents, TreeNode::new(tx_id)));
+    }
+
+    /// Add the candidate of `state` to the conflict set of its account state
+    pub fn on_query(&mut self, state: &AccountStateChoice) {
+        let _ = self
+            .conflict_set
+            .entry(state.account_state_id)
+            .or_default()
+            .insert(state.tx.get_tx_id());
+    }
+
+    /// Resolve a round in which `acceptance` of the sampled peers accepted
+    /// the candidate of `state`, returning what was decided, if anything
+    pub fn on_response(&mut self, state: &AccountStateChoice, acceptance: u64) -> Option<Decision> {
+        self.on_query(state);
+        match decide(
+            &self.config,
+            &mut self.choices,
+            &mut self.tree,
+            state,
+            acceptance,
+        ) {
+            ConsensusStatus::Accept(accepted) => Some(Decision {
+                account_state: state.account_state_id,
+                accepted,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Candidate chosen for an account state, once one reached the threshold
+    pub fn choice(&self, account_state: &Hash) -> Option<TxId> {
+        self.choices.get(account_state).copied()
+    }
+
+    pub fn conflict_set(&self) -> &AccountConflictSet {
+        &self.conflict_set
+    }
+
+    pub fn tree(&self) -> &HashTreeNode {
+        &self.tree
+    }
+
+    /// Drop the conflict sets holding any of the `finalized` transactions
+    pub fn prune(&mut self, finalized: &HashSet<TxId>) {
+        self.conflict_set
+            .retain(|_, set| set.is_disjoint(finalized));
+    }
+}
+
+/// Resolve a round of the candidate of `state`: once `acceptance` reaches
+/// the threshold, the candidate becomes the choice for its account state,
+/// unless it already has one, and the confidence of its ancestors in `tree`
+/// is updated until one of them commits. Paths follow the parent of every
+/// ancestor: the path from the parent of the candidate is walked first, then
+/// the paths from the other parents met along the way, nearest first, and
+/// then those from the other tips the candidate references. Paths joining
+/// one walked already stop there.
+pub(crate) fn decide(
+    config: &ConsensusConfig,
+    choices: &mut HashMap<Hash, TxId>,
+    tree: &mut HashTreeNode,
+    state: &AccountStateChoice,
+    acceptance: u64,
+) -> ConsensusStatus {
+    if !config.threshold(acceptance) {
+        tracing::debug!("Round rejected");
+        return ConsensusStatus::Reject;
+    }
+    if choices.contains_key(&state.account_state_id) {
+        tracing::debug!("Account state already has a choice");
+        return ConsensusStatus::Reject;
+    }
+    let _ = choices.insert(state.account_state_id, state.tx.get_tx_id());
+
+    // Ancestors on the paths walked already
+    let mut visited = HashSet::new();
+    // Where the paths left to walk start, the next one last
+    let mut starts = state.tx.parents().iter().rev().copied().collect::<Vec<_>>();
+    while let Some(start) = starts.pop() {
+        let mut parent_hash = start;
+        let mut walked = vec![];
+        while let Some(path) = tree
+            .get(&parent_hash)
+            .filter(|_| !visited.contains(&parent_hash))
+        {
+            walked.push(parent_hash);
+            let (parents, mut node) = path.clone();
+            let (parent, others) = match parents.split_first() {
+                Some(split) => split,
+                None => break,
+            };
+            tracing::trace!(ancestor = %parent, "Updating confidence");
+            parent_hash = *parent;
+            starts.extend(others.iter().rev());
+            if let Some(preferred_confidence) = tree.get(&node.preferred) {
+                let preferred_confidence = preferred_confidence.clone().1;
+                // Compare Confidence Tree
+                if node.confidence > preferred_confidence.confidence {
+                    node.preferred = node.node;
+                }
+                if node.node != node.last {
+                    node.last = node.node;
+                    node.count = 0;
+                } else {
+                    node.count += 1;
+                }
+            }
+            // Update Tree Node state
+            let updated_node = (parents.clone(), node.clone());
+            *tree.entry(parent_hash).or_insert(updated_node) = updated_node.clone();
+            // Check early commitment
+            if node.confidence > config.beta {
+                return ConsensusStatus::Accept(node.node);
+            }
+            // Check consecutive counter commitment
+            if node.count > config.beta2 {
+                return ConsensusStatus::Accept(state.tx.get_tx_id());
+            }
+        }
+        visited.extend(walked);
+    }
+    tracing::debug!("Reached the threshold without enough confidence");
+    ConsensusStatus::Reject
+}
+
+#[test]
+fn test_core_decides_without_a_network() {
+    use crate::{
+        account::Account,
+        amount::Amount,
+        transaction::{Transaction, TransactionType},
+    };
+
+    let config = ConsensusConfig::builder().k(5).build().unwrap();
+    let mut core = ConsensusCore::new(config);
+    let mut parent = TxId::default();
+    let mut decisions = vec![];
+    let mut states = vec![];
+    for n in 0..6 {
+        let origin = Account::create(&Hash::new(format!("{}", n).as_bytes()).into(), &parent);
+        let mut tx = Transaction::new(
+            parent,
+            origin,
+            Hash::new("destination".as_bytes()).into(),
+            Amount::new(1),
+            TransactionType::Transfer,
+            vec![],
+        );
+        tx.calculate_tx_id().unwrap();
+        core.add_vertex(tx.get_tx_id(), vec![parent]);
+        let state = AccountStateChoice::new(parent.into(), &tx);
+        // Below the threshold nothing is decided nor chosen
+        assert_eq!(core.on_response(&state, 3), None);
+        assert_eq!(core.choice(&state.account_state_id), None);
+        decisions.push(core.on_response(&state, 5));
+        parent = tx.get_tx_id();
+        states.push(state);
+    }
+
+    // The first transaction has no ancestor to gain confidence in
+    assert_eq!(decisions[0], None);
+    for (decision, state) in decisions.iter().zip(&states).skip(1) {
+        assert_eq!(
+            *decision,
+            Some(Decision {
+                account_state: state.account_state_id,
+                accepted: state.tx.get_tx_id(),
+            })
+        );
+    }
+    // An account state is only chosen for once
+    assert_eq!(core.on_response(&states[5], 5), None);
+    assert_eq!(core.conflict_set().len(), 6);
+    core.prune(&[states[5].tx.get_tx_id()].into());
+    assert_eq!(core.conflict_set().len(), 5);
+}
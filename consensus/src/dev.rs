@@ -0,0 +1,189 @@
+//! Single-node engine for local development.
+//!
+//! Finalizes the transactions of the node instantly instead of sampling the
+//! network, so that applications can be developed against one node. Accepted
+//! transactions flow through storage and events like any other.
+
+use crate::{
+    account::{AccountStateChoice, SequenceTracker},
+    config::ConsensusConfig,
+    drain::{self, EngineState},
+    id::TxId,
+    network::{CommonConsensusNetwork, ConsensusNetwork},
+    transaction::Transaction,
+    tree::HashTreeNode,
+    AccountConflictSet, Consensus, ConsensusStatus,
+};
+use crypto::hash::Hash;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+
+/// Engine accepting the first valid transaction of each account state, see
+/// [`ConsensusConfig::is_dev`]
+pub struct DevConsensus {
+    conflict_set: Arc<RwLock<AccountConflictSet>>,
+    choice: Arc<RwLock<HashMap<Hash, TxId>>>,
+    sequences: Arc<SequenceTracker>,
+}
+
+impl DevConsensus {
+    /// Accept a transaction unless another one was accepted for its account
+    /// state, or it is out of sequence
+    fn finalize(&self, state: &AccountStateChoice) -> ConsensusStatus {
+        let tx_id = state.tx.get_tx_id();
+        let mut choice = self.choice.write().unwrap();
+        if let Some(chosen) = choice.get(&state.account_state_id) {
+            return match *chosen == tx_id {
+                true => ConsensusStatus::Accept(tx_id),
+                false => ConsensusStatus::Reject,
+            };
+        }
+        if !self.sequences.is_next(&state.tx) {
+            return ConsensusStatus::Reject;
+        }
+        let _ = choice.insert(state.account_state_id, tx_id);
+        self.sequences.advance(&state.tx);
+        ConsensusStatus::Accept(tx_id)
+    }
+}
+
+impl Consensus for DevConsensus {
+    fn new(_config: ConsensusConfig) -> Self
+    where
+        Self: Sized,
+    {
+        Self {
+            conflict_set: Default::default(),
+            choice: Default::default(),
+            sequences: Default::default(),
+        }
+    }
+
+    fn query(&self, state: &AccountStateChoice) -> &Self
+    where
+        Self: Sized,
+    {
+        let _ = self
+            .conflict_set
+            .write()
+            .unwrap()
+            .entry(state.account_state_id)
+            .or_default()
+            .insert(state.tx.get_tx_id());
+        self
+    }
+
+    /// There is nobody to send requests to, the transaction is only recorded
+    fn send_consensus_requests<T, N>(
+        &self,
+        state: &AccountStateChoice,
+        _tx: &Transaction,
+        _network: &mut T,
+        _common_network: &mut N,
+        _count: usize,
+    ) where
+        T: ConsensusNetwork,
+        N: CommonConsensusNetwork,
+    {
+        self.query(state);
+    }
+
+    fn complete_dag_consensus(
+        &self,
+        _acceptance: usize,
+        state: &AccountStateChoice,
+        _tree: &mut HashTreeNode,
+    ) -> ConsensusStatus {
+        self.query(state);
+        self.finalize(state)
+    }
+
+    fn fire_consensus<T, N>(
+        &mut self,
+        state: &AccountStateChoice,
+        _network: &mut T,
+        _common_network: &mut N,
+        _tree: Option<&mut HashTreeNode>,
+    ) -> ConsensusStatus
+    where
+        T: ConsensusNetwork,
+        N: CommonConsensusNetwork,
+    {
+        self.query(state);
+        self.finalize(state)
+    }
+
+    fn on_query(&self, state: &AccountStateChoice) -> (TxId, bool) {
+        match self.choice.read().unwrap().get(&state.account_state_id) {
+            Some(choice) => (*choice, true),
+            None => (state.tx.get_tx_id(), self.sequences.is_next(&state.tx)),
+        }
+    }
+
+    /// No peer is ever sampled
+    fn target_count(&self) -> usize {
+        0
+    }
+
+    fn conflict_set(&self) -> AccountConflictSet {
+        self.conflict_set.read().unwrap().clone()
+    }
+
+    fn prune(&self, finalized: &HashSet<TxId>) {
+        drain::prune_conflict_set(&mut self.conflict_set.write().unwrap(), finalized);
+    }
+
+    fn export_state(&self) -> EngineState {
+        EngineState::export(&self.conflict_set, &self.choice, &self.sequences)
+    }
+
+    fn import_state(&self, state: &EngineState) {
+        state.merge_into(&self.conflict_set, &self.choice, &self.sequences);
+    }
+}
+
+#[test]
+fn test_dev_engine_finalizes_instantly() {
+    use crate::{
+        account::Account,
+        amount::Amount,
+        engine::{ConsensusEngine, DEV_ENGINE},
+        transaction::TransactionType,
+    };
+
+    let config = ConsensusConfig::builder().dev(true).build().unwrap();
+    let engine = ConsensusEngine::new(config);
+    assert_eq!(engine.name(), DEV_ENGINE);
+
+    let transfer = |destination: &str| {
+        let origin = Account::create(&Hash::new("A".as_bytes()).into(), &Hash::default().into());
+        let mut tx = Transaction::new(
+            Hash::default().into(),
+            origin,
+            Hash::new(destination.as_bytes()).into(),
+            Amount::new(1),
+            TransactionType::Transfer,
+            vec![],
+        );
+        tx.calculate_tx_id().unwrap();
+        AccountStateChoice::new(Hash::default(), &tx)
+    };
+    let (state, double_spend) = (transfer("B"), transfer("C"));
+    let mut tree = HashMap::new();
+    let tx_id = state.tx.get_tx_id();
+    assert_eq!(
+        Consensus::complete_dag_consensus(&engine, 0, &state, &mut tree),
+        ConsensusStatus::Accept(tx_id)
+    );
+    // Finalizing is idempotent and conflicting transactions are refused
+    assert_eq!(
+        Consensus::complete_dag_consensus(&engine, 0, &state, &mut tree),
+        ConsensusStatus::Accept(tx_id)
+    );
+    assert_eq!(
+        Consensus::complete_dag_consensus(&engine, 0, &double_spend, &mut tree),
+        ConsensusStatus::Reject
+    );
+    assert_eq!(Consensus::on_query(&engine, &double_spend), (tx_id, true));
+    assert_eq!(Consensus::target_count(&engine), 0);
+}
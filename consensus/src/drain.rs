@@ -0,0 +1,222 @@
+use crate::{
+    account::{AccountStateChoice, SequenceTracker},
+    config::ConsensusConfig,
+    id::{AccountId, TxId},
+    submission::SubmissionWindow,
+    AccountConflictSet, Consensus, ConsensusError,
+};
+use crypto::hash::Hash;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::RwLock;
+use std::time::Duration;
+
+/// Resumable state of a consensus engine.
+///
+/// Handed from a drained engine to the next instance on shutdown or engine
+/// swap, so conflict sets and choices made so far survive the handover.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct EngineState {
+    pub conflict_set: AccountConflictSet,
+    /// Current choice per account state
+    pub choices: HashMap<Hash, TxId>,
+    /// Latest accepted sequence number per account
+    pub sequences: HashMap<AccountId, u64>,
+    /// Choices whose round had not resolved when draining ended, to be
+    /// resubmitted to the next instance
+    pub unresolved: Vec<AccountStateChoice>,
+}
+
+impl EngineState {
+    /// Serialize the state into a blob
+    pub fn to_bytes(&self) -> Result<Vec<u8>, ConsensusError> {
+        bincode::serialize(self).map_err(|e| ConsensusError::SerializationError(e.to_string()))
+    }
+
+    /// Deserialize the state from a blob
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ConsensusError> {
+        bincode::deserialize(bytes).map_err(|e| ConsensusError::SerializationError(e.to_string()))
+    }
+
+    /// Initialize a new engine instance resuming from this state
+    pub fn resume<C: Consensus>(&self, config: ConsensusConfig) -> C {
+        let engine = C::new(config);
+        engine.import_state(self);
+        engine
+    }
+
+    /// Snapshot of the tables every engine keeps
+    pub(crate) fn export(
+        conflict_set: &RwLock<AccountConflictSet>,
+        choice: &RwLock<HashMap<Hash, TxId>>,
+        sequences: &SequenceTracker,
+    ) -> Self {
+        Self {
+            conflict_set: conflict_set.read().unwrap().clone(),
+            choices: choice.read().unwrap().clone(),
+            sequences: sequences.snapshot(),
+            unresolved: vec![],
+        }
+    }
+
+    /// Merge the state into the tables every engine keeps. Choices the
+    /// engine made already are kept over the handed over ones.
+    pub(crate) fn merge_into(
+        &self,
+        conflict_set: &RwLock<AccountConflictSet>,
+        choice: &RwLock<HashMap<Hash, TxId>>,
+        sequences: &SequenceTracker,
+    ) {
+        {
+            let mut conflict_set = conflict_set.write().unwrap();
+            for (account_state_id, set) in &self.conflict_set {
+                conflict_set
+                    .entry(*account_state_id)
+                    .or_default()
+                    .extend(set.iter().copied());
+            }
+        }
+        let mut choice = choice.write().unwrap();
+        for (account_state_id, tx_id) in &self.choices {
+            let _ = choice.entry(*account_state_id).or_insert(*tx_id);
+        }
+        sequences.merge(&self.sequences);
+    }
+}
+
+/// Drop the conflict sets resolved by finalized transactions
+pub(crate) fn prune_conflict_set(conflict_set: &mut AccountConflictSet, finalized: &HashSet<TxId>) {
+    conflict_set.retain(|_, set| set.is_disjoint(finalized));
+}
+
+/// Drain an engine before shutting it down or swapping it.
+///
+/// Stops accepting new submissions, waits up to `timeout` for in-flight
+/// rounds to resolve, and exports whatever partial state is left. The
+/// choices of the rounds still in flight are looked up through `pending`,
+/// as the window only knows their transaction IDs.
+pub fn drain<C, F>(
+    engine: &C,
+    window: &SubmissionWindow,
+    timeout: Duration,
+    pending: F,
+) -> EngineState
+where
+    C: Consensus,
+    F: Fn(&TxId) -> Option<AccountStateChoice>,
+{
+    let in_flight = window.drain(timeout);
+    if !in_flight.is_empty() {
+        log::warn!(
+            "Draining timed out with {} rounds in flight",
+            in_flight.len()
+        );
+    }
+    let mut state = engine.export_state();
+    state.unresolved = in_flight
+        .iter()
+        .filter_map(|tx_id| {
+            let choice = pending(tx_id);
+            if choice.is_none() {
+                log::warn!("Dropping unresolved {}: its choice is unknown", tx_id);
+            }
+            choice
+        })
+        .collect();
+    state
+}
+
+#[test]
+fn test_drain_waits_for_in_flight_rounds() {
+    use crate::{dag_consensus::DagConsensus, ConsensusStatus};
+    use std::sync::Arc;
+
+    let engine = DagConsensus::new(ConsensusConfig::default());
+    let window = Arc::new(SubmissionWindow::default());
+    let tx_id = TxId::from(Hash::new("tx".as_bytes()));
+
+    let handle = {
+        let window = window.clone();
+        std::thread::spawn(move || {
+            window.submit(tx_id, || {
+                std::thread::sleep(Duration::from_millis(50));
+                ConsensusStatus::Accept(tx_id)
+            })
+        })
+    };
+    while !window.is_in_flight(&tx_id) {
+        std::thread::yield_now();
+    }
+
+    let state = drain(&engine, &window, Duration::from_secs(5), |_| None);
+    assert!(state.unresolved.is_empty());
+    assert_eq!(handle.join().unwrap(), ConsensusStatus::Accept(tx_id));
+
+    let other = TxId::from(Hash::new("other".as_bytes()));
+    assert_eq!(
+        window.submit(other, || panic!("submitted while draining")),
+        ConsensusStatus::Draining
+    );
+    window.resume();
+    assert_eq!(
+        window.submit(other, || ConsensusStatus::Reject),
+        ConsensusStatus::Reject
+    );
+}
+
+#[test]
+fn test_drain_hands_over_unresolved_state() {
+    use crate::{
+        account::{Account, AccountStateChoice},
+        amount::Amount,
+        dag_consensus::DagConsensus,
+        transaction::{Transaction, TransactionType},
+        ConsensusStatus,
+    };
+    use std::sync::{mpsc, Arc};
+
+    let engine = DagConsensus::new(ConsensusConfig::default());
+    let origin = Account::create(&Hash::new("A".as_bytes()).into(), &Hash::default().into());
+    let mut tx = Transaction::new(
+        Hash::default().into(),
+        origin,
+        Hash::new("B".as_bytes()).into(),
+        Amount::new(1),
+        TransactionType::Transfer,
+        vec![],
+    );
+    tx.calculate_tx_id().unwrap();
+    let state = AccountStateChoice::new(Hash::default(), &tx);
+    engine.query(&state);
+
+    let window = Arc::new(SubmissionWindow::default());
+    let (release, stuck) = mpsc::channel::<()>();
+    let handle = {
+        let window = window.clone();
+        let tx_id = tx.get_tx_id();
+        std::thread::spawn(move || {
+            window.submit(tx_id, || {
+                let _ = stuck.recv();
+                ConsensusStatus::InProgress
+            })
+        })
+    };
+    while !window.is_in_flight(&tx.get_tx_id()) {
+        std::thread::yield_now();
+    }
+
+    let pending = HashMap::from([(tx.get_tx_id(), state.clone())]);
+    let drained = drain(&engine, &window, Duration::from_millis(10), |tx_id| {
+        pending.get(tx_id).cloned()
+    });
+    assert_eq!(drained.unresolved, vec![state.clone()]);
+    release.send(()).unwrap();
+    let _ = handle.join();
+
+    let blob = drained.to_bytes().unwrap();
+    let resumed: DagConsensus = EngineState::from_bytes(&blob)
+        .unwrap()
+        .resume(ConsensusConfig::default());
+    assert_eq!(resumed.conflict_set(), engine.conflict_set());
+    assert_eq!(resumed.on_query(&state), (tx.get_tx_id(), true));
+}
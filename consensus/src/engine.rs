@@ -0,0 +1,618 @@
+use crate::{
+    account::AccountStateChoice,
+    config::ConsensusConfig,
+    dag_consensus::DagConsensus,
+    dev::DevConsensus,
+    drain::EngineState,
+    id::{NodeId, TxId},
+    inspect::ConflictReport,
+    network::{CommonConsensusNetwork, ConsensusNetwork},
+    quantum::QuantumConsensus,
+    transaction::Transaction,
+    tree::HashTreeNode,
+    AccountConflictSet, Consensus, ConsensusStatus,
+};
+use crypto::hash::Hash;
+use std::collections::{HashMap, HashSet};
+
+/// Object-safe view of a [`ConsensusNetwork`] along with the
+/// [`CommonConsensusNetwork`] it samples nodes from
+pub trait DynConsensusNetwork {
+    fn get_sample_network(&self, k: u64, current_node: NodeId) -> Vec<NodeId>;
+
+    fn request_consensus(&mut self, node_id: NodeId, data: &AccountStateChoice) -> TxId;
+
+    fn request_dag_consensus(&self, node_id: NodeId, data: &AccountStateChoice) -> bool;
+
+    fn send_dag_consensus_request(
+        &mut self,
+        node_id: NodeId,
+        data: &AccountStateChoice,
+        tx: &Transaction,
+        count: usize,
+    );
+
+    fn add_outgoing_dag_consensus_request(
+        &mut self,
+        node_id: NodeId,
+        data: &AccountStateChoice,
+        tx: &Transaction,
+        count: usize,
+    );
+
+    fn accept_incoming_consensus_response(
+        &mut self,
+        node_id: NodeId,
+        data: TxId,
+        accepted: bool,
+    ) -> (usize, usize);
+
+    fn remove_outgoing_dag_transaction(&mut self, tx_id: TxId) -> Option<Transaction>;
+
+    fn get_node_id(&self) -> NodeId;
+
+    fn add_transaction_to_batch(
+        &mut self,
+        k: u64,
+        tx: &Transaction,
+        data: &AccountStateChoice,
+        max_batch_size: usize,
+        max_batch_interval: f32,
+        count: usize,
+    );
+}
+
+/// Pairs a consensus network with the network it samples nodes from, so
+/// that both can be handed to a [`DynConsensus`]
+pub struct NetworkPair<'a, T: ConsensusNetwork, N: CommonConsensusNetwork> {
+    pub network: &'a mut T,
+    pub common_network: &'a N,
+}
+
+impl<'a, T: ConsensusNetwork, N: CommonConsensusNetwork> NetworkPair<'a, T, N> {
+    pub fn new(network: &'a mut T, common_network: &'a N) -> Self {
+        Self {
+            network,
+            common_network,
+        }
+    }
+}
+
+impl<T: ConsensusNetwork, N: CommonConsensusNetwork> DynConsensusNetwork for NetworkPair<'_, T, N> {
+    fn get_sample_network(&self, k: u64, current_node: NodeId) -> Vec<NodeId> {
+        self.network
+            .get_sample_network(k, current_node, self.common_network)
+    }
+
+    fn request_consensus(&mut self, node_id: NodeId, data: &AccountStateChoice) -> TxId {
+        self.network.request_consensus(node_id, data)
+    }
+
+    fn request_dag_consensus(&self, node_id: NodeId, data: &AccountStateChoice) -> bool {
+        self.network.request_dag_consensus(node_id, data)
+    }
+
+    fn send_dag_consensus_request(
+        &mut self,
+        node_id: NodeId,
+        data: &AccountStateChoice,
+        tx: &Transaction,
+        count: usize,
+    ) {
+        self.network
+            .send_dag_consensus_request(node_id, data, tx, count)
+    }
+
+    fn add_outgoing_dag_consensus_request(
+        &mut self,
+        node_id: NodeId,
+        data: &AccountStateChoice,
+        tx: &Transaction,
+        count: usize,
+    ) {
+        self.network
+            .add_outgoing_dag_consensus_request(node_id, data, tx, count)
+    }
+
+    fn accept_incoming_consensus_response(
+        &mut self,
+        node_id: NodeId,
+        data: TxId,
+        accepted: bool,
+    ) -> (usize, usize) {
+        self.network
+            .accept_incoming_consensus_response(node_id, data, accepted)
+    }
+
+    fn remove_outgoing_dag_transaction(&mut self, tx_id: TxId) -> Option<Transaction> {
+        self.network.remove_outgoing_dag_transaction(tx_id)
+    }
+
+    fn get_node_id(&self) -> NodeId {
+        self.network.get_node_id()
+    }
+
+    fn add_transaction_to_batch(
+        &mut self,
+        k: u64,
+        tx: &Transaction,
+        data: &AccountStateChoice,
+        max_batch_size: usize,
+        max_batch_interval: f32,
+        count: usize,
+    ) {
+        self.network.add_transaction_to_batch(
+            k,
+            tx,
+            data,
+            self.common_network,
+            max_batch_size,
+            max_batch_interval,
+            count,
+        )
+    }
+}
+
+/// Lets an engine run its rounds over a [`DynConsensusNetwork`]. Nodes are
+/// sampled from the network the [`DynConsensusNetwork`] was built with.
+struct DynNetwork<'a>(&'a mut dyn DynConsensusNetwork);
+
+/// Stands in for the common network of a [`DynNetwork`], which never
+/// samples from it
+struct SampledByNetwork;
+
+impl CommonConsensusNetwork for SampledByNetwork {
+    fn get_nodes_except_one(&self, _k: u64, _node_id: NodeId) -> Vec<NodeId> {
+        vec![]
+    }
+}
+
+impl ConsensusNetwork for DynNetwork<'_> {
+    fn get_sample_network<T: CommonConsensusNetwork>(
+        &self,
+        k: u64,
+        current_node: NodeId,
+        _network: &T,
+    ) -> Vec<NodeId> {
+        self.0.get_sample_network(k, current_node)
+    }
+
+    fn request_consensus(&mut self, node_id: NodeId, data: &AccountStateChoice) -> TxId {
+        self.0.request_consensus(node_id, data)
+    }
+
+    fn request_dag_consensus(&self, node_id: NodeId, data: &AccountStateChoice) -> bool {
+        self.0.request_dag_consensus(node_id, data)
+    }
+
+    fn send_dag_consensus_request(
+        &mut self,
+        node_id: NodeId,
+        data: &AccountStateChoice,
+        tx: &Transaction,
+        count: usize,
+    ) {
+        self.0.send_dag_consensus_request(node_id, data, tx, count)
+    }
+
+    fn add_outgoing_dag_consensus_request(
+        &mut self,
+        node_id: NodeId,
+        data: &AccountStateChoice,
+        tx: &Transaction,
+        count: usize,
+    ) {
+        self.0
+            .add_outgoing_dag_consensus_request(node_id, data, tx, count)
+    }
+
+    fn accept_incoming_consensus_response(
+        &mut self,
+        node_id: NodeId,
+        data: TxId,
+        accepted: bool,
+    ) -> (usize, usize) {
+        self.0
+            .accept_incoming_consensus_response(node_id, data, accepted)
+    }
+
+    fn remove_outgoing_dag_transaction(&mut self, tx_id: TxId) -> Option<Transaction> {
+        self.0.remove_outgoing_dag_transaction(tx_id)
+    }
+
+    fn get_node_id(&self) -> NodeId {
+        self.0.get_node_id()
+    }
+
+    fn add_transaction_to_batch<N: CommonConsensusNetwork>(
+        &mut self,
+        k: u64,
+        tx: &Transaction,
+        data: &AccountStateChoice,
+        _network: &N,
+        max_batch_size: usize,
+        max_batch_interval: f32,
+        count: usize,
+    ) {
+        self.0
+            .add_transaction_to_batch(k, tx, data, max_batch_size, max_batch_interval, count)
+    }
+}
+
+/// Object-safe facade over [`Consensus`], so that a node can host any
+/// engine behind a `Box<dyn DynConsensus>`.
+///
+/// Implemented for every [`Consensus`] engine.
+pub trait DynConsensus: Send + Sync {
+    fn query(&self, state: &AccountStateChoice);
+
+    fn send_consensus_requests(
+        &self,
+        state: &AccountStateChoice,
+        tx: &Transaction,
+        network: &mut dyn DynConsensusNetwork,
+        count: usize,
+    );
+
+    fn complete_dag_consensus(
+        &self,
+        acceptance: usize,
+        state: &AccountStateChoice,
+        tree: &mut HashTreeNode,
+    ) -> ConsensusStatus;
+
+    fn fire_consensus(
+        &mut self,
+        state: &AccountStateChoice,
+        network: &mut dyn DynConsensusNetwork,
+        tree: Option<&mut HashTreeNode>,
+    ) -> ConsensusStatus;
+
+    fn expire_rounds(
+        &self,
+        network: &mut dyn DynConsensusNetwork,
+    ) -> Vec<(AccountStateChoice, ConsensusStatus)>;
+
+    fn on_query(&self, state: &AccountStateChoice) -> (TxId, bool);
+
+    fn target_count(&self) -> usize;
+
+    fn conflict_set(&self) -> AccountConflictSet;
+
+    fn conflicts_for(&self, account_state_id: &Hash) -> Option<ConflictReport>;
+
+    fn prune(&self, finalized: &HashSet<TxId>);
+
+    fn export_state(&self) -> EngineState;
+
+    fn import_state(&self, state: &EngineState);
+}
+
+impl<C: Consensus + Send + Sync> DynConsensus for C {
+    fn query(&self, state: &AccountStateChoice) {
+        Consensus::query(self, state);
+    }
+
+    fn send_consensus_requests(
+        &self,
+        state: &AccountStateChoice,
+        tx: &Transaction,
+        network: &mut dyn DynConsensusNetwork,
+        count: usize,
+    ) {
+        Consensus::send_consensus_requests(
+            self,
+            state,
+            tx,
+            &mut DynNetwork(network),
+            &mut SampledByNetwork,
+            count,
+        )
+    }
+
+    fn complete_dag_consensus(
+        &self,
+        acceptance: usize,
+        state: &AccountStateChoice,
+        tree: &mut HashTreeNode,
+    ) -> ConsensusStatus {
+        Consensus::complete_dag_consensus(self, acceptance, state, tree)
+    }
+
+    fn fire_consensus(
+        &mut self,
+        state: &AccountStateChoice,
+        network: &mut dyn DynConsensusNetwork,
+        tree: Option<&mut HashTreeNode>,
+    ) -> ConsensusStatus {
+        Consensus::fire_consensus(
+            self,
+            state,
+            &mut DynNetwork(network),
+            &mut SampledByNetwork,
+            tree,
+        )
+    }
+
+    fn expire_rounds(
+        &self,
+        network: &mut dyn DynConsensusNetwork,
+    ) -> Vec<(AccountStateChoice, ConsensusStatus)> {
+        Consensus::expire_rounds(self, &mut DynNetwork(network), &mut SampledByNetwork)
+    }
+
+    fn on_query(&self, state: &AccountStateChoice) -> (TxId, bool) {
+        Consensus::on_query(self, state)
+    }
+
+    fn target_count(&self) -> usize {
+        Consensus::target_count(self)
+    }
+
+    fn conflict_set(&self) -> AccountConflictSet {
+        Consensus::conflict_set(self)
+    }
+
+    fn conflicts_for(&self, account_state_id: &Hash) -> Option<ConflictReport> {
+        Consensus::conflicts_for(self, account_state_id)
+    }
+
+    fn prune(&self, finalized: &HashSet<TxId>) {
+        Consensus::prune(self, finalized)
+    }
+
+    fn export_state(&self) -> EngineState {
+        Consensus::export_state(self)
+    }
+
+    fn import_state(&self, state: &EngineState) {
+        Consensus::import_state(self, state)
+    }
+}
+
+/// Consensus engines shipped with DAGchain, picked at runtime from the
+/// consensus parameters
+pub enum ConsensusEngine {
+    Dag(DagConsensus),
+    Quantum(QuantumConsensus),
+    Dev(DevConsensus),
+}
+
+impl ConsensusEngine {
+    /// Name of the engine, as known to [`EngineRegistry`]
+    pub fn name(&self) -> &'static str {
+        match self {
+            ConsensusEngine::Dag(_) => DAG_ENGINE,
+            ConsensusEngine::Quantum(_) => QUANTUM_ENGINE,
+            ConsensusEngine::Dev(_) => DEV_ENGINE,
+        }
+    }
+
+    /// Initialize the engine selected by `config`, behind a trait object
+    pub fn boxed(config: ConsensusConfig) -> Box<dyn DynConsensus> {
+        match Self::new(config) {
+            ConsensusEngine::Dag(engine) => Box::new(engine),
+            ConsensusEngine::Quantum(engine) => Box::new(engine),
+            ConsensusEngine::Dev(engine) => Box::new(engine),
+        }
+    }
+}
+
+macro_rules! dispatch {
+    ($engine:expr, $inner:ident => $call:expr) => {
+        match $engine {
+            ConsensusEngine::Dag($inner) => $call,
+            ConsensusEngine::Quantum($inner) => $call,
+            ConsensusEngine::Dev($inner) => $call,
+        }
+    };
+}
+
+impl Consensus for ConsensusEngine {
+    fn new(config: ConsensusConfig) -> Self
+    where
+        Self: Sized,
+    {
+        if config.is_dev() {
+            ConsensusEngine::Dev(DevConsensus::new(config))
+        } else if config.is_quantum() {
+            ConsensusEngine::Quantum(QuantumConsensus::new(config))
+        } else {
+            ConsensusEngine::Dag(DagConsensus::new(config))
+        }
+    }
+
+    fn query(&self, state: &AccountStateChoice) -> &Self
+    where
+        Self: Sized,
+    {
+        dispatch!(self, engine => { Consensus::query(engine, state); });
+        self
+    }
+
+    fn send_consensus_requests<T, N>(
+        &self,
+        state: &AccountStateChoice,
+        tx: &Transaction,
+        network: &mut T,
+        common_network: &mut N,
+        count: usize,
+    ) where
+        T: ConsensusNetwork,
+        N: CommonConsensusNetwork,
+    {
+        dispatch!(self, engine => {
+            Consensus::send_consensus_requests(engine, state, tx, network, common_network, count)
+        })
+    }
+
+    fn complete_dag_consensus(
+        &self,
+        acceptance: usize,
+        state: &AccountStateChoice,
+        tree: &mut HashTreeNode,
+    ) -> ConsensusStatus {
+        dispatch!(self, engine => {
+            Consensus::complete_dag_consensus(engine, acceptance, state, tree)
+        })
+    }
+
+    fn fire_consensus<T, N>(
+        &mut self,
+        state: &AccountStateChoice,
+        network: &mut T,
+        common_network: &mut N,
+        tree: Option<&mut HashTreeNode>,
+    ) -> ConsensusStatus
+    where
+        T: ConsensusNetwork,
+        N: CommonConsensusNetwork,
+    {
+        dispatch!(self, engine => {
+            Consensus::fire_consensus(engine, state, network, common_network, tree)
+        })
+    }
+
+    fn expire_rounds<T, N>(
+        &self,
+        network: &mut T,
+        common_network: &mut N,
+    ) -> Vec<(AccountStateChoice, ConsensusStatus)>
+    where
+        T: ConsensusNetwork,
+        N: CommonConsensusNetwork,
+    {
+        dispatch!(self, engine => Consensus::expire_rounds(engine, network, common_network))
+    }
+
+    fn on_query(&self, state: &AccountStateChoice) -> (TxId, bool) {
+        dispatch!(self, engine => Consensus::on_query(engine, state))
+    }
+
+    fn target_count(&self) -> usize {
+        dispatch!(self, engine => Consensus::target_count(engine))
+    }
+
+    fn conflict_set(&self) -> AccountConflictSet {
+        dispatch!(self, engine => Consensus::conflict_set(engine))
+    }
+
+    fn conflicts_for(&self, account_state_id: &Hash) -> Option<ConflictReport> {
+        dispatch!(self, engine => Consensus::conflicts_for(engine, account_state_id))
+    }
+
+    fn prune(&self, finalized: &HashSet<TxId>) {
+        dispatch!(self, engine => Consensus::prune(engine, finalized))
+    }
+
+    fn export_state(&self) -> EngineState {
+        dispatch!(self, engine => Consensus::export_state(engine))
+    }
+
+    fn import_state(&self, state: &EngineState) {
+        dispatch!(self, engine => Consensus::import_state(engine, state))
+    }
+}
+
+pub const DAG_ENGINE: &str = "dag";
+pub const QUANTUM_ENGINE: &str = "quantum";
+pub const DEV_ENGINE: &str = "dev";
+
+/// Initializes an engine from consensus parameters
+pub type EngineConstructor = fn(ConsensusConfig) -> Box<dyn DynConsensus>;
+
+/// Engines a node can be configured to run, by name.
+///
+/// Starts with the engines shipped with DAGchain; embedders register their
+/// own to run them without changing the node.
+pub struct EngineRegistry {
+    constructors: HashMap<String, EngineConstructor>,
+}
+
+impl Default for EngineRegistry {
+    fn default() -> Self {
+        let mut registry = Self {
+            constructors: HashMap::new(),
+        };
+        registry
+            .register(DAG_ENGINE, |config| Box::new(DagConsensus::new(config)))
+            .register(QUANTUM_ENGINE, |config| {
+                Box::new(QuantumConsensus::new(config))
+            })
+            .register(DEV_ENGINE, |config| Box::new(DevConsensus::new(config)));
+        registry
+    }
+}
+
+impl EngineRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register an engine, replacing any engine of the same name
+    pub fn register(&mut self, name: &str, constructor: EngineConstructor) -> &mut Self {
+        let _ = self.constructors.insert(name.to_string(), constructor);
+        self
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.constructors.contains_key(name)
+    }
+
+    /// Names of the registered engines
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.constructors.keys().map(String::as_str)
+    }
+
+    /// Initialize the engine registered as `name`
+    pub fn build(&self, name: &str, config: ConsensusConfig) -> Option<Box<dyn DynConsensus>> {
+        self.constructors
+            .get(name)
+            .map(|constructor| constructor(config))
+    }
+}
+
+#[test]
+fn test_engines_are_picked_at_runtime() {
+    use crate::{account::Account, amount::Amount, transaction::TransactionType};
+    use crypto::hash::Hash;
+
+    let engine = ConsensusEngine::new(ConsensusConfig::default());
+    assert_eq!(engine.name(), DAG_ENGINE);
+    let quantum = ConsensusConfig::builder().quantum(true).build().unwrap();
+    assert_eq!(ConsensusEngine::new(quantum.clone()).name(), QUANTUM_ENGINE);
+
+    let origin = Account::create(&Hash::new("A".as_bytes()).into(), &Hash::default().into());
+    let mut tx = Transaction::new(
+        Hash::default().into(),
+        origin,
+        Hash::new("B".as_bytes()).into(),
+        Amount::new(1),
+        TransactionType::Transfer,
+        vec![],
+    );
+    tx.calculate_tx_id().unwrap();
+    let state = AccountStateChoice::new(Hash::default(), &tx);
+
+    let mut registry = EngineRegistry::new();
+    registry.register("shadowed", |config| {
+        Box::new(crate::shadow::ShadowConsensus::<DagConsensus, DagConsensus>::new(config))
+    });
+    let engines: Vec<Box<dyn DynConsensus>> = vec![
+        ConsensusEngine::boxed(quantum),
+        registry
+            .build(DAG_ENGINE, ConsensusConfig::default())
+            .unwrap(),
+        registry
+            .build("shadowed", ConsensusConfig::default())
+            .unwrap(),
+    ];
+    for engine in &engines {
+        engine.query(&state);
+        assert_eq!(engine.on_query(&state), (tx.get_tx_id(), true));
+        assert_eq!(engine.target_count(), 10);
+    }
+    assert!(registry
+        .build("unknown", ConsensusConfig::default())
+        .is_none());
+}
@@ -0,0 +1,207 @@
+//! # Consensus errors
+
+use crate::{
+    amount::Amount,
+    budget::Resource,
+    id::{AccountId, TxId},
+};
+use crypto::hash::Hash;
+use storage::StorageError;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ConsensusError {
+    #[error("Transaction has no ID")]
+    MissingTransactionId,
+    #[error("Transaction claims ID {claimed}, its contents hash to {actual}")]
+    TxIdMismatch { claimed: TxId, actual: TxId },
+    #[error("Duplicate transaction: {0}")]
+    DuplicateTransaction(TxId),
+    #[error("Double spend of account state {account_state}: conflicts with {existing}")]
+    DoubleSpend { account_state: Hash, existing: TxId },
+    #[error("Transaction from {origin:?} carries sequence {got}, expected {expected}")]
+    InvalidSequence {
+        origin: AccountId,
+        expected: u64,
+        got: u64,
+    },
+    #[error("Unknown account: {0}")]
+    UnknownAccount(AccountId),
+    #[error("Account {0} exists already")]
+    AccountExists(AccountId),
+    #[error("Account {account} is not derived from the public key creating it, {derived} is")]
+    AccountKeyMismatch {
+        account: AccountId,
+        derived: AccountId,
+    },
+    #[error("Invalid public key: {0}")]
+    InvalidPublicKey(String),
+    #[error("Public key of account {0} is unknown")]
+    UnknownAccountKey(AccountId),
+    #[error("Account {account} holds {balance}, cannot send {amount}")]
+    InsufficientBalance {
+        account: AccountId,
+        balance: Amount,
+        amount: Amount,
+    },
+    #[error("Memo of {len} bytes exceeds the maximum of {max}")]
+    MemoTooLong { len: usize, max: usize },
+    #[error("Data keys take from 1 to {max} bytes, got {len}")]
+    InvalidDataKey { len: usize, max: usize },
+    #[error("Data value of {len} bytes exceeds the maximum of {max}")]
+    DataValueTooLong { len: usize, max: usize },
+    #[error("Storing {size} bytes of data costs {rent}, the fee is {fee}")]
+    InsufficientDataFee {
+        size: usize,
+        rent: Amount,
+        fee: Amount,
+    },
+    #[error("{len} tips referenced besides the parent exceed the maximum of {max}")]
+    TooManyReferences { len: usize, max: usize },
+    #[error("Mempool is full")]
+    MempoolFull,
+    #[error("Serialization error: {0}")]
+    SerializationError(String),
+    #[error("Unsupported transaction encoding version {0}")]
+    UnsupportedEncoding(u8),
+    #[error("Storage error: {0}")]
+    StorageError(StorageError),
+    #[error("Invalid config: {0}")]
+    InvalidConfig(ConfigError),
+    #[error("Invalid state transition: {0}")]
+    StateError(StateError),
+    #[error("Account {account} may send at most {limit} per transaction, not {amount}")]
+    SpendingLimitExceeded {
+        account: AccountId,
+        limit: Amount,
+        amount: Amount,
+    },
+    #[error("Account {account} sent {spent} of its {limit} this epoch, cannot send {amount}")]
+    VelocityLimitExceeded {
+        account: AccountId,
+        limit: Amount,
+        spent: Amount,
+        amount: Amount,
+    },
+    #[error("Account {account} may not send to {destination}")]
+    DestinationNotAllowed {
+        account: AccountId,
+        destination: AccountId,
+    },
+    #[error("Spending policy of {0} is not signed by its owner")]
+    UnauthorizedPolicy(AccountId),
+    #[error("Validator {0} must stake a non-zero amount")]
+    ZeroStake(AccountId),
+    #[error("Unknown validator: {0}")]
+    UnknownValidator(AccountId),
+    #[error("Finality certificate of {0} is invalid")]
+    InvalidCertificate(TxId),
+    #[error("Finality of {tx_id} is certified by {certified} stake, {required} required")]
+    InsufficientStake {
+        tx_id: TxId,
+        certified: u128,
+        required: u128,
+    },
+    #[error("Execution error: {0}")]
+    ExecutionError(String),
+    #[error("Audit log is broken at entry {0}")]
+    AuditChainBroken(u64),
+    #[error("Signature error: {0}")]
+    SignatureError(String),
+    #[error("{resource:?} refused, in-flight state takes {used} of its {limit} bytes")]
+    ResourceExhausted {
+        resource: Resource,
+        used: usize,
+        limit: usize,
+    },
+}
+
+/// Balance change an account cannot hold
+#[derive(Clone, Debug, Error, PartialEq)]
+pub enum StateError {
+    #[error("Account {account} holds {balance}, cannot send {amount}")]
+    BalanceUnderflow {
+        account: AccountId,
+        balance: Amount,
+        amount: Amount,
+    },
+    #[error("Account {account} holds {balance}, receiving {amount} overflows it")]
+    BalanceOverflow {
+        account: AccountId,
+        balance: Amount,
+        amount: Amount,
+    },
+}
+
+/// Invalid consensus parameters
+#[derive(Clone, Debug, Error, PartialEq)]
+pub enum ConfigError {
+    #[error("alpha must be in (0.5, 1], got {0}")]
+    InvalidAlpha(f64),
+    #[error("k must be at least 1")]
+    InvalidSampleSize,
+    #[error("beta and beta2 must be at least 1, got {beta} and {beta2}")]
+    InvalidBeta { beta: u64, beta2: u64 },
+    #[error("Batch interval must be positive, got {0}")]
+    InvalidBatchInterval(f32),
+    #[error("Submission window must be a finite, non-negative number of seconds, got {0}")]
+    InvalidSubmissionWindow(f32),
+    #[error("Tip revalidation interval must be in (0, {max_age}], got {revalidation_interval}")]
+    InvalidTipAge {
+        revalidation_interval: f32,
+        max_age: f32,
+    },
+    #[error("New transactions need from 1 to {max} parents, got {parents}")]
+    InvalidTipParents { parents: usize, max: usize },
+    #[error("Rounds need a positive timeout and an attempt, got {timeout}s and {attempts}")]
+    InvalidRoundDeadline { timeout: f32, attempts: usize },
+    #[error("Memory budget of {budget} bytes is below the mempool cap of {mempool_max_bytes}")]
+    InvalidMemoryBudget {
+        budget: usize,
+        mempool_max_bytes: usize,
+    },
+    #[error("Account {0} is funded more than once at genesis")]
+    DuplicateGenesisAccount(AccountId),
+    #[error("Validator {0} is registered more than once at genesis")]
+    DuplicateGenesisValidator(AccountId),
+    #[error("Validator {0} stakes nothing at genesis")]
+    ZeroGenesisStake(AccountId),
+    #[error("Validator {0} did not prove possession of its key at genesis")]
+    InvalidPossessionProof(AccountId),
+    #[error("Genesis names no chain")]
+    EmptyChainId,
+    #[error("Invalid genesis file {0}")]
+    InvalidGenesisFile(String),
+}
+
+/// Amount that could not be parsed
+#[derive(Clone, Debug, Error, PartialEq)]
+pub enum AmountError {
+    #[error("Invalid amount: {0}")]
+    Invalid(String),
+    #[error("Amount has more than {0} decimals")]
+    TooPrecise(u32),
+    #[error("Amount overflows")]
+    Overflow,
+}
+
+impl From<StorageError> for ConsensusError {
+    #[inline]
+    fn from(e: StorageError) -> Self {
+        ConsensusError::StorageError(e)
+    }
+}
+
+impl From<ConfigError> for ConsensusError {
+    #[inline]
+    fn from(e: ConfigError) -> Self {
+        ConsensusError::InvalidConfig(e)
+    }
+}
+
+impl From<StateError> for ConsensusError {
+    #[inline]
+    fn from(e: StateError) -> Self {
+        ConsensusError::StateError(e)
+    }
+}
@@ -0,0 +1,206 @@
+//! Application logic run on accepted transactions.
+//!
+//! Consensus only orders transactions and moves funds, their payload is
+//! opaque to it. An [`Executor`] gives the payload meaning: once a
+//! transaction is accepted, the node hands it to the executor along with the
+//! application state, and commits the changes it returns. Executors must be
+//! deterministic, every node has to end up with the same state.
+
+use crate::{
+    id::AccountId,
+    transaction::{Transaction, TransactionType},
+    ConsensusError,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// State of the application, as keys mapped to values
+pub type AppState = BTreeMap<Vec<u8>, Vec<u8>>;
+
+/// Changes an executed transaction makes to the application state
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct StateDelta {
+    /// New value per key, `None` deleting the key
+    writes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+}
+
+impl StateDelta {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+
+    pub fn writes(&self) -> impl Iterator<Item = (&Vec<u8>, &Option<Vec<u8>>)> {
+        self.writes.iter()
+    }
+
+    pub fn put(&mut self, key: Vec<u8>, value: Vec<u8>) -> &mut Self {
+        let _ = self.writes.insert(key, Some(value));
+        self
+    }
+
+    pub fn delete(&mut self, key: Vec<u8>) -> &mut Self {
+        let _ = self.writes.insert(key, None);
+        self
+    }
+
+    /// Commit the changes to `state`
+    pub fn apply(&self, state: &mut AppState) {
+        for (key, value) in &self.writes {
+            let _ = match value {
+                Some(value) => state.insert(key.clone(), value.clone()),
+                None => state.remove(key),
+            };
+        }
+    }
+}
+
+/// Deterministic state transition of the application
+pub trait Executor: Send + Sync {
+    /// Execute an accepted transaction against the application state,
+    /// returning the changes to commit. The state is left untouched if it
+    /// fails.
+    fn execute(&self, tx: &Transaction, state: &AppState) -> Result<StateDelta, ConsensusError>;
+}
+
+/// Executor leaving the application state as is
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoopExecutor;
+
+impl Executor for NoopExecutor {
+    fn execute(&self, _: &Transaction, _: &AppState) -> Result<StateDelta, ConsensusError> {
+        Ok(StateDelta::new())
+    }
+}
+
+/// Operation in the payload of an `Execute` transaction run by
+/// [`KvExecutor`]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum KvOp {
+    Put { key: Vec<u8>, value: Vec<u8> },
+    Delete { key: Vec<u8> },
+}
+
+impl KvOp {
+    /// Build the payload of an `Execute` transaction
+    pub fn to_payload(&self) -> Result<Vec<u8>, ConsensusError> {
+        bincode::serialize(self).map_err(|e| ConsensusError::SerializationError(e.to_string()))
+    }
+}
+
+/// Key-value store where every account writes under its own namespace
+#[derive(Clone, Copy, Debug, Default)]
+pub struct KvExecutor;
+
+impl KvExecutor {
+    /// Key of the state `key` of `account` is stored under
+    pub fn key_of(account: &AccountId, key: &[u8]) -> Vec<u8> {
+        [account.as_ref(), key].concat()
+    }
+}
+
+impl Executor for KvExecutor {
+    fn execute(&self, tx: &Transaction, _: &AppState) -> Result<StateDelta, ConsensusError> {
+        let mut delta = StateDelta::new();
+        if tx.tx_type != TransactionType::Execute {
+            return Ok(delta);
+        }
+        let op = bincode::deserialize::<KvOp>(&tx.payload)
+            .map_err(|e| ConsensusError::ExecutionError(e.to_string()))?;
+        match op {
+            KvOp::Put { key, value } => delta.put(Self::key_of(&tx.origin, &key), value),
+            KvOp::Delete { key } => delta.delete(Self::key_of(&tx.origin, &key)),
+        };
+        Ok(delta)
+    }
+}
+
+/// Application state kept up to date by running accepted transactions
+/// through an executor
+pub struct Application {
+    executor: Box<dyn Executor>,
+    state: AppState,
+}
+
+impl Default for Application {
+    fn default() -> Self {
+        Self::new(Box::new(NoopExecutor))
+    }
+}
+
+impl Application {
+    pub fn new(executor: Box<dyn Executor>) -> Self {
+        Self {
+            executor,
+            state: AppState::new(),
+        }
+    }
+
+    pub fn state(&self) -> &AppState {
+        &self.state
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<&Vec<u8>> {
+        self.state.get(key)
+    }
+
+    /// Execute an accepted transaction and commit its changes
+    pub fn on_accepted(&mut self, tx: &Transaction) -> Result<StateDelta, ConsensusError> {
+        let delta = self.executor.execute(tx, &self.state)?;
+        delta.apply(&mut self.state);
+        Ok(delta)
+    }
+}
+
+#[test]
+fn test_kv_executor() {
+    use crate::{account::Account, amount::Amount};
+    use crypto::hash::Hash;
+
+    let origin = Account::create(&Hash::new("A".as_bytes()).into(), &Hash::default().into());
+    let execute = |payload: Vec<u8>| {
+        Transaction::new(
+            Hash::default().into(),
+            origin.clone(),
+            Hash::new("B".as_bytes()).into(),
+            Amount::ZERO,
+            TransactionType::Execute,
+            payload,
+        )
+    };
+    let key = KvExecutor::key_of(&origin.id, b"doc");
+
+    let mut app = Application::new(Box::new(KvExecutor));
+    let put = KvOp::Put {
+        key: b"doc".to_vec(),
+        value: b"v1".to_vec(),
+    };
+    let delta = app
+        .on_accepted(&execute(put.to_payload().unwrap()))
+        .unwrap();
+    assert!(!delta.is_empty());
+    assert_eq!(app.get(&key), Some(&b"v1".to_vec()));
+
+    // A payload the executor can't make sense of leaves the state as is
+    assert!(matches!(
+        app.on_accepted(&execute(vec![0xff])),
+        Err(ConsensusError::ExecutionError(_))
+    ));
+    assert_eq!(app.state().len(), 1);
+
+    let delete = KvOp::Delete {
+        key: b"doc".to_vec(),
+    };
+    app.on_accepted(&execute(delete.to_payload().unwrap()))
+        .unwrap();
+    assert!(app.get(&key).is_none());
+
+    let mut noop = Application::default();
+    assert!(noop
+        .on_accepted(&execute(put.to_payload().unwrap()))
+        .unwrap()
+        .is_empty());
+}
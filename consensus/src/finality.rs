@@ -0,0 +1,318 @@
+//! Certificates of the finality of transactions.
+//!
+//! Only the nodes taking part in the rounds on a transaction know it was
+//! accepted. A [`FinalityCertificate`] lets anybody else check it: every
+//! validator accepting the transaction signs its ID, and the BLS
+//! signatures gossiped by the validators are aggregated into one as they
+//! arrive. Signers are recorded as a bitmap over the validators of the
+//! [`StakeTable`] sorted by ID, so that certificates stay small however
+//! many validators sign. Verifying a certificate needs the stake table
+//! only, not the DAG nor the rounds.
+
+use crate::{
+    id::TxId,
+    reconcile::{Quorum, StakeTable},
+    ConsensusError,
+};
+use crypto::{
+    hash::{Hash, HashDomain},
+    signature::{PrivateKey, PublicKey, Signature},
+};
+use serde::{Deserialize, Serialize};
+use storage::{Storage, TypedStore};
+
+/// Keyspace of the certificates
+const FINALITY_TREE: &str = "finality";
+/// Schema version of the stored certificates. Those of version 1 signed
+/// the round accepting the transaction too, and are dropped.
+const FINALITY_VERSION: u32 = 2;
+
+/// Aggregated signatures of the validators certifying that a transaction
+/// was accepted
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct FinalityCertificate {
+    pub tx_id: TxId,
+    pub signature: Option<Signature>,
+    /// Bit `i` is set if the `i`-th validator of the stake table, sorted by
+    /// ID, signed
+    pub signers: Vec<u8>,
+}
+
+impl FinalityCertificate {
+    /// Initialize a certificate nobody signed yet
+    pub fn new(tx_id: TxId) -> Self {
+        Self {
+            tx_id,
+            signature: None,
+            signers: vec![],
+        }
+    }
+
+    /// Message validators sign to certify that they accepted `tx_id`
+    pub fn message(tx_id: &TxId) -> Hash {
+        Hash::combine_in(HashDomain::Custom("dagchain/finality"), &[tx_id.as_ref()])
+    }
+
+    /// Add our signature to the aggregate, unless we signed already. Only
+    /// sign the transactions we accepted.
+    pub fn sign(
+        &mut self,
+        stake: &StakeTable,
+        private_key: &PrivateKey,
+    ) -> Result<&mut Self, ConsensusError> {
+        let signature = Signature::sign(private_key, Self::message(&self.tx_id));
+        self.add_signature(stake, &private_key.public_key(), signature)
+    }
+
+    /// Add the signature of a validator to the aggregate, unless it signed
+    /// already. Signatures of unknown validators or on another message are
+    /// refused.
+    pub fn add_signature(
+        &mut self,
+        stake: &StakeTable,
+        public_key: &PublicKey,
+        signature: Signature,
+    ) -> Result<&mut Self, ConsensusError> {
+        let signer = Hash::new(&public_key.to_bytes());
+        let index = stake
+            .signers()
+            .iter()
+            .position(|id| *id == signer)
+            .ok_or_else(|| ConsensusError::UnknownValidator(signer.into()))?;
+        if self.has_signed(index) {
+            return Ok(self);
+        }
+        if !signature.verify(public_key, Self::message(&self.tx_id)) {
+            return Err(ConsensusError::SignatureError(format!(
+                "{} did not sign the finality of {}",
+                signer, self.tx_id
+            )));
+        }
+        let mut signatures = vec![signature];
+        signatures.extend(self.signature);
+        self.signature = Some(
+            Signature::aggregate(&signatures)
+                .map_err(|e| ConsensusError::SignatureError(e.to_string()))?,
+        );
+        if self.signers.len() <= index / 8 {
+            self.signers.resize(index / 8 + 1, 0);
+        }
+        self.signers[index / 8] |= 1 << (index % 8);
+        Ok(self)
+    }
+
+    /// Merge the signatures of another certificate of the transaction,
+    /// e.g. one gossiped by a validator, into ours. Aggregates overlapping
+    /// can't be told apart, so if some validator signed both, the one
+    /// certifying more stake is kept. Invalid certificates are refused.
+    pub fn merge(
+        &mut self,
+        stake: &StakeTable,
+        other: &FinalityCertificate,
+    ) -> Result<&mut Self, ConsensusError> {
+        if other.tx_id != self.tx_id {
+            return Err(ConsensusError::InvalidCertificate(other.tx_id));
+        }
+        let theirs = other.certified(stake)?;
+        // Ours may predate a change of the validators
+        let (Some(signature), Ok(ours)) = (self.signature, self.certified(stake)) else {
+            *self = other.clone();
+            return Ok(self);
+        };
+        let overlapping = self
+            .signers
+            .iter()
+            .zip(&other.signers)
+            .any(|(ours, theirs)| ours & theirs != 0);
+        if overlapping {
+            if theirs > ours {
+                *self = other.clone();
+            }
+            return Ok(self);
+        }
+        let signatures = [signature, other.signature.unwrap()];
+        self.signature = Some(
+            Signature::aggregate(&signatures)
+                .map_err(|e| ConsensusError::SignatureError(e.to_string()))?,
+        );
+        if self.signers.len() < other.signers.len() {
+            self.signers.resize(other.signers.len(), 0);
+        }
+        for (ours, theirs) in self.signers.iter_mut().zip(&other.signers) {
+            *ours |= theirs;
+        }
+        Ok(self)
+    }
+
+    /// IDs of the validators of `stake` who signed, or None if the bitmap
+    /// names validators `stake` doesn't have
+    pub fn signer_ids(&self, stake: &StakeTable) -> Option<Vec<Hash>> {
+        let validators = stake.signers();
+        if self.signers.len() > validators.len().div_ceil(8)
+            || (validators.len()..self.signers.len() * 8).any(|index| self.has_signed(index))
+        {
+            return None;
+        }
+        Some(
+            validators
+                .into_iter()
+                .enumerate()
+                .filter(|(index, _)| self.has_signed(*index))
+                .map(|(_, id)| id)
+                .collect(),
+        )
+    }
+
+    /// Check that the validators of `stake` who signed certify `quorum`
+    /// of its stake, returning the stake they do. Certificates whose
+    /// aggregated signature is invalid, or whose bitmap names unknown
+    /// validators, are refused.
+    pub fn verify(&self, stake: &StakeTable, quorum: Quorum) -> Result<u128, ConsensusError> {
+        let certified = self.certified(stake)?;
+        let required = quorum.required(stake.total());
+        if certified < required {
+            return Err(ConsensusError::InsufficientStake {
+                tx_id: self.tx_id,
+                certified,
+                required,
+            });
+        }
+        Ok(certified)
+    }
+
+    /// Stake of the validators of `stake` who signed, refusing invalid
+    /// certificates
+    fn certified(&self, stake: &StakeTable) -> Result<u128, ConsensusError> {
+        let invalid = || ConsensusError::InvalidCertificate(self.tx_id);
+        let (signature, signers) = match (self.signature, self.signer_ids(stake)) {
+            (Some(signature), Some(signers)) if !signers.is_empty() => (signature, signers),
+            _ => return Err(invalid()),
+        };
+        let validators = signers
+            .iter()
+            .filter_map(|signer| stake.get(signer))
+            .collect::<Vec<_>>();
+        let public_keys = validators
+            .iter()
+            .map(|(public_key, _)| *public_key)
+            .collect::<Vec<_>>();
+        if !signature.verify_aggregate(&public_keys, Self::message(&self.tx_id)) {
+            return Err(invalid());
+        }
+        Ok(validators.iter().map(|(_, stake)| stake).sum())
+    }
+
+    fn has_signed(&self, index: usize) -> bool {
+        self.signers
+            .get(index / 8)
+            .is_some_and(|byte| byte & (1 << (index % 8)) != 0)
+    }
+}
+
+/// Certificates persisted through a [`Storage`], by transaction ID
+pub struct FinalityStore<S: Storage> {
+    certificates: TypedStore<S, FinalityCertificate>,
+}
+
+impl<S: Storage> FinalityStore<S> {
+    /// Keep the certificates in their own keyspace of `storage`
+    pub fn open(storage: &S) -> Result<Self, ConsensusError> {
+        Ok(Self {
+            certificates: TypedStore::open(storage, FINALITY_TREE, FINALITY_VERSION)?,
+        })
+    }
+
+    /// Store `certificate`, replacing the previous certificate of its
+    /// transaction
+    pub fn record(&mut self, certificate: &FinalityCertificate) -> Result<(), ConsensusError> {
+        Ok(self
+            .certificates
+            .put(certificate.tx_id.as_hash(), certificate)?)
+    }
+
+    /// Certificate of a transaction, if we hold one still valid
+    pub fn get(&self, tx_id: &TxId) -> Result<Option<FinalityCertificate>, ConsensusError> {
+        match self.certificates.get_raw(tx_id.as_hash())? {
+            Some((version, _)) if version < FINALITY_VERSION => Ok(None),
+            _ => Ok(self.certificates.get(tx_id.as_hash())?),
+        }
+    }
+
+    pub fn flush(&mut self) -> Result<(), ConsensusError> {
+        Ok(self.certificates.flush()?)
+    }
+}
+
+#[test]
+fn test_finality_certificates() {
+    use storage::memory::MemoryStorage;
+
+    let [alice, bob, carol, mallory] = [(); 4].map(|_| PrivateKey::generate());
+    let mut stake = StakeTable::new();
+    let _ = stake
+        .insert(alice.public_key(), 60)
+        .insert(bob.public_key(), 30)
+        .insert(carol.public_key(), 10);
+    let tx_id = TxId::from(Hash::new("tx".as_bytes()));
+    let quorum = Quorum::new(2, 3);
+
+    let mut certificate = FinalityCertificate::new(tx_id);
+    assert!(matches!(
+        certificate.verify(&stake, quorum),
+        Err(ConsensusError::InvalidCertificate(_))
+    ));
+    let _ = certificate.sign(&stake, &alice).unwrap();
+    assert!(matches!(
+        certificate.verify(&stake, quorum),
+        Err(ConsensusError::InsufficientStake {
+            certified: 60,
+            required: 67,
+            ..
+        })
+    ));
+    // Bob's certificate, gossiped once he accepted the transaction, is
+    // merged into ours
+    let mut gossiped = FinalityCertificate::new(tx_id);
+    let _ = gossiped.sign(&stake, &bob).unwrap();
+    let _ = certificate
+        .merge(&stake, &gossiped)
+        .unwrap()
+        .sign(&stake, &alice)
+        .unwrap();
+    assert_eq!(certificate.signer_ids(&stake).unwrap().len(), 2);
+    assert_eq!(certificate.verify(&stake, quorum).unwrap(), 90);
+    // Overlapping certificates are not aggregated, the stronger one wins
+    let _ = certificate.merge(&stake, &gossiped).unwrap();
+    assert_eq!(certificate.verify(&stake, quorum).unwrap(), 90);
+    let mut weaker = gossiped.clone();
+    let _ = weaker.merge(&stake, &certificate).unwrap();
+    assert_eq!(weaker, certificate);
+
+    // Unknown signers and signatures on another message are refused
+    assert!(matches!(
+        certificate.clone().sign(&stake, &mallory),
+        Err(ConsensusError::UnknownValidator(_))
+    ));
+    let other = Signature::sign(&carol, Hash::new("other".as_bytes()));
+    assert!(certificate
+        .clone()
+        .add_signature(&stake, &carol.public_key(), other)
+        .is_err());
+
+    // Forged transactions and bitmaps certify nothing
+    let mut forged = certificate.clone();
+    forged.tx_id = TxId::from(Hash::new("other".as_bytes()));
+    assert!(forged.verify(&stake, quorum).is_err());
+    assert!(certificate.clone().merge(&stake, &forged).is_err());
+    let mut forged = certificate.clone();
+    forged.signers = vec![0xff];
+    assert!(forged.verify(&stake, quorum).is_err());
+    assert!(gossiped.clone().merge(&stake, &forged).is_err());
+
+    let storage = MemoryStorage::new(None).unwrap();
+    let mut store = FinalityStore::open(&storage).unwrap();
+    store.record(&certificate).unwrap();
+    store.flush().unwrap();
+    let reopened = FinalityStore::open(&storage).unwrap();
+    assert_eq!(reopened.get(&tx_id).unwrap(), Some(certificate));
+}
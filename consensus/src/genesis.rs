@@ -0,0 +1,458 @@
+//! Accounts funded when a network starts.
+//!
+//! Every node of a network is configured with the same [`GenesisConfig`],
+//! from which it derives the same genesis transactions and state: one
+//! `CreateAccount` transaction per funded account, sent from the empty
+//! genesis account with every varying field fixed, in the order of the
+//! account IDs.
+//!
+//! A [`Genesis`] describes a whole network, as shared in a JSON file: the
+//! name of its chain, the accounts funded, the validators registered and
+//! the consensus parameters. Validators are registered by one
+//! `RegisterValidator` transaction each, sent from their account after the
+//! accounts are created, in the order of their account IDs. They prove
+//! possession of their key, which their signatures on finality are
+//! aggregated with.
+
+use crate::{
+    account::Account,
+    amount::Amount,
+    config::ConsensusConfig,
+    error::ConfigError,
+    id::{AccountId, TxId},
+    reconcile::StakeTable,
+    state::StateTrie,
+    transaction::{Transaction, TransactionType},
+    validators::ValidatorSet,
+    ConsensusError,
+};
+use crypto::{
+    hash::{Hash, HashDomain},
+    signature::{PublicKey, Signature},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::Duration;
+
+/// Account funded at genesis
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct GenesisAccount {
+    pub public_key: PublicKey,
+    pub balance: Amount,
+}
+
+impl GenesisAccount {
+    /// ID of the account, derived from its public key
+    pub fn id(&self) -> AccountId {
+        AccountId::from(Hash::new(&self.public_key.to_bytes()))
+    }
+}
+
+/// Accounts the network starts with
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct GenesisConfig {
+    accounts: Vec<GenesisAccount>,
+}
+
+impl GenesisConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fund the account of `public_key` with `balance` at genesis
+    pub fn fund(&mut self, public_key: PublicKey, balance: Amount) -> &mut Self {
+        self.accounts.push(GenesisAccount {
+            public_key,
+            balance,
+        });
+        self
+    }
+
+    pub fn accounts(&self) -> &[GenesisAccount] {
+        &self.accounts
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty()
+    }
+
+    /// Check that no account is funded twice
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        self.sorted().map(|_| ())
+    }
+
+    /// Genesis transactions, in the order of the accounts they create
+    pub fn transactions(&self) -> Result<Vec<Transaction>, ConsensusError> {
+        let origin = genesis_origin();
+        self.sorted()?
+            .into_iter()
+            .map(|(id, account)| {
+                let mut tx = Transaction::genesis(
+                    TxId::default(),
+                    origin.clone(),
+                    id,
+                    account.balance,
+                    TransactionType::CreateAccount,
+                    account.public_key.to_bytes(),
+                );
+                tx.calculate_tx_id()
+                    .map_err(|e| ConsensusError::SerializationError(e.to_string()))?;
+                Ok(tx)
+            })
+            .collect()
+    }
+
+    /// ID of the chain starting from these accounts, which peers must share.
+    /// Commits to every genesis transaction.
+    pub fn chain_id(&self) -> Result<Hash, ConsensusError> {
+        let ids = self
+            .transactions()?
+            .iter()
+            .map(|tx| Hash::from(tx.get_tx_id()))
+            .collect::<Vec<_>>();
+        let parts = ids.iter().map(|id| id.as_ref()).collect::<Vec<_>>();
+        Ok(Hash::combine_in(
+            HashDomain::Custom("dagchain/chain-id"),
+            &parts,
+        ))
+    }
+
+    /// State once the genesis transactions are applied
+    pub fn state(&self) -> Result<StateTrie, ConsensusError> {
+        let accounts = self
+            .transactions()?
+            .iter()
+            .map(|tx| {
+                let mut account = Account::create(&tx.destination, &tx.get_tx_id());
+                account.created = Duration::ZERO;
+                let _ = account.increase_balance(tx.amount)?;
+                Ok(account)
+            })
+            .collect::<Result<Vec<_>, ConsensusError>>()?;
+        let mut state = StateTrie::from_accounts(accounts);
+        for account in &self.accounts {
+            let _ = state.register_key(account.public_key);
+        }
+        Ok(state)
+    }
+
+    fn sorted(&self) -> Result<BTreeMap<AccountId, &GenesisAccount>, ConfigError> {
+        let mut sorted = BTreeMap::new();
+        for account in &self.accounts {
+            let id = account.id();
+            if sorted.insert(id, account).is_some() {
+                return Err(ConfigError::DuplicateGenesisAccount(id));
+            }
+        }
+        Ok(sorted)
+    }
+}
+
+/// Validator registered at genesis
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct GenesisValidator {
+    pub public_key: PublicKey,
+    pub stake: Amount,
+    /// Proof of possession of the private key, without which the key could
+    /// cancel out others in aggregated signatures
+    pub proof: Signature,
+}
+
+impl GenesisValidator {
+    /// ID of the account of the validator, derived from its public key
+    pub fn id(&self) -> AccountId {
+        AccountId::from(Hash::new(&self.public_key.to_bytes()))
+    }
+}
+
+/// Everything a network starts from
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Genesis {
+    /// Name of the chain, e.g. `dagchain-testnet`, committed to by the
+    /// chain hash so that networks started alike stay apart
+    pub chain_id: String,
+    #[serde(default)]
+    accounts: Vec<GenesisAccount>,
+    #[serde(default)]
+    validators: Vec<GenesisValidator>,
+    #[serde(default)]
+    consensus: ConsensusConfig,
+}
+
+impl Genesis {
+    pub fn new(chain_id: impl Into<String>) -> Self {
+        Self {
+            chain_id: chain_id.into(),
+            accounts: vec![],
+            validators: vec![],
+            consensus: ConsensusConfig::default(),
+        }
+    }
+
+    /// Read a genesis from a JSON file, and validate it
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let invalid = |e: &dyn std::fmt::Display| {
+            ConfigError::InvalidGenesisFile(format!("{}: {}", path.display(), e))
+        };
+        let bytes = std::fs::read(path).map_err(|e| invalid(&e))?;
+        let genesis: Self = serde_json::from_slice(&bytes).map_err(|e| invalid(&e))?;
+        genesis.validate()?;
+        Ok(genesis)
+    }
+
+    /// Fund the account of `public_key` with `balance` at genesis
+    pub fn fund(&mut self, public_key: PublicKey, balance: Amount) -> &mut Self {
+        self.accounts.push(GenesisAccount {
+            public_key,
+            balance,
+        });
+        self
+    }
+
+    /// Register the validator of `public_key` with `stake` at genesis,
+    /// along with its proof of possession of the private key
+    pub fn add_validator(
+        &mut self,
+        public_key: PublicKey,
+        stake: Amount,
+        proof: Signature,
+    ) -> &mut Self {
+        self.validators.push(GenesisValidator {
+            public_key,
+            stake,
+            proof,
+        });
+        self
+    }
+
+    pub fn set_consensus(&mut self, consensus: ConsensusConfig) -> &mut Self {
+        self.consensus = consensus;
+        self
+    }
+
+    pub fn accounts(&self) -> &[GenesisAccount] {
+        &self.accounts
+    }
+
+    /// Accounts funded at genesis, e.g. to configure a node with
+    pub fn allocation(&self) -> GenesisConfig {
+        GenesisConfig {
+            accounts: self.accounts.clone(),
+        }
+    }
+
+    pub fn validators(&self) -> &[GenesisValidator] {
+        &self.validators
+    }
+
+    pub fn consensus(&self) -> &ConsensusConfig {
+        &self.consensus
+    }
+
+    /// Check that the chain is named, that no account is funded nor
+    /// validator registered twice, that every validator stakes something
+    /// and proved possession of its key, and that the consensus parameters
+    /// are usable
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.chain_id.trim().is_empty() {
+            return Err(ConfigError::EmptyChainId);
+        }
+        self.allocation().validate()?;
+        for (id, validator) in self.sorted_validators()? {
+            if !validator.public_key.verify_possession(&validator.proof) {
+                return Err(ConfigError::InvalidPossessionProof(id));
+            }
+        }
+        self.consensus.validate()
+    }
+
+    /// Genesis transactions: the accounts created, then the validators
+    /// registered
+    pub fn transactions(&self) -> Result<Vec<Transaction>, ConsensusError> {
+        let mut transactions = self.allocation().transactions()?;
+        for (id, validator) in self.sorted_validators()? {
+            let mut origin = Account::create(&id, &TxId::default());
+            origin.created = Duration::ZERO;
+            let mut tx = Transaction::genesis(
+                TxId::default(),
+                origin,
+                id,
+                validator.stake,
+                TransactionType::RegisterValidator,
+                vec![],
+            );
+            tx.calculate_tx_id()
+                .map_err(|e| ConsensusError::SerializationError(e.to_string()))?;
+            transactions.push(tx);
+        }
+        Ok(transactions)
+    }
+
+    /// ID of the chain, which peers must share. Commits to its name, every
+    /// genesis transaction and the consensus parameters.
+    pub fn chain_hash(&self) -> Result<Hash, ConsensusError> {
+        let ids = self
+            .transactions()?
+            .iter()
+            .map(|tx| Hash::from(tx.get_tx_id()))
+            .collect::<Vec<_>>();
+        let consensus = bincode::serialize(&self.consensus)
+            .map_err(|e| ConsensusError::SerializationError(e.to_string()))?;
+        let mut parts = vec![self.chain_id.as_bytes(), consensus.as_slice()];
+        parts.extend(ids.iter().map(|id| id.as_ref()));
+        Ok(Hash::combine_in(
+            HashDomain::Custom("dagchain/chain"),
+            &parts,
+        ))
+    }
+
+    /// State once the genesis transactions are applied. The stake of the
+    /// validators is issued at genesis rather than bonded from a balance.
+    pub fn state(&self) -> Result<StateTrie, ConsensusError> {
+        let mut state = self.allocation().state()?;
+        for validator in &self.validators {
+            let _ = state.register_validator(validator.public_key, validator.stake.base_units())?;
+        }
+        Ok(state)
+    }
+
+    pub fn state_root(&self) -> Result<Hash, ConsensusError> {
+        Ok(self.state()?.root())
+    }
+
+    /// Validators registered at genesis, along with their stake
+    pub fn validator_set(&self) -> Result<ValidatorSet, ConsensusError> {
+        let mut validators = ValidatorSet::new();
+        for tx in self.transactions()? {
+            let _ = validators.apply(&tx)?;
+        }
+        Ok(validators)
+    }
+
+    /// Keys and stake of the validators registered at genesis, to check
+    /// their signatures with
+    pub fn stake_table(&self) -> StakeTable {
+        let mut stake = StakeTable::new();
+        for validator in &self.validators {
+            let _ = stake.insert(validator.public_key, validator.stake.base_units());
+        }
+        stake
+    }
+
+    fn sorted_validators(&self) -> Result<BTreeMap<AccountId, &GenesisValidator>, ConfigError> {
+        let mut sorted = BTreeMap::new();
+        for validator in &self.validators {
+            let id = validator.id();
+            if validator.stake == Amount::ZERO {
+                return Err(ConfigError::ZeroGenesisStake(id));
+            }
+            if sorted.insert(id, validator).is_some() {
+                return Err(ConfigError::DuplicateGenesisValidator(id));
+            }
+        }
+        Ok(sorted)
+    }
+}
+
+/// Account the genesis transactions are sent from
+fn genesis_origin() -> Account {
+    let mut origin = Account::create(&AccountId::default(), &TxId::default());
+    origin.created = Duration::ZERO;
+    origin
+}
+
+#[test]
+fn test_genesis_is_deterministic() {
+    use crypto::signature::PrivateKey;
+
+    let alice = PrivateKey::generate().public_key();
+    let bob = PrivateKey::generate().public_key();
+    let mut genesis = GenesisConfig::new();
+    let _ = genesis
+        .fund(alice, Amount::new(1_000))
+        .fund(bob, Amount::new(500));
+    let mut reordered = GenesisConfig::new();
+    let _ = reordered
+        .fund(bob, Amount::new(500))
+        .fund(alice, Amount::new(1_000));
+
+    let transactions = genesis.transactions().unwrap();
+    assert_eq!(transactions, reordered.transactions().unwrap());
+    assert_eq!(transactions.len(), 2);
+    let state = genesis.state().unwrap();
+    assert_eq!(state.root(), reordered.state().unwrap().root());
+    let id = AccountId::from(Hash::new(&alice.to_bytes()));
+    assert_eq!(state.get(&id).unwrap().balance, Amount::new(1_000));
+
+    let _ = reordered.fund(alice, Amount::new(1));
+    assert_eq!(
+        reordered.validate(),
+        Err(ConfigError::DuplicateGenesisAccount(id))
+    );
+}
+
+#[test]
+fn test_genesis_file_describes_the_network() {
+    use crypto::signature::PrivateKey;
+
+    let alice = PrivateKey::generate().public_key();
+    let validator_key = PrivateKey::generate();
+    let (validator, proof) = (validator_key.public_key(), validator_key.prove_possession());
+    let mut genesis = Genesis::new("dagchain-testnet");
+    let _ = genesis
+        .fund(alice, Amount::new(1_000))
+        .add_validator(validator, Amount::new(50), proof)
+        .set_consensus(ConsensusConfig::builder().k(20).build().unwrap());
+
+    let path = std::env::temp_dir().join(format!("genesis-{}.json", Hash::generate_random()));
+    std::fs::write(&path, serde_json::to_vec_pretty(&genesis).unwrap()).unwrap();
+    let loaded = Genesis::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(loaded, genesis);
+    assert_eq!(loaded.chain_hash().unwrap(), genesis.chain_hash().unwrap());
+    assert_eq!(loaded.state_root().unwrap(), genesis.state_root().unwrap());
+    assert_eq!(loaded.consensus().k(), 20);
+
+    let transactions = genesis.transactions().unwrap();
+    assert_eq!(transactions.len(), 2);
+    assert_eq!(transactions[1].tx_type, TransactionType::RegisterValidator);
+    let validator_id = AccountId::from(Hash::new(&validator.to_bytes()));
+    assert_eq!(
+        genesis.validator_set().unwrap().stake(&validator_id),
+        Some(50)
+    );
+    assert_eq!(
+        genesis.stake_table().get(&validator_id.into()).unwrap().1,
+        50
+    );
+    let state = genesis.state().unwrap();
+    assert_eq!(state.validators().stake(&validator_id), Some(50));
+    assert_eq!(state.stake_table().get(&validator_id.into()).unwrap().1, 50);
+
+    // Networks starting alike are told apart by their name
+    let mut renamed = genesis.clone();
+    renamed.chain_id = "dagchain-devnet".to_string();
+    assert_ne!(renamed.chain_hash().unwrap(), genesis.chain_hash().unwrap());
+    assert_eq!(renamed.state_root().unwrap(), genesis.state_root().unwrap());
+
+    let _ = renamed.add_validator(validator, Amount::new(1), proof);
+    assert_eq!(
+        renamed.validate(),
+        Err(ConfigError::DuplicateGenesisValidator(validator_id))
+    );
+    // Keys registered without proving possession of them are refused
+    let rogue = PrivateKey::generate().public_key();
+    let rogue_id = AccountId::from(Hash::new(&rogue.to_bytes()));
+    let mut unproven = genesis.clone();
+    let _ = unproven.add_validator(rogue, Amount::new(1), proof);
+    assert_eq!(
+        unproven.validate(),
+        Err(ConfigError::InvalidPossessionProof(rogue_id))
+    );
+    assert_eq!(Genesis::new(" ").validate(), Err(ConfigError::EmptyChainId));
+    assert!(matches!(
+        Genesis::load(Path::new("/nonexistent/genesis.json")),
+        Err(ConfigError::InvalidGenesisFile(_))
+    ));
+}
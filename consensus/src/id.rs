@@ -0,0 +1,92 @@
+//! Identifiers of nodes, transactions and accounts.
+//!
+//! All of them are hashes, and were passed around as bare [`Hash`]es. Giving
+//! each its own type catches a transaction ID passed where a peer was
+//! expected at compile time. They serialize as the hash they wrap, so the
+//! wire format is unchanged.
+
+use crypto::hash::Hash;
+use serde::{Deserialize, Serialize};
+
+macro_rules! hash_id {
+    ($(#[$doc:meta])* $name:ident) => {
+        $(#[$doc])*
+        #[derive(
+            Clone, Copy, Default, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize,
+        )]
+        #[serde(transparent)]
+        pub struct $name(Hash);
+
+        impl $name {
+            pub const fn new(hash: Hash) -> Self {
+                Self(hash)
+            }
+
+            /// Hash the ID wraps
+            pub fn as_hash(&self) -> &Hash {
+                &self.0
+            }
+
+            pub fn to_hex(&self) -> String {
+                self.0.to_hex()
+            }
+        }
+
+        impl From<Hash> for $name {
+            #[inline]
+            fn from(hash: Hash) -> Self {
+                Self(hash)
+            }
+        }
+
+        impl From<$name> for Hash {
+            #[inline]
+            fn from(id: $name) -> Self {
+                id.0
+            }
+        }
+
+        impl AsRef<[u8]> for $name {
+            fn as_ref(&self) -> &[u8] {
+                self.0.as_ref()
+            }
+        }
+
+        impl std::fmt::Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                std::fmt::Display::fmt(&self.0, f)
+            }
+        }
+
+        impl std::fmt::Debug for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                std::fmt::Debug::fmt(&self.0, f)
+            }
+        }
+    };
+}
+
+hash_id!(
+    /// ID of a node, the hash of its public key
+    NodeId
+);
+hash_id!(
+    /// ID of a transaction, the hash of its signed content
+    TxId
+);
+hash_id!(
+    /// ID of an account
+    AccountId
+);
+
+#[test]
+fn test_ids_serialize_as_hashes() {
+    let hash = Hash::new("node".as_bytes());
+    let node = NodeId::from(hash);
+    assert_eq!(Hash::from(node), hash);
+    assert_eq!(
+        bincode::serialize(&node).unwrap(),
+        bincode::serialize(&hash).unwrap()
+    );
+    assert_eq!(format!("{}", node), format!("{}", hash));
+}
@@ -0,0 +1,36 @@
+//! Views of the conflict sets an engine is resolving, for operators to
+//! inspect what a stuck or contested account state is waiting on.
+
+use crate::id::TxId;
+use crypto::hash::Hash;
+use serde::{Deserialize, Serialize};
+
+/// Transactions competing to spend an account state
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ConflictReport {
+    pub account_state: Hash,
+    /// Candidate the engine settled on, if any
+    pub choice: Option<TxId>,
+    /// Ordered by tx id
+    pub candidates: Vec<CandidateReport>,
+}
+
+/// Progress of one candidate of a conflict set
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct CandidateReport {
+    pub tx_id: TxId,
+    /// Rounds whose sample reached the quorum for the candidate
+    pub confidence: u64,
+    /// Rounds resolved for the candidate
+    pub rounds: u64,
+    /// Queries sent for the round in flight, none if no round is
+    pub attempts: usize,
+}
+
+impl ConflictReport {
+    pub fn candidate(&self, tx_id: &TxId) -> Option<&CandidateReport> {
+        self.candidates
+            .iter()
+            .find(|candidate| candidate.tx_id == *tx_id)
+    }
+}
@@ -1,23 +1,69 @@
 #![warn(clippy::all)]
 
 pub mod account;
+pub mod amount;
+pub mod audit;
+pub mod budget;
+pub mod checkpoint;
 pub mod clock;
 pub mod config;
+pub mod dag;
 pub mod dag_consensus;
+pub mod data;
+pub mod decision;
+pub mod dev;
+pub mod drain;
+pub mod engine;
+pub mod error;
+pub mod executor;
+pub mod finality;
+pub mod genesis;
+pub mod id;
+pub mod inspect;
+pub mod memo;
+pub mod mempool;
 pub mod network;
+pub mod policy;
 pub mod quantum;
+pub mod receipt;
+pub mod reconcile;
+pub mod safety;
+pub mod sampling;
+pub mod scheduler;
+pub mod shadow;
+pub mod sim;
+pub mod state;
+pub mod store;
+pub mod submission;
+pub mod time;
+pub mod tip_selection;
+pub mod tips;
 pub mod transaction;
 pub mod tree;
+pub mod validators;
 
 use account::AccountStateChoice;
+pub use amount::Amount;
 use config::ConsensusConfig;
 use crypto::hash::Hash;
+use drain::EngineState;
+pub use error::{AmountError, ConfigError, ConsensusError, StateError};
+pub use id::{AccountId, NodeId, TxId};
+use inspect::{CandidateReport, ConflictReport};
 use network::{CommonConsensusNetwork, ConsensusNetwork};
 use std::collections::{HashMap, HashSet};
 use transaction::Transaction;
 use tree::HashTreeNode;
 
-pub type AccountConflictSet = HashMap<Hash, HashSet<Hash>>;
+pub type AccountConflictSet = HashMap<Hash, HashSet<TxId>>;
+
+/// Span the consensus round of `tx_id` runs in, on the node proposing the
+/// transaction and on the peers it queries. Messages relayed for the round
+/// record the same tx id, so that the transaction can be followed across
+/// hops.
+pub fn round_span(tx_id: &TxId) -> tracing::Span {
+    tracing::info_span!("consensus_round", tx_id = %tx_id)
+}
 
 pub trait Consensus {
     fn new(config: ConsensusConfig) -> Self
@@ -57,14 +103,69 @@ pub trait Consensus {
         T: ConsensusNetwork,
         N: CommonConsensusNetwork;
 
-    fn on_query(&self, state: &AccountStateChoice) -> (Hash, bool);
+    /// Resample the peers of rounds whose responses are overdue, and end
+    /// the ones out of attempts with [`ConsensusStatus::TimedOut`],
+    /// returning them. Engines resolving rounds right away have none.
+    fn expire_rounds<T, N>(
+        &self,
+        _network: &mut T,
+        _common_network: &mut N,
+    ) -> Vec<(AccountStateChoice, ConsensusStatus)>
+    where
+        T: ConsensusNetwork,
+        N: CommonConsensusNetwork,
+    {
+        vec![]
+    }
+
+    fn on_query(&self, state: &AccountStateChoice) -> (TxId, bool);
 
     fn target_count(&self) -> usize;
+
+    /// Snapshot of the conflict sets currently tracked by the engine
+    fn conflict_set(&self) -> AccountConflictSet;
+
+    /// Candidates competing for `account_state_id`, if it has any. Engines
+    /// not tracking the progress of rounds report the candidates alone.
+    fn conflicts_for(&self, account_state_id: &Hash) -> Option<ConflictReport> {
+        let mut candidates = self
+            .conflict_set()
+            .remove(account_state_id)?
+            .into_iter()
+            .map(|tx_id| CandidateReport {
+                tx_id,
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        candidates.sort_by_key(|candidate| candidate.tx_id);
+        Some(ConflictReport {
+            account_state: *account_state_id,
+            choice: None,
+            candidates,
+        })
+    }
+
+    /// Drop the conflict sets resolved by finalized transactions
+    fn prune(&self, finalized: &HashSet<TxId>);
+
+    /// Snapshot of the engine state, to be resumed by another instance
+    fn export_state(&self) -> EngineState;
+
+    /// Merge state handed over by a previous instance
+    fn import_state(&self, state: &EngineState);
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum ConsensusStatus {
     InProgress,
-    Accept(Hash),
+    Accept(TxId),
     Reject,
+    Checkpointed(Hash),
+    /// Submission refused because the engine is shutting down
+    Draining,
+    /// Submission refused because in-flight state is over its memory budget
+    Overloaded,
+    /// Round of the transaction ran out of attempts before enough
+    /// responses arrived. It was queued again, or dropped if configured so.
+    TimedOut(Hash),
 }
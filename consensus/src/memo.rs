@@ -0,0 +1,116 @@
+//! Index of transactions by memo, to look payments up by their reference.
+
+use crate::{id::TxId, transaction::Transaction};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Transactions sorted by memo, so that a prefix maps to a range
+#[derive(Clone, Debug, Default)]
+pub struct MemoIndex {
+    memos: BTreeMap<String, BTreeSet<TxId>>,
+}
+
+impl MemoIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of distinct memos
+    pub fn len(&self) -> usize {
+        self.memos.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.memos.is_empty()
+    }
+
+    /// Index a transaction under its memo. Transactions without a memo or
+    /// an ID are skipped.
+    pub fn insert(&mut self, tx: &Transaction) {
+        if let (Some(memo), Some(tx_id)) = (tx.memo(), tx.try_get_tx_id()) {
+            let _ = self
+                .memos
+                .entry(memo.to_string())
+                .or_default()
+                .insert(tx_id);
+        }
+    }
+
+    pub fn remove(&mut self, tx: &Transaction) {
+        let (memo, tx_id) = match (tx.memo(), tx.try_get_tx_id()) {
+            (Some(memo), Some(tx_id)) => (memo, tx_id),
+            _ => return,
+        };
+        if let Some(txs) = self.memos.get_mut(memo) {
+            let _ = txs.remove(&tx_id);
+            if txs.is_empty() {
+                let _ = self.memos.remove(memo);
+            }
+        }
+    }
+
+    /// Transactions whose memo starts with `prefix`, sorted by memo
+    pub fn transactions_with_memo_prefix(&self, prefix: &str) -> Vec<TxId> {
+        self.memos
+            .range(prefix.to_string()..)
+            .take_while(|(memo, _)| memo.starts_with(prefix))
+            .flat_map(|(_, txs)| txs.iter().copied())
+            .collect()
+    }
+}
+
+#[test]
+fn test_transactions_with_memo_prefix() {
+    use crate::{account::Account, amount::Amount, transaction::TransactionType, ConsensusError};
+    use crypto::hash::Hash;
+
+    let labelled = |memo: Option<&str>| {
+        let origin = Account::create(&Hash::new("A".as_bytes()).into(), &Hash::default().into());
+        let mut tx = Transaction::new(
+            Hash::default().into(),
+            origin,
+            Hash::new("B".as_bytes()).into(),
+            Amount::new(1),
+            TransactionType::Transfer,
+            vec![],
+        );
+        if let Some(memo) = memo {
+            tx.set_memo(memo).unwrap();
+        }
+        tx.calculate_tx_id().unwrap();
+        tx
+    };
+
+    let invoice = labelled(Some("invoice-42"));
+    let other_invoice = labelled(Some("invoice-7"));
+    let refund = labelled(Some("refund-42"));
+    let mut index = MemoIndex::new();
+    for tx in [&invoice, &other_invoice, &refund, &labelled(None)] {
+        index.insert(tx);
+    }
+    assert_eq!(index.len(), 3);
+
+    let mut found = index.transactions_with_memo_prefix("invoice-");
+    found.sort();
+    let mut expected = vec![invoice.get_tx_id(), other_invoice.get_tx_id()];
+    expected.sort();
+    assert_eq!(found, expected);
+    assert_eq!(index.transactions_with_memo_prefix("invoice-42").len(), 1);
+    assert!(index.transactions_with_memo_prefix("receipt").is_empty());
+
+    index.remove(&invoice);
+    assert_eq!(
+        index.transactions_with_memo_prefix("invoice-"),
+        vec![other_invoice.get_tx_id()]
+    );
+
+    // The memo is signed over, so it changes the ID
+    assert_ne!(
+        labelled(Some("a")).get_tx_id(),
+        labelled(Some("b")).get_tx_id()
+    );
+    let mut tx = labelled(None);
+    assert!(matches!(
+        tx.set_memo("x".repeat(crate::transaction::MAX_MEMO_LEN + 1)),
+        Err(ConsensusError::MemoTooLong { .. })
+    ));
+}
@@ -0,0 +1,514 @@
+use crate::{
+    account::AccountStateChoice,
+    amount::Amount,
+    budget::{MemoryBudget, Resource},
+    config::ConsensusConfig,
+    id::{AccountId, TxId},
+    network::{CommonConsensusNetwork, ConsensusNetwork},
+    tree::HashTreeNode,
+    AccountConflictSet, Consensus, ConsensusError, ConsensusStatus,
+};
+use crypto::hash::Hash;
+use metrics::Metrics;
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Ordering key of a pending transaction.
+/// Higher fees come first, then older transactions, then tx id as a tie-breaker.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+struct Priority {
+    fee: Amount,
+    age: Reverse<Duration>,
+    tx_id: TxId,
+}
+
+impl Priority {
+    fn of(state: &AccountStateChoice) -> Self {
+        Self {
+            fee: state.tx.fee,
+            age: Reverse(state.tx.timestamp),
+            tx_id: state.tx.get_tx_id(),
+        }
+    }
+}
+
+/// Notifications about transactions leaving the pool without being processed
+#[derive(Clone, Debug, PartialEq)]
+pub enum MempoolEvent {
+    /// A pending transaction was evicted to make room, its originator may resubmit it
+    Evicted { tx_id: TxId, origin: AccountId },
+}
+
+/// Pool of transactions awaiting consensus
+pub struct Mempool {
+    capacity: usize,
+    max_bytes: usize,
+    bytes: usize,
+    entries: HashMap<TxId, (AccountStateChoice, usize)>,
+    queue: BTreeSet<Priority>,
+    /// Pending tx id spending each account state
+    spends: HashMap<Hash, TxId>,
+    /// Number of pending transactions per origin account
+    per_account: HashMap<AccountId, usize>,
+    events: VecDeque<MempoolEvent>,
+    /// Drop the transactions of timed out rounds rather than queue them
+    /// again
+    drop_timed_out: bool,
+    /// Shared with the rest of the in-flight state
+    budget: MemoryBudget,
+    metrics: Arc<Metrics>,
+}
+
+impl Mempool {
+    /// Initialize a Mempool holding at most `capacity` transactions
+    /// taking up at most `max_bytes` of memory
+    pub fn new(capacity: usize, max_bytes: usize) -> Self {
+        Self {
+            capacity,
+            max_bytes,
+            bytes: 0,
+            entries: HashMap::new(),
+            queue: BTreeSet::new(),
+            spends: HashMap::new(),
+            per_account: HashMap::new(),
+            events: VecDeque::new(),
+            drop_timed_out: false,
+            budget: MemoryBudget::unlimited(),
+            metrics: Arc::new(Metrics::new()),
+        }
+    }
+
+    /// Initialize a Mempool from consensus parameters
+    pub fn from_config(config: &ConsensusConfig) -> Self {
+        let mut mempool = Self::new(config.mempool_capacity, config.mempool_max_bytes);
+        mempool.drop_timed_out = config.drop_timed_out;
+        mempool
+    }
+
+    /// Set the metrics updated by the pool
+    pub fn set_metrics(&mut self, metrics: Arc<Metrics>) -> &mut Self {
+        self.metrics = metrics;
+        self.metrics.set_mempool_depth(self.len());
+        self
+    }
+
+    /// Count pending transactions against `budget`, and shed them once the
+    /// in-flight state goes over it
+    pub fn set_budget(&mut self, budget: MemoryBudget) -> &mut Self {
+        self.budget = budget;
+        self.budget.set_used(Resource::Mempool, self.bytes);
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn contains(&self, tx_id: &TxId) -> bool {
+        self.entries.contains_key(tx_id)
+    }
+
+    pub fn get(&self, tx_id: &TxId) -> Option<&AccountStateChoice> {
+        self.entries.get(tx_id).map(|(state, _)| state)
+    }
+
+    /// Estimated memory taken up by pending transactions
+    pub fn size_in_bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Take the events reported since the last call
+    pub fn drain_events(&mut self) -> Vec<MempoolEvent> {
+        self.events.drain(..).collect()
+    }
+
+    /// Check a transaction against the pool and the engine's conflict set
+    /// without inserting it
+    pub fn check(
+        &self,
+        state: &AccountStateChoice,
+        conflict_set: &AccountConflictSet,
+    ) -> Result<(), ConsensusError> {
+        let tx_id = state
+            .tx
+            .try_get_tx_id()
+            .ok_or(ConsensusError::MissingTransactionId)?;
+        if self.entries.contains_key(&tx_id) {
+            return Err(ConsensusError::DuplicateTransaction(tx_id));
+        }
+        if let Some(existing) = self.spends.get(&state.account_state_id) {
+            return Err(ConsensusError::DoubleSpend {
+                account_state: state.account_state_id,
+                existing: *existing,
+            });
+        }
+        if let Some(set) = conflict_set.get(&state.account_state_id) {
+            if set.contains(&tx_id) {
+                return Err(ConsensusError::DuplicateTransaction(tx_id));
+            }
+            if let Some(existing) = set.iter().next() {
+                return Err(ConsensusError::DoubleSpend {
+                    account_state: state.account_state_id,
+                    existing: *existing,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Insert a transaction, evicting lower priority ones while the pool is
+    /// over its capacity or memory cap, or the in-flight state over its
+    /// budget. Returns the IDs of evicted transactions.
+    pub fn insert(
+        &mut self,
+        state: AccountStateChoice,
+        conflict_set: &AccountConflictSet,
+    ) -> Result<Vec<TxId>, ConsensusError> {
+        self.check(&state, conflict_set)?;
+        let priority = Priority::of(&state);
+        let size = bincode::serialized_size(&state).unwrap_or_default() as usize;
+        if size > self.max_bytes {
+            return Err(ConsensusError::MempoolFull);
+        }
+
+        let victims = self.eviction_victims(&priority, size)?;
+        for tx_id in &victims {
+            self.evict(tx_id);
+        }
+
+        let _ = self.queue.insert(priority);
+        let _ = self.spends.insert(state.account_state_id, priority.tx_id);
+        *self.per_account.entry(state.tx.origin).or_insert(0) += 1;
+        self.bytes += size;
+        let _ = self.entries.insert(priority.tx_id, (state, size));
+        self.budget.set_used(Resource::Mempool, self.bytes);
+        self.metrics.set_mempool_depth(self.len());
+        Ok(victims)
+    }
+
+    /// Evict the lowest priority transactions until the in-flight state is
+    /// back under its budget, or the pool is empty. Returns the IDs of
+    /// evicted transactions.
+    pub fn shed(&mut self) -> Vec<TxId> {
+        let mut victims = vec![];
+        while self.budget.is_exhausted() {
+            let lowest = match self.queue.iter().next() {
+                Some(priority) => priority.tx_id,
+                None => break,
+            };
+            self.evict(&lowest);
+            victims.push(lowest);
+        }
+        victims
+    }
+
+    fn evict(&mut self, tx_id: &TxId) {
+        if let Some(evicted) = self.remove(tx_id) {
+            log::debug!("Mempool full, evicted {:?}", tx_id);
+            self.events.push_back(MempoolEvent::Evicted {
+                tx_id: *tx_id,
+                origin: evicted.tx.origin,
+            });
+        }
+    }
+
+    /// Pick the transactions to evict to make room for a new one.
+    ///
+    /// Only transactions with a lower priority than the new one are evicted.
+    /// Accounts with several pending transactions are evicted from first, so
+    /// the only pending transaction of an account goes last; within each group
+    /// lower fees go first, then newer transactions.
+    fn eviction_victims(
+        &self,
+        incoming: &Priority,
+        size: usize,
+    ) -> Result<Vec<TxId>, ConsensusError> {
+        let mut len = self.entries.len();
+        let mut bytes = self.bytes;
+        // Bytes the rest of the in-flight state holds, which we can't free
+        let others = self.budget.used().saturating_sub(self.bytes);
+        if others.saturating_add(size) > self.budget.limit() {
+            return Err(ConsensusError::ResourceExhausted {
+                resource: Resource::Mempool,
+                used: self.budget.used(),
+                limit: self.budget.limit(),
+            });
+        }
+        let mut per_account = self.per_account.clone();
+        let mut candidates = self
+            .queue
+            .range(..incoming)
+            .map(|priority| priority.tx_id)
+            .collect::<Vec<_>>();
+        let mut victims = vec![];
+
+        while len >= self.capacity
+            || bytes + size > self.max_bytes
+            || others + bytes + size > self.budget.limit()
+        {
+            let (position, _) = candidates
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, tx_id)| {
+                    let (state, _) = &self.entries[*tx_id];
+                    let sole = per_account.get(&state.tx.origin) == Some(&1);
+                    (sole, Priority::of(state))
+                })
+                .ok_or(ConsensusError::MempoolFull)?;
+            let tx_id = candidates.swap_remove(position);
+            let (state, victim_size) = &self.entries[&tx_id];
+            if let Some(count) = per_account.get_mut(&state.tx.origin) {
+                *count -= 1;
+            }
+            len -= 1;
+            bytes -= victim_size;
+            victims.push(tx_id);
+        }
+        Ok(victims)
+    }
+
+    /// Remove a transaction from the pool
+    pub fn remove(&mut self, tx_id: &TxId) -> Option<AccountStateChoice> {
+        let (state, size) = self.entries.remove(tx_id)?;
+        let _ = self.queue.remove(&Priority::of(&state));
+        let _ = self.spends.remove(&state.account_state_id);
+        if let Some(count) = self.per_account.get_mut(&state.tx.origin) {
+            *count -= 1;
+            if *count == 0 {
+                let _ = self.per_account.remove(&state.tx.origin);
+            }
+        }
+        self.bytes -= size;
+        self.budget.set_used(Resource::Mempool, self.bytes);
+        self.metrics.set_mempool_depth(self.len());
+        Some(state)
+    }
+
+    /// Take the highest priority transactions out of the pool
+    pub fn next_batch(&mut self, max_batch_size: usize) -> Vec<AccountStateChoice> {
+        let ids = self
+            .queue
+            .iter()
+            .rev()
+            .take(max_batch_size)
+            .map(|priority| priority.tx_id)
+            .collect::<Vec<_>>();
+        ids.iter().filter_map(|tx_id| self.remove(tx_id)).collect()
+    }
+
+    /// Pump the next batch of pending transactions into consensus
+    pub fn pump<C, T, N>(
+        &mut self,
+        engine: &mut C,
+        network: &mut T,
+        common_network: &mut N,
+        mut tree: Option<&mut HashTreeNode>,
+        max_batch_size: usize,
+    ) -> Vec<(TxId, ConsensusStatus)>
+    where
+        C: Consensus,
+        T: ConsensusNetwork,
+        N: CommonConsensusNetwork,
+    {
+        self.next_batch(max_batch_size)
+            .into_iter()
+            .map(|state| {
+                let status =
+                    engine.fire_consensus(&state, network, common_network, tree.as_deref_mut());
+                (state.tx.get_tx_id(), status)
+            })
+            .collect()
+    }
+
+    /// Expire the overdue rounds of `engine`, queueing the transactions of
+    /// the ones timed out again unless they are dropped
+    pub fn expire_rounds<C, T, N>(
+        &mut self,
+        engine: &C,
+        network: &mut T,
+        common_network: &mut N,
+    ) -> Vec<(TxId, ConsensusStatus)>
+    where
+        C: Consensus,
+        T: ConsensusNetwork,
+        N: CommonConsensusNetwork,
+    {
+        let timed_out = engine.expire_rounds(network, common_network);
+        let conflict_set = engine.conflict_set();
+        timed_out
+            .into_iter()
+            .map(|(state, status)| {
+                let tx_id = state.tx.get_tx_id();
+                if !self.drop_timed_out {
+                    if let Err(e) = self.insert(state, &conflict_set) {
+                        log::warn!("Could not queue {:?} again: {}", tx_id, e);
+                    }
+                }
+                (tx_id, status)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+fn pending(account_state: &str, fee: u128, secs: u64) -> AccountStateChoice {
+    pending_from("origin", account_state, fee, secs)
+}
+
+#[cfg(test)]
+fn pending_from(origin: &str, account_state: &str, fee: u128, secs: u64) -> AccountStateChoice {
+    use crate::{
+        account::Account,
+        transaction::{Transaction, TransactionType},
+    };
+
+    let origin = Account::create(
+        &Hash::new(origin.as_bytes()).into(),
+        &Hash::default().into(),
+    );
+    let mut tx = Transaction::new(
+        Hash::default().into(),
+        origin,
+        Hash::new("destination".as_bytes()).into(),
+        Amount::new(10),
+        TransactionType::Transfer,
+        vec![],
+    );
+    tx.timestamp = Duration::from_secs(secs);
+    tx.set_fee(Amount::new(fee)).calculate_tx_id().unwrap();
+    AccountStateChoice::new(Hash::new(account_state.as_bytes()), &tx)
+}
+
+#[test]
+fn test_mempool_priority_order() {
+    let mut mempool = Mempool::new(10, usize::MAX);
+    let conflicts = AccountConflictSet::new();
+    let cheap = pending("A", 1, 1);
+    let old = pending("B", 5, 1);
+    let new = pending("C", 5, 2);
+    for state in [cheap.clone(), new.clone(), old.clone()] {
+        assert!(mempool.insert(state, &conflicts).is_ok());
+    }
+
+    let batch = mempool.next_batch(2);
+    assert_eq!(batch, vec![old, new]);
+    assert_eq!(mempool.next_batch(2), vec![cheap]);
+    assert!(mempool.is_empty());
+}
+
+#[test]
+fn test_mempool_rejects_duplicates_and_double_spends() {
+    let mut mempool = Mempool::new(10, usize::MAX);
+    let mut conflicts = AccountConflictSet::new();
+    let state = pending("A", 1, 1);
+    assert!(mempool.insert(state.clone(), &conflicts).is_ok());
+    assert!(matches!(
+        mempool.insert(state.clone(), &conflicts),
+        Err(ConsensusError::DuplicateTransaction(_))
+    ));
+    assert!(matches!(
+        mempool.insert(pending("A", 2, 1), &conflicts),
+        Err(ConsensusError::DoubleSpend { .. })
+    ));
+
+    let in_consensus = pending("B", 1, 1);
+    conflicts
+        .entry(in_consensus.account_state_id)
+        .or_default()
+        .insert(in_consensus.tx.get_tx_id());
+    assert!(matches!(
+        mempool.insert(pending("B", 3, 3), &conflicts),
+        Err(ConsensusError::DoubleSpend { .. })
+    ));
+}
+
+#[test]
+fn test_mempool_evicts_lowest_priority() {
+    let mut mempool = Mempool::new(2, usize::MAX);
+    let conflicts = AccountConflictSet::new();
+    let cheap = pending("A", 1, 1);
+    assert!(mempool.insert(cheap.clone(), &conflicts).is_ok());
+    assert!(mempool.insert(pending("B", 2, 1), &conflicts).is_ok());
+
+    let evicted = mempool.insert(pending("C", 3, 1), &conflicts).unwrap();
+    assert_eq!(evicted, vec![cheap.tx.get_tx_id()]);
+    assert!(matches!(
+        mempool.insert(pending("D", 0, 1), &conflicts),
+        Err(ConsensusError::MempoolFull)
+    ));
+    assert_eq!(mempool.len(), 2);
+}
+
+#[test]
+fn test_mempool_memory_pressure_eviction_is_fair() {
+    let conflicts = AccountConflictSet::new();
+    let sole = pending_from("alice", "A", 1, 1);
+    let busy_cheap = pending_from("bob", "B", 2, 1);
+    let busy = pending_from("bob", "C", 3, 1);
+    let size = bincode::serialized_size(&sole).unwrap() as usize;
+    let mut mempool = Mempool::new(usize::MAX, size * 3);
+    for state in [sole.clone(), busy_cheap.clone(), busy.clone()] {
+        assert!(mempool.insert(state, &conflicts).unwrap().is_empty());
+    }
+    assert_eq!(mempool.size_in_bytes(), size * 3);
+
+    // Alice's only pending transaction survives despite its lower fee
+    let evicted = mempool
+        .insert(pending_from("carol", "D", 5, 1), &conflicts)
+        .unwrap();
+    assert_eq!(evicted, vec![busy_cheap.tx.get_tx_id()]);
+    assert!(mempool.contains(&sole.tx.get_tx_id()));
+    assert_eq!(
+        mempool.drain_events(),
+        vec![MempoolEvent::Evicted {
+            tx_id: busy_cheap.tx.get_tx_id(),
+            origin: busy_cheap.tx.origin,
+        }]
+    );
+
+    // Once every account has a single pending transaction, the lowest fee goes
+    let evicted = mempool
+        .insert(pending_from("dave", "E", 5, 1), &conflicts)
+        .unwrap();
+    assert_eq!(evicted, vec![sole.tx.get_tx_id()]);
+    assert!(mempool.drain_events().len() == 1);
+    assert!(mempool.drain_events().is_empty());
+}
+
+#[test]
+fn test_mempool_sheds_over_memory_budget() {
+    let conflicts = AccountConflictSet::new();
+    let cheap = pending("A", 1, 1);
+    let size = bincode::serialized_size(&cheap).unwrap() as usize;
+    let budget = MemoryBudget::new(size * 4);
+    let mut mempool = Mempool::new(usize::MAX, usize::MAX);
+    mempool.set_budget(budget.clone());
+    assert!(mempool.insert(cheap.clone(), &conflicts).is_ok());
+    assert!(mempool.insert(pending("B", 2, 1), &conflicts).is_ok());
+    assert_eq!(budget.used_by(Resource::Mempool), size * 2);
+
+    // Other state growing leaves less room, down to none
+    budget.set_used(Resource::Outboxes, size * 2);
+    let evicted = mempool.insert(pending("C", 3, 1), &conflicts).unwrap();
+    assert_eq!(evicted, vec![cheap.tx.get_tx_id()]);
+    budget.set_used(Resource::Outboxes, size * 4);
+    assert!(matches!(
+        mempool.insert(pending("D", 5, 1), &conflicts),
+        Err(ConsensusError::ResourceExhausted {
+            resource: Resource::Mempool,
+            ..
+        })
+    ));
+
+    // Going over the budget sheds the lowest priority transactions
+    budget.set_used(Resource::Outboxes, size * 3);
+    assert_eq!(mempool.shed(), vec![pending("B", 2, 1).tx.get_tx_id()]);
+    assert_eq!(mempool.len(), 1);
+    assert!(!budget.is_exhausted());
+}
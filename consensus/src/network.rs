@@ -1,26 +1,29 @@
-use crate::{account::AccountStateChoice, transaction::Transaction};
-use crypto::hash::Hash;
+use crate::{
+    account::AccountStateChoice,
+    id::{NodeId, TxId},
+    transaction::Transaction,
+};
 use std::collections::HashMap;
 
 pub trait CommonConsensusNetwork {
-    fn get_nodes_except_one(&self, k: u64, node_id: Hash) -> Vec<Hash>;
+    fn get_nodes_except_one(&self, k: u64, node_id: NodeId) -> Vec<NodeId>;
 }
 
 pub trait ConsensusNetwork {
     fn get_sample_network<T: CommonConsensusNetwork>(
         &self,
         k: u64,
-        current_node: Hash,
+        current_node: NodeId,
         network: &T,
-    ) -> Vec<Hash>;
+    ) -> Vec<NodeId>;
 
-    fn request_consensus(&mut self, node_id: Hash, data: &AccountStateChoice) -> Hash;
+    fn request_consensus(&mut self, node_id: NodeId, data: &AccountStateChoice) -> TxId;
 
-    fn request_dag_consensus(&self, node_id: Hash, data: &AccountStateChoice) -> bool;
+    fn request_dag_consensus(&self, node_id: NodeId, data: &AccountStateChoice) -> bool;
 
     fn send_dag_consensus_request(
         &mut self,
-        node_id: Hash,
+        node_id: NodeId,
         data: &AccountStateChoice,
         tx: &Transaction,
         count: usize,
@@ -28,7 +31,7 @@ pub trait ConsensusNetwork {
 
     fn add_outgoing_dag_consensus_request(
         &mut self,
-        node_id: Hash,
+        node_id: NodeId,
         _data: &AccountStateChoice,
         tx: &Transaction,
         count: usize,
@@ -36,24 +39,26 @@ pub trait ConsensusNetwork {
 
     fn accept_incoming_consensus_response(
         &mut self,
-        node_id: Hash,
-        data: Hash,
+        node_id: NodeId,
+        data: TxId,
         accepted: bool,
     ) -> (usize, usize);
 
-    fn remove_outgoing_dag_transaction(&mut self, tx_id: Hash) -> Transaction;
+    /// Stop tracking an outgoing transaction. Returns `None` if it was not
+    /// tracked.
+    fn remove_outgoing_dag_transaction(&mut self, tx_id: TxId) -> Option<Transaction>;
 
-    fn get_node_id(&self) -> Hash;
+    fn get_node_id(&self) -> NodeId;
 
     fn query<T: CommonConsensusNetwork>(
         &mut self,
         k: u64,
         data: &AccountStateChoice,
         network: &T,
-    ) -> HashMap<Hash, u64> {
+    ) -> HashMap<TxId, u64> {
         let nodes = self.get_sample_network(k, self.get_node_id(), network);
-        log::info!("PRINT: get_sample_network {:?}", nodes);
-        let mut query_result: HashMap<Hash, u64> = HashMap::new();
+        tracing::trace!(sample = ?nodes, "Sampled peers to query");
+        let mut query_result: HashMap<TxId, u64> = HashMap::new();
         for node_id in nodes {
             let choice = self.request_consensus(node_id, data);
             *query_result.entry(choice).or_insert(1) += 1;
@@ -76,6 +81,7 @@ pub trait ConsensusNetwork {
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn send_dag_queries_batched<N: CommonConsensusNetwork>(
         &mut self,
         k: u64,
@@ -97,6 +103,7 @@ pub trait ConsensusNetwork {
         );
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn add_transaction_to_batch<N: CommonConsensusNetwork>(
         &mut self,
         k: u64,
@@ -115,12 +122,11 @@ pub trait ConsensusNetwork {
         network: &N,
     ) -> u64 {
         let nodes = self.get_sample_network(k, self.get_node_id(), network);
-        log::info!("PRINT: dag_query: {:?}", nodes);
+        tracing::trace!(sample = ?nodes, "Sampled peers to query");
         let mut query_result: u64 = 0;
         for node_id in nodes {
-            log::info!("PRINT: dag_query: node_id {:?}", node_id);
             let preferred = self.request_dag_consensus(node_id, data);
-            log::info!("PRINT: dag_query: choice {:?}", preferred);
+            tracing::trace!(peer = %node_id, preferred, "Peer answered");
             if preferred {
                 query_result += 1;
             }
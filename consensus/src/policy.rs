@@ -0,0 +1,286 @@
+//! Spending policies accounts opt into, e.g. for custody.
+//!
+//! The owner of an account sets its policy with a `SetSpendingPolicy`
+//! transaction signed by the key the account ID derives from. Transactions
+//! breaking the policy of their origin are then refused.
+
+use crate::{
+    amount::Amount,
+    id::AccountId,
+    transaction::{Transaction, TransactionType},
+    ConsensusError,
+};
+use crypto::{hash::Hash, merkle::MerkleTree, signature::PublicKey};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+/// Cap on the amount an account sends per epoch
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct VelocityCap {
+    pub limit: Amount,
+    pub epoch: Duration,
+}
+
+impl VelocityCap {
+    /// Epoch a transaction falls in, by its timestamp
+    fn epoch_of(&self, tx: &Transaction) -> u64 {
+        match self.epoch.as_millis() {
+            0 => 0,
+            epoch => (tx.timestamp.as_millis() / epoch) as u64,
+        }
+    }
+}
+
+/// Guardrails on the transactions sent from an account. Unset limits do not
+/// apply.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct SpendingPolicy {
+    pub max_per_tx: Option<Amount>,
+    pub velocity: Option<VelocityCap>,
+    /// Only these destinations may be sent to
+    pub allowed_destinations: Option<HashSet<AccountId>>,
+}
+
+impl SpendingPolicy {
+    /// Whether the policy lets anything through
+    pub fn is_unrestricted(&self) -> bool {
+        self.max_per_tx.is_none() && self.velocity.is_none() && self.allowed_destinations.is_none()
+    }
+}
+
+/// Payload of a `SetSpendingPolicy` transaction
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PolicyUpdate {
+    pub policy: SpendingPolicy,
+    /// Key of the owner, whose hash is the account ID
+    pub owner: PublicKey,
+}
+
+impl PolicyUpdate {
+    /// Build the payload of a `SetSpendingPolicy` transaction
+    pub fn to_payload(&self) -> Result<Vec<u8>, ConsensusError> {
+        bincode::serialize(self).map_err(|e| ConsensusError::SerializationError(e.to_string()))
+    }
+}
+
+/// Spending policies of accounts and what they spent in the current epoch
+#[derive(Clone, Debug, Default)]
+pub struct PolicyBook {
+    policies: HashMap<AccountId, SpendingPolicy>,
+    /// Amount sent per account, in the epoch it was last sent in
+    spent: HashMap<AccountId, (u64, Amount)>,
+}
+
+impl PolicyBook {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, account: &AccountId) -> Option<&SpendingPolicy> {
+        self.policies.get(account)
+    }
+
+    /// Set the policy of the origin of a `SetSpendingPolicy` transaction,
+    /// once checked that its owner signed it. An unrestricted policy lifts
+    /// the previous one.
+    pub fn update(&mut self, tx: &Transaction) -> Result<(), ConsensusError> {
+        let update = bincode::deserialize::<PolicyUpdate>(&tx.payload)
+            .map_err(|e| ConsensusError::SerializationError(e.to_string()))?;
+        let owner = AccountId::from(Hash::new(&update.owner.to_bytes()));
+        let signed = tx.clone().verify_tx_sig(&update.owner).unwrap_or(false);
+        if owner != tx.origin || !signed {
+            return Err(ConsensusError::UnauthorizedPolicy(tx.origin));
+        }
+        if update.policy.is_unrestricted() {
+            let _ = self.policies.remove(&tx.origin);
+        } else {
+            let _ = self.policies.insert(tx.origin, update.policy);
+        }
+        Ok(())
+    }
+
+    /// Check a transaction against the policy of its origin. Policy updates
+    /// are not subject to it, so that the owner can always lift it.
+    pub fn check(&self, tx: &Transaction) -> Result<(), ConsensusError> {
+        let policy = match self.policies.get(&tx.origin) {
+            Some(policy) if tx.tx_type != TransactionType::SetSpendingPolicy => policy,
+            _ => return Ok(()),
+        };
+        if let Some(limit) = policy.max_per_tx.filter(|limit| tx.amount > *limit) {
+            return Err(ConsensusError::SpendingLimitExceeded {
+                account: tx.origin,
+                limit,
+                amount: tx.amount,
+            });
+        }
+        if let Some(cap) = policy.velocity {
+            let spent = self.spent_in(&tx.origin, cap.epoch_of(tx));
+            if spent.saturating_add(tx.amount) > cap.limit {
+                return Err(ConsensusError::VelocityLimitExceeded {
+                    account: tx.origin,
+                    limit: cap.limit,
+                    spent,
+                    amount: tx.amount,
+                });
+            }
+        }
+        if let Some(allowed) = &policy.allowed_destinations {
+            if !allowed.contains(&tx.destination) {
+                return Err(ConsensusError::DestinationNotAllowed {
+                    account: tx.origin,
+                    destination: tx.destination,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Record the amount sent by an applied transaction
+    pub fn record(&mut self, tx: &Transaction) {
+        let cap = match self
+            .policies
+            .get(&tx.origin)
+            .and_then(|policy| policy.velocity)
+        {
+            Some(cap) if tx.tx_type != TransactionType::SetSpendingPolicy => cap,
+            _ => return,
+        };
+        let epoch = cap.epoch_of(tx);
+        let spent = self.spent_in(&tx.origin, epoch).saturating_add(tx.amount);
+        let _ = self.spent.insert(tx.origin, (epoch, spent));
+    }
+
+    /// Root of a Merkle tree over the policies, sorted by account, along
+    /// with what each account spent under its velocity cap
+    pub fn root(&self) -> Hash {
+        let mut accounts = self.policies.keys().collect::<Vec<_>>();
+        accounts.sort();
+        let leaves = accounts
+            .into_iter()
+            .map(|account| policy_leaf(account, &self.policies[account], self.spent.get(account)))
+            .collect::<Vec<_>>();
+        MerkleTree::new(&leaves).root()
+    }
+
+    fn spent_in(&self, account: &AccountId, epoch: u64) -> Amount {
+        match self.spent.get(account) {
+            Some((spent_epoch, spent)) if *spent_epoch == epoch => *spent,
+            _ => Amount::ZERO,
+        }
+    }
+}
+
+/// Tree leaf of the policy of an account. Destinations are sorted, so that
+/// the leaf doesn't depend on the order of the set.
+fn policy_leaf(
+    account: &AccountId,
+    policy: &SpendingPolicy,
+    spent: Option<&(u64, Amount)>,
+) -> Hash {
+    let mut bytes = account.as_ref().to_vec();
+    match policy.max_per_tx {
+        Some(limit) => {
+            bytes.push(1);
+            bytes.extend_from_slice(&limit.base_units().to_le_bytes());
+        }
+        None => bytes.push(0),
+    }
+    match policy.velocity {
+        Some(cap) => {
+            bytes.push(1);
+            bytes.extend_from_slice(&cap.limit.base_units().to_le_bytes());
+            bytes.extend_from_slice(&cap.epoch.as_millis().to_le_bytes());
+        }
+        None => bytes.push(0),
+    }
+    match &policy.allowed_destinations {
+        Some(allowed) => {
+            let mut allowed = allowed.iter().collect::<Vec<_>>();
+            allowed.sort();
+            bytes.push(1);
+            bytes.extend_from_slice(&(allowed.len() as u64).to_le_bytes());
+            for destination in allowed {
+                bytes.extend_from_slice(destination.as_ref());
+            }
+        }
+        None => bytes.push(0),
+    }
+    if let Some((epoch, spent)) = spent {
+        bytes.extend_from_slice(&epoch.to_le_bytes());
+        bytes.extend_from_slice(&spent.base_units().to_le_bytes());
+    }
+    Hash::new(&bytes)
+}
+
+#[test]
+fn test_spending_policy_is_enforced() {
+    use crate::account::Account;
+    use crypto::signature::PrivateKey;
+
+    let key = PrivateKey::generate();
+    let owner = AccountId::from(Hash::new(&key.public_key().to_bytes()));
+    let account = Account::create(&owner, &Hash::default().into());
+    let (allowed, other) = (
+        AccountId::from(Hash::new("allowed".as_bytes())),
+        AccountId::from(Hash::new("other".as_bytes())),
+    );
+    let transfer = |destination: AccountId, amount: u128, timestamp: u64| {
+        let mut tx = Transaction::new(
+            Hash::default().into(),
+            account.clone(),
+            destination,
+            Amount::new(amount),
+            TransactionType::Transfer,
+            vec![],
+        );
+        tx.timestamp = Duration::from_secs(timestamp);
+        tx
+    };
+
+    let update = PolicyUpdate {
+        policy: SpendingPolicy {
+            max_per_tx: Some(Amount::new(50)),
+            velocity: Some(VelocityCap {
+                limit: Amount::new(80),
+                epoch: Duration::from_secs(60),
+            }),
+            allowed_destinations: Some([allowed].into_iter().collect()),
+        },
+        owner: key.public_key(),
+    };
+    let mut policy_tx = Transaction::new(
+        Hash::default().into(),
+        account.clone(),
+        owner,
+        Amount::ZERO,
+        TransactionType::SetSpendingPolicy,
+        update.to_payload().unwrap(),
+    );
+    let mut book = PolicyBook::new();
+    assert!(matches!(
+        book.update(&policy_tx),
+        Err(ConsensusError::UnauthorizedPolicy(_))
+    ));
+    policy_tx.sign_and_set_signature(&key).unwrap();
+    book.update(&policy_tx).unwrap();
+
+    assert!(matches!(
+        book.check(&transfer(allowed, 60, 0)),
+        Err(ConsensusError::SpendingLimitExceeded { limit, .. }) if limit == Amount::new(50)
+    ));
+    assert!(matches!(
+        book.check(&transfer(other, 10, 0)),
+        Err(ConsensusError::DestinationNotAllowed { .. })
+    ));
+    for tx in [transfer(allowed, 50, 0), transfer(allowed, 20, 30)] {
+        book.check(&tx).unwrap();
+        book.record(&tx);
+    }
+    assert!(matches!(
+        book.check(&transfer(allowed, 20, 59)),
+        Err(ConsensusError::VelocityLimitExceeded { spent, .. }) if spent == Amount::new(70)
+    ));
+    // The cap resets with the next epoch
+    book.check(&transfer(allowed, 20, 60)).unwrap();
+}
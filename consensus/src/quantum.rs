@@ -1,6 +1,8 @@
 use crate::{
-    account::AccountStateChoice,
+    account::{AccountStateChoice, SequenceTracker},
     config::ConsensusConfig,
+    drain::{self, EngineState},
+    id::TxId,
     network::{CommonConsensusNetwork, ConsensusNetwork},
     transaction::Transaction,
     tree::HashTreeNode,
@@ -12,7 +14,8 @@ use std::sync::{Arc, RwLock};
 
 pub struct QuantumConsensus {
     conflict_set: Arc<RwLock<AccountConflictSet>>,
-    choice: Arc<RwLock<HashMap<Hash, Hash>>>,
+    choice: Arc<RwLock<HashMap<Hash, TxId>>>,
+    sequences: Arc<SequenceTracker>,
     // network: Box<dyn ConsensusNetwork>,
     config: ConsensusConfig,
 }
@@ -36,6 +39,7 @@ impl Consensus for QuantumConsensus {
         Self {
             conflict_set: Arc::new(RwLock::new(HashMap::new())),
             choice: Arc::new(RwLock::new(HashMap::new())),
+            sequences: Arc::new(SequenceTracker::default()),
             config,
         }
     }
@@ -49,7 +53,7 @@ impl Consensus for QuantumConsensus {
             if let Some(set) = cs.get_mut(&state.account_state_id) {
                 set.insert(state.tx.get_tx_id());
             } else {
-                let mut set: HashSet<Hash> = HashSet::new();
+                let mut set: HashSet<TxId> = HashSet::new();
                 set.insert(state.tx.get_tx_id());
                 cs.insert(state.account_state_id, set);
             }
@@ -68,9 +72,8 @@ impl Consensus for QuantumConsensus {
         T: ConsensusNetwork,
         N: CommonConsensusNetwork,
     {
+        let _span = crate::round_span(&tx.get_tx_id()).entered();
         self.query(state);
-
-        log::info!("PRINT: fire_consensus: #3");
         network.send_dag_queries(self.config.k, tx, state, common_network, count);
     }
 
@@ -94,12 +97,13 @@ impl Consensus for QuantumConsensus {
         T: ConsensusNetwork,
         N: CommonConsensusNetwork,
     {
+        let _span = crate::round_span(&state.tx.get_tx_id()).entered();
         let exists = self.has_conflicts(state);
         self.query(state);
         if exists {
             return ConsensusStatus::InProgress;
         }
-        let mut confidence: HashMap<Hash, u64> = HashMap::new();
+        let mut confidence: HashMap<TxId, u64> = HashMap::new();
         let mut choice = state.tx.get_tx_id();
         {
             self.choice
@@ -110,9 +114,8 @@ impl Consensus for QuantumConsensus {
         let mut last_choice = state.tx.get_tx_id();
         let mut choice_count: u64 = 0;
         loop {
-            log::info!("PRINT: choice_count: {:?}", choice_count);
-            let acceptance = network.query(self.config.k, &state, common_network);
-            log::info!("PRINT: acceptance: {:?}\n", acceptance);
+            let acceptance = network.query(self.config.k, state, common_network);
+            tracing::trace!(choice_count, ?acceptance, "Queried the sample");
 
             let cs = self
                 .conflict_set
@@ -122,9 +125,8 @@ impl Consensus for QuantumConsensus {
                 .unwrap()
                 .clone();
             for set_id in &cs {
-                log::info!("PRINT: set_id: {:?}", set_id);
                 if let Some(p) = acceptance.get(set_id) {
-                    log::info!("PRINT:# set_id: {:?} [{:?}]", set_id, p);
+                    tracing::trace!(candidate = %set_id, votes = *p, "Counted votes");
                     if self.config.threshold(*p) {
                         *confidence.entry(*set_id).or_insert(1) += 1;
                         let iterated_confidence_count = confidence.get(set_id);
@@ -147,6 +149,9 @@ impl Consensus for QuantumConsensus {
                         } else {
                             choice_count += 1;
                             if choice_count > self.config.beta {
+                                if choice == state.tx.get_tx_id() {
+                                    self.sequences.advance(&state.tx);
+                                }
                                 return ConsensusStatus::Accept(choice);
                             }
                         }
@@ -158,7 +163,10 @@ impl Consensus for QuantumConsensus {
         //ConsensusStatus::Reject
     }
 
-    fn on_query(&self, state: &AccountStateChoice) -> (Hash, bool) {
+    fn on_query(&self, state: &AccountStateChoice) -> (TxId, bool) {
+        if !self.sequences.is_next(&state.tx) {
+            return (state.tx.get_tx_id(), false);
+        }
         let exists = if let Some(set) = self
             .conflict_set
             .write()
@@ -178,4 +186,20 @@ impl Consensus for QuantumConsensus {
     fn target_count(&self) -> usize {
         self.config.k as usize
     }
+
+    fn conflict_set(&self) -> AccountConflictSet {
+        self.conflict_set.read().unwrap().clone()
+    }
+
+    fn prune(&self, finalized: &HashSet<TxId>) {
+        drain::prune_conflict_set(&mut self.conflict_set.write().unwrap(), finalized);
+    }
+
+    fn export_state(&self) -> EngineState {
+        EngineState::export(&self.conflict_set, &self.choice, &self.sequences)
+    }
+
+    fn import_state(&self, state: &EngineState) {
+        state.merge_into(&self.conflict_set, &self.choice, &self.sequences);
+    }
 }
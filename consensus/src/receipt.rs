@@ -0,0 +1,145 @@
+//! Receipts of transactions.
+//!
+//! Once submitted, a transaction is settled by consensus rounds the
+//! submitter takes no part in. A [`Receipt`] records its fate: its status,
+//! the round that accepted it, when it was settled and the aggregated
+//! signature certifying it. A [`ReceiptStore`] keeps them through a
+//! [`Storage`], so that they survive restarts of the node.
+
+use crate::{id::TxId, transaction::TransactionStatus, ConsensusError};
+use crypto::signature::Signature;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime};
+use storage::{Storage, TypedStore};
+
+/// Keyspace of the receipts
+const RECEIPTS_TREE: &str = "receipts";
+/// Schema version of the stored receipts
+const RECEIPT_VERSION: u32 = 1;
+
+/// Fate of a transaction
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Receipt {
+    pub tx_id: TxId,
+    pub status: TransactionStatus,
+    /// Round that settled the transaction, none while it is pending or when
+    /// it was settled without rounds, as on development nodes
+    pub round: Option<u64>,
+    /// When the receipt was last updated, since the Unix epoch
+    pub timestamp: Duration,
+    /// Aggregated signature of the signers, once the transaction is accepted
+    pub aggregate_sig: Option<Signature>,
+}
+
+impl Receipt {
+    /// Receipt of a transaction waiting for consensus
+    pub fn pending(tx_id: TxId) -> Self {
+        Self {
+            tx_id,
+            status: TransactionStatus::Pending,
+            round: None,
+            timestamp: now(),
+            aggregate_sig: None,
+        }
+    }
+
+    /// Record the outcome of consensus on the transaction
+    pub fn settle(
+        &mut self,
+        status: TransactionStatus,
+        round: Option<u64>,
+        aggregate_sig: Option<Signature>,
+    ) -> &mut Self {
+        self.status = status;
+        self.round = round;
+        self.aggregate_sig = aggregate_sig;
+        self.timestamp = now();
+        self
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.status == TransactionStatus::Pending
+    }
+}
+
+fn now() -> Duration {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+}
+
+/// Receipts persisted through a [`Storage`], by transaction ID
+pub struct ReceiptStore<S: Storage> {
+    receipts: TypedStore<S, Receipt>,
+}
+
+impl<S: Storage> ReceiptStore<S> {
+    /// Keep the receipts in their own keyspace of `storage`
+    pub fn open(storage: &S) -> Result<Self, ConsensusError> {
+        Ok(Self {
+            receipts: TypedStore::open(storage, RECEIPTS_TREE, RECEIPT_VERSION)?,
+        })
+    }
+
+    /// Store `receipt`, replacing the previous receipt of its transaction
+    pub fn record(&mut self, receipt: &Receipt) -> Result<(), ConsensusError> {
+        Ok(self.receipts.put(receipt.tx_id.as_hash(), receipt)?)
+    }
+
+    pub fn get(&self, tx_id: &TxId) -> Result<Option<Receipt>, ConsensusError> {
+        Ok(self.receipts.get(tx_id.as_hash())?)
+    }
+
+    /// Settle the pending receipt of a transaction, returning it, or None
+    /// if the transaction has no receipt
+    pub fn settle(
+        &mut self,
+        tx_id: &TxId,
+        status: TransactionStatus,
+        round: Option<u64>,
+        aggregate_sig: Option<Signature>,
+    ) -> Result<Option<Receipt>, ConsensusError> {
+        let mut receipt = match self.get(tx_id)? {
+            Some(receipt) => receipt,
+            None => return Ok(None),
+        };
+        let _ = receipt.settle(status, round, aggregate_sig);
+        self.record(&receipt)?;
+        Ok(Some(receipt))
+    }
+
+    pub fn flush(&mut self) -> Result<(), ConsensusError> {
+        Ok(self.receipts.flush()?)
+    }
+}
+
+#[test]
+fn test_receipts_survive_the_store() {
+    use crypto::hash::Hash;
+    use storage::memory::MemoryStorage;
+
+    let storage = MemoryStorage::new(None).unwrap();
+    let tx_id = TxId::from(Hash::new("tx".as_bytes()));
+    let mut receipts = ReceiptStore::open(&storage).unwrap();
+    assert_eq!(receipts.get(&tx_id).unwrap(), None);
+    assert_eq!(
+        receipts
+            .settle(&tx_id, TransactionStatus::Accepted, Some(3), None)
+            .unwrap(),
+        None
+    );
+
+    receipts.record(&Receipt::pending(tx_id)).unwrap();
+    assert!(receipts.get(&tx_id).unwrap().unwrap().is_pending());
+    let settled = receipts
+        .settle(&tx_id, TransactionStatus::Accepted, Some(3), None)
+        .unwrap()
+        .unwrap();
+    receipts.flush().unwrap();
+
+    let reopened = ReceiptStore::open(&storage).unwrap();
+    let receipt = reopened.get(&tx_id).unwrap().unwrap();
+    assert_eq!(receipt, settled);
+    assert_eq!(receipt.status, TransactionStatus::Accepted);
+    assert_eq!(receipt.round, Some(3));
+}
@@ -0,0 +1,344 @@
+use crate::{finality::FinalityCertificate, id::TxId, transaction::Transaction, ConsensusError};
+use crypto::{hash::Hash, signature::PublicKey};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// Summary of the transactions a node finalized, exchanged once a partition
+/// heals to find out whether both sides diverged
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct StateDigest {
+    /// Finalized transaction per account state
+    pub finalized: BTreeMap<Hash, TxId>,
+    pub digest: Hash,
+}
+
+impl StateDigest {
+    pub fn new(finalized: BTreeMap<Hash, TxId>) -> Result<Self, ConsensusError> {
+        let digest = Hash::serialize(&finalized)
+            .map_err(|e| ConsensusError::SerializationError(e.to_string()))?;
+        Ok(Self { finalized, digest })
+    }
+
+    /// Check that the digest commits to the finalized transactions
+    pub fn verify(&self) -> bool {
+        Hash::serialize(&self.finalized).is_ok_and(|digest| digest == self.digest)
+    }
+
+    /// Account states both sides finalized different transactions for
+    pub fn diverging(&self, other: &StateDigest) -> Vec<Hash> {
+        if self.digest == other.digest {
+            return vec![];
+        }
+        self.finalized
+            .iter()
+            .filter(|(account_state, tx_id)| {
+                other
+                    .finalized
+                    .get(account_state)
+                    .is_some_and(|theirs| theirs != *tx_id)
+            })
+            .map(|(account_state, _)| *account_state)
+            .collect()
+    }
+}
+
+/// Share of the total stake a certificate must carry. A fraction of
+/// integers, so that every node computes the same threshold.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct Quorum {
+    pub numerator: u128,
+    pub denominator: u128,
+}
+
+impl Quorum {
+    /// Two thirds of the stake, so that certificates of conflicting
+    /// transactions need a third of it to sign both
+    pub const TWO_THIRDS: Self = Self::new(2, 3);
+
+    pub const fn new(numerator: u128, denominator: u128) -> Self {
+        Self {
+            numerator,
+            denominator,
+        }
+    }
+
+    /// Least stake out of `total` reaching the quorum, rounded up
+    pub fn required(&self, total: u128) -> u128 {
+        let denominator = self.denominator.max(1);
+        let whole = (total / denominator).saturating_mul(self.numerator);
+        let part = (total % denominator)
+            .saturating_mul(self.numerator)
+            .div_ceil(denominator);
+        whole.saturating_add(part)
+    }
+}
+
+/// Stake of the validators whose signatures certify finalized transactions
+#[derive(Clone, Debug, Default)]
+pub struct StakeTable {
+    validators: HashMap<Hash, (PublicKey, u128)>,
+}
+
+impl StakeTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, public_key: PublicKey, stake: u128) -> &mut Self {
+        let _ = self
+            .validators
+            .insert(Hash::new(&public_key.to_bytes()), (public_key, stake));
+        self
+    }
+
+    pub fn total(&self) -> u128 {
+        self.validators.values().map(|(_, stake)| stake).sum()
+    }
+
+    /// Public key and stake of the validator whose public key hashes to
+    /// `signer`
+    pub fn get(&self, signer: &Hash) -> Option<(PublicKey, u128)> {
+        self.validators.get(signer).copied()
+    }
+
+    /// IDs of the validators, sorted
+    pub fn signers(&self) -> Vec<Hash> {
+        let mut signers = self.validators.keys().copied().collect::<Vec<_>>();
+        signers.sort();
+        signers
+    }
+}
+
+/// Outcome of reconciling a diverging account state
+#[derive(Clone, Debug, PartialEq)]
+pub enum Resolution {
+    /// Our transaction carries the stronger certificate
+    Keep { account_state: Hash, tx_id: TxId },
+    /// Their transaction carries the stronger certificate and replaces ours
+    Adopt {
+        account_state: Hash,
+        replaced: TxId,
+        tx_id: TxId,
+    },
+    /// Neither certificate prevails, operators have to step in
+    Irreconcilable {
+        account_state: Hash,
+        ours: TxId,
+        theirs: TxId,
+    },
+}
+
+/// Transaction finalized for an account state, along with the certificate
+/// of its finality
+pub type Claim = (Hash, Transaction, FinalityCertificate);
+
+/// Resolves the transactions both sides of a healed partition finalized for
+/// the same account state.
+///
+/// The transaction whose finality certificate carries more stake wins,
+/// provided the certificate reaches the quorum. Ties and conflicts where no
+/// certificate reaches the quorum are irreconcilable.
+pub struct Reconciler {
+    stake: StakeTable,
+    /// Share of the total stake a certificate must carry
+    quorum: Quorum,
+    finalized: HashMap<Hash, (Transaction, FinalityCertificate)>,
+}
+
+impl Reconciler {
+    pub fn new(stake: StakeTable, quorum: Quorum) -> Self {
+        Self {
+            stake,
+            quorum,
+            finalized: HashMap::new(),
+        }
+    }
+
+    /// Record a transaction finalized locally, with its certificate
+    pub fn finalize(
+        &mut self,
+        account_state: Hash,
+        tx: Transaction,
+        certificate: FinalityCertificate,
+    ) {
+        let _ = self.finalized.insert(account_state, (tx, certificate));
+    }
+
+    pub fn finalized(&self, account_state: &Hash) -> Option<&Transaction> {
+        self.finalized.get(account_state).map(|(tx, _)| tx)
+    }
+
+    /// Digest of the transactions finalized locally
+    pub fn digest(&self) -> Result<StateDigest, ConsensusError> {
+        StateDigest::new(
+            self.finalized
+                .iter()
+                .map(|(account_state, (tx, _))| (*account_state, tx.get_tx_id()))
+                .collect(),
+        )
+    }
+
+    /// Transactions to send a peer whose digest diverges from ours, with
+    /// their certificates, so that it can reconcile them
+    pub fn claims_for(&self, theirs: &StateDigest) -> Result<Vec<Claim>, ConsensusError> {
+        Ok(self
+            .digest()?
+            .diverging(theirs)
+            .into_iter()
+            .filter_map(|account_state| {
+                self.finalized
+                    .get(&account_state)
+                    .map(|(tx, certificate)| (account_state, tx.clone(), certificate.clone()))
+            })
+            .collect())
+    }
+
+    /// Reconcile the transactions a peer finalized with ours, adopting theirs
+    /// wherever they carry the stronger certificate
+    pub fn reconcile(&mut self, claims: Vec<Claim>) -> Vec<Resolution> {
+        let mut resolutions = vec![];
+        for (account_state, theirs, certificate) in claims {
+            let ours = match self.finalized.get(&account_state) {
+                Some(ours) if ours.0.get_tx_id() != theirs.get_tx_id() => ours,
+                _ => continue,
+            };
+            let resolution = self.resolve(account_state, ours, (&theirs, &certificate));
+            if let Resolution::Adopt { .. } = resolution {
+                log::warn!(
+                    "Replacing transaction finalized for {:?} after partition",
+                    account_state
+                );
+                let _ = self.finalized.insert(account_state, (theirs, certificate));
+            }
+            resolutions.push(resolution);
+        }
+        resolutions
+    }
+
+    /// Stake certifying the finality of `tx`, if its certificate reaches
+    /// the quorum
+    fn certified(&self, tx: &Transaction, certificate: &FinalityCertificate) -> Option<u128> {
+        if certificate.tx_id != tx.get_tx_id() {
+            return None;
+        }
+        certificate.verify(&self.stake, self.quorum).ok()
+    }
+
+    fn resolve(
+        &self,
+        account_state: Hash,
+        (ours, our_certificate): &(Transaction, FinalityCertificate),
+        (theirs, their_certificate): (&Transaction, &FinalityCertificate),
+    ) -> Resolution {
+        let our_stake = self.certified(ours, our_certificate);
+        let their_stake = self.certified(theirs, their_certificate);
+        if our_stake == their_stake {
+            log::error!(
+                "Irreconcilable conflict for {:?}: {:?} against {:?} stake",
+                account_state,
+                our_stake,
+                their_stake
+            );
+            return Resolution::Irreconcilable {
+                account_state,
+                ours: ours.get_tx_id(),
+                theirs: theirs.get_tx_id(),
+            };
+        }
+        if our_stake > their_stake {
+            Resolution::Keep {
+                account_state,
+                tx_id: ours.get_tx_id(),
+            }
+        } else {
+            Resolution::Adopt {
+                account_state,
+                replaced: ours.get_tx_id(),
+                tx_id: theirs.get_tx_id(),
+            }
+        }
+    }
+}
+
+#[test]
+fn test_reconcile_after_partition() {
+    use crate::{account::Account, amount::Amount, transaction::TransactionType};
+    use crypto::signature::PrivateKey;
+
+    let keys = (0..4).map(|_| PrivateKey::generate()).collect::<Vec<_>>();
+    let mut stake = StakeTable::new();
+    for (key, weight) in keys.iter().zip([10, 20, 30, 40]) {
+        stake.insert(key.public_key(), weight);
+    }
+    let certified = |name: &str, signers: &[&PrivateKey]| {
+        let origin = Account::create(&Hash::new(name.as_bytes()).into(), &Hash::default().into());
+        let mut tx = Transaction::new(
+            Hash::default().into(),
+            origin,
+            Hash::new("B".as_bytes()).into(),
+            Amount::new(1),
+            TransactionType::Transfer,
+            vec![],
+        );
+        tx.calculate_tx_id().unwrap();
+        let mut certificate = FinalityCertificate::new(tx.get_tx_id());
+        for key in signers {
+            certificate.sign(&stake, key).unwrap();
+        }
+        (tx, certificate)
+    };
+
+    let contested = Hash::new("contested".as_bytes());
+    let tied = Hash::new("tied".as_bytes());
+    let (ours, our_certificate) = certified("ours", &[&keys[0], &keys[1]]);
+    let (theirs, their_certificate) = certified("theirs", &[&keys[2], &keys[3]]);
+    let quorum = Quorum::new(1, 2);
+    let mut left = Reconciler::new(stake.clone(), quorum);
+    let mut right = Reconciler::new(stake.clone(), quorum);
+    left.finalize(contested, ours.clone(), our_certificate.clone());
+    right.finalize(contested, theirs.clone(), their_certificate.clone());
+    let (tied_left, tied_left_certificate) = certified("tied-left", &[&keys[0], &keys[1]]);
+    let (tied_right, tied_right_certificate) = certified("tied-right", &[&keys[2]]);
+    left.finalize(tied, tied_left, tied_left_certificate);
+    right.finalize(tied, tied_right, tied_right_certificate);
+
+    let (left_digest, right_digest) = (left.digest().unwrap(), right.digest().unwrap());
+    assert!(left_digest.verify());
+    let mut diverging = left_digest.diverging(&right_digest);
+    diverging.sort();
+    let mut expected = vec![contested, tied];
+    expected.sort();
+    assert_eq!(diverging, expected);
+
+    let resolutions = left.reconcile(right.claims_for(&left_digest).unwrap());
+    assert!(resolutions.contains(&Resolution::Adopt {
+        account_state: contested,
+        replaced: ours.get_tx_id(),
+        tx_id: theirs.get_tx_id(),
+    }));
+    assert!(resolutions.iter().any(|resolution| matches!(
+        resolution,
+        Resolution::Irreconcilable { account_state, .. } if *account_state == tied
+    )));
+    assert_eq!(left.finalized(&contested), Some(&theirs));
+
+    // Both sides settle on the same transaction
+    let resolutions = right.reconcile(vec![(contested, ours.clone(), our_certificate)]);
+    assert!(
+        matches!(resolutions[0], Resolution::Keep { tx_id, .. } if tx_id == theirs.get_tx_id())
+    );
+
+    // Certificates of another transaction certify nothing
+    let mut fresh = Reconciler::new(stake, quorum);
+    fresh.finalize(
+        contested,
+        ours.clone(),
+        FinalityCertificate::new(ours.get_tx_id()),
+    );
+    let resolutions = fresh.reconcile(vec![(contested, theirs.clone(), their_certificate.clone())]);
+    assert!(matches!(resolutions[0], Resolution::Adopt { .. }));
+    let resolutions = fresh.reconcile(vec![(contested, ours, their_certificate)]);
+    assert!(
+        matches!(resolutions[0], Resolution::Keep { tx_id, .. } if tx_id == theirs.get_tx_id())
+    );
+}
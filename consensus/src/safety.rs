@@ -0,0 +1,165 @@
+//! Audit of the safety of consensus on finalized transactions.
+//!
+//! Consensus is safe if no account spends the same state twice: at most
+//! one transaction carrying a given sequence of an origin may ever be
+//! accepted. A [`SafetyAuditor`] takes finalized transactions, e.g. as read
+//! back from the store of a node or as accepted by a [`Simulation`], and
+//! reports every pair that breaks that rule.
+//!
+//! [`Simulation`]: crate::sim::Simulation
+
+use crate::{
+    id::{AccountId, TxId},
+    transaction::{Transaction, TransactionStatus},
+    ConsensusError,
+};
+use std::collections::{BTreeSet, HashMap};
+use storage::{Storage, TypedStore};
+
+/// Transactions finalized for the same sequence of an account
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConflictingFinalization {
+    pub origin: AccountId,
+    pub sequence: u64,
+    pub tx_ids: BTreeSet<TxId>,
+}
+
+/// Checks that no two finalized transactions spend the same account state
+#[derive(Clone, Debug, Default)]
+pub struct SafetyAuditor {
+    /// Transactions finalized per origin and sequence
+    finalized: HashMap<(AccountId, u64), BTreeSet<TxId>>,
+}
+
+impl SafetyAuditor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Finalized transactions recorded so far
+    pub fn len(&self) -> usize {
+        self.finalized.values().map(BTreeSet::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.finalized.is_empty()
+    }
+
+    /// Record a finalized transaction, returning the conflict it makes with
+    /// those recorded before, if any. Recording a transaction again is a
+    /// no-op.
+    pub fn record(&mut self, tx: &Transaction) -> Result<(), ConflictingFinalization> {
+        let tx_id = match tx.try_get_tx_id() {
+            Some(tx_id) => tx_id,
+            None => return Ok(()),
+        };
+        let tx_ids = self.finalized.entry((tx.origin, tx.sequence)).or_default();
+        let _ = tx_ids.insert(tx_id);
+        if tx_ids.len() > 1 {
+            return Err(ConflictingFinalization {
+                origin: tx.origin,
+                sequence: tx.sequence,
+                tx_ids: tx_ids.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Record the transactions of `tx_ids` that `store` holds as accepted,
+    /// returning how many were
+    pub fn audit_store<S: Storage>(
+        &mut self,
+        store: &TypedStore<S, Transaction>,
+        tx_ids: impl IntoIterator<Item = TxId>,
+    ) -> Result<usize, ConsensusError> {
+        let mut accepted = 0;
+        for tx_id in tx_ids {
+            match store.get(tx_id.as_hash())? {
+                Some(tx) if tx.status == TransactionStatus::Accepted => {
+                    accepted += 1;
+                    if let Err(conflict) = self.record(&tx) {
+                        log::error!(
+                            "{:?} finalized {} transactions for sequence {}",
+                            conflict.origin,
+                            conflict.tx_ids.len(),
+                            conflict.sequence
+                        );
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(accepted)
+    }
+
+    /// Every conflict among the transactions recorded, by origin then
+    /// sequence
+    pub fn conflicts(&self) -> Vec<ConflictingFinalization> {
+        let mut conflicts = self
+            .finalized
+            .iter()
+            .filter(|(_, tx_ids)| tx_ids.len() > 1)
+            .map(|((origin, sequence), tx_ids)| ConflictingFinalization {
+                origin: *origin,
+                sequence: *sequence,
+                tx_ids: tx_ids.clone(),
+            })
+            .collect::<Vec<_>>();
+        conflicts.sort_by_key(|conflict| (conflict.origin, conflict.sequence));
+        conflicts
+    }
+
+    /// Check that no conflicting transactions were recorded, returning the
+    /// first conflict otherwise
+    pub fn check(&self) -> Result<(), ConflictingFinalization> {
+        match self.conflicts().into_iter().next() {
+            Some(conflict) => Err(conflict),
+            None => Ok(()),
+        }
+    }
+}
+
+#[test]
+fn test_safety_auditor_reports_conflicting_finalizations() {
+    use crate::{account::Account, amount::Amount, transaction::TransactionType};
+    use crypto::hash::Hash;
+    use storage::memory::MemoryStorage;
+
+    let origin = Account::create(&Hash::new("A".as_bytes()).into(), &TxId::default());
+    let spend = |destination: &str, status| {
+        let mut tx = Transaction::new(
+            TxId::default(),
+            origin.clone(),
+            Hash::new(destination.as_bytes()).into(),
+            Amount::new(1),
+            TransactionType::Transfer,
+            vec![],
+        );
+        let _ = tx.set_tx_status(status).calculate_tx_id().unwrap();
+        tx
+    };
+    let accepted = spend("B", TransactionStatus::Accepted);
+    let rejected = spend("C", TransactionStatus::Rejected);
+    let conflicting = spend("D", TransactionStatus::Accepted);
+
+    let storage = MemoryStorage::new(None).unwrap();
+    let mut store = TypedStore::open(&storage, "transactions", 1).unwrap();
+    for tx in [&accepted, &rejected] {
+        store.put(tx.get_tx_id().as_hash(), tx).unwrap();
+    }
+    let ids = [&accepted, &rejected, &conflicting].map(Transaction::get_tx_id);
+    let mut auditor = SafetyAuditor::new();
+    // The conflicting transaction is not in the store
+    assert_eq!(auditor.audit_store(&store, ids).unwrap(), 1);
+    assert_eq!(auditor.check(), Ok(()));
+    assert_eq!(auditor.record(&accepted), Ok(()));
+
+    let conflict = auditor.record(&conflicting).unwrap_err();
+    assert_eq!(conflict.origin, origin.id);
+    assert_eq!(
+        conflict.tx_ids,
+        BTreeSet::from([accepted.get_tx_id(), conflicting.get_tx_id()])
+    );
+    assert_eq!(auditor.check(), Err(conflict));
+    assert_eq!(auditor.len(), 2);
+}
@@ -0,0 +1,127 @@
+//! Query samples peers can check were drawn without bias.
+//!
+//! A node drawing its own sample could pick peers it colludes with. A
+//! [`VerifiableSample`] is instead drawn from the VRF output of the
+//! transaction and round under the node's key: candidates are ranked by
+//! the hash of the output and their id, and the first `k` are queried.
+//! Anyone knowing the node's public key and the candidates can recompute
+//! the sample, and the node can't try out outputs until one suits it.
+
+use crate::id::{NodeId, TxId};
+use crypto::{
+    hash::{Hash, HashDomain},
+    signature::{PrivateKey, PublicKey},
+    vrf::{self, VrfProof},
+};
+use serde::{Deserialize, Serialize};
+
+const SAMPLE_DOMAIN: HashDomain = HashDomain::Custom("dagchain/sample");
+
+/// Peers sampled for a round of queries, with the proof they were drawn from
+/// the VRF output of the round
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct VerifiableSample {
+    pub tx_id: TxId,
+    /// Queries of the transaction sent before this round
+    pub attempt: u64,
+    pub proof: VrfProof,
+    /// Peers the sample was drawn from
+    pub candidates: Vec<NodeId>,
+    pub peers: Vec<NodeId>,
+}
+
+impl VerifiableSample {
+    /// Draw `k` of `candidates` for a round of queries of `tx_id`
+    pub fn draw(
+        private_key: &PrivateKey,
+        tx_id: TxId,
+        attempt: u64,
+        mut candidates: Vec<NodeId>,
+        k: usize,
+    ) -> Self {
+        candidates.sort();
+        candidates.dedup();
+        let (output, proof) = vrf::prove(private_key, &round_input(&tx_id, attempt));
+        let peers = select(&output, &candidates, k);
+        Self {
+            tx_id,
+            attempt,
+            proof,
+            candidates,
+            peers,
+        }
+    }
+
+    /// Whether the sample was drawn by the holder of `pub_key` from the VRF
+    /// output of its round
+    pub fn verify(&self, pub_key: &PublicKey) -> bool {
+        let mut candidates = self.candidates.clone();
+        candidates.sort();
+        candidates.dedup();
+        if candidates != self.candidates {
+            return false;
+        }
+        match vrf::verify(
+            pub_key,
+            &round_input(&self.tx_id, self.attempt),
+            &self.proof,
+        ) {
+            Some(output) => select(&output, &self.candidates, self.peers.len()) == self.peers,
+            None => false,
+        }
+    }
+
+    pub fn contains(&self, peer: &NodeId) -> bool {
+        self.peers.contains(peer)
+    }
+}
+
+fn round_input(tx_id: &TxId, attempt: u64) -> Vec<u8> {
+    let mut input = tx_id.as_ref().to_vec();
+    input.extend_from_slice(&attempt.to_le_bytes());
+    input
+}
+
+/// The `k` candidates ranking first by the hash of `output` and their id
+fn select(output: &Hash, candidates: &[NodeId], k: usize) -> Vec<NodeId> {
+    let mut ranked = candidates
+        .iter()
+        .map(|candidate| {
+            let rank = Hash::combine_in(SAMPLE_DOMAIN, &[output.as_ref(), candidate.as_ref()]);
+            (rank, *candidate)
+        })
+        .collect::<Vec<_>>();
+    ranked.sort();
+    ranked.into_iter().take(k).map(|(_, peer)| peer).collect()
+}
+
+#[test]
+fn test_samples_are_verifiable() {
+    let key = PrivateKey::generate();
+    let candidates = (0..20)
+        .map(|n| NodeId::from(Hash::new(format!("peer {}", n).as_bytes())))
+        .collect::<Vec<_>>();
+    let tx_id = TxId::from(Hash::new("tx".as_bytes()));
+    let sample = VerifiableSample::draw(&key, tx_id, 0, candidates.clone(), 5);
+
+    assert_eq!(sample.peers.len(), 5);
+    assert!(sample.verify(&key.public_key()));
+    assert_eq!(
+        VerifiableSample::draw(&key, tx_id, 0, candidates.clone(), 5),
+        sample
+    );
+    // Another round draws another sample
+    assert_ne!(
+        VerifiableSample::draw(&key, tx_id, 1, candidates.clone(), 5).peers,
+        sample.peers
+    );
+
+    // Hand-picked peers, or a sample passed off for another round, fail
+    let mut biased = sample.clone();
+    biased.peers[0] = *candidates.iter().find(|c| !sample.contains(c)).unwrap();
+    assert!(!biased.verify(&key.public_key()));
+    let mut replayed = sample.clone();
+    replayed.attempt = 1;
+    assert!(!replayed.verify(&key.public_key()));
+    assert!(!sample.verify(&PrivateKey::generate().public_key()));
+}
@@ -0,0 +1,250 @@
+//! Application of finalized transactions, in dependency order.
+//!
+//! Transactions are finalized by the engine, or learnt from gossip and
+//! sync, in whatever order they arrive. An [`ApplyScheduler`] holds each
+//! one back until its parent was applied and its origin expects its
+//! sequence, then applies it to the state. Transactions ready at the same
+//! time are applied in the order of their tx ids, so that every node ends
+//! up applying them in the same order, and each one applied may in turn
+//! make others ready.
+
+use crate::{
+    id::{AccountId, TxId},
+    state::StateTrie,
+    transaction::{Transaction, TransactionType},
+    ConsensusError,
+};
+use crypto::cache::VerificationCache;
+use std::collections::{BTreeMap, HashSet};
+
+/// Outcome of applying a finalized transaction
+#[derive(Debug)]
+pub enum Application {
+    Applied(TxId),
+    /// A `CreateAccount` transaction was applied, creating `account_id`.
+    /// Follows the application of the transaction.
+    AccountCreated {
+        tx_id: TxId,
+        account_id: AccountId,
+    },
+    /// The transaction was ready but the state refused it, e.g. for want of
+    /// funds
+    Failed {
+        tx_id: TxId,
+        error: ConsensusError,
+    },
+}
+
+/// Applies finalized transactions to a state once their dependencies are
+pub struct ApplyScheduler {
+    state: StateTrie,
+    applied: HashSet<TxId>,
+    /// Finalized transactions waiting on their dependencies
+    waiting: BTreeMap<TxId, Transaction>,
+    /// Verifies the signatures of the transactions before they apply, if
+    /// set
+    verification: Option<VerificationCache>,
+}
+
+impl ApplyScheduler {
+    /// Apply transactions to `state`, whose transactions are taken as
+    /// applied already
+    pub fn new(state: StateTrie) -> Self {
+        let applied = state.accounts().map(|account| account.last_tx_id).collect();
+        Self {
+            state,
+            applied,
+            waiting: BTreeMap::new(),
+            verification: None,
+        }
+    }
+
+    /// Refuse the transactions not signed by their origin, verifying them
+    /// through `cache`, e.g. the one gossiped transactions were verified
+    /// through on receipt
+    pub fn set_verification_cache(&mut self, cache: VerificationCache) -> &mut Self {
+        self.verification = Some(cache);
+        self
+    }
+
+    pub fn state(&self) -> &StateTrie {
+        &self.state
+    }
+
+    /// Record `tx_id` as applied, e.g. a genesis transaction or one the state
+    /// was restored past
+    pub fn mark_applied(&mut self, tx_id: TxId) -> Vec<Application> {
+        let _ = self.applied.insert(tx_id);
+        self.apply_ready()
+    }
+
+    pub fn is_applied(&self, tx_id: &TxId) -> bool {
+        self.applied.contains(tx_id)
+    }
+
+    /// Finalized transactions waiting on their dependencies
+    pub fn waiting(&self) -> usize {
+        self.waiting.len()
+    }
+
+    /// Take a finalized transaction, returning the transactions applied or
+    /// refused as a result, in the order they were
+    pub fn submit(&mut self, tx: Transaction) -> Result<Vec<Application>, ConsensusError> {
+        let tx_id = tx
+            .try_get_tx_id()
+            .ok_or(ConsensusError::MissingTransactionId)?;
+        if self.applied.contains(&tx_id) || self.waiting.contains_key(&tx_id) {
+            return Ok(vec![]);
+        }
+        let _ = self.waiting.insert(tx_id, tx);
+        Ok(self.apply_ready())
+    }
+
+    /// Apply the waiting transactions that are ready, until none is
+    fn apply_ready(&mut self) -> Vec<Application> {
+        let mut outcomes = vec![];
+        while let Some(tx_id) = self
+            .waiting
+            .iter()
+            .find(|(_, tx)| self.is_ready(tx))
+            .map(|(tx_id, _)| *tx_id)
+        {
+            let tx = self.waiting.remove(&tx_id).unwrap();
+            let verified = match &self.verification {
+                Some(cache) => self.state.verify_signature(&tx, cache),
+                None => Ok(()),
+            };
+            match verified.and_then(|_| self.state.apply(&tx)) {
+                Ok(_) => {
+                    let _ = self.applied.insert(tx_id);
+                    outcomes.push(Application::Applied(tx_id));
+                    if tx.tx_type == TransactionType::CreateAccount {
+                        outcomes.push(Application::AccountCreated {
+                            tx_id,
+                            account_id: tx.destination,
+                        });
+                    }
+                }
+                Err(error) => {
+                    log::warn!("Finalized transaction {} was refused: {}", tx_id, error);
+                    outcomes.push(Application::Failed { tx_id, error });
+                }
+            }
+        }
+        outcomes
+    }
+
+    /// Whether the parents of `tx` were applied, and its origin expects its
+    /// sequence. Transactions whose sequence went by are ready too, for
+    /// the state to refuse them.
+    fn is_ready(&self, tx: &Transaction) -> bool {
+        let parents_applied = tx
+            .parents()
+            .iter()
+            .all(|parent| *parent == TxId::default() || self.applied.contains(parent));
+        parents_applied
+            && self
+                .state
+                .get(&tx.origin)
+                .is_some_and(|origin| tx.sequence <= origin.next_sequence())
+    }
+}
+
+#[test]
+fn test_transactions_are_applied_in_dependency_order() {
+    use crate::{account::Account, amount::Amount, transaction::TransactionType};
+    use crypto::hash::Hash;
+
+    let mut origin = Account::create(&Hash::new("A".as_bytes()).into(), &TxId::default());
+    origin.increase_balance(Amount::new(100)).unwrap();
+    let mut scheduler = ApplyScheduler::new(StateTrie::from_accounts(vec![origin.clone()]));
+    let destination = Hash::new("B".as_bytes()).into();
+
+    let mut chain = vec![];
+    let mut parent = TxId::default();
+    for amount in [10, 20, 200] {
+        let mut tx = Transaction::new(
+            parent,
+            origin.clone(),
+            destination,
+            Amount::new(amount),
+            TransactionType::Transfer,
+            vec![],
+        );
+        tx.calculate_tx_id().unwrap();
+        parent = tx.get_tx_id();
+        origin.update_sequence(tx.sequence);
+        chain.push(tx);
+    }
+    let ids = chain.iter().map(Transaction::get_tx_id).collect::<Vec<_>>();
+
+    // Received last to first
+    assert!(scheduler.submit(chain[2].clone()).unwrap().is_empty());
+    assert!(scheduler.submit(chain[1].clone()).unwrap().is_empty());
+    assert_eq!(scheduler.waiting(), 2);
+    let outcomes = scheduler.submit(chain[0].clone()).unwrap();
+    assert!(matches!(
+        outcomes.as_slice(),
+        [
+            Application::Applied(first),
+            Application::Applied(second),
+            Application::Failed {
+                error: ConsensusError::InsufficientBalance { .. },
+                ..
+            },
+        ] if *first == ids[0] && *second == ids[1]
+    ));
+    assert_eq!(scheduler.waiting(), 0);
+    assert!(scheduler.is_applied(&ids[1]) && !scheduler.is_applied(&ids[2]));
+    assert_eq!(
+        scheduler.state().get(&destination).unwrap().balance,
+        Amount::new(30)
+    );
+    assert!(scheduler.submit(chain[0].clone()).unwrap().is_empty());
+}
+
+#[test]
+fn test_transactions_not_signed_by_their_origin_are_refused() {
+    use crate::{account::Account, amount::Amount};
+    use crypto::signature::PrivateKey;
+
+    let key = PrivateKey::generate();
+    let mut state = StateTrie::new();
+    let origin_id = state.register_key(key.public_key());
+    let mut origin = Account::create(&origin_id, &TxId::default());
+    origin.increase_balance(Amount::new(100)).unwrap();
+    state.insert(&origin);
+    let mut scheduler = ApplyScheduler::new(state);
+    let _ = scheduler.set_verification_cache(VerificationCache::default());
+
+    let transfer = |signer: Option<&PrivateKey>| {
+        let mut tx = Transaction::new(
+            TxId::default(),
+            origin.clone(),
+            origin_id,
+            Amount::new(10),
+            TransactionType::Transfer,
+            vec![],
+        );
+        tx.calculate_tx_id().unwrap();
+        if let Some(signer) = signer {
+            let signature = tx.sign_tx(signer).unwrap();
+            let _ = tx.set_signature(&key.public_key(), &signature);
+        }
+        tx
+    };
+    for tx in [transfer(None), transfer(Some(&PrivateKey::generate()))] {
+        assert!(matches!(
+            scheduler.submit(tx).unwrap().as_slice(),
+            [Application::Failed {
+                error: ConsensusError::SignatureError(_),
+                ..
+            }]
+        ));
+    }
+    let signed = transfer(Some(&key));
+    assert!(matches!(
+        scheduler.submit(signed.clone()).unwrap().as_slice(),
+        [Application::Applied(tx_id)] if *tx_id == signed.get_tx_id()
+    ));
+}
@@ -0,0 +1,320 @@
+use crate::{
+    account::AccountStateChoice,
+    checkpoint::Checkpoint,
+    config::ConsensusConfig,
+    drain::EngineState,
+    id::TxId,
+    inspect::ConflictReport,
+    network::{CommonConsensusNetwork, ConsensusNetwork},
+    transaction::Transaction,
+    tree::HashTreeNode,
+    AccountConflictSet, Consensus, ConsensusStatus,
+};
+use crypto::hash::Hash;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::RwLock;
+
+/// How often the shadow engine agreed with the primary
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct ShadowReport {
+    /// Queries answered by both engines
+    pub answers: u64,
+    /// Queries the engines answered differently
+    pub divergent_answers: u64,
+    /// Rounds resolved by both engines
+    pub outcomes: u64,
+    /// Rounds the engines resolved differently
+    pub divergent_outcomes: u64,
+}
+
+/// Runs a shadow engine alongside the primary one.
+///
+/// The shadow receives the same inputs as the primary, but only the primary
+/// answers and talks to the network: the outputs of the shadow are compared
+/// with those of the primary and discrepancies logged. Once it can be
+/// trusted, the shadow is promoted to primary at a checkpoint.
+pub struct ShadowConsensus<P: Consensus, S: Consensus> {
+    primary: P,
+    shadow: S,
+    /// Height of the checkpoint to promote the shadow at
+    promotion: Option<u64>,
+    report: RwLock<ShadowReport>,
+}
+
+impl<P: Consensus, S: Consensus> ShadowConsensus<P, S> {
+    /// Shadow `primary` with `shadow`, which picks up the state of the primary
+    pub fn with_engines(primary: P, shadow: S) -> Self {
+        shadow.import_state(&primary.export_state());
+        Self {
+            primary,
+            shadow,
+            promotion: None,
+            report: Default::default(),
+        }
+    }
+
+    pub fn primary(&self) -> &P {
+        &self.primary
+    }
+
+    pub fn shadow(&self) -> &S {
+        &self.shadow
+    }
+
+    pub fn report(&self) -> ShadowReport {
+        self.report.read().unwrap().clone()
+    }
+
+    /// Schedule the promotion of the shadow at the checkpoint of `height`
+    pub fn promote_at(&mut self, height: u64) -> &mut Self {
+        self.promotion = Some(height);
+        self
+    }
+
+    pub fn cancel_promotion(&mut self) -> &mut Self {
+        self.promotion = None;
+        self
+    }
+
+    /// Whether the shadow is due to be promoted at `checkpoint`
+    pub fn is_promotion_due(&self, checkpoint: &Checkpoint) -> bool {
+        self.promotion
+            .is_some_and(|height| checkpoint.height >= height)
+    }
+
+    /// Promote the shadow to primary, handing it over the state of the
+    /// primary so that choices made so far survive the swap
+    pub fn promote(self) -> S {
+        let report = self.report();
+        log::info!(
+            "Promoting shadow engine ({}/{} answers and {}/{} outcomes divergent)",
+            report.divergent_answers,
+            report.answers,
+            report.divergent_outcomes,
+            report.outcomes
+        );
+        self.shadow.import_state(&self.primary.export_state());
+        self.shadow
+    }
+
+    fn compare_answers(
+        &self,
+        state: &AccountStateChoice,
+        primary: &(TxId, bool),
+        shadow: &(TxId, bool),
+    ) {
+        let mut report = self.report.write().unwrap();
+        report.answers += 1;
+        if primary != shadow {
+            report.divergent_answers += 1;
+            log::warn!(
+                "Shadow engine answered {:?} instead of {:?} for {:?}",
+                shadow,
+                primary,
+                state.tx.get_tx_id()
+            );
+        }
+    }
+
+    fn compare_outcomes(
+        &self,
+        state: &AccountStateChoice,
+        primary: &ConsensusStatus,
+        shadow: &ConsensusStatus,
+    ) {
+        let mut report = self.report.write().unwrap();
+        report.outcomes += 1;
+        if primary != shadow {
+            report.divergent_outcomes += 1;
+            log::warn!(
+                "Shadow engine resolved {:?} instead of {:?} for {:?}",
+                shadow,
+                primary,
+                state.tx.get_tx_id()
+            );
+        }
+    }
+}
+
+impl<P: Consensus, S: Consensus> Consensus for ShadowConsensus<P, S> {
+    fn new(config: ConsensusConfig) -> Self
+    where
+        Self: Sized,
+    {
+        Self::with_engines(P::new(config.clone()), S::new(config))
+    }
+
+    fn query(&self, state: &AccountStateChoice) -> &Self
+    where
+        Self: Sized,
+    {
+        self.primary.query(state);
+        self.shadow.query(state);
+        self
+    }
+
+    /// Only the primary sends requests, the shadow just records the query
+    fn send_consensus_requests<T, N>(
+        &self,
+        state: &AccountStateChoice,
+        tx: &Transaction,
+        network: &mut T,
+        common_network: &mut N,
+        count: usize,
+    ) where
+        T: ConsensusNetwork,
+        N: CommonConsensusNetwork,
+    {
+        self.shadow.query(state);
+        self.primary
+            .send_consensus_requests(state, tx, network, common_network, count);
+    }
+
+    /// Both engines resolve the round from the same acceptance, the shadow
+    /// on a copy of the tree
+    fn complete_dag_consensus(
+        &self,
+        acceptance: usize,
+        state: &AccountStateChoice,
+        tree: &mut HashTreeNode,
+    ) -> ConsensusStatus {
+        let mut shadow_tree = tree.clone();
+        let status = self.primary.complete_dag_consensus(acceptance, state, tree);
+        let shadow = self
+            .shadow
+            .complete_dag_consensus(acceptance, state, &mut shadow_tree);
+        self.compare_outcomes(state, &status, &shadow);
+        status
+    }
+
+    /// Only the primary queries the network, the shadow just records the
+    /// query
+    fn fire_consensus<T, N>(
+        &mut self,
+        state: &AccountStateChoice,
+        network: &mut T,
+        common_network: &mut N,
+        tree: Option<&mut HashTreeNode>,
+    ) -> ConsensusStatus
+    where
+        T: ConsensusNetwork,
+        N: CommonConsensusNetwork,
+    {
+        self.shadow.query(state);
+        self.primary
+            .fire_consensus(state, network, common_network, tree)
+    }
+
+    /// Only the primary resamples peers, the shadow forgets the rounds that
+    /// timed out
+    fn expire_rounds<T, N>(
+        &self,
+        network: &mut T,
+        common_network: &mut N,
+    ) -> Vec<(AccountStateChoice, ConsensusStatus)>
+    where
+        T: ConsensusNetwork,
+        N: CommonConsensusNetwork,
+    {
+        let timed_out = self.primary.expire_rounds(network, common_network);
+        if !timed_out.is_empty() {
+            self.shadow.prune(
+                &timed_out
+                    .iter()
+                    .map(|(state, _)| state.tx.get_tx_id())
+                    .collect(),
+            );
+        }
+        timed_out
+    }
+
+    fn on_query(&self, state: &AccountStateChoice) -> (TxId, bool) {
+        let answer = self.primary.on_query(state);
+        let shadow = self.shadow.on_query(state);
+        self.compare_answers(state, &answer, &shadow);
+        answer
+    }
+
+    fn target_count(&self) -> usize {
+        self.primary.target_count()
+    }
+
+    fn conflict_set(&self) -> AccountConflictSet {
+        self.primary.conflict_set()
+    }
+
+    fn conflicts_for(&self, account_state_id: &Hash) -> Option<ConflictReport> {
+        self.primary.conflicts_for(account_state_id)
+    }
+
+    fn prune(&self, finalized: &HashSet<TxId>) {
+        self.primary.prune(finalized);
+        self.shadow.prune(finalized);
+    }
+
+    fn export_state(&self) -> EngineState {
+        self.primary.export_state()
+    }
+
+    fn import_state(&self, state: &EngineState) {
+        self.primary.import_state(state);
+        self.shadow.import_state(state);
+    }
+}
+
+#[test]
+fn test_shadow_engine_is_compared_and_promoted() {
+    use crate::{
+        account::Account,
+        amount::Amount,
+        dag_consensus::DagConsensus,
+        transaction::{Transaction, TransactionType},
+    };
+    use std::collections::HashMap;
+
+    let mut engine: ShadowConsensus<DagConsensus, DagConsensus> =
+        ShadowConsensus::new(ConsensusConfig::default());
+    let origin = Account::create(&Hash::new("A".as_bytes()).into(), &Hash::default().into());
+    let mut tx = Transaction::new(
+        Hash::default().into(),
+        origin,
+        Hash::new("B".as_bytes()).into(),
+        Amount::new(1),
+        TransactionType::Transfer,
+        vec![],
+    );
+    tx.calculate_tx_id().unwrap();
+    let state = AccountStateChoice::new(Hash::default(), &tx);
+
+    engine.query(&state);
+    assert_eq!(engine.on_query(&state), (tx.get_tx_id(), true));
+    let mut tree = HashMap::new();
+    let status = engine.complete_dag_consensus(0, &state, &mut tree);
+    assert_eq!(status, ConsensusStatus::Reject);
+    assert_eq!(
+        engine.report(),
+        ShadowReport {
+            answers: 1,
+            divergent_answers: 0,
+            outcomes: 1,
+            divergent_outcomes: 0,
+        }
+    );
+
+    let checkpoint = Checkpoint {
+        id: Hash::default(),
+        height: 1,
+        previous: Hash::default(),
+        state_root: Hash::default(),
+        anchor: Hash::default().into(),
+        finalized: 0,
+        timestamp: Default::default(),
+    };
+    assert!(!engine.is_promotion_due(&checkpoint));
+    engine.promote_at(1);
+    assert!(engine.is_promotion_due(&checkpoint));
+
+    let promoted = engine.promote();
+    assert_eq!(promoted.on_query(&state), (tx.get_tx_id(), true));
+}
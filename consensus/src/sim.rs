@@ -0,0 +1,364 @@
+//! Deterministic, in-process simulation of a network of consensus nodes.
+//!
+//! Lets DagConsensus parameters be tried out against message delays and
+//! drops without running real nodes. Runs are reproducible from their seed.
+
+use crate::{
+    account::AccountStateChoice,
+    config::ConsensusConfig,
+    dag_consensus::DagConsensus,
+    drain::EngineState,
+    id::{NodeId, TxId},
+    network::CommonConsensusNetwork,
+    transaction::Transaction,
+    tree::{HashTreeNode, TreeNode},
+    Consensus, ConsensusStatus,
+};
+use crypto::hash::Hash;
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+/// Shape of the simulated network
+#[derive(Clone, Debug, PartialEq)]
+pub struct SimConfig {
+    pub nodes: usize,
+    pub seed: u64,
+    /// Probability of a message being lost, in [0, 1]
+    pub drop_rate: f64,
+    pub min_delay: Duration,
+    pub max_delay: Duration,
+    /// Messages delayed longer than this miss their round
+    pub timeout: Duration,
+    /// Rounds run for a transaction whose query missed the quorum
+    pub max_rounds: usize,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        Self {
+            nodes: 20,
+            seed: 0,
+            drop_rate: 0.0,
+            min_delay: Duration::from_millis(10),
+            max_delay: Duration::from_millis(50),
+            timeout: Duration::from_millis(200),
+            max_rounds: 5,
+        }
+    }
+}
+
+/// Summary of a simulation run
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SimReport {
+    pub rounds: usize,
+    pub accepted: usize,
+    pub rejected: usize,
+    /// Messages lost or delayed past the timeout
+    pub dropped_messages: usize,
+    /// Simulated time spent in rounds
+    pub elapsed: Duration,
+}
+
+/// Nodes accepted different transactions for the same account state
+#[derive(Clone, Debug, PartialEq)]
+pub struct SafetyViolation {
+    pub account_state: Hash,
+    pub accepted: HashSet<TxId>,
+}
+
+struct SimNode {
+    id: NodeId,
+    engine: DagConsensus,
+    tree: HashTreeNode,
+}
+
+/// In-process network of simulated consensus nodes
+pub struct Simulation {
+    config: SimConfig,
+    nodes: Vec<SimNode>,
+    rng: RefCell<StdRng>,
+    /// Transactions accepted per account state, by node
+    accepted: HashMap<Hash, HashMap<usize, TxId>>,
+    report: SimReport,
+}
+
+impl Simulation {
+    pub fn new(config: SimConfig, consensus: ConsensusConfig) -> Self {
+        let nodes = (0..config.nodes)
+            .map(|n| SimNode {
+                id: Hash::new(format!("sim-node-{}", n).as_bytes()).into(),
+                engine: DagConsensus::new(consensus.clone()),
+                tree: HashTreeNode::new(),
+            })
+            .collect();
+        Self {
+            rng: RefCell::new(StdRng::seed_from_u64(config.seed)),
+            config,
+            nodes,
+            accepted: HashMap::new(),
+            report: SimReport::default(),
+        }
+    }
+
+    pub fn node_ids(&self) -> Vec<NodeId> {
+        self.nodes.iter().map(|node| node.id).collect()
+    }
+
+    pub fn engine(&self, node: usize) -> &DagConsensus {
+        &self.nodes[node].engine
+    }
+
+    pub fn report(&self) -> &SimReport {
+        &self.report
+    }
+
+    /// Add a transaction to the tree of every node
+    pub fn add_vertex(&mut self, tx: &Transaction) {
+        let tx_id = tx.get_tx_id();
+        for node in &mut self.nodes {
+            let _ = node
+                .tree
+                .entry(tx_id)
+                .or_insert_with(|| (tx.parents().to_vec(), TreeNode::new(tx_id)));
+        }
+    }
+
+    /// Have `proposer` run consensus on a transaction, retrying rounds whose
+    /// query missed the quorum because of lost or late messages
+    pub fn submit(&mut self, proposer: usize, state: &AccountStateChoice) -> ConsensusStatus {
+        self.add_vertex(&state.tx);
+        let mut status = ConsensusStatus::Reject;
+        for _ in 0..self.config.max_rounds.max(1) {
+            status = self.round(proposer, state);
+            let chose = self.nodes[proposer]
+                .engine
+                .on_query(state)
+                .0
+                .eq(&state.tx.get_tx_id());
+            if matches!(status, ConsensusStatus::Accept(_)) || chose {
+                break;
+            }
+        }
+        match status {
+            ConsensusStatus::Accept(tx_id) => {
+                self.report.accepted += 1;
+                let _ = self
+                    .accepted
+                    .entry(state.account_state_id)
+                    .or_default()
+                    .insert(proposer, tx_id);
+                self.announce(proposer, state.account_state_id, tx_id);
+            }
+            _ => self.report.rejected += 1,
+        }
+        status
+    }
+
+    /// Check that no two nodes accepted conflicting transactions
+    pub fn check_safety(&self) -> Result<(), SafetyViolation> {
+        for (account_state, accepted) in &self.accepted {
+            let accepted = accepted.values().copied().collect::<HashSet<_>>();
+            if accepted.len() > 1 {
+                return Err(SafetyViolation {
+                    account_state: *account_state,
+                    accepted,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Transactions accepted by any node, e.g. to check them with a
+    /// [`SafetyAuditor`](crate::safety::SafetyAuditor)
+    pub fn accepted_transactions(&self) -> HashSet<TxId> {
+        self.accepted
+            .values()
+            .flat_map(|accepted| accepted.values().copied())
+            .collect()
+    }
+
+    /// Check that a transaction was accepted for each account state,
+    /// returning the first one left without
+    pub fn check_liveness(&self, account_states: &[Hash]) -> Result<(), Hash> {
+        match account_states
+            .iter()
+            .find(|state| !self.accepted.contains_key(state))
+        {
+            Some(state) => Err(*state),
+            None => Ok(()),
+        }
+    }
+
+    /// Query a sample of the network and resolve the round at the proposer
+    fn round(&mut self, proposer: usize, state: &AccountStateChoice) -> ConsensusStatus {
+        self.report.rounds += 1;
+        let node = &self.nodes[proposer];
+        node.engine.query(state);
+        let sample = self.get_nodes_except_one(node.engine.target_count() as u64, node.id);
+
+        let mut acceptance = 0;
+        let mut slowest = Duration::default();
+        for id in sample {
+            let delay = match self.deliver() {
+                Some(delay) => delay,
+                None => {
+                    slowest = self.config.timeout;
+                    continue;
+                }
+            };
+            slowest = slowest.max(delay);
+            let responder = &self.nodes.iter().find(|node| node.id == id).unwrap().engine;
+            responder.query(state);
+            if responder.on_query(state).0 == state.tx.get_tx_id() {
+                acceptance += 1;
+            }
+        }
+        self.report.elapsed += slowest;
+
+        let node = &mut self.nodes[proposer];
+        node.engine
+            .complete_dag_consensus(acceptance, state, &mut node.tree)
+    }
+
+    /// Let the other nodes know about an accepted transaction
+    fn announce(&mut self, proposer: usize, account_state: Hash, tx_id: TxId) {
+        let state = EngineState {
+            choices: [(account_state, tx_id)].into_iter().collect(),
+            ..Default::default()
+        };
+        for n in 0..self.nodes.len() {
+            if n != proposer && self.deliver().is_some() {
+                self.nodes[n].engine.import_state(&state);
+            }
+        }
+    }
+
+    /// Draw the delay of a message, or `None` if it is lost or late
+    fn deliver(&mut self) -> Option<Duration> {
+        let mut rng = self.rng.borrow_mut();
+        let lost = rng.gen_bool(self.config.drop_rate.clamp(0.0, 1.0));
+        let delay = if self.config.max_delay > self.config.min_delay {
+            rng.gen_range(self.config.min_delay..=self.config.max_delay)
+        } else {
+            self.config.min_delay
+        };
+        if lost || delay > self.config.timeout {
+            self.report.dropped_messages += 1;
+            return None;
+        }
+        Some(delay)
+    }
+}
+
+impl CommonConsensusNetwork for Simulation {
+    fn get_nodes_except_one(&self, k: u64, node_id: NodeId) -> Vec<NodeId> {
+        let others = self
+            .nodes
+            .iter()
+            .map(|node| node.id)
+            .filter(|id| *id != node_id)
+            .collect::<Vec<_>>();
+        others
+            .choose_multiple(&mut *self.rng.borrow_mut(), k as usize)
+            .copied()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+fn chain(length: usize) -> Vec<AccountStateChoice> {
+    use crate::{account::Account, amount::Amount, transaction::TransactionType};
+
+    let mut parent = TxId::default();
+    (0..length)
+        .map(|n| {
+            let origin = Account::create(&Hash::new(format!("{}", n).as_bytes()).into(), &parent);
+            let mut tx = Transaction::new(
+                parent,
+                origin,
+                Hash::new("destination".as_bytes()).into(),
+                Amount::new(1),
+                TransactionType::Transfer,
+                vec![],
+            );
+            tx.calculate_tx_id().unwrap();
+            let state = AccountStateChoice::new(parent.into(), &tx);
+            parent = tx.get_tx_id();
+            state
+        })
+        .collect()
+}
+
+#[test]
+fn test_simulation_is_safe_and_live() {
+    use crate::{account::Account, amount::Amount, transaction::TransactionType};
+
+    let consensus = ConsensusConfig::builder().k(5).build().unwrap();
+    let mut sim = Simulation::new(
+        SimConfig {
+            nodes: 10,
+            ..Default::default()
+        },
+        consensus,
+    );
+    let states = chain(6);
+    for (n, state) in states.iter().enumerate() {
+        let _ = sim.submit(n % 10, state);
+    }
+    // Ancestors need enough confidence before their descendants get accepted
+    let live = states[2..]
+        .iter()
+        .map(|state| state.account_state_id)
+        .collect::<Vec<_>>();
+    assert_eq!(sim.check_liveness(&live), Ok(()));
+    assert_eq!(
+        sim.check_liveness(&[states[0].account_state_id]),
+        Err(states[0].account_state_id)
+    );
+
+    // A double spend proposed elsewhere is refused by the nodes that learnt
+    // of the accepted transaction
+    let double_spend = &states[5];
+    let origin = Account::create(
+        &Hash::new("other".as_bytes()).into(),
+        &double_spend.tx.parent(),
+    );
+    let mut tx = Transaction::new(
+        double_spend.tx.parent(),
+        origin,
+        Hash::new("elsewhere".as_bytes()).into(),
+        Amount::new(1),
+        TransactionType::Transfer,
+        vec![],
+    );
+    tx.calculate_tx_id().unwrap();
+    let conflict = AccountStateChoice::new(double_spend.account_state_id, &tx);
+    assert_eq!(sim.submit(9, &conflict), ConsensusStatus::Reject);
+    assert_eq!(sim.check_safety(), Ok(()));
+}
+
+#[test]
+fn test_simulation_is_deterministic() {
+    let run = || {
+        let mut sim = Simulation::new(
+            SimConfig {
+                nodes: 10,
+                seed: 7,
+                drop_rate: 0.2,
+                max_delay: Duration::from_millis(300),
+                ..Default::default()
+            },
+            ConsensusConfig::builder().k(5).build().unwrap(),
+        );
+        for (n, state) in chain(8).iter().enumerate() {
+            let _ = sim.submit(n % 10, state);
+        }
+        assert_eq!(sim.check_safety(), Ok(()));
+        sim.report().clone()
+    };
+    let report = run();
+    assert!(report.dropped_messages > 0);
+    assert_eq!(run(), report);
+}
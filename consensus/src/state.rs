@@ -0,0 +1,1163 @@
+use crate::{
+    account::Account,
+    amount::Amount,
+    checkpoint::Checkpoint,
+    data::{DataBook, DataEntry, StoredData},
+    id::{AccountId, TxId},
+    memo::MemoIndex,
+    policy::PolicyBook,
+    reconcile::StakeTable,
+    transaction::{Transaction, TransactionType},
+    validators::ValidatorSet,
+    ConsensusError,
+};
+use crypto::{
+    cache::VerificationCache,
+    hash::Hash,
+    merkle::{MerkleProof, MerkleTree},
+    signature::PublicKey,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use storage::WriteBatch;
+
+/// Authenticated map of accounts.
+///
+/// Accounts are kept sorted by ID and committed to with a binary Merkle tree,
+/// so a single root commits to both which accounts exist and which don't.
+/// The policies, keys, data and validators are each committed to the same
+/// way, and the roots of these domains folded into the state root, see
+/// [`DomainRoots`].
+#[derive(Clone, Debug)]
+pub struct StateTrie {
+    accounts: BTreeMap<AccountId, Account>,
+    /// Merkle tree over the accounts, kept for proofs and updated in place
+    /// as they change
+    tree: MerkleTree,
+    /// IDs of the accounts, in the order of the leaves of `tree`
+    ids: Vec<AccountId>,
+    /// Roots of the current domains, recomputed as they change
+    domains: DomainRoots,
+    /// Root the domains fold into
+    root: Hash,
+    /// Spending policies the accounts opted into
+    policies: PolicyBook,
+    /// Applied transactions by memo
+    memos: MemoIndex,
+    /// Entries written by `StoreData` transactions
+    data: DataBook,
+    /// Public keys of the accounts created with one, which sign for them
+    keys: BTreeMap<AccountId, PublicKey>,
+    /// Validators registered, with the stake they bonded
+    validators: ValidatorSet,
+}
+
+impl StateTrie {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Build the state of a set of accounts, e.g. a checkpoint snapshot
+    pub fn from_accounts(accounts: impl IntoIterator<Item = Account>) -> Self {
+        let mut trie = Self {
+            accounts: accounts
+                .into_iter()
+                .map(|account| (account.id, account))
+                .collect(),
+            tree: MerkleTree::new(&[]),
+            ids: vec![],
+            domains: DomainRoots::default(),
+            root: Hash::default(),
+            policies: PolicyBook::default(),
+            memos: MemoIndex::default(),
+            data: DataBook::default(),
+            keys: BTreeMap::new(),
+            validators: ValidatorSet::new(),
+        };
+        trie.rebuild_accounts();
+        trie.domains = DomainRoots {
+            accounts: trie.tree.root(),
+            policies: trie.policies.root(),
+            keys: trie.keys_root(),
+            data: trie.data.root(),
+            validators: trie.validators.root(),
+        };
+        trie.root = trie.domains.root();
+        trie
+    }
+
+    pub fn len(&self) -> usize {
+        self.accounts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty()
+    }
+
+    pub fn get(&self, account_id: &AccountId) -> Option<&Account> {
+        self.accounts.get(account_id)
+    }
+
+    /// Iterate over accounts sorted by ID
+    pub fn accounts(&self) -> impl Iterator<Item = &Account> {
+        self.accounts.values()
+    }
+
+    /// Insert or update an account
+    pub fn insert(&mut self, account: &Account) {
+        let _ = self.accounts.insert(account.id, account.clone());
+        self.update_accounts(&[account.id]);
+    }
+
+    pub fn remove(&mut self, account_id: &AccountId) -> Option<Account> {
+        let account = self.accounts.remove(account_id)?;
+        self.rebuild_accounts();
+        self.update_root();
+        Some(account)
+    }
+
+    /// Record the public key signing for its account, e.g. one funded at
+    /// genesis. Returns the ID of the account.
+    pub fn register_key(&mut self, public_key: PublicKey) -> AccountId {
+        let account_id = AccountId::from(Hash::new(&public_key.to_bytes()));
+        let _ = self.keys.insert(account_id, public_key);
+        self.domains.keys = self.keys_root();
+        self.root = self.domains.root();
+        account_id
+    }
+
+    /// Public key signing for an account, if it was created with one
+    pub fn public_key(&self, account_id: &AccountId) -> Option<&PublicKey> {
+        self.keys.get(account_id)
+    }
+
+    /// Validators registered so far, with their stake
+    pub fn validators(&self) -> &ValidatorSet {
+        &self.validators
+    }
+
+    /// Register a validator with `stake` it didn't bond from its balance,
+    /// e.g. one registered at genesis
+    pub fn register_validator(
+        &mut self,
+        public_key: PublicKey,
+        stake: u128,
+    ) -> Result<AccountId, ConsensusError> {
+        let account_id = self.register_key(public_key);
+        self.validators.register(account_id, stake)?;
+        self.domains.validators = self.validators.root();
+        self.root = self.domains.root();
+        Ok(account_id)
+    }
+
+    /// Keys and stake of the registered validators, to check their
+    /// signatures with. Validators whose key we don't know are left out.
+    pub fn stake_table(&self) -> StakeTable {
+        let mut stake = StakeTable::new();
+        for (public_key, validator) in self
+            .keys
+            .iter()
+            .filter_map(|(id, key)| Some((key, self.validators.stake(id)?)))
+        {
+            let _ = stake.insert(*public_key, validator);
+        }
+        stake
+    }
+
+    /// Check that each transaction is signed by the key of its origin.
+    /// Signatures are verified through `cache`, those it doesn't know of
+    /// in one batch.
+    pub fn verify_signatures(
+        &self,
+        txs: &[&Transaction],
+        cache: &VerificationCache,
+    ) -> Vec<Result<(), ConsensusError>> {
+        let mut outcomes = vec![];
+        let mut items = vec![];
+        for tx in txs {
+            let item = self
+                .public_key(&tx.origin)
+                .ok_or(ConsensusError::UnknownAccountKey(tx.origin))
+                .and_then(|public_key| {
+                    let signature = tx.origin_signature().ok_or_else(|| {
+                        ConsensusError::SignatureError(format!("{} is not signed", tx.origin))
+                    })?;
+                    let payload = tx
+                        .signed_payload()
+                        .map_err(|e| ConsensusError::SerializationError(e.to_string()))?;
+                    Ok((*public_key, payload, *signature))
+                });
+            outcomes.push(item.map(|item| items.push(item)));
+        }
+        let mut verifications = cache.verify_batch(&items).into_iter();
+        outcomes
+            .into_iter()
+            .zip(txs)
+            .map(|(outcome, tx)| {
+                outcome?;
+                match verifications.next() {
+                    Some(verification) if verification.valid => Ok(()),
+                    _ => Err(ConsensusError::SignatureError(format!(
+                        "{} did not sign {}",
+                        tx.origin,
+                        tx.try_get_tx_id().unwrap_or_default()
+                    ))),
+                }
+            })
+            .collect()
+    }
+
+    /// Check that a transaction is signed by the key of its origin, see
+    /// [`verify_signatures`](StateTrie::verify_signatures)
+    pub fn verify_signature(
+        &self,
+        tx: &Transaction,
+        cache: &VerificationCache,
+    ) -> Result<(), ConsensusError> {
+        self.verify_signatures(&[tx], cache).remove(0)
+    }
+
+    pub fn policies(&self) -> &PolicyBook {
+        &self.policies
+    }
+
+    /// Entries the accounts stored
+    pub fn data(&self) -> &DataBook {
+        &self.data
+    }
+
+    /// Applied transactions whose memo starts with `prefix`
+    pub fn transactions_with_memo_prefix(&self, prefix: &str) -> Vec<TxId> {
+        self.memos.transactions_with_memo_prefix(prefix)
+    }
+
+    /// Apply a transaction to the accounts it moves funds between, creating
+    /// the destination if needed. Transactions breaking the spending policy
+    /// of their origin are refused, as are `CreateAccount` transactions not
+    /// creating a new account, see [`check_account_creation`]. Returns the
+    /// new state root. The state is left untouched if the transaction is
+    /// refused.
+    ///
+    /// [`check_account_creation`]: StateTrie::check_account_creation
+    pub fn apply(&mut self, tx: &Transaction) -> Result<Hash, ConsensusError> {
+        let delta = self.prepare(tx)?;
+        self.commit(tx, &delta)
+    }
+
+    /// Check a transaction against the state and collect the changes it
+    /// makes to accounts, without changing anything. The changes may be
+    /// persisted, see [`AccountDelta::to_batch`], before they are committed.
+    pub fn prepare(&self, tx: &Transaction) -> Result<AccountDelta, ConsensusError> {
+        let tx_id = tx
+            .try_get_tx_id()
+            .ok_or(ConsensusError::MissingTransactionId)?;
+        let origin = self
+            .get(&tx.origin)
+            .ok_or(ConsensusError::UnknownAccount(tx.origin))?;
+        tx.check_sequence(origin)?;
+        self.policies.check(tx)?;
+        if tx.tx_type == TransactionType::StoreData {
+            let _ = check_data_entry(tx)?;
+        }
+        self.check_funds(tx)?;
+        if tx.tx_type == TransactionType::CreateAccount {
+            let _ = self.check_account_creation(tx)?;
+        }
+        let destination = self
+            .get(&tx.destination)
+            .cloned()
+            .unwrap_or_else(|| Account::create(&tx.destination, &tx_id));
+        let mut delta = tx.apply(origin, &destination)?;
+        let _ = delta.debit(&origin.id, tx.fee)?;
+        match tx.tx_type {
+            TransactionType::RegisterValidator if tx.amount == Amount::ZERO => {
+                return Err(ConsensusError::ZeroStake(tx.origin));
+            }
+            // The stake bonded is released to the validator leaving
+            TransactionType::UnregisterValidator => {
+                let bonded = self
+                    .validators
+                    .stake(&tx.origin)
+                    .ok_or(ConsensusError::UnknownValidator(tx.origin))?;
+                let _ = delta.credit(&origin.id, Amount::new(bonded))?;
+            }
+            _ => {}
+        }
+        Ok(delta)
+    }
+
+    /// Commit the changes `prepare` collected for a transaction, along with
+    /// the policy, data and memo it carries. Every account changes at once,
+    /// and nothing changes if the transaction carries an invalid policy.
+    pub fn commit(
+        &mut self,
+        tx: &Transaction,
+        delta: &AccountDelta,
+    ) -> Result<Hash, ConsensusError> {
+        let tx_id = tx
+            .try_get_tx_id()
+            .ok_or(ConsensusError::MissingTransactionId)?;
+        let entry = match tx.tx_type {
+            TransactionType::StoreData => Some(check_data_entry(tx)?),
+            _ => None,
+        };
+        if tx.tx_type == TransactionType::SetSpendingPolicy {
+            self.policies.update(tx)?;
+        }
+        if self.validators.apply(tx)? {
+            self.domains.validators = self.validators.root();
+        }
+        if tx.tx_type == TransactionType::CreateAccount {
+            let public_key = PublicKey::from_bytes(&tx.payload)
+                .map_err(|e| ConsensusError::InvalidPublicKey(e.to_string()))?;
+            let _ = self.register_key(public_key);
+        }
+        if let Some(entry) = entry {
+            self.data.insert(StoredData {
+                account_id: tx.origin,
+                key: entry.key,
+                value: entry.value,
+                tx_id,
+            });
+            self.domains.data = self.data.root();
+        }
+        // What the origin spent counts against its policy
+        let policed = tx.tx_type == TransactionType::SetSpendingPolicy
+            || self.policies.get(&tx.origin).is_some();
+        self.policies.record(tx);
+        if policed {
+            self.domains.policies = self.policies.root();
+        }
+        self.memos.insert(tx);
+        for account in delta.accounts() {
+            let _ = self.accounts.insert(account.id, account.clone());
+        }
+        self.update_accounts(
+            &delta
+                .accounts()
+                .map(|account| account.id)
+                .collect::<Vec<_>>(),
+        );
+        Ok(self.root)
+    }
+
+    /// Check that the origin of a transaction holds what applying it
+    /// charges: its amount plus its fee. Lets nodes refuse transactions the
+    /// state would refuse anyway before consensus on them starts.
+    pub fn check_funds(&self, tx: &Transaction) -> Result<(), ConsensusError> {
+        let origin = self
+            .get(&tx.origin)
+            .ok_or(ConsensusError::UnknownAccount(tx.origin))?;
+        match tx.amount.checked_add(tx.fee) {
+            Some(charged) if charged <= origin.balance => Ok(()),
+            charged => Err(ConsensusError::InsufficientBalance {
+                account: origin.id,
+                balance: origin.balance,
+                amount: charged.unwrap_or(Amount::MAX),
+            }),
+        }
+    }
+
+    /// Check that a `CreateAccount` transaction creates the account of the
+    /// public key in its payload, and that the account does not exist yet.
+    /// Its amount, if any, funds the new account from the origin. Returns
+    /// the ID of the account.
+    pub fn check_account_creation(&self, tx: &Transaction) -> Result<AccountId, ConsensusError> {
+        let public_key = PublicKey::from_bytes(&tx.payload)
+            .map_err(|e| ConsensusError::InvalidPublicKey(e.to_string()))?;
+        let account_id = AccountId::from(Hash::new(&public_key.to_bytes()));
+        if account_id != tx.destination {
+            return Err(ConsensusError::AccountKeyMismatch {
+                account: tx.destination,
+                derived: account_id,
+            });
+        }
+        if self.accounts.contains_key(&account_id) {
+            return Err(ConsensusError::AccountExists(account_id));
+        }
+        Ok(account_id)
+    }
+
+    /// Root committing to the whole state: accounts, policies, keys, data
+    /// and validators
+    pub fn root(&self) -> Hash {
+        self.root
+    }
+
+    /// Roots of the domains folded into the state root
+    pub fn domains(&self) -> &DomainRoots {
+        &self.domains
+    }
+
+    /// Prove the balance of an account at a checkpoint taken from this state
+    pub fn prove_balance(
+        &self,
+        checkpoint: &Checkpoint,
+        account_id: &AccountId,
+    ) -> Option<BalanceProof> {
+        self.prove_balance_under(checkpoint, &self.domains, account_id)
+    }
+
+    /// Prove the balance of an account at a checkpoint whose domains are
+    /// `domains`, e.g. from a snapshot of its accounts only
+    pub(crate) fn prove_balance_under(
+        &self,
+        checkpoint: &Checkpoint,
+        domains: &DomainRoots,
+        account_id: &AccountId,
+    ) -> Option<BalanceProof> {
+        if checkpoint.state_root != domains.root() || domains.accounts != self.domains.accounts {
+            return None;
+        }
+        let account = self.get(account_id)?;
+        Some(BalanceProof {
+            checkpoint: checkpoint.id,
+            state_root: checkpoint.state_root,
+            domains: *domains,
+            account_id: account.id,
+            balance: account.balance,
+            last_tx_id: account.last_tx_id,
+            sequence: account.sequence,
+            proof: self.prove_membership(account_id)?.proof,
+        })
+    }
+
+    /// Update the leaves of the accounts changed, all at once, then the
+    /// state root. The tree is rebuilt instead if any of them is new, as
+    /// it shifts the leaves after it.
+    fn update_accounts(&mut self, changed: &[AccountId]) {
+        let indices = changed
+            .iter()
+            .map(|account_id| self.ids.binary_search(account_id).ok())
+            .collect::<Option<Vec<_>>>();
+        match indices {
+            Some(indices) => {
+                for (index, account_id) in indices.into_iter().zip(changed) {
+                    let digest = account_digest(&self.accounts[account_id]);
+                    let _ = self.tree.update(index, &leaf(account_id, &digest));
+                }
+            }
+            None => self.rebuild_accounts(),
+        }
+        self.update_root();
+    }
+
+    /// Rebuild the tree over every account, e.g. once one was removed
+    fn rebuild_accounts(&mut self) {
+        self.ids = self.accounts.keys().copied().collect();
+        self.tree = MerkleTree::new(&self.leaves());
+    }
+
+    /// Fold the current domains into the state root
+    fn update_root(&mut self) {
+        self.domains.accounts = self.tree.root();
+        self.root = self.domains.root();
+    }
+
+    /// Root of a Merkle tree over the public keys, sorted by account
+    fn keys_root(&self) -> Hash {
+        let leaves = self
+            .keys
+            .iter()
+            .map(|(account_id, key)| leaf(account_id, &Hash::new(&key.to_bytes())))
+            .collect::<Vec<_>>();
+        MerkleTree::new(&leaves).root()
+    }
+
+    /// Prove that an account exists, or that it does not
+    pub fn prove(&self, account_id: &AccountId) -> StateProof {
+        match self.prove_membership(account_id) {
+            Some(proof) => StateProof::Present(Box::new(proof)),
+            None => StateProof::Absent(Box::new(self.prove_absence(account_id).unwrap())),
+        }
+    }
+
+    /// Prove that an account exists with its current state
+    pub fn prove_membership(&self, account_id: &AccountId) -> Option<MembershipProof> {
+        let index = self.ids.binary_search(account_id).ok()?;
+        Some(self.proof_at(index))
+    }
+
+    /// Prove that an account does not exist.
+    /// Returns `None` if the account does exist.
+    pub fn prove_absence(&self, account_id: &AccountId) -> Option<AbsenceProof> {
+        // Index of the first account sorted after the absent one
+        let right = self.ids.binary_search(account_id).err()?;
+        Some(AbsenceProof {
+            domains: self.domains,
+            left: right.checked_sub(1).map(|index| self.proof_at(index)),
+            right: (right < self.accounts.len()).then(|| self.proof_at(right)),
+        })
+    }
+
+    fn leaves(&self) -> Vec<Hash> {
+        self.accounts
+            .values()
+            .map(|account| leaf(&account.id, &account_digest(account)))
+            .collect()
+    }
+
+    fn proof_at(&self, index: usize) -> MembershipProof {
+        let account_id = self.ids[index];
+        MembershipProof {
+            domains: self.domains,
+            account_id,
+            digest: account_digest(&self.accounts[&account_id]),
+            proof: self.tree.proof(index).unwrap(),
+        }
+    }
+}
+
+impl Default for StateTrie {
+    fn default() -> Self {
+        Self::from_accounts(vec![])
+    }
+}
+
+/// Changes a transaction makes to accounts, collected so that they are
+/// checked before any is committed
+#[derive(Clone, Debug, Default)]
+pub struct AccountDelta {
+    /// New state of every account changed, by ID
+    accounts: BTreeMap<AccountId, Account>,
+}
+
+impl AccountDelta {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.accounts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty()
+    }
+
+    pub fn get(&self, account_id: &AccountId) -> Option<&Account> {
+        self.accounts.get(account_id)
+    }
+
+    /// New state of the accounts changed, sorted by ID
+    pub fn accounts(&self) -> impl Iterator<Item = &Account> {
+        self.accounts.values()
+    }
+
+    /// Set the new state of an account, replacing the one set before
+    pub fn put(&mut self, account: Account) -> &mut Self {
+        let _ = self.accounts.insert(account.id, account);
+        self
+    }
+
+    /// Take `amount` from an account changed already, e.g. to charge a fee
+    pub fn debit(
+        &mut self,
+        account_id: &AccountId,
+        amount: Amount,
+    ) -> Result<&mut Self, ConsensusError> {
+        let _ = self
+            .accounts
+            .get_mut(account_id)
+            .ok_or(ConsensusError::UnknownAccount(*account_id))?
+            .decrease_balance(amount)?;
+        Ok(self)
+    }
+
+    /// Give `amount` to an account changed already, e.g. the stake a
+    /// validator bonded
+    pub fn credit(
+        &mut self,
+        account_id: &AccountId,
+        amount: Amount,
+    ) -> Result<&mut Self, ConsensusError> {
+        let _ = self
+            .accounts
+            .get_mut(account_id)
+            .ok_or(ConsensusError::UnknownAccount(*account_id))?
+            .increase_balance(amount)?;
+        Ok(self)
+    }
+
+    /// Storage batch writing the new state of every account under its ID,
+    /// for the changes to be persisted at once, see
+    /// [`WalStorage::write_batch`](storage::WalStorage::write_batch)
+    pub fn to_batch(&self) -> Result<WriteBatch, ConsensusError> {
+        let mut batch = WriteBatch::new();
+        for account in self.accounts() {
+            let bytes = bincode::serialize(account)
+                .map_err(|e| ConsensusError::SerializationError(e.to_string()))?;
+            let _ = batch.insert(*account.id.as_hash(), bytes);
+        }
+        Ok(batch)
+    }
+}
+
+/// Read the entry of a `StoreData` transaction, whose fee must cover its
+/// rent
+fn check_data_entry(tx: &Transaction) -> Result<DataEntry, ConsensusError> {
+    let entry = DataEntry::from_payload(&tx.payload)?;
+    if tx.fee < entry.rent() {
+        return Err(ConsensusError::InsufficientDataFee {
+            size: entry.size(),
+            rent: entry.rent(),
+            fee: tx.fee,
+        });
+    }
+    Ok(entry)
+}
+
+/// Roots of the domains of a state, each a Merkle tree over its entries
+/// sorted by key. They are the leaves of the tree whose root is the state
+/// root, so that a proof against one domain is checked against the state
+/// root along with the others.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct DomainRoots {
+    pub accounts: Hash,
+    pub policies: Hash,
+    pub keys: Hash,
+    pub data: Hash,
+    pub validators: Hash,
+}
+
+impl DomainRoots {
+    /// State root the domains fold into
+    pub fn root(&self) -> Hash {
+        MerkleTree::new(&[
+            self.accounts,
+            self.policies,
+            self.keys,
+            self.data,
+            self.validators,
+        ])
+        .root()
+    }
+}
+
+/// Proof that an account with a given state digest exists under a root
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct MembershipProof {
+    /// Domains of the state the account is proven in
+    pub domains: DomainRoots,
+    pub account_id: AccountId,
+    /// Digest of the account state, see [`account_digest`]
+    pub digest: Hash,
+    pub proof: MerkleProof,
+}
+
+impl MembershipProof {
+    /// Verify the proof against a state root
+    pub fn verify(&self, root: &Hash) -> bool {
+        self.domains.root() == *root
+            && self.proof.verify(
+                &leaf(&self.account_id, &self.digest),
+                &self.domains.accounts,
+            )
+    }
+
+    /// Verify the proof against a state root and the full account it claims
+    pub fn verify_account(&self, root: &Hash, account: &Account) -> bool {
+        self.account_id == account.id && self.digest == account_digest(account) && self.verify(root)
+    }
+}
+
+/// Proof that an account does not exist under a root.
+///
+/// Made of the accounts sorted right before and after the absent ID, which
+/// must sit next to each other in the tree. Both are missing if the state
+/// has no accounts at all.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct AbsenceProof {
+    /// Domains of the state, whose accounts root is empty if both are
+    /// missing
+    pub domains: DomainRoots,
+    pub left: Option<MembershipProof>,
+    pub right: Option<MembershipProof>,
+}
+
+impl AbsenceProof {
+    /// Verify that `account_id` is absent from the state committed to by `root`
+    pub fn verify(&self, root: &Hash, account_id: &AccountId) -> bool {
+        let left_ok = self
+            .left
+            .as_ref()
+            .is_none_or(|left| left.verify(root) && left.account_id < *account_id);
+        let right_ok = self
+            .right
+            .as_ref()
+            .is_none_or(|right| right.verify(root) && right.account_id > *account_id);
+        let adjacent = match (&self.left, &self.right) {
+            (Some(left), Some(right)) => {
+                left.proof.leaf_count == right.proof.leaf_count
+                    && left.proof.index + 1 == right.proof.index
+            }
+            (Some(left), None) => left.proof.index + 1 == left.proof.leaf_count,
+            (None, Some(right)) => right.proof.index == 0,
+            (None, None) => {
+                self.domains.root() == *root && self.domains.accounts == Hash::default()
+            }
+        };
+        left_ok && right_ok && adjacent
+    }
+}
+
+/// Membership or non-membership proof of an account
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum StateProof {
+    Present(Box<MembershipProof>),
+    Absent(Box<AbsenceProof>),
+}
+
+impl StateProof {
+    /// Verify the proof for `account_id` against a state root
+    pub fn verify(&self, root: &Hash, account_id: &AccountId) -> bool {
+        match self {
+            StateProof::Present(proof) => proof.account_id == *account_id && proof.verify(root),
+            StateProof::Absent(proof) => proof.verify(root, account_id),
+        }
+    }
+}
+
+/// Proof that an account had a balance at a checkpoint.
+///
+/// Light clients holding the checkpoint can check it without the state.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct BalanceProof {
+    pub checkpoint: Hash,
+    pub state_root: Hash,
+    /// Domains folding into the state root
+    pub domains: DomainRoots,
+    pub account_id: AccountId,
+    pub balance: Amount,
+    pub last_tx_id: TxId,
+    pub sequence: u64,
+    pub proof: MerkleProof,
+}
+
+impl BalanceProof {
+    /// Verify the proof against a checkpoint, itself checked against its ID
+    pub fn verify(&self, checkpoint: &Checkpoint) -> bool {
+        let digest = digest(
+            &self.account_id,
+            self.balance,
+            &self.last_tx_id,
+            self.sequence,
+        );
+        checkpoint.verify_id()
+            && checkpoint.id == self.checkpoint
+            && checkpoint.state_root == self.state_root
+            && self.domains.root() == self.state_root
+            && self
+                .proof
+                .verify(&leaf(&self.account_id, &digest), &self.domains.accounts)
+    }
+}
+
+/// Deterministic digest of the committed fields of an account
+pub fn account_digest(account: &Account) -> Hash {
+    digest(
+        &account.id,
+        account.balance,
+        &account.last_tx_id,
+        account.sequence,
+    )
+}
+
+fn digest(account_id: &AccountId, balance: Amount, last_tx_id: &TxId, sequence: u64) -> Hash {
+    let mut bytes = account_id.as_ref().to_vec();
+    bytes.extend_from_slice(&balance.base_units().to_le_bytes());
+    bytes.extend_from_slice(last_tx_id.as_ref());
+    bytes.extend_from_slice(&sequence.to_le_bytes());
+    Hash::new(&bytes)
+}
+
+/// Tree leaf of an account
+fn leaf(account_id: &AccountId, digest: &Hash) -> Hash {
+    let mut bytes = account_id.as_ref().to_vec();
+    bytes.extend_from_slice(&digest.0);
+    Hash::new(&bytes)
+}
+
+#[cfg(test)]
+fn trie_with(names: &[&str]) -> StateTrie {
+    let mut trie = StateTrie::new();
+    for name in names {
+        let mut account =
+            Account::create(&Hash::new(name.as_bytes()).into(), &Hash::default().into());
+        account.increase_balance(Amount::new(100)).unwrap();
+        trie.insert(&account);
+    }
+    trie
+}
+
+#[test]
+fn test_state_membership_proof() {
+    let trie = trie_with(&["A", "B", "C", "D", "E"]);
+    let root = trie.root();
+    for name in ["A", "B", "C", "D", "E"] {
+        let id = AccountId::from(Hash::new(name.as_bytes()));
+        let proof = trie.prove(&id);
+        assert!(matches!(proof, StateProof::Present(_)));
+        assert!(proof.verify(&root, &id));
+        assert!(trie
+            .prove_membership(&id)
+            .unwrap()
+            .verify_account(&root, trie.get(&id).unwrap()));
+    }
+}
+
+#[test]
+fn test_state_tree_updates_in_place() {
+    let mut trie = trie_with(&["A", "B", "C", "D", "E"]);
+    let ids =
+        ["A", "B", "C", "D", "E", "F"].map(|name| AccountId::from(Hash::new(name.as_bytes())));
+    let rebuilt = |trie: &StateTrie| StateTrie::from_accounts(trie.accounts().cloned());
+
+    // Changed accounts, new ones and removed ones leave the tree as if it was
+    // built from scratch, and proofs hold under the new root
+    let mut changed = trie.get(&ids[2]).unwrap().clone();
+    changed.increase_balance(Amount::new(1)).unwrap();
+    trie.insert(&changed);
+    assert_eq!(trie.root(), rebuilt(&trie).root());
+    trie.insert(&Account::create(&ids[5], &TxId::default()));
+    assert_eq!(trie.root(), rebuilt(&trie).root());
+    let _ = trie.remove(&ids[0]).unwrap();
+    assert_eq!(trie.root(), rebuilt(&trie).root());
+    for id in &ids {
+        assert!(trie.prove(id).verify(&trie.root(), id));
+    }
+    assert!(matches!(trie.prove(&ids[0]), StateProof::Absent(_)));
+}
+
+#[test]
+fn test_state_absence_proof() {
+    let trie = trie_with(&["A", "B", "C", "D", "E"]);
+    let root = trie.root();
+    for name in ["F", "G", "H", "I", "J", "K"] {
+        let id = AccountId::from(Hash::new(name.as_bytes()));
+        let proof = trie.prove(&id);
+        assert!(matches!(proof, StateProof::Absent(_)));
+        assert!(proof.verify(&root, &id));
+    }
+
+    let present = AccountId::from(Hash::new("A".as_bytes()));
+    assert!(trie.prove_absence(&present).is_none());
+    let absent = trie.prove(&Hash::new("F".as_bytes()).into());
+    assert!(!absent.verify(&root, &present));
+
+    let empty = StateTrie::new();
+    assert!(empty.prove(&present).verify(&empty.root(), &present));
+}
+
+#[test]
+fn test_state_proof_rejects_stale_root() {
+    let mut trie = trie_with(&["A", "B", "C"]);
+    let id = AccountId::from(Hash::new("A".as_bytes()));
+    let proof = trie.prove(&id);
+    let old_root = trie.root();
+
+    let mut account = trie.get(&id).unwrap().clone();
+    account.increase_balance(Amount::new(1)).unwrap();
+    trie.insert(&account);
+
+    assert!(proof.verify(&old_root, &id));
+    assert!(!proof.verify(&trie.root(), &id));
+}
+
+#[test]
+fn test_root_commits_to_every_domain() {
+    use crypto::signature::PrivateKey;
+
+    let mut trie = trie_with(&["A"]);
+    let id = AccountId::from(Hash::new("A".as_bytes()));
+    let accounts = trie.domains().accounts;
+    let mut roots = vec![trie.root()];
+
+    let validator = trie
+        .register_validator(PrivateKey::generate().public_key(), 10)
+        .unwrap();
+    roots.push(trie.root());
+    assert_ne!(trie.domains().keys, DomainRoots::default().keys);
+    assert_ne!(trie.domains().validators, DomainRoots::default().validators);
+    let _ = trie.register_key(PrivateKey::generate().public_key());
+    roots.push(trie.root());
+    assert_eq!(trie.validators().stake(&validator), Some(10));
+
+    // Accounts untouched, yet every change moved the state root, under which
+    // the accounts are still proven
+    assert_eq!(trie.domains().accounts, accounts);
+    assert_eq!(trie.domains().root(), trie.root());
+    roots.dedup();
+    assert_eq!(roots.len(), 3);
+    assert!(trie.prove(&id).verify(&trie.root(), &id));
+    assert!(!trie.prove(&id).verify(&roots[0], &id));
+}
+
+#[test]
+fn test_apply_updates_root() {
+    let mut trie = trie_with(&["A"]);
+    let origin = trie
+        .get(&AccountId::from(Hash::new("A".as_bytes())))
+        .unwrap()
+        .clone();
+    let root = trie.root();
+    let mut tx = Transaction::new(
+        Hash::default().into(),
+        origin.clone(),
+        Hash::new("B".as_bytes()).into(),
+        Amount::new(40),
+        TransactionType::Transfer,
+        vec![],
+    );
+    tx.calculate_tx_id().unwrap();
+
+    let new_root = trie.apply(&tx).unwrap();
+    assert_ne!(new_root, root);
+    assert_eq!(new_root, trie.root());
+    assert_eq!(trie.get(&origin.id).unwrap().balance, Amount::new(60));
+    assert_eq!(trie.get(&tx.destination).unwrap().balance, Amount::new(40));
+    assert_eq!(
+        StateTrie::from_accounts(trie.accounts().cloned()).root(),
+        new_root
+    );
+
+    // Replaying the transaction is refused and leaves the state untouched
+    assert!(matches!(
+        trie.apply(&tx),
+        Err(ConsensusError::InvalidSequence { .. })
+    ));
+    assert_eq!(trie.root(), new_root);
+}
+
+#[test]
+fn test_apply_charges_fees() {
+    let mut trie = trie_with(&["A"]);
+    let id = AccountId::from(Hash::new("A".as_bytes()));
+    let transfer = |trie: &StateTrie, amount, fee| {
+        let origin = trie.get(&id).unwrap().clone();
+        let mut tx = Transaction::new(
+            origin.last_tx_id,
+            origin,
+            Hash::new("B".as_bytes()).into(),
+            Amount::new(amount),
+            TransactionType::Transfer,
+            vec![],
+        );
+        tx.set_fee(Amount::new(fee)).calculate_tx_id().unwrap();
+        tx
+    };
+
+    // The fee leaves the origin without reaching the destination
+    let _ = trie.apply(&transfer(&trie, 40, 10)).unwrap();
+    assert_eq!(trie.get(&id).unwrap().balance, Amount::new(50));
+    assert_eq!(
+        trie.get(&Hash::new("B".as_bytes()).into()).unwrap().balance,
+        Amount::new(40)
+    );
+
+    // Affording the amount but not the fee along with it isn't enough
+    let tx = transfer(&trie, 50, 1);
+    assert!(matches!(
+        trie.check_funds(&tx),
+        Err(ConsensusError::InsufficientBalance { .. })
+    ));
+    assert!(matches!(
+        trie.apply(&tx),
+        Err(ConsensusError::InsufficientBalance { .. })
+    ));
+    assert_eq!(trie.get(&id).unwrap().balance, Amount::new(50));
+}
+
+#[test]
+fn test_apply_creates_accounts() {
+    use crypto::signature::PrivateKey;
+
+    let mut trie = trie_with(&["A"]);
+    let origin = trie
+        .get(&AccountId::from(Hash::new("A".as_bytes())))
+        .unwrap()
+        .clone();
+    let public_key = PrivateKey::generate().public_key();
+    let account_id = AccountId::from(Hash::new(&public_key.to_bytes()));
+    let create = |origin: &Account, destination, payload| {
+        let mut tx = Transaction::new(
+            Hash::default().into(),
+            origin.clone(),
+            destination,
+            Amount::new(25),
+            TransactionType::CreateAccount,
+            payload,
+        );
+        tx.calculate_tx_id().unwrap();
+        tx
+    };
+
+    let other = AccountId::from(Hash::new("B".as_bytes()));
+    assert!(matches!(
+        trie.apply(&create(&origin, other, public_key.to_bytes())),
+        Err(ConsensusError::AccountKeyMismatch { account, derived })
+            if account == other && derived == account_id
+    ));
+    assert!(matches!(
+        trie.apply(&create(&origin, account_id, vec![1, 2, 3])),
+        Err(ConsensusError::InvalidPublicKey(_))
+    ));
+    assert!(trie.get(&account_id).is_none());
+
+    let tx = create(&origin, account_id, public_key.to_bytes());
+    let _ = trie.apply(&tx).unwrap();
+    assert_eq!(trie.public_key(&account_id), Some(&public_key));
+    let account = trie.get(&account_id).unwrap();
+    assert_eq!(account.balance, Amount::new(25));
+    assert_eq!(account.last_tx_id, tx.get_tx_id());
+    assert_eq!(trie.get(&origin.id).unwrap().balance, Amount::new(75));
+
+    let origin = trie.get(&origin.id).unwrap().clone();
+    let root = trie.root();
+    assert!(matches!(
+        trie.apply(&create(&origin, account_id, public_key.to_bytes())),
+        Err(ConsensusError::AccountExists(id)) if id == account_id
+    ));
+    assert_eq!(trie.root(), root);
+}
+
+#[test]
+fn test_apply_stores_data() {
+    let mut trie = StateTrie::new();
+    let mut origin = Account::create(&Hash::new("A".as_bytes()).into(), &TxId::default());
+    origin.increase_balance(Amount::new(100_000)).unwrap();
+    trie.insert(&origin);
+    let entry = DataEntry::new("doc", Hash::new("document".as_bytes()).0.to_vec());
+    let store = |origin: &Account, fee| {
+        let mut tx = Transaction::new(
+            Hash::default().into(),
+            origin.clone(),
+            origin.id,
+            Amount::ZERO,
+            TransactionType::StoreData,
+            entry.to_payload().unwrap(),
+        );
+        tx.set_fee(fee).calculate_tx_id().unwrap();
+        tx
+    };
+
+    let cheap = store(&origin, Amount::new(1));
+    assert!(matches!(
+        trie.apply(&cheap),
+        Err(ConsensusError::InsufficientDataFee { size: 35, .. })
+    ));
+    let tx = store(&origin, entry.rent());
+    let _ = trie.apply(&tx).unwrap();
+    let data = trie.data().get(&origin.id, "doc").unwrap();
+    assert_eq!(data.value, entry.value);
+    assert_eq!(data.tx_id, tx.get_tx_id());
+    assert_eq!(trie.data().of_account(&origin.id).count(), 1);
+    assert_eq!(trie.domains().data, trie.data().root());
+    assert_ne!(trie.domains().data, DataBook::new().root());
+    assert_eq!(
+        trie.get(&origin.id).unwrap().balance,
+        Amount::new(100_000 - 35_000)
+    );
+}
+
+#[test]
+fn test_validators_bond_their_stake() {
+    let mut trie = trie_with(&["A"]);
+    let id = AccountId::from(Hash::new("A".as_bytes()));
+    let validator_tx = |trie: &StateTrie, amount, tx_type| {
+        let origin = trie.get(&id).unwrap().clone();
+        let mut tx = Transaction::new(
+            origin.last_tx_id,
+            origin,
+            id,
+            Amount::new(amount),
+            tx_type,
+            vec![],
+        );
+        tx.calculate_tx_id().unwrap();
+        tx
+    };
+
+    // The stake leaves the balance of the validator, even sent to itself
+    let register = validator_tx(&trie, 40, TransactionType::RegisterValidator);
+    let _ = trie.apply(&register).unwrap();
+    assert_eq!(trie.get(&id).unwrap().balance, Amount::new(60));
+    assert_eq!(trie.validators().stake(&id), Some(40));
+    let top_up = validator_tx(&trie, 20, TransactionType::RegisterValidator);
+    let _ = trie.apply(&top_up).unwrap();
+    assert_eq!(trie.get(&id).unwrap().balance, Amount::new(40));
+    assert_eq!(trie.validators().stake(&id), Some(60));
+    let zero = validator_tx(&trie, 0, TransactionType::RegisterValidator);
+    assert!(matches!(
+        trie.apply(&zero),
+        Err(ConsensusError::ZeroStake(_))
+    ));
+    assert!(matches!(
+        trie.apply(&validator_tx(&trie, 50, TransactionType::RegisterValidator)),
+        Err(ConsensusError::InsufficientBalance { .. })
+    ));
+
+    // And is released once it unregisters
+    let unregister = validator_tx(&trie, 0, TransactionType::UnregisterValidator);
+    let _ = trie.apply(&unregister).unwrap();
+    assert_eq!(trie.get(&id).unwrap().balance, Amount::new(100));
+    assert!(trie.validators().is_empty());
+    assert!(matches!(
+        trie.apply(&validator_tx(
+            &trie,
+            0,
+            TransactionType::UnregisterValidator
+        )),
+        Err(ConsensusError::UnknownValidator(_))
+    ));
+}
+
+#[test]
+fn test_state_delta_is_committed_at_once() {
+    use storage::{memory::MemoryStorage, Storage, WalStorage};
+
+    let mut trie = trie_with(&["A"]);
+    let origin = trie
+        .get(&AccountId::from(Hash::new("A".as_bytes())))
+        .unwrap()
+        .clone();
+    let transfer = |amount| {
+        let mut tx = Transaction::new(
+            Hash::default().into(),
+            origin.clone(),
+            Hash::new("B".as_bytes()).into(),
+            Amount::new(amount),
+            TransactionType::Transfer,
+            vec![],
+        );
+        tx.calculate_tx_id().unwrap();
+        tx
+    };
+
+    // Preparing changes nothing, whether the transaction is valid or not
+    let root = trie.root();
+    assert!(trie.prepare(&transfer(101)).is_err());
+    let tx = transfer(40);
+    let delta = trie.prepare(&tx).unwrap();
+    assert_eq!(trie.root(), root);
+    assert_eq!(delta.len(), 2);
+    assert_eq!(delta.get(&origin.id).unwrap().balance, Amount::new(60));
+    assert_eq!(delta.get(&tx.destination).unwrap().balance, Amount::new(40));
+
+    // Persisted in one batch, then committed
+    let dir = std::env::temp_dir().join(format!("dagchain-delta-{}", Hash::generate_random()));
+    let mut wal = WalStorage::open(MemoryStorage::new(None).unwrap(), &dir).unwrap();
+    wal.write_batch(delta.to_batch().unwrap()).unwrap();
+    let stored = wal.get(*tx.destination.as_hash()).unwrap();
+    assert_eq!(
+        bincode::deserialize::<Account>(&stored).unwrap().balance,
+        Amount::new(40)
+    );
+    drop(wal);
+    std::fs::remove_file(dir).unwrap();
+
+    let new_root = trie.commit(&tx, &delta).unwrap();
+    assert_ne!(new_root, root);
+    assert_eq!(trie.get(&origin.id).unwrap().balance, Amount::new(60));
+    assert_eq!(trie.get(&tx.destination).unwrap().balance, Amount::new(40));
+}
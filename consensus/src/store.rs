@@ -0,0 +1,398 @@
+//! Persistence of the consensus tree.
+//!
+//! The [`HashTreeNode`] the engine resolves rounds against lives in memory,
+//! so a restart loses the DAG. A [`DagStore`] keeps every vertex in
+//! storage along with its parents, its children and whether it was accepted.
+//! Vertices are only read from storage once they are asked for, and
+//! changes are held in memory until enough of them are pending, so that
+//! recording a round doesn't wait on the disk. Pending changes are written
+//! on [`DagStore::flush`], and when the store is dropped.
+//!
+//! Vertices used to have a single parent. Those stored back then are
+//! migrated as they are read, and written again in the current schema on
+//! the next flush.
+
+use crate::{
+    id::TxId,
+    tree::{HashTreeNode, TreeNode},
+    ConsensusError, ConsensusStatus,
+};
+use crypto::hash::Hash;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use storage::Storage;
+
+const DAG_ROOTS_KEY: &[u8] = b"dag:roots";
+/// Tag of the vertices stored with a single parent
+const DAG_LEGACY_VERTEX_TAG: &[u8] = b"dag:vertex:";
+const DAG_VERTEX_TAG: &[u8] = b"dag:vertex:v2:";
+
+/// Changes held in memory before they are written
+pub const DEFAULT_WRITE_BEHIND: usize = 256;
+
+/// Outcome of the rounds on a vertex
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub enum VertexStatus {
+    #[default]
+    Pending,
+    Accepted,
+    Rejected,
+}
+
+/// Vertex of the DAG as stored
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Vertex {
+    pub node: TreeNode,
+    /// The parent of the transaction first, then the tips it references
+    pub parents: Vec<TxId>,
+    pub children: Vec<TxId>,
+    pub status: VertexStatus,
+}
+
+/// Vertex as stored before transactions had several parents
+#[derive(Deserialize, Serialize)]
+struct LegacyVertex {
+    node: TreeNode,
+    parent: TxId,
+    children: Vec<TxId>,
+    status: VertexStatus,
+}
+
+impl From<LegacyVertex> for Vertex {
+    #[inline]
+    fn from(vertex: LegacyVertex) -> Self {
+        Self {
+            node: vertex.node,
+            parents: vec![vertex.parent],
+            children: vertex.children,
+            status: vertex.status,
+        }
+    }
+}
+
+/// DAG persisted through a [`Storage`], with a write-behind cache
+pub struct DagStore<S: Storage> {
+    storage: S,
+    /// Vertices loaded or changed so far
+    cache: HashMap<TxId, Vertex>,
+    /// Vertices changed since the latest flush
+    dirty: HashSet<TxId>,
+    /// Vertices whose parent is not in the store, to load the DAG from
+    roots: Vec<TxId>,
+    roots_dirty: bool,
+    write_behind: usize,
+}
+
+impl<S: Storage> DagStore<S> {
+    /// Initialize a DagStore, resuming from the DAG in storage
+    pub fn new(storage: S) -> Self {
+        let roots = storage
+            .get(Hash::new(DAG_ROOTS_KEY))
+            .ok()
+            .and_then(|bytes| bincode::deserialize(&bytes).ok())
+            .unwrap_or_default();
+        Self {
+            storage,
+            cache: HashMap::new(),
+            dirty: HashSet::new(),
+            roots,
+            roots_dirty: false,
+            write_behind: DEFAULT_WRITE_BEHIND,
+        }
+    }
+
+    /// Write the pending changes once `write_behind` of them are pending
+    pub fn set_write_behind(&mut self, write_behind: usize) -> &mut Self {
+        self.write_behind = write_behind.max(1);
+        self
+    }
+
+    /// Vertices the DAG is loaded from
+    pub fn roots(&self) -> &[TxId] {
+        &self.roots
+    }
+
+    /// Number of changes not written yet
+    pub fn pending(&self) -> usize {
+        self.dirty.len()
+    }
+
+    pub fn contains(&mut self, tx_id: &TxId) -> Result<bool, ConsensusError> {
+        Ok(self.get(tx_id)?.is_some())
+    }
+
+    /// Vertex of `tx_id`, read from storage if it was not loaded yet
+    pub fn get(&mut self, tx_id: &TxId) -> Result<Option<&Vertex>, ConsensusError> {
+        self.load(tx_id)?;
+        Ok(self.cache.get(tx_id))
+    }
+
+    pub fn children(&mut self, tx_id: &TxId) -> Result<Vec<TxId>, ConsensusError> {
+        Ok(self
+            .get(tx_id)?
+            .map(|vertex| vertex.children.clone())
+            .unwrap_or_default())
+    }
+
+    pub fn status(&mut self, tx_id: &TxId) -> Result<Option<VertexStatus>, ConsensusError> {
+        Ok(self.get(tx_id)?.map(|vertex| vertex.status))
+    }
+
+    /// Store the vertex of `tx_id` under `parents`, or update its node if it
+    /// is already stored
+    pub fn insert(
+        &mut self,
+        tx_id: TxId,
+        parents: Vec<TxId>,
+        node: TreeNode,
+    ) -> Result<(), ConsensusError> {
+        if self.load(&tx_id)? {
+            let vertex = self.cache.get_mut(&tx_id).unwrap();
+            if vertex.node != node {
+                vertex.node = node;
+                let _ = self.dirty.insert(tx_id);
+            }
+            return self.write_behind();
+        }
+
+        let mut linked = false;
+        for parent in &parents {
+            if *parent != tx_id && self.load(parent)? {
+                let children = &mut self.cache.get_mut(parent).unwrap().children;
+                if !children.contains(&tx_id) {
+                    children.push(tx_id);
+                }
+                let _ = self.dirty.insert(*parent);
+                linked = true;
+            }
+        }
+        if !linked {
+            self.roots.push(tx_id);
+            self.roots_dirty = true;
+        }
+        let vertex = Vertex {
+            node,
+            parents,
+            children: vec![],
+            status: VertexStatus::Pending,
+        };
+        let _ = self.cache.insert(tx_id, vertex);
+        let _ = self.dirty.insert(tx_id);
+        self.write_behind()
+    }
+
+    /// Record the outcome of a round on `tx_id`, ignoring rounds still in
+    /// progress. Returns false if the vertex is not stored.
+    pub fn record_status(
+        &mut self,
+        tx_id: &TxId,
+        status: &ConsensusStatus,
+    ) -> Result<bool, ConsensusError> {
+        let status = match status {
+            ConsensusStatus::Accept(_) | ConsensusStatus::Checkpointed(_) => VertexStatus::Accepted,
+            ConsensusStatus::Reject => VertexStatus::Rejected,
+            _ => return self.contains(tx_id),
+        };
+        if !self.load(tx_id)? {
+            return Ok(false);
+        }
+        let vertex = self.cache.get_mut(tx_id).unwrap();
+        if vertex.status != status {
+            vertex.status = status;
+            let _ = self.dirty.insert(*tx_id);
+        }
+        self.write_behind()?;
+        Ok(true)
+    }
+
+    /// Store every vertex of an in-memory tree
+    pub fn save_tree(&mut self, tree: &HashTreeNode) -> Result<(), ConsensusError> {
+        // Parents first, so that children are linked to them rather than
+        // taken for roots
+        let mut pending = tree.iter().collect::<Vec<_>>();
+        while !pending.is_empty() {
+            let (ready, waiting): (Vec<_>, Vec<_>) =
+                pending.into_iter().partition(|(_, (parents, _))| {
+                    parents
+                        .iter()
+                        .all(|parent| !tree.contains_key(parent) || self.cache.contains_key(parent))
+                });
+            if ready.is_empty() {
+                // Cycles have no parent to start from
+                for (tx_id, (parents, node)) in waiting {
+                    self.insert(*tx_id, parents.clone(), node.clone())?;
+                }
+                break;
+            }
+            for (tx_id, (parents, node)) in ready {
+                self.insert(*tx_id, parents.clone(), node.clone())?;
+            }
+            pending = waiting;
+        }
+        Ok(())
+    }
+
+    /// Rebuild the in-memory tree from the vertices reachable from the roots
+    pub fn load_tree(&mut self) -> Result<HashTreeNode, ConsensusError> {
+        let mut tree = HashTreeNode::new();
+        let mut queue = self.roots.clone();
+        while let Some(tx_id) = queue.pop() {
+            if tree.contains_key(&tx_id) {
+                continue;
+            }
+            if let Some(vertex) = self.get(&tx_id)? {
+                queue.extend(vertex.children.iter().copied());
+                let _ = tree.insert(tx_id, (vertex.parents.clone(), vertex.node.clone()));
+            }
+        }
+        Ok(tree)
+    }
+
+    /// Write the pending changes to storage
+    pub fn flush(&mut self) -> Result<(), ConsensusError> {
+        for tx_id in std::mem::take(&mut self.dirty) {
+            let vertex = &self.cache[&tx_id];
+            self.storage
+                .insert(vertex_key(&tx_id), serialize(vertex)?)?;
+        }
+        if self.roots_dirty {
+            self.storage
+                .insert(Hash::new(DAG_ROOTS_KEY), serialize(&self.roots)?)?;
+            self.roots_dirty = false;
+        }
+        self.storage.flush()?;
+        Ok(())
+    }
+
+    /// Load the vertex of `tx_id` into the cache, returning whether it is
+    /// stored
+    fn load(&mut self, tx_id: &TxId) -> Result<bool, ConsensusError> {
+        if self.cache.contains_key(tx_id) {
+            return Ok(true);
+        }
+        let vertex = match self.storage.get(vertex_key(tx_id)) {
+            Ok(bytes) => deserialize::<Vertex>(&bytes)?,
+            Err(storage::StorageError::NoneError) => {
+                match self.storage.get(tagged_key(DAG_LEGACY_VERTEX_TAG, tx_id)) {
+                    Ok(bytes) => {
+                        let _ = self.dirty.insert(*tx_id);
+                        deserialize::<LegacyVertex>(&bytes)?.into()
+                    }
+                    Err(storage::StorageError::NoneError) => return Ok(false),
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let _ = self.cache.insert(*tx_id, vertex);
+        Ok(true)
+    }
+
+    fn write_behind(&mut self) -> Result<(), ConsensusError> {
+        if self.dirty.len() >= self.write_behind {
+            self.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl<S: Storage> Drop for DagStore<S> {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            log::error!("Failed to persist the DAG: {}", e);
+        }
+    }
+}
+
+fn vertex_key(tx_id: &TxId) -> Hash {
+    tagged_key(DAG_VERTEX_TAG, tx_id)
+}
+
+fn tagged_key(tag: &[u8], tx_id: &TxId) -> Hash {
+    let mut key = tag.to_vec();
+    key.extend_from_slice(tx_id.as_hash().as_ref());
+    Hash::new(&key)
+}
+
+fn serialize<T: Serialize>(value: &T) -> Result<Vec<u8>, ConsensusError> {
+    bincode::serialize(value).map_err(|e| ConsensusError::SerializationError(e.to_string()))
+}
+
+fn deserialize<T: serde::de::DeserializeOwned>(bytes: &[u8]) -> Result<T, ConsensusError> {
+    bincode::deserialize(bytes).map_err(|e| ConsensusError::SerializationError(e.to_string()))
+}
+
+#[test]
+fn test_dag_survives_restart() {
+    use storage::memory::MemoryStorage;
+
+    let id = |name: &str| TxId::from(Hash::new(name.as_bytes()));
+    let (genesis, a, b) = (id("genesis"), id("a"), id("b"));
+    let mut tree = HashTreeNode::new();
+    let _ = tree.insert(a, (vec![genesis], TreeNode::new(a)));
+    let _ = tree.insert(b, (vec![a], TreeNode::new(b)));
+
+    let mut store = DagStore::new(MemoryStorage::new(None).unwrap());
+    let _ = store.set_write_behind(16);
+    store.save_tree(&tree).unwrap();
+    assert_eq!(store.roots(), &[a]);
+    assert_eq!(store.children(&a).unwrap(), vec![b]);
+    assert!(store
+        .record_status(&b, &ConsensusStatus::Accept(b))
+        .unwrap());
+    // Nothing reached the storage yet
+    assert_eq!(store.pending(), 2);
+    assert!(store.storage.get(vertex_key(&a)).is_err());
+
+    store.flush().unwrap();
+    assert_eq!(store.pending(), 0);
+    let storage = std::mem::replace(&mut store.storage, MemoryStorage::new(None).unwrap());
+    let mut restarted = DagStore::new(storage);
+    assert_eq!(restarted.load_tree().unwrap(), tree);
+    assert_eq!(restarted.status(&b).unwrap(), Some(VertexStatus::Accepted));
+    assert_eq!(restarted.status(&genesis).unwrap(), None);
+}
+
+#[test]
+fn test_dag_store_links_every_parent() {
+    use storage::memory::MemoryStorage;
+
+    let id = |name: &str| TxId::from(Hash::new(name.as_bytes()));
+    let (genesis, a, b, merge) = (id("genesis"), id("a"), id("b"), id("merge"));
+    let mut storage = MemoryStorage::new(None).unwrap();
+    // Stored before vertices had several parents
+    for (tx_id, parent, children) in [(a, genesis, vec![merge]), (b, genesis, vec![])] {
+        let vertex = LegacyVertex {
+            node: TreeNode::new(tx_id),
+            parent,
+            children,
+            status: VertexStatus::Accepted,
+        };
+        storage
+            .insert(
+                tagged_key(DAG_LEGACY_VERTEX_TAG, &tx_id),
+                serialize(&vertex).unwrap(),
+            )
+            .unwrap();
+    }
+    storage
+        .insert(Hash::new(DAG_ROOTS_KEY), serialize(&vec![a, b]).unwrap())
+        .unwrap();
+
+    let mut store = DagStore::new(storage);
+    assert_eq!(store.get(&a).unwrap().unwrap().parents, vec![genesis]);
+    store
+        .insert(merge, vec![a, b], TreeNode::new(merge))
+        .unwrap();
+    assert_eq!(store.children(&a).unwrap(), vec![merge]);
+    assert_eq!(store.children(&b).unwrap(), vec![merge]);
+    assert_eq!(store.roots(), &[a, b]);
+    store.flush().unwrap();
+    assert!(store.storage.get(vertex_key(&a)).is_ok());
+
+    let storage = std::mem::replace(&mut store.storage, MemoryStorage::new(None).unwrap());
+    let tree = DagStore::new(storage).load_tree().unwrap();
+    assert_eq!(tree[&merge].0, vec![a, b]);
+    assert_eq!(tree[&b].0, vec![genesis]);
+    assert_eq!(tree.len(), 3);
+}
@@ -0,0 +1,345 @@
+use crate::{
+    budget::MemoryBudget,
+    config::ConsensusConfig,
+    id::TxId,
+    time::{Clock, SystemClock},
+    ConsensusStatus,
+};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// In-flight and recently completed attempts keyed by tx id
+type Attempts = HashMap<TxId, (Arc<Attempt>, Option<Instant>)>;
+
+/// A single consensus attempt for a transaction, shared by every caller
+/// that submitted the same tx id while it was in flight
+struct Attempt {
+    /// `Some(None)` once the attempt failed without a result
+    result: Mutex<Option<Option<ConsensusStatus>>>,
+    done: Condvar,
+}
+
+impl Attempt {
+    fn new() -> Self {
+        Self {
+            result: Mutex::new(None),
+            done: Condvar::new(),
+        }
+    }
+
+    /// Publish the result and wake up every attached caller
+    fn complete(&self, status: ConsensusStatus) {
+        *self.result.lock().unwrap() = Some(Some(status));
+        self.done.notify_all();
+    }
+
+    /// Wake up every attached caller without a result
+    fn fail(&self) {
+        *self.result.lock().unwrap() = Some(None);
+        self.done.notify_all();
+    }
+
+    /// Block until the attempt has a result, or failed without one
+    fn wait(&self) -> Option<ConsensusStatus> {
+        let mut result = self.result.lock().unwrap();
+        while result.is_none() {
+            result = self.done.wait(result).unwrap();
+        }
+        result.clone().unwrap()
+    }
+}
+
+/// Publishes `Reject` and forgets the attempt if the consensus flow unwinds
+/// before completing, so attached callers are never left waiting forever
+struct AttemptGuard<'a> {
+    window: &'a SubmissionWindow,
+    tx_id: TxId,
+    attempt: &'a Attempt,
+    completed: bool,
+}
+
+impl Drop for AttemptGuard<'_> {
+    fn drop(&mut self) {
+        if !self.completed {
+            if let Ok(mut attempts) = self.window.attempts.lock() {
+                attempts.remove(&self.tx_id);
+            }
+            self.attempt.complete(ConsensusStatus::Reject);
+            self.window.resolved.notify_all();
+        }
+    }
+}
+
+/// Sender-side deduplication window for transaction submissions.
+///
+/// The first submission of a tx id fires consensus; any further submission
+/// of the same tx id, while that attempt is in flight or within `window`
+/// after it completed, is attached to it and receives the same result. An
+/// attempt that failed is forgotten at once, so that its retry fires again.
+///
+/// While draining, new tx ids are refused so in-flight attempts can resolve
+/// before the engine is shut down or swapped. So are they while the in-flight
+/// state is over its memory budget, until it frees up.
+pub struct SubmissionWindow {
+    window: Duration,
+    clock: Arc<dyn Clock>,
+    budget: MemoryBudget,
+    attempts: Mutex<Attempts>,
+    resolved: Condvar,
+    draining: AtomicBool,
+}
+
+impl SubmissionWindow {
+    /// Initialize a SubmissionWindow
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            clock: Arc::new(SystemClock),
+            budget: MemoryBudget::unlimited(),
+            attempts: Mutex::new(HashMap::new()),
+            resolved: Condvar::new(),
+            draining: AtomicBool::new(false),
+        }
+    }
+
+    /// Initialize a SubmissionWindow from consensus parameters. A window
+    /// that was never validated and isn't a duration keeps no completed
+    /// attempts.
+    pub fn from_config(config: &ConsensusConfig) -> Self {
+        Self::new(Duration::try_from_secs_f32(config.submission_window).unwrap_or_default())
+    }
+
+    /// Tell the time from `clock`, e.g. a manual one in tests
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) -> &mut Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Refuse new submissions while `budget` is exhausted
+    pub fn set_budget(&mut self, budget: MemoryBudget) -> &mut Self {
+        self.budget = budget;
+        self
+    }
+
+    /// Submit a transaction, firing consensus through `fire` only if no
+    /// attempt for the same tx id is in flight or recently completed
+    pub fn submit<F>(&self, tx_id: TxId, fire: F) -> ConsensusStatus
+    where
+        F: FnOnce() -> ConsensusStatus,
+    {
+        match self.try_submit(tx_id, || Ok::<_, Infallible>(fire())) {
+            Ok(status) => status,
+            Err(e) => match e {},
+        }
+    }
+
+    /// Submit a transaction like [`Self::submit`], through a `fire` that
+    /// may fail. A failure is returned to the caller that fired and isn't
+    /// recorded: callers attached to the attempt fire again themselves.
+    pub fn try_submit<F, E>(&self, tx_id: TxId, fire: F) -> Result<ConsensusStatus, E>
+    where
+        F: FnOnce() -> Result<ConsensusStatus, E>,
+    {
+        let attempt = loop {
+            let mut attempts = self.attempts.lock().unwrap();
+            self.prune(&mut attempts, self.clock.now());
+            if let Some((attempt, _)) = attempts.get(&tx_id) {
+                log::debug!("Attaching duplicate submission of {:?}", tx_id);
+                let attempt = attempt.clone();
+                drop(attempts);
+                match attempt.wait() {
+                    Some(status) => return Ok(status),
+                    None => continue,
+                }
+            }
+            if self.is_draining() {
+                log::debug!("Refusing submission of {:?} while draining", tx_id);
+                return Ok(ConsensusStatus::Draining);
+            }
+            if self.budget.is_exhausted() {
+                log::debug!("Refusing submission of {:?} over the memory budget", tx_id);
+                return Ok(ConsensusStatus::Overloaded);
+            }
+            let attempt = Arc::new(Attempt::new());
+            attempts.insert(tx_id, (attempt.clone(), None));
+            break attempt;
+        };
+
+        let mut guard = AttemptGuard {
+            window: self,
+            tx_id,
+            attempt: &attempt,
+            completed: false,
+        };
+        let result = fire();
+        guard.completed = true;
+        match &result {
+            Ok(status) => {
+                attempt.complete(status.clone());
+                if let Some((_, completed_at)) = self.attempts.lock().unwrap().get_mut(&tx_id) {
+                    *completed_at = Some(self.clock.now());
+                }
+            }
+            Err(_) => {
+                log::debug!("Forgetting failed submission of {:?}", tx_id);
+                let _ = self.attempts.lock().unwrap().remove(&tx_id);
+                attempt.fail();
+            }
+        }
+        self.resolved.notify_all();
+        result
+    }
+
+    /// Check if a submission of the given tx id is currently in flight
+    pub fn is_in_flight(&self, tx_id: &TxId) -> bool {
+        matches!(self.attempts.lock().unwrap().get(tx_id), Some((_, None)))
+    }
+
+    /// Stop accepting new submissions and wait up to `timeout` for in-flight
+    /// attempts to resolve. Returns the tx ids still in flight afterwards.
+    pub fn drain(&self, timeout: Duration) -> Vec<TxId> {
+        self.draining.store(true, Ordering::SeqCst);
+        let attempts = self.attempts.lock().unwrap();
+        let (attempts, _) = self
+            .resolved
+            .wait_timeout_while(attempts, timeout, |attempts| {
+                attempts
+                    .values()
+                    .any(|(_, completed_at)| completed_at.is_none())
+            })
+            .unwrap();
+        attempts
+            .iter()
+            .filter(|(_, (_, completed_at))| completed_at.is_none())
+            .map(|(tx_id, _)| *tx_id)
+            .collect()
+    }
+
+    /// Check if the window is draining
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    /// Accept new submissions again after draining
+    pub fn resume(&self) {
+        self.draining.store(false, Ordering::SeqCst);
+    }
+
+    /// Forget every completed attempt that fell out of the window
+    fn prune(&self, attempts: &mut Attempts, now: Instant) {
+        attempts.retain(|_, (_, completed_at)| match completed_at {
+            Some(at) => now.duration_since(*at) < self.window,
+            None => true,
+        });
+    }
+}
+
+impl Default for SubmissionWindow {
+    fn default() -> Self {
+        Self::from_config(&ConsensusConfig::default())
+    }
+}
+
+#[cfg(test)]
+use crypto::hash::Hash;
+
+#[test]
+fn test_duplicate_submission_reuses_result() {
+    let window = SubmissionWindow::default();
+    let tx_id = TxId::from(Hash::new("tx".as_bytes()));
+    let accepted = TxId::from(Hash::new("accepted".as_bytes()));
+
+    let first = window.submit(tx_id, || ConsensusStatus::Accept(accepted));
+    let second = window.submit(tx_id, || panic!("consensus fired twice"));
+
+    assert_eq!(first, ConsensusStatus::Accept(accepted));
+    assert_eq!(second, first);
+}
+
+#[test]
+fn test_concurrent_submission_attaches_to_in_flight_attempt() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let window = Arc::new(SubmissionWindow::default());
+    let fired = Arc::new(AtomicUsize::new(0));
+    let tx_id = TxId::from(Hash::new("tx".as_bytes()));
+
+    let handles = (0..4)
+        .map(|_| {
+            let window = window.clone();
+            let fired = fired.clone();
+            std::thread::spawn(move || {
+                window.submit(tx_id, || {
+                    fired.fetch_add(1, Ordering::SeqCst);
+                    std::thread::sleep(Duration::from_millis(50));
+                    ConsensusStatus::Accept(tx_id)
+                })
+            })
+        })
+        .collect::<Vec<_>>();
+
+    for handle in handles {
+        assert_eq!(handle.join().unwrap(), ConsensusStatus::Accept(tx_id));
+    }
+    assert_eq!(fired.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn test_submission_window_expires() {
+    use crate::time::ManualClock;
+
+    let clock = ManualClock::new();
+    let mut window = SubmissionWindow::new(Duration::from_secs(10));
+    let _ = window.set_clock(Arc::new(clock.clone()));
+    let tx_id = TxId::from(Hash::new("tx".as_bytes()));
+
+    assert_eq!(
+        window.submit(tx_id, || ConsensusStatus::Reject),
+        ConsensusStatus::Reject
+    );
+    assert!(!window.is_in_flight(&tx_id));
+    clock.advance(Duration::from_secs(9));
+    let attached = window.submit(tx_id, || panic!("consensus fired twice"));
+    assert_eq!(attached, ConsensusStatus::Reject);
+
+    clock.advance(Duration::from_secs(1));
+    let retried = window.submit(tx_id, || ConsensusStatus::Accept(tx_id));
+    assert_eq!(retried, ConsensusStatus::Accept(tx_id));
+}
+
+#[test]
+fn test_submission_refused_over_memory_budget() {
+    use crate::budget::Resource;
+
+    let budget = MemoryBudget::new(1024);
+    let mut window = SubmissionWindow::default();
+    let _ = window.set_budget(budget.clone());
+    let tx_id = TxId::from(Hash::new("tx".as_bytes()));
+
+    budget.set_used(Resource::ConflictSets, 2048);
+    let refused = window.submit(tx_id, || panic!("consensus fired over budget"));
+    assert_eq!(refused, ConsensusStatus::Overloaded);
+    assert!(!window.is_in_flight(&tx_id));
+
+    budget.set_used(Resource::ConflictSets, 512);
+    let accepted = window.submit(tx_id, || ConsensusStatus::Accept(tx_id));
+    assert_eq!(accepted, ConsensusStatus::Accept(tx_id));
+}
+
+#[test]
+fn test_failed_submission_is_retried() {
+    let window = SubmissionWindow::default();
+    let tx_id = TxId::from(Hash::new("tx".as_bytes()));
+
+    let failed = window.try_submit(tx_id, || Err("engine unavailable"));
+    assert_eq!(failed, Err("engine unavailable"));
+    assert!(!window.is_in_flight(&tx_id));
+
+    let retried = window.try_submit(tx_id, || Ok::<_, &str>(ConsensusStatus::Accept(tx_id)));
+    assert_eq!(retried, Ok(ConsensusStatus::Accept(tx_id)));
+    let attached = window.submit(tx_id, || panic!("consensus fired twice"));
+    assert_eq!(attached, ConsensusStatus::Accept(tx_id));
+}
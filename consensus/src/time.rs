@@ -0,0 +1,97 @@
+//! Sources of time.
+//!
+//! Rounds, submission windows and the timeouts of the node read the time
+//! from a [`Clock`] rather than from `Instant::now`, so that tests can run
+//! them on a [`ManualClock`] and decide when time passes, instead of
+//! sleeping and hoping the scheduler keeps up. `ManualClock` is built for
+//! this crate's tests, and for dependents enabling the `test-clock` feature.
+
+use std::fmt::Debug;
+use std::time::Instant;
+#[cfg(any(test, feature = "test-clock"))]
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// Tells the time, and waits for it
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> Instant;
+
+    /// Block until `deadline`, returning right away if it passed already
+    fn sleep_until(&self, deadline: Instant);
+}
+
+/// Wall-clock time
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep_until(&self, deadline: Instant) {
+        let now = Instant::now();
+        if deadline > now {
+            std::thread::sleep(deadline - now);
+        }
+    }
+}
+
+/// Time only passing when told to. Clones share the same time.
+#[cfg(any(test, feature = "test-clock"))]
+#[derive(Clone, Debug)]
+pub struct ManualClock {
+    now: Arc<Mutex<Instant>>,
+}
+
+#[cfg(any(test, feature = "test-clock"))]
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(any(test, feature = "test-clock"))]
+impl ManualClock {
+    /// Initialize a clock stopped at the current time
+    pub fn new() -> Self {
+        Self {
+            now: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.now.lock().unwrap() += by;
+    }
+}
+
+#[cfg(any(test, feature = "test-clock"))]
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().unwrap()
+    }
+
+    /// Sleeping moves the clock forward to `deadline`, so that nothing
+    /// actually waits
+    fn sleep_until(&self, deadline: Instant) {
+        let mut now = self.now.lock().unwrap();
+        *now = (*now).max(deadline);
+    }
+}
+
+#[test]
+fn test_manual_clock() {
+    let clock = ManualClock::new();
+    let start = clock.now();
+    let shared = clock.clone();
+    shared.advance(Duration::from_secs(3));
+    assert_eq!(clock.now(), start + Duration::from_secs(3));
+
+    clock.sleep_until(start + Duration::from_secs(10));
+    assert_eq!(shared.now(), start + Duration::from_secs(10));
+    // Deadlines in the past don't turn the clock back
+    clock.sleep_until(start);
+    assert_eq!(clock.now(), start + Duration::from_secs(10));
+}
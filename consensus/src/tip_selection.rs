@@ -0,0 +1,218 @@
+//! Choice of the parents of new transactions.
+//!
+//! A new transaction confirms the tips it builds on, so the tips chosen
+//! decide which parts of the [`Dag`] grow. A [`TipSelector`] picks them
+//! among the tips no transaction builds on yet, either uniformly or through
+//! a random walk weighted towards the heaviest part of the DAG, as in the
+//! Markov chain Monte Carlo selection of the Tangle. The walk starts a few
+//! parent links behind a random tip and steps to a child with a probability
+//! growing with the number of transactions confirming it, so that tips left
+//! behind by a lazy or conflicting branch are rarely confirmed.
+
+use crate::{config::ConsensusConfig, dag::Dag, id::TxId, transaction::MAX_REFERENCES};
+use rand::{distributions::WeightedIndex, prelude::Distribution, seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Parents referenced by default, the parent and one tip
+pub const DEFAULT_TIP_PARENTS: usize = 2;
+/// Most parents a transaction may have
+pub const MAX_TIP_PARENTS: usize = MAX_REFERENCES + 1;
+/// Bias of the walk towards heavier children. At 0, the walk picks children
+/// uniformly.
+pub const DEFAULT_WALK_ALPHA: f64 = 0.5;
+/// Parent links between the tip the walk starts behind and its start
+pub const DEFAULT_WALK_DEPTH: usize = 10;
+/// Walks per parent before giving up on distinct tips
+const WALKS_PER_PARENT: usize = 4;
+
+/// How tips are picked
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub enum TipSelection {
+    /// Uniformly among the tips
+    #[default]
+    Uniform,
+    /// Through random walks towards the heaviest tips
+    WeightedWalk,
+}
+
+impl FromStr for TipSelection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "uniform" => Ok(TipSelection::Uniform),
+            "weighted-walk" | "mcmc" => Ok(TipSelection::WeightedWalk),
+            _ => Err(format!("Unknown tip selection: {}", s)),
+        }
+    }
+}
+
+/// Picks the parents of new transactions among the tips of a [`Dag`]
+#[derive(Clone, Debug)]
+pub struct TipSelector {
+    selection: TipSelection,
+    parents: usize,
+    walk_alpha: f64,
+    walk_depth: usize,
+}
+
+impl TipSelector {
+    /// Pick up to `parents` tips, at least 1 and at most [`MAX_TIP_PARENTS`]
+    pub fn new(selection: TipSelection, parents: usize) -> Self {
+        Self {
+            selection,
+            parents: parents.clamp(1, MAX_TIP_PARENTS),
+            walk_alpha: DEFAULT_WALK_ALPHA,
+            walk_depth: DEFAULT_WALK_DEPTH,
+        }
+    }
+
+    pub fn from_config(config: &ConsensusConfig) -> Self {
+        Self::new(config.tip_selection(), config.tip_parents())
+    }
+
+    pub fn set_walk_alpha(&mut self, walk_alpha: f64) -> &mut Self {
+        self.walk_alpha = walk_alpha.max(0.0);
+        self
+    }
+
+    pub fn set_walk_depth(&mut self, walk_depth: usize) -> &mut Self {
+        self.walk_depth = walk_depth;
+        self
+    }
+
+    pub fn selection(&self) -> TipSelection {
+        self.selection
+    }
+
+    pub fn parents(&self) -> usize {
+        self.parents
+    }
+
+    /// Distinct tips for a new transaction to build on, the first to use as
+    /// its parent, see [`Transaction::set_parents`]. Fewer are returned when
+    /// the DAG has fewer tips, none when it is empty.
+    ///
+    /// [`Transaction::set_parents`]: crate::transaction::Transaction::set_parents
+    pub fn select<R: Rng + ?Sized>(&self, dag: &Dag, rng: &mut R) -> Vec<TxId> {
+        let tips = dag.tips();
+        if tips.is_empty() {
+            return vec![];
+        }
+        match self.selection {
+            TipSelection::Uniform => tips.choose_multiple(rng, self.parents).copied().collect(),
+            TipSelection::WeightedWalk => {
+                let wanted = self.parents.min(tips.len());
+                let mut selected = Vec::with_capacity(wanted);
+                for _ in 0..self.parents * WALKS_PER_PARENT {
+                    let tip = self.walk(dag, &tips, rng);
+                    if !selected.contains(&tip) {
+                        selected.push(tip);
+                    }
+                    if selected.len() == wanted {
+                        break;
+                    }
+                }
+                selected
+            }
+        }
+    }
+
+    /// Walk from behind a random tip to a tip
+    fn walk<R: Rng + ?Sized>(&self, dag: &Dag, tips: &[TxId], rng: &mut R) -> TxId {
+        let tip = tips.choose(rng).unwrap();
+        let mut current = dag
+            .ancestors(tip, self.walk_depth)
+            .last()
+            .copied()
+            .unwrap_or(*tip);
+        loop {
+            let children = dag.children_of(&current);
+            let weights = children
+                .iter()
+                .map(|child| dag.cumulative_weight(child) as f64)
+                .collect::<Vec<_>>();
+            let heaviest = match weights.iter().copied().reduce(f64::max) {
+                Some(heaviest) => heaviest,
+                None => return current,
+            };
+            // Relative to the heaviest child, so that the weights don't
+            // overflow in large DAGs
+            let weights = weights
+                .iter()
+                .map(|weight| (self.walk_alpha * (weight - heaviest)).exp());
+            current = match WeightedIndex::new(weights) {
+                Ok(index) => children[index.sample(rng)],
+                Err(_) => children[0],
+            };
+        }
+    }
+}
+
+impl Default for TipSelector {
+    fn default() -> Self {
+        Self::new(TipSelection::default(), DEFAULT_TIP_PARENTS)
+    }
+}
+
+#[test]
+fn test_tip_selection() {
+    use crate::{
+        account::Account,
+        amount::Amount,
+        transaction::{Transaction, TransactionType},
+    };
+    use crypto::hash::Hash;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    let origin = Account::create(&Hash::new("A".as_bytes()).into(), &TxId::default());
+    let mut dag = Dag::new();
+    let mut add = |parent: TxId, amount| {
+        let mut tx = Transaction::new(
+            parent,
+            origin.clone(),
+            Hash::new("B".as_bytes()).into(),
+            Amount::new(amount),
+            TransactionType::Transfer,
+            vec![],
+        );
+        tx.calculate_tx_id().unwrap();
+        dag.add_vertex(&tx).unwrap();
+        tx.get_tx_id()
+    };
+    // A lonely tip next to a busy branch
+    let root = add(TxId::default(), 0);
+    let lonely = add(root, 1);
+    let busy = add(root, 2);
+    let mut busy_tips = vec![];
+    for amount in 3..6 {
+        let parent = add(busy, amount);
+        busy_tips.push(add(parent, amount + 10));
+    }
+    let mut rng = StdRng::seed_from_u64(7);
+
+    assert!(TipSelector::default()
+        .select(&Dag::new(), &mut rng)
+        .is_empty());
+
+    let uniform = TipSelector::new(TipSelection::Uniform, 8);
+    let mut selected = uniform.select(&dag, &mut rng);
+    selected.sort();
+    let mut tips = dag.tips();
+    tips.sort();
+    assert_eq!(selected, tips);
+
+    let mut walk = TipSelector::new(TipSelection::WeightedWalk, 2);
+    let _ = walk.set_walk_alpha(10.0);
+    for _ in 0..20 {
+        let selected = walk.select(&dag, &mut rng);
+        assert_eq!(selected.len(), 2);
+        assert!(!selected.contains(&lonely));
+        assert!(selected.iter().all(|tip| busy_tips.contains(tip)));
+    }
+    assert_eq!(
+        "mcmc".parse::<TipSelection>(),
+        Ok(TipSelection::WeightedWalk)
+    );
+}
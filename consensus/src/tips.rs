@@ -0,0 +1,229 @@
+//! Tips of the DAG, the pending transactions no other transaction uses as its
+//! parent yet.
+//!
+//! A tip nobody builds on gets no confirmation from the transactions after
+//! it, and would stay pending forever. [`Tips`] tracks how long each tip went
+//! unreferenced: once it is stale it is handed back to consensus for another
+//! round, and past its max age it is dropped and its originator notified, so
+//! that they may resubmit it.
+
+use crate::{
+    account::AccountStateChoice,
+    config::ConsensusConfig,
+    id::{AccountId, TxId},
+    network::{CommonConsensusNetwork, ConsensusNetwork},
+    tree::HashTreeNode,
+    Consensus, ConsensusStatus,
+};
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// Notifications about tips leaving the DAG without being decided
+#[derive(Clone, Debug, PartialEq)]
+pub enum TipEvent {
+    /// A tip went unreferenced past its max age and was dropped, its
+    /// originator may resubmit it
+    Expired { tx_id: TxId, origin: AccountId },
+}
+
+#[derive(Clone, Debug)]
+struct Tip {
+    state: AccountStateChoice,
+    added: Instant,
+    /// Latest time the tip was added or revalidated
+    revalidated: Instant,
+}
+
+/// Pending transactions no transaction references yet
+#[derive(Clone, Debug)]
+pub struct Tips {
+    revalidation_interval: Duration,
+    max_age: Duration,
+    tips: HashMap<TxId, Tip>,
+    events: VecDeque<TipEvent>,
+}
+
+impl Tips {
+    /// Initialize tips revalidated every `revalidation_interval` they go
+    /// unreferenced, and expired after `max_age`
+    pub fn new(revalidation_interval: Duration, max_age: Duration) -> Self {
+        Self {
+            revalidation_interval,
+            max_age,
+            tips: HashMap::new(),
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Initialize tips from consensus parameters
+    pub fn from_config(config: &ConsensusConfig) -> Self {
+        Self::new(
+            Duration::from_secs_f32(config.tip_revalidation_interval),
+            Duration::from_secs_f32(config.tip_max_age),
+        )
+    }
+
+    pub fn len(&self) -> usize {
+        self.tips.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tips.is_empty()
+    }
+
+    pub fn contains(&self, tx_id: &TxId) -> bool {
+        self.tips.contains_key(tx_id)
+    }
+
+    /// Track a pending transaction as a tip. Its parent and the tips it
+    /// references are referenced from now on, so they are no longer ones.
+    pub fn insert(&mut self, state: AccountStateChoice, now: Instant) {
+        for parent in state.tx.parents() {
+            let _ = self.tips.remove(parent);
+        }
+        let tip = Tip {
+            state,
+            added: now,
+            revalidated: now,
+        };
+        let _ = self.tips.insert(tip.state.tx.get_tx_id(), tip);
+    }
+
+    /// Up to `max` tips for a new transaction to reference, the ones waiting
+    /// the longest first
+    pub fn references(&self, max: usize) -> Vec<TxId> {
+        let mut tips = self
+            .tips
+            .iter()
+            .map(|(tx_id, tip)| (tip.added, *tx_id))
+            .collect::<Vec<_>>();
+        tips.sort();
+        tips.into_iter().take(max).map(|(_, tx_id)| tx_id).collect()
+    }
+
+    /// Stop tracking a tip, e.g. once consensus decided it
+    pub fn remove(&mut self, tx_id: &TxId) -> Option<AccountStateChoice> {
+        self.tips.remove(tx_id).map(|tip| tip.state)
+    }
+
+    /// Take the events reported since the last call
+    pub fn drain_events(&mut self) -> Vec<TipEvent> {
+        self.events.drain(..).collect()
+    }
+
+    /// Tips unreferenced for a revalidation interval since they were added
+    /// or last revalidated. Tips past their max age are expired instead.
+    pub fn stale(&mut self, now: Instant) -> Vec<AccountStateChoice> {
+        let expired = self
+            .tips
+            .iter()
+            .filter(|(_, tip)| now.saturating_duration_since(tip.added) >= self.max_age)
+            .map(|(tx_id, _)| *tx_id)
+            .collect::<Vec<_>>();
+        for tx_id in expired {
+            if let Some(state) = self.remove(&tx_id) {
+                log::debug!("Tip {} expired unreferenced", tx_id);
+                self.events.push_back(TipEvent::Expired {
+                    tx_id,
+                    origin: state.tx.origin,
+                });
+            }
+        }
+
+        let interval = self.revalidation_interval;
+        self.tips
+            .values_mut()
+            .filter(|tip| now.saturating_duration_since(tip.revalidated) >= interval)
+            .map(|tip| {
+                tip.revalidated = now;
+                tip.state.clone()
+            })
+            .collect()
+    }
+
+    /// Run another consensus round on the stale tips. Tips consensus decides
+    /// are no longer tracked.
+    pub fn revalidate<C, T, N>(
+        &mut self,
+        engine: &mut C,
+        network: &mut T,
+        common_network: &mut N,
+        mut tree: Option<&mut HashTreeNode>,
+        now: Instant,
+    ) -> Vec<(TxId, ConsensusStatus)>
+    where
+        C: Consensus,
+        T: ConsensusNetwork,
+        N: CommonConsensusNetwork,
+    {
+        self.stale(now)
+            .into_iter()
+            .map(|state| {
+                let tx_id = state.tx.get_tx_id();
+                let status =
+                    engine.fire_consensus(&state, network, common_network, tree.as_deref_mut());
+                if status != ConsensusStatus::InProgress {
+                    let _ = self.remove(&tx_id);
+                }
+                (tx_id, status)
+            })
+            .collect()
+    }
+}
+
+#[test]
+fn test_stale_tips_are_revalidated_then_expired() {
+    use crate::{
+        account::Account,
+        amount::Amount,
+        transaction::{Transaction, TransactionType},
+    };
+    use crypto::hash::Hash;
+
+    let origin = Account::create(&Hash::new("A".as_bytes()).into(), &Hash::default().into());
+    let pending = |parent: TxId, amount| {
+        let mut tx = Transaction::new(
+            parent,
+            origin.clone(),
+            Hash::new("B".as_bytes()).into(),
+            amount,
+            TransactionType::Transfer,
+            vec![],
+        );
+        tx.calculate_tx_id().unwrap();
+        AccountStateChoice::new(Hash::new("state".as_bytes()), &tx)
+    };
+    let secs = Duration::from_secs;
+    let start = Instant::now();
+    let mut tips = Tips::new(secs(10), secs(25));
+
+    let parent = pending(Hash::default().into(), Amount::new(1));
+    let child = pending(parent.tx.get_tx_id(), Amount::new(2));
+    let lonely = pending(Hash::default().into(), Amount::new(3));
+    tips.insert(parent.clone(), start);
+    tips.insert(lonely.clone(), start);
+    tips.insert(child.clone(), start + secs(5));
+    assert_eq!(tips.len(), 2);
+    assert!(!tips.contains(&parent.tx.get_tx_id()));
+    // The tips waiting the longest are referenced first
+    assert_eq!(tips.references(1), vec![lonely.tx.get_tx_id()]);
+    assert_eq!(tips.references(5).len(), 2);
+
+    assert!(tips.stale(start + secs(9)).is_empty());
+    assert_eq!(tips.stale(start + secs(10)), vec![lonely.clone()]);
+    // Revalidated tips wait another interval
+    assert_eq!(tips.stale(start + secs(15)), vec![child.clone()]);
+    assert!(tips.stale(start + secs(19)).is_empty());
+
+    assert_eq!(tips.stale(start + secs(25)), vec![child.clone()]);
+    assert!(!tips.contains(&lonely.tx.get_tx_id()));
+    assert_eq!(
+        tips.drain_events(),
+        vec![TipEvent::Expired {
+            tx_id: lonely.tx.get_tx_id(),
+            origin: lonely.tx.origin,
+        }]
+    );
+    assert_eq!(tips.remove(&child.tx.get_tx_id()), Some(child));
+    assert!(tips.is_empty());
+}
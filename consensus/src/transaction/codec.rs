@@ -0,0 +1,479 @@
+//! Canonical wire format of transactions.
+//!
+//! Deriving the encoding from the struct ties it to the field list, so any
+//! change to the struct silently breaks older nodes. Transactions are encoded
+//! here instead, field by field, behind a version byte. Peers announce the
+//! versions they decode when identifying, and [`negotiate`] picks the latest
+//! both sides know. Versions are only ever added, and a node keeps decoding
+//! the ones it supported before.
+//!
+//! Version 2 is laid out as follows, integers being little-endian and every
+//! `len` a `u32` counting the items that follow it:
+//!
+//! | Field          | Encoding                                              |
+//! |----------------|-------------------------------------------------------|
+//! | version        | `u8`, 2                                               |
+//! | id             | `0`, or `1` then 32 bytes                             |
+//! | parents        | `len`, at least 1, then 32 bytes each: the parent,    |
+//! |                | then the other tips in ascending order                |
+//! | origin         | 32 bytes                                              |
+//! | destination    | 32 bytes                                              |
+//! | amount, fee    | `u128` each, in base units                            |
+//! | status         | `u8`: none 0, pending 1, accepted 2, rejected 3       |
+//! | tx type        | `u8`, see [`tx_type_tag`]                             |
+//! | payload        | `len`, then the bytes                                 |
+//! | hvc            | `len`, then 32 bytes and a `u64` per entry, in        |
+//! |                | ascending order of keys, then the `u64` logical clock |
+//! | timestamp      | `u64` seconds then `u32` nanoseconds                  |
+//! | sequence       | `u64`                                                 |
+//! | memo           | `0`, or `1` then `len` and the UTF-8 bytes            |
+//! | signatures     | `len`, then a 32-byte signer and a 96-byte signature  |
+//! |                | each, in ascending order of signers                   |
+//! | aggregate      | `0`, or `1` then 96 bytes                             |
+//! | children       | `len`, then 32 bytes each                             |
+//!
+//! Nothing may follow the last field. An encoded ID is checked against the
+//! one the other fields hash to, and the transaction refused if they differ.
+//!
+//! Version 1 has the parent apart, as 32 bytes, then the other tips as a
+//! `len` and 32 bytes each, and is otherwise laid out the same. Records in
+//! it decode to the same transaction, and are written in version 2 the next
+//! time they are encoded. IDs and signatures cover version 1 whatever the
+//! latest version, see [`Transaction::signed_payload`], so that they stay
+//! the same across versions.
+
+use super::{Transaction, TransactionStatus, TransactionType, MAX_MEMO_LEN, MAX_REFERENCES};
+use crate::{amount::Amount, clock::Hvc, id::TxId, ConsensusError};
+use crypto::{hash::Hash, signature::Signature};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Latest version of the encoding, the one written by default
+pub const VERSION: u8 = 2;
+/// Oldest version still decoded
+pub const MIN_VERSION: u8 = 1;
+/// Length of an encoded signature
+const SIGNATURE_LEN: usize = 96;
+
+/// Encode `tx` in the latest version
+pub fn encode(tx: &Transaction) -> Vec<u8> {
+    let mut out = vec![VERSION];
+    write_option(&mut out, tx.id.as_ref(), |out, id| {
+        out.extend_from_slice(&Hash::from(*id).0)
+    });
+    write_len(&mut out, tx.parents.len());
+    for parent in &tx.parents {
+        out.extend_from_slice(&Hash::from(*parent).0);
+    }
+    encode_body(tx, &mut out);
+    out
+}
+
+/// Encode `tx` in version 1, with the parent apart from the other tips
+pub(super) fn encode_v1(tx: &Transaction) -> Vec<u8> {
+    let mut out = vec![1];
+    write_option(&mut out, tx.id.as_ref(), |out, id| {
+        out.extend_from_slice(&Hash::from(*id).0)
+    });
+    out.extend_from_slice(&Hash::from(tx.parent()).0);
+    write_len(&mut out, tx.parents.len() - 1);
+    for reference in &tx.parents[1..] {
+        out.extend_from_slice(&Hash::from(*reference).0);
+    }
+    encode_body(tx, &mut out);
+    out
+}
+
+/// Encode the fields following the parents, the same in every version
+fn encode_body(tx: &Transaction, out: &mut Vec<u8>) {
+    out.extend_from_slice(&Hash::from(tx.origin).0);
+    out.extend_from_slice(&Hash::from(tx.destination).0);
+    out.extend_from_slice(&tx.amount.base_units().to_le_bytes());
+    out.extend_from_slice(&tx.fee.base_units().to_le_bytes());
+    out.push(status_tag(&tx.status));
+    out.push(tx_type_tag(tx.tx_type));
+    write_len(out, tx.payload.len());
+    out.extend_from_slice(&tx.payload);
+    let (vector, order) = tx.hvc.parts();
+    let mut vector = vector.iter().collect::<Vec<_>>();
+    vector.sort();
+    write_len(out, vector.len());
+    for (key, clock) in vector {
+        out.extend_from_slice(&key.0);
+        out.extend_from_slice(&clock.to_le_bytes());
+    }
+    out.extend_from_slice(&order.to_le_bytes());
+    out.extend_from_slice(&tx.timestamp.as_secs().to_le_bytes());
+    out.extend_from_slice(&tx.timestamp.subsec_nanos().to_le_bytes());
+    out.extend_from_slice(&tx.sequence.to_le_bytes());
+    write_option(out, tx.memo.as_ref(), |out, memo| {
+        write_len(out, memo.len());
+        out.extend_from_slice(memo.as_bytes());
+    });
+    let mut signatures = tx.signatures.iter().collect::<Vec<_>>();
+    signatures.sort_by_key(|(signer, _)| **signer);
+    write_len(out, signatures.len());
+    for (signer, signature) in signatures {
+        out.extend_from_slice(&signer.0);
+        out.extend_from_slice(&signature.as_bytes());
+    }
+    write_option(out, tx.agg_signature.as_ref(), |out, signature| {
+        out.extend_from_slice(&signature.as_bytes())
+    });
+    write_len(out, tx.children.len());
+    for child in &tx.children {
+        out.extend_from_slice(&Hash::from(*child).0);
+    }
+}
+
+/// Encode `tx` in `version`, e.g. the one negotiated with a peer
+pub fn encode_as(tx: &Transaction, version: u8) -> Result<Vec<u8>, ConsensusError> {
+    match version {
+        VERSION => Ok(encode(tx)),
+        1 => Ok(encode_v1(tx)),
+        version => Err(ConsensusError::UnsupportedEncoding(version)),
+    }
+}
+
+/// Decode a transaction in any supported version
+pub fn decode(bytes: &[u8]) -> Result<Transaction, ConsensusError> {
+    let mut reader = Reader(bytes);
+    match reader.u8()? {
+        1 => decode_v1(&mut reader),
+        2 => decode_v2(&mut reader),
+        version => Err(ConsensusError::UnsupportedEncoding(version)),
+    }
+}
+
+/// Latest version of the encoding both we and a peer decoding versions
+/// `min..=max` support
+pub fn negotiate(min: u8, max: u8) -> Option<u8> {
+    let version = max.min(VERSION);
+    (version >= min.max(MIN_VERSION)).then_some(version)
+}
+
+fn decode_v1(reader: &mut Reader) -> Result<Transaction, ConsensusError> {
+    let id = reader.option(|reader| reader.hash())?.map(Into::into);
+    let mut parents = vec![reader.hash()?.into()];
+    parents.extend(reader.list(32, |reader| reader.hash().map(TxId::from))?);
+    decode_body(reader, id, parents)
+}
+
+fn decode_v2(reader: &mut Reader) -> Result<Transaction, ConsensusError> {
+    let id = reader.option(|reader| reader.hash())?.map(Into::into);
+    let parents = reader.list(32, |reader| reader.hash().map(Into::into))?;
+    decode_body(reader, id, parents)
+}
+
+/// Decode the fields following the parents, checking that the parents
+/// are canonical: the parent, then at most [`MAX_REFERENCES`] other tips
+/// in ascending order
+fn decode_body(
+    reader: &mut Reader,
+    id: Option<TxId>,
+    parents: Vec<TxId>,
+) -> Result<Transaction, ConsensusError> {
+    let tips = match parents.split_first() {
+        Some((parent, tips)) if !tips.contains(parent) => tips,
+        Some(_) => return Err(malformed("parent is repeated among the tips")),
+        None => return Err(malformed("no parent")),
+    };
+    if tips.len() > MAX_REFERENCES {
+        return Err(ConsensusError::TooManyReferences {
+            len: tips.len(),
+            max: MAX_REFERENCES,
+        });
+    }
+    if !tips.windows(2).all(|pair| pair[0] < pair[1]) {
+        return Err(malformed("tips are not in ascending order"));
+    }
+    let origin = reader.hash()?.into();
+    let destination = reader.hash()?.into();
+    let amount = Amount::new(reader.u128()?);
+    let fee = Amount::new(reader.u128()?);
+    let status = match reader.u8()? {
+        0 => TransactionStatus::None,
+        1 => TransactionStatus::Pending,
+        2 => TransactionStatus::Accepted,
+        3 => TransactionStatus::Rejected,
+        tag => return Err(malformed(format!("unknown status {}", tag))),
+    };
+    let tx_type = tx_type_from_tag(reader.u8()?)?;
+    let payload = reader.bytes()?.to_vec();
+    let vector = reader.list(40, |reader| Ok((reader.hash()?, reader.u64()?)))?;
+    let order = reader.u64()?;
+    let entries = vector.len();
+    let vector = vector.into_iter().collect::<HashMap<_, _>>();
+    if vector.len() != entries {
+        return Err(malformed("hvc entries repeat"));
+    }
+    let hvc = Hvc::from_parts(vector, order);
+    let (secs, nanos) = (reader.u64()?, reader.u32()?);
+    if nanos >= 1_000_000_000 {
+        return Err(malformed("timestamp nanoseconds exceed a second"));
+    }
+    let timestamp = Duration::new(secs, nanos);
+    let sequence = reader.u64()?;
+    let memo = reader.option(|reader| {
+        String::from_utf8(reader.bytes()?.to_vec()).map_err(|_| malformed("memo is not UTF-8"))
+    })?;
+    if let Some(memo) = memo.as_ref().filter(|memo| memo.len() > MAX_MEMO_LEN) {
+        return Err(ConsensusError::MemoTooLong {
+            len: memo.len(),
+            max: MAX_MEMO_LEN,
+        });
+    }
+    let signatures = reader.list(32 + SIGNATURE_LEN, |reader| {
+        Ok((reader.hash()?, reader.signature()?))
+    })?;
+    let count = signatures.len();
+    let signatures = signatures.into_iter().collect::<HashMap<_, _>>();
+    if signatures.len() != count {
+        return Err(malformed("signers repeat"));
+    }
+    let agg_signature = reader.option(|reader| reader.signature())?;
+    let children = reader.list(32, |reader| reader.hash().map(Into::into))?;
+    if !reader.0.is_empty() {
+        return Err(malformed(format!("{} trailing bytes", reader.0.len())));
+    }
+    let mut tx = Transaction {
+        id,
+        parents,
+        origin,
+        destination,
+        amount,
+        fee,
+        status,
+        tx_type,
+        payload,
+        hvc,
+        timestamp,
+        sequence,
+        memo,
+        signatures,
+        agg_signature,
+        children,
+    };
+    // The ID isn't signed, so the one on the wire is only kept if the
+    // contents hash to it
+    if tx.id.is_some() {
+        let _ = tx.verify_tx_id()?;
+    }
+    Ok(tx)
+}
+
+fn status_tag(status: &TransactionStatus) -> u8 {
+    match status {
+        TransactionStatus::None => 0,
+        TransactionStatus::Pending => 1,
+        TransactionStatus::Accepted => 2,
+        TransactionStatus::Rejected => 3,
+    }
+}
+
+/// Tag of a transaction type on the wire, which stays the same whatever
+/// the order of the variants
+pub fn tx_type_tag(tx_type: TransactionType) -> u8 {
+    match tx_type {
+        TransactionType::CreateAccount => 0,
+        TransactionType::Transfer => 1,
+        TransactionType::RegisterValidator => 2,
+        TransactionType::UnregisterValidator => 3,
+        TransactionType::SetSpendingPolicy => 4,
+        TransactionType::Execute => 5,
+        TransactionType::StoreData => 6,
+    }
+}
+
+fn tx_type_from_tag(tag: u8) -> Result<TransactionType, ConsensusError> {
+    Ok(match tag {
+        0 => TransactionType::CreateAccount,
+        1 => TransactionType::Transfer,
+        2 => TransactionType::RegisterValidator,
+        3 => TransactionType::UnregisterValidator,
+        4 => TransactionType::SetSpendingPolicy,
+        5 => TransactionType::Execute,
+        6 => TransactionType::StoreData,
+        tag => return Err(malformed(format!("unknown transaction type {}", tag))),
+    })
+}
+
+fn malformed(reason: impl Into<String>) -> ConsensusError {
+    ConsensusError::SerializationError(format!("malformed transaction: {}", reason.into()))
+}
+
+fn write_len(out: &mut Vec<u8>, len: usize) {
+    out.extend_from_slice(&(len as u32).to_le_bytes());
+}
+
+fn write_option<T>(out: &mut Vec<u8>, value: Option<&T>, write: impl FnOnce(&mut Vec<u8>, &T)) {
+    match value {
+        Some(value) => {
+            out.push(1);
+            write(out, value);
+        }
+        None => out.push(0),
+    }
+}
+
+/// Bytes left to decode
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], ConsensusError> {
+        if self.0.len() < len {
+            return Err(malformed("unexpected end"));
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], ConsensusError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, ConsensusError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, ConsensusError> {
+        self.array().map(u32::from_le_bytes)
+    }
+
+    fn u64(&mut self) -> Result<u64, ConsensusError> {
+        self.array().map(u64::from_le_bytes)
+    }
+
+    fn u128(&mut self) -> Result<u128, ConsensusError> {
+        self.array().map(u128::from_le_bytes)
+    }
+
+    fn hash(&mut self) -> Result<Hash, ConsensusError> {
+        self.array().map(Hash)
+    }
+
+    fn signature(&mut self) -> Result<Signature, ConsensusError> {
+        Signature::from_bytes(self.take(SIGNATURE_LEN)?)
+            .map_err(|e| malformed(format!("invalid signature: {}", e)))
+    }
+
+    /// Length-prefixed bytes
+    fn bytes(&mut self) -> Result<&'a [u8], ConsensusError> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    fn option<T>(
+        &mut self,
+        read: impl FnOnce(&mut Self) -> Result<T, ConsensusError>,
+    ) -> Result<Option<T>, ConsensusError> {
+        match self.u8()? {
+            0 => Ok(None),
+            1 => read(self).map(Some),
+            tag => Err(malformed(format!("invalid option tag {}", tag))),
+        }
+    }
+
+    /// Length-prefixed items of at least `item_len` bytes each. The length
+    /// is checked against the bytes left before anything is allocated.
+    fn list<T>(
+        &mut self,
+        item_len: usize,
+        mut read: impl FnMut(&mut Self) -> Result<T, ConsensusError>,
+    ) -> Result<Vec<T>, ConsensusError> {
+        let len = self.u32()? as usize;
+        if len.saturating_mul(item_len) > self.0.len() {
+            return Err(malformed("unexpected end"));
+        }
+        (0..len).map(|_| read(self)).collect()
+    }
+}
+
+#[test]
+fn test_transactions_round_trip_through_the_codec() {
+    use crate::{account::Account, id::TxId};
+    use crypto::signature::PrivateKey;
+
+    let key = PrivateKey::generate();
+    let mut origin = Account::create(&Hash::new("A".as_bytes()).into(), &TxId::default());
+    origin.update_hvc();
+    let parent = TxId::from(Hash::new("parent".as_bytes()));
+    let mut tx = Transaction::new(
+        parent,
+        origin.clone(),
+        Hash::new("B".as_bytes()).into(),
+        Amount::new(42),
+        TransactionType::Execute,
+        vec![1, 2, 3],
+    );
+    tx.set_hvc(&origin)
+        .set_fee(Amount::new(2))
+        .set_parents([parent, TxId::from(Hash::new("tip".as_bytes()))])
+        .unwrap()
+        .set_memo("invoice 7")
+        .unwrap()
+        .calculate_tx_id()
+        .unwrap();
+    tx.accept_tx(&key).unwrap();
+    let _ = tx.set_children(vec![TxId::from(Hash::new("child".as_bytes()))]);
+
+    let bytes = encode(&tx);
+    assert_eq!(bytes[0], VERSION);
+    assert_eq!(decode(&bytes).unwrap(), tx);
+    assert_eq!(encode_as(&tx, VERSION).unwrap(), bytes);
+    // Serde goes through the codec too
+    let serialized = bincode::serialize(&tx).unwrap();
+    assert_eq!(
+        bincode::deserialize::<Transaction>(&serialized).unwrap(),
+        tx
+    );
+
+    // Version 1 records decode to the same transaction, under the same ID
+    let v1 = encode_as(&tx, 1).unwrap();
+    assert_eq!(v1[0], 1);
+    assert_ne!(v1[1..], bytes[1..]);
+    let migrated = decode(&v1).unwrap();
+    assert_eq!(migrated, tx);
+    assert_eq!(encode(&migrated), bytes);
+    let mut single_parent = Transaction::new(
+        parent,
+        origin.clone(),
+        Hash::new("B".as_bytes()).into(),
+        Amount::new(1),
+        TransactionType::Transfer,
+        vec![],
+    );
+    single_parent.calculate_tx_id().unwrap();
+    let migrated = decode(&encode_as(&single_parent, 1).unwrap()).unwrap();
+    assert_eq!(migrated.parents(), [parent]);
+    assert_eq!(migrated.get_tx_id(), single_parent.get_tx_id());
+    // A transaction has a parent, not repeated among its tips
+    let mut orphan = encode(&single_parent);
+    orphan[34..38].copy_from_slice(&0u32.to_le_bytes());
+    orphan.drain(38..70);
+    assert!(decode(&orphan).is_err());
+
+    assert!(matches!(
+        decode(&[VERSION + 1]),
+        Err(ConsensusError::UnsupportedEncoding(3))
+    ));
+    assert!(decode(&bytes[..bytes.len() - 1]).is_err());
+    let mut trailing = bytes.clone();
+    trailing.push(0);
+    assert!(decode(&trailing).is_err());
+    // A list claiming more items than the bytes left is refused up front
+    let mut truncated = bytes[..34].to_vec();
+    truncated.extend_from_slice(&u32::MAX.to_le_bytes());
+    assert!(decode(&truncated).is_err());
+    // So is a transaction claiming another ID than the one of its contents
+    let mut renamed = tx.clone();
+    renamed.set_tx_id(TxId::from(Hash::new("other".as_bytes())));
+    assert!(matches!(
+        decode(&encode(&renamed)),
+        Err(ConsensusError::TxIdMismatch { .. })
+    ));
+
+    assert_eq!(negotiate(1, 5), Some(VERSION));
+    assert_eq!(negotiate(VERSION + 1, VERSION + 2), None);
+}
@@ -0,0 +1,505 @@
+use crate::{
+    account::Account,
+    amount::Amount,
+    clock::Hvc,
+    error::StateError,
+    id::{AccountId, TxId},
+    state::AccountDelta,
+    ConsensusError,
+};
+use crypto::{
+    error::CryptoError,
+    hash::Hash,
+    signature::{PrivateKey, PublicKey, Signature},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+pub mod codec;
+
+/// Longest memo a transaction may carry, in bytes
+pub const MAX_MEMO_LEN: usize = 256;
+/// Most tips a transaction may reference besides its parent
+pub const MAX_REFERENCES: usize = 7;
+
+/// Basic representation of a transaction.
+///
+/// Serialized as its [`codec`] encoding, so that the bytes nodes exchange,
+/// store and hash don't depend on the layout of the struct.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Transaction {
+    id: Option<TxId>,
+    /// The parent first, the account state the transaction spends, then
+    /// the other tips it confirms in ascending order. Covered by the tx id.
+    parents: Vec<TxId>,
+    pub origin: AccountId,
+    pub destination: AccountId,
+    pub amount: Amount,
+    pub fee: Amount,
+    pub status: TransactionStatus,
+    pub tx_type: TransactionType,
+    pub payload: Vec<u8>,
+    pub hvc: Hvc,
+    pub timestamp: Duration,
+    /// Must follow the sequence of the latest transaction sent from the origin
+    pub sequence: u64,
+    /// Label set by the sender, e.g. a payment reference, covered by the
+    /// signatures
+    memo: Option<String>,
+    signatures: HashMap<Hash, Signature>,
+    agg_signature: Option<Signature>,
+    children: Vec<TxId>,
+}
+
+impl Transaction {
+    pub fn new(
+        parent: TxId,
+        origin: Account,
+        destination: AccountId,
+        amount: Amount,
+        tx_type: TransactionType,
+        payload: Vec<u8>,
+    ) -> Self {
+        Self {
+            id: None,
+            parents: vec![parent],
+            sequence: origin.next_sequence(),
+            origin: origin.id,
+            destination,
+            amount,
+            fee: Amount::ZERO,
+            status: TransactionStatus::Pending,
+            tx_type,
+            payload,
+            hvc: Hvc::new(),
+            timestamp: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap(),
+            memo: None,
+            signatures: HashMap::new(),
+            agg_signature: None,
+            children: vec![],
+        }
+    }
+
+    /// Same as the Transation::new()
+    /// But sets the timestamp to `0` so all nodes can create the genesis transaction
+    pub fn genesis(
+        parent: TxId,
+        origin: Account,
+        destination: AccountId,
+        amount: Amount,
+        tx_type: TransactionType,
+        payload: Vec<u8>,
+    ) -> Self {
+        let mut tx = Self::new(parent, origin, destination, amount, tx_type, payload);
+        tx.timestamp = Duration::from_secs(0);
+        tx
+    }
+
+    /// Changes the transaction makes to the accounts it moves funds
+    /// between. Refused if the origin does not hold the amount or the
+    /// destination would overflow. The amount of a `RegisterValidator`
+    /// transaction is bonded as stake instead, credited to nobody.
+    pub fn apply(
+        &self,
+        origin: &Account,
+        destination: &Account,
+    ) -> Result<AccountDelta, StateError> {
+        let tx_id = self.id.unwrap();
+        let mut debited = origin.clone();
+        let _ = debited
+            .update_last_tx(&tx_id)
+            .update_sequence(self.sequence)
+            .update_hvc()
+            .decrease_balance(self.amount)?;
+        let mut delta = AccountDelta::new();
+        if self.tx_type == TransactionType::RegisterValidator {
+            // Released once the origin unregisters, see `StateTrie`
+        } else if destination.id == origin.id {
+            // Sent to itself, the origin keeps the amount
+            let _ = debited.increase_balance(self.amount)?;
+        } else {
+            let mut credited = destination.clone();
+            let _ = credited
+                .update_last_tx(&tx_id)
+                .update_hvc()
+                .increase_balance(self.amount)?;
+            let _ = delta.put(credited);
+        }
+        let _ = delta.put(debited);
+        Ok(delta)
+    }
+
+    /// Get restricted version of transaction for:
+    /// * Hashing
+    /// * Verifying
+    /// * Signing
+    ///
+    /// The status and children are left out, as nodes set them once the
+    /// transaction is signed.
+    fn restricted_tx(&self) -> Self {
+        let mut tx = self.clone();
+        tx.id = None;
+        tx.signatures = HashMap::new();
+        tx.agg_signature = None;
+        tx.status = TransactionStatus::Pending;
+        tx.children = vec![];
+        tx
+    }
+
+    /// Bytes the signatures of the transaction sign. Like the ID, they
+    /// cover the restricted transaction in version 1 of the [`codec`],
+    /// whatever the latest version, so that neither changes along with the
+    /// encoding.
+    pub fn signed_payload(&self) -> Result<Vec<u8>, CryptoError> {
+        bincode::serialize(&codec::encode_v1(&self.restricted_tx()))
+            .map_err(|e| CryptoError::SerializationError(e.to_string()))
+    }
+
+    /// Signature of the origin, the account of the public key signing it
+    pub fn origin_signature(&self) -> Option<&Signature> {
+        self.signatures.get(self.origin.as_hash())
+    }
+
+    /// Calculate ID of transaction
+    pub fn calculate_tx_id(&mut self) -> Result<&mut Self, CryptoError> {
+        let tx = codec::encode_v1(&self.restricted_tx());
+        self.id = Some(Hash::serialize(&tx)?.into());
+        Ok(self)
+    }
+
+    /// Calculate the ID of a transaction received from a peer or client,
+    /// refusing it if it carries another one. Signatures don't cover the
+    /// ID, so the one supplied can't be trusted.
+    pub fn verify_tx_id(&mut self) -> Result<TxId, ConsensusError> {
+        let claimed = self.id;
+        let actual = self
+            .calculate_tx_id()
+            .map_err(|e| ConsensusError::SerializationError(e.to_string()))?
+            .get_tx_id();
+        match claimed {
+            Some(claimed) if claimed != actual => {
+                self.id = Some(claimed);
+                Err(ConsensusError::TxIdMismatch { claimed, actual })
+            }
+            _ => Ok(actual),
+        }
+    }
+
+    /// Sign transaction
+    pub fn sign_tx(&self, private_key: &PrivateKey) -> Result<Signature, CryptoError> {
+        Ok(Signature::sign(private_key, self.signed_payload()?))
+    }
+
+    /// Add tx signature to list of signatures
+    pub fn set_signature(&mut self, pubkey: &PublicKey, sig: &Signature) -> &mut Self {
+        self.signatures.insert(Hash::new(&pubkey.to_bytes()), *sig);
+        self
+    }
+
+    /// Sign tx and add signature to list of signatures
+    pub fn sign_and_set_signature(
+        &mut self,
+        private_key: &PrivateKey,
+    ) -> Result<&mut Self, CryptoError> {
+        let sig = self.sign_tx(private_key)?;
+        Ok(self.set_signature(&private_key.public_key(), &sig))
+    }
+
+    /// Aggregate tx signatures
+    pub fn aggregate_signatures(&mut self) -> Result<&mut Self, CryptoError> {
+        let mut sigs: Vec<Signature> = vec![];
+        for (_, sig) in self.signatures.iter() {
+            sigs.push(*sig);
+        }
+        self.agg_signature =
+            Some(Signature::aggregate(&sigs).map_err(CryptoError::BlsSignatureError)?);
+        Ok(self)
+    }
+
+    /// Accept transaction
+    pub fn accept_tx(&mut self, private_key: &PrivateKey) -> Result<&mut Self, CryptoError> {
+        self.set_tx_status(TransactionStatus::Accepted)
+            .sign_and_set_signature(private_key)?
+            .aggregate_signatures()?;
+        Ok(self)
+    }
+
+    /// Set transaction status
+    pub fn set_tx_status(&mut self, status: TransactionStatus) -> &mut Self {
+        self.status = status;
+        self
+    }
+
+    /// Retrieve all signatures
+    pub fn get_sigs(&self) -> HashMap<Hash, Signature> {
+        self.signatures.clone()
+    }
+
+    /// Retrieve aggregated signature
+    pub fn get_aggregate_sig(&self) -> Option<Signature> {
+        self.agg_signature
+    }
+
+    /// Verify the signature of a transaction
+    pub fn verify_tx_sig(&mut self, pubkey: &PublicKey) -> Result<bool, CryptoError> {
+        let sig = self.signatures.get(&Hash::new(&pubkey.to_bytes()));
+        if sig.is_none() {
+            return Ok(false);
+        }
+        Ok(sig.unwrap().verify(pubkey, self.signed_payload()?))
+    }
+
+    /// Verify the signatures of many transactions in one batch, the
+    /// signature of each one by the public key it is paired with. Returns
+    /// false if one of them is missing or invalid, see
+    /// [`Signature::batch_verify`].
+    pub fn verify_batch(signed: &[(&Transaction, PublicKey)]) -> Result<bool, CryptoError> {
+        let mut payloads = Vec::with_capacity(signed.len());
+        let mut signatures = Vec::with_capacity(signed.len());
+        for (tx, pubkey) in signed {
+            match tx.signatures.get(&Hash::new(&pubkey.to_bytes())) {
+                Some(sig) => signatures.push((*pubkey, *sig)),
+                None => return Ok(false),
+            }
+            payloads.push(tx.signed_payload()?);
+        }
+        let items = signatures
+            .into_iter()
+            .zip(&payloads)
+            .map(|((pubkey, sig), payload)| (pubkey, payload.as_slice(), sig))
+            .collect::<Vec<_>>();
+        Ok(Signature::batch_verify(&items))
+    }
+
+    /// Verify the aggregated signature against the public keys of all the
+    /// signers. Returns false if the signatures were not aggregated.
+    pub fn verify_aggregate_sig(&self, pubkeys: &[PublicKey]) -> Result<bool, CryptoError> {
+        let agg_signature = match self.agg_signature {
+            Some(agg_signature) => agg_signature,
+            None => return Ok(false),
+        };
+        Ok(agg_signature.verify_aggregate(pubkeys, self.signed_payload()?))
+    }
+
+    pub fn get_tx_id(&self) -> TxId {
+        self.id.unwrap()
+    }
+
+    /// Get ID of transaction, if it was already calculated
+    pub fn try_get_tx_id(&self) -> Option<TxId> {
+        self.id
+    }
+
+    pub fn set_tx_id(&mut self, id: TxId) {
+        self.id = Some(id);
+    }
+
+    /// Set the fee paid for prioritizing the transaction
+    pub fn set_fee(&mut self, fee: Amount) -> &mut Self {
+        self.fee = fee;
+        self
+    }
+
+    /// Label the transaction, e.g. with a payment reference. Has to be set
+    /// before signing.
+    pub fn set_memo(&mut self, memo: impl Into<String>) -> Result<&mut Self, ConsensusError> {
+        let memo = memo.into();
+        if memo.len() > MAX_MEMO_LEN {
+            return Err(ConsensusError::MemoTooLong {
+                len: memo.len(),
+                max: MAX_MEMO_LEN,
+            });
+        }
+        self.memo = Some(memo);
+        Ok(self)
+    }
+
+    pub fn memo(&self) -> Option<&str> {
+        self.memo.as_deref()
+    }
+
+    /// Build on `parents`, e.g. as a
+    /// [`TipSelector`](crate::tip_selection::TipSelector) picked them: the
+    /// first becomes the parent, and the others are sorted after it with
+    /// repeats dropped. An empty list keeps the parent. Has to be set before
+    /// the tx id is calculated.
+    pub fn set_parents(
+        &mut self,
+        parents: impl IntoIterator<Item = TxId>,
+    ) -> Result<&mut Self, ConsensusError> {
+        let mut parents = parents.into_iter();
+        let parent = parents.next().unwrap_or_else(|| self.parent());
+        let mut tips = parents.filter(|tip| *tip != parent).collect::<Vec<_>>();
+        tips.sort();
+        tips.dedup();
+        if tips.len() > MAX_REFERENCES {
+            return Err(ConsensusError::TooManyReferences {
+                len: tips.len(),
+                max: MAX_REFERENCES,
+            });
+        }
+        self.parents = std::iter::once(parent).chain(tips).collect();
+        Ok(self)
+    }
+
+    /// Parent whose account state the transaction spends
+    pub fn parent(&self) -> TxId {
+        self.parents[0]
+    }
+
+    /// The parent, then the other tips the transaction confirms
+    pub fn parents(&self) -> &[TxId] {
+        &self.parents
+    }
+
+    pub fn set_hvc(&mut self, source: &Account) -> &mut Self {
+        self.hvc = source.hvc.clone();
+        self
+    }
+
+    pub fn set_children(&mut self, children: Vec<TxId>) -> &mut Self {
+        self.children = children;
+        self
+    }
+
+    pub fn get_children(&self) -> Vec<TxId> {
+        self.children.clone()
+    }
+
+    pub fn check_transfer_availability(&self, source: &Account) -> bool {
+        source.balance >= self.amount
+    }
+
+    /// Check that the transaction carries the next sequence of its origin
+    pub fn check_sequence(&self, origin: &Account) -> Result<(), ConsensusError> {
+        if self.origin != origin.id || self.sequence != origin.next_sequence() {
+            return Err(ConsensusError::InvalidSequence {
+                origin: origin.id,
+                expected: origin.next_sequence(),
+                got: self.sequence,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Serialize for Transaction {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_bytes(&codec::encode(self))
+    }
+}
+
+impl<'de> Deserialize<'de> for Transaction {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_bytes(TransactionVisitor)
+    }
+}
+
+struct TransactionVisitor;
+
+impl<'de> serde::de::Visitor<'de> for TransactionVisitor {
+    type Value = Transaction;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("an encoded transaction")
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        codec::decode(v).map_err(E::custom)
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: serde::de::SeqAccess<'de>,
+    {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(4096));
+        while let Some(byte) = seq.next_element::<u8>()? {
+            bytes.push(byte);
+        }
+        self.visit_bytes(&bytes)
+    }
+}
+
+/// Transaction type
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize)]
+pub enum TransactionType {
+    CreateAccount,
+    Transfer,
+    /// Register the origin as a validator staking the amount, see
+    /// [`ValidatorSet`](crate::validators::ValidatorSet)
+    RegisterValidator,
+    /// Remove the origin from the validators
+    UnregisterValidator,
+    /// Set the spending policy of the origin, see
+    /// [`PolicyUpdate`](crate::policy::PolicyUpdate)
+    SetSpendingPolicy,
+    /// Run the payload through the executor of the application, see
+    /// [`Executor`](crate::executor::Executor)
+    Execute,
+    /// Write the [`DataEntry`](crate::data::DataEntry) in the payload into
+    /// the state of the origin, paying its rent with the fee
+    StoreData,
+}
+
+/// Transaction status
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum TransactionStatus {
+    None,
+    Pending,
+    Accepted,
+    Rejected,
+}
+
+#[test]
+fn test_parents_are_canonical_and_bounded() {
+    use crate::amount::Amount;
+
+    let origin = Account::create(&Hash::new("A".as_bytes()).into(), &TxId::default());
+    let tip = |n: u8| TxId::from(Hash::new(&[n]));
+    let mut tx = Transaction::new(
+        tip(0),
+        origin,
+        Hash::new("B".as_bytes()).into(),
+        Amount::new(1),
+        TransactionType::Transfer,
+        vec![],
+    );
+    let mut single_parent = tx.clone();
+    single_parent.calculate_tx_id().unwrap();
+
+    tx.set_parents([tip(0), tip(2), tip(0), tip(1), tip(2)])
+        .unwrap();
+    let mut sorted = [tip(1), tip(2)];
+    sorted.sort();
+    assert_eq!(tx.parents().len(), 3);
+    assert_eq!(tx.parent(), tip(0));
+    assert_eq!(tx.parents()[1..], sorted);
+
+    // Covered by the id, whatever order the tips were given in
+    let mut reordered = tx.clone();
+    reordered.set_parents([tip(0), tip(1), tip(2)]).unwrap();
+    tx.calculate_tx_id().unwrap();
+    reordered.calculate_tx_id().unwrap();
+    assert_eq!(tx.get_tx_id(), reordered.get_tx_id());
+    assert_ne!(tx.get_tx_id(), single_parent.get_tx_id());
+    let mut rebuilt = single_parent.clone();
+    rebuilt.set_parents([]).unwrap();
+    assert_eq!(rebuilt.parents(), [tip(0)]);
+
+    assert!(matches!(
+        tx.set_parents((0..=8).map(tip)),
+        Err(ConsensusError::TooManyReferences { len: 8, max: 7 })
+    ));
+}
@@ -1,22 +1,25 @@
-use crypto::hash::Hash;
+use crate::id::TxId;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
 /// Hash Tree Node
-/// Basic representation of a consensus tree structure
-pub type HashTreeNode = HashMap<Hash, (Hash, TreeNode)>;
+/// Basic representation of a consensus tree structure: the node of every
+/// transaction, along with its parents, the parent first and then the tips
+/// it references
+pub type HashTreeNode = HashMap<TxId, (Vec<TxId>, TreeNode)>;
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct TreeNode {
-    pub node: Hash,
+    pub node: TxId,
     pub confidence: u64,
-    pub preferred: Hash,
-    pub last: Hash,
+    pub preferred: TxId,
+    pub last: TxId,
     pub count: u64,
 }
 
 impl TreeNode {
     /// Initialize a TreeNode
-    pub fn new(node: Hash) -> Self {
+    pub fn new(node: TxId) -> Self {
         Self {
             node,
             confidence: 0,
@@ -27,13 +30,13 @@ impl TreeNode {
     }
 
     /// Set preferred value
-    pub fn set_preferred(&mut self, p: Hash) -> &mut Self {
+    pub fn set_preferred(&mut self, p: TxId) -> &mut Self {
         self.preferred = p;
         self
     }
 
     /// Set last selected value
-    pub fn set_last(&mut self, l: Hash) -> &mut Self {
+    pub fn set_last(&mut self, l: TxId) -> &mut Self {
         self.last = l;
         self
     }
@@ -0,0 +1,188 @@
+//! Registered validators and stake-weighted sampling.
+//!
+//! Validators join and leave through `RegisterValidator` and
+//! `UnregisterValidator` transactions, the state bonding the amount of the
+//! first from their balance until the second releases it. Queries sample
+//! them with a probability proportional to their stake, so that influence
+//! over consensus has to be paid for.
+
+use crate::{
+    amount::Amount,
+    id::{AccountId, NodeId},
+    network::CommonConsensusNetwork,
+    transaction::{Transaction, TransactionType},
+    ConsensusError,
+};
+use crypto::{hash::Hash, merkle::MerkleTree};
+use rand::Rng;
+use std::collections::BTreeMap;
+
+/// Validators registered with their stake
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ValidatorSet {
+    /// Sorted so that seeded samples are reproducible
+    stakes: BTreeMap<AccountId, u128>,
+}
+
+impl ValidatorSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.stakes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stakes.is_empty()
+    }
+
+    pub fn contains(&self, validator: &AccountId) -> bool {
+        self.stakes.contains_key(validator)
+    }
+
+    pub fn stake(&self, validator: &AccountId) -> Option<u128> {
+        self.stakes.get(validator).copied()
+    }
+
+    pub fn total_stake(&self) -> u128 {
+        self.stakes.values().sum()
+    }
+
+    /// Root of a Merkle tree over the validators and their stake, sorted by
+    /// ID
+    pub fn root(&self) -> Hash {
+        let leaves = self
+            .stakes
+            .iter()
+            .map(|(validator, stake)| {
+                let mut bytes = validator.as_ref().to_vec();
+                bytes.extend_from_slice(&stake.to_le_bytes());
+                Hash::new(&bytes)
+            })
+            .collect::<Vec<_>>();
+        MerkleTree::new(&leaves).root()
+    }
+
+    /// Register a validator, or update its stake if already registered
+    pub fn register(&mut self, validator: AccountId, stake: u128) -> Result<(), ConsensusError> {
+        if stake == 0 {
+            return Err(ConsensusError::ZeroStake(validator));
+        }
+        let _ = self.stakes.insert(validator, stake);
+        Ok(())
+    }
+
+    pub fn unregister(&mut self, validator: &AccountId) -> Result<u128, ConsensusError> {
+        self.stakes
+            .remove(validator)
+            .ok_or(ConsensusError::UnknownValidator(*validator))
+    }
+
+    /// Apply a validator transaction. Returns whether `tx` was one, other
+    /// transaction types leave the set untouched. The stake of a validator
+    /// registering again is added to the one it bonded already.
+    pub fn apply(&mut self, tx: &Transaction) -> Result<bool, ConsensusError> {
+        match tx.tx_type {
+            TransactionType::RegisterValidator => {
+                if tx.amount == Amount::ZERO {
+                    return Err(ConsensusError::ZeroStake(tx.origin));
+                }
+                let bonded = self.stake(&tx.origin).unwrap_or_default();
+                self.register(tx.origin, bonded.saturating_add(tx.amount.base_units()))?
+            }
+            TransactionType::UnregisterValidator => {
+                let _ = self.unregister(&tx.origin)?;
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Sample up to `k` distinct validators other than `except`, each drawn
+    /// with a probability proportional to its stake
+    pub fn sample<R: Rng>(&self, k: usize, except: &AccountId, rng: &mut R) -> Vec<AccountId> {
+        // Weighted sampling without replacement (Efraimidis-Spirakis): keep
+        // the validators with the highest u^(1/stake)
+        let mut keyed = self
+            .stakes
+            .iter()
+            .filter(|(validator, _)| *validator != except)
+            .map(|(validator, stake)| {
+                let key = rng.gen::<f64>().powf(1.0 / *stake as f64);
+                (key, *validator)
+            })
+            .collect::<Vec<_>>();
+        keyed.sort_by(|a, b| b.0.total_cmp(&a.0));
+        keyed
+            .into_iter()
+            .take(k)
+            .map(|(_, validator)| validator)
+            .collect()
+    }
+}
+
+impl CommonConsensusNetwork for ValidatorSet {
+    /// Validators run nodes under the ID of their account, the hash of the
+    /// same key
+    fn get_nodes_except_one(&self, k: u64, node_id: NodeId) -> Vec<NodeId> {
+        let except = AccountId::new(*node_id.as_hash());
+        self.sample(k as usize, &except, &mut rand::thread_rng())
+            .into_iter()
+            .map(|validator| NodeId::new(*validator.as_hash()))
+            .collect()
+    }
+}
+
+#[test]
+fn test_sampling_is_stake_weighted() {
+    use crate::account::Account;
+    use crypto::hash::Hash;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    let validator_tx = |name: &str, stake: u128, tx_type: TransactionType| {
+        let origin = Account::create(&Hash::new(name.as_bytes()).into(), &Hash::default().into());
+        Transaction::new(
+            Hash::default().into(),
+            origin,
+            Hash::default().into(),
+            Amount::new(stake),
+            tx_type,
+            vec![],
+        )
+    };
+    let (whale, minnow, other) = (
+        AccountId::from(Hash::new("whale".as_bytes())),
+        AccountId::from(Hash::new("minnow".as_bytes())),
+        AccountId::from(Hash::new("other".as_bytes())),
+    );
+    let mut validators = ValidatorSet::new();
+    for (name, stake) in [("whale", 98), ("minnow", 1), ("other", 1)] {
+        let tx = validator_tx(name, stake, TransactionType::RegisterValidator);
+        assert!(validators.apply(&tx).unwrap());
+    }
+    let transfer = validator_tx("whale", 1, TransactionType::Transfer);
+    assert!(!validators.apply(&transfer).unwrap());
+    assert!(matches!(
+        validators.register(other, 0),
+        Err(ConsensusError::ZeroStake(_))
+    ));
+    assert_eq!(validators.total_stake(), 100);
+
+    let mut rng = StdRng::seed_from_u64(0);
+    let whale_picks = (0..1000)
+        .filter(|_| validators.sample(1, &AccountId::default(), &mut rng) == vec![whale])
+        .count();
+    assert!(whale_picks > 900);
+    assert_eq!(validators.sample(5, &whale, &mut rng).len(), 2);
+
+    let tx = validator_tx("whale", 0, TransactionType::UnregisterValidator);
+    assert!(validators.apply(&tx).unwrap());
+    assert!(!validators.contains(&whale));
+    assert!(validators.apply(&tx).is_err());
+    let node = |account: AccountId| NodeId::new(*account.as_hash());
+    assert_eq!(
+        validators.get_nodes_except_one(5, node(minnow)),
+        vec![node(other)]
+    );
+}
@@ -0,0 +1,101 @@
+//! Double spends submitted to several nodes of a simulated network.
+//!
+//! Every account sends conflicting transactions, carrying the same
+//! sequence, through different proposers, under lossy and slow networks.
+//! Whatever the network does, at most one of them may be accepted.
+
+use consensus::{
+    account::{Account, AccountStateChoice},
+    amount::Amount,
+    config::ConsensusConfig,
+    safety::SafetyAuditor,
+    sim::{SimConfig, Simulation},
+    transaction::{Transaction, TransactionType},
+    TxId,
+};
+use crypto::hash::Hash;
+use std::collections::HashMap;
+use std::time::Duration;
+
+const NODES: usize = 10;
+const ACCOUNTS: usize = 6;
+const SPENDS_PER_ACCOUNT: usize = 3;
+
+/// Conflicting spends of every account. The spends of an account build on
+/// the first spend of the account before, whose state they all spend, so
+/// that the transactions before them gain confidence.
+fn double_spends() -> Vec<Vec<AccountStateChoice>> {
+    let mut parent = TxId::default();
+    (0..ACCOUNTS)
+        .map(|account| {
+            let origin = Account::create(
+                &Hash::new(format!("account-{}", account).as_bytes()).into(),
+                &parent,
+            );
+            let spends = (0..SPENDS_PER_ACCOUNT)
+                .map(|spend| {
+                    let mut tx = Transaction::new(
+                        parent,
+                        origin.clone(),
+                        Hash::new(format!("destination-{}", spend).as_bytes()).into(),
+                        Amount::new(100),
+                        TransactionType::Transfer,
+                        vec![],
+                    );
+                    tx.calculate_tx_id().unwrap();
+                    AccountStateChoice::new(parent.into(), &tx)
+                })
+                .collect::<Vec<_>>();
+            parent = spends[0].tx.get_tx_id();
+            spends
+        })
+        .collect()
+}
+
+/// Submit the spends of every account through different proposers, and
+/// audit what the network accepted. Returns how many transactions were.
+fn run(config: SimConfig) -> usize {
+    let mut sim = Simulation::new(config, ConsensusConfig::builder().k(5).build().unwrap());
+    let mut transactions = HashMap::new();
+    let mut proposer = 0;
+    for spends in double_spends() {
+        for state in &spends {
+            let _ = transactions.insert(state.tx.get_tx_id(), state.tx.clone());
+            let _ = sim.submit(proposer % NODES, state);
+            proposer += 1;
+        }
+    }
+
+    assert_eq!(sim.check_safety(), Ok(()));
+    let mut auditor = SafetyAuditor::new();
+    for tx_id in sim.accepted_transactions() {
+        auditor.record(&transactions[&tx_id]).unwrap();
+    }
+    assert_eq!(auditor.check(), Ok(()));
+    auditor.len()
+}
+
+#[test]
+fn test_double_spends_are_accepted_at_most_once() {
+    for seed in 0..5 {
+        let accepted = run(SimConfig {
+            nodes: NODES,
+            seed,
+            ..Default::default()
+        });
+        assert!(accepted > 0);
+    }
+}
+
+#[test]
+fn test_double_spends_on_a_lossy_network() {
+    for seed in 0..5 {
+        let _ = run(SimConfig {
+            nodes: NODES,
+            seed,
+            drop_rate: 0.3,
+            max_delay: Duration::from_millis(300),
+            ..Default::default()
+        });
+    }
+}
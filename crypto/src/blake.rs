@@ -14,7 +14,7 @@ impl Blake {
         let blake_hash = Params::new()
             .hash_length(LONG_HASH_LEN)
             .to_state()
-            .update(&src)
+            .update(src)
             .finalize();
         let mut hash: [u8; LONG_HASH_LEN] = [0; LONG_HASH_LEN];
         let bh = blake_hash.as_ref().to_vec();
@@ -35,7 +35,7 @@ impl Blake {
         Params::new()
             .hash_length(hash_len)
             .to_state()
-            .update(&src)
+            .update(src)
             .finalize()
             .as_ref()
             .to_vec()
@@ -0,0 +1,235 @@
+//! Cache of signature verifications.
+//!
+//! Gossip and batched consensus deliver the same signed messages many
+//! times over, each delivery costing a pairing to verify. A
+//! [`VerificationCache`] remembers the outcome for each public key and
+//! message hash, along with the signature it was reached for, so that
+//! duplicates are answered without verifying them again. A different
+//! signature on the same message is verified as if it was never seen.
+
+use crate::{
+    hash::Hash,
+    signature::{PublicKey, Signature},
+};
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Verifications remembered by default
+pub const DEFAULT_CACHE_CAPACITY: usize = 8192;
+
+type CacheKey = (Vec<u8>, Hash);
+
+#[derive(Debug, Default)]
+struct Entries {
+    /// Signature verified for each key and whether it was valid
+    outcomes: HashMap<CacheKey, (Vec<u8>, bool)>,
+    /// Keys in the order they were cached, the oldest evicted first
+    order: VecDeque<CacheKey>,
+}
+
+/// Outcome of a verification through a [`VerificationCache`]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Verification {
+    pub valid: bool,
+    /// Whether the outcome was cached rather than computed
+    pub cached: bool,
+}
+
+/// Bounded cache of signature verifications. Clones share the same entries
+/// and counters.
+#[derive(Clone, Debug)]
+pub struct VerificationCache {
+    entries: Arc<Mutex<Entries>>,
+    capacity: usize,
+    hits: Arc<AtomicU64>,
+    misses: Arc<AtomicU64>,
+}
+
+impl Default for VerificationCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_CACHE_CAPACITY)
+    }
+}
+
+impl VerificationCache {
+    /// Initialize a cache remembering up to `capacity` verifications
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: Default::default(),
+            capacity: capacity.max(1),
+            hits: Default::default(),
+            misses: Default::default(),
+        }
+    }
+
+    /// Verify `signature` of `data` by `pub_key`, unless it already was
+    pub fn verify<T>(&self, signature: &Signature, pub_key: &PublicKey, data: T) -> Verification
+    where
+        T: AsRef<[u8]>,
+    {
+        let key = (pub_key.to_bytes(), Hash::new(data.as_ref()));
+        if let Some(verification) = self.lookup(&key, signature) {
+            return verification;
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let valid = signature.verify(pub_key, data);
+        self.remember(key, signature, valid);
+        Verification {
+            valid,
+            cached: false,
+        }
+    }
+
+    /// Verify many signatures, each of its message by its public key. The
+    /// outcomes not cached are computed in one batch, see
+    /// [`Signature::batch_verify`], and one by one only if the batch fails.
+    pub fn verify_batch<T>(&self, items: &[(PublicKey, T, Signature)]) -> Vec<Verification>
+    where
+        T: AsRef<[u8]>,
+    {
+        let keys = items
+            .iter()
+            .map(|(pub_key, data, _)| (pub_key.to_bytes(), Hash::new(data.as_ref())))
+            .collect::<Vec<_>>();
+        let mut outcomes = items
+            .iter()
+            .zip(&keys)
+            .map(|((_, _, signature), key)| self.lookup(key, signature))
+            .collect::<Vec<_>>();
+        let uncached = (0..items.len())
+            .filter(|index| outcomes[*index].is_none())
+            .collect::<Vec<_>>();
+        let batch = uncached
+            .iter()
+            .map(|index| {
+                let (pub_key, data, signature) = &items[*index];
+                (*pub_key, data.as_ref(), *signature)
+            })
+            .collect::<Vec<_>>();
+        let all_valid = batch.len() > 1 && Signature::batch_verify(&batch);
+        for (index, (pub_key, data, signature)) in uncached.into_iter().zip(&batch) {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            let valid = all_valid || signature.verify(pub_key, data);
+            self.remember(keys[index].clone(), signature, valid);
+            outcomes[index] = Some(Verification {
+                valid,
+                cached: false,
+            });
+        }
+        outcomes.into_iter().flatten().collect()
+    }
+
+    /// Outcome cached for `key`, if it was reached for `signature`
+    fn lookup(&self, key: &CacheKey, signature: &Signature) -> Option<Verification> {
+        let entries = self.entries.lock().unwrap();
+        let (cached, valid) = entries.outcomes.get(key)?;
+        if *cached != signature.as_bytes() {
+            return None;
+        }
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Some(Verification {
+            valid: *valid,
+            cached: true,
+        })
+    }
+
+    fn remember(&self, key: CacheKey, signature: &Signature, valid: bool) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries
+            .outcomes
+            .insert(key.clone(), (signature.as_bytes(), valid))
+            .is_none()
+        {
+            entries.order.push_back(key);
+            if entries.order.len() > self.capacity {
+                if let Some(oldest) = entries.order.pop_front() {
+                    let _ = entries.outcomes.remove(&oldest);
+                }
+            }
+        }
+    }
+
+    /// Verifications answered from the cache
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Verifications that had to be computed
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().outcomes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[test]
+fn test_duplicate_verifications_hit_the_cache() {
+    use crate::signature::PrivateKey;
+
+    let key = PrivateKey::generate();
+    let pub_key = key.public_key();
+    let signature = Signature::sign(&key, "vote");
+    let cache = VerificationCache::new(2);
+
+    assert_eq!(
+        cache.verify(&signature, &pub_key, "vote"),
+        Verification {
+            valid: true,
+            cached: false
+        }
+    );
+    assert!(cache.verify(&signature, &pub_key, "vote").cached);
+    assert_eq!((cache.hits(), cache.misses()), (1, 1));
+
+    // Another signature of the same message is verified again
+    let forged = Signature::sign(&PrivateKey::generate(), "vote");
+    let verification = cache.verify(&forged, &pub_key, "vote");
+    assert!(!verification.valid && !verification.cached);
+    assert!(!cache.verify(&forged, &pub_key, "vote").valid);
+    assert_eq!(cache.len(), 1);
+
+    let _ = cache.verify(&Signature::sign(&key, "a"), &pub_key, "a");
+    let _ = cache.verify(&Signature::sign(&key, "b"), &pub_key, "b");
+    assert_eq!(cache.len(), 2);
+    assert!(!cache.verify(&forged, &pub_key, "vote").cached);
+}
+
+#[test]
+fn test_batches_verify_what_is_not_cached() {
+    use crate::signature::PrivateKey;
+
+    let [alice, bob] = [(); 2].map(|_| PrivateKey::generate());
+    let cache = VerificationCache::default();
+    let alice_vote = Signature::sign(&alice, "vote");
+    let _ = cache.verify(&alice_vote, &alice.public_key(), "vote");
+
+    let items = [
+        (alice.public_key(), "vote", alice_vote),
+        (bob.public_key(), "vote", Signature::sign(&bob, "vote")),
+        (
+            bob.public_key(),
+            "forged",
+            Signature::sign(&alice, "forged"),
+        ),
+    ];
+    let verifications = cache.verify_batch(&items);
+    assert_eq!(
+        verifications
+            .iter()
+            .map(|verification| (verification.valid, verification.cached))
+            .collect::<Vec<_>>(),
+        vec![(true, true), (true, false), (false, false)]
+    );
+    assert_eq!((cache.hits(), cache.misses()), (1, 3));
+    assert!(cache
+        .verify_batch(&items)
+        .iter()
+        .all(|verification| verification.cached));
+}
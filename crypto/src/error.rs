@@ -11,6 +11,14 @@ pub enum CryptoError {
     SerializationError(String),
     #[error("Deserialization error: {0}")]
     DeserializationError(String),
+    #[error("Mnemonic error: {0}")]
+    MnemonicError(String),
+    #[error("Key derivation error: {0}")]
+    DerivationError(String),
+    #[error("Threshold signature error: {0}")]
+    ThresholdError(String),
+    #[error("Key exchange error: {0}")]
+    KeyExchangeError(String),
     #[error("Option(None) returned error")]
     NoneError,
 }
@@ -1,19 +1,93 @@
-use super::{blake::Blake, error::CryptoError};
-use bytes::BytesMut;
+use super::{blake::Blake, error::CryptoError, keccak::Keccak, sha::Sha};
 use rand::{thread_rng, Rng};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
 const DISPLAY_HASH_LEN: usize = 4;
 const RANDOM_HASH_BUF: usize = 4096;
 
+/// Algorithm a [`Hash`] is computed with. Hashes of the chain itself are
+/// Blake2b, the others are for verifying data hashed by other chains.
+#[derive(
+    Clone, Copy, Debug, Default, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize,
+)]
+pub enum HashAlgorithm {
+    #[default]
+    Blake2b,
+    Sha256,
+    Keccak256,
+}
+
+impl HashAlgorithm {
+    /// Hash bytes with the algorithm
+    pub fn digest(self, data: &[u8]) -> [u8; 32] {
+        match self {
+            Self::Blake2b => Blake::long(data),
+            Self::Sha256 => Sha::sha256(data),
+            Self::Keccak256 => Keccak::keccak256(data),
+        }
+    }
+}
+
+/// What a hash combining several parts is taken of. Its tag is hashed
+/// ahead of the parts, so that hashes of different kinds of data never
+/// collide, even when their parts do.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum HashDomain {
+    Transaction,
+    Identity,
+    Checkpoint,
+    StateRoot,
+    /// Messages signed to prove VRF outputs
+    VrfInput,
+    VrfOutput,
+    Custom(&'static str),
+}
+
+impl HashDomain {
+    pub fn tag(&self) -> &'static str {
+        match self {
+            Self::Transaction => "dagchain/transaction",
+            Self::Identity => "dagchain/identity",
+            Self::Checkpoint => "dagchain/checkpoint",
+            Self::StateRoot => "dagchain/state-root",
+            Self::VrfInput => "dagchain/vrf-input",
+            Self::VrfOutput => "dagchain/vrf-output",
+            Self::Custom(tag) => tag,
+        }
+    }
+}
+
+/// Concatenate `parts`, each prefixed with its length in 8 bytes
+fn encode_parts(parts: &[&[u8]]) -> Vec<u8> {
+    let len = parts.iter().map(|part| 8 + part.len()).sum();
+    let mut buf = Vec::with_capacity(len);
+    for part in parts {
+        buf.extend_from_slice(&(part.len() as u64).to_le_bytes());
+        buf.extend_from_slice(part);
+    }
+    buf
+}
+
+fn encode_domain_parts(domain: HashDomain, parts: &[&[u8]]) -> Vec<u8> {
+    let mut tagged = Vec::with_capacity(parts.len() + 1);
+    tagged.push(domain.tag().as_bytes());
+    tagged.extend_from_slice(parts);
+    encode_parts(&tagged)
+}
+
 /// Hash representation
-#[derive(Clone, Copy, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[derive(Clone, Copy, Default, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
 pub struct Hash(pub [u8; 32]);
 
 impl Hash {
     /// Creates a Hash from bytes
     pub fn new(data: &[u8]) -> Self {
-        Self(Blake::long(&data))
+        Self(Blake::long(data))
+    }
+
+    /// Creates a Hash from bytes with `algorithm`
+    pub fn new_with(algorithm: HashAlgorithm, data: &[u8]) -> Self {
+        Self(algorithm.digest(data))
     }
 
     /// Creates a Hash for any serializable data
@@ -27,7 +101,7 @@ impl Hash {
     pub fn generate_random() -> Self {
         let mut bytes: [u8; RANDOM_HASH_BUF] = [0; RANDOM_HASH_BUF];
         thread_rng().fill(&mut bytes);
-        Self(Blake::long(&bytes.to_vec()))
+        Self(Blake::long(bytes.as_ref()))
     }
 
     /// Converts a Hash to a hex string
@@ -35,13 +109,24 @@ impl Hash {
         hex::encode(self.0)
     }
 
-    /// Takes in byte arrays and outputs a Hash
-    pub fn bytes_arrays_to_hash(bytes_arrays: Vec<Vec<u8>>) -> Self {
-        let mut buf = BytesMut::new();
-        for b in bytes_arrays {
-            buf.copy_from_slice(&b);
-        }
-        Self(Blake::long(&buf[..]))
+    /// Parses a Hash from a hex string
+    pub fn from_hex(s: &str) -> Result<Self, CryptoError> {
+        let mut hash = [0; 32];
+        hex::decode_to_slice(s, &mut hash)
+            .map_err(|e| CryptoError::DeserializationError(e.to_string()))?;
+        Ok(Self(hash))
+    }
+
+    /// Hash several parts, each prefixed with its length so that no two
+    /// ways of splitting the same bytes hash the same
+    pub fn combine(parts: &[&[u8]]) -> Self {
+        Self(Blake::long(&encode_parts(parts)))
+    }
+
+    /// Hash several parts in `domain`, so that the hash can't be taken for
+    /// one of the same parts in another domain
+    pub fn combine_in(domain: HashDomain, parts: &[&[u8]]) -> Self {
+        Self(Blake::long(&encode_domain_parts(domain, parts)))
     }
 }
 
@@ -51,12 +136,6 @@ impl AsRef<[u8]> for Hash {
     }
 }
 
-impl Default for Hash {
-    fn default() -> Self {
-        Self { 0: [0; 32] }
-    }
-}
-
 impl std::fmt::Display for Hash {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         let mut s = String::new();
@@ -86,13 +165,15 @@ impl std::fmt::Debug for Hash {
 }
 
 /// Hash representation
-#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[derive(
+    Clone, Copy, Debug, Default, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize,
+)]
 pub struct ShortHash(pub [u8; 20]);
 
 impl ShortHash {
     /// Creates a ShortHash from bytes
     pub fn new(data: &[u8]) -> Self {
-        Self(Blake::short(&data))
+        Self(Blake::short(data))
     }
 
     /// Creates a ShortHash for any serializable data
@@ -106,7 +187,7 @@ impl ShortHash {
     pub fn generate_random() -> Self {
         let mut bytes: [u8; RANDOM_HASH_BUF] = [0; RANDOM_HASH_BUF];
         thread_rng().fill(&mut bytes);
-        Self(Blake::short(&bytes.to_vec()))
+        Self(Blake::short(bytes.as_ref()))
     }
 
     /// Converts a ShortHash to a hex string
@@ -114,13 +195,14 @@ impl ShortHash {
         hex::encode(self.0)
     }
 
-    /// Takes in byte arrays and outputs a ShortHash
-    pub fn bytes_arrays_to_hash(bytes_arrays: Vec<Vec<u8>>) -> Self {
-        let mut buf = BytesMut::new();
-        for b in bytes_arrays {
-            buf.copy_from_slice(&b);
-        }
-        Self(Blake::short(&buf[..]))
+    /// Hash several parts, each prefixed with its length
+    pub fn combine(parts: &[&[u8]]) -> Self {
+        Self(Blake::short(&encode_parts(parts)))
+    }
+
+    /// Hash several parts in `domain`
+    pub fn combine_in(domain: HashDomain, parts: &[&[u8]]) -> Self {
+        Self(Blake::short(&encode_domain_parts(domain, parts)))
     }
 }
 
@@ -130,12 +212,6 @@ impl AsRef<[u8]> for ShortHash {
     }
 }
 
-impl Default for ShortHash {
-    fn default() -> Self {
-        Self { 0: [0; 20] }
-    }
-}
-
 /// Hash type representation.
 /// Used for common Hash relations.
 pub trait HashType:
@@ -167,3 +243,57 @@ impl<N> HashType for N where
         + Default
 {
 }
+
+#[test]
+fn test_hash_algorithms_match_known_digests() {
+    let digest = |algorithm, data: &str| Hash::new_with(algorithm, data.as_bytes()).to_hex();
+
+    assert_eq!(
+        digest(HashAlgorithm::Sha256, "abc"),
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+    assert_eq!(
+        digest(HashAlgorithm::Keccak256, ""),
+        "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470"
+    );
+    assert_eq!(
+        digest(HashAlgorithm::Keccak256, "abc"),
+        "4e03657aea45a94fc7d47ba826c8d667c0d1e6e33a64a036ec44f58fa12d6c45"
+    );
+    assert_eq!(
+        digest(
+            HashAlgorithm::Keccak256,
+            "The quick brown fox jumps over the lazy dog"
+        ),
+        "4d741b6f1eb29cb2a9b9911c82f56fa8d73b04959d3d9d222895df6c0b28aa15"
+    );
+    assert_eq!(
+        Hash::new_with(HashAlgorithm::default(), b"abc"),
+        Hash::new(b"abc")
+    );
+}
+
+#[test]
+fn test_combined_parts_are_length_prefixed_and_domain_separated() {
+    let ab_c = Hash::combine(&[b"ab", b"c"]);
+    assert_eq!(ab_c, Hash::combine(&[b"ab", b"c"]));
+    assert_ne!(ab_c, Hash::combine(&[b"a", b"bc"]));
+    assert_ne!(ab_c, Hash::combine(&[b"abc"]));
+    assert_ne!(Hash::combine(&[]), Hash::combine(&[b""]));
+
+    let transaction = Hash::combine_in(HashDomain::Transaction, &[b"ab", b"c"]);
+    assert_ne!(transaction, ab_c);
+    assert_ne!(
+        transaction,
+        Hash::combine_in(HashDomain::Identity, &[b"ab", b"c"])
+    );
+    // The tag is a part of its own
+    assert_eq!(
+        transaction,
+        Hash::combine(&[b"dagchain/transaction", b"ab", b"c"])
+    );
+    assert_ne!(
+        ShortHash::combine(&[b"ab", b"c"]),
+        ShortHash::combine(&[b"a", b"bc"])
+    );
+}
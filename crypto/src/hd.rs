@@ -0,0 +1,255 @@
+//! Hierarchical deterministic keys.
+//!
+//! Keys derive from a BIP39 mnemonic along BIP44 style paths, e.g.
+//! `m/44'/0'/0'/0'/0'`, so that a wallet restores every account key from a
+//! single backup. Derivation follows SLIP-0010: BLS and Ed25519 secrets
+//! cannot be tweaked like secp256k1 ones, so every index is hardened.
+//!
+//! SLIP-0010 only specifies Ed25519 among these schemes. BLS keys derive
+//! the same way under the master HMAC key `bls12-381 seed`, which no
+//! standard defines: this is not EIP-2333, and BLS keys derived here don't
+//! match those other wallets derive from the same mnemonic and path.
+
+use crate::{error::CryptoError, secret::Secret, signature::PrivateKey};
+use hmac::{Hmac, Mac, NewMac};
+use sha2::Sha512;
+use std::fmt;
+use std::str::FromStr;
+
+/// Offset of hardened child indices
+pub const HARDENED: u32 = 1 << 31;
+
+/// Signature schemes keys are derived for. Each scheme derives its own tree
+/// from the same seed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum KeyScheme {
+    Bls,
+    Ed25519,
+}
+
+impl KeyScheme {
+    /// HMAC key of the master key derivation, as in SLIP-0010
+    fn curve_key(&self) -> &'static [u8] {
+        match self {
+            KeyScheme::Bls => b"bls12-381 seed",
+            KeyScheme::Ed25519 => b"ed25519 seed",
+        }
+    }
+}
+
+/// Generate a mnemonic of `word_count` words (12, 15, 18, 21 or 24)
+pub fn generate_mnemonic(word_count: usize) -> Result<Secret<String>, CryptoError> {
+    use rand::RngCore;
+    if !(12..=24).contains(&word_count) || !word_count.is_multiple_of(3) {
+        return Err(CryptoError::MnemonicError(format!(
+            "Unsupported word count: {}",
+            word_count
+        )));
+    }
+    let mut entropy = vec![0u8; word_count / 3 * 4];
+    rand::thread_rng().fill_bytes(&mut entropy);
+    let entropy = Secret::new(entropy);
+    let mnemonic = bip39::Mnemonic::from_entropy(entropy.expose_secret())
+        .map_err(|e| CryptoError::MnemonicError(e.to_string()))?;
+    Ok(Secret::new(mnemonic.to_string()))
+}
+
+/// Seed all the keys of a wallet derive from
+pub struct Seed(Secret<Vec<u8>>);
+
+impl Seed {
+    /// Seed of a BIP39 mnemonic, protected by an optional passphrase
+    pub fn from_mnemonic(phrase: &str, passphrase: &str) -> Result<Self, CryptoError> {
+        let mnemonic = bip39::Mnemonic::parse(phrase)
+            .map_err(|e| CryptoError::MnemonicError(e.to_string()))?;
+        Ok(Self(Secret::new(mnemonic.to_seed(passphrase).to_vec())))
+    }
+
+    pub fn from_bytes(seed: &[u8]) -> Self {
+        Self(Secret::new(seed.to_vec()))
+    }
+
+    pub fn expose_secret(&self) -> &[u8] {
+        self.0.expose_secret()
+    }
+}
+
+/// Path of child indices from the master key, e.g. `m/44'/0'/0'`
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct DerivationPath(Vec<u32>);
+
+impl DerivationPath {
+    /// Path of the key of account `account`, in the BIP44 layout
+    /// `m/44'/coin_type'/account'/0'/index'`
+    pub fn bip44(coin_type: u32, account: u32, index: u32) -> Self {
+        Self(
+            [44, coin_type, account, 0, index]
+                .iter()
+                .map(|i| i | HARDENED)
+                .collect(),
+        )
+    }
+
+    pub fn indices(&self) -> &[u32] {
+        &self.0
+    }
+}
+
+impl FromStr for DerivationPath {
+    type Err = CryptoError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || CryptoError::DerivationError(format!("Invalid path: {}", s));
+        let mut parts = s.split('/');
+        if parts.next() != Some("m") {
+            return Err(invalid());
+        }
+        parts
+            .map(|part| {
+                let (index, hardened) = match part.strip_suffix('\'') {
+                    Some(index) => (index, true),
+                    None => (part, false),
+                };
+                let index = index.parse::<u32>().map_err(|_| invalid())?;
+                if index >= HARDENED {
+                    return Err(invalid());
+                }
+                Ok(if hardened { index | HARDENED } else { index })
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+impl fmt::Display for DerivationPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("m")?;
+        for index in &self.0 {
+            if index & HARDENED != 0 {
+                write!(f, "/{}'", index & !HARDENED)?;
+            } else {
+                write!(f, "/{}", index)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Key of a node of the derivation tree, with the chain code its children
+/// derive from
+#[derive(Clone)]
+pub struct ExtendedKey {
+    scheme: KeyScheme,
+    secret: Secret<[u8; 32]>,
+    chain_code: Secret<[u8; 32]>,
+}
+
+impl ExtendedKey {
+    /// Master key of a seed
+    pub fn master(seed: &Seed, scheme: KeyScheme) -> Result<Self, CryptoError> {
+        Self::from_hmac(scheme, scheme.curve_key(), seed.expose_secret())
+    }
+
+    /// Derive the key at `path` below this one
+    pub fn derive(&self, path: &DerivationPath) -> Result<Self, CryptoError> {
+        path.0
+            .iter()
+            .try_fold(self.clone(), |key, index| key.derive_child(*index))
+    }
+
+    /// Derive a hardened child key
+    pub fn derive_child(&self, index: u32) -> Result<Self, CryptoError> {
+        if index & HARDENED == 0 {
+            return Err(CryptoError::DerivationError(format!(
+                "Index {} is not hardened",
+                index
+            )));
+        }
+        let mut data = Vec::with_capacity(37);
+        data.push(0);
+        data.extend_from_slice(self.secret.expose_secret());
+        data.extend_from_slice(&index.to_be_bytes());
+        let data = Secret::new(data);
+        Self::from_hmac(
+            self.scheme,
+            self.chain_code.expose_secret(),
+            data.expose_secret(),
+        )
+    }
+
+    pub fn scheme(&self) -> KeyScheme {
+        self.scheme
+    }
+
+    /// Secret of the key, the private key itself for Ed25519
+    pub fn expose_secret(&self) -> &[u8; 32] {
+        self.secret.expose_secret()
+    }
+
+    /// BLS private key generated from the secret of the key
+    pub fn to_bls_private_key(&self) -> Result<PrivateKey, CryptoError> {
+        if self.scheme != KeyScheme::Bls {
+            return Err(CryptoError::DerivationError(format!(
+                "{:?} key cannot be used as a BLS key",
+                self.scheme
+            )));
+        }
+        PrivateKey::from_key_material(self.secret.expose_secret())
+    }
+
+    fn from_hmac(scheme: KeyScheme, key: &[u8], data: &[u8]) -> Result<Self, CryptoError> {
+        let mut mac = Hmac::<Sha512>::new_from_slice(key)
+            .map_err(|e| CryptoError::DerivationError(e.to_string()))?;
+        mac.update(data);
+        let output = Secret::new(mac.finalize().into_bytes().to_vec());
+        let (mut secret, mut chain_code) = ([0u8; 32], [0u8; 32]);
+        secret.copy_from_slice(&output.expose_secret()[..32]);
+        chain_code.copy_from_slice(&output.expose_secret()[32..]);
+        Ok(Self {
+            scheme,
+            secret: Secret::new(secret),
+            chain_code: Secret::new(chain_code),
+        })
+    }
+}
+
+#[test]
+fn test_hd_derivation() {
+    // SLIP-0010 test vector 1 for ed25519
+    let seed = Seed::from_bytes(&hex::decode("000102030405060708090a0b0c0d0e0f").unwrap());
+    let master = ExtendedKey::master(&seed, KeyScheme::Ed25519).unwrap();
+    assert_eq!(
+        hex::encode(master.expose_secret()),
+        "2b4be7f19ee27bbf30c667b642d5f4aa69fd169872f8fc3059c08ebae2eb19e7"
+    );
+    let path = "m/0'/1'/2'".parse::<DerivationPath>().unwrap();
+    assert_eq!(path.to_string(), "m/0'/1'/2'");
+    assert_eq!(
+        hex::encode(master.derive(&path).unwrap().expose_secret()),
+        "92a5b23c0b8a99e37d07df3fb9966917f5d06e02ddbd909c7e184371463e9fc9"
+    );
+    assert!(master.derive(&"m/0'/1".parse().unwrap()).is_err());
+    assert!("0'/1'".parse::<DerivationPath>().is_err());
+
+    let phrase = generate_mnemonic(12).unwrap();
+    assert_eq!(phrase.expose_secret().split(' ').count(), 12);
+    let seed = Seed::from_mnemonic(phrase.expose_secret(), "").unwrap();
+    let master = ExtendedKey::master(&seed, KeyScheme::Bls).unwrap();
+    let key = |account| {
+        master
+            .derive(&DerivationPath::bip44(0, account, 0))
+            .unwrap()
+            .to_bls_private_key()
+            .unwrap()
+    };
+    assert_eq!(key(0), key(0));
+    assert_ne!(key(0), key(1));
+    let other = Seed::from_mnemonic(phrase.expose_secret(), "passphrase").unwrap();
+    assert_ne!(
+        ExtendedKey::master(&other, KeyScheme::Bls)
+            .unwrap()
+            .expose_secret(),
+        master.expose_secret()
+    );
+    assert!(Seed::from_mnemonic("not a mnemonic", "").is_err());
+}
@@ -0,0 +1,151 @@
+//! Keccak-256, as used by Ethereum.
+//!
+//! This is the original Keccak submission, padded with `0x01`, rather than
+//! the standardized SHA3-256 padded with `0x06`: the two give different
+//! hashes of the same data.
+
+use serde::{Deserialize, Serialize};
+
+const HASH_LEN: usize = 32;
+/// Bytes absorbed per permutation, for a 512 bit capacity
+const RATE: usize = 136;
+const ROUNDS: usize = 24;
+
+const ROUND_CONSTANTS: [u64; ROUNDS] = [
+    0x0000000000000001,
+    0x0000000000008082,
+    0x800000000000808a,
+    0x8000000080008000,
+    0x000000000000808b,
+    0x0000000080000001,
+    0x8000000080008081,
+    0x8000000000008009,
+    0x000000000000008a,
+    0x0000000000000088,
+    0x0000000080008009,
+    0x000000008000000a,
+    0x000000008000808b,
+    0x800000000000008b,
+    0x8000000000008089,
+    0x8000000000008003,
+    0x8000000000008002,
+    0x8000000000000080,
+    0x000000000000800a,
+    0x800000008000000a,
+    0x8000000080008081,
+    0x8000000000008080,
+    0x0000000080000001,
+    0x8000000080008008,
+];
+
+/// Rotation of each lane, in the order lanes are visited by rho and pi
+const ROTATIONS: [u32; 24] = [
+    1, 3, 6, 10, 15, 21, 28, 36, 45, 55, 2, 14, 27, 41, 56, 8, 25, 43, 62, 18, 39, 61, 20, 44,
+];
+const LANES: [usize; 24] = [
+    10, 7, 11, 17, 18, 3, 5, 16, 8, 21, 24, 4, 15, 23, 19, 13, 12, 2, 20, 14, 22, 9, 6, 1,
+];
+
+/// Keccak hash representation
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub struct Keccak;
+
+impl Keccak {
+    /// Produces a Keccak-256 byte Hash array from source bytes
+    pub fn keccak256(src: &[u8]) -> [u8; HASH_LEN] {
+        let mut state = [0u64; 25];
+        let mut blocks = src.chunks_exact(RATE);
+        for block in &mut blocks {
+            absorb(&mut state, block);
+        }
+        let rest = blocks.remainder();
+        let mut last = [0u8; RATE];
+        last[..rest.len()].copy_from_slice(rest);
+        last[rest.len()] ^= 0x01;
+        last[RATE - 1] ^= 0x80;
+        absorb(&mut state, &last);
+
+        let mut hash: [u8; HASH_LEN] = [0; HASH_LEN];
+        for (bytes, lane) in hash.chunks_exact_mut(8).zip(state) {
+            bytes.copy_from_slice(&lane.to_le_bytes());
+        }
+        hash
+    }
+}
+
+fn absorb(state: &mut [u64; 25], block: &[u8]) {
+    for (lane, bytes) in state.iter_mut().zip(block.chunks_exact(8)) {
+        *lane ^= u64::from_le_bytes(bytes.try_into().unwrap());
+    }
+    permute(state);
+}
+
+/// The Keccak-f[1600] permutation
+fn permute(state: &mut [u64; 25]) {
+    for round_constant in ROUND_CONSTANTS {
+        // Theta
+        let mut columns = [0u64; 5];
+        for (x, column) in columns.iter_mut().enumerate() {
+            *column = (0..5).fold(0, |parity, y| parity ^ state[x + 5 * y]);
+        }
+        for x in 0..5 {
+            let d = columns[(x + 4) % 5] ^ columns[(x + 1) % 5].rotate_left(1);
+            for y in 0..5 {
+                state[x + 5 * y] ^= d;
+            }
+        }
+
+        // Rho and pi
+        let mut carried = state[1];
+        for (rotation, lane) in ROTATIONS.iter().zip(LANES) {
+            let next = state[lane];
+            state[lane] = carried.rotate_left(*rotation);
+            carried = next;
+        }
+
+        // Chi
+        for y in 0..5 {
+            let row = [
+                state[5 * y],
+                state[5 * y + 1],
+                state[5 * y + 2],
+                state[5 * y + 3],
+                state[5 * y + 4],
+            ];
+            for x in 0..5 {
+                state[x + 5 * y] = row[x] ^ (!row[(x + 1) % 5] & row[(x + 2) % 5]);
+            }
+        }
+
+        // Iota
+        state[0] ^= round_constant;
+    }
+}
+
+#[test]
+fn test_keccak256_across_block_boundaries() {
+    let digest = |len| hex::encode(Keccak::keccak256(&vec![b'a'; len]));
+
+    // Padding fits in the last byte of the block at 135 bytes, and takes a
+    // block of its own at 136
+    assert_eq!(
+        digest(RATE - 1),
+        "34367dc248bbd832f4e3e69dfaac2f92638bd0bbd18f2912ba4ef454919cf446"
+    );
+    assert_eq!(
+        digest(RATE),
+        "a6c4d403279fe3e0af03729caada8374b5ca54d8065329a3ebcaeb4b60aa386e"
+    );
+    assert_eq!(
+        digest(RATE + 1),
+        "d869f639c7046b4929fc92a4d988a8b22c55fbadb802c0c66ebcd484f1915f39"
+    );
+    assert_eq!(
+        digest(2 * RATE),
+        "cf7fcd4f705ee749930d19ca84561a9bf62516bd90a471545fa2f49fdc7e63c8"
+    );
+    assert_eq!(
+        digest(300),
+        "5b7e0e47a96f32a88b4f14ca177982790807c40e1a105742ba0fc1babe1ef826"
+    );
+}
@@ -4,6 +4,13 @@
 #![warn(clippy::all)]
 
 pub mod blake;
+pub mod cache;
 pub mod error;
 pub mod hash;
+pub mod hd;
+pub mod keccak;
+pub mod merkle;
+pub mod secret;
+pub mod sha;
 pub mod signature;
+pub mod vrf;
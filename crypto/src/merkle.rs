@@ -0,0 +1,220 @@
+use super::hash::Hash;
+use serde::{Deserialize, Serialize};
+
+/// Tag hashed along with leaves
+pub const LEAF_TAG: u8 = 0x00;
+/// Tag hashed along with inner nodes
+pub const NODE_TAG: u8 = 0x01;
+/// Tag hashed along with the leaf count and the top node into the root
+pub const ROOT_TAG: u8 = 0x02;
+
+/// Binary Merkle tree over `Hash` leaves.
+///
+/// Leaves and inner nodes are hashed with distinct tags so a leaf can never
+/// pass for an inner node. An odd last node is promoted to the next level
+/// as is. The root commits to the number of leaves along with the top node,
+/// so that a proof cannot pass for the proof of another index in a tree of
+/// another size. The root of an empty tree is the default Hash.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MerkleTree {
+    /// Every level of the tree, from hashed leaves up to the root
+    levels: Vec<Vec<Hash>>,
+}
+
+impl MerkleTree {
+    /// Build a tree over the given leaves
+    pub fn new(leaves: &[Hash]) -> Self {
+        let mut levels = vec![leaves.iter().map(leaf_hash).collect::<Vec<_>>()];
+        while levels[levels.len() - 1].len() > 1 {
+            let next = next_level(&levels[levels.len() - 1]);
+            levels.push(next);
+        }
+        Self { levels }
+    }
+
+    /// Number of leaves
+    pub fn len(&self) -> usize {
+        self.levels[0].len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.levels[0].is_empty()
+    }
+
+    /// Root committing to every leaf and to their number
+    pub fn root(&self) -> Hash {
+        self.levels
+            .last()
+            .and_then(|level| level.first())
+            .map_or_else(Hash::default, |top| root_hash(self.len(), top))
+    }
+
+    /// Replace the leaf at `index`, rehashing only the nodes above it.
+    /// Returns `false`, leaving the tree untouched, if there is no leaf at
+    /// `index`.
+    pub fn update(&mut self, index: usize, leaf: &Hash) -> bool {
+        if index >= self.len() {
+            return false;
+        }
+        self.levels[0][index] = leaf_hash(leaf);
+        let mut position = index;
+        for depth in 1..self.levels.len() {
+            let below = &self.levels[depth - 1];
+            let left = position & !1;
+            let node = match below.get(left + 1) {
+                Some(right) => node_hash(&below[left], right),
+                None => below[left],
+            };
+            position /= 2;
+            self.levels[depth][position] = node;
+        }
+        true
+    }
+
+    /// Prove the inclusion of the leaf at `index`
+    pub fn proof(&self, index: usize) -> Option<MerkleProof> {
+        if index >= self.len() {
+            return None;
+        }
+        let mut siblings = vec![];
+        let mut position = index;
+        for level in &self.levels[..self.levels.len() - 1] {
+            if let Some(sibling) = level.get(position ^ 1) {
+                siblings.push(*sibling);
+            }
+            position /= 2;
+        }
+        Some(MerkleProof {
+            index,
+            leaf_count: self.len(),
+            siblings,
+        })
+    }
+}
+
+/// Inclusion proof of a leaf in a `MerkleTree`
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct MerkleProof {
+    pub index: usize,
+    pub leaf_count: usize,
+    /// Sibling hashes from the leaf level up
+    pub siblings: Vec<Hash>,
+}
+
+impl MerkleProof {
+    /// Verify that `leaf` sits at the proven index of a tree of
+    /// `leaf_count` leaves under `root`
+    pub fn verify(&self, leaf: &Hash, root: &Hash) -> bool {
+        if self.index >= self.leaf_count {
+            return false;
+        }
+        let mut hash = leaf_hash(leaf);
+        let mut index = self.index;
+        let mut width = self.leaf_count;
+        let mut siblings = self.siblings.iter();
+        while width > 1 {
+            if index ^ 1 < width {
+                let sibling = match siblings.next() {
+                    Some(sibling) => sibling,
+                    None => return false,
+                };
+                hash = if index.is_multiple_of(2) {
+                    node_hash(&hash, sibling)
+                } else {
+                    node_hash(sibling, &hash)
+                };
+            }
+            index /= 2;
+            width = width.div_ceil(2);
+        }
+        siblings.next().is_none() && root_hash(self.leaf_count, &hash) == *root
+    }
+}
+
+fn leaf_hash(leaf: &Hash) -> Hash {
+    let mut bytes = vec![LEAF_TAG];
+    bytes.extend_from_slice(&leaf.0);
+    Hash::new(&bytes)
+}
+
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    let mut bytes = vec![NODE_TAG];
+    bytes.extend_from_slice(&left.0);
+    bytes.extend_from_slice(&right.0);
+    Hash::new(&bytes)
+}
+
+fn root_hash(leaf_count: usize, top: &Hash) -> Hash {
+    let mut bytes = vec![ROOT_TAG];
+    bytes.extend_from_slice(&(leaf_count as u64).to_le_bytes());
+    bytes.extend_from_slice(&top.0);
+    Hash::new(&bytes)
+}
+
+/// Hash one level of the tree into the next, promoting an odd last node
+fn next_level(level: &[Hash]) -> Vec<Hash> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => node_hash(left, right),
+            [single] => *single,
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+#[test]
+fn test_merkle_proofs() {
+    for count in 1..=9 {
+        let leaves = (0..count)
+            .map(|i| Hash::new(&[i as u8]))
+            .collect::<Vec<_>>();
+        let tree = MerkleTree::new(&leaves);
+        let root = tree.root();
+        for (index, leaf) in leaves.iter().enumerate() {
+            let proof = tree.proof(index).unwrap();
+            assert!(proof.verify(leaf, &root));
+            assert!(!proof.verify(&Hash::new("other".as_bytes()), &root));
+        }
+        assert!(tree.proof(count).is_none());
+    }
+}
+
+#[test]
+fn test_merkle_root_commits_to_order() {
+    let a = Hash::new("A".as_bytes());
+    let b = Hash::new("B".as_bytes());
+    let tree = MerkleTree::new(&[a, b]);
+    assert_ne!(tree.root(), MerkleTree::new(&[b, a]).root());
+    assert_ne!(tree.root(), MerkleTree::new(&[a]).root());
+    assert_eq!(MerkleTree::new(&[]).root(), Hash::default());
+
+    let mut proof = tree.proof(0).unwrap();
+    proof.index = 1;
+    assert!(!proof.verify(&a, &tree.root()));
+
+    // The last of 3 leaves doesn't pass for the second of 2
+    let c = Hash::new("C".as_bytes());
+    let tree = MerkleTree::new(&[a, b, c]);
+    let mut proof = tree.proof(2).unwrap();
+    assert!(proof.verify(&c, &tree.root()));
+    proof.index = 1;
+    proof.leaf_count = 2;
+    assert!(!proof.verify(&c, &tree.root()));
+}
+
+#[test]
+fn test_merkle_update_matches_rebuild() {
+    for count in 1..=9 {
+        let mut leaves = (0..count)
+            .map(|i| Hash::new(&[i as u8]))
+            .collect::<Vec<_>>();
+        let mut tree = MerkleTree::new(&leaves);
+        for index in 0..count {
+            leaves[index] = Hash::new(&[index as u8, 1]);
+            assert!(tree.update(index, &leaves[index]));
+            assert_eq!(tree, MerkleTree::new(&leaves));
+        }
+        assert!(!tree.update(count, &Hash::default()));
+    }
+}
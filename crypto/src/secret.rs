@@ -0,0 +1,39 @@
+//! Secrets wiped from memory once dropped.
+
+use zeroize::Zeroize;
+
+/// Secret value zeroed when dropped.
+///
+/// The value is only reachable through [`Secret::expose_secret`], so that
+/// uses of secrets stand out, and is redacted from `Debug` output.
+#[derive(Clone, PartialEq)]
+pub struct Secret<T: Zeroize>(T);
+
+impl<T: Zeroize> Secret<T> {
+    pub fn new(secret: T) -> Self {
+        Self(secret)
+    }
+
+    /// Borrow the secret value
+    pub fn expose_secret(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: Zeroize> From<T> for Secret<T> {
+    fn from(secret: T) -> Self {
+        Self::new(secret)
+    }
+}
+
+impl<T: Zeroize> Drop for Secret<T> {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl<T: Zeroize> std::fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Secret([REDACTED])")
+    }
+}
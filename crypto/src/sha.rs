@@ -0,0 +1,17 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+const HASH_LEN: usize = 32;
+
+/// SHA-256 hash representation
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub struct Sha;
+
+impl Sha {
+    /// Produces a SHA-256 byte Hash array from source bytes
+    pub fn sha256(src: &[u8]) -> [u8; HASH_LEN] {
+        let mut hash: [u8; HASH_LEN] = [0; HASH_LEN];
+        hash.copy_from_slice(&Sha256::digest(src));
+        hash
+    }
+}
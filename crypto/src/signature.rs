@@ -1,3 +1,14 @@
+use crate::{
+    error::CryptoError,
+    hash::{Hash, HashDomain},
+    secret::Secret,
+};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroize;
+
+/// Least key material a PrivateKey is generated from, in bytes
+pub const KEY_MATERIAL_LEN: usize = 32;
+
 /// BLS Signature
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Signature(bls_signatures::Signature);
@@ -45,6 +56,66 @@ impl Signature {
         let aggr_sig = bls_signatures::aggregate(&signatures)?;
         Ok(Self::new(aggr_sig))
     }
+
+    /// Verify an aggregate of signatures on the same message.
+    ///
+    /// Open to rogue-key attacks, so the public keys must be ones whose
+    /// owners proved possession of the private key, see
+    /// [`PublicKey::verify_possession`].
+    pub fn verify_aggregate<T>(&self, pub_keys: &[PublicKey], data: T) -> bool
+    where
+        T: AsRef<[u8]>,
+    {
+        match PublicKey::aggregate(pub_keys) {
+            Some(pub_key) => self.verify(&pub_key, data),
+            None => false,
+        }
+    }
+
+    /// Verify an aggregate of signatures on distinct messages, the i-th
+    /// message being signed by the i-th public key.
+    /// Fails if any two messages are the same.
+    pub fn verify_aggregate_distinct<T>(&self, pub_keys: &[PublicKey], messages: &[T]) -> bool
+    where
+        T: AsRef<[u8]>,
+    {
+        let pub_keys = pub_keys.iter().map(|x| x.0).collect::<Vec<_>>();
+        let messages = messages.iter().map(|x| x.as_ref()).collect::<Vec<_>>();
+        bls_signatures::verify_messages(&self.0, &messages, &pub_keys)
+    }
+
+    /// Verify many signatures at once, each of its message by its public
+    /// key, faster than one by one. Messages may repeat.
+    ///
+    /// The signatures are combined with random 64-bit weights, so that
+    /// invalid ones cannot cancel out, and checked with a single product
+    /// of pairings. Returns whether all are valid, not which ones aren't.
+    pub fn batch_verify(items: &[(PublicKey, &[u8], Signature)]) -> bool {
+        use bls12_381::{
+            multi_miller_loop, G1Affine, G1Projective, G2Affine, G2Prepared, G2Projective, Gt,
+            Scalar,
+        };
+        use rand::Rng;
+        let mut rng = rand::thread_rng();
+        let mut combined = G2Projective::identity();
+        let mut terms = Vec::with_capacity(items.len() + 1);
+        for (pub_key, message, signature) in items {
+            let weight = Scalar::from(rng.gen_range(1..=u64::MAX));
+            combined += G2Projective::from(signature.0) * weight;
+            let pub_key = G1Affine::from(G1Projective::from(pub_key.0) * weight);
+            let message = G2Prepared::from(G2Affine::from(bls_signatures::hash(message)));
+            terms.push((pub_key, message));
+        }
+        terms.push((
+            -G1Affine::generator(),
+            G2Prepared::from(G2Affine::from(combined)),
+        ));
+        let terms = terms
+            .iter()
+            .map(|(pub_key, message)| (pub_key, message))
+            .collect::<Vec<_>>();
+        multi_miller_loop(&terms).final_exponentiation() == Gt::identity()
+    }
 }
 
 impl serde::Serialize for Signature {
@@ -62,7 +133,7 @@ impl<'de> serde::Deserialize<'de> for Signature {
     where
         D: serde::Deserializer<'de>,
     {
-        deserializer.deserialize_bytes(SignatureVisitor)
+        deserializer.deserialize_bytes(SIGNATURE_VISITOR)
     }
 }
 
@@ -82,6 +153,32 @@ impl PublicKey {
         use bls_signatures::Serialize;
         self.0.as_bytes()
     }
+
+    /// Aggregate PublicKeys, to verify an aggregate of signatures on the
+    /// same message. Returns `None` if there are no keys.
+    pub fn aggregate(pub_keys: &[Self]) -> Option<Self> {
+        use bls12_381::G1Projective;
+        let (first, rest) = pub_keys.split_first()?;
+        let aggr_key = rest.iter().fold(G1Projective::from(first.0), |acc, x| {
+            acc + G1Projective::from(x.0)
+        });
+        Some(Self(aggr_key.into()))
+    }
+
+    /// Check that the owner of this key holds its private key, so that
+    /// signatures by this key can be aggregated with others
+    pub fn verify_possession(&self, proof: &Signature) -> bool {
+        proof.verify(self, possession_message(self))
+    }
+}
+
+/// Message signed to prove possession of the private key of `public_key`,
+/// in a domain of its own so that no other signature passes for a proof
+fn possession_message(public_key: &PublicKey) -> Hash {
+    Hash::combine_in(
+        HashDomain::Custom("dagchain/possession"),
+        &[&public_key.to_bytes()],
+    )
 }
 
 impl serde::Serialize for PublicKey {
@@ -99,12 +196,12 @@ impl<'de> serde::Deserialize<'de> for PublicKey {
     where
         D: serde::Deserializer<'de>,
     {
-        deserializer.deserialize_bytes(PublicKeyVisitor)
+        deserializer.deserialize_bytes(PUBLIC_KEY_VISITOR)
     }
 }
 
-/// BLS Private Key
-#[derive(Clone, Copy, Debug, PartialEq)]
+/// BLS Private Key, zeroed when dropped
+#[derive(Clone, PartialEq)]
 pub struct PrivateKey(bls_signatures::PrivateKey);
 
 impl PrivateKey {
@@ -114,10 +211,23 @@ impl PrivateKey {
         Ok(Self(bls_signatures::PrivateKey::from_bytes(raw)?))
     }
 
-    /// Convert PrivateKey to bytes
-    pub fn to_bytes(&self) -> Vec<u8> {
+    /// Convert PrivateKey to bytes, zeroed once dropped
+    pub fn expose_secret(&self) -> Secret<Vec<u8>> {
         use bls_signatures::Serialize;
-        self.0.as_bytes()
+        Secret::new(self.0.as_bytes())
+    }
+
+    /// Generate a PrivateKey deterministically from at least 32 bytes of key
+    /// material, e.g. a derived key
+    pub fn from_key_material(ikm: &[u8]) -> Result<Self, CryptoError> {
+        if ikm.len() < KEY_MATERIAL_LEN {
+            return Err(CryptoError::DerivationError(format!(
+                "Key material is {} bytes, at least {} are needed",
+                ikm.len(),
+                KEY_MATERIAL_LEN
+            )));
+        }
+        Ok(Self(bls_signatures::PrivateKey::new(ikm)))
     }
 
     /// Generate a random PrivateKey
@@ -131,6 +241,61 @@ impl PrivateKey {
     pub fn public_key(&self) -> PublicKey {
         PublicKey(self.0.public_key())
     }
+
+    /// Diffie-Hellman key exchange: the point this key shares with the
+    /// owner of `peer`, zeroed once dropped. Refuses the identity, which
+    /// anybody sharing it would know.
+    pub fn agree(&self, peer: &PublicKey) -> Result<Secret<Vec<u8>>, CryptoError> {
+        use bls12_381::{G1Affine, G1Projective, Scalar};
+        let mut scalar = Scalar::from(self.0);
+        let shared = G1Affine::from(G1Projective::from(peer.0) * scalar);
+        wipe_scalar(&mut scalar);
+        if bool::from(shared.is_identity()) {
+            return Err(CryptoError::KeyExchangeError(
+                "the keys share the identity".to_string(),
+            ));
+        }
+        Ok(Secret::new(shared.to_compressed().to_vec()))
+    }
+
+    /// Prove possession of this key to the ones aggregating signatures by
+    /// its public key, see [`PublicKey::verify_possession`]
+    pub fn prove_possession(&self) -> Signature {
+        Signature::sign(self, possession_message(&self.public_key()))
+    }
+}
+
+impl Zeroize for PrivateKey {
+    fn zeroize(&mut self) {
+        use bls12_381::Scalar;
+        // Volatile so that the write is not optimized away, like zeroize
+        // does for its own types
+        unsafe {
+            std::ptr::write_volatile(&mut self.0, Scalar::zero().into());
+        }
+        std::sync::atomic::compiler_fence(std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+/// Overwrite a scalar derived from a private key with zero
+fn wipe_scalar(scalar: &mut bls12_381::Scalar) {
+    // Volatile for the same reason as in `PrivateKey::zeroize`
+    unsafe {
+        std::ptr::write_volatile(scalar, bls12_381::Scalar::zero());
+    }
+    std::sync::atomic::compiler_fence(std::sync::atomic::Ordering::SeqCst);
+}
+
+impl Drop for PrivateKey {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+impl std::fmt::Debug for PrivateKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("PrivateKey([REDACTED])")
+    }
 }
 
 impl serde::Serialize for PrivateKey {
@@ -138,8 +303,7 @@ impl serde::Serialize for PrivateKey {
     where
         S: serde::Serializer,
     {
-        use bls_signatures::Serialize;
-        serializer.serialize_bytes(&self.0.as_bytes())
+        serializer.serialize_bytes(self.expose_secret().expose_secret())
     }
 }
 
@@ -148,82 +312,220 @@ impl<'de> serde::Deserialize<'de> for PrivateKey {
     where
         D: serde::Deserializer<'de>,
     {
-        deserializer.deserialize_bytes(PrivateKeyVisitor)
+        deserializer.deserialize_bytes(PRIVATE_KEY_VISITOR)
     }
 }
 
-struct SignatureVisitor;
-
-impl<'de> serde::de::Visitor<'de> for SignatureVisitor {
-    type Value = Signature;
+/// Share of a threshold key, held by one member of a committee.
+///
+/// Members sign with their share, and any `threshold` of the resulting
+/// [`ThresholdSignatureShare`]s combine into one signature verifying
+/// against the [`ThresholdKeys::public_key`] of the committee.
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyShare {
+    /// Point the share was evaluated at, from 1
+    pub index: u64,
+    pub private_key: PrivateKey,
+}
 
-    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-        formatter.write_str("a signature byte array")
+impl KeyShare {
+    /// Public key of the share, to check the signature shares of its holder
+    pub fn public_key(&self) -> PublicKey {
+        self.private_key.public_key()
     }
 
-    fn visit_borrowed_bytes<E>(self, v: &'de [u8]) -> Result<Self::Value, E>
+    /// Sign a message with the share
+    pub fn sign<T>(&self, data: T) -> ThresholdSignatureShare
     where
-        E: serde::de::Error,
+        T: AsRef<[u8]>,
     {
-        Ok(Signature::from_bytes(v).unwrap())
+        ThresholdSignatureShare {
+            index: self.index,
+            signature: Signature::sign(&self.private_key, data),
+        }
     }
+}
 
-    fn visit_borrowed_str<E>(self, v: &'de str) -> Result<Self::Value, E>
+/// Keys of a committee, `threshold` of whose members must sign together
+#[derive(Debug)]
+pub struct ThresholdKeys {
+    pub threshold: usize,
+    /// Group public key, which combined signatures verify against
+    pub public_key: PublicKey,
+    /// Shares to hand out to the members, one each
+    pub shares: Vec<KeyShare>,
+}
+
+impl ThresholdKeys {
+    /// Deal `count` shares of a random key, any `threshold` of which can
+    /// sign with it.
+    ///
+    /// The key is the constant of a random polynomial of degree
+    /// `threshold - 1`, and the i-th share the polynomial at i. The dealer
+    /// learns the key, so must be trusted to forget it: the coefficients
+    /// of the polynomial are wiped from memory once the shares are dealt.
+    pub fn generate(threshold: usize, count: usize) -> Result<Self, CryptoError> {
+        use bls12_381::Scalar;
+        if threshold == 0 || threshold > count {
+            return Err(CryptoError::ThresholdError(format!(
+                "cannot deal {} shares with a threshold of {}",
+                count, threshold
+            )));
+        }
+        // Private keys, so that the coefficients are wiped once dropped
+        let coefficients = (0..threshold)
+            .map(|_| PrivateKey::generate())
+            .collect::<Vec<_>>();
+        let public_key = coefficients[0].public_key();
+        let shares = (1..=count as u64)
+            .map(|index| {
+                let x = Scalar::from(index);
+                let mut y = coefficients
+                    .iter()
+                    .rev()
+                    .fold(Scalar::zero(), |acc, coefficient| {
+                        acc * x + Scalar::from(coefficient.0)
+                    });
+                let private_key = PrivateKey(y.into());
+                wipe_scalar(&mut y);
+                KeyShare { index, private_key }
+            })
+            .collect();
+        Ok(Self {
+            threshold,
+            public_key,
+            shares,
+        })
+    }
+}
+
+/// Signature of a message with a [`KeyShare`]
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ThresholdSignatureShare {
+    /// Index of the share that signed
+    pub index: u64,
+    pub signature: Signature,
+}
+
+impl ThresholdSignatureShare {
+    /// Verify the share against the public key of the [`KeyShare`] that
+    /// signed, before combining it
+    pub fn verify<T>(&self, share_key: &PublicKey, data: T) -> bool
     where
-        E: serde::de::Error,
+        T: AsRef<[u8]>,
     {
-        Ok(Signature::from_bytes(v.as_bytes()).unwrap())
+        self.signature.verify(share_key, data)
+    }
+}
+
+/// Combine `threshold` signature shares into the signature of the group
+/// key, by interpolating them at 0.
+///
+/// Fails if fewer than `threshold` shares of distinct indices are given.
+/// Shares beyond the first `threshold` are ignored, and an invalid share
+/// yields a signature that doesn't verify.
+pub fn combine_shares(
+    shares: &[ThresholdSignatureShare],
+    threshold: usize,
+) -> Result<Signature, CryptoError> {
+    use bls12_381::{G2Projective, Scalar};
+    let mut indices = shares.iter().map(|share| share.index).collect::<Vec<_>>();
+    indices.sort_unstable();
+    indices.dedup();
+    if threshold == 0 || indices.len() != shares.len() || indices.contains(&0) {
+        return Err(CryptoError::ThresholdError(
+            "signature shares must have distinct, non-zero indices".to_string(),
+        ));
+    }
+    if shares.len() < threshold {
+        return Err(CryptoError::ThresholdError(format!(
+            "{} signature shares given, {} needed",
+            shares.len(),
+            threshold
+        )));
+    }
+    let shares = &shares[..threshold];
+    let mut combined = G2Projective::identity();
+    for share in shares {
+        let x = Scalar::from(share.index);
+        let (numerator, denominator) = shares
+            .iter()
+            .filter(|other| other.index != share.index)
+            .map(|other| Scalar::from(other.index))
+            .fold((Scalar::one(), Scalar::one()), |(num, den), other| {
+                (num * other, den * (other - x))
+            });
+        // Distinct indices make the denominator non-zero
+        let lagrange = numerator * denominator.invert().unwrap();
+        combined += G2Projective::from(share.signature.0) * lagrange;
     }
+    Ok(Signature(combined.into()))
 }
 
-struct PublicKeyVisitor;
+/// Visitor of the bytes of a BLS type, whether borrowed, owned or a
+/// sequence, e.g. in JSON
+struct BytesVisitor<T> {
+    expecting: &'static str,
+    parse: fn(&[u8]) -> Result<T, bls_signatures::Error>,
+}
 
-impl<'de> serde::de::Visitor<'de> for PublicKeyVisitor {
-    type Value = PublicKey;
+impl<'de, T> serde::de::Visitor<'de> for BytesVisitor<T> {
+    type Value = T;
 
     fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-        formatter.write_str("a signature byte array")
+        formatter.write_str(self.expecting)
     }
 
-    fn visit_borrowed_bytes<E>(self, v: &'de [u8]) -> Result<Self::Value, E>
+    fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E>
     where
         E: serde::de::Error,
     {
-        Ok(PublicKey::from_bytes(v).unwrap())
+        (self.parse)(v).map_err(|e| E::custom(format!("invalid {}: {}", self.expecting, e)))
     }
 
-    fn visit_borrowed_str<E>(self, v: &'de str) -> Result<Self::Value, E>
+    fn visit_byte_buf<E>(self, mut v: Vec<u8>) -> Result<Self::Value, E>
     where
         E: serde::de::Error,
     {
-        Ok(PublicKey::from_bytes(v.as_bytes()).unwrap())
+        let value = self.visit_bytes(&v);
+        v.zeroize();
+        value
     }
-}
-
-struct PrivateKeyVisitor;
 
-impl<'de> serde::de::Visitor<'de> for PrivateKeyVisitor {
-    type Value = PrivateKey;
-
-    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-        formatter.write_str("a signature byte array")
-    }
-
-    fn visit_borrowed_bytes<E>(self, v: &'de [u8]) -> Result<Self::Value, E>
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
     where
         E: serde::de::Error,
     {
-        Ok(PrivateKey::from_bytes(v).unwrap())
+        self.visit_bytes(v.as_bytes())
     }
 
-    fn visit_borrowed_str<E>(self, v: &'de str) -> Result<Self::Value, E>
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
     where
-        E: serde::de::Error,
+        A: serde::de::SeqAccess<'de>,
     {
-        Ok(PrivateKey::from_bytes(v.as_bytes()).unwrap())
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(96));
+        while let Some(byte) = seq.next_element::<u8>()? {
+            bytes.push(byte);
+        }
+        self.visit_byte_buf(bytes)
     }
 }
 
+const SIGNATURE_VISITOR: BytesVisitor<Signature> = BytesVisitor {
+    expecting: "signature byte array",
+    parse: Signature::from_bytes,
+};
+
+const PUBLIC_KEY_VISITOR: BytesVisitor<PublicKey> = BytesVisitor {
+    expecting: "public key byte array",
+    parse: PublicKey::from_bytes,
+};
+
+const PRIVATE_KEY_VISITOR: BytesVisitor<PrivateKey> = BytesVisitor {
+    expecting: "private key byte array",
+    parse: PrivateKey::from_bytes,
+};
+
 #[test]
 fn test_signature() {
     let secret_key = PrivateKey::generate();
@@ -243,6 +545,98 @@ fn test_signature() {
     assert!(s_sig.verify(&public_key, data));
 }
 
+#[test]
+fn test_private_key_from_key_material() {
+    assert!(PrivateKey::from_key_material(&[7; KEY_MATERIAL_LEN - 1]).is_err());
+    let key = PrivateKey::from_key_material(&[7; KEY_MATERIAL_LEN]).unwrap();
+    let again = PrivateKey::from_key_material(&[7; KEY_MATERIAL_LEN]).unwrap();
+    assert_eq!(key.public_key(), again.public_key());
+}
+
+#[test]
+fn test_aggregate_signature() {
+    let keys = (0..3).map(|_| PrivateKey::generate()).collect::<Vec<_>>();
+    let pub_keys = keys.iter().map(|x| x.public_key()).collect::<Vec<_>>();
+
+    let data = "data to be signed";
+    let sigs = keys
+        .iter()
+        .map(|x| Signature::sign(x, data))
+        .collect::<Vec<_>>();
+    let aggr_sig = Signature::aggregate(&sigs).unwrap();
+    assert!(aggr_sig.verify_aggregate(&pub_keys, data));
+    assert!(!aggr_sig.verify_aggregate(&pub_keys[1..], data));
+    assert!(!aggr_sig.verify_aggregate(&pub_keys, "other data"));
+    assert!(!aggr_sig.verify_aggregate(&[], data));
+
+    let messages = ["first", "second", "third"];
+    let sigs = keys
+        .iter()
+        .zip(messages)
+        .map(|(x, message)| Signature::sign(x, message))
+        .collect::<Vec<_>>();
+    let aggr_sig = Signature::aggregate(&sigs).unwrap();
+    assert!(aggr_sig.verify_aggregate_distinct(&pub_keys, &messages));
+    assert!(!aggr_sig.verify_aggregate_distinct(&pub_keys, &["first", "second", "other"]));
+    assert!(!aggr_sig.verify_aggregate_distinct(&pub_keys[..2], &messages[..2]));
+}
+
+#[test]
+fn test_batch_verify() {
+    let keys = (0..4).map(|_| PrivateKey::generate()).collect::<Vec<_>>();
+    let messages: [&[u8]; 4] = [b"first", b"second", b"second", b"third"];
+    let mut items = keys
+        .iter()
+        .zip(messages)
+        .map(|(key, message)| (key.public_key(), message, Signature::sign(key, message)))
+        .collect::<Vec<_>>();
+    assert!(Signature::batch_verify(&items));
+    assert!(Signature::batch_verify(&[]));
+
+    // A single bad signature fails the batch
+    items[2].2 = Signature::sign(&keys[2], "other");
+    assert!(!Signature::batch_verify(&items));
+    // Signatures swapped between items don't cancel out
+    items[2].2 = Signature::sign(&keys[2], messages[2]);
+    let (first, second) = (items[0].2, items[1].2);
+    items[0].2 = second;
+    items[1].2 = first;
+    assert!(!Signature::batch_verify(&items));
+}
+
+#[test]
+fn test_malformed_keys_fail_to_deserialize() {
+    let secret_key = PrivateKey::generate();
+    let public_key = secret_key.public_key();
+    let signature = Signature::sign(&secret_key, "data");
+
+    // JSON carries bytes as sequences
+    let json = serde_json::to_string(&public_key).unwrap();
+    assert_eq!(
+        serde_json::from_str::<PublicKey>(&json).unwrap(),
+        public_key
+    );
+    let json = serde_json::to_string(&signature).unwrap();
+    assert_eq!(serde_json::from_str::<Signature>(&json).unwrap(), signature);
+    let json = serde_json::to_string(&secret_key).unwrap();
+    assert_eq!(
+        serde_json::from_str::<PrivateKey>(&json).unwrap(),
+        secret_key
+    );
+
+    assert!(serde_json::from_str::<PublicKey>("[1, 2, 3]").is_err());
+    assert!(serde_json::from_str::<Signature>("[256]").is_err());
+    let mut truncated = bincode::serialize(&signature).unwrap();
+    truncated.truncate(truncated.len() - 1);
+    assert!(bincode::deserialize::<Signature>(&truncated).is_err());
+    let error = bincode::deserialize::<PublicKey>(&bincode::serialize(&[0u8; 48][..]).unwrap())
+        .unwrap_err();
+    assert!(error.to_string().contains("invalid public key byte array"));
+    assert!(
+        bincode::deserialize::<PrivateKey>(&bincode::serialize(&[1u8; 4][..]).unwrap()).is_err()
+    );
+}
+
 #[test]
 fn test_public_key() {
     let secret_key = PrivateKey::generate();
@@ -259,6 +653,26 @@ fn test_public_key() {
     assert_eq!(public_key, s_pub_key);
 }
 
+#[test]
+fn test_private_key_is_zeroed() {
+    let mut secret_key = PrivateKey::generate();
+    let public_key = secret_key.public_key();
+    assert_eq!(format!("{:?}", secret_key), "PrivateKey([REDACTED])");
+    assert!(secret_key
+        .expose_secret()
+        .expose_secret()
+        .iter()
+        .any(|x| *x != 0));
+
+    secret_key.zeroize();
+    assert!(secret_key
+        .expose_secret()
+        .expose_secret()
+        .iter()
+        .all(|x| *x == 0));
+    assert_ne!(secret_key.public_key(), public_key);
+}
+
 #[test]
 fn test_private_key() {
     let secret_key = PrivateKey::generate();
@@ -272,3 +686,63 @@ fn test_private_key() {
     let s_secret = deserialized_secret_key.unwrap();
     assert_eq!(secret_key, s_secret);
 }
+
+#[test]
+fn test_threshold_shares_combine_into_group_signature() {
+    let keys = ThresholdKeys::generate(3, 5).unwrap();
+    let data = "checkpoint";
+    let shares = keys
+        .shares
+        .iter()
+        .map(|share| share.sign(data))
+        .collect::<Vec<_>>();
+    assert!(shares[0].verify(&keys.shares[0].public_key(), data));
+    assert!(!shares[0].verify(&keys.shares[1].public_key(), data));
+
+    // Any 3 of the 5 shares make the same signature
+    let signature = combine_shares(&shares[..3], 3).unwrap();
+    assert!(signature.verify(&keys.public_key, data));
+    assert!(!signature.verify(&keys.public_key, "other data"));
+    let others = [shares[4], shares[1], shares[3]];
+    assert_eq!(combine_shares(&others, 3).unwrap(), signature);
+
+    // Too few shares can't sign for the group
+    assert!(combine_shares(&shares[..2], 3).is_err());
+    let combined = combine_shares(&shares[..2], 2).unwrap();
+    assert!(!combined.verify(&keys.public_key, data));
+    assert!(combine_shares(&[shares[0], shares[0], shares[1]], 3).is_err());
+    assert!(ThresholdKeys::generate(4, 3).is_err());
+}
+
+#[test]
+fn test_key_exchange() {
+    use bls12_381::{G1Affine, G1Projective};
+    let [alice, bob, mallory] = [(); 3].map(|_| PrivateKey::generate());
+    let shared = alice.agree(&bob.public_key()).unwrap();
+    assert_eq!(
+        shared.expose_secret(),
+        bob.agree(&alice.public_key()).unwrap().expose_secret()
+    );
+    assert_ne!(
+        shared.expose_secret(),
+        mallory.agree(&bob.public_key()).unwrap().expose_secret()
+    );
+
+    let identity = G1Affine::from(G1Projective::identity()).to_compressed();
+    let identity = PublicKey::from_bytes(&identity).unwrap();
+    assert!(alice.agree(&identity).is_err());
+}
+
+#[test]
+fn test_proof_of_possession() {
+    let secret_key = PrivateKey::generate();
+    let public_key = secret_key.public_key();
+    let proof = secret_key.prove_possession();
+    assert!(public_key.verify_possession(&proof));
+
+    // Proofs are bound to their key, and signatures on the key itself
+    // don't pass for proofs
+    let other = PrivateKey::generate();
+    assert!(!other.public_key().verify_possession(&proof));
+    assert!(!public_key.verify_possession(&Signature::sign(&secret_key, public_key.to_bytes())));
+}
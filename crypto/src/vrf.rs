@@ -0,0 +1,66 @@
+//! Verifiable random function, on BLS signatures.
+//!
+//! A BLS signature is deterministic and unique for a key and message, so
+//! hashing it gives an output only the holder of the private key can
+//! compute, yet everyone can check against the public key. The input is
+//! signed in a domain of its own, so that a proof can't be passed off as a
+//! signature on the same bytes, nor the other way round.
+
+use crate::{
+    hash::{Hash, HashDomain},
+    signature::{PrivateKey, PublicKey, Signature},
+};
+use serde::{Deserialize, Serialize};
+
+/// Proof that a VRF output was computed from an input with a private key
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct VrfProof(Signature);
+
+impl VrfProof {
+    /// Output the proof attests to
+    pub fn output(&self) -> Hash {
+        Hash::combine_in(HashDomain::VrfOutput, &[&self.0.as_bytes()])
+    }
+}
+
+/// Message signed to prove the output for `input`
+fn vrf_message(input: &[u8]) -> Hash {
+    Hash::combine_in(HashDomain::VrfInput, &[input])
+}
+
+/// Compute the VRF output of `input` under `private_key`, along with its
+/// proof
+pub fn prove(private_key: &PrivateKey, input: &[u8]) -> (Hash, VrfProof) {
+    let proof = VrfProof(Signature::sign(private_key, vrf_message(input)));
+    (proof.output(), proof)
+}
+
+/// Check `proof` for `input` under `pub_key`, returning the output it
+/// attests to if it is valid
+pub fn verify(pub_key: &PublicKey, input: &[u8], proof: &VrfProof) -> Option<Hash> {
+    proof
+        .0
+        .verify(pub_key, vrf_message(input))
+        .then(|| proof.output())
+}
+
+#[test]
+fn test_vrf_outputs_are_unique_and_verifiable() {
+    let key = PrivateKey::generate();
+    let pub_key = key.public_key();
+    let (output, proof) = prove(&key, b"round 1");
+
+    assert_eq!(verify(&pub_key, b"round 1", &proof), Some(output));
+    // Deterministic for a key and input
+    assert_eq!(prove(&key, b"round 1"), (output, proof));
+    assert_ne!(prove(&key, b"round 2").0, output);
+
+    assert_eq!(verify(&pub_key, b"round 2", &proof), None);
+    assert_eq!(
+        verify(&PrivateKey::generate().public_key(), b"round 1", &proof),
+        None
+    );
+    // Not a plain signature on the input
+    let signature = VrfProof(Signature::sign(&key, b"round 1"));
+    assert_eq!(verify(&pub_key, b"round 1", &signature), None);
+}
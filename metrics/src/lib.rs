@@ -0,0 +1,455 @@
+//! Runtime metrics of a DAGchain node
+
+#![warn(clippy::all)]
+
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Counters and gauges shared by the components of a node.
+///
+/// Components hold an `Arc<Metrics>` and update it as they go; callers
+/// scrape it through [`Metrics::snapshot`].
+#[derive(Debug, Default)]
+pub struct Metrics {
+    connections: AtomicU64,
+    connections_opened: AtomicU64,
+    connected_subnets: AtomicU64,
+    largest_subnet_connections: AtomicU64,
+    routed_messages: AtomicU64,
+    dropped_messages: AtomicU64,
+    looped_messages: AtomicU64,
+    rate_limited_messages: AtomicU64,
+    outbox_depth: AtomicU64,
+    outbox_overflows: AtomicU64,
+    consensus_rounds: AtomicU64,
+    accepted: AtomicU64,
+    rejected: AtomicU64,
+    timed_out: AtomicU64,
+    acceptance_latency_us: AtomicU64,
+    mempool_depth: AtomicU64,
+    storage_bytes: AtomicU64,
+    routing_convergences: AtomicU64,
+    routing_convergence_us: AtomicU64,
+    last_routing_convergence_us: AtomicU64,
+    signature_cache_hits: AtomicU64,
+    signature_cache_misses: AtomicU64,
+    storage_cache_hits: AtomicU64,
+    storage_cache_misses: AtomicU64,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a connection to a peer being established
+    pub fn connection_opened(&self) {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+        self.connections_opened.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a connection to a peer being lost
+    pub fn connection_closed(&self) {
+        let _ = self
+            .connections
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1));
+    }
+
+    /// Record how connected peers spread across subnets
+    pub fn set_peer_diversity(&self, subnets: usize, largest: usize) {
+        self.connected_subnets
+            .store(subnets as u64, Ordering::Relaxed);
+        self.largest_subnet_connections
+            .store(largest as u64, Ordering::Relaxed);
+    }
+
+    /// Record a message forwarded towards another node
+    pub fn message_routed(&self) {
+        self.routed_messages.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a message dropped before reaching its destination
+    pub fn message_dropped(&self) {
+        self.dropped_messages.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a relayed message dropped because it came back to a node it
+    /// already visited
+    pub fn message_looped(&self) {
+        self.dropped_messages.fetch_add(1, Ordering::Relaxed);
+        self.looped_messages.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a message dropped because its sender went over its rate
+    /// limits
+    pub fn message_rate_limited(&self) {
+        self.dropped_messages.fetch_add(1, Ordering::Relaxed);
+        self.rate_limited_messages.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record the envelopes queued in the outbox for all next hops
+    pub fn set_outbox_depth(&self, depth: usize) {
+        self.outbox_depth.store(depth as u64, Ordering::Relaxed);
+    }
+
+    /// Record an envelope queued for a next hop whose queue was full
+    pub fn outbox_overflowed(&self) {
+        self.outbox_overflows.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a consensus round accepting its transaction after `latency`
+    pub fn round_accepted(&self, latency: Duration) {
+        self.consensus_rounds.fetch_add(1, Ordering::Relaxed);
+        self.accepted.fetch_add(1, Ordering::Relaxed);
+        self.acceptance_latency_us
+            .fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Record a consensus round rejecting its transaction
+    pub fn round_rejected(&self) {
+        self.consensus_rounds.fetch_add(1, Ordering::Relaxed);
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a consensus round running out of attempts before enough
+    /// responses arrived
+    pub fn round_timed_out(&self) {
+        self.consensus_rounds.fetch_add(1, Ordering::Relaxed);
+        self.timed_out.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn set_mempool_depth(&self, depth: usize) {
+        self.mempool_depth.store(depth as u64, Ordering::Relaxed);
+    }
+
+    pub fn set_storage_bytes(&self, bytes: u64) {
+        self.storage_bytes.store(bytes, Ordering::Relaxed);
+    }
+
+    /// Record the routing table settling `took` after a change of topology
+    pub fn routing_converged(&self, took: Duration) {
+        let took = took.as_micros() as u64;
+        self.routing_convergences.fetch_add(1, Ordering::Relaxed);
+        self.routing_convergence_us
+            .fetch_add(took, Ordering::Relaxed);
+        self.last_routing_convergence_us
+            .store(took, Ordering::Relaxed);
+    }
+
+    /// Record a signature verification, answered from the cache or not
+    pub fn signature_verified(&self, cached: bool) {
+        if cached {
+            self.signature_cache_hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.signature_cache_misses.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Record a storage read answered from the cache
+    pub fn storage_cache_hit(&self) {
+        self.storage_cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a storage read that missed the cache
+    pub fn storage_cache_missed(&self) {
+        self.storage_cache_misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Retrieve the current value of every metric
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            connections: self.connections.load(Ordering::Relaxed),
+            connections_opened: self.connections_opened.load(Ordering::Relaxed),
+            connected_subnets: self.connected_subnets.load(Ordering::Relaxed),
+            largest_subnet_connections: self.largest_subnet_connections.load(Ordering::Relaxed),
+            routed_messages: self.routed_messages.load(Ordering::Relaxed),
+            dropped_messages: self.dropped_messages.load(Ordering::Relaxed),
+            looped_messages: self.looped_messages.load(Ordering::Relaxed),
+            rate_limited_messages: self.rate_limited_messages.load(Ordering::Relaxed),
+            outbox_depth: self.outbox_depth.load(Ordering::Relaxed),
+            outbox_overflows: self.outbox_overflows.load(Ordering::Relaxed),
+            consensus_rounds: self.consensus_rounds.load(Ordering::Relaxed),
+            accepted: self.accepted.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+            timed_out: self.timed_out.load(Ordering::Relaxed),
+            acceptance_latency: Duration::from_micros(
+                self.acceptance_latency_us.load(Ordering::Relaxed),
+            ),
+            mempool_depth: self.mempool_depth.load(Ordering::Relaxed),
+            storage_bytes: self.storage_bytes.load(Ordering::Relaxed),
+            routing_convergences: self.routing_convergences.load(Ordering::Relaxed),
+            routing_convergence_time: Duration::from_micros(
+                self.routing_convergence_us.load(Ordering::Relaxed),
+            ),
+            last_routing_convergence: Duration::from_micros(
+                self.last_routing_convergence_us.load(Ordering::Relaxed),
+            ),
+            signature_cache_hits: self.signature_cache_hits.load(Ordering::Relaxed),
+            signature_cache_misses: self.signature_cache_misses.load(Ordering::Relaxed),
+            storage_cache_hits: self.storage_cache_hits.load(Ordering::Relaxed),
+            storage_cache_misses: self.storage_cache_misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Point-in-time values of the node metrics
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct MetricsSnapshot {
+    /// Peers currently connected
+    pub connections: u64,
+    /// Connections established since startup
+    pub connections_opened: u64,
+    /// Distinct subnets connected peers come from
+    pub connected_subnets: u64,
+    /// Connected peers in the most represented subnet
+    pub largest_subnet_connections: u64,
+    pub routed_messages: u64,
+    pub dropped_messages: u64,
+    /// Dropped messages that came back to a node they already visited
+    pub looped_messages: u64,
+    /// Dropped messages whose sender went over its rate limits
+    pub rate_limited_messages: u64,
+    /// Envelopes queued in the outbox for all next hops
+    pub outbox_depth: u64,
+    /// Envelopes queued for a next hop whose queue was full
+    pub outbox_overflows: u64,
+    pub consensus_rounds: u64,
+    pub accepted: u64,
+    pub rejected: u64,
+    /// Rounds out of attempts before enough responses arrived
+    pub timed_out: u64,
+    /// Sum of acceptance latencies of accepted transactions
+    pub acceptance_latency: Duration,
+    pub mempool_depth: u64,
+    pub storage_bytes: u64,
+    /// Changes of topology the routing table settled after
+    pub routing_convergences: u64,
+    /// Sum of the times the routing table took to settle
+    pub routing_convergence_time: Duration,
+    /// Time the routing table took to settle after the latest change
+    pub last_routing_convergence: Duration,
+    /// Signature verifications answered from the cache
+    pub signature_cache_hits: u64,
+    /// Signature verifications computed for want of a cached outcome
+    pub signature_cache_misses: u64,
+    /// Storage reads answered from the cache
+    pub storage_cache_hits: u64,
+    /// Storage reads that missed the cache
+    pub storage_cache_misses: u64,
+}
+
+impl MetricsSnapshot {
+    /// Mean acceptance latency of accepted transactions
+    pub fn mean_acceptance_latency(&self) -> Duration {
+        if self.accepted == 0 {
+            return Duration::default();
+        }
+        self.acceptance_latency / self.accepted as u32
+    }
+
+    /// Mean time the routing table took to settle after a change of topology
+    pub fn mean_routing_convergence(&self) -> Duration {
+        if self.routing_convergences == 0 {
+            return Duration::default();
+        }
+        self.routing_convergence_time / self.routing_convergences as u32
+    }
+
+    /// Name, kind (counter or gauge), description and value of every
+    /// metric of the snapshot
+    pub fn samples(&self) -> Vec<(&'static str, &'static str, &'static str, f64)> {
+        vec![
+            (
+                "connections",
+                "gauge",
+                "Peers currently connected",
+                self.connections as f64,
+            ),
+            (
+                "connections_opened_total",
+                "counter",
+                "Connections established since startup",
+                self.connections_opened as f64,
+            ),
+            (
+                "connected_subnets",
+                "gauge",
+                "Distinct subnets connected peers come from",
+                self.connected_subnets as f64,
+            ),
+            (
+                "largest_subnet_connections",
+                "gauge",
+                "Connected peers in the most represented subnet",
+                self.largest_subnet_connections as f64,
+            ),
+            (
+                "routed_messages_total",
+                "counter",
+                "Messages forwarded towards another node",
+                self.routed_messages as f64,
+            ),
+            (
+                "dropped_messages_total",
+                "counter",
+                "Messages dropped before reaching their destination",
+                self.dropped_messages as f64,
+            ),
+            (
+                "looped_messages_total",
+                "counter",
+                "Relayed messages dropped for revisiting a node",
+                self.looped_messages as f64,
+            ),
+            (
+                "rate_limited_messages_total",
+                "counter",
+                "Messages dropped for their sender going over its rate limits",
+                self.rate_limited_messages as f64,
+            ),
+            (
+                "outbox_depth",
+                "gauge",
+                "Envelopes queued in the outbox for all next hops",
+                self.outbox_depth as f64,
+            ),
+            (
+                "outbox_overflows_total",
+                "counter",
+                "Envelopes queued for a next hop whose queue was full",
+                self.outbox_overflows as f64,
+            ),
+            (
+                "consensus_rounds_total",
+                "counter",
+                "Completed consensus rounds",
+                self.consensus_rounds as f64,
+            ),
+            (
+                "accepted_total",
+                "counter",
+                "Transactions accepted by consensus",
+                self.accepted as f64,
+            ),
+            (
+                "rejected_total",
+                "counter",
+                "Transactions rejected by consensus",
+                self.rejected as f64,
+            ),
+            (
+                "timed_out_total",
+                "counter",
+                "Consensus rounds out of attempts before enough responses arrived",
+                self.timed_out as f64,
+            ),
+            (
+                "acceptance_latency_seconds_sum",
+                "counter",
+                "Sum of acceptance latencies of accepted transactions",
+                self.acceptance_latency.as_secs_f64(),
+            ),
+            (
+                "mempool_depth",
+                "gauge",
+                "Transactions waiting in the mempool",
+                self.mempool_depth as f64,
+            ),
+            (
+                "storage_bytes",
+                "gauge",
+                "Size of the stored data in bytes",
+                self.storage_bytes as f64,
+            ),
+            (
+                "routing_convergences_total",
+                "counter",
+                "Changes of topology the routing table settled after",
+                self.routing_convergences as f64,
+            ),
+            (
+                "routing_convergence_seconds_sum",
+                "counter",
+                "Sum of the times the routing table took to settle",
+                self.routing_convergence_time.as_secs_f64(),
+            ),
+            (
+                "last_routing_convergence_seconds",
+                "gauge",
+                "Time the routing table took to settle after the latest change",
+                self.last_routing_convergence.as_secs_f64(),
+            ),
+            (
+                "signature_cache_hits_total",
+                "counter",
+                "Signature verifications answered from the cache",
+                self.signature_cache_hits as f64,
+            ),
+            (
+                "signature_cache_misses_total",
+                "counter",
+                "Signature verifications computed for want of a cached outcome",
+                self.signature_cache_misses as f64,
+            ),
+            (
+                "storage_cache_hits_total",
+                "counter",
+                "Storage reads answered from the cache",
+                self.storage_cache_hits as f64,
+            ),
+            (
+                "storage_cache_misses_total",
+                "counter",
+                "Storage reads that missed the cache",
+                self.storage_cache_misses as f64,
+            ),
+        ]
+    }
+
+    /// Render the snapshot in the Prometheus text exposition format
+    pub fn to_prometheus(&self) -> String {
+        let mut text = String::new();
+        for (name, kind, help, value) in self.samples() {
+            let _ = writeln!(text, "# HELP dagchain_{} {}", name, help);
+            let _ = writeln!(text, "# TYPE dagchain_{} {}", name, kind);
+            let _ = writeln!(text, "dagchain_{} {}", name, value);
+        }
+        text
+    }
+}
+
+#[test]
+fn test_metrics_snapshot() {
+    let metrics = Metrics::new();
+    metrics.connection_opened();
+    metrics.connection_opened();
+    metrics.connection_closed();
+    metrics.round_accepted(Duration::from_millis(30));
+    metrics.round_accepted(Duration::from_millis(10));
+    metrics.round_rejected();
+    metrics.round_timed_out();
+    metrics.set_mempool_depth(7);
+    metrics.message_rate_limited();
+    metrics.routing_converged(Duration::from_secs(3));
+    metrics.routing_converged(Duration::from_secs(1));
+
+    let snapshot = metrics.snapshot();
+    assert_eq!(snapshot.connections, 1);
+    assert_eq!(snapshot.connections_opened, 2);
+    assert_eq!(snapshot.consensus_rounds, 4);
+    assert_eq!(snapshot.timed_out, 1);
+    assert_eq!(snapshot.dropped_messages, snapshot.rate_limited_messages);
+    assert_eq!(
+        snapshot.mean_acceptance_latency(),
+        Duration::from_millis(20)
+    );
+
+    assert_eq!(snapshot.mean_routing_convergence(), Duration::from_secs(2));
+    assert_eq!(snapshot.last_routing_convergence, Duration::from_secs(1));
+
+    let text = snapshot.to_prometheus();
+    assert!(text.contains("# TYPE dagchain_connections gauge\ndagchain_connections 1\n"));
+    assert!(text.contains("dagchain_mempool_depth 7\n"));
+}
@@ -0,0 +1,162 @@
+//! JSON-RPC client of a running node, used by `tx send`.
+
+use crate::error::NodeError;
+use consensus::{
+    account::Account,
+    amount::Amount,
+    transaction::{Transaction, TransactionType},
+    AccountId, ConsensusError, TxId,
+};
+use crypto::hash::Hash;
+use p2p::node::identity::Identity;
+use serde_json::{json, Value};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+
+/// Calls the JSON-RPC methods of the node listening at an address
+pub struct RpcClient {
+    addr: SocketAddr,
+}
+
+impl RpcClient {
+    pub fn new(addr: SocketAddr) -> Self {
+        Self { addr }
+    }
+
+    /// Call `method`, returning its result
+    pub fn call(&self, method: &str, params: Value) -> Result<Value, NodeError> {
+        let body = json!({
+            "jsonrpc": "2.0",
+            "method": method,
+            "params": params,
+            "id": 1,
+        })
+        .to_string();
+        let mut stream = TcpStream::connect(self.addr)?;
+        write!(
+            stream,
+            "POST / HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.addr,
+            body.len(),
+            body
+        )?;
+        let mut response = String::new();
+        let _ = stream.read_to_string(&mut response)?;
+        let (_, body) = response
+            .split_once("\r\n\r\n")
+            .ok_or_else(|| NodeError::RpcError("Malformed HTTP response".to_string()))?;
+        let mut response = serde_json::from_str::<Value>(body)?;
+        if let Some(error) = response.get("error") {
+            return Err(NodeError::RpcError(
+                error["message"].as_str().unwrap_or_default().to_string(),
+            ));
+        }
+        Ok(response["result"].take())
+    }
+
+    /// Send `amount` from the account of `identity` to `destination`
+    pub fn transfer(
+        &self,
+        identity: &Identity,
+        destination: AccountId,
+        amount: Amount,
+        memo: Option<String>,
+    ) -> Result<TxId, NodeError> {
+        let origin = account_id(identity);
+        let account = self.call("get_account", json!([origin.to_hex()]))?;
+        let tx = transfer(identity, &account, destination, amount, memo)?;
+        let encoded = hex::encode(bincode::serialize(&tx)?);
+        let tx_id = self.call("submit_transaction", json!([encoded]))?;
+        Ok(parse_hash(tx_id.as_str().unwrap_or_default())?.into())
+    }
+}
+
+/// Account owned by the key of `identity`
+pub fn account_id(identity: &Identity) -> AccountId {
+    AccountId::from(Hash::new(&identity.get_public_key().to_bytes()))
+}
+
+pub fn parse_hash(hex: &str) -> Result<Hash, NodeError> {
+    Hash::from_hex(hex).map_err(|e| NodeError::InvalidArgument(format!("{}: {}", hex, e)))
+}
+
+/// Transfer signed by `identity` from its account, as `get_account`
+/// returned it
+fn transfer(
+    identity: &Identity,
+    account: &Value,
+    destination: AccountId,
+    amount: Amount,
+    memo: Option<String>,
+) -> Result<Transaction, NodeError> {
+    let origin_id = account_id(identity);
+    if account.is_null() {
+        return Err(NodeError::InvalidArgument(format!(
+            "Account {} is unknown to the node",
+            origin_id.to_hex()
+        )));
+    }
+    let field = |name: &str| {
+        account[name]
+            .as_str()
+            .ok_or_else(|| NodeError::RpcError(format!("Account without {}", name)))
+    };
+    let last_tx_id = parse_hash(field("last_tx_id")?)?.into();
+    let balance = field("balance")?
+        .parse::<u128>()
+        .map_err(|e| NodeError::RpcError(e.to_string()))?;
+    let mut origin = Account::create(&origin_id, &last_tx_id);
+    let _ = origin
+        .increase_balance(Amount::new(balance))
+        .map_err(ConsensusError::from)?
+        .update_sequence(account["sequence"].as_u64().unwrap_or_default());
+
+    let mut tx = Transaction::new(
+        last_tx_id,
+        origin,
+        destination,
+        amount,
+        TransactionType::Transfer,
+        vec![],
+    );
+    if let Some(memo) = memo {
+        let _ = tx.set_memo(memo)?;
+    }
+    let _ = tx
+        .calculate_tx_id()?
+        .sign_and_set_signature(identity.get_private_key())?;
+    Ok(tx)
+}
+
+#[test]
+fn test_transfers_follow_the_account_of_the_node() {
+    let identity = Identity::new();
+    let destination = AccountId::from(Hash::new("destination".as_bytes()));
+    let last_tx_id = TxId::from(Hash::new("last".as_bytes()));
+    let account = json!({
+        "id": account_id(&identity).to_hex(),
+        "balance": "500",
+        "last_tx_id": last_tx_id.to_hex(),
+        "created": 0,
+        "sequence": 3,
+    });
+
+    let tx = transfer(
+        &identity,
+        &account,
+        destination,
+        Amount::new(20),
+        Some("rent".to_string()),
+    )
+    .unwrap();
+    assert_eq!(tx.origin, account_id(&identity));
+    assert_eq!(tx.parents(), [last_tx_id]);
+    assert_eq!(tx.sequence, 4);
+    assert_eq!(tx.memo(), Some("rent"));
+    assert!(tx.try_get_tx_id().is_some());
+    assert!(matches!(
+        transfer(&identity, &Value::Null, destination, Amount::new(20), None),
+        Err(NodeError::InvalidArgument(_))
+    ));
+}
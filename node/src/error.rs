@@ -0,0 +1,38 @@
+use consensus::{ConsensusError, TxId};
+use crypto::error::CryptoError;
+use p2p::error::{ConfigError, P2pError};
+use std::path::PathBuf;
+use storage::StorageError;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum NodeError {
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("JSON error: {0}")]
+    JsonError(#[from] serde_json::Error),
+    #[error("Bincode error: {0}")]
+    BincodeError(#[from] bincode::Error),
+    #[error("P2p error: {0}")]
+    P2pError(#[from] P2pError),
+    #[error("Invalid configuration: {0}")]
+    ConfigError(#[from] ConfigError),
+    #[error("Invalid genesis: {0}")]
+    GenesisError(#[from] consensus::ConfigError),
+    #[error("Consensus error: {0}")]
+    ConsensusError(#[from] ConsensusError),
+    #[error("Crypto error: {0}")]
+    CryptoError(#[from] CryptoError),
+    #[error("Storage error: {0}")]
+    StorageError(#[from] StorageError),
+    #[error("{0} holds a node already, pass --force to overwrite it")]
+    AlreadyInitialized(PathBuf),
+    #[error("{0} holds no node, run `dagchain-node init` first")]
+    NotInitialized(PathBuf),
+    #[error("Invalid argument: {0}")]
+    InvalidArgument(String),
+    #[error("RPC error: {0}")]
+    RpcError(String),
+    #[error("Applied transaction {0} is missing from the database")]
+    MissingTransaction(TxId),
+}
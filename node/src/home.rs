@@ -0,0 +1,194 @@
+//! Home directory of a node.
+//!
+//! Holds everything a node needs across restarts: its identity, whose key
+//! also owns the account funded at genesis, its settings, the genesis of
+//! its network and its database.
+
+use crate::error::NodeError;
+use consensus::genesis::Genesis;
+use crypto::hash::Hash;
+use crypto::signature::PublicKey;
+use p2p::node::identity::{Identity, PublicId};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+const IDENTITY_FILE: &str = "identity";
+const SETTINGS_FILE: &str = "node.json";
+const GENESIS_FILE: &str = "genesis.json";
+const DB_DIR: &str = "db";
+
+/// Settings of a node, edited by `init` and `peer add`
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct NodeSettings {
+    /// Port peers connect to
+    pub port: u16,
+    /// Where the JSON-RPC endpoint listens, for `tx send` among others
+    pub rpc_addr: SocketAddr,
+    /// Peers to bootstrap from
+    pub peers: BTreeSet<SocketAddr>,
+    /// Network the node takes part in, hex-encoded
+    pub network_id: String,
+    /// Run alone, finalizing our own transactions instantly
+    pub dev: bool,
+    /// Public key of the node whose benchmark runs we take part in,
+    /// hex-encoded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub benchmark_coordinator: Option<String>,
+    /// OTLP/HTTP receiver of the collector our traces and metrics are
+    /// exported to, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub otlp_endpoint: Option<SocketAddr>,
+}
+
+impl NodeSettings {
+    pub fn network_id(&self) -> Result<Hash, NodeError> {
+        Hash::from_hex(&self.network_id)
+            .map_err(|e| NodeError::InvalidArgument(format!("network ID: {}", e)))
+    }
+
+    pub fn benchmark_coordinator(&self) -> Result<Option<PublicId>, NodeError> {
+        let invalid =
+            |e: String| NodeError::InvalidArgument(format!("benchmark coordinator: {}", e));
+        self.benchmark_coordinator
+            .as_ref()
+            .map(|encoded| {
+                let bytes = hex::decode(encoded).map_err(|e| invalid(e.to_string()))?;
+                let public_key =
+                    PublicKey::from_bytes(&bytes).map_err(|e| invalid(e.to_string()))?;
+                Ok(PublicId { public_key })
+            })
+            .transpose()
+    }
+}
+
+/// Directory the files of a node live in
+#[derive(Clone, Debug)]
+pub struct Home {
+    dir: PathBuf,
+}
+
+impl Home {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn db_dir(&self) -> PathBuf {
+        self.dir.join(DB_DIR)
+    }
+
+    pub fn is_initialized(&self) -> bool {
+        self.dir.join(IDENTITY_FILE).exists()
+    }
+
+    /// Write the files of a new node, refusing to overwrite those of an
+    /// existing one unless `force` is set
+    pub fn init(
+        &self,
+        identity: &Identity,
+        settings: &NodeSettings,
+        genesis: &Genesis,
+        force: bool,
+    ) -> Result<(), NodeError> {
+        if self.is_initialized() && !force {
+            return Err(NodeError::AlreadyInitialized(self.dir.clone()));
+        }
+        fs::create_dir_all(&self.dir)?;
+        write_secret(&self.dir.join(IDENTITY_FILE), identity)?;
+        self.save_settings(settings)?;
+        fs::write(
+            self.dir.join(GENESIS_FILE),
+            serde_json::to_vec_pretty(genesis)?,
+        )?;
+        Ok(())
+    }
+
+    pub fn identity(&self) -> Result<Identity, NodeError> {
+        let encoded = fs::read_to_string(self.existing(IDENTITY_FILE)?)?;
+        Ok(Identity::decode(encoded.trim())?)
+    }
+
+    pub fn settings(&self) -> Result<NodeSettings, NodeError> {
+        let bytes = fs::read(self.existing(SETTINGS_FILE)?)?;
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+
+    pub fn save_settings(&self, settings: &NodeSettings) -> Result<(), NodeError> {
+        fs::write(
+            self.dir.join(SETTINGS_FILE),
+            serde_json::to_vec_pretty(settings)?,
+        )?;
+        Ok(())
+    }
+
+    pub fn genesis(&self) -> Result<Genesis, NodeError> {
+        Ok(Genesis::load(&self.existing(GENESIS_FILE)?)?)
+    }
+
+    fn existing(&self, file: &str) -> Result<PathBuf, NodeError> {
+        let path = self.dir.join(file);
+        if !path.exists() {
+            return Err(NodeError::NotInitialized(self.dir.clone()));
+        }
+        Ok(path)
+    }
+}
+
+/// Write the identity, private key included, readable by its owner only
+fn write_secret(path: &Path, identity: &Identity) -> Result<(), NodeError> {
+    let mut options = fs::OpenOptions::new();
+    let _ = options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        let _ = options.mode(0o600);
+    }
+    let encoded = identity.encode()?;
+    options
+        .open(path)?
+        .write_all(encoded.expose_secret().as_bytes())?;
+    Ok(())
+}
+
+#[test]
+fn test_home_keeps_the_node_files() {
+    let dir = std::env::temp_dir().join(format!("dagchain-home-{}", Hash::generate_random()));
+    let home = Home::new(&dir);
+    let identity = Identity::new();
+    let settings = NodeSettings {
+        port: 9000,
+        rpc_addr: "127.0.0.1:9100".parse().unwrap(),
+        peers: BTreeSet::new(),
+        network_id: Hash::default().to_hex(),
+        dev: true,
+        benchmark_coordinator: None,
+        otlp_endpoint: None,
+    };
+    let mut genesis = Genesis::new("dagchain-test");
+    let _ = genesis.fund(*identity.get_public_key(), consensus::Amount::new(100));
+
+    assert!(matches!(home.settings(), Err(NodeError::NotInitialized(_))));
+    home.init(&identity, &settings, &genesis, false).unwrap();
+    assert!(matches!(
+        home.init(&identity, &settings, &genesis, false),
+        Err(NodeError::AlreadyInitialized(_))
+    ));
+    assert_eq!(
+        home.identity().unwrap().get_our_hash().unwrap(),
+        identity.get_our_hash().unwrap()
+    );
+    assert_eq!(home.settings().unwrap(), settings);
+    assert_eq!(home.genesis().unwrap(), genesis);
+    assert_eq!(
+        home.settings().unwrap().network_id().unwrap(),
+        Hash::default()
+    );
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,267 @@
+//! Reference node of the DAGchain.
+//!
+//! Spins up a node from the command line, without writing any Rust:
+//!
+//! ```text
+//! dagchain-node --home a init --dev
+//! dagchain-node --home a run
+//! dagchain-node --home a tx send <account ID> 1000
+//! dagchain-node --home a tx status <transaction ID>
+//! ```
+//!
+//! Nodes joining an existing network are initialized from the genesis of
+//! its first node, and pointed to one of its peers:
+//!
+//! ```text
+//! dagchain-node --home b init --port 9001 --rpc-addr 127.0.0.1:9101 --genesis a/genesis.json
+//! dagchain-node --home b peer add 127.0.0.1:9000
+//! ```
+
+#![forbid(
+    arithmetic_overflow,
+    mutable_transmutes,
+    no_mangle_const_items,
+    unknown_crate_types
+)]
+#![warn(clippy::all)]
+
+mod client;
+mod error;
+mod home;
+mod node;
+mod rounds;
+
+use client::{account_id, parse_hash, RpcClient};
+use consensus::{amount::Amount, genesis::Genesis};
+use crypto::hash::Hash;
+use error::NodeError;
+use home::{Home, NodeSettings};
+use log::{LevelFilter, Log, Metadata, Record};
+use p2p::node::identity::Identity;
+use std::collections::BTreeSet;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "dagchain-node", about = "Reference node of the DAGchain")]
+struct Opt {
+    /// Directory holding the identity, settings and database of the node
+    #[structopt(long, default_value = ".dagchain", parse(from_os_str))]
+    home: PathBuf,
+    /// Most verbose level logged to stderr
+    #[structopt(long, default_value = "info")]
+    log_level: LevelFilter,
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Generate the identity and settings of a new node
+    Init {
+        /// Port peers connect to
+        #[structopt(long, default_value = "9000")]
+        port: u16,
+        /// Address the JSON-RPC endpoint listens at
+        #[structopt(long, default_value = "127.0.0.1:9100")]
+        rpc_addr: SocketAddr,
+        /// Genesis of the network to join, e.g. as written by `init` on its
+        /// first node. Without one, a new network is started funding our
+        /// account.
+        #[structopt(long, parse(from_os_str))]
+        genesis: Option<PathBuf>,
+        /// Name of the chain of a new network
+        #[structopt(long, default_value = "dagchain-dev")]
+        chain_id: String,
+        /// Balance of our account in a new network, in base units
+        #[structopt(long, default_value = "1000000000000")]
+        fund: u128,
+        /// Network to take part in, hex-encoded
+        #[structopt(
+            long,
+            default_value = "0000000000000000000000000000000000000000000000000000000000000000"
+        )]
+        network_id: String,
+        /// Run alone, finalizing our own transactions instantly
+        #[structopt(long)]
+        dev: bool,
+        /// Take part in the benchmark runs of the node with this public
+        /// key, hex-encoded
+        #[structopt(long)]
+        benchmark_coordinator: Option<String>,
+        /// Export traces and metrics to the OTLP/HTTP receiver of a
+        /// collector
+        #[structopt(long)]
+        otlp_endpoint: Option<SocketAddr>,
+        /// Overwrite the node already in the home directory
+        #[structopt(long)]
+        force: bool,
+    },
+    /// Run the node until interrupted
+    Run,
+    /// Print a new identity along with its account ID, without storing it
+    Keygen,
+    /// Manage the peers the node bootstraps from
+    Peer(PeerCommand),
+    /// Send transactions through the running node
+    Tx(TxCommand),
+}
+
+#[derive(Debug, StructOpt)]
+enum PeerCommand {
+    /// Bootstrap from the peer at `addr` on the next run
+    Add { addr: SocketAddr },
+}
+
+#[derive(Debug, StructOpt)]
+enum TxCommand {
+    /// Transfer `amount` base units from our account to `destination`
+    Send {
+        /// Hex-encoded account ID
+        destination: String,
+        amount: u128,
+        #[structopt(long)]
+        memo: Option<String>,
+    },
+    /// Print the receipt of a transaction submitted to the running node
+    Status {
+        /// Hex-encoded transaction ID
+        tx_id: String,
+    },
+}
+
+/// Writes log records to stderr
+struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!(
+                "[{}] {}: {}",
+                record.level(),
+                record.target(),
+                record.args()
+            );
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
+
+fn main() {
+    let opt = Opt::from_args();
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(opt.log_level);
+    }
+    if let Err(e) = execute(opt) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn execute(opt: Opt) -> Result<(), NodeError> {
+    let home = Home::new(opt.home);
+    match opt.command {
+        Command::Init {
+            port,
+            rpc_addr,
+            genesis,
+            chain_id,
+            fund,
+            network_id,
+            dev,
+            benchmark_coordinator,
+            otlp_endpoint,
+            force,
+        } => {
+            let identity = Identity::new();
+            let genesis = match genesis {
+                Some(path) => Genesis::load(&path)?,
+                None => {
+                    let mut genesis = Genesis::new(chain_id);
+                    let _ = genesis.fund(*identity.get_public_key(), Amount::new(fund));
+                    genesis
+                }
+            };
+            let settings = NodeSettings {
+                port,
+                rpc_addr,
+                peers: BTreeSet::new(),
+                network_id: parse_hash(&network_id)?.to_hex(),
+                dev,
+                benchmark_coordinator,
+                otlp_endpoint,
+            };
+            let _ = settings.benchmark_coordinator()?;
+            home.init(&identity, &settings, &genesis, force)?;
+            println!(
+                "Initialized node {} in {:?}",
+                identity.get_our_hash()?,
+                home.dir()
+            );
+            println!("Account: {}", account_id(&identity).to_hex());
+            println!(
+                "Public key: {}",
+                hex::encode(identity.get_public_key().to_bytes())
+            );
+            println!("Chain: {} ({})", genesis.chain_id, genesis.chain_hash()?);
+        }
+        Command::Run => node::run(&home)?,
+        Command::Keygen => {
+            let identity = Identity::new();
+            println!("Node: {}", identity.get_our_hash()?);
+            println!("Account: {}", account_id(&identity).to_hex());
+            println!("Identity: {}", identity.encode()?.expose_secret());
+        }
+        Command::Peer(PeerCommand::Add { addr }) => {
+            let mut settings = home.settings()?;
+            if settings.dev {
+                return Err(NodeError::InvalidArgument(
+                    "Development nodes run alone".to_string(),
+                ));
+            }
+            if settings.peers.insert(addr) {
+                home.save_settings(&settings)?;
+            }
+            println!("Bootstrapping from {:?}", settings.peers);
+        }
+        Command::Tx(TxCommand::Send {
+            destination,
+            amount,
+            memo,
+        }) => {
+            let identity = home.identity()?;
+            let settings = home.settings()?;
+            let destination = Hash::from_hex(&destination)
+                .map_err(|e| NodeError::InvalidArgument(format!("destination: {}", e)))?;
+            let tx_id = RpcClient::new(settings.rpc_addr).transfer(
+                &identity,
+                destination.into(),
+                Amount::new(amount),
+                memo,
+            )?;
+            println!("Submitted {}", tx_id);
+        }
+        Command::Tx(TxCommand::Status { tx_id }) => {
+            let settings = home.settings()?;
+            let tx_id = parse_hash(&tx_id)?;
+            let receipt = RpcClient::new(settings.rpc_addr)
+                .call("get_receipt", serde_json::json!([tx_id.to_hex()]))?;
+            if receipt.is_null() {
+                return Err(NodeError::InvalidArgument(format!(
+                    "Transaction {} is unknown to the node",
+                    tx_id
+                )));
+            }
+            println!("{}", serde_json::to_string_pretty(&receipt)?);
+        }
+    }
+    Ok(())
+}
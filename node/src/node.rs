@@ -0,0 +1,1311 @@
+//! The node started by `dagchain-node run`.
+//!
+//! Wires the p2p layer, the state and storage together: peers are greeted
+//! and identified through [`Connection`], transactions submitted over
+//! JSON-RPC are stored and gossiped to the network, and those gossiped by
+//! peers are stored in turn. Development nodes finalize their transactions
+//! instantly and apply them to the state, which is rebuilt at startup by
+//! applying the transactions applied before again. Networked nodes settle
+//! them through consensus [`Rounds`] with sampled peers, answering the
+//! polls of their peers in turn. Validators sign the finality of the
+//! transactions they accept and gossip their signature, which every node
+//! merges into the [`FinalityCertificate`] it stores and serves.
+
+use crate::{error::NodeError, home::Home, rounds::Rounds};
+use consensus::{
+    account::Account,
+    dag::Dag,
+    data::{DataEntry, DataStore, StoredData},
+    executor::{self, KvExecutor},
+    finality::{FinalityCertificate, FinalityStore},
+    genesis::Genesis,
+    receipt::{Receipt, ReceiptStore},
+    reconcile::{Quorum, StakeTable},
+    scheduler::{Application, ApplyScheduler},
+    submission::SubmissionWindow,
+    transaction::{Transaction, TransactionStatus, TransactionType},
+    AccountId, ConsensusStatus, NodeId, TxId,
+};
+use crossbeam_channel::{Receiver, Sender};
+use crypto::{cache::VerificationCache, hash::Hash, signature::PrivateKey};
+use metrics::{Metrics, MetricsSnapshot};
+use p2p::error::P2pError;
+use p2p::node::{
+    benchmark::BenchmarkParticipant,
+    builder::NodeConfig,
+    capacity::CapacityAdvertisement,
+    config::TelemetryConfig,
+    connection::Connection,
+    event::Event,
+    identity::Identity,
+    message::Message,
+    messaging::Messaging,
+    rpc::{RpcError, RpcHandler, RpcServer, INVALID_PARAMS, SERVER_ERROR},
+    telemetry::{OtlpCollector, OtlpExporter},
+};
+use p2p::transport::{self, Transport};
+use quic_p2p::{Config as QuicConfig, Event as QuicEvent, EventSenders, Peer};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use storage::{sled::SledStorage, Storage, TypedStore};
+
+/// Prefix of the transactions we gossip, telling them from other gossip
+const TX_GOSSIP_DOMAIN: &[u8] = b"dagchain:tx";
+/// Prefix of the finality certificates we gossip
+const FINALITY_GOSSIP_DOMAIN: &[u8] = b"dagchain:finality";
+/// Stake a certificate must carry for its transaction to count as final
+const FINALITY_QUORUM: Quorum = Quorum::TWO_THIRDS;
+/// Schema version of the stored transactions
+const TX_STORE_VERSION: u32 = 1;
+/// Keyspace of the IDs of the applied transactions, by the order they were
+/// applied in
+const APPLIED_TREE: &str = "applied";
+/// Schema version of the IDs of the applied transactions
+const APPLIED_VERSION: u32 = 1;
+/// Longest the event loop waits before its periodic work
+const TICK: Duration = Duration::from_millis(100);
+/// Interval between measurements of the size of the database
+const STORAGE_REPORT_INTERVAL: Duration = Duration::from_secs(10);
+/// Interval between exports of traces and metrics to the collector
+const TELEMETRY_EXPORT_INTERVAL: Duration = Duration::from_secs(15);
+/// Name the node reports its traces and metrics under
+const SERVICE_NAME: &str = "dagchain-node";
+
+/// Benchmark run we take part in
+struct Benchmark {
+    participant: BenchmarkParticipant,
+    /// Account state our last benchmark transaction spent, which the
+    /// conflicting ones spend again
+    last_origin: Option<Account>,
+    /// Control messages waiting to be sent to the coordinator
+    outgoing: Vec<Message>,
+}
+
+/// State shared by the event loop and the RPC endpoint
+struct NodeState {
+    scheduler: ApplyScheduler,
+    /// Database every store below keeps its keyspace in
+    storage: SledStorage,
+    transactions: TypedStore<SledStorage, Transaction>,
+    /// IDs of the applied transactions, replayed at startup to rebuild the
+    /// state from genesis
+    applied: TypedStore<SledStorage, TxId>,
+    /// Transactions applied so far, the index of the next one in `applied`
+    applied_count: u64,
+    receipts: ReceiptStore<SledStorage>,
+    /// Entries written by the applied `StoreData` transactions
+    data: DataStore<SledStorage>,
+    /// Application state the accepted `Execute` transactions run against
+    app: executor::Application,
+    /// Certificates of the accepted transactions, ours or gossiped
+    finality: FinalityStore<SledStorage>,
+    /// Validators whose signatures certify finality, those registered in
+    /// the state
+    stake: StakeTable,
+    /// Key we sign certificates with, if we are a validator
+    private_key: PrivateKey,
+    /// Signatures verified so far, shared with the scheduler so that
+    /// transactions verified on receipt aren't verified again once applied
+    verification: VerificationCache,
+    /// Accepted transactions, linked to their parents and children
+    dag: Dag,
+    peers: Vec<NodeId>,
+    dev: bool,
+    /// Transactions submitted over RPC, waiting to be gossiped
+    outgoing: Vec<Transaction>,
+    /// Certificates we signed, waiting to be gossiped
+    outgoing_certificates: Vec<FinalityCertificate>,
+    /// Round that accepted each transaction, until it is applied and its
+    /// receipt names the round
+    accepted_rounds: HashMap<TxId, u64>,
+    /// Benchmark runs we take part in, if a coordinator is configured
+    benchmark: Option<Benchmark>,
+    /// Consensus rounds on the transactions we learn of, unless we are a
+    /// development node
+    rounds: Option<Rounds>,
+    /// Shared with the p2p layer, served on `GET /metrics`
+    metrics: Arc<Metrics>,
+}
+
+impl NodeState {
+    /// Open the stores of `storage`, rebuilding the state from `genesis`
+    /// and the transactions applied before we last stopped
+    fn open(
+        storage: SledStorage,
+        genesis: &Genesis,
+        private_key: PrivateKey,
+        dev: bool,
+        metrics: Arc<Metrics>,
+    ) -> Result<Self, NodeError> {
+        let verification = VerificationCache::default();
+        let mut scheduler = ApplyScheduler::new(genesis.state()?);
+        let _ = scheduler.set_verification_cache(verification.clone());
+        let mut state = Self {
+            scheduler,
+            transactions: TypedStore::open(&storage, "transactions", TX_STORE_VERSION)?,
+            applied: TypedStore::open(&storage, APPLIED_TREE, APPLIED_VERSION)?,
+            applied_count: 0,
+            receipts: ReceiptStore::open(&storage)?,
+            data: DataStore::open(&storage)?,
+            app: executor::Application::new(Box::new(KvExecutor)),
+            finality: FinalityStore::open(&storage)?,
+            storage,
+            stake: genesis.stake_table(),
+            private_key,
+            verification,
+            dag: Dag::new(),
+            peers: vec![],
+            dev,
+            outgoing: vec![],
+            outgoing_certificates: vec![],
+            accepted_rounds: HashMap::new(),
+            benchmark: None,
+            rounds: None,
+            metrics,
+        };
+        let replayed = state.replay()?;
+        if replayed > 0 {
+            log::info!("Replayed {} applied transactions", replayed);
+        }
+        state.stake = state.scheduler.state().stake_table();
+        Ok(state)
+    }
+
+    /// Apply the transactions applied before we last stopped again, in the
+    /// order they were, returning how many were. Their statuses, receipts
+    /// and certificates are stored already.
+    fn replay(&mut self) -> Result<u64, NodeError> {
+        while let Some(tx_id) = self.applied.get(&applied_key(self.applied_count))? {
+            let tx = self
+                .transactions
+                .get(tx_id.as_hash())?
+                .ok_or(NodeError::MissingTransaction(tx_id))?;
+            for application in self.scheduler.submit(tx.clone())? {
+                if let Application::Failed { tx_id, error } = application {
+                    log::warn!("Replaying {} failed: {}", tx_id, error);
+                }
+            }
+            if let Err(e) = self.app.on_accepted(&tx) {
+                log::warn!("Executing {} failed: {}", tx_id, e);
+            }
+            let _ = self.dag.add_vertex(&tx)?;
+            self.applied_count += 1;
+        }
+        Ok(self.applied_count)
+    }
+
+    /// Apply a finalized transaction to the state once its dependencies
+    /// are, settling the transactions applied or refused as a result
+    fn apply(&mut self, tx: Transaction) -> Result<(), NodeError> {
+        for application in self.scheduler.submit(tx)? {
+            let (tx_id, status) = match application {
+                Application::Applied(tx_id) => {
+                    self.applied.put(&applied_key(self.applied_count), &tx_id)?;
+                    self.applied_count += 1;
+                    (tx_id, TransactionStatus::Accepted)
+                }
+                Application::Failed { tx_id, .. } => (tx_id, TransactionStatus::Rejected),
+                Application::AccountCreated { account_id, .. } => {
+                    log::info!("Created account {}", account_id);
+                    continue;
+                }
+            };
+            let round = self.accepted_rounds.remove(&tx_id);
+            self.record_status(&tx_id, status, round)?;
+        }
+        self.applied.flush()?;
+        Ok(())
+    }
+
+    /// Take a transaction submitted to us or gossiped by a peer, returning
+    /// false if we knew it already. Its ID is calculated again, and the
+    /// transaction refused if it claims another one.
+    fn accept(&mut self, mut tx: Transaction) -> Result<bool, NodeError> {
+        let tx_id = tx.verify_tx_id()?;
+        if self.transactions.contains(tx_id.as_hash())? {
+            return Ok(false);
+        }
+        let _ = tx.set_tx_status(TransactionStatus::Pending);
+        self.transactions.put(tx_id.as_hash(), &tx)?;
+        self.receipts.record(&Receipt::pending(tx_id))?;
+        if self.dev {
+            self.apply(tx)?;
+        } else if let Some(rounds) = &mut self.rounds {
+            rounds.poll(&tx, Instant::now());
+        }
+        self.transactions.flush()?;
+        self.receipts.flush()?;
+        self.data.flush()?;
+        self.finality.flush()?;
+        Ok(true)
+    }
+
+    /// Take the transactions gossiped by peers, verifying their signatures
+    /// in one batch. Those not signed by their origin, or claiming another
+    /// ID than theirs, are dropped.
+    fn accept_gossiped(&mut self, mut txs: Vec<Transaction>) -> Result<(), NodeError> {
+        txs.retain_mut(|tx| match tx.verify_tx_id() {
+            Ok(_) => true,
+            Err(e) => {
+                log::warn!("Dropped gossiped transaction: {}", e);
+                false
+            }
+        });
+        let verified = self
+            .scheduler
+            .state()
+            .verify_signatures(&txs.iter().collect::<Vec<_>>(), &self.verification);
+        for (tx, verified) in txs.into_iter().zip(verified) {
+            match verified {
+                Ok(()) => {
+                    let _ = self.accept(tx)?;
+                }
+                Err(e) => log::warn!("Dropped gossiped transaction: {}", e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Answer the poll of a peer on a transaction, taking it if it is new
+    /// to us. Transactions not signed by their origin are never preferred.
+    fn answer(&mut self, sender: NodeId, mut tx: Transaction) -> Result<(), NodeError> {
+        if let Err(e) = tx.verify_tx_id() {
+            log::warn!("Poll from {} dropped: {}", sender, e);
+            return Ok(());
+        }
+        let valid = self
+            .scheduler
+            .state()
+            .verify_signature(&tx, &self.verification)
+            .is_ok();
+        if valid {
+            let _ = self.accept(tx.clone())?;
+        }
+        if let Some(rounds) = &mut self.rounds {
+            rounds.answer(sender, &tx, valid);
+        }
+        Ok(())
+    }
+
+    /// Resolve the consensus rounds over at `now`, applying the
+    /// transactions accepted. Returns the queries and answers to send.
+    fn run_rounds(&mut self, now: Instant) -> Result<Vec<(NodeId, Message)>, NodeError> {
+        let Some(rounds) = &mut self.rounds else {
+            return Ok(vec![]);
+        };
+        let settled = rounds.resolve(now);
+        let outgoing = rounds.take_outgoing();
+        for settled in settled {
+            let tx_id = settled.tx_id;
+            match settled.status {
+                TransactionStatus::Accepted => {
+                    let tx = self
+                        .transactions
+                        .get(tx_id.as_hash())?
+                        .ok_or(NodeError::MissingTransaction(tx_id))?;
+                    let _ = self.accepted_rounds.insert(tx_id, settled.round);
+                    self.apply(tx)?;
+                }
+                status => self.record_status(&tx_id, status, Some(settled.round))?,
+            }
+        }
+        self.transactions.flush()?;
+        self.receipts.flush()?;
+        self.data.flush()?;
+        self.finality.flush()?;
+        Ok(outgoing)
+    }
+
+    /// Settle a transaction in `round`. Development nodes settle theirs
+    /// without rounds, so their receipts name no round. Validators
+    /// registering or leaving change the stake certifying finality.
+    fn record_status(
+        &mut self,
+        tx_id: &TxId,
+        status: TransactionStatus,
+        round: Option<u64>,
+    ) -> Result<(), NodeError> {
+        if let Some(mut tx) = self.transactions.get(tx_id.as_hash())? {
+            let _ = tx.set_tx_status(status.clone());
+            self.transactions.put(tx_id.as_hash(), &tx)?;
+            if status == TransactionStatus::Accepted && tx.tx_type == TransactionType::StoreData {
+                let state = self.scheduler.state();
+                let key = DataEntry::from_payload(&tx.payload)?.key;
+                if let Some(data) = state.data().get(&tx.origin, &key) {
+                    self.data.record(data)?;
+                }
+            }
+            let validator_tx = matches!(
+                tx.tx_type,
+                TransactionType::RegisterValidator | TransactionType::UnregisterValidator
+            );
+            if status == TransactionStatus::Accepted && validator_tx {
+                self.stake = self.scheduler.state().stake_table();
+            }
+            if status == TransactionStatus::Accepted {
+                if let Err(e) = self.app.on_accepted(&tx) {
+                    log::warn!("Executing {} failed: {}", tx_id, e);
+                }
+                self.certify(tx_id)?;
+            }
+            if let Some(benchmark) = &mut self.benchmark {
+                let accepted = status == TransactionStatus::Accepted;
+                benchmark
+                    .participant
+                    .finalized(tx_id, accepted, unix_time());
+            }
+            if status == TransactionStatus::Accepted && self.dag.add_vertex(&tx)? {
+                self.update_children(tx_id)?;
+                for parent in self.dag.parents_of(tx_id).unwrap_or_default().to_vec() {
+                    self.update_children(&parent)?;
+                }
+            }
+            let _ = self
+                .receipts
+                .settle(tx_id, status, round, tx.get_aggregate_sig())?;
+        }
+        Ok(())
+    }
+
+    /// Take a transaction submitted over RPC, queueing it for gossip, and
+    /// tell how far consensus got with it
+    fn submit(&mut self, tx: Transaction) -> Result<ConsensusStatus, NodeError> {
+        let tx_id = tx.get_tx_id();
+        if self.accept(tx.clone())? && !self.dev {
+            self.outgoing.push(tx);
+        }
+        let status = self.transactions.get(tx_id.as_hash())?.map(|tx| tx.status);
+        Ok(match status {
+            Some(TransactionStatus::Accepted) => ConsensusStatus::Accept(tx_id),
+            Some(TransactionStatus::Rejected) => ConsensusStatus::Reject,
+            _ => ConsensusStatus::InProgress,
+        })
+    }
+
+    /// Submit the benchmark transactions due at UNIX time `now`, and
+    /// report the run once it is over
+    fn run_benchmark(&mut self, now: Duration) -> Result<(), NodeError> {
+        let due = match &self.benchmark {
+            Some(benchmark) => benchmark.participant.due(now),
+            None => return Ok(()),
+        };
+        let our_id = AccountId::from(Hash::new(&self.private_key.public_key().to_bytes()));
+        for _ in 0..due {
+            let benchmark = self.benchmark.as_mut().unwrap();
+            let origin = match &benchmark.last_origin {
+                Some(origin) if benchmark.participant.next_conflicts() => origin.clone(),
+                _ => match self.scheduler.state().get(&our_id) {
+                    Some(origin) => origin.clone(),
+                    None => {
+                        log::warn!("Our account doesn't exist, benchmark transactions skipped");
+                        break;
+                    }
+                },
+            };
+            let mut tx = Transaction::new(
+                origin.last_tx_id,
+                origin.clone(),
+                Hash::generate_random().into(),
+                consensus::Amount::new(1),
+                TransactionType::Transfer,
+                vec![],
+            );
+            let _ = tx
+                .calculate_tx_id()?
+                .sign_and_set_signature(&self.private_key)?;
+            benchmark.last_origin = Some(origin);
+            benchmark.participant.submitted(tx.get_tx_id(), now);
+            let _ = self.submit(tx)?;
+        }
+        if let Some(benchmark) = &mut self.benchmark {
+            if let Some(report) = benchmark.participant.report(now)? {
+                benchmark.outgoing.push(report);
+            }
+        }
+        Ok(())
+    }
+
+    /// Sign the finality of a transaction we accepted, if we are a
+    /// validator, and gossip our signature. It is merged into the
+    /// certificate of the transaction along with those of the validators
+    /// who accepted it too.
+    fn certify(&mut self, tx_id: &TxId) -> Result<(), NodeError> {
+        let our_id = Hash::new(&self.private_key.public_key().to_bytes());
+        if self.stake.get(&our_id).is_none() {
+            return Ok(());
+        }
+        let mut certificate = FinalityCertificate::new(*tx_id);
+        let _ = certificate.sign(&self.stake, &self.private_key)?;
+        self.outgoing_certificates.push(certificate.clone());
+        self.receive_certificate(certificate)
+    }
+
+    /// Merge a certificate, ours or gossiped by a validator, into the one
+    /// we hold for its transaction. Invalid certificates are dropped.
+    fn receive_certificate(&mut self, certificate: FinalityCertificate) -> Result<(), NodeError> {
+        let tx_id = certificate.tx_id;
+        let known = self.finality.get(&tx_id)?;
+        let final_before = known
+            .as_ref()
+            .is_some_and(|known| known.verify(&self.stake, FINALITY_QUORUM).is_ok());
+        let mut merged = known.unwrap_or_else(|| FinalityCertificate::new(tx_id));
+        if let Err(e) = merged.merge(&self.stake, &certificate) {
+            log::debug!("Dropped a finality certificate of {}: {}", tx_id, e);
+            return Ok(());
+        }
+        if let (false, Ok(certified)) = (final_before, merged.verify(&self.stake, FINALITY_QUORUM))
+        {
+            log::info!("Finality of {} certified by {} stake", tx_id, certified);
+        }
+        self.finality.record(&merged)?;
+        self.finality.flush()?;
+        Ok(())
+    }
+
+    /// Report the size of the database to the metrics
+    fn report_storage(&self) {
+        match self.storage.size_on_disk() {
+            Ok(bytes) => self.metrics.set_storage_bytes(bytes),
+            Err(e) => log::warn!("Could not measure the database: {}", e),
+        }
+    }
+
+    /// Store the children the DAG knows of along with a transaction
+    fn update_children(&mut self, tx_id: &TxId) -> Result<(), NodeError> {
+        if let Some(mut tx) = self.transactions.get(tx_id.as_hash())? {
+            let _ = tx.set_children(self.dag.children_of(tx_id));
+            self.transactions.put(tx_id.as_hash(), &tx)?;
+        }
+        Ok(())
+    }
+
+    /// Fate of a transaction submitted to us or gossiped by a peer
+    fn get_receipt(&self, tx_id: &TxId) -> Result<Option<Receipt>, NodeError> {
+        Ok(self.receipts.get(tx_id)?)
+    }
+
+    /// Entry an account stored under `key`
+    fn get_data(&self, account_id: &AccountId, key: &str) -> Result<Option<StoredData>, NodeError> {
+        Ok(self.data.get(account_id, key)?)
+    }
+
+    fn get_finality_certificate(
+        &self,
+        tx_id: &TxId,
+    ) -> Result<Option<FinalityCertificate>, NodeError> {
+        Ok(self.finality.get(tx_id)?)
+    }
+}
+
+/// Serves the RPC methods from the state of the node
+struct Handler {
+    state: Arc<Mutex<NodeState>>,
+    /// Submissions of the same transaction retried by clients attach to
+    /// the first one
+    window: SubmissionWindow,
+}
+
+impl RpcHandler for Handler {
+    fn submit_transaction(&self, mut tx: Transaction) -> Result<TxId, RpcError> {
+        let server_error = |e: NodeError| RpcError::new(SERVER_ERROR, e.to_string());
+        let tx_id = tx
+            .verify_tx_id()
+            .map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))?;
+        // Refused before it is gossiped or settled, as the state would
+        // refuse it once finalized
+        {
+            let state = self.state.lock().unwrap();
+            state
+                .scheduler
+                .state()
+                .verify_signature(&tx, &state.verification)
+                .and_then(|_| state.scheduler.state().check_funds(&tx))
+                .map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))?;
+        }
+        let _ = self
+            .window
+            .try_submit(tx_id, || self.state.lock().unwrap().submit(tx))
+            .map_err(server_error)?;
+        Ok(tx_id)
+    }
+
+    fn get_account(&self, account_id: &AccountId) -> Option<Account> {
+        let state = self.state.lock().unwrap();
+        state.scheduler.state().get(account_id).cloned()
+    }
+
+    fn get_peers(&self) -> Vec<NodeId> {
+        self.state.lock().unwrap().peers.clone()
+    }
+
+    fn get_transaction_status(&self, tx_id: &TxId) -> Option<TransactionStatus> {
+        self.get_transaction(tx_id).map(|tx| tx.status)
+    }
+
+    fn get_transaction(&self, tx_id: &TxId) -> Option<Transaction> {
+        let state = self.state.lock().unwrap();
+        state.transactions.get(tx_id.as_hash()).ok().flatten()
+    }
+
+    fn get_receipt(&self, tx_id: &TxId) -> Option<Receipt> {
+        let state = self.state.lock().unwrap();
+        state.get_receipt(tx_id).ok().flatten()
+    }
+
+    fn get_data(&self, account_id: &AccountId, key: &str) -> Option<StoredData> {
+        let state = self.state.lock().unwrap();
+        state.get_data(account_id, key).ok().flatten()
+    }
+
+    fn get_finality_certificate(&self, tx_id: &TxId) -> Option<FinalityCertificate> {
+        let state = self.state.lock().unwrap();
+        state.get_finality_certificate(tx_id).ok().flatten()
+    }
+
+    fn metrics(&self) -> Option<MetricsSnapshot> {
+        Some(self.state.lock().unwrap().metrics.snapshot())
+    }
+}
+
+/// Networking half of the node, driven by the events of the transport
+struct Network {
+    identity: Identity,
+    our_hash: NodeId,
+    connection: Connection,
+    messaging: Messaging,
+    transport: Box<dyn Transport>,
+    node_tx: Sender<Event>,
+    /// Our signed capacity, advertised to the peers we connect to
+    capacity: CapacityAdvertisement,
+}
+
+impl Network {
+    fn handle(&mut self, event: QuicEvent) -> Result<(), NodeError> {
+        let transport = self.transport.as_mut();
+        match event {
+            QuicEvent::ConnectedTo { peer } => self
+                .connection
+                .handle_successful_connection(&peer, transport)?,
+            QuicEvent::NewMessage { peer, msg } => {
+                let peer_addr = peer.peer_addr();
+                self.connection
+                    .check_rate(&peer_addr, msg.len(), transport)?;
+                let message = self.connection.decode_message(&peer_addr, &msg)?;
+                self.dispatch(&peer, message)?
+            }
+            QuicEvent::ConnectionFailure { peer, err } => self
+                .connection
+                .handle_connection_failure(peer, err, &self.node_tx, transport, &self.our_hash)?,
+            QuicEvent::SentUserMessage { token, .. } => self.messaging.handle_sent_message(token),
+            QuicEvent::UnsentUserMessage { peer, msg, token } => self
+                .messaging
+                .handle_unsent_message(msg, token, peer.peer_addr())?,
+            QuicEvent::BootstrapFailure => log::warn!("Could not bootstrap to any peer"),
+            QuicEvent::BootstrappedTo { node } => log::debug!("Bootstrapped to {:?}", node),
+            QuicEvent::Finish => {}
+        }
+        Ok(())
+    }
+
+    fn dispatch(&mut self, peer: &Peer, message: Message) -> Result<(), NodeError> {
+        let transport = self.transport.as_mut();
+        match message {
+            Message::Hello {
+                protocol_version,
+                features,
+                chain_id,
+                challenge,
+                exchange_key,
+            } => self.connection.handle_hello(
+                &self.identity,
+                peer,
+                protocol_version,
+                features,
+                chain_id,
+                challenge,
+                &exchange_key,
+                &self.node_tx,
+                transport,
+            )?,
+            Message::Identification {
+                public_id,
+                network_id,
+                protocol,
+                tx_versions,
+                signature,
+            } => self.connection.handle_peer_identification(
+                self.our_hash,
+                peer,
+                &public_id,
+                network_id,
+                protocol,
+                tx_versions,
+                &signature,
+                &self.node_tx,
+                transport,
+            )?,
+            Message::RoutingTable {
+                routing_table,
+                source,
+            } => self.connection.update_routing_table(
+                routing_table,
+                source,
+                transport,
+                &self.our_hash,
+            ),
+            Message::AgentMessage { payload } => self.messaging.handle_agent_message(
+                &self.identity,
+                peer,
+                payload,
+                self.connection.get_active_connections(),
+                transport,
+                &self.node_tx,
+                self.connection.our_routing_table(),
+            ),
+            Message::Fragment(fragment) => {
+                if let Some(message) = self
+                    .messaging
+                    .handle_fragment(peer, fragment)
+                    .map_err(P2pError::from)?
+                {
+                    self.dispatch(peer, message)?;
+                }
+            }
+            Message::ObservedAddress(addr) => self.connection.handle_observed_address(peer, addr),
+            Message::PunchRequest { target } => self
+                .connection
+                .handle_punch_request(peer, target, transport),
+            Message::Punch { peer: target, addr } => {
+                self.connection.handle_punch(peer, target, addr, transport)
+            }
+            Message::CapacityAdvertisement(advertisement) => {
+                let _ = self
+                    .connection
+                    .handle_capacity_advertisement(peer, advertisement)?;
+            }
+            Message::Disconnecting => self.connection.handle_peer_disconnecting(
+                peer,
+                &self.node_tx,
+                transport,
+                &self.our_hash,
+            )?,
+            message => log::debug!(
+                "Ignoring {:?} from {:?}, not handled by this node",
+                message,
+                peer.peer_addr()
+            ),
+        }
+        Ok(())
+    }
+
+    /// Gossip transactions and finality certificates to the network
+    fn gossip(
+        &mut self,
+        txs: Vec<Transaction>,
+        certificates: Vec<FinalityCertificate>,
+    ) -> Result<(), NodeError> {
+        let routing_table = self.connection.our_routing_table();
+        for tx in txs {
+            let mut content = TX_GOSSIP_DOMAIN.to_vec();
+            content.extend(bincode::serialize(&tx)?);
+            let _ = self.messaging.gossip(&content, &routing_table);
+        }
+        for certificate in certificates {
+            let mut content = FINALITY_GOSSIP_DOMAIN.to_vec();
+            content.extend(bincode::serialize(&certificate)?);
+            let _ = self.messaging.gossip(&content, &routing_table);
+        }
+        Ok(())
+    }
+
+    /// Send a message to a peer, through the routes we know
+    fn send(&mut self, target: NodeId, message: Message) -> Result<(), NodeError> {
+        let routing_table = self.connection.our_routing_table();
+        self.messaging.push_to_outbox(
+            &self.our_hash,
+            target,
+            message,
+            &routing_table,
+            self.connection.get_active_connections(),
+            self.transport.as_mut(),
+        )?;
+        Ok(())
+    }
+
+    /// Work due every tick: retries, expiries, fragments of dropped peers
+    /// and queued messages
+    fn tick(&mut self) {
+        for peer in self.connection.expire_hole_punches() {
+            log::debug!("Hole punching to {} failed, relaying instead", peer);
+        }
+        for peer in self.connection.take_dropped() {
+            self.messaging.forget_fragments(&peer);
+        }
+        let transport = self.transport.as_mut();
+        let active_connections = self.connection.get_active_connections();
+        self.messaging.expire_sends();
+        self.messaging.expire_fragments();
+        self.messaging
+            .flush_piggybacked(active_connections, transport);
+        let _ = self.messaging.drain_outbox(active_connections, transport);
+    }
+}
+
+/// Run the node of `home` until its transport stops
+pub fn run(home: &Home) -> Result<(), NodeError> {
+    let identity = home.identity()?;
+    let settings = home.settings()?;
+    let genesis = home.genesis()?;
+    let quic = QuicConfig {
+        port: Some(settings.port),
+        ..Default::default()
+    };
+    let network_id = settings.network_id()?;
+    let mut builder = NodeConfig::builder()
+        .consensus(|_| genesis.consensus().clone().into())
+        .genesis(genesis.allocation())
+        .p2p(|p2p| {
+            p2p.bootstrap_nodes(settings.peers.iter().copied())
+                .quic(quic)
+                .rpc_addr(settings.rpc_addr)
+                .network_id(network_id)
+                .telemetry(TelemetryConfig::new(settings.otlp_endpoint, SERVICE_NAME))
+        });
+    if settings.dev {
+        builder = builder.dev();
+    }
+    let config = builder.build()?;
+
+    let metrics = Arc::new(Metrics::new());
+    let mut node_state = NodeState::open(
+        SledStorage::new(Some(&home.db_dir()))?,
+        &genesis,
+        identity.get_private_key().clone(),
+        settings.dev,
+        metrics.clone(),
+    )?;
+    if let Some(coordinator) = settings.benchmark_coordinator()? {
+        node_state.benchmark = Some(Benchmark {
+            participant: BenchmarkParticipant::new(identity.clone(), network_id, &coordinator)?,
+            last_origin: None,
+            outgoing: vec![],
+        });
+    }
+    let verification = node_state.verification.clone();
+    let state = Arc::new(Mutex::new(node_state));
+    let rpc = RpcServer::start(
+        settings.rpc_addr,
+        Arc::new(Handler {
+            state: state.clone(),
+            window: SubmissionWindow::from_config(genesis.consensus()),
+        }),
+    )?;
+    log::info!("Serving JSON-RPC on {}", rpc.local_addr());
+
+    let (quic_tx, quic_rx) = quic_channel::unbounded();
+    let (client_tx, _client_rx) = quic_channel::unbounded();
+    let mut transport = transport::open(
+        config.p2p(),
+        EventSenders {
+            node_tx: quic_tx,
+            client_tx,
+        },
+    )?;
+    log::info!("Listening for peers on {}", transport.our_addr()?);
+
+    let diversity = config.p2p().get_diversity_config();
+    let mut connection = Connection::new();
+    let _ = connection
+        .set_network_id(network_id)
+        .set_chain_id(genesis.chain_hash()?)
+        .set_max_message_size(config.p2p().get_max_message_size())
+        .set_max_connections_per_subnet(diversity.max_connections_per_subnet())
+        .set_rate_limit_config(config.p2p().get_rate_limit_config())
+        .set_routing_config(config.p2p().get_routing_config())
+        .set_nat_config(config.p2p().get_nat_config())
+        .set_metrics(metrics.clone());
+    connection.bootstrap(settings.peers.iter().copied().collect(), transport.as_mut());
+    let (node_tx, node_rx) = crossbeam_channel::unbounded();
+    let mut messaging = Messaging::new();
+    let _ = messaging
+        .set_network_id(network_id)
+        .set_hop_limits(config.p2p().get_hop_limits().clone())
+        .set_outbox_config(config.p2p().get_outbox_config())
+        .set_send_config(config.p2p().get_send_config())
+        .set_gossip_config(config.p2p().get_gossip_config())
+        .set_piggyback_config(config.p2p().get_piggyback_config())
+        .set_fragment_config(config.p2p().get_fragment_config())
+        .set_event_sender(node_tx.clone())
+        .set_verification_cache(verification)
+        .set_metrics(metrics.clone());
+    let capacity = CapacityAdvertisement::sign(
+        config.p2p().get_capacity_config().capacity(),
+        network_id,
+        &identity,
+    )?;
+    if !settings.dev {
+        state.lock().unwrap().rounds = Some(Rounds::new(
+            genesis.consensus(),
+            identity.get_our_hash()?,
+            connection.consensus_peers().clone(),
+            metrics.clone(),
+        ));
+    }
+    let mut network = Network {
+        our_hash: identity.get_our_hash()?,
+        capacity,
+        identity,
+        connection,
+        messaging,
+        transport,
+        node_tx,
+    };
+    if let Some(exporter) = OtlpExporter::from_config(config.p2p().get_telemetry_config()) {
+        export_telemetry(exporter, metrics);
+    }
+    log::info!("Running node {}", network.our_hash);
+    event_loop(&mut network, &quic_rx, &node_rx, &state)
+}
+
+/// Record our spans, and export them along with the metrics to the
+/// collector of `exporter` periodically, from a thread of their own so that
+/// a slow collector doesn't hold up the event loop
+fn export_telemetry(exporter: OtlpExporter, metrics: Arc<Metrics>) {
+    let collector = OtlpCollector::new();
+    if let Err(e) = tracing::subscriber::set_global_default(collector.clone()) {
+        log::warn!("Could not record traces: {}", e);
+        return;
+    }
+    log::info!("Exporting traces and metrics to {}", exporter.endpoint());
+    let _ = std::thread::spawn(move || loop {
+        std::thread::sleep(TELEMETRY_EXPORT_INTERVAL);
+        if let Err(e) = collector.export(&exporter) {
+            log::warn!("Could not export traces: {}", e);
+        }
+        if let Err(e) = exporter.export_metrics(&metrics.snapshot()) {
+            log::warn!("Could not export metrics: {}", e);
+        }
+    });
+}
+
+fn event_loop(
+    network: &mut Network,
+    quic_rx: &quic_channel::Receiver<QuicEvent>,
+    node_rx: &Receiver<Event>,
+    state: &Mutex<NodeState>,
+) -> Result<(), NodeError> {
+    let mut storage_reported: Option<Instant> = None;
+    loop {
+        match quic_rx.recv_timeout(TICK) {
+            Ok(event) => network
+                .handle(event)
+                .unwrap_or_else(|e| log::warn!("Error: {}", e)),
+            Err(quic_channel::RecvTimeoutError::Timeout) => {}
+            Err(quic_channel::RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+        let (outgoing, certificates, benchmark, rounds) = {
+            let mut state = state.lock().unwrap();
+            if storage_reported.is_none_or(|at| at.elapsed() >= STORAGE_REPORT_INTERVAL) {
+                state.report_storage();
+                storage_reported = Some(Instant::now());
+            }
+            state
+                .run_benchmark(unix_time())
+                .unwrap_or_else(|e| log::warn!("Error running benchmark: {}", e));
+            let rounds = state.run_rounds(Instant::now()).unwrap_or_else(|e| {
+                log::warn!("Error running consensus rounds: {}", e);
+                vec![]
+            });
+            let benchmark = state.benchmark.as_mut().map(|benchmark| {
+                (
+                    benchmark.participant.coordinator(),
+                    std::mem::take(&mut benchmark.outgoing),
+                )
+            });
+            (
+                std::mem::take(&mut state.outgoing),
+                std::mem::take(&mut state.outgoing_certificates),
+                benchmark,
+                rounds,
+            )
+        };
+        network.gossip(outgoing, certificates)?;
+        for (peer, message) in rounds {
+            network
+                .send(peer, message)
+                .unwrap_or_else(|e| log::warn!("Error reaching {}: {}", peer, e));
+        }
+        if let Some((coordinator, messages)) = benchmark {
+            for message in messages {
+                network
+                    .send(coordinator, message)
+                    .unwrap_or_else(|e| log::warn!("Error reaching the coordinator: {}", e));
+            }
+        }
+        let mut gossiped = vec![];
+        for event in node_rx.try_iter() {
+            if let Event::ConnectedTo(_) = event {
+                network
+                    .connection
+                    .advertise_capacity(&network.capacity, network.transport.as_mut());
+            }
+            handle_node_event(event, state, &mut gossiped)
+                .unwrap_or_else(|e| log::warn!("Error handling event: {}", e));
+        }
+        if !gossiped.is_empty() {
+            state
+                .lock()
+                .unwrap()
+                .accept_gossiped(gossiped)
+                .unwrap_or_else(|e| log::warn!("Error accepting gossip: {}", e));
+        }
+        network.tick();
+    }
+}
+
+/// Handle an event of the p2p layer. Gossiped transactions are collected
+/// into `gossiped`, for their signatures to be verified in one batch.
+fn handle_node_event(
+    event: Event,
+    state: &Mutex<NodeState>,
+    gossiped: &mut Vec<Transaction>,
+) -> Result<(), NodeError> {
+    let mut state = state.lock().unwrap();
+    match event {
+        Event::ConnectedTo(peer) => {
+            log::info!("Connected to {}", peer);
+            state.peers.push(peer);
+        }
+        Event::DisconnectedFrom(peer) => {
+            log::info!("Disconnected from {}", peer);
+            state.peers.retain(|known| *known != peer);
+        }
+        Event::NewMessage(content) => {
+            if let Some(tx) = content.strip_prefix(TX_GOSSIP_DOMAIN) {
+                gossiped.push(bincode::deserialize::<Transaction>(tx)?);
+            } else if let Some(certificate) = content.strip_prefix(FINALITY_GOSSIP_DOMAIN) {
+                let certificate = bincode::deserialize::<FinalityCertificate>(certificate)?;
+                state.receive_certificate(certificate)?;
+            }
+        }
+        Event::DagConsensusRequest { sender, tx, .. } => state.answer(sender, *tx)?,
+        Event::DagConsensusResponse {
+            hash,
+            sender,
+            accepted,
+        } => {
+            if let Some(rounds) = &mut state.rounds {
+                rounds.vote(sender, &hash, accepted);
+            }
+        }
+        Event::BenchmarkControl {
+            sender,
+            nonce,
+            command,
+        } => {
+            if let Some(benchmark) = &mut state.benchmark {
+                match benchmark.participant.handle_command(sender, nonce, command) {
+                    Ok(Some(answer)) => benchmark.outgoing.push(answer),
+                    Ok(None) => {}
+                    Err(e) => log::warn!("Benchmark command from {} refused: {}", sender, e),
+                }
+            }
+        }
+        event => log::debug!("{:?}", event),
+    }
+    Ok(())
+}
+
+/// Key of the `index`-th applied transaction
+fn applied_key(index: u64) -> Hash {
+    Hash::new(&index.to_le_bytes())
+}
+
+fn unix_time() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+#[test]
+fn test_dev_node_applies_submitted_transactions() {
+    use consensus::amount::Amount;
+    use p2p::node::benchmark::{BenchmarkCommand, BenchmarkPlan};
+
+    let dir = std::env::temp_dir().join(format!("dagchain-node-{}", Hash::generate_random()));
+    let identity = Identity::new();
+    let mut genesis = Genesis::new("test");
+    let _ = genesis
+        .fund(*identity.get_public_key(), Amount::new(1_000_000))
+        .add_validator(
+            *identity.get_public_key(),
+            Amount::new(100),
+            identity.get_private_key().prove_possession(),
+        );
+    let origin = genesis
+        .state()
+        .unwrap()
+        .get(&genesis.accounts()[0].id())
+        .cloned()
+        .unwrap();
+    let open = || {
+        NodeState::open(
+            SledStorage::new(Some(&dir)).unwrap(),
+            &genesis,
+            identity.get_private_key().clone(),
+            true,
+            Arc::new(Metrics::new()),
+        )
+        .unwrap()
+    };
+    let handler = Handler {
+        state: Arc::new(Mutex::new(open())),
+        window: SubmissionWindow::default(),
+    };
+
+    let destination = AccountId::from(Hash::new("destination".as_bytes()));
+    let transfer = |amount| {
+        let mut tx = Transaction::new(
+            origin.last_tx_id,
+            origin.clone(),
+            destination,
+            Amount::new(amount),
+            TransactionType::Transfer,
+            vec![],
+        );
+        let _ = tx
+            .calculate_tx_id()
+            .unwrap()
+            .sign_and_set_signature(identity.get_private_key())
+            .unwrap();
+        tx
+    };
+    let first = transfer(40);
+    let tx_id = handler.submit_transaction(first.clone()).unwrap();
+    assert_eq!(
+        handler.get_transaction_status(&tx_id),
+        Some(TransactionStatus::Accepted)
+    );
+    let receipt = handler.get_receipt(&tx_id).unwrap();
+    assert_eq!(receipt.status, TransactionStatus::Accepted);
+    assert_eq!(receipt.round, None);
+    // Retrying the submission attaches to the first one
+    assert_eq!(handler.submit_transaction(first).unwrap(), tx_id);
+    // Our signature certifies its finality, and is gossiped
+    let certificate = handler.get_finality_certificate(&tx_id).unwrap();
+    assert_eq!(
+        certificate
+            .verify(&handler.state.lock().unwrap().stake, Quorum::TWO_THIRDS)
+            .unwrap(),
+        100
+    );
+    assert_eq!(
+        handler.state.lock().unwrap().outgoing_certificates,
+        vec![certificate]
+    );
+    assert_eq!(
+        handler.get_account(&destination).unwrap().balance,
+        Amount::new(40)
+    );
+    // Transfers the origin cannot fund are refused before consensus
+    let error = handler.submit_transaction(transfer(2_000_000)).unwrap_err();
+    assert_eq!(error.code, INVALID_PARAMS);
+    // So are transactions not signed by their origin, submitted or gossiped
+    let mut forged = transfer(50);
+    let signature = forged.sign_tx(&PrivateKey::generate()).unwrap();
+    let _ = forged.set_signature(identity.get_public_key(), &signature);
+    let error = handler.submit_transaction(forged.clone()).unwrap_err();
+    assert_eq!(error.code, INVALID_PARAMS);
+    // Or signed, but claiming another ID than the one of their contents
+    let mut renamed = transfer(50);
+    let actual = renamed.get_tx_id();
+    renamed.set_tx_id(TxId::from(Hash::generate_random()));
+    let error = handler.submit_transaction(renamed.clone()).unwrap_err();
+    assert_eq!(error.code, INVALID_PARAMS);
+    let mut state = handler.state.lock().unwrap();
+    state.accept_gossiped(vec![forged.clone()]).unwrap();
+    assert!(!state
+        .transactions
+        .contains(forged.get_tx_id().as_hash())
+        .unwrap());
+    state.accept_gossiped(vec![renamed.clone()]).unwrap();
+    for id in [renamed.get_tx_id(), actual] {
+        assert!(!state.transactions.contains(id.as_hash()).unwrap());
+    }
+    drop(state);
+    // A second spend of the same sequence is refused by the state
+    let replayed = handler.submit_transaction(transfer(50)).unwrap();
+    assert_eq!(
+        handler.get_transaction_status(&replayed),
+        Some(TransactionStatus::Rejected)
+    );
+    assert!(handler.state.lock().unwrap().outgoing.is_empty());
+
+    // Accepted transactions are linked to the ones they build on
+    let mut next = Transaction::new(
+        tx_id,
+        handler.get_account(&origin.id).unwrap(),
+        destination,
+        Amount::new(10),
+        TransactionType::Transfer,
+        vec![],
+    );
+    let _ = next
+        .calculate_tx_id()
+        .unwrap()
+        .sign_and_set_signature(identity.get_private_key())
+        .unwrap();
+    let next_id = handler.submit_transaction(next).unwrap();
+    assert_eq!(
+        handler.get_transaction(&tx_id).unwrap().get_children(),
+        vec![next_id]
+    );
+    assert!(!handler.state.lock().unwrap().dag.contains(&replayed));
+
+    // Data entries are stored once applied
+    let entry = DataEntry::new("doc", Hash::new("document".as_bytes()).0.to_vec());
+    let mut store = Transaction::new(
+        next_id,
+        handler.get_account(&origin.id).unwrap(),
+        origin.id,
+        Amount::ZERO,
+        TransactionType::StoreData,
+        entry.to_payload().unwrap(),
+    );
+    let _ = store
+        .set_fee(entry.rent())
+        .calculate_tx_id()
+        .unwrap()
+        .sign_and_set_signature(identity.get_private_key())
+        .unwrap();
+    let store_id = handler.submit_transaction(store).unwrap();
+    let data = handler.get_data(&origin.id, "doc").unwrap();
+    assert_eq!((data.value, data.tx_id), (entry.value, store_id));
+
+    // Accepted `Execute` transactions run through the executor
+    let op = executor::KvOp::Put {
+        key: b"doc".to_vec(),
+        value: b"v1".to_vec(),
+    };
+    let mut execute = Transaction::new(
+        store_id,
+        handler.get_account(&origin.id).unwrap(),
+        origin.id,
+        Amount::ZERO,
+        TransactionType::Execute,
+        op.to_payload().unwrap(),
+    );
+    let _ = execute
+        .calculate_tx_id()
+        .unwrap()
+        .sign_and_set_signature(identity.get_private_key())
+        .unwrap();
+    let execute_id = handler.submit_transaction(execute).unwrap();
+    assert_eq!(
+        handler
+            .state
+            .lock()
+            .unwrap()
+            .app
+            .get(&KvExecutor::key_of(&origin.id, b"doc")),
+        Some(&b"v1".to_vec())
+    );
+    // Registering validators bond stake, which certifies finality
+    let mut register = Transaction::new(
+        execute_id,
+        handler.get_account(&origin.id).unwrap(),
+        origin.id,
+        Amount::new(50),
+        TransactionType::RegisterValidator,
+        vec![],
+    );
+    let _ = register
+        .calculate_tx_id()
+        .unwrap()
+        .sign_and_set_signature(identity.get_private_key())
+        .unwrap();
+    let balance = handler.get_account(&origin.id).unwrap().balance;
+    let _ = handler.submit_transaction(register).unwrap();
+    assert_eq!(
+        handler.get_account(&origin.id).unwrap().balance,
+        balance.checked_sub(Amount::new(50)).unwrap()
+    );
+    let our_id = Hash::new(&identity.get_public_key().to_bytes());
+    assert_eq!(
+        handler.state.lock().unwrap().stake.get(&our_id).unwrap().1,
+        150
+    );
+    handler.state.lock().unwrap().report_storage();
+    assert!(handler.metrics().unwrap().storage_bytes > 0);
+
+    // Benchmark runs submit transactions at the planned rate and report
+    let coordinator = Identity::new();
+    let mut state = handler.state.lock().unwrap();
+    state.benchmark = Some(Benchmark {
+        participant: BenchmarkParticipant::new(
+            identity.clone(),
+            Hash::default(),
+            &coordinator.get_public_id(),
+        )
+        .unwrap(),
+        last_origin: None,
+        outgoing: vec![],
+    });
+    let plan = BenchmarkPlan {
+        id: Hash::generate_random(),
+        duration: Duration::from_secs(1),
+        rate: 4,
+        conflict_ratio: 0.5,
+    };
+    let start_at = unix_time();
+    let commands = [
+        BenchmarkCommand::Plan(plan.clone()),
+        BenchmarkCommand::Start {
+            plan_id: plan.id,
+            start_at,
+        },
+    ];
+    for (nonce, command) in commands.into_iter().enumerate() {
+        let sender = coordinator.get_our_hash().unwrap();
+        let benchmark = state.benchmark.as_mut().unwrap();
+        let _ = benchmark
+            .participant
+            .handle_command(sender, nonce as u64 + 1, command)
+            .unwrap();
+    }
+    state.run_benchmark(start_at + plan.duration).unwrap();
+    let benchmark = state.benchmark.as_ref().unwrap();
+    let stats = benchmark.participant.stats();
+    assert_eq!((stats.submitted, stats.accepted, stats.rejected), (4, 2, 2));
+    assert_eq!(benchmark.outgoing.len(), 1);
+    let account = state.scheduler.state().get(&origin.id).cloned().unwrap();
+    drop(state);
+    drop(handler);
+
+    // The state is rebuilt from the applied transactions once restarted
+    let state = open();
+    assert_eq!(state.applied_count, 5 + 2);
+    assert_eq!(state.stake.get(&our_id).unwrap().1, 150);
+    let restored = state.scheduler.state().get(&origin.id).unwrap();
+    assert_eq!(
+        (restored.balance, restored.next_sequence()),
+        (account.balance, account.next_sequence())
+    );
+    assert_eq!(
+        state.scheduler.state().get(&destination).unwrap().balance,
+        Amount::new(50)
+    );
+    assert!(state
+        .scheduler
+        .state()
+        .check_funds(&transfer(2_000_000))
+        .is_err());
+    assert_eq!(
+        state.app.get(&KvExecutor::key_of(&origin.id, b"doc")),
+        Some(&b"v1".to_vec())
+    );
+    assert!(state.dag.contains(&next_id));
+    drop(state);
+    std::fs::remove_dir_all(dir).unwrap();
+}
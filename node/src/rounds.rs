@@ -0,0 +1,551 @@
+//! Consensus rounds of a networked node.
+//!
+//! Every transaction the node learns of is polled: a sample of the
+//! connected peers is asked whether they prefer it, and once they all
+//! answered or the round timed out, it enters the [`Mempool`]. Pumping the
+//! mempool resolves its rounds through the [`DagConsensus`] engine from the
+//! answers collected. Transactions accepted or rejected are settled, and
+//! the others polled again with a new sample until they run out of
+//! attempts. Answers only tell what a peer prefers: validators sign the
+//! finality of a transaction once they accepted it themselves, see
+//! [`FinalityCertificate`](consensus::finality::FinalityCertificate).
+
+use consensus::{
+    account::AccountStateChoice,
+    config::ConsensusConfig,
+    dag_consensus::DagConsensus,
+    mempool::Mempool,
+    network::{CommonConsensusNetwork, ConsensusNetwork},
+    transaction::{Transaction, TransactionStatus},
+    tree::{HashTreeNode, TreeNode},
+    Consensus, ConsensusStatus, NodeId, TxId,
+};
+use crypto::hash::Hash;
+use metrics::Metrics;
+use p2p::node::{message::Message, peers::ConsensusPeers};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Poll of a sample of peers on a transaction
+struct Poll {
+    state: AccountStateChoice,
+    sample: Vec<NodeId>,
+    /// Answers of the sampled peers so far: whether they prefer the
+    /// transaction
+    votes: HashMap<NodeId, bool>,
+    /// When the round times out if some answers are missing
+    deadline: Instant,
+    /// Rounds polled so far
+    attempts: usize,
+    /// Set once the poll is over and the transaction waits in the mempool
+    queued: bool,
+}
+
+/// Polls in flight, which the engine queries through the answers collected
+/// rather than through the network.
+///
+/// Only the peers' answers and our own ID are served. Sampling, sending
+/// requests, batching and collecting responses happen in [`Rounds`], so the
+/// other methods of [`ConsensusNetwork`] and [`CommonConsensusNetwork`] are
+/// stubs returning nothing.
+struct Polls {
+    our_id: NodeId,
+    polls: HashMap<TxId, Poll>,
+}
+
+impl CommonConsensusNetwork for Polls {
+    fn get_nodes_except_one(&self, _k: u64, _node_id: NodeId) -> Vec<NodeId> {
+        vec![]
+    }
+}
+
+impl ConsensusNetwork for Polls {
+    fn get_sample_network<T: CommonConsensusNetwork>(
+        &self,
+        _k: u64,
+        _current_node: NodeId,
+        _network: &T,
+    ) -> Vec<NodeId> {
+        vec![]
+    }
+
+    fn request_consensus(&mut self, _node_id: NodeId, data: &AccountStateChoice) -> TxId {
+        data.tx.get_tx_id()
+    }
+
+    fn request_dag_consensus(&self, node_id: NodeId, data: &AccountStateChoice) -> bool {
+        self.polls
+            .get(&data.tx.get_tx_id())
+            .and_then(|poll| poll.votes.get(&node_id))
+            .copied()
+            .unwrap_or(false)
+    }
+
+    fn send_dag_consensus_request(
+        &mut self,
+        _node_id: NodeId,
+        _data: &AccountStateChoice,
+        _tx: &Transaction,
+        _count: usize,
+    ) {
+    }
+
+    fn add_outgoing_dag_consensus_request(
+        &mut self,
+        _node_id: NodeId,
+        _data: &AccountStateChoice,
+        _tx: &Transaction,
+        _count: usize,
+    ) {
+    }
+
+    fn accept_incoming_consensus_response(
+        &mut self,
+        _node_id: NodeId,
+        _data: TxId,
+        _accepted: bool,
+    ) -> (usize, usize) {
+        (0, 0)
+    }
+
+    /// The transaction of the poll, if any. Polls are dropped once settled,
+    /// not here.
+    fn remove_outgoing_dag_transaction(&mut self, tx_id: TxId) -> Option<Transaction> {
+        self.polls.get(&tx_id).map(|poll| poll.state.tx.clone())
+    }
+
+    fn get_node_id(&self) -> NodeId {
+        self.our_id
+    }
+
+    fn add_transaction_to_batch<N: CommonConsensusNetwork>(
+        &mut self,
+        _k: u64,
+        _tx: &Transaction,
+        _data: &AccountStateChoice,
+        _network: &N,
+        _max_batch_size: usize,
+        _max_batch_interval: f32,
+        _count: usize,
+    ) {
+    }
+
+    /// Count the sampled peers who answered that they prefer the
+    /// transaction
+    fn dag_query<N: CommonConsensusNetwork>(
+        &mut self,
+        _k: u64,
+        data: &AccountStateChoice,
+        _network: &N,
+    ) -> u64 {
+        self.polls.get(&data.tx.get_tx_id()).map_or(0, |poll| {
+            poll.votes.values().filter(|preferred| **preferred).count() as u64
+        })
+    }
+}
+
+/// Transaction settled by its rounds
+#[derive(Debug, PartialEq)]
+pub struct Settled {
+    pub tx_id: TxId,
+    pub status: TransactionStatus,
+    /// Round that settled the transaction, counted from 1
+    pub round: u64,
+}
+
+/// Consensus rounds on the transactions we learn of, along with our
+/// answers to the polls of our peers
+pub struct Rounds {
+    engine: DagConsensus,
+    mempool: Mempool,
+    /// Transactions polled so far, linked to their parents
+    tree: HashTreeNode,
+    polls: Polls,
+    /// Connected peers, sampled for every poll
+    peers: ConsensusPeers,
+    /// First transaction we learned of spending each account state, which
+    /// we prefer over the ones conflicting with it
+    first_seen: HashMap<Hash, TxId>,
+    /// Queries and answers waiting to be sent
+    outgoing: Vec<(NodeId, Message)>,
+    k: u64,
+    timeout: Duration,
+    max_round_attempts: usize,
+    max_batch_size: usize,
+}
+
+impl Rounds {
+    pub fn new(
+        config: &ConsensusConfig,
+        our_id: NodeId,
+        peers: ConsensusPeers,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        let mut engine = DagConsensus::new(config.clone());
+        let _ = engine.set_metrics(metrics.clone());
+        let mut mempool = Mempool::from_config(config);
+        let _ = mempool.set_metrics(metrics);
+        Self {
+            engine,
+            mempool,
+            tree: HashTreeNode::new(),
+            polls: Polls {
+                our_id,
+                polls: HashMap::new(),
+            },
+            peers,
+            first_seen: HashMap::new(),
+            outgoing: vec![],
+            k: config.k(),
+            timeout: Duration::try_from_secs_f32(config.round_timeout()).unwrap_or_default(),
+            max_round_attempts: config.max_round_attempts(),
+            max_batch_size: config.max_batch_size(),
+        }
+    }
+
+    /// Start polling a transaction, unless we are already. Its parents
+    /// missing from the tree are added as roots, so that its rounds can
+    /// walk up to them.
+    pub fn poll(&mut self, tx: &Transaction, now: Instant) {
+        let tx_id = tx.get_tx_id();
+        if self.polls.polls.contains_key(&tx_id) {
+            return;
+        }
+        for parent in tx.parents() {
+            let _ = self
+                .tree
+                .entry(*parent)
+                .or_insert_with(|| (vec![TxId::default()], TreeNode::new(*parent)));
+        }
+        let _ = self
+            .tree
+            .entry(tx_id)
+            .or_insert_with(|| (tx.parents().to_vec(), TreeNode::new(tx_id)));
+        let state = AccountStateChoice::new(tx.parent().into(), tx);
+        let _ = self
+            .first_seen
+            .entry(state.account_state_id)
+            .or_insert(tx_id);
+        let _ = self.polls.polls.insert(
+            tx_id,
+            Poll {
+                state,
+                sample: vec![],
+                votes: HashMap::new(),
+                deadline: now,
+                attempts: 0,
+                queued: false,
+            },
+        );
+        self.query(&tx_id, now);
+    }
+
+    /// Answer the poll of a peer on a transaction. Transactions found
+    /// invalid are never preferred; the others are unless we prefer
+    /// another one spending the same account state.
+    pub fn answer(&mut self, sender: NodeId, tx: &Transaction, valid: bool) {
+        let tx_id = tx.get_tx_id();
+        let state = AccountStateChoice::new(tx.parent().into(), tx);
+        let preferred = valid
+            && self
+                .chosen(&state)
+                .or_else(|| self.first_seen.get(&state.account_state_id).copied())
+                .is_none_or(|preferred| preferred == tx_id);
+        self.outgoing.push((
+            sender,
+            Message::DagConsensusResponse {
+                sender: self.polls.our_id,
+                hash: tx_id,
+                strongly_preferred: preferred,
+            },
+        ));
+    }
+
+    /// Count the answer of a peer to our poll on `tx_id`. Answers from
+    /// peers we didn't sample, to polls that are over or after the first
+    /// one of a peer are ignored.
+    pub fn vote(&mut self, sender: NodeId, tx_id: &TxId, preferred: bool) {
+        if let Some(poll) = self.polls.polls.get_mut(tx_id) {
+            if !poll.queued && poll.sample.contains(&sender) && !poll.votes.contains_key(&sender) {
+                let _ = poll.votes.insert(sender, preferred);
+            }
+        }
+    }
+
+    /// Queue the transactions whose polls are over at `now` into the
+    /// mempool, and resolve the rounds of the next batch. Returns the
+    /// transactions settled as a result.
+    pub fn resolve(&mut self, now: Instant) -> Vec<Settled> {
+        let mut undecided = vec![];
+        let mut over = self
+            .polls
+            .polls
+            .iter()
+            .filter(|(_, poll)| {
+                !poll.queued && (poll.votes.len() == poll.sample.len() || poll.deadline <= now)
+            })
+            .map(|(tx_id, _)| *tx_id)
+            .collect::<Vec<_>>();
+        // The transactions we prefer are queued ahead of those conflicting
+        // with them, which the mempool refuses as double spends meanwhile
+        over.sort_by_key(|tx_id| {
+            let account_state = self.polls.polls[tx_id].state.account_state_id;
+            self.first_seen.get(&account_state) != Some(tx_id)
+        });
+        for tx_id in over {
+            let state = self.polls.polls[&tx_id].state.clone();
+            // Transactions polled again sit in the conflict set of the
+            // engine since their first round
+            let mut conflict_set = self.engine.conflict_set();
+            if let Some(set) = conflict_set.get_mut(&state.account_state_id) {
+                let _ = set.remove(&tx_id);
+                if set.is_empty() {
+                    let _ = conflict_set.remove(&state.account_state_id);
+                }
+            }
+            match self.mempool.insert(state, &conflict_set) {
+                Ok(evicted) => {
+                    self.polls.polls.get_mut(&tx_id).unwrap().queued = true;
+                    undecided.extend(evicted);
+                }
+                Err(e) => {
+                    log::debug!("{} not queued: {}", tx_id, e);
+                    undecided.push(tx_id);
+                }
+            }
+        }
+
+        let mut settled = vec![];
+        let statuses = self.mempool.pump(
+            &mut self.engine,
+            &mut self.polls,
+            &mut self.peers,
+            Some(&mut self.tree),
+            self.max_batch_size,
+        );
+        for (tx_id, status) in statuses {
+            match status {
+                ConsensusStatus::Accept(accepted) => {
+                    settled.push(self.settled(&accepted, TransactionStatus::Accepted));
+                    if accepted != tx_id {
+                        undecided.push(tx_id);
+                    }
+                }
+                _ => undecided.push(tx_id),
+            }
+        }
+        for tx_id in undecided {
+            let Some(poll) = self.polls.polls.get(&tx_id) else {
+                continue;
+            };
+            if self
+                .chosen(&poll.state)
+                .is_some_and(|chosen| chosen != tx_id)
+            {
+                settled.push(self.settled(&tx_id, TransactionStatus::Rejected));
+            } else if poll.attempts >= self.max_round_attempts {
+                log::warn!("{} undecided after {} rounds", tx_id, poll.attempts);
+                self.forget(&tx_id, false);
+            } else {
+                self.query(&tx_id, now);
+            }
+        }
+        for settled in &settled {
+            self.forget(
+                &settled.tx_id,
+                settled.status == TransactionStatus::Accepted,
+            );
+        }
+        self.engine
+            .prune(&settled.iter().map(|settled| settled.tx_id).collect());
+        settled
+    }
+
+    /// Take the queries and answers waiting to be sent, with the peers
+    /// they are for
+    pub fn take_outgoing(&mut self) -> Vec<(NodeId, Message)> {
+        std::mem::take(&mut self.outgoing)
+    }
+
+    /// Whether we are still polling `tx_id`
+    #[cfg(test)]
+    fn is_polling(&self, tx_id: &TxId) -> bool {
+        self.polls.polls.contains_key(tx_id)
+    }
+
+    /// Poll a new sample of peers on `tx_id`
+    fn query(&mut self, tx_id: &TxId, now: Instant) {
+        let sample = self.peers.get_nodes_except_one(self.k, self.polls.our_id);
+        let poll = self.polls.polls.get_mut(tx_id).unwrap();
+        poll.attempts += 1;
+        poll.deadline = now + self.timeout;
+        poll.votes.clear();
+        poll.queued = false;
+        for peer in &sample {
+            self.outgoing.push((
+                *peer,
+                Message::DagConsensusRequest {
+                    sender: self.polls.our_id,
+                    data: poll.state.clone(),
+                    tx: Box::new(poll.state.tx.clone()),
+                    count: poll.attempts,
+                },
+            ));
+        }
+        poll.sample = sample;
+    }
+
+    /// Settle `tx_id` in the round of its poll, if we polled it
+    fn settled(&self, tx_id: &TxId, status: TransactionStatus) -> Settled {
+        Settled {
+            tx_id: *tx_id,
+            status,
+            round: self
+                .polls
+                .polls
+                .get(tx_id)
+                .map_or(0, |poll| poll.attempts as u64),
+        }
+    }
+
+    /// Transaction the engine chose for the account state `state` spends,
+    /// if it did
+    fn chosen(&self, state: &AccountStateChoice) -> Option<TxId> {
+        self.engine
+            .conflicts_for(&state.account_state_id)
+            .and_then(|report| report.choice)
+    }
+
+    /// Stop polling `tx_id`. The account states spent by accepted
+    /// transactions stay spent, the others can be spent by the next
+    /// transaction we learn of.
+    fn forget(&mut self, tx_id: &TxId, accepted: bool) {
+        if let Some(poll) = self.polls.polls.remove(tx_id) {
+            let account_state = poll.state.account_state_id;
+            if !accepted && self.first_seen.get(&account_state) == Some(tx_id) {
+                let _ = self.first_seen.remove(&account_state);
+            }
+        }
+        let _ = self.mempool.remove(tx_id);
+    }
+}
+
+#[test]
+fn test_rounds_settle_from_the_answers_of_sampled_peers() {
+    use consensus::{account::Account, amount::Amount, transaction::TransactionType};
+
+    let config = ConsensusConfig::builder().k(2).build().unwrap();
+    let node = |i: u8| NodeId::from(Hash::new(&[i]));
+    let rounds = |i: u8, others: &[u8]| {
+        let peers = ConsensusPeers::new();
+        for other in others {
+            peers.connected(
+                node(*other),
+                format!("10.0.{}.1:5000", other).parse().unwrap(),
+            );
+        }
+        Rounds::new(&config, node(i), peers, Arc::new(Metrics::new()))
+    };
+    let mut nodes = [rounds(0, &[1, 2]), rounds(1, &[0, 2]), rounds(2, &[0, 1])];
+    let origin = Account::create(&Hash::new(b"origin").into(), &TxId::default());
+    let spend = |to: &[u8]| {
+        let mut tx = Transaction::new(
+            origin.last_tx_id,
+            origin.clone(),
+            Hash::new(to).into(),
+            Amount::new(1),
+            TransactionType::Transfer,
+            vec![],
+        );
+        let _ = tx.calculate_tx_id().unwrap();
+        tx
+    };
+    let (tx, double_spend) = (spend(b"bob"), spend(b"carol"));
+    let now = Instant::now();
+    let to = |target: NodeId| (0..3u8).find(|i| node(*i) == target).unwrap() as usize;
+    // Deliver the queries and answers between the nodes until none is left
+    let deliver = |nodes: &mut [Rounds; 3]| loop {
+        let mut delivered = false;
+        for from in 0..3 {
+            for (target, message) in nodes[from].take_outgoing() {
+                delivered = true;
+                let to = to(target);
+                match message {
+                    Message::DagConsensusRequest { sender, tx, .. } => {
+                        nodes[to].poll(&tx, now);
+                        nodes[to].answer(sender, &tx, true);
+                    }
+                    Message::DagConsensusResponse {
+                        sender,
+                        hash,
+                        strongly_preferred,
+                    } => nodes[to].vote(sender, &hash, strongly_preferred),
+                    message => panic!("unexpected {:?}", message),
+                }
+            }
+        }
+        if !delivered {
+            break;
+        }
+    };
+
+    // Every node learns of the transaction before its double spend, so
+    // they all prefer it
+    nodes[0].poll(&tx, now);
+    deliver(&mut nodes);
+    nodes[1].poll(&double_spend, now);
+    deliver(&mut nodes);
+    for rounds in &mut nodes {
+        let settled = rounds.resolve(now);
+        assert_eq!(settled.len(), 2);
+        assert_eq!(
+            (settled[0].tx_id, &settled[0].status, settled[0].round),
+            (tx.get_tx_id(), &TransactionStatus::Accepted, 1)
+        );
+        assert_eq!(
+            (settled[1].tx_id, &settled[1].status),
+            (double_spend.get_tx_id(), &TransactionStatus::Rejected)
+        );
+        assert!(!rounds.is_polling(&tx.get_tx_id()));
+    }
+    // The account state stays spent once the transaction is settled
+    nodes[2].answer(node(0), &double_spend, true);
+    assert!(matches!(
+        nodes[2].take_outgoing()[..],
+        [(
+            _,
+            Message::DagConsensusResponse {
+                strongly_preferred: false,
+                ..
+            }
+        )]
+    ));
+
+    // Unanswered polls time out, and give up after their last attempt
+    let mut alone = rounds(3, &[4]);
+    let lonely = spend(b"dave");
+    alone.poll(&lonely, now);
+    assert_eq!(alone.take_outgoing().len(), 1);
+    assert!(alone.resolve(now).is_empty());
+    assert!(alone.is_polling(&lonely.get_tx_id()));
+    assert_eq!(
+        alone
+            .polls
+            .remove_outgoing_dag_transaction(lonely.get_tx_id()),
+        Some(lonely.clone())
+    );
+    let mut at = now;
+    for _ in 0..config.max_round_attempts() {
+        at += Duration::from_secs(60);
+        assert!(alone.resolve(at).is_empty());
+    }
+    assert!(!alone.is_polling(&lonely.get_tx_id()));
+    // Polls settled or given up on are no longer served
+    assert_eq!(
+        alone
+            .polls
+            .remove_outgoing_dag_transaction(lonely.get_tx_id()),
+        None
+    );
+}
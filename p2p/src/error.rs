@@ -1,4 +1,7 @@
-use crate::node::event::Event;
+use crate::node::{auth::Scope, event::Event, tokens::MessageClass};
+use crypto::hash::Hash;
+use std::net::SocketAddr;
+use std::time::Duration;
 use thiserror::Error;
 
 /// P2p-related errors
@@ -17,9 +20,174 @@ pub enum P2pError {
     #[error("Crossbeam receiver error: {0}")]
     CrossbeamReceiverError(crossbeam_channel::RecvError),
     #[error("Crossbeam sender error: {0}")]
-    CrossbeamSenderError(crossbeam_channel::SendError<Event>),
+    CrossbeamSenderError(Box<crossbeam_channel::SendError<Event>>),
+    #[error("No route to node {0:?}")]
+    NoRoute(consensus::NodeId),
     #[error("Invalid signature error")]
     InvalidSignature,
+    #[error("Invalid config: {0}")]
+    ConfigError(ConfigError),
+    #[error("Authentication error: {0}")]
+    AuthError(AuthError),
+    #[error("Decode error: {0}")]
+    DecodeError(DecodeError),
+    #[error("Peer runs protocol {theirs}, we run {ours}")]
+    ProtocolMismatch { ours: Hash, theirs: Hash },
+    #[error("Signed for network {theirs}, we are on {ours}")]
+    NetworkMismatch { ours: Hash, theirs: Hash },
+    #[error("Peer follows chain {theirs}, we follow {ours}")]
+    ChainMismatch { ours: Hash, theirs: Hash },
+    #[error("Peer speaks protocol version {theirs}, we speak {ours}")]
+    IncompatibleProtocol { ours: u32, theirs: u32 },
+    #[error("Peer at {0} is over its rate limits")]
+    RateLimited(SocketAddr),
+    #[error("Peer identified before greeting us")]
+    MissingHello,
+    #[error("Peer decodes transaction encodings {theirs:?}, we {ours:?}")]
+    IncompatibleEncoding { ours: (u8, u8), theirs: (u8, u8) },
+    #[error("Peer identified as {actual:?}, we dialed {expected:?}")]
+    IdentityMismatch {
+        expected: consensus::NodeId,
+        actual: consensus::NodeId,
+    },
     #[error("Custom error: {0}")]
     CustomError(String),
 }
+
+impl From<crossbeam_channel::SendError<Event>> for P2pError {
+    #[inline]
+    fn from(e: crossbeam_channel::SendError<Event>) -> Self {
+        P2pError::CrossbeamSenderError(Box::new(e))
+    }
+}
+
+impl From<ConfigError> for P2pError {
+    #[inline]
+    fn from(e: ConfigError) -> Self {
+        P2pError::ConfigError(e)
+    }
+}
+
+impl From<AuthError> for P2pError {
+    #[inline]
+    fn from(e: AuthError) -> Self {
+        P2pError::AuthError(e)
+    }
+}
+
+impl From<DecodeError> for P2pError {
+    #[inline]
+    fn from(e: DecodeError) -> Self {
+        P2pError::DecodeError(e)
+    }
+}
+
+/// Data received from a peer that could not be decoded
+#[derive(Clone, Debug, Error, PartialEq)]
+pub enum DecodeError {
+    #[error("Message of {size} bytes exceeds the limit of {limit}")]
+    TooLarge { size: u64, limit: u64 },
+    #[error("Malformed message: {0}")]
+    Malformed(String),
+}
+
+impl DecodeError {
+    /// Whether the peer sent more data than allowed, a likely attack rather
+    /// than a bug or a version mismatch
+    pub fn is_oversized(&self) -> bool {
+        matches!(self, DecodeError::TooLarge { .. })
+    }
+}
+
+/// Failures of a light client, see [`crate::light`]
+#[derive(Clone, Debug, Error, PartialEq)]
+pub enum LightError {
+    #[error("No full node to query")]
+    NoFullNodes,
+    #[error("No checkpoint was synced yet")]
+    NotSynced,
+    #[error("Checkpoint at height {0} does not extend the previous one")]
+    BrokenChain(u64),
+    #[error(
+        "Checkpoint at height {height} is certified by {certified} stake, {required} required"
+    )]
+    InsufficientStake {
+        height: u64,
+        certified: u128,
+        required: u128,
+    },
+    #[error("No full node served a valid proof")]
+    Unavailable,
+    #[error("Full node error: {0}")]
+    FullNode(String),
+}
+
+/// Refused remote administration tokens
+#[derive(Clone, Debug, Error, PartialEq)]
+pub enum AuthError {
+    #[error("No token was presented")]
+    MissingToken,
+    #[error("Token is unknown or malformed")]
+    InvalidToken,
+    #[error("Token has expired")]
+    Expired,
+    #[error("Token was revoked")]
+    Revoked,
+    #[error("Token was not issued by a trusted key")]
+    UntrustedIssuer,
+    #[error("Command was not signed by a trusted operator")]
+    UntrustedOperator,
+    #[error("Command is meant for another node")]
+    Misdirected,
+    #[error("Command nonce {nonce} is not above the last accepted, {last}")]
+    Replayed { nonce: u64, last: u64 },
+    #[error("Command nonce {0} is too far from our clock")]
+    StaleNonce(u64),
+    #[error("Method requires {required:?} access, token grants {granted:?}")]
+    InsufficientScope { required: Scope, granted: Scope },
+}
+
+/// Invalid node configuration
+#[derive(Clone, Debug, Error, PartialEq)]
+pub enum ConfigError {
+    #[error(
+        "Keep-alive interval {keep_alive:?} must be shorter than idle timeout {idle_timeout:?}"
+    )]
+    KeepAliveTooLong {
+        keep_alive: Duration,
+        idle_timeout: Duration,
+    },
+    #[error("Hop limit of {0} messages must be at least 1")]
+    ZeroHopLimit(String),
+    #[error("At least one connection per subnet must be allowed")]
+    NoConnectionsPerSubnet,
+    #[error("Max message size must be at least 1 byte")]
+    ZeroMaxMessageSize,
+    #[error("Peer rate limits must allow at least 1 message and 1 byte per second")]
+    ZeroRateLimit,
+    #[error("Fragment and reassembly sizes must be at least 1 byte")]
+    ZeroFragmentSize,
+    #[error("Outbox capacity must be at least 1")]
+    ZeroOutboxCapacity,
+    #[error("Send timeout of {0:?} messages must be positive")]
+    ZeroSendTimeout(MessageClass),
+    #[error("Gossip fanout bounds must satisfy 1 <= {min} <= {max}")]
+    InvalidFanout { min: usize, max: usize },
+    #[error("Duplicate ratios must satisfy 0 <= {min} < {max} <= 1")]
+    InvalidDuplicateRatios { min: f64, max: f64 },
+    #[error("Development mode nodes run alone and may not bootstrap")]
+    DevModeWithPeers,
+    #[error("Development mode nodes start from genesis")]
+    DevModeSync,
+    #[error("Nodes trusting a peer for their state may not validate")]
+    UntrustedValidator,
+    #[error("Invalid consensus config: {0}")]
+    Consensus(consensus::ConfigError),
+}
+
+impl From<consensus::ConfigError> for ConfigError {
+    #[inline]
+    fn from(e: consensus::ConfigError) -> Self {
+        ConfigError::Consensus(e)
+    }
+}
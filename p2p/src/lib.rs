@@ -10,5 +10,9 @@
 
 /// P2p-related errors
 pub mod error;
+/// Clients checking what full nodes serve, without storing the DAG
+pub mod light;
 /// Functionality of a node on the network
 pub mod node;
+/// Transports carrying messages between peers
+pub mod transport;
@@ -0,0 +1,587 @@
+//! Light clients, for wallets that can neither follow consensus nor store
+//! the DAG.
+//!
+//! A light client trusts the validators and their stake, and nothing else.
+//! From full nodes it downloads only the checkpoints validators certified,
+//! checking that each extends the previous one and carries the aggregated
+//! signatures of a quorum of stake. Balances are then proven with Merkle
+//! proofs against the state root of the latest checkpoint, and transactions
+//! with the finality certificates of the validators who accepted them. Full
+//! nodes are never trusted: whatever fails to verify is ignored, and the next
+//! one asked.
+
+use crate::error::LightError;
+use consensus::{
+    checkpoint::{Checkpoint, CheckpointCertificate},
+    finality::FinalityCertificate,
+    reconcile::{Quorum, StakeTable},
+    state::BalanceProof,
+    transaction::{Transaction, TransactionStatus},
+    AccountId, Amount, TxId,
+};
+use crypto::hash::Hash;
+
+/// What a full node serves to light clients
+pub trait FullNode {
+    /// Certified checkpoints from `height` up to the latest, in order
+    fn checkpoints(&mut self, height: u64) -> Result<Vec<CheckpointCertificate>, LightError>;
+
+    /// Proof of the balance of an account at a checkpoint, `None` if the
+    /// account didn't exist
+    fn balance_proof(
+        &mut self,
+        account_id: &AccountId,
+        checkpoint: &Hash,
+    ) -> Result<Option<BalanceProof>, LightError>;
+
+    /// Transaction with the given id
+    fn transaction(&mut self, tx_id: &TxId) -> Result<Option<Transaction>, LightError>;
+
+    /// Aggregated signatures of the validators on the finality of a
+    /// transaction
+    fn finality_certificate(
+        &mut self,
+        tx_id: &TxId,
+    ) -> Result<Option<FinalityCertificate>, LightError>;
+}
+
+/// Answers queries about the chain from what full nodes prove
+pub struct LightClient {
+    stake: StakeTable,
+    /// Share of the total stake certificates must carry
+    quorum: Quorum,
+    latest: Option<Checkpoint>,
+    nodes: Vec<Box<dyn FullNode>>,
+}
+
+impl LightClient {
+    /// Initialize a client trusting the validators in `stake`, syncing from
+    /// the first checkpoint
+    pub fn new(stake: StakeTable, quorum: Quorum) -> Self {
+        Self {
+            stake,
+            quorum,
+            latest: None,
+            nodes: vec![],
+        }
+    }
+
+    /// Initialize a client resuming from a checkpoint it verified before
+    pub fn with_checkpoint(stake: StakeTable, quorum: Quorum, checkpoint: Checkpoint) -> Self {
+        Self {
+            latest: Some(checkpoint),
+            ..Self::new(stake, quorum)
+        }
+    }
+
+    /// Query `node` from now on
+    pub fn connect(&mut self, node: Box<dyn FullNode>) -> &mut Self {
+        self.nodes.push(node);
+        self
+    }
+
+    /// Latest checkpoint verified
+    pub fn latest(&self) -> Option<&Checkpoint> {
+        self.latest.as_ref()
+    }
+
+    /// Download the checkpoints taken since the latest one we verified. The
+    /// longest chain that verifies wins, so that a full node holding back
+    /// recent checkpoints can't keep us behind.
+    pub fn sync(&mut self) -> Result<Option<&Checkpoint>, LightError> {
+        let height = self.latest.as_ref().map_or(0, |latest| latest.height + 1);
+        let mut failure = LightError::NoFullNodes;
+        let mut longest: Option<Vec<Checkpoint>> = None;
+        for index in 0..self.nodes.len() {
+            let chain = self.nodes[index]
+                .checkpoints(height)
+                .and_then(|certificates| self.verify_chain(certificates));
+            match chain {
+                Ok(chain)
+                    if longest
+                        .as_ref()
+                        .is_none_or(|longest| longest.len() < chain.len()) =>
+                {
+                    longest = Some(chain)
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    log::warn!("Full node served invalid checkpoints: {}", e);
+                    failure = e;
+                }
+            }
+        }
+        match longest {
+            Some(chain) => {
+                if let Some(latest) = chain.into_iter().last() {
+                    log::debug!("Synced to checkpoint {:?} at {}", latest.id, latest.height);
+                    self.latest = Some(latest);
+                }
+                Ok(self.latest.as_ref())
+            }
+            None => Err(failure),
+        }
+    }
+
+    /// Check that every certificate reaches the quorum and extends the
+    /// checkpoint before it
+    fn verify_chain(
+        &self,
+        certificates: Vec<CheckpointCertificate>,
+    ) -> Result<Vec<Checkpoint>, LightError> {
+        let required = self.quorum.required(self.stake.total());
+        let mut chain: Vec<Checkpoint> = vec![];
+        for certificate in certificates {
+            let checkpoint = certificate.checkpoint.clone();
+            let previous = chain.last().or(self.latest.as_ref());
+            let extends = match previous {
+                Some(previous) => {
+                    checkpoint.height == previous.height + 1 && checkpoint.previous == previous.id
+                }
+                None => checkpoint.height == 0 && checkpoint.previous == Hash::default(),
+            };
+            if !extends || !checkpoint.verify_id() {
+                return Err(LightError::BrokenChain(checkpoint.height));
+            }
+            let certified = certificate.certified(&self.stake);
+            if certified < required {
+                return Err(LightError::InsufficientStake {
+                    height: checkpoint.height,
+                    certified,
+                    required,
+                });
+            }
+            chain.push(checkpoint);
+        }
+        Ok(chain)
+    }
+
+    /// Balance of an account at the latest checkpoint, proven by a full node
+    pub fn get_balance(&mut self, account_id: &AccountId) -> Result<Amount, LightError> {
+        let checkpoint = self.latest.clone().ok_or(LightError::NotSynced)?;
+        for node in self.nodes.iter_mut() {
+            match node.balance_proof(account_id, &checkpoint.id) {
+                Ok(Some(proof)) if proof.account_id == *account_id && proof.verify(&checkpoint) => {
+                    return Ok(proof.balance)
+                }
+                Ok(Some(_)) => log::warn!("Full node served an invalid balance proof"),
+                Ok(None) => {}
+                Err(e) => log::debug!("Failed to query a full node: {}", e),
+            }
+        }
+        Err(LightError::Unavailable)
+    }
+
+    /// Status of a transaction. It is accepted once a full node serves it
+    /// with a finality certificate of a quorum of stake, and pending while
+    /// no node can.
+    pub fn get_tx_status(&mut self, tx_id: &TxId) -> Result<TransactionStatus, LightError> {
+        if self.nodes.is_empty() {
+            return Err(LightError::NoFullNodes);
+        }
+        let mut status = TransactionStatus::None;
+        for node in self.nodes.iter_mut() {
+            let tx = match node.transaction(tx_id) {
+                Ok(Some(tx)) => tx,
+                Ok(None) => continue,
+                Err(e) => {
+                    log::debug!("Failed to query a full node: {}", e);
+                    continue;
+                }
+            };
+            let mut recomputed = tx.clone();
+            if recomputed.calculate_tx_id().is_err() || recomputed.get_tx_id() != *tx_id {
+                log::warn!("Full node served a transaction other than {}", tx_id);
+                continue;
+            }
+            status = TransactionStatus::Pending;
+            let certificate = match node.finality_certificate(tx_id) {
+                Ok(Some(certificate)) if certificate.tx_id == *tx_id => certificate,
+                Ok(_) => continue,
+                Err(e) => {
+                    log::debug!("Failed to query a full node: {}", e);
+                    continue;
+                }
+            };
+            match certificate.verify(&self.stake, self.quorum) {
+                Ok(_) => return Ok(TransactionStatus::Accepted),
+                Err(e) => log::debug!("Full node served an insufficient certificate: {}", e),
+            }
+        }
+        Ok(status)
+    }
+}
+
+#[cfg(feature = "rpc")]
+pub use self::rpc::RpcFullNode;
+
+#[cfg(feature = "rpc")]
+mod rpc {
+    use super::FullNode;
+    use crate::{error::LightError, node::codec};
+    use consensus::{
+        checkpoint::CheckpointCertificate, finality::FinalityCertificate, state::BalanceProof,
+        transaction::Transaction, AccountId, TxId,
+    };
+    use crypto::hash::Hash;
+    use serde::de::DeserializeOwned;
+    use serde_json::{json, Value};
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpStream};
+    use std::time::Duration;
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+    /// Largest response accepted from a full node
+    const MAX_RESPONSE_SIZE: u64 = 16 * 1024 * 1024;
+
+    /// Full node reached over its JSON-RPC endpoint
+    pub struct RpcFullNode {
+        addr: SocketAddr,
+        token: Option<String>,
+    }
+
+    impl RpcFullNode {
+        pub fn new(addr: SocketAddr) -> Self {
+            Self { addr, token: None }
+        }
+
+        /// Present `token` to nodes requiring authentication
+        pub fn with_token(mut self, token: impl Into<String>) -> Self {
+            self.token = Some(token.into());
+            self
+        }
+
+        /// Call a method, decoding its hex-encoded bincode result
+        fn call<T: DeserializeOwned>(
+            &self,
+            method: &str,
+            params: Value,
+        ) -> Result<Option<T>, LightError> {
+            let request = json!({"jsonrpc": "2.0", "method": method, "params": params, "id": 1});
+            let response: Value = serde_json::from_str(&self.post(&request.to_string())?)
+                .map_err(|e| LightError::FullNode(e.to_string()))?;
+            if let Some(error) = response.get("error") {
+                return Err(LightError::FullNode(error.to_string()));
+            }
+            let encoded = match response.get("result") {
+                Some(Value::String(encoded)) => encoded,
+                Some(Value::Null) => return Ok(None),
+                _ => return Err(LightError::FullNode("Unexpected result".to_string())),
+            };
+            let bytes = hex::decode(encoded).map_err(|e| LightError::FullNode(e.to_string()))?;
+            codec::decode(&bytes, MAX_RESPONSE_SIZE)
+                .map(Some)
+                .map_err(|e| LightError::FullNode(e.to_string()))
+        }
+
+        fn post(&self, body: &str) -> Result<String, LightError> {
+            let io = |e: std::io::Error| LightError::FullNode(e.to_string());
+            let mut stream = TcpStream::connect_timeout(&self.addr, TIMEOUT).map_err(io)?;
+            stream.set_read_timeout(Some(TIMEOUT)).map_err(io)?;
+            let authorization = self.token.as_ref().map_or(String::new(), |token| {
+                format!("Authorization: Bearer {}\r\n", token)
+            });
+            write!(
+                stream,
+                "POST / HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+                 {}Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                self.addr,
+                authorization,
+                body.len(),
+                body
+            )
+            .map_err(io)?;
+            let mut response = String::new();
+            let _ = stream
+                .take(MAX_RESPONSE_SIZE)
+                .read_to_string(&mut response)
+                .map_err(io)?;
+            match response.split_once("\r\n\r\n") {
+                Some((head, body)) if head.starts_with("HTTP/1.1 200") => Ok(body.to_string()),
+                Some((head, _)) => Err(LightError::FullNode(
+                    head.lines().next().unwrap_or_default().to_string(),
+                )),
+                None => Err(LightError::FullNode("Malformed HTTP response".to_string())),
+            }
+        }
+    }
+
+    impl FullNode for RpcFullNode {
+        fn checkpoints(&mut self, height: u64) -> Result<Vec<CheckpointCertificate>, LightError> {
+            Ok(self
+                .call("get_checkpoints", json!([height]))?
+                .unwrap_or_default())
+        }
+
+        fn balance_proof(
+            &mut self,
+            account_id: &AccountId,
+            checkpoint: &Hash,
+        ) -> Result<Option<BalanceProof>, LightError> {
+            self.call(
+                "get_balance_proof",
+                json!([account_id.to_hex(), checkpoint.to_hex()]),
+            )
+        }
+
+        fn transaction(&mut self, tx_id: &TxId) -> Result<Option<Transaction>, LightError> {
+            self.call("get_transaction", json!([tx_id.to_hex()]))
+        }
+
+        fn finality_certificate(
+            &mut self,
+            tx_id: &TxId,
+        ) -> Result<Option<FinalityCertificate>, LightError> {
+            self.call("get_finality_certificate", json!([tx_id.to_hex()]))
+        }
+    }
+}
+
+/// Full node serving a fixed chain, honest or not
+#[cfg(test)]
+#[derive(Clone, Default)]
+struct TestNode {
+    certificates: Vec<CheckpointCertificate>,
+    proofs: Vec<BalanceProof>,
+    transactions: Vec<Transaction>,
+    finality: Vec<FinalityCertificate>,
+}
+
+#[cfg(test)]
+impl FullNode for TestNode {
+    fn checkpoints(&mut self, height: u64) -> Result<Vec<CheckpointCertificate>, LightError> {
+        Ok(self
+            .certificates
+            .iter()
+            .filter(|certificate| certificate.checkpoint.height >= height)
+            .cloned()
+            .collect())
+    }
+
+    fn balance_proof(
+        &mut self,
+        account_id: &AccountId,
+        checkpoint: &Hash,
+    ) -> Result<Option<BalanceProof>, LightError> {
+        Ok(self
+            .proofs
+            .iter()
+            .find(|proof| proof.account_id == *account_id && proof.checkpoint == *checkpoint)
+            .cloned())
+    }
+
+    fn transaction(&mut self, tx_id: &TxId) -> Result<Option<Transaction>, LightError> {
+        Ok(self
+            .transactions
+            .iter()
+            .find(|tx| tx.get_tx_id() == *tx_id)
+            .cloned())
+    }
+
+    fn finality_certificate(
+        &mut self,
+        tx_id: &TxId,
+    ) -> Result<Option<FinalityCertificate>, LightError> {
+        Ok(self
+            .finality
+            .iter()
+            .find(|certificate| certificate.tx_id == *tx_id)
+            .cloned())
+    }
+}
+
+#[test]
+fn test_light_client_verifies_what_full_nodes_serve() {
+    use consensus::{account::Account, state::StateTrie, transaction::TransactionType};
+    use crypto::signature::PrivateKey;
+
+    let validators = (0..3).map(|_| PrivateKey::generate()).collect::<Vec<_>>();
+    let mut stake = StakeTable::new();
+    for validator in &validators {
+        let _ = stake.insert(validator.public_key(), 10);
+    }
+    let certify = |checkpoint: &Checkpoint, signers: &[PrivateKey]| {
+        let mut certificate = CheckpointCertificate::new(checkpoint.clone());
+        for signer in signers {
+            let _ = certificate.sign(signer).unwrap();
+        }
+        certificate
+    };
+
+    let mut account = Account::create(&Hash::new("A".as_bytes()).into(), &Hash::default().into());
+    account.increase_balance(Amount::new(42)).unwrap();
+    let mut state = StateTrie::new();
+    state.insert(&account);
+    let first = Checkpoint::new(None, StateTrie::new().root(), TxId::default(), 0).unwrap();
+    let second = Checkpoint::new(Some(&first), state.root(), TxId::default(), 1).unwrap();
+
+    let mut tx = Transaction::new(
+        TxId::default(),
+        account.clone(),
+        Hash::new("B".as_bytes()).into(),
+        Amount::new(5),
+        TransactionType::Transfer,
+        vec![],
+    );
+    tx.calculate_tx_id().unwrap();
+    let mut finality = FinalityCertificate::new(tx.get_tx_id());
+    for validator in &validators[..2] {
+        let _ = finality.sign(&stake, validator).unwrap();
+    }
+    let mut weak_finality = FinalityCertificate::new(tx.get_tx_id());
+    let _ = weak_finality.sign(&stake, &validators[0]).unwrap();
+
+    let honest = TestNode {
+        certificates: vec![
+            certify(&first, &validators),
+            certify(&second, &validators[..2]),
+        ],
+        proofs: vec![state.prove_balance(&second, &account.id).unwrap()],
+        transactions: vec![tx.clone()],
+        finality: vec![finality],
+    };
+    // Holds back the latest checkpoint, and lies about balances
+    let mut lagging = TestNode {
+        certificates: honest.certificates[..1].to_vec(),
+        ..honest.clone()
+    };
+    lagging.proofs[0].balance = Amount::new(1_000);
+    // Serves checkpoints signed by too little stake
+    let weak = TestNode {
+        certificates: vec![certify(&first, &validators[..1])],
+        ..Default::default()
+    };
+
+    let mut client = LightClient::new(stake.clone(), Quorum::TWO_THIRDS);
+    assert_eq!(client.sync().err(), Some(LightError::NoFullNodes));
+    let _ = client.connect(Box::new(weak));
+    assert_eq!(
+        client.sync().err(),
+        Some(LightError::InsufficientStake {
+            height: 0,
+            certified: 10,
+            required: 20
+        })
+    );
+    let _ = client
+        .connect(Box::new(lagging))
+        .connect(Box::new(honest.clone()));
+    assert_eq!(client.sync().unwrap(), Some(&second));
+    assert_eq!(client.get_balance(&account.id), Ok(Amount::new(42)));
+    assert_eq!(
+        client.get_balance(&Hash::new("B".as_bytes()).into()),
+        Err(LightError::Unavailable)
+    );
+    assert_eq!(
+        client.get_tx_status(&tx.get_tx_id()),
+        Ok(TransactionStatus::Accepted)
+    );
+
+    // A checkpoint that doesn't extend ours is refused
+    let mut resumed =
+        LightClient::with_checkpoint(stake.clone(), Quorum::TWO_THIRDS, first.clone());
+    let fork = Checkpoint::new(None, state.root(), TxId::default(), 1).unwrap();
+    let forked = Checkpoint::new(Some(&fork), state.root(), TxId::default(), 1).unwrap();
+    let _ = resumed.connect(Box::new(TestNode {
+        certificates: vec![certify(&forked, &validators)],
+        ..Default::default()
+    }));
+    assert_eq!(resumed.sync().err(), Some(LightError::BrokenChain(1)));
+
+    // Transactions without a quorum certificate stay pending
+    let mut uncertified = LightClient::new(stake, Quorum::TWO_THIRDS);
+    let _ = uncertified.connect(Box::new(TestNode {
+        transactions: vec![tx.clone()],
+        finality: vec![weak_finality],
+        ..Default::default()
+    }));
+    assert_eq!(
+        uncertified.get_tx_status(&tx.get_tx_id()),
+        Ok(TransactionStatus::Pending)
+    );
+    assert_eq!(
+        uncertified.get_tx_status(&TxId::default()),
+        Ok(TransactionStatus::None)
+    );
+}
+
+#[cfg(all(test, feature = "rpc"))]
+struct TestHandler(TestNode);
+
+#[cfg(all(test, feature = "rpc"))]
+impl crate::node::rpc::RpcHandler for TestHandler {
+    fn submit_transaction(&self, _tx: Transaction) -> Result<TxId, crate::node::rpc::RpcError> {
+        unimplemented!()
+    }
+
+    fn get_account(&self, _account_id: &AccountId) -> Option<consensus::account::Account> {
+        None
+    }
+
+    fn get_peers(&self) -> Vec<consensus::NodeId> {
+        vec![]
+    }
+
+    fn get_transaction_status(&self, _tx_id: &TxId) -> Option<TransactionStatus> {
+        None
+    }
+
+    fn get_checkpoints(&self, height: u64) -> Vec<CheckpointCertificate> {
+        self.0.clone().checkpoints(height).unwrap()
+    }
+
+    fn get_balance_proof(&self, account_id: &AccountId, checkpoint: &Hash) -> Option<BalanceProof> {
+        self.0
+            .clone()
+            .balance_proof(account_id, checkpoint)
+            .unwrap()
+    }
+
+    fn get_transaction(&self, tx_id: &TxId) -> Option<Transaction> {
+        self.0.clone().transaction(tx_id).unwrap()
+    }
+
+    fn get_finality_certificate(&self, tx_id: &TxId) -> Option<FinalityCertificate> {
+        self.0.clone().finality_certificate(tx_id).unwrap()
+    }
+}
+
+#[cfg(feature = "rpc")]
+#[test]
+fn test_light_client_over_rpc() {
+    use crate::node::rpc::RpcServer;
+    use consensus::{account::Account, state::StateTrie};
+    use crypto::signature::PrivateKey;
+    use std::sync::Arc;
+
+    let validator = PrivateKey::generate();
+    let mut stake = StakeTable::new();
+    let _ = stake.insert(validator.public_key(), 1);
+    let mut account = Account::create(&Hash::new("A".as_bytes()).into(), &Hash::default().into());
+    account.increase_balance(Amount::new(7)).unwrap();
+    let mut state = StateTrie::new();
+    state.insert(&account);
+    let checkpoint = Checkpoint::new(None, state.root(), TxId::default(), 1).unwrap();
+    let mut certificate = CheckpointCertificate::new(checkpoint.clone());
+    let _ = certificate.sign(&validator).unwrap();
+    let node = TestNode {
+        certificates: vec![certificate],
+        proofs: vec![state.prove_balance(&checkpoint, &account.id).unwrap()],
+        transactions: vec![],
+        finality: vec![],
+    };
+
+    let server = RpcServer::start(
+        std::net::SocketAddr::from(([127, 0, 0, 1], 0)),
+        Arc::new(TestHandler(node)),
+    )
+    .unwrap();
+    let mut client = LightClient::new(stake, Quorum::new(1, 1));
+    let _ = client.connect(Box::new(RpcFullNode::new(server.local_addr())));
+    assert_eq!(client.sync().unwrap(), Some(&checkpoint));
+    assert_eq!(client.get_balance(&account.id), Ok(Amount::new(7)));
+    assert_eq!(
+        client.get_tx_status(&TxId::default()),
+        Ok(TransactionStatus::None)
+    );
+}
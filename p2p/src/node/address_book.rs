@@ -0,0 +1,219 @@
+use consensus::{network::CommonConsensusNetwork, NodeId};
+use crypto::hash::Hash;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+
+/// Network prefix shared by addresses likely under the same operator:
+/// the /16 of IPv4 addresses and the /32 of IPv6 ones
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Subnet {
+    V4([u8; 2]),
+    V6([u16; 2]),
+}
+
+impl From<&SocketAddr> for Subnet {
+    fn from(addr: &SocketAddr) -> Self {
+        match addr.ip() {
+            IpAddr::V4(ip) => {
+                let [a, b, _, _] = ip.octets();
+                Subnet::V4([a, b])
+            }
+            IpAddr::V6(ip) => {
+                let segments = ip.segments();
+                Subnet::V6([segments[0], segments[1]])
+            }
+        }
+    }
+}
+
+/// Spread of the connected peers across subnets
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Diversity {
+    /// Distinct subnets peers connect from
+    pub subnets: usize,
+    /// Peers in the most represented subnet
+    pub largest: usize,
+}
+
+/// Addresses of the peers we are connected to
+#[derive(Clone, Debug, Default)]
+pub struct AddressBook {
+    peers: HashMap<NodeId, SocketAddr>,
+}
+
+impl AddressBook {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, peer: NodeId, addr: SocketAddr) {
+        let _ = self.peers.insert(peer, addr);
+    }
+
+    pub fn remove(&mut self, peer: &NodeId) -> Option<SocketAddr> {
+        self.peers.remove(peer)
+    }
+
+    pub fn get(&self, peer: &NodeId) -> Option<&SocketAddr> {
+        self.peers.get(peer)
+    }
+
+    pub fn peers(&self) -> impl Iterator<Item = NodeId> + '_ {
+        self.peers.keys().copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    /// Number of peers in the subnet of `addr`
+    pub fn subnet_count(&self, addr: &SocketAddr) -> usize {
+        let subnet = Subnet::from(addr);
+        self.peers
+            .values()
+            .filter(|peer| Subnet::from(*peer) == subnet)
+            .count()
+    }
+
+    pub fn diversity(&self) -> Diversity {
+        let counts = self.subnet_counts();
+        Diversity {
+            subnets: counts.len(),
+            largest: counts.values().copied().max().unwrap_or(0),
+        }
+    }
+
+    /// Sample up to `k` peers other than `except`, spread over as many
+    /// subnets as possible: one peer per subnet is taken in turn before a
+    /// subnet contributes a second one
+    pub fn diverse_sample(&self, k: usize, except: &NodeId) -> Vec<NodeId> {
+        self.diverse_sample_where(k, |peer| peer != except)
+    }
+
+    /// Same as [`AddressBook::diverse_sample`], among the peers `eligible`
+    /// holds for
+    pub fn diverse_sample_where(
+        &self,
+        k: usize,
+        eligible: impl Fn(&NodeId) -> bool,
+    ) -> Vec<NodeId> {
+        self.diverse_sample_weighted(k, |peer| u64::from(eligible(peer)))
+    }
+
+    /// Same as [`AddressBook::diverse_sample`], the peers of a subnet being
+    /// taken in a random order where heavier ones tend to come first. Peers
+    /// weighing 0 are left out.
+    pub fn diverse_sample_weighted(
+        &self,
+        k: usize,
+        weight: impl Fn(&NodeId) -> u64,
+    ) -> Vec<NodeId> {
+        let mut by_subnet: HashMap<Subnet, Vec<(NodeId, u64)>> = HashMap::new();
+        for (peer, addr) in &self.peers {
+            let weight = weight(peer);
+            if weight > 0 {
+                by_subnet
+                    .entry(Subnet::from(addr))
+                    .or_default()
+                    .push((*peer, weight));
+            }
+        }
+        // Random order, both within and across subnets
+        let mut subnets = by_subnet
+            .into_values()
+            .map(weighted_shuffle)
+            .collect::<Vec<_>>();
+        shuffle(&mut subnets);
+
+        let mut sample = vec![];
+        let mut round = 0;
+        while sample.len() < k {
+            let picked = subnets
+                .iter()
+                .filter_map(|peers| peers.get(round))
+                .take(k - sample.len())
+                .copied()
+                .collect::<Vec<_>>();
+            if picked.is_empty() {
+                break;
+            }
+            sample.extend(picked);
+            round += 1;
+        }
+        sample
+    }
+
+    fn subnet_counts(&self) -> HashMap<Subnet, usize> {
+        let mut counts = HashMap::new();
+        for addr in self.peers.values() {
+            *counts.entry(Subnet::from(addr)).or_insert(0) += 1;
+        }
+        counts
+    }
+}
+
+impl CommonConsensusNetwork for AddressBook {
+    fn get_nodes_except_one(&self, k: u64, node_id: NodeId) -> Vec<NodeId> {
+        self.diverse_sample(k as usize, &node_id)
+    }
+}
+
+/// Shuffle items in place
+pub(super) fn shuffle<T>(items: &mut Vec<T>) {
+    let mut keyed = items
+        .drain(..)
+        .map(|item| (Hash::generate_random(), item))
+        .collect::<Vec<_>>();
+    keyed.sort_by_key(|(key, _)| *key);
+    items.extend(keyed.into_iter().map(|(_, item)| item));
+}
+
+/// Random order of weighted items, each item coming before another with a
+/// probability proportional to its weight
+fn weighted_shuffle<T>(items: Vec<(T, u64)>) -> Vec<T> {
+    let mut keyed = items
+        .into_iter()
+        .map(|(item, weight)| {
+            let random = Hash::generate_random();
+            let unit =
+                u64::from_le_bytes(random.0[..8].try_into().unwrap()) as f64 / u64::MAX as f64;
+            (unit.powf(1.0 / weight as f64), item)
+        })
+        .collect::<Vec<_>>();
+    keyed.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+    keyed.into_iter().map(|(_, item)| item).collect()
+}
+
+#[test]
+fn test_diverse_sample() {
+    let mut book = AddressBook::new();
+    let peer = |i: u8| NodeId::from(Hash::new(&[i]));
+    // Four peers in 10.0.0.0/16, one in each of two other subnets
+    for i in 0..4 {
+        book.insert(peer(i), SocketAddr::from(([10, 0, i, 1], 5000)));
+    }
+    book.insert(peer(4), SocketAddr::from(([10, 1, 0, 1], 5000)));
+    book.insert(peer(5), SocketAddr::from(([192, 168, 0, 1], 5000)));
+
+    assert_eq!(
+        book.diversity(),
+        Diversity {
+            subnets: 3,
+            largest: 4
+        }
+    );
+    assert_eq!(book.subnet_count(&SocketAddr::from(([10, 0, 9, 9], 1))), 4);
+
+    let sample = book.diverse_sample(3, &NodeId::default());
+    assert_eq!(sample.len(), 3);
+    assert!(sample.contains(&peer(4)));
+    assert!(sample.contains(&peer(5)));
+
+    let sample = book.diverse_sample(10, &peer(5));
+    assert_eq!(sample.len(), 5);
+    assert!(!sample.contains(&peer(5)));
+}
@@ -0,0 +1,297 @@
+//! Administration of remote nodes over the p2p layer.
+//!
+//! Fleets managed without exposing the RPC endpoint are administered by an
+//! operator key instead: the operator signs an [`AdminRequest`] for one of
+//! its nodes, which the node carries out if the key is trusted. Requests
+//! name the node and network they are meant for and carry a nonce, the time they were
+//! signed at in microseconds: nodes only accept nonces larger than the last
+//! one they accepted from the same operator, and close to their own clock,
+//! so that captured requests can neither be replayed nor held back to be
+//! sent later, even to a node that restarted since. Accepted and refused
+//! requests are logged under the `dagchain::audit` target.
+
+use super::{
+    identity::{Identity, PublicId},
+    message::Message,
+};
+use crate::error::{AuthError, P2pError};
+use consensus::NodeId;
+use crypto::{hash::Hash, signature::Signature};
+use log::LevelFilter;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub(super) const AUDIT_TARGET: &str = "dagchain::audit";
+
+/// Domain of the signed requests
+const ADMIN_DOMAIN: &[u8] = b"dagchain:admin";
+/// Furthest a nonce may be from our clock
+const MAX_CLOCK_SKEW: Duration = Duration::from_secs(300);
+
+/// Commands an operator can send its nodes
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum AdminCommand {
+    /// Log at `level` from now on
+    SetLogLevel(LevelFilter),
+    /// Write a snapshot of the engine state and the DAG
+    Snapshot,
+    /// Stop taking submissions, finish the rounds in flight and shut down
+    Drain,
+    /// Stop answering and sending consensus queries, e.g. for maintenance
+    PauseConsensus,
+    /// Take part in consensus again
+    ResumeConsensus,
+}
+
+/// Command signed by an operator for one node
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct AdminRequest {
+    pub command: AdminCommand,
+    /// Node the command is meant for
+    pub target: NodeId,
+    /// Network of the target
+    pub network_id: Hash,
+    /// Microseconds since the Unix epoch when the request was signed
+    pub nonce: u64,
+    pub sender: PublicId,
+    signature: Signature,
+}
+
+impl AdminRequest {
+    /// Sign `command` for `target` on `network_id` as of now
+    pub fn sign(
+        command: AdminCommand,
+        target: NodeId,
+        network_id: Hash,
+        identity: &Identity,
+    ) -> Result<Self, P2pError> {
+        let nonce = unix_time().as_micros() as u64;
+        Self::sign_with_nonce(command, target, network_id, nonce, identity)
+    }
+
+    pub fn sign_with_nonce(
+        command: AdminCommand,
+        target: NodeId,
+        network_id: Hash,
+        nonce: u64,
+        identity: &Identity,
+    ) -> Result<Self, P2pError> {
+        let bytes = signed_bytes(&command, &target, &network_id, nonce)?;
+        let signature = identity.sign_message(&bytes);
+        Ok(Self {
+            command,
+            target,
+            network_id,
+            nonce,
+            sender: identity.get_public_id(),
+            signature,
+        })
+    }
+
+    /// Wrap the request into a message
+    pub fn into_message(self) -> Message {
+        Message::AdminRequest(self)
+    }
+
+    /// Check the signature, returning the operator that signed the request
+    pub fn verify(&self) -> Result<NodeId, P2pError> {
+        let bytes = signed_bytes(&self.command, &self.target, &self.network_id, self.nonce)?;
+        if !self.signature.verify(&self.sender.public_key, &bytes) {
+            return Err(P2pError::InvalidSignature);
+        }
+        Hash::serialize(&self.sender.public_key)
+            .map(NodeId::from)
+            .map_err(P2pError::CryptoError)
+    }
+}
+
+fn signed_bytes(
+    command: &AdminCommand,
+    target: &NodeId,
+    network_id: &Hash,
+    nonce: u64,
+) -> Result<Vec<u8>, P2pError> {
+    let mut bytes = ADMIN_DOMAIN.to_vec();
+    bytes.extend(
+        bincode::serialize(&(command, target, network_id, nonce))
+            .map_err(P2pError::BincodeError)?,
+    );
+    Ok(bytes)
+}
+
+#[derive(Debug, Default)]
+struct Operators {
+    /// Last nonce accepted from each trusted operator
+    last_nonces: HashMap<NodeId, u64>,
+}
+
+/// Checks the admin requests a node receives. Clones share the same
+/// operators and nonces.
+#[derive(Clone, Debug)]
+pub struct AdminGuard {
+    /// Our ID, which requests must be addressed to
+    id: NodeId,
+    /// Our network, which requests must be signed for
+    network_id: Hash,
+    operators: Arc<RwLock<Operators>>,
+}
+
+impl AdminGuard {
+    pub fn new(id: NodeId, network_id: Hash) -> Self {
+        Self {
+            id,
+            network_id,
+            operators: Default::default(),
+        }
+    }
+
+    /// Accept requests signed by `operator`
+    pub fn trust_operator(&self, operator: &PublicId) -> Result<&Self, P2pError> {
+        let id = Hash::serialize(&operator.public_key)
+            .map(NodeId::from)
+            .map_err(P2pError::CryptoError)?;
+        let _ = self
+            .operators
+            .write()
+            .unwrap()
+            .last_nonces
+            .entry(id)
+            .or_default();
+        Ok(self)
+    }
+
+    /// Stop accepting requests signed by `operator`
+    pub fn distrust_operator(&self, operator: &PublicId) -> Result<bool, P2pError> {
+        let id = Hash::serialize(&operator.public_key)
+            .map(NodeId::from)
+            .map_err(P2pError::CryptoError)?;
+        Ok(self
+            .operators
+            .write()
+            .unwrap()
+            .last_nonces
+            .remove(&id)
+            .is_some())
+    }
+
+    /// Check a request, returning the operator that sent it. Both accepted
+    /// and refused requests are audited.
+    pub fn check(&self, request: &AdminRequest) -> Result<NodeId, P2pError> {
+        let result = self.check_at(request, unix_time());
+        match &result {
+            Ok(operator) => log::info!(
+                target: AUDIT_TARGET,
+                "Admin command {:?} from operator {:?} accepted",
+                request.command,
+                operator
+            ),
+            Err(e) => log::warn!(
+                target: AUDIT_TARGET,
+                "Refused admin command {:?}: {}",
+                request.command,
+                e
+            ),
+        }
+        result
+    }
+
+    fn check_at(&self, request: &AdminRequest, now: Duration) -> Result<NodeId, P2pError> {
+        let operator = request.verify()?;
+        if request.target != self.id {
+            return Err(AuthError::Misdirected.into());
+        }
+        if request.network_id != self.network_id {
+            return Err(P2pError::NetworkMismatch {
+                ours: self.network_id,
+                theirs: request.network_id,
+            });
+        }
+        let skew = Duration::from_micros(request.nonce.abs_diff(now.as_micros() as u64));
+        if skew > MAX_CLOCK_SKEW {
+            return Err(AuthError::StaleNonce(request.nonce).into());
+        }
+        let mut operators = self.operators.write().unwrap();
+        let last = operators
+            .last_nonces
+            .get_mut(&operator)
+            .ok_or(AuthError::UntrustedOperator)?;
+        if request.nonce <= *last {
+            return Err(AuthError::Replayed {
+                nonce: request.nonce,
+                last: *last,
+            }
+            .into());
+        }
+        *last = request.nonce;
+        Ok(operator)
+    }
+}
+
+fn unix_time() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+#[test]
+fn test_admin_requests_are_not_replayed() {
+    let operator = Identity::new();
+    let node = NodeId::from(Hash::new("node".as_bytes()));
+    let guard = AdminGuard::new(node, Hash::default());
+    let now = unix_time();
+    let nonce = now.as_micros() as u64;
+    let sign = |command, target, nonce| {
+        AdminRequest::sign_with_nonce(command, target, Hash::default(), nonce, &operator).unwrap()
+    };
+
+    let request = sign(AdminCommand::Snapshot, node, nonce);
+    assert!(matches!(
+        guard.check_at(&request, now),
+        Err(P2pError::AuthError(AuthError::UntrustedOperator))
+    ));
+    let _ = guard.trust_operator(&operator.get_public_id()).unwrap();
+    assert_eq!(
+        guard.check_at(&request, now).unwrap(),
+        operator.get_our_hash().unwrap()
+    );
+    assert!(matches!(
+        guard.check_at(&request, now),
+        Err(P2pError::AuthError(AuthError::Replayed { .. }))
+    ));
+
+    let other = NodeId::from(Hash::new("other".as_bytes()));
+    let misdirected = sign(AdminCommand::Drain, other, nonce + 1);
+    assert!(matches!(
+        guard.check_at(&misdirected, now),
+        Err(P2pError::AuthError(AuthError::Misdirected))
+    ));
+    let testnet = Hash::new("testnet".as_bytes());
+    let other_network =
+        AdminRequest::sign_with_nonce(AdminCommand::Drain, node, testnet, nonce + 1, &operator)
+            .unwrap();
+    assert!(matches!(
+        guard.check_at(&other_network, now),
+        Err(P2pError::NetworkMismatch { .. })
+    ));
+    let stale = sign(AdminCommand::Drain, node, nonce + 1);
+    assert!(matches!(
+        guard.check_at(&stale, now + 2 * MAX_CLOCK_SKEW),
+        Err(P2pError::AuthError(AuthError::StaleNonce(_)))
+    ));
+    let mut forged = sign(
+        AdminCommand::SetLogLevel(LevelFilter::Debug),
+        node,
+        nonce + 1,
+    );
+    forged.command = AdminCommand::Drain;
+    assert!(matches!(
+        guard.check_at(&forged, now),
+        Err(P2pError::InvalidSignature)
+    ));
+
+    assert!(guard.distrust_operator(&operator.get_public_id()).unwrap());
+    let later = sign(AdminCommand::Snapshot, node, nonce + 2);
+    assert!(guard.check_at(&later, now).is_err());
+}
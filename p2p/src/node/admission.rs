@@ -0,0 +1,144 @@
+//! Admission control of incoming connections.
+//!
+//! Before we identify ourselves to a peer that connected to us, an
+//! [`AdmissionPolicy`] decides whether to go on with the handshake. Embedders
+//! plug in their own rules, e.g. by region or by customer, through
+//! [`Connection::set_admission_policy`]; [`open`], [`allowlist`] and
+//! [`rate_limited`] cover the common cases.
+//!
+//! [`Connection::set_admission_policy`]: super::connection::Connection::set_admission_policy
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// What we know of a peer connecting to us, before the handshake
+#[derive(Clone, Debug)]
+pub struct IncomingPeerInfo {
+    pub addr: SocketAddr,
+    /// Connections we hold, the incoming one excluded
+    pub connections: usize,
+    /// Connections we hold to peers in the subnet of `addr`
+    pub subnet_connections: usize,
+    pub now: Instant,
+}
+
+/// Decision of an admission policy
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Admission {
+    /// Go on with the handshake
+    Accept,
+    /// Disconnect from the peer
+    Reject,
+    /// Turn the peer away for now, pointing it to our other peers as when
+    /// our connections are full
+    Throttle,
+}
+
+/// Hook deciding whether to admit an incoming connection
+pub type AdmissionPolicy = Box<dyn Fn(&IncomingPeerInfo) -> Admission + Send + Sync>;
+
+/// Admit every peer
+pub fn open() -> AdmissionPolicy {
+    Box::new(|_| Admission::Accept)
+}
+
+/// Admit only the peers connecting from `ips`
+pub fn allowlist(ips: impl IntoIterator<Item = IpAddr>) -> AdmissionPolicy {
+    let ips = ips.into_iter().collect::<HashSet<_>>();
+    Box::new(move |peer| {
+        if ips.contains(&peer.addr.ip()) {
+            Admission::Accept
+        } else {
+            Admission::Reject
+        }
+    })
+}
+
+/// Admit at most `max` connections from the same IP address per `window`,
+/// throttling the ones beyond
+pub fn rate_limited(max: usize, window: Duration) -> AdmissionPolicy {
+    let admitted = Mutex::new(HashMap::<IpAddr, VecDeque<Instant>>::new());
+    Box::new(move |peer| {
+        let mut admitted = admitted.lock().unwrap();
+        admitted.retain(|_, times| {
+            while times
+                .front()
+                .is_some_and(|time| peer.now.saturating_duration_since(*time) >= window)
+            {
+                let _ = times.pop_front();
+            }
+            !times.is_empty()
+        });
+        let times = admitted.entry(peer.addr.ip()).or_default();
+        if times.len() >= max {
+            return Admission::Throttle;
+        }
+        times.push_back(peer.now);
+        Admission::Accept
+    })
+}
+
+#[test]
+fn test_incoming_peers_are_admitted_by_policy() {
+    use super::{config::TransportKind, connection::Connection};
+    use crate::{error::P2pError, transport::Transport};
+    use bytes::Bytes;
+    use quic_p2p::Peer;
+
+    /// Transport recording the peers we disconnect from
+    #[derive(Default)]
+    struct Recorder {
+        disconnected: Vec<SocketAddr>,
+    }
+
+    impl Transport for Recorder {
+        fn kind(&self) -> TransportKind {
+            TransportKind::Tcp
+        }
+
+        fn our_addr(&mut self) -> Result<SocketAddr, P2pError> {
+            Ok(([127, 0, 0, 1], 9000).into())
+        }
+
+        fn connect_to(&mut self, _peer: SocketAddr) {}
+
+        fn disconnect_from(&mut self, peer: SocketAddr) {
+            self.disconnected.push(peer);
+        }
+
+        fn send(&mut self, _peer: SocketAddr, _msg: Bytes, _token: u64) {}
+    }
+
+    let now = Instant::now();
+    let peer = |addr: SocketAddr, now: Instant| IncomingPeerInfo {
+        addr,
+        connections: 0,
+        subnet_connections: 0,
+        now,
+    };
+    let friend: SocketAddr = ([10, 0, 0, 1], 9000).into();
+    let stranger: SocketAddr = ([10, 0, 1, 1], 9000).into();
+
+    let limited = rate_limited(2, Duration::from_secs(10));
+    assert_eq!(limited(&peer(friend, now)), Admission::Accept);
+    assert_eq!(limited(&peer(friend, now)), Admission::Accept);
+    assert_eq!(limited(&peer(friend, now)), Admission::Throttle);
+    assert_eq!(limited(&peer(stranger, now)), Admission::Accept);
+    let later = now + Duration::from_secs(10);
+    assert_eq!(limited(&peer(friend, later)), Admission::Accept);
+
+    let mut connection = Connection::new();
+    let _ = connection.set_admission_policy(allowlist(vec![friend.ip()]));
+    let mut transport = Recorder::default();
+    for addr in [friend, stranger] {
+        connection
+            .handle_successful_connection(&Peer::Node(addr), &mut transport)
+            .unwrap();
+    }
+    assert!(connection.our_connections().contains_key(&friend));
+    assert!(!connection.our_connections().contains_key(&stranger));
+    assert_eq!(transport.disconnected, vec![stranger]);
+    assert_eq!(open()(&peer(stranger, now)), Admission::Accept);
+}
@@ -0,0 +1,274 @@
+//! Authentication of remote administration.
+//!
+//! Callers present either a static token configured on the node or a
+//! capability token signed by a trusted issuer. Either grants a [`Scope`],
+//! checked against the scope a method requires.
+
+use super::identity::{Identity, PublicId};
+use crate::error::{AuthError, P2pError};
+use crypto::{hash::Hash, signature::Signature};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::RwLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Set of methods a token gives access to
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub enum Scope {
+    /// Queries that do not change the node state
+    ReadOnly,
+    /// Every method, including those changing the node state
+    Operator,
+}
+
+impl Scope {
+    /// Whether this scope covers methods requiring `required`
+    pub fn allows(self, required: Scope) -> bool {
+        self >= required
+    }
+}
+
+/// Capability token signed by an issuer, granting a scope until it expires
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct CapabilityToken {
+    pub id: Hash,
+    pub scope: Scope,
+    /// UNIX time after which the token is refused
+    pub expires_at: Duration,
+    pub issuer: PublicId,
+    pub signature: Signature,
+}
+
+impl CapabilityToken {
+    /// Issue a token valid for `validity` from now
+    pub fn issue(identity: &Identity, scope: Scope, validity: Duration) -> Result<Self, P2pError> {
+        let id = Hash::generate_random();
+        let expires_at = unix_time() + validity;
+        let issuer = identity.get_public_id();
+        let bytes = Self::signed_bytes(&id, scope, expires_at, &issuer)?;
+        Ok(Self {
+            id,
+            scope,
+            expires_at,
+            issuer,
+            signature: identity.sign_message(&bytes),
+        })
+    }
+
+    /// Encode the token for transport, e.g. in an `Authorization` header
+    pub fn encode(&self) -> Result<String, P2pError> {
+        let buffer = bincode::serialize(self).map_err(P2pError::BincodeError)?;
+        Ok(multibase::encode(multibase::Base::Base32Z, buffer))
+    }
+
+    pub fn decode(encoded: &str) -> Result<Self, AuthError> {
+        let (_base, bytes) = multibase::decode(encoded).map_err(|_| AuthError::InvalidToken)?;
+        bincode::deserialize(&bytes).map_err(|_| AuthError::InvalidToken)
+    }
+
+    /// Check the signature and expiry of the token
+    pub fn verify(&self) -> Result<(), AuthError> {
+        let bytes = Self::signed_bytes(&self.id, self.scope, self.expires_at, &self.issuer)
+            .map_err(|_| AuthError::InvalidToken)?;
+        if !self.signature.verify(&self.issuer.public_key, bytes) {
+            return Err(AuthError::InvalidToken);
+        }
+        if unix_time() >= self.expires_at {
+            return Err(AuthError::Expired);
+        }
+        Ok(())
+    }
+
+    fn signed_bytes(
+        id: &Hash,
+        scope: Scope,
+        expires_at: Duration,
+        issuer: &PublicId,
+    ) -> Result<Vec<u8>, P2pError> {
+        bincode::serialize(&(id, scope, expires_at, issuer)).map_err(P2pError::BincodeError)
+    }
+}
+
+/// Access granted to an authenticated caller
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Grant {
+    pub scope: Scope,
+    /// ID of the capability token, or hash of the static token, used
+    pub subject: Hash,
+}
+
+#[derive(Default)]
+struct AuthState {
+    /// Scopes of static tokens, by token hash
+    static_tokens: HashMap<Hash, Scope>,
+    /// Hashes of the keys trusted to issue capability tokens
+    issuers: HashSet<Hash>,
+    /// Capability tokens revoked before their expiry
+    revoked: HashSet<Hash>,
+}
+
+/// Checks the tokens presented to the RPC and admin layers.
+///
+/// Tokens and issuers can be added and removed while serving, so keys and
+/// tokens can be rotated without a restart: trust the new one, hand it out,
+/// then drop the old one.
+#[derive(Default)]
+pub struct Authenticator {
+    state: RwLock<AuthState>,
+}
+
+impl Authenticator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_static_token(&self, token: &str, scope: Scope) -> &Self {
+        let _ = self
+            .state
+            .write()
+            .unwrap()
+            .static_tokens
+            .insert(Hash::new(token.as_bytes()), scope);
+        self
+    }
+
+    /// Stop accepting a static token, returning whether it was known
+    pub fn remove_static_token(&self, token: &str) -> bool {
+        self.state
+            .write()
+            .unwrap()
+            .static_tokens
+            .remove(&Hash::new(token.as_bytes()))
+            .is_some()
+    }
+
+    /// Accept capability tokens signed by `issuer`
+    pub fn trust_issuer(&self, issuer: &PublicId) -> Result<&Self, P2pError> {
+        let hash = Hash::serialize(&issuer.public_key).map_err(P2pError::CryptoError)?;
+        let _ = self.state.write().unwrap().issuers.insert(hash);
+        Ok(self)
+    }
+
+    /// Stop accepting capability tokens signed by `issuer`
+    pub fn distrust_issuer(&self, issuer: &PublicId) -> Result<bool, P2pError> {
+        let hash = Hash::serialize(&issuer.public_key).map_err(P2pError::CryptoError)?;
+        Ok(self.state.write().unwrap().issuers.remove(&hash))
+    }
+
+    /// Refuse a capability token before it expires
+    pub fn revoke(&self, token_id: &Hash) {
+        let _ = self.state.write().unwrap().revoked.insert(*token_id);
+    }
+
+    /// Check a static or encoded capability token
+    pub fn authenticate(&self, token: &str) -> Result<Grant, AuthError> {
+        let state = self.state.read().unwrap();
+        let token_hash = Hash::new(token.as_bytes());
+        if let Some(scope) = state.static_tokens.get(&token_hash) {
+            return Ok(Grant {
+                scope: *scope,
+                subject: token_hash,
+            });
+        }
+        let capability = CapabilityToken::decode(token)?;
+        let issuer =
+            Hash::serialize(&capability.issuer.public_key).map_err(|_| AuthError::InvalidToken)?;
+        if !state.issuers.contains(&issuer) {
+            return Err(AuthError::UntrustedIssuer);
+        }
+        if state.revoked.contains(&capability.id) {
+            return Err(AuthError::Revoked);
+        }
+        capability.verify()?;
+        Ok(Grant {
+            scope: capability.scope,
+            subject: capability.id,
+        })
+    }
+
+    /// Check that a token grants `required`
+    pub fn authorize(&self, token: Option<&str>, required: Scope) -> Result<Grant, AuthError> {
+        let grant = self.authenticate(token.ok_or(AuthError::MissingToken)?)?;
+        if !grant.scope.allows(required) {
+            return Err(AuthError::InsufficientScope {
+                required,
+                granted: grant.scope,
+            });
+        }
+        Ok(grant)
+    }
+}
+
+fn unix_time() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+#[test]
+fn test_static_tokens() {
+    let auth = Authenticator::new();
+    auth.add_static_token("reader", Scope::ReadOnly)
+        .add_static_token("operator", Scope::Operator);
+
+    assert!(auth.authorize(Some("reader"), Scope::ReadOnly).is_ok());
+    assert_eq!(
+        auth.authorize(Some("reader"), Scope::Operator),
+        Err(AuthError::InsufficientScope {
+            required: Scope::Operator,
+            granted: Scope::ReadOnly,
+        })
+    );
+    assert!(auth.authorize(Some("operator"), Scope::Operator).is_ok());
+    assert_eq!(
+        auth.authorize(None, Scope::ReadOnly),
+        Err(AuthError::MissingToken)
+    );
+
+    assert!(auth.remove_static_token("operator"));
+    assert_eq!(
+        auth.authorize(Some("operator"), Scope::ReadOnly),
+        Err(AuthError::InvalidToken)
+    );
+}
+
+#[test]
+fn test_capability_tokens() {
+    let issuer = Identity::new();
+    let auth = Authenticator::new();
+    let token = CapabilityToken::issue(&issuer, Scope::Operator, Duration::from_secs(60)).unwrap();
+    let encoded = token.encode().unwrap();
+    assert_eq!(auth.authenticate(&encoded), Err(AuthError::UntrustedIssuer));
+
+    let _ = auth.trust_issuer(&issuer.get_public_id()).unwrap();
+    assert_eq!(
+        auth.authorize(Some(&encoded), Scope::Operator),
+        Ok(Grant {
+            scope: Scope::Operator,
+            subject: token.id,
+        })
+    );
+
+    let mut forged = token.clone();
+    forged.expires_at += Duration::from_secs(3600);
+    assert_eq!(
+        auth.authenticate(&forged.encode().unwrap()),
+        Err(AuthError::InvalidToken)
+    );
+    let expired = CapabilityToken::issue(&issuer, Scope::ReadOnly, Duration::default()).unwrap();
+    assert_eq!(
+        auth.authenticate(&expired.encode().unwrap()),
+        Err(AuthError::Expired)
+    );
+
+    auth.revoke(&token.id);
+    assert_eq!(auth.authenticate(&encoded), Err(AuthError::Revoked));
+
+    // Rotating the issuer key invalidates the tokens it signed
+    assert!(auth.distrust_issuer(&issuer.get_public_id()).unwrap());
+    let other = CapabilityToken::issue(&issuer, Scope::ReadOnly, Duration::from_secs(60)).unwrap();
+    assert_eq!(
+        auth.authenticate(&other.encode().unwrap()),
+        Err(AuthError::UntrustedIssuer)
+    );
+}
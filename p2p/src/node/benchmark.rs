@@ -0,0 +1,643 @@
+//! Network-wide benchmarks.
+//!
+//! A [`BenchmarkCoordinator`] discovers participants, pushes them a plan and
+//! starts them all at the same instant. Every [`BenchmarkParticipant`] then
+//! loads its node at the planned rate and reports its statistics back.
+//! Commands carry a nonce, increasing with every command signed and starting
+//! from the time in microseconds so that it keeps increasing across restarts,
+//! and are refused unless it is larger than the last one accepted from the
+//! same sender, so that captured commands cannot be replayed. Participants
+//! only follow the coordinator they were configured with.
+
+use super::{
+    identity::{Identity, PublicId},
+    message::Message,
+};
+use crate::error::{AuthError, P2pError};
+use consensus::{NodeId, TxId};
+use crypto::{hash::Hash, signature::Signature};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Parameters of a network-wide benchmark run
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct BenchmarkPlan {
+    pub id: Hash,
+    /// How long participants submit transactions for
+    pub duration: Duration,
+    /// Transactions per second each participant submits
+    pub rate: u64,
+    /// Fraction of submitted transactions that conflict with another one
+    pub conflict_ratio: f32,
+}
+
+/// Statistics collected by a single participant over a benchmark run
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct NodeStats {
+    pub submitted: u64,
+    pub accepted: u64,
+    pub rejected: u64,
+    /// Sum of submission-to-finalization latencies of accepted transactions
+    pub total_latency: Duration,
+}
+
+/// Control commands exchanged between a benchmark coordinator and participants
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum BenchmarkCommand {
+    /// Coordinator asks nodes willing to participate to join
+    Discover,
+    /// Participant joins the benchmark announced by the coordinator
+    Join,
+    /// Coordinator pushes the plan to every participant
+    Plan(BenchmarkPlan),
+    /// Coordinator starts the run at the given UNIX time on every participant
+    Start { plan_id: Hash, start_at: Duration },
+    /// Participant reports its statistics once the run is over
+    Report { plan_id: Hash, stats: NodeStats },
+}
+
+impl BenchmarkCommand {
+    /// Sign a command for `network_id` into a control message, under the
+    /// next nonce
+    pub fn sign(self, network_id: Hash, identity: &Identity) -> Result<Message, P2pError> {
+        self.sign_with_nonce(network_id, next_nonce(), identity)
+    }
+
+    pub fn sign_with_nonce(
+        self,
+        network_id: Hash,
+        nonce: u64,
+        identity: &Identity,
+    ) -> Result<Message, P2pError> {
+        let bytes =
+            bincode::serialize(&(&self, network_id, nonce)).map_err(P2pError::BincodeError)?;
+        Ok(Message::BenchmarkControl {
+            command: self,
+            network_id,
+            nonce,
+            signature: identity.sign_message(&bytes),
+            sender: identity.get_public_id(),
+        })
+    }
+
+    /// Verify a received command against the signature of its sender
+    pub fn verify(
+        &self,
+        network_id: &Hash,
+        nonce: u64,
+        signature: &Signature,
+        sender: &PublicId,
+    ) -> Result<NodeId, P2pError> {
+        let bytes =
+            bincode::serialize(&(self, network_id, nonce)).map_err(P2pError::BincodeError)?;
+        if !signature.verify(&sender.public_key, bytes) {
+            return Err(P2pError::InvalidSignature);
+        }
+        node_id(sender)
+    }
+}
+
+fn node_id(sender: &PublicId) -> Result<NodeId, P2pError> {
+    Hash::serialize(&sender.public_key)
+        .map(NodeId::from)
+        .map_err(P2pError::CryptoError)
+}
+
+/// Last nonce a command was signed under
+static LAST_NONCE: AtomicU64 = AtomicU64::new(0);
+
+/// Nonce above every one signed before, even by commands signed within the
+/// same microsecond
+fn next_nonce() -> u64 {
+    let now = unix_time().as_micros() as u64;
+    let last = LAST_NONCE
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| {
+            Some(now.max(last.saturating_add(1)))
+        })
+        .unwrap_or_default();
+    now.max(last.saturating_add(1))
+}
+
+fn unix_time() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+/// Accept `nonce` from `sender` if it is above the last one accepted
+fn check_nonce(
+    last_nonces: &mut HashMap<NodeId, u64>,
+    sender: NodeId,
+    nonce: u64,
+) -> Result<(), P2pError> {
+    let last = last_nonces.entry(sender).or_default();
+    if nonce <= *last {
+        return Err(AuthError::Replayed { nonce, last: *last }.into());
+    }
+    *last = nonce;
+    Ok(())
+}
+
+/// Merged result of a benchmark run across every participant
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct BenchmarkReport {
+    pub plan: BenchmarkPlan,
+    pub per_node: HashMap<NodeId, NodeStats>,
+    /// Participants that joined but never reported
+    pub missing: HashSet<NodeId>,
+    pub totals: NodeStats,
+}
+
+impl BenchmarkReport {
+    /// Accepted transactions per second across the network
+    pub fn throughput(&self) -> f64 {
+        let secs = self.plan.duration.as_secs_f64();
+        if secs == 0.0 {
+            return 0.0;
+        }
+        self.totals.accepted as f64 / secs
+    }
+
+    /// Mean finalization latency of accepted transactions
+    pub fn mean_latency(&self) -> Duration {
+        if self.totals.accepted == 0 {
+            return Duration::default();
+        }
+        let nanos = self.totals.total_latency.as_nanos() / u128::from(self.totals.accepted);
+        u64::try_from(nanos).map_or(Duration::MAX, Duration::from_nanos)
+    }
+}
+
+/// Coordinator role of a network-wide benchmark.
+///
+/// Discovers participants, pushes a plan, starts every participant at the same
+/// instant and merges the statistics they report back.
+pub struct BenchmarkCoordinator {
+    identity: Identity,
+    /// Network the benchmark runs on
+    network_id: Hash,
+    participants: HashSet<NodeId>,
+    plan: Option<BenchmarkPlan>,
+    reports: HashMap<NodeId, NodeStats>,
+    /// Last nonce accepted from each participant
+    last_nonces: HashMap<NodeId, u64>,
+}
+
+impl BenchmarkCoordinator {
+    pub fn new(identity: Identity, network_id: Hash) -> Self {
+        Self {
+            identity,
+            network_id,
+            participants: HashSet::new(),
+            plan: None,
+            reports: HashMap::new(),
+            last_nonces: HashMap::new(),
+        }
+    }
+
+    /// Signed discovery message to broadcast to all known peers
+    pub fn discover(&self) -> Result<Message, P2pError> {
+        BenchmarkCommand::Discover.sign(self.network_id, &self.identity)
+    }
+
+    /// Participants that joined so far
+    pub fn participants(&self) -> &HashSet<NodeId> {
+        &self.participants
+    }
+
+    /// Create the plan and the signed message pushing it to participants
+    pub fn plan(
+        &mut self,
+        duration: Duration,
+        rate: u64,
+        conflict_ratio: f32,
+    ) -> Result<Message, P2pError> {
+        let plan = BenchmarkPlan {
+            id: Hash::generate_random(),
+            duration,
+            rate,
+            conflict_ratio: conflict_ratio.clamp(0.0, 1.0),
+        };
+        self.plan = Some(plan.clone());
+        self.reports.clear();
+        BenchmarkCommand::Plan(plan).sign(self.network_id, &self.identity)
+    }
+
+    /// Signed message starting the run `lead_time` from now, leaving
+    /// participants time to receive it before the common start instant
+    pub fn start(&self, lead_time: Duration) -> Result<Message, P2pError> {
+        let plan = self
+            .plan
+            .as_ref()
+            .ok_or_else(|| P2pError::CustomError("Benchmark started without a plan".to_string()))?;
+        let start_at = unix_time() + lead_time;
+        BenchmarkCommand::Start {
+            plan_id: plan.id,
+            start_at,
+        }
+        .sign(self.network_id, &self.identity)
+    }
+
+    /// Handle a verified command coming from a participant, unless it is
+    /// replayed
+    pub fn handle_command(&mut self, sender: NodeId, nonce: u64, command: BenchmarkCommand) {
+        if let Err(e) = check_nonce(&mut self.last_nonces, sender, nonce) {
+            log::warn!("Benchmark command from {:?} refused: {}", sender, e);
+            return;
+        }
+        match command {
+            BenchmarkCommand::Join => {
+                log::debug!("Benchmark participant joined: {:?}", sender);
+                let _ = self.participants.insert(sender);
+            }
+            BenchmarkCommand::Report { plan_id, stats } => {
+                if !self.participants.contains(&sender) {
+                    log::warn!("Benchmark report from unknown participant {:?}", sender);
+                    return;
+                }
+                match &self.plan {
+                    Some(plan) if plan.id == plan_id => {
+                        let _ = self.reports.insert(sender, stats);
+                    }
+                    _ => log::warn!("Benchmark report for unknown plan {:?}", plan_id),
+                }
+            }
+            _ => log::warn!("Unexpected benchmark command from {:?}", sender),
+        }
+    }
+
+    /// Check if every participant has reported
+    pub fn is_complete(&self) -> bool {
+        self.plan.is_some() && self.reports.len() == self.participants.len()
+    }
+
+    /// Merge the reports received so far
+    pub fn report(&self) -> Option<BenchmarkReport> {
+        let plan = self.plan.clone()?;
+        let totals = self
+            .reports
+            .values()
+            .fold(NodeStats::default(), |mut totals, stats| {
+                totals.submitted = totals.submitted.saturating_add(stats.submitted);
+                totals.accepted = totals.accepted.saturating_add(stats.accepted);
+                totals.rejected = totals.rejected.saturating_add(stats.rejected);
+                totals.total_latency = totals.total_latency.saturating_add(stats.total_latency);
+                totals
+            });
+        let missing = self
+            .participants
+            .iter()
+            .filter(|node| !self.reports.contains_key(node))
+            .cloned()
+            .collect();
+        Some(BenchmarkReport {
+            plan,
+            per_node: self.reports.clone(),
+            missing,
+            totals,
+        })
+    }
+}
+
+/// Participant role of a network-wide benchmark.
+///
+/// Follows the commands of its coordinator, tells the node how many
+/// transactions are due as the run goes, and reports the statistics of the
+/// run once it is over.
+pub struct BenchmarkParticipant {
+    identity: Identity,
+    network_id: Hash,
+    coordinator: NodeId,
+    /// Last nonce accepted from the coordinator
+    last_nonces: HashMap<NodeId, u64>,
+    plan: Option<BenchmarkPlan>,
+    /// UNIX time the run starts at
+    start_at: Option<Duration>,
+    stats: NodeStats,
+    /// UNIX time our transactions still pending were submitted at
+    pending: HashMap<TxId, Duration>,
+    reported: bool,
+}
+
+impl BenchmarkParticipant {
+    /// Take part in the benchmarks `coordinator` runs on `network_id`
+    pub fn new(
+        identity: Identity,
+        network_id: Hash,
+        coordinator: &PublicId,
+    ) -> Result<Self, P2pError> {
+        Ok(Self {
+            identity,
+            network_id,
+            coordinator: node_id(coordinator)?,
+            last_nonces: HashMap::new(),
+            plan: None,
+            start_at: None,
+            stats: NodeStats::default(),
+            pending: HashMap::new(),
+            reported: false,
+        })
+    }
+
+    /// Handle a verified command, returning the signed answer to send the
+    /// coordinator, if any. Commands from other nodes and replayed ones
+    /// are refused.
+    pub fn handle_command(
+        &mut self,
+        sender: NodeId,
+        nonce: u64,
+        command: BenchmarkCommand,
+    ) -> Result<Option<Message>, P2pError> {
+        if sender != self.coordinator {
+            return Err(AuthError::UntrustedOperator.into());
+        }
+        check_nonce(&mut self.last_nonces, sender, nonce)?;
+        match command {
+            BenchmarkCommand::Discover => {
+                return BenchmarkCommand::Join
+                    .sign(self.network_id, &self.identity)
+                    .map(Some);
+            }
+            BenchmarkCommand::Plan(plan) => {
+                log::info!("Joining benchmark {:?}", plan.id);
+                self.plan = Some(plan);
+                self.start_at = None;
+                self.stats = NodeStats::default();
+                self.pending.clear();
+                self.reported = false;
+            }
+            BenchmarkCommand::Start { plan_id, start_at } => match &self.plan {
+                Some(plan) if plan.id == plan_id => self.start_at = Some(start_at),
+                _ => log::warn!("Benchmark start for unknown plan {:?}", plan_id),
+            },
+            command => log::warn!("Unexpected benchmark command {:?}", command),
+        }
+        Ok(None)
+    }
+
+    /// Node whose commands we follow, and report to
+    pub fn coordinator(&self) -> NodeId {
+        self.coordinator
+    }
+
+    /// Plan of the benchmark we take part in
+    pub fn plan(&self) -> Option<&BenchmarkPlan> {
+        self.plan.as_ref()
+    }
+
+    pub fn stats(&self) -> &NodeStats {
+        &self.stats
+    }
+
+    /// Number of transactions to submit at UNIX time `now` to keep up with
+    /// the planned rate
+    pub fn due(&self, now: Duration) -> u64 {
+        let (plan, start_at) = match (&self.plan, self.start_at) {
+            (Some(plan), Some(start_at)) => (plan, start_at),
+            _ => return 0,
+        };
+        let elapsed = now.saturating_sub(start_at).min(plan.duration);
+        let planned = (elapsed.as_secs_f64() * plan.rate as f64) as u64;
+        planned.saturating_sub(self.stats.submitted)
+    }
+
+    /// Check if the next transaction should conflict with the one before,
+    /// so that the planned ratio of them do
+    pub fn next_conflicts(&self) -> bool {
+        let ratio = self.plan.as_ref().map_or(0.0, |plan| plan.conflict_ratio) as f64;
+        let submitted = self.stats.submitted as f64;
+        ((submitted + 1.0) * ratio).floor() > (submitted * ratio).floor()
+    }
+
+    /// Count a transaction submitted at UNIX time `now`
+    pub fn submitted(&mut self, tx_id: TxId, now: Duration) {
+        self.stats.submitted += 1;
+        let _ = self.pending.insert(tx_id, now);
+    }
+
+    /// Count the finalization at UNIX time `now` of one of our
+    /// transactions. Others are ignored.
+    pub fn finalized(&mut self, tx_id: &TxId, accepted: bool, now: Duration) {
+        let submitted_at = match self.pending.remove(tx_id) {
+            Some(submitted_at) => submitted_at,
+            None => return,
+        };
+        if accepted {
+            self.stats.accepted += 1;
+            self.stats.total_latency += now.saturating_sub(submitted_at);
+        } else {
+            self.stats.rejected += 1;
+        }
+    }
+
+    /// Signed report of the run, once it is over at UNIX time `now`. The
+    /// report is only returned once.
+    pub fn report(&mut self, now: Duration) -> Result<Option<Message>, P2pError> {
+        let (plan, start_at) = match (&self.plan, self.start_at) {
+            (Some(plan), Some(start_at)) => (plan, start_at),
+            _ => return Ok(None),
+        };
+        if self.reported || now < start_at + plan.duration {
+            return Ok(None);
+        }
+        self.reported = true;
+        BenchmarkCommand::Report {
+            plan_id: plan.id,
+            stats: self.stats.clone(),
+        }
+        .sign(self.network_id, &self.identity)
+        .map(Some)
+    }
+}
+
+#[test]
+fn test_benchmark_command_signature() {
+    let identity = Identity::new();
+    let other = Identity::new();
+    if let Message::BenchmarkControl {
+        command,
+        network_id,
+        nonce,
+        signature,
+        ..
+    } = BenchmarkCommand::Join
+        .sign(Hash::default(), &identity)
+        .unwrap()
+    {
+        let sender = command
+            .verify(&network_id, nonce, &signature, &identity.get_public_id())
+            .unwrap();
+        assert_eq!(sender, identity.get_our_hash().unwrap());
+        assert!(matches!(
+            command.verify(&network_id, nonce, &signature, &other.get_public_id()),
+            Err(P2pError::InvalidSignature)
+        ));
+        let testnet = Hash::new("testnet".as_bytes());
+        assert!(matches!(
+            command.verify(&testnet, nonce, &signature, &identity.get_public_id()),
+            Err(P2pError::InvalidSignature)
+        ));
+        // The nonce is signed along with the command
+        assert!(matches!(
+            command.verify(
+                &network_id,
+                nonce + 1,
+                &signature,
+                &identity.get_public_id()
+            ),
+            Err(P2pError::InvalidSignature)
+        ));
+    } else {
+        panic!("Expected a benchmark control message");
+    }
+    // Commands signed in a row get increasing nonces
+    let nonces = (0..100).map(|_| next_nonce()).collect::<Vec<_>>();
+    assert!(nonces.windows(2).all(|pair| pair[0] < pair[1]));
+}
+
+#[test]
+fn test_benchmark_coordinator_merges_reports() {
+    let mut coordinator = BenchmarkCoordinator::new(Identity::new(), Hash::default());
+    let node_a = NodeId::from(Hash::new("A".as_bytes()));
+    let node_b = NodeId::from(Hash::new("B".as_bytes()));
+    coordinator.handle_command(node_a, 1, BenchmarkCommand::Join);
+    coordinator.handle_command(node_b, 1, BenchmarkCommand::Join);
+
+    let _ = coordinator.plan(Duration::from_secs(10), 100, 0.1).unwrap();
+    assert!(coordinator.start(Duration::from_secs(1)).is_ok());
+    let plan_id = coordinator.report().unwrap().plan.id;
+
+    coordinator.handle_command(
+        node_a,
+        2,
+        BenchmarkCommand::Report {
+            plan_id,
+            stats: NodeStats {
+                submitted: 1000,
+                accepted: 900,
+                rejected: 100,
+                total_latency: Duration::from_secs(90),
+            },
+        },
+    );
+    assert!(!coordinator.is_complete());
+    assert!(coordinator.report().unwrap().missing.contains(&node_b));
+
+    coordinator.handle_command(
+        node_b,
+        2,
+        BenchmarkCommand::Report {
+            plan_id,
+            stats: NodeStats {
+                submitted: 1000,
+                accepted: 1000,
+                rejected: 0,
+                total_latency: Duration::from_secs(100),
+            },
+        },
+    );
+    assert!(coordinator.is_complete());
+
+    let report = coordinator.report().unwrap();
+    assert_eq!(report.totals.accepted, 1900);
+    assert_eq!(report.throughput(), 190.0);
+    assert_eq!(report.mean_latency(), Duration::from_millis(100));
+
+    // Totals saturate rather than overflow
+    let node_c = NodeId::from(Hash::new("C".as_bytes()));
+    coordinator.handle_command(node_c, 1, BenchmarkCommand::Join);
+    coordinator.handle_command(
+        node_c,
+        2,
+        BenchmarkCommand::Report {
+            plan_id,
+            stats: NodeStats {
+                submitted: u64::MAX,
+                total_latency: Duration::MAX,
+                ..NodeStats::default()
+            },
+        },
+    );
+    let totals = coordinator.report().unwrap().totals;
+    assert_eq!(
+        (totals.submitted, totals.total_latency),
+        (u64::MAX, Duration::MAX)
+    );
+}
+
+#[test]
+fn test_benchmark_participant_follows_its_coordinator() {
+    let coordinator_identity = Identity::new();
+    let coordinator_id = coordinator_identity.get_our_hash().unwrap();
+    let mut coordinator = BenchmarkCoordinator::new(coordinator_identity.clone(), Hash::default());
+    let mut participant = BenchmarkParticipant::new(
+        Identity::new(),
+        Hash::default(),
+        &coordinator_identity.get_public_id(),
+    )
+    .unwrap();
+    let command = |message| match message {
+        Message::BenchmarkControl { command, nonce, .. } => (nonce, command),
+        message => panic!("Expected a benchmark control message, got {:?}", message),
+    };
+
+    // Only the coordinator is followed, and none of its commands twice
+    let (nonce, discover) = command(coordinator.discover().unwrap());
+    let rogue = NodeId::from(Hash::new("rogue".as_bytes()));
+    assert!(participant
+        .handle_command(rogue, nonce, discover.clone())
+        .is_err());
+    let join = participant
+        .handle_command(coordinator_id, nonce, discover.clone())
+        .unwrap();
+    assert!(matches!(command(join.unwrap()).1, BenchmarkCommand::Join));
+    assert!(participant
+        .handle_command(coordinator_id, nonce, discover)
+        .is_err());
+
+    let (nonce, plan) = command(coordinator.plan(Duration::from_secs(10), 10, 0.5).unwrap());
+    let _ = participant
+        .handle_command(coordinator_id, nonce, plan)
+        .unwrap();
+    let start_at = Duration::from_secs(1_000);
+    let plan_id = participant.plan().unwrap().id;
+    let _ = participant
+        .handle_command(
+            coordinator_id,
+            nonce + 1,
+            BenchmarkCommand::Start { plan_id, start_at },
+        )
+        .unwrap();
+
+    // The run submits at the planned rate, half of the transactions
+    // conflicting
+    assert_eq!(participant.due(start_at - Duration::from_secs(1)), 0);
+    assert_eq!(participant.due(start_at + Duration::from_secs(2)), 20);
+    let mut conflicts = 0;
+    for i in 0..20 {
+        conflicts += participant.next_conflicts() as u64;
+        let tx_id = TxId::from(Hash::new(&[i]));
+        participant.submitted(tx_id, start_at);
+        participant.finalized(&tx_id, i % 4 != 0, start_at + Duration::from_millis(100));
+    }
+    assert_eq!(conflicts, 10);
+    assert_eq!(participant.due(start_at + Duration::from_secs(2)), 0);
+    assert_eq!(participant.due(start_at + Duration::from_secs(60)), 80);
+
+    assert!(participant.report(start_at).unwrap().is_none());
+    let end = start_at + Duration::from_secs(10);
+    let (_, report) = command(participant.report(end).unwrap().unwrap());
+    assert!(participant.report(end).unwrap().is_none());
+    match report {
+        BenchmarkCommand::Report { stats, .. } => {
+            assert_eq!(
+                (stats.submitted, stats.accepted, stats.rejected),
+                (20, 15, 5)
+            );
+            assert_eq!(stats.total_latency, Duration::from_millis(1_500));
+        }
+        command => panic!("Expected a report, got {:?}", command),
+    }
+}
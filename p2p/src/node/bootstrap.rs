@@ -0,0 +1,270 @@
+//! Audit trail of the connections we attempt.
+//!
+//! A node whose bootstrap contacts all fail otherwise just ends up with no
+//! peers. Every dial is recorded along with its outcome, so that a
+//! [`BootstrapReport`] can tell which contacts were tried and why they
+//! failed. Once the last bootstrap contact answers or fails, a summary is
+//! logged, and [`Event::BootstrapFailed`] emitted if none of them could be
+//! connected to.
+//!
+//! [`Event::BootstrapFailed`]: super::event::Event::BootstrapFailed
+
+use consensus::NodeId;
+use quic_p2p::QuicP2pError as QuicError;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+
+/// Why a dial failed
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum DialFailure {
+    Timeout,
+    Refused,
+    /// The peer was reached but we could not agree with it, e.g. on the
+    /// protocol
+    Handshake(String),
+    /// The peer identified itself as another node than the one we dialed
+    IdentityMismatch {
+        expected: NodeId,
+        actual: NodeId,
+    },
+    /// The connection failed for another reason
+    Other(String),
+}
+
+impl DialFailure {
+    /// Classify the error a connection failed with
+    pub fn from_error(error: &QuicError) -> Self {
+        let reason = || match error.source() {
+            Some(source) => format!("{}: {}", error, source),
+            None => error.to_string(),
+        };
+        match error {
+            QuicError::Io(e) => match e.kind() {
+                io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => Self::Timeout,
+                io::ErrorKind::ConnectionRefused => Self::Refused,
+                _ => Self::Other(reason()),
+            },
+            QuicError::Connect(_)
+            | QuicError::Connection(_)
+            | QuicError::TLS(_)
+            | QuicError::CertificateParseError => Self::Handshake(reason()),
+            _ => Self::Other(reason()),
+        }
+    }
+}
+
+impl fmt::Display for DialFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Timeout => write!(f, "timed out"),
+            Self::Refused => write!(f, "refused"),
+            Self::Handshake(reason) => write!(f, "handshake failed: {}", reason),
+            Self::IdentityMismatch { expected, actual } => {
+                write!(f, "identified as {}, expected {}", actual, expected)
+            }
+            Self::Other(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+/// Outcome of a dial
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum DialOutcome {
+    /// Waiting for the connection, or for the peer to identify itself
+    Pending,
+    Connected(NodeId),
+    Failed(DialFailure),
+}
+
+/// A connection we attempted
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DialAttempt {
+    pub addr: SocketAddr,
+    /// Whether the peer was one of our bootstrap contacts
+    pub bootstrap: bool,
+    pub outcome: DialOutcome,
+}
+
+/// Dials attempted, in the order they were
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct BootstrapReport {
+    pub attempts: Vec<DialAttempt>,
+}
+
+impl BootstrapReport {
+    fn bootstrap_attempts(&self) -> impl Iterator<Item = &DialAttempt> {
+        self.attempts.iter().filter(|attempt| attempt.bootstrap)
+    }
+
+    /// Bootstrap contacts connected to
+    pub fn connected(&self) -> usize {
+        self.bootstrap_attempts()
+            .filter(|attempt| matches!(attempt.outcome, DialOutcome::Connected(_)))
+            .count()
+    }
+
+    /// Whether bootstrap contacts are still being dialed
+    pub fn is_pending(&self) -> bool {
+        self.bootstrap_attempts()
+            .any(|attempt| attempt.outcome == DialOutcome::Pending)
+    }
+
+    /// Whether every bootstrap contact was dialed and none connected to
+    pub fn has_failed(&self) -> bool {
+        self.bootstrap_attempts().next().is_some() && !self.is_pending() && self.connected() == 0
+    }
+}
+
+impl fmt::Display for BootstrapReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "connected to {} of {} bootstrap contacts",
+            self.connected(),
+            self.bootstrap_attempts().count()
+        )?;
+        for attempt in self.bootstrap_attempts() {
+            match &attempt.outcome {
+                DialOutcome::Pending => write!(f, "; {} pending", attempt.addr)?,
+                DialOutcome::Connected(id) => write!(f, "; {} connected as {}", attempt.addr, id)?,
+                DialOutcome::Failed(failure) => write!(f, "; {} {}", attempt.addr, failure)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Records the dials of a node
+#[derive(Debug, Default)]
+pub struct DialLog {
+    report: BootstrapReport,
+    /// Whether the bootstrap summary was logged
+    reported: bool,
+}
+
+impl DialLog {
+    pub fn report(&self) -> &BootstrapReport {
+        &self.report
+    }
+
+    /// Record a dial to `addr`
+    pub fn dialed(&mut self, addr: SocketAddr, bootstrap: bool) {
+        self.report.attempts.push(DialAttempt {
+            addr,
+            bootstrap,
+            outcome: DialOutcome::Pending,
+        });
+        if bootstrap {
+            self.reported = false;
+        }
+    }
+
+    /// Record the outcome of the pending dial to `addr`, if any. Returns
+    /// the report once the last pending bootstrap contact resolved.
+    pub fn resolved(
+        &mut self,
+        addr: &SocketAddr,
+        outcome: DialOutcome,
+    ) -> Option<&BootstrapReport> {
+        let attempt = self
+            .report
+            .attempts
+            .iter_mut()
+            .rev()
+            .find(|attempt| attempt.addr == *addr && attempt.outcome == DialOutcome::Pending)?;
+        match &outcome {
+            DialOutcome::Failed(failure) => log::info!("Dial to {:?} failed: {}", addr, failure),
+            _ => log::debug!("Dial to {:?}: {:?}", addr, outcome),
+        }
+        attempt.outcome = outcome;
+        if !attempt.bootstrap || self.reported || self.report.is_pending() {
+            return None;
+        }
+        self.reported = true;
+        if self.report.has_failed() {
+            log::warn!("Bootstrap failed: {}", self.report);
+        } else {
+            log::info!("Bootstrapped: {}", self.report);
+        }
+        Some(&self.report)
+    }
+}
+
+#[test]
+fn test_failed_bootstrap_is_reported() {
+    use super::{config::TransportKind, connection::Connection, event::Event};
+    use crate::{error::P2pError, transport::Transport};
+    use bytes::Bytes;
+    use crypto::hash::Hash;
+    use quic_p2p::Peer;
+
+    /// Transport connecting nowhere
+    struct Unreachable;
+
+    impl Transport for Unreachable {
+        fn kind(&self) -> TransportKind {
+            TransportKind::Tcp
+        }
+
+        fn our_addr(&mut self) -> Result<SocketAddr, P2pError> {
+            Ok(([127, 0, 0, 1], 9000).into())
+        }
+
+        fn connect_to(&mut self, _peer: SocketAddr) {}
+
+        fn disconnect_from(&mut self, _peer: SocketAddr) {}
+
+        fn send(&mut self, _peer: SocketAddr, _msg: Bytes, _token: u64) {}
+    }
+
+    let (node_tx, node_rx) = crossbeam_channel::unbounded();
+    let our_id = NodeId::from(Hash::new("us".as_bytes()));
+    let contacts: Vec<SocketAddr> =
+        vec![([10, 0, 0, 1], 9000).into(), ([10, 0, 1, 1], 9000).into()];
+    let mut connection = Connection::new();
+    let mut transport = Unreachable;
+    connection.bootstrap(contacts.clone(), &mut transport);
+    assert!(connection.bootstrap_report().is_pending());
+
+    let fail = |connection: &mut Connection, addr: SocketAddr, kind: io::ErrorKind| {
+        connection
+            .handle_connection_failure(
+                Peer::Node(addr),
+                QuicError::Io(kind.into()),
+                &node_tx,
+                &mut Unreachable,
+                &our_id,
+            )
+            .unwrap()
+    };
+    fail(
+        &mut connection,
+        contacts[0],
+        io::ErrorKind::ConnectionRefused,
+    );
+    assert!(node_rx.try_recv().is_err());
+    fail(&mut connection, contacts[1], io::ErrorKind::TimedOut);
+
+    let report = connection.bootstrap_report().clone();
+    assert!(report.has_failed());
+    assert_eq!(
+        report
+            .attempts
+            .iter()
+            .map(|attempt| attempt.outcome.clone())
+            .collect::<Vec<_>>(),
+        vec![
+            DialOutcome::Failed(DialFailure::Refused),
+            DialOutcome::Failed(DialFailure::Timeout)
+        ]
+    );
+    assert!(report
+        .to_string()
+        .starts_with("connected to 0 of 2 bootstrap contacts"));
+    assert_eq!(node_rx.try_recv().unwrap(), Event::BootstrapFailed(report));
+    // Connections failing later are not bootstrap failures
+    fail(&mut connection, contacts[1], io::ErrorKind::TimedOut);
+    assert!(node_rx.try_recv().is_err());
+}
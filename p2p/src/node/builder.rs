@@ -0,0 +1,278 @@
+use super::config::{P2pConfig, P2pConfigBuilder};
+use crate::error::ConfigError;
+use consensus::{
+    config::{ConsensusConfig, ConsensusConfigBuilder},
+    genesis::GenesisConfig,
+    Amount,
+};
+use crypto::{hash::Hash, signature::PublicKey};
+use std::net::SocketAddr;
+
+/// Where a new node obtains the state from, trading trust for startup time
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum SyncSource {
+    /// Replay every transaction from genesis. Slow, but trusts nothing but
+    /// the genesis accounts.
+    #[default]
+    Genesis,
+    /// Start from the checkpoint of the given ID, obtained out of band and
+    /// certified by the validators, then replay what followed it. Trusts the
+    /// validators that signed it.
+    Checkpoint(Hash),
+    /// Take the state the given peer serves first, unchecked. Trusts that
+    /// peer, so the node may not validate.
+    TrustedPeer(SocketAddr),
+}
+
+impl SyncSource {
+    /// Whether a node synced this way may act as a validator. A node
+    /// trusting a single peer could certify a state nobody else checked.
+    pub fn may_validate(&self) -> bool {
+        !matches!(self, Self::TrustedPeer(_))
+    }
+}
+
+/// Validated configuration of a node
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct NodeConfig {
+    p2p: P2pConfig,
+    consensus: ConsensusConfig,
+    genesis: GenesisConfig,
+    sync_source: SyncSource,
+    validator: bool,
+}
+
+impl NodeConfig {
+    /// Initialize a builder starting from the default configuration
+    pub fn builder() -> NodeBuilder {
+        NodeBuilder::default()
+    }
+
+    pub fn p2p(&self) -> &P2pConfig {
+        &self.p2p
+    }
+
+    pub fn consensus(&self) -> &ConsensusConfig {
+        &self.consensus
+    }
+
+    /// Accounts funded at genesis, the same on every node of the network
+    pub fn genesis(&self) -> &GenesisConfig {
+        &self.genesis
+    }
+
+    /// Whether the node runs alone in development mode
+    pub fn is_dev(&self) -> bool {
+        self.consensus.is_dev()
+    }
+
+    /// Where the node obtains the state from on startup
+    pub fn sync_source(&self) -> SyncSource {
+        self.sync_source
+    }
+
+    /// Whether the node takes part in consensus as a validator
+    pub fn is_validator(&self) -> bool {
+        self.validator
+    }
+}
+
+/// Builder of a node configuration.
+///
+/// Starts from the default networking and consensus parameters; callers only
+/// override what they need and get every parameter validated at once.
+#[derive(Clone, Debug, Default)]
+pub struct NodeBuilder {
+    p2p: P2pConfigBuilder,
+    consensus: ConsensusConfigBuilder,
+    genesis: GenesisConfig,
+    sync_source: SyncSource,
+    validator: bool,
+}
+
+impl NodeBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adjust the networking parameters
+    pub fn p2p<F>(mut self, configure: F) -> Self
+    where
+        F: FnOnce(P2pConfigBuilder) -> P2pConfigBuilder,
+    {
+        self.p2p = configure(self.p2p);
+        self
+    }
+
+    /// Adjust the consensus parameters
+    pub fn consensus<F>(mut self, configure: F) -> Self
+    where
+        F: FnOnce(ConsensusConfigBuilder) -> ConsensusConfigBuilder,
+    {
+        self.consensus = configure(self.consensus);
+        self
+    }
+
+    /// Start the network from the accounts of `genesis`
+    pub fn genesis(mut self, genesis: GenesisConfig) -> Self {
+        self.genesis = genesis;
+        self
+    }
+
+    /// Fund the account of `public_key` with `balance` at genesis
+    pub fn fund_account(mut self, public_key: PublicKey, balance: Amount) -> Self {
+        let _ = self.genesis.fund(public_key, balance);
+        self
+    }
+
+    /// Obtain the state from `source` on startup. A trusted peer is added to
+    /// the bootstrap contacts.
+    pub fn sync_from(mut self, source: SyncSource) -> Self {
+        self.sync_source = source;
+        self
+    }
+
+    /// Take part in consensus as a validator
+    pub fn validator(mut self, validator: bool) -> Self {
+        self.validator = validator;
+        self
+    }
+
+    /// Run a single node finalizing its own transactions instantly, for
+    /// local development
+    pub fn dev(mut self) -> Self {
+        self.consensus = self.consensus.dev(true);
+        self
+    }
+
+    /// Validate every parameter and build the node configuration.
+    ///
+    /// Nodes in development mode may not bootstrap, since they would
+    /// finalize transactions on their own in a real network, and start from
+    /// genesis. Validators may not trust a peer for their state.
+    pub fn build(self) -> Result<NodeConfig, ConfigError> {
+        let mut p2p = self.p2p;
+        if let SyncSource::TrustedPeer(peer) = self.sync_source {
+            p2p = p2p.bootstrap_nodes(vec![peer]);
+        }
+        let config = NodeConfig {
+            p2p: p2p.build()?,
+            consensus: self.consensus.build()?,
+            genesis: self.genesis,
+            sync_source: self.sync_source,
+            validator: self.validator,
+        };
+        config.genesis.validate()?;
+        if config.is_dev() && config.p2p.get_bootstrap_contacts().next().is_some() {
+            return Err(ConfigError::DevModeWithPeers);
+        }
+        if config.is_dev() && config.sync_source != SyncSource::Genesis {
+            return Err(ConfigError::DevModeSync);
+        }
+        if config.validator && !config.sync_source.may_validate() {
+            return Err(ConfigError::UntrustedValidator);
+        }
+        Ok(config)
+    }
+}
+
+#[test]
+fn test_node_builder() {
+    let peer: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+    let config = NodeBuilder::new()
+        .p2p(|p2p| p2p.bootstrap_nodes(vec![peer]))
+        .consensus(|consensus| consensus.k(20))
+        .build()
+        .unwrap();
+    assert_eq!(config.consensus().k(), 20);
+    assert_eq!(
+        config.consensus().alpha(),
+        ConsensusConfig::default().alpha()
+    );
+    assert!(config
+        .p2p()
+        .get_bootstrap_contacts()
+        .any(|addr| *addr == peer));
+
+    let key = crypto::signature::PrivateKey::generate().public_key();
+    let funded = NodeBuilder::new()
+        .fund_account(key, Amount::new(1_000))
+        .build()
+        .unwrap();
+    assert_eq!(funded.genesis().transactions().unwrap().len(), 1);
+    assert!(matches!(
+        NodeBuilder::new()
+            .fund_account(key, Amount::new(1_000))
+            .fund_account(key, Amount::new(1))
+            .build(),
+        Err(ConfigError::Consensus(
+            consensus::ConfigError::DuplicateGenesisAccount(_)
+        ))
+    ));
+
+    assert!(matches!(
+        NodeBuilder::new()
+            .consensus(|consensus| consensus.alpha(2.0))
+            .build(),
+        Err(ConfigError::Consensus(
+            consensus::ConfigError::InvalidAlpha(_)
+        ))
+    ));
+}
+
+#[test]
+fn test_dev_mode_runs_alone() {
+    let config = NodeBuilder::new().dev().build().unwrap();
+    assert!(config.is_dev());
+    assert!(config.consensus().is_dev());
+
+    let peer = "127.0.0.1:5000".parse().unwrap();
+    assert_eq!(
+        NodeBuilder::new()
+            .dev()
+            .p2p(|p2p| p2p.bootstrap_nodes(vec![peer]))
+            .build()
+            .unwrap_err(),
+        ConfigError::DevModeWithPeers
+    );
+}
+
+#[test]
+fn test_trusted_peer_sync_may_not_validate() {
+    let peer: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+    let config = NodeBuilder::new()
+        .sync_from(SyncSource::TrustedPeer(peer))
+        .build()
+        .unwrap();
+    assert_eq!(config.sync_source(), SyncSource::TrustedPeer(peer));
+    assert!(config
+        .p2p()
+        .get_bootstrap_contacts()
+        .any(|addr| *addr == peer));
+    assert_eq!(
+        NodeBuilder::new()
+            .sync_from(SyncSource::TrustedPeer(peer))
+            .validator(true)
+            .build()
+            .unwrap_err(),
+        ConfigError::UntrustedValidator
+    );
+
+    let checkpoint = SyncSource::Checkpoint(Hash::new("checkpoint".as_bytes()));
+    let config = NodeBuilder::new()
+        .sync_from(checkpoint)
+        .validator(true)
+        .build()
+        .unwrap();
+    assert!(config.is_validator());
+    assert_eq!(
+        NodeBuilder::new()
+            .dev()
+            .sync_from(checkpoint)
+            .build()
+            .unwrap_err(),
+        ConfigError::DevModeSync
+    );
+    assert_eq!(NodeConfig::default().sync_source(), SyncSource::Genesis);
+}
@@ -0,0 +1,152 @@
+//! Capacity statements nodes sign and advertise to their peers.
+//!
+//! Networks mix small nodes with large ones. Each node advertises how many
+//! transactions per second it is willing to validate, the bandwidth it
+//! spends on the network and how it stores the DAG, signed so that the
+//! statement can be kept and audited later. Requesters sample peers in
+//! proportion to the capacity they advertise and cap the batches they send
+//! them accordingly, rather than loading small nodes like large ones.
+
+use super::identity::{Identity, PublicId};
+use crate::error::P2pError;
+use consensus::NodeId;
+use crypto::{hash::Hash, signature::Signature};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Domain of the signed statements
+const CAPACITY_DOMAIN: &[u8] = b"dagchain:capacity";
+
+/// How a node keeps the DAG
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub enum StorageMode {
+    /// Every transaction since genesis
+    #[default]
+    Archive,
+    /// Transactions since the latest checkpoint
+    Pruned,
+}
+
+impl FromStr for StorageMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "archive" => Ok(StorageMode::Archive),
+            "pruned" => Ok(StorageMode::Pruned),
+            _ => Err(format!("Unknown storage mode: {}", s)),
+        }
+    }
+}
+
+/// What a node is willing to handle
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Capacity {
+    /// Transactions per second the node validates
+    pub max_tps: u64,
+    /// Bytes per second the node spends on the network
+    pub bandwidth: u64,
+    pub storage: StorageMode,
+}
+
+/// Capacity signed by the node stating it
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct CapacityAdvertisement {
+    pub capacity: Capacity,
+    /// Network the statement is meant for
+    pub network_id: Hash,
+    pub sender: PublicId,
+    /// When the statement was signed, since the Unix epoch. Later
+    /// statements replace earlier ones.
+    pub issued: Duration,
+    signature: Signature,
+}
+
+impl CapacityAdvertisement {
+    /// Sign a statement of `capacity` on `network_id` as of now
+    pub fn sign(
+        capacity: Capacity,
+        network_id: Hash,
+        identity: &Identity,
+    ) -> Result<Self, P2pError> {
+        let issued = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Self::sign_at(capacity, network_id, identity, issued)
+    }
+
+    pub fn sign_at(
+        capacity: Capacity,
+        network_id: Hash,
+        identity: &Identity,
+        issued: Duration,
+    ) -> Result<Self, P2pError> {
+        let signature = identity.sign_message(&signed_bytes(&capacity, &network_id, issued)?);
+        Ok(Self {
+            capacity,
+            network_id,
+            sender: identity.get_public_id(),
+            issued,
+            signature,
+        })
+    }
+
+    /// Check the signature, returning the node that signed the statement
+    pub fn verify(&self) -> Result<NodeId, P2pError> {
+        let bytes = signed_bytes(&self.capacity, &self.network_id, self.issued)?;
+        if !self.signature.verify(&self.sender.public_key, &bytes) {
+            return Err(P2pError::InvalidSignature);
+        }
+        self.signer()
+    }
+
+    /// Node claiming to have signed the statement
+    pub fn signer(&self) -> Result<NodeId, P2pError> {
+        Hash::serialize(&self.sender.public_key)
+            .map(NodeId::from)
+            .map_err(P2pError::CryptoError)
+    }
+}
+
+fn signed_bytes(
+    capacity: &Capacity,
+    network_id: &Hash,
+    issued: Duration,
+) -> Result<Vec<u8>, P2pError> {
+    let mut bytes = CAPACITY_DOMAIN.to_vec();
+    bytes.extend(
+        bincode::serialize(&(capacity, network_id, issued)).map_err(P2pError::BincodeError)?,
+    );
+    Ok(bytes)
+}
+
+#[test]
+fn test_capacity_advertisement_signature() {
+    let identity = Identity::new();
+    let capacity = Capacity {
+        max_tps: 200,
+        bandwidth: 1 << 20,
+        storage: StorageMode::Pruned,
+    };
+    let advertisement = CapacityAdvertisement::sign(capacity, Hash::default(), &identity).unwrap();
+    assert_eq!(
+        advertisement.verify().unwrap(),
+        identity.get_our_hash().unwrap()
+    );
+
+    // Overstating the capacity breaks the signature
+    let mut forged = advertisement.clone();
+    forged.capacity.max_tps = 100_000;
+    assert!(matches!(forged.verify(), Err(P2pError::InvalidSignature)));
+    let mut replayed = advertisement;
+    replayed.issued += Duration::from_secs(60);
+    assert!(matches!(replayed.verify(), Err(P2pError::InvalidSignature)));
+    let mut other_network = replayed;
+    other_network.issued -= Duration::from_secs(60);
+    other_network.network_id = Hash::new("testnet".as_bytes());
+    assert!(matches!(
+        other_network.verify(),
+        Err(P2pError::InvalidSignature)
+    ));
+}
@@ -0,0 +1,73 @@
+//! Decoding of data received from peers.
+//!
+//! Peers pick the lengths written in what they send, and plain
+//! `bincode::deserialize` puts no bound on them, so a crafted message can
+//! make the node allocate far more than it received. Everything peers send
+//! is decoded here instead, under a limit on the bytes a single value may
+//! take. Failures tell whether the peer sent something too large or
+//! something malformed, so that it can be scored accordingly.
+
+use crate::error::DecodeError;
+use bincode::Options;
+use serde::de::DeserializeOwned;
+
+/// Largest message accepted from a peer by default
+pub const DEFAULT_MAX_MESSAGE_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Options matching `bincode::serialize`, with a limit on the decoded size
+fn options(limit: u64) -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(limit)
+}
+
+/// Decode a value of at most `limit` bytes sent by a peer
+pub fn decode<T: DeserializeOwned>(bytes: &[u8], limit: u64) -> Result<T, DecodeError> {
+    if bytes.len() as u64 > limit {
+        return Err(DecodeError::TooLarge {
+            size: bytes.len() as u64,
+            limit,
+        });
+    }
+    options(limit).deserialize(bytes).map_err(|e| match *e {
+        bincode::ErrorKind::SizeLimit => DecodeError::TooLarge {
+            size: bytes.len() as u64,
+            limit,
+        },
+        e => DecodeError::Malformed(e.to_string()),
+    })
+}
+
+#[test]
+fn test_decode_limits() {
+    use super::message::Message;
+
+    let message = Message::UserMessage(vec![7; 64]);
+    let bytes = bincode::serialize(&message).unwrap();
+    assert!(matches!(
+        decode::<Message>(&bytes, 1024),
+        Ok(Message::UserMessage(content)) if content == vec![7; 64]
+    ));
+    assert_eq!(
+        decode::<Message>(&bytes, 16).err(),
+        Some(DecodeError::TooLarge {
+            size: bytes.len() as u64,
+            limit: 16
+        })
+    );
+
+    // A short message claiming a huge vector fails once its content runs out,
+    // without allocating the vector it claims
+    let mut bomb = bincode::serialize(&Message::UserMessage(vec![])).unwrap();
+    let len_at = bomb.len() - 8;
+    bomb[len_at..].copy_from_slice(&u64::MAX.to_le_bytes());
+    assert!(matches!(
+        decode::<Message>(&bomb, 1024),
+        Err(DecodeError::Malformed(_))
+    ));
+    assert!(matches!(
+        decode::<Message>(&[0xff; 4], 1024),
+        Err(DecodeError::Malformed(_))
+    ));
+}
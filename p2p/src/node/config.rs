@@ -1,12 +1,50 @@
+use super::{
+    capacity::{Capacity, StorageMode},
+    codec::DEFAULT_MAX_MESSAGE_SIZE,
+    message::Message,
+    tokens::MessageClass,
+};
+use crate::error::ConfigError;
+use crypto::hash::Hash;
 use quic_p2p::Config as QuicConfig;
+use serde::{Deserialize, Serialize};
 use std::collections::hash_set::{self, HashSet};
+use std::collections::HashMap;
 use std::iter::IntoIterator;
 use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::Duration;
 use structopt::StructOpt;
 
-/// P2p node configuration
+pub(super) const DEFAULT_HOP_LIMIT: usize = 5;
+const DEFAULT_MAX_CONNECTIONS_PER_SUBNET: usize = 2;
+const DEFAULT_OUTBOX_CAPACITY: usize = 1024;
+const DEFAULT_SEND_TIMEOUT_MSEC: u64 = 30_000;
+const DEFAULT_SEND_RETRIES: u32 = 5;
+const DEFAULT_MIN_FANOUT: usize = 2;
+const DEFAULT_MAX_FANOUT: usize = 16;
+const DEFAULT_MIN_DUPLICATE_RATIO: f64 = 0.2;
+const DEFAULT_MAX_DUPLICATE_RATIO: f64 = 0.7;
+const DEFAULT_PIGGYBACK_MAX_SIZE: usize = 256;
+const DEFAULT_PUNCH_TIMEOUT_MSEC: u64 = 5_000;
+const DEFAULT_MAX_TPS: u64 = 100;
+const DEFAULT_BANDWIDTH_BUDGET: u64 = 1 << 20;
+const DEFAULT_ROUTE_TTL_SEC: u64 = 300;
+const DEFAULT_TOMBSTONE_TTL_SEC: u64 = 600;
+const DEFAULT_PEER_MESSAGES_PER_SEC: u64 = 500;
+const DEFAULT_PEER_BYTES_PER_SEC: u64 = 4 << 20;
+const DEFAULT_PEER_THROTTLE_SEC: u64 = 10;
+const DEFAULT_PEER_MAX_THROTTLES: u32 = 3;
+const DEFAULT_FRAGMENT_SIZE: usize = 64 * 1024;
+const DEFAULT_REASSEMBLY_TIMEOUT_SEC: u64 = 30;
+const DEFAULT_SERVICE_NAME: &str = "dagchain";
+
+/// P2p node configuration.
+///
+/// Built through [`P2pConfig::builder`], which validates it.
 #[derive(Clone, Debug, Default, StructOpt)]
 #[structopt(rename_all = "kebab-case")]
+#[non_exhaustive]
 pub struct P2pConfig {
     #[structopt(short, long, default_value = "[]", parse(try_from_str = serde_json::from_str))]
     bootstrap_nodes: HashSet<SocketAddr>,
@@ -14,23 +52,67 @@ pub struct P2pConfig {
     quic: QuicConfig,
     #[structopt(short, long)]
     deploy_agent: bool,
+    #[structopt(long)]
+    rpc_addr: Option<SocketAddr>,
+    /// Largest message accepted from peers, in bytes
+    #[structopt(long)]
+    max_message_size: Option<u64>,
+    /// Network the node takes part in, e.g. a testnet. Identification and
+    /// signed messages from other networks are dropped.
+    #[structopt(long, default_value = "0000000000000000000000000000000000000000000000000000000000000000", parse(try_from_str = Hash::from_hex))]
+    network_id: Hash,
+    #[structopt(flatten)]
+    transport: TransportConfig,
+    #[structopt(flatten)]
+    hop_limits: HopLimits,
+    #[structopt(flatten)]
+    diversity: DiversityConfig,
+    #[structopt(flatten)]
+    outbox: OutboxConfig,
+    #[structopt(flatten)]
+    send: SendConfig,
+    #[structopt(flatten)]
+    gossip: GossipConfig,
+    #[structopt(flatten)]
+    piggyback: PiggybackConfig,
+    #[structopt(flatten)]
+    nat: NatConfig,
+    #[structopt(flatten)]
+    capacity: CapacityConfig,
+    #[structopt(flatten)]
+    routing: RoutingConfig,
+    #[structopt(flatten)]
+    rate_limits: RateLimitConfig,
+    #[structopt(flatten)]
+    fragments: FragmentConfig,
+    #[structopt(flatten)]
+    telemetry: TelemetryConfig,
 }
 
 impl P2pConfig {
+    /// Initialize a builder starting from the default configuration
+    pub fn builder() -> P2pConfigBuilder {
+        P2pConfigBuilder::default()
+    }
+
+    /// Retrieve the QUIC configuration, with transport tuning filled in
+    /// wherever it was not set explicitly
     pub fn get_quic_config(&self) -> QuicConfig {
-        self.quic.clone()
+        let mut quic = self.quic.clone();
+        self.transport.apply(&mut quic);
+        quic
     }
 
     pub fn set_quic_config(&mut self, qconfig: QuicConfig) {
         self.quic = qconfig;
     }
 
-    pub fn get_bootstrap_contacts(&self) -> hash_set::Iter<SocketAddr> {
+    pub fn get_bootstrap_contacts(&self) -> hash_set::Iter<'_, SocketAddr> {
         self.bootstrap_nodes.iter()
     }
 
     pub fn add_bootstrap_contacts(&mut self, peers: impl IntoIterator<Item = SocketAddr>) {
-        let _ = self.bootstrap_nodes.extend(peers);
+        self.bootstrap_nodes.extend(peers);
     }
 
     pub fn bootstrap_nodes_mut(&mut self) -> &mut HashSet<SocketAddr> {
@@ -44,4 +126,1114 @@ impl P2pConfig {
     pub fn should_deploy(&self) -> bool {
         self.deploy_agent
     }
+
+    pub fn get_rpc_addr(&self) -> Option<SocketAddr> {
+        self.rpc_addr
+    }
+
+    pub fn set_rpc_addr(&mut self, addr: SocketAddr) {
+        self.rpc_addr = Some(addr);
+    }
+
+    /// Largest message accepted from peers, in bytes
+    pub fn get_max_message_size(&self) -> u64 {
+        self.max_message_size.unwrap_or(DEFAULT_MAX_MESSAGE_SIZE)
+    }
+
+    pub fn set_max_message_size(&mut self, size: u64) {
+        self.max_message_size = Some(size);
+    }
+
+    /// Network the node takes part in
+    pub fn get_network_id(&self) -> Hash {
+        self.network_id
+    }
+
+    pub fn set_network_id(&mut self, network_id: Hash) {
+        self.network_id = network_id;
+    }
+
+    pub fn get_transport_config(&self) -> &TransportConfig {
+        &self.transport
+    }
+
+    pub fn set_transport_config(&mut self, transport: TransportConfig) {
+        self.transport = transport;
+    }
+
+    pub fn get_hop_limits(&self) -> &HopLimits {
+        &self.hop_limits
+    }
+
+    pub fn set_hop_limits(&mut self, hop_limits: HopLimits) {
+        self.hop_limits = hop_limits;
+    }
+
+    pub fn get_diversity_config(&self) -> &DiversityConfig {
+        &self.diversity
+    }
+
+    pub fn set_diversity_config(&mut self, diversity: DiversityConfig) {
+        self.diversity = diversity;
+    }
+
+    pub fn get_outbox_config(&self) -> &OutboxConfig {
+        &self.outbox
+    }
+
+    pub fn set_outbox_config(&mut self, outbox: OutboxConfig) {
+        self.outbox = outbox;
+    }
+
+    pub fn get_send_config(&self) -> &SendConfig {
+        &self.send
+    }
+
+    pub fn set_send_config(&mut self, send: SendConfig) {
+        self.send = send;
+    }
+
+    pub fn get_gossip_config(&self) -> &GossipConfig {
+        &self.gossip
+    }
+
+    pub fn set_gossip_config(&mut self, gossip: GossipConfig) {
+        self.gossip = gossip;
+    }
+
+    pub fn get_piggyback_config(&self) -> &PiggybackConfig {
+        &self.piggyback
+    }
+
+    pub fn set_piggyback_config(&mut self, piggyback: PiggybackConfig) {
+        self.piggyback = piggyback;
+    }
+
+    pub fn get_nat_config(&self) -> &NatConfig {
+        &self.nat
+    }
+
+    pub fn set_nat_config(&mut self, nat: NatConfig) {
+        self.nat = nat;
+    }
+
+    pub fn get_capacity_config(&self) -> &CapacityConfig {
+        &self.capacity
+    }
+
+    pub fn set_capacity_config(&mut self, capacity: CapacityConfig) {
+        self.capacity = capacity;
+    }
+
+    pub fn get_routing_config(&self) -> &RoutingConfig {
+        &self.routing
+    }
+
+    pub fn set_routing_config(&mut self, routing: RoutingConfig) {
+        self.routing = routing;
+    }
+
+    pub fn get_rate_limit_config(&self) -> &RateLimitConfig {
+        &self.rate_limits
+    }
+
+    pub fn set_rate_limit_config(&mut self, rate_limits: RateLimitConfig) {
+        self.rate_limits = rate_limits;
+    }
+
+    pub fn get_fragment_config(&self) -> &FragmentConfig {
+        &self.fragments
+    }
+
+    pub fn set_fragment_config(&mut self, fragments: FragmentConfig) {
+        self.fragments = fragments;
+    }
+
+    pub fn get_telemetry_config(&self) -> &TelemetryConfig {
+        &self.telemetry
+    }
+
+    pub fn set_telemetry_config(&mut self, telemetry: TelemetryConfig) {
+        self.telemetry = telemetry;
+    }
+
+    /// Check that the configuration is usable, e.g. after parsing it from
+    /// the command line
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.get_max_message_size() == 0 {
+            return Err(ConfigError::ZeroMaxMessageSize);
+        }
+        self.transport.validate()?;
+        self.hop_limits.validate()?;
+        self.diversity.validate()?;
+        self.outbox.validate()?;
+        self.send.validate()?;
+        self.rate_limits.validate()?;
+        self.fragments.validate()?;
+        self.gossip.validate()
+    }
+}
+
+/// Builder of validated [`P2pConfig`]s
+#[derive(Clone, Debug, Default)]
+pub struct P2pConfigBuilder {
+    config: P2pConfig,
+}
+
+impl P2pConfigBuilder {
+    pub fn bootstrap_nodes(mut self, peers: impl IntoIterator<Item = SocketAddr>) -> Self {
+        self.config.bootstrap_nodes.extend(peers);
+        self
+    }
+
+    pub fn quic(mut self, quic: QuicConfig) -> Self {
+        self.config.quic = quic;
+        self
+    }
+
+    pub fn deploy_agent(mut self, deploy_agent: bool) -> Self {
+        self.config.deploy_agent = deploy_agent;
+        self
+    }
+
+    pub fn rpc_addr(mut self, addr: SocketAddr) -> Self {
+        self.config.rpc_addr = Some(addr);
+        self
+    }
+
+    pub fn max_message_size(mut self, size: u64) -> Self {
+        self.config.max_message_size = Some(size);
+        self
+    }
+
+    pub fn network_id(mut self, network_id: Hash) -> Self {
+        self.config.network_id = network_id;
+        self
+    }
+
+    pub fn transport(mut self, transport: TransportConfig) -> Self {
+        self.config.transport = transport;
+        self
+    }
+
+    pub fn hop_limits(mut self, hop_limits: HopLimits) -> Self {
+        self.config.hop_limits = hop_limits;
+        self
+    }
+
+    pub fn diversity(mut self, diversity: DiversityConfig) -> Self {
+        self.config.diversity = diversity;
+        self
+    }
+
+    pub fn outbox(mut self, outbox: OutboxConfig) -> Self {
+        self.config.outbox = outbox;
+        self
+    }
+
+    pub fn send(mut self, send: SendConfig) -> Self {
+        self.config.send = send;
+        self
+    }
+
+    pub fn gossip(mut self, gossip: GossipConfig) -> Self {
+        self.config.gossip = gossip;
+        self
+    }
+
+    pub fn piggyback(mut self, piggyback: PiggybackConfig) -> Self {
+        self.config.piggyback = piggyback;
+        self
+    }
+
+    pub fn nat(mut self, nat: NatConfig) -> Self {
+        self.config.nat = nat;
+        self
+    }
+
+    pub fn capacity(mut self, capacity: CapacityConfig) -> Self {
+        self.config.capacity = capacity;
+        self
+    }
+
+    pub fn routing(mut self, routing: RoutingConfig) -> Self {
+        self.config.routing = routing;
+        self
+    }
+
+    pub fn rate_limits(mut self, rate_limits: RateLimitConfig) -> Self {
+        self.config.rate_limits = rate_limits;
+        self
+    }
+
+    pub fn fragments(mut self, fragments: FragmentConfig) -> Self {
+        self.config.fragments = fragments;
+        self
+    }
+
+    pub fn telemetry(mut self, telemetry: TelemetryConfig) -> Self {
+        self.config.telemetry = telemetry;
+        self
+    }
+
+    /// Validate the configuration and build it
+    pub fn build(self) -> Result<P2pConfig, ConfigError> {
+        self.config.validate()?;
+        Ok(self.config)
+    }
+}
+
+/// Maximum number of hops relayed messages may travel, per message type
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize, StructOpt)]
+pub struct HopLimits {
+    #[structopt(long = "default-hop-limit", default_value = "5")]
+    default: usize,
+    /// Limits of specific message types, e.g. `{"UserMessage": 8}`
+    #[structopt(long = "hop-limits", default_value = "{}", parse(try_from_str = serde_json::from_str))]
+    per_type: HashMap<String, usize>,
+}
+
+impl HopLimits {
+    /// Initialize HopLimits applying `default` to every message type
+    pub fn new(default: usize) -> Self {
+        Self {
+            default,
+            per_type: HashMap::new(),
+        }
+    }
+
+    /// Set the limit of a message type, see [`Message::kind`]
+    pub fn set(&mut self, kind: &str, limit: usize) -> &mut Self {
+        let _ = self.per_type.insert(kind.to_string(), limit);
+        self
+    }
+
+    /// Hop limit of a message
+    pub fn get(&self, message: &Message) -> usize {
+        self.get_kind(message.kind())
+    }
+
+    /// Hop limit of a message type, see [`Message::kind`]
+    pub fn get_kind(&self, kind: &str) -> usize {
+        self.per_type.get(kind).copied().unwrap_or(self.default)
+    }
+
+    /// Check that every message can leave the node
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.default == 0 {
+            return Err(ConfigError::ZeroHopLimit("default".to_string()));
+        }
+        match self.per_type.iter().find(|(_, limit)| **limit == 0) {
+            Some((kind, _)) => Err(ConfigError::ZeroHopLimit(kind.clone())),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Default for HopLimits {
+    fn default() -> Self {
+        Self::new(DEFAULT_HOP_LIMIT)
+    }
+}
+
+/// Limits on how concentrated connections may be in a single subnet,
+/// making it harder for one operator to eclipse the node
+#[derive(Clone, Debug, PartialEq, StructOpt)]
+pub struct DiversityConfig {
+    /// Connections allowed from a single /16 (IPv4) or /32 (IPv6) subnet
+    #[structopt(long, default_value = "2")]
+    max_connections_per_subnet: usize,
+}
+
+impl DiversityConfig {
+    pub fn new(max_connections_per_subnet: usize) -> Self {
+        Self {
+            max_connections_per_subnet,
+        }
+    }
+
+    pub fn max_connections_per_subnet(&self) -> usize {
+        self.max_connections_per_subnet
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.max_connections_per_subnet == 0 {
+            return Err(ConfigError::NoConnectionsPerSubnet);
+        }
+        Ok(())
+    }
+}
+
+impl Default for DiversityConfig {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_CONNECTIONS_PER_SUBNET)
+    }
+}
+
+/// Bounds on the envelopes queued for each next hop, so that a dead or slow
+/// peer cannot make the outbox grow forever
+#[derive(Clone, Debug, PartialEq, StructOpt)]
+pub struct OutboxConfig {
+    /// Envelopes queued for a single next hop, and unsent messages waiting
+    /// to be retried for a single peer
+    #[structopt(long, default_value = "1024")]
+    outbox_capacity: usize,
+    /// What to do with envelopes for a full queue: drop-new, drop-old or
+    /// reroute
+    #[structopt(long, default_value = "drop-old")]
+    overflow_policy: OverflowPolicy,
+}
+
+impl OutboxConfig {
+    pub fn new(outbox_capacity: usize, overflow_policy: OverflowPolicy) -> Self {
+        Self {
+            outbox_capacity,
+            overflow_policy,
+        }
+    }
+
+    pub fn outbox_capacity(&self) -> usize {
+        self.outbox_capacity
+    }
+
+    pub fn overflow_policy(&self) -> OverflowPolicy {
+        self.overflow_policy
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.outbox_capacity == 0 {
+            return Err(ConfigError::ZeroOutboxCapacity);
+        }
+        Ok(())
+    }
+}
+
+impl Default for OutboxConfig {
+    fn default() -> Self {
+        Self::new(DEFAULT_OUTBOX_CAPACITY, OverflowPolicy::default())
+    }
+}
+
+/// What happens to an envelope queued for a next hop whose queue is full
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub enum OverflowPolicy {
+    /// Drop the envelope being queued
+    DropNew,
+    /// Drop the oldest envelope of the queue to make room
+    #[default]
+    DropOld,
+    /// Queue the envelope for another neighbour with room left, dropping it
+    /// if there is none
+    Reroute,
+}
+
+impl FromStr for OverflowPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "drop-new" => Ok(OverflowPolicy::DropNew),
+            "drop-old" => Ok(OverflowPolicy::DropOld),
+            "reroute" => Ok(OverflowPolicy::Reroute),
+            _ => Err(format!("Unknown overflow policy: {}", s)),
+        }
+    }
+}
+
+/// How long a message may take to be sent and how many times it is retried
+/// when QUIC fails to send it
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct SendPolicy {
+    timeout_msec: u64,
+    max_retries: u32,
+}
+
+impl SendPolicy {
+    pub fn new(timeout_msec: u64, max_retries: u32) -> Self {
+        Self {
+            timeout_msec,
+            max_retries,
+        }
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_msec)
+    }
+
+    pub fn max_retries(&self) -> u32 {
+        self.max_retries
+    }
+}
+
+impl Default for SendPolicy {
+    fn default() -> Self {
+        Self::new(DEFAULT_SEND_TIMEOUT_MSEC, DEFAULT_SEND_RETRIES)
+    }
+}
+
+/// Send policies of the message classes
+#[derive(Clone, Debug, Default, PartialEq, StructOpt)]
+pub struct SendConfig {
+    /// Policies of specific message classes, e.g.
+    /// `{"Consensus": {"timeout_msec": 1000, "max_retries": 1}}`
+    #[structopt(
+        long = "send-policies",
+        default_value = "{}",
+        parse(try_from_str = serde_json::from_str)
+    )]
+    per_class: HashMap<MessageClass, SendPolicy>,
+}
+
+impl SendConfig {
+    /// Set the policy of a message class
+    pub fn set(&mut self, class: MessageClass, policy: SendPolicy) -> &mut Self {
+        let _ = self.per_class.insert(class, policy);
+        self
+    }
+
+    /// Policy of a message class. Consensus messages are not worth sending
+    /// once their round is over, so they get a shorter default.
+    pub fn get(&self, class: MessageClass) -> SendPolicy {
+        self.per_class.get(&class).copied().unwrap_or(match class {
+            MessageClass::Consensus => SendPolicy::new(2_000, 2),
+            MessageClass::Routing => SendPolicy::new(5_000, 1),
+            MessageClass::Control | MessageClass::User => SendPolicy::default(),
+        })
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        match self
+            .per_class
+            .iter()
+            .find(|(_, policy)| policy.timeout_msec == 0)
+        {
+            Some((class, _)) => Err(ConfigError::ZeroSendTimeout(*class)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Bounds of the gossip fanout, and the share of duplicate gossip received
+/// outside of which it is adjusted
+#[derive(Clone, Debug, PartialEq, StructOpt)]
+pub struct GossipConfig {
+    #[structopt(long = "gossip-min-fanout", default_value = "2")]
+    min_fanout: usize,
+    #[structopt(long = "gossip-max-fanout", default_value = "16")]
+    max_fanout: usize,
+    /// Below this share of duplicates, gossip is assumed not to cover the
+    /// network and the fanout grows
+    #[structopt(long = "gossip-min-duplicate-ratio", default_value = "0.2")]
+    min_duplicate_ratio: f64,
+    /// Above this share of duplicates, bandwidth is wasted and the fanout
+    /// shrinks
+    #[structopt(long = "gossip-max-duplicate-ratio", default_value = "0.7")]
+    max_duplicate_ratio: f64,
+}
+
+impl GossipConfig {
+    pub fn new(min_fanout: usize, max_fanout: usize) -> Self {
+        Self {
+            min_fanout,
+            max_fanout,
+            min_duplicate_ratio: DEFAULT_MIN_DUPLICATE_RATIO,
+            max_duplicate_ratio: DEFAULT_MAX_DUPLICATE_RATIO,
+        }
+    }
+
+    pub fn min_fanout(&self) -> usize {
+        self.min_fanout
+    }
+
+    pub fn max_fanout(&self) -> usize {
+        self.max_fanout
+    }
+
+    /// Range of duplicate ratios the fanout is left alone in
+    pub fn duplicate_ratios(&self) -> (f64, f64) {
+        (self.min_duplicate_ratio, self.max_duplicate_ratio)
+    }
+
+    pub fn set_duplicate_ratios(&mut self, min: f64, max: f64) -> &mut Self {
+        self.min_duplicate_ratio = min;
+        self.max_duplicate_ratio = max;
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.min_fanout == 0 || self.min_fanout > self.max_fanout {
+            return Err(ConfigError::InvalidFanout {
+                min: self.min_fanout,
+                max: self.max_fanout,
+            });
+        }
+        let (min, max) = self.duplicate_ratios();
+        if !(0.0..=1.0).contains(&min) || !(0.0..=1.0).contains(&max) || min >= max {
+            return Err(ConfigError::InvalidDuplicateRatios { min, max });
+        }
+        Ok(())
+    }
+}
+
+impl Default for GossipConfig {
+    fn default() -> Self {
+        Self::new(DEFAULT_MIN_FANOUT, DEFAULT_MAX_FANOUT)
+    }
+}
+
+/// How long small consensus responses may wait for other traffic to their
+/// next hop, to be sent along with it instead of on their own
+#[derive(Clone, Debug, PartialEq, StructOpt)]
+pub struct PiggybackConfig {
+    /// Longest a response waits, 0 sending responses right away
+    #[structopt(long = "piggyback-delay-msec", default_value = "0")]
+    delay_msec: u64,
+    /// Responses serialized larger than this are never held
+    #[structopt(long = "piggyback-max-size", default_value = "256")]
+    max_size: usize,
+}
+
+impl PiggybackConfig {
+    pub fn new(delay: Duration, max_size: usize) -> Self {
+        Self {
+            delay_msec: delay.as_millis() as u64,
+            max_size,
+        }
+    }
+
+    pub fn delay(&self) -> Duration {
+        Duration::from_millis(self.delay_msec)
+    }
+
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.delay_msec > 0 && self.max_size > 0
+    }
+}
+
+impl Default for PiggybackConfig {
+    fn default() -> Self {
+        Self::new(Duration::default(), DEFAULT_PIGGYBACK_MAX_SIZE)
+    }
+}
+
+/// Traversal of NATs between peers unable to accept connections
+#[derive(Clone, Debug, PartialEq, StructOpt)]
+pub struct NatConfig {
+    /// Coordinate hole punches between our peers, and relay their messages
+    /// when punching fails. Meant for nodes with a public address.
+    #[structopt(long = "relay")]
+    relay: bool,
+    /// Time a hole punch may take before falling back to a relay
+    #[structopt(long = "punch-timeout-msec", default_value = "5000")]
+    punch_timeout_msec: u64,
+}
+
+impl NatConfig {
+    pub fn new(relay: bool, punch_timeout: Duration) -> Self {
+        Self {
+            relay,
+            punch_timeout_msec: punch_timeout.as_millis() as u64,
+        }
+    }
+
+    pub fn relay(&self) -> bool {
+        self.relay
+    }
+
+    pub fn punch_timeout(&self) -> Duration {
+        Duration::from_millis(self.punch_timeout_msec)
+    }
+}
+
+impl Default for NatConfig {
+    fn default() -> Self {
+        Self::new(false, Duration::from_millis(DEFAULT_PUNCH_TIMEOUT_MSEC))
+    }
+}
+
+/// Capacity the node advertises to its peers
+#[derive(Clone, Debug, PartialEq, StructOpt)]
+pub struct CapacityConfig {
+    /// Transactions per second we validate for peers
+    #[structopt(long = "max-tps", default_value = "100")]
+    max_tps: u64,
+    /// Bytes per second we spend on the network
+    #[structopt(long = "bandwidth-budget", default_value = "1048576")]
+    bandwidth_budget: u64,
+    /// How we keep the DAG: archive or pruned
+    #[structopt(long = "storage-mode", default_value = "archive")]
+    storage_mode: StorageMode,
+}
+
+impl CapacityConfig {
+    pub fn new(max_tps: u64, bandwidth_budget: u64, storage_mode: StorageMode) -> Self {
+        Self {
+            max_tps,
+            bandwidth_budget,
+            storage_mode,
+        }
+    }
+
+    pub fn max_tps(&self) -> u64 {
+        self.max_tps
+    }
+
+    pub fn bandwidth_budget(&self) -> u64 {
+        self.bandwidth_budget
+    }
+
+    pub fn storage_mode(&self) -> StorageMode {
+        self.storage_mode
+    }
+
+    /// Statement of the capacity, to be signed and advertised
+    pub fn capacity(&self) -> Capacity {
+        Capacity {
+            max_tps: self.max_tps,
+            bandwidth: self.bandwidth_budget,
+            storage: self.storage_mode,
+        }
+    }
+}
+
+impl Default for CapacityConfig {
+    fn default() -> Self {
+        Self::new(
+            DEFAULT_MAX_TPS,
+            DEFAULT_BANDWIDTH_BUDGET,
+            StorageMode::default(),
+        )
+    }
+}
+
+/// Expiry of the routes learnt from peers
+#[derive(Clone, Debug, PartialEq, StructOpt)]
+pub struct RoutingConfig {
+    /// Time a route is kept without its next hop advertising it again
+    #[structopt(long = "route-ttl-sec", default_value = "300")]
+    route_ttl_sec: u64,
+    /// Time routes to a departed peer are ignored in the tables peers share
+    #[structopt(long = "tombstone-ttl-sec", default_value = "600")]
+    tombstone_ttl_sec: u64,
+}
+
+impl RoutingConfig {
+    pub fn new(route_ttl: Duration, tombstone_ttl: Duration) -> Self {
+        Self {
+            route_ttl_sec: route_ttl.as_secs(),
+            tombstone_ttl_sec: tombstone_ttl.as_secs(),
+        }
+    }
+
+    pub fn route_ttl(&self) -> Duration {
+        Duration::from_secs(self.route_ttl_sec)
+    }
+
+    pub fn tombstone_ttl(&self) -> Duration {
+        Duration::from_secs(self.tombstone_ttl_sec)
+    }
+}
+
+impl Default for RoutingConfig {
+    fn default() -> Self {
+        Self::new(
+            Duration::from_secs(DEFAULT_ROUTE_TTL_SEC),
+            Duration::from_secs(DEFAULT_TOMBSTONE_TTL_SEC),
+        )
+    }
+}
+
+/// Rates each peer may send us messages at, see
+/// [`RateLimiter`](super::ratelimit::RateLimiter)
+#[derive(Clone, Debug, PartialEq, StructOpt)]
+pub struct RateLimitConfig {
+    /// Messages per second a peer may send us, in bursts of as many
+    #[structopt(long = "peer-messages-per-sec", default_value = "500")]
+    messages_per_sec: u64,
+    /// Bytes per second a peer may send us, in bursts of as many
+    #[structopt(long = "peer-bytes-per-sec", default_value = "4194304")]
+    bytes_per_sec: u64,
+    /// Time the messages of a peer over its limits are dropped for
+    #[structopt(long = "peer-throttle-sec", default_value = "10")]
+    throttle_sec: u64,
+    /// Times a peer may be throttled before we disconnect from it
+    #[structopt(long = "peer-max-throttles", default_value = "3")]
+    max_throttles: u32,
+}
+
+impl RateLimitConfig {
+    pub fn new(
+        messages_per_sec: u64,
+        bytes_per_sec: u64,
+        throttle: Duration,
+        max_throttles: u32,
+    ) -> Self {
+        Self {
+            messages_per_sec,
+            bytes_per_sec,
+            throttle_sec: throttle.as_secs(),
+            max_throttles,
+        }
+    }
+
+    pub fn messages_per_sec(&self) -> u64 {
+        self.messages_per_sec
+    }
+
+    pub fn bytes_per_sec(&self) -> u64 {
+        self.bytes_per_sec
+    }
+
+    pub fn throttle(&self) -> Duration {
+        Duration::from_secs(self.throttle_sec)
+    }
+
+    pub fn max_throttles(&self) -> u32 {
+        self.max_throttles
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.messages_per_sec == 0 || self.bytes_per_sec == 0 {
+            return Err(ConfigError::ZeroRateLimit);
+        }
+        Ok(())
+    }
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self::new(
+            DEFAULT_PEER_MESSAGES_PER_SEC,
+            DEFAULT_PEER_BYTES_PER_SEC,
+            Duration::from_secs(DEFAULT_PEER_THROTTLE_SEC),
+            DEFAULT_PEER_MAX_THROTTLES,
+        )
+    }
+}
+
+/// Splitting of large messages into fragments, see
+/// [`Reassembler`](super::fragment::Reassembler)
+#[derive(Clone, Debug, PartialEq, StructOpt)]
+pub struct FragmentConfig {
+    /// Messages serialized larger than this are sent in fragments of at
+    /// most as many bytes
+    #[structopt(long = "fragment-size", default_value = "65536")]
+    fragment_size: usize,
+    /// Time the fragments of a message may take to all arrive
+    #[structopt(long = "reassembly-timeout-sec", default_value = "30")]
+    reassembly_timeout_sec: u64,
+    /// Bytes of incomplete messages buffered for each peer
+    #[structopt(long = "max-reassembly-size", default_value = "16777216")]
+    max_reassembly_size: u64,
+}
+
+impl FragmentConfig {
+    pub fn new(
+        fragment_size: usize,
+        reassembly_timeout: Duration,
+        max_reassembly_size: u64,
+    ) -> Self {
+        Self {
+            fragment_size,
+            reassembly_timeout_sec: reassembly_timeout.as_secs(),
+            max_reassembly_size,
+        }
+    }
+
+    pub fn fragment_size(&self) -> usize {
+        self.fragment_size
+    }
+
+    pub fn reassembly_timeout(&self) -> Duration {
+        Duration::from_secs(self.reassembly_timeout_sec)
+    }
+
+    pub fn max_reassembly_size(&self) -> u64 {
+        self.max_reassembly_size
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.fragment_size == 0 || self.max_reassembly_size == 0 {
+            return Err(ConfigError::ZeroFragmentSize);
+        }
+        Ok(())
+    }
+}
+
+impl Default for FragmentConfig {
+    fn default() -> Self {
+        Self::new(
+            DEFAULT_FRAGMENT_SIZE,
+            Duration::from_secs(DEFAULT_REASSEMBLY_TIMEOUT_SEC),
+            DEFAULT_MAX_MESSAGE_SIZE,
+        )
+    }
+}
+
+/// Export of traces and metrics to an OpenTelemetry collector
+#[derive(Clone, Debug, PartialEq, StructOpt)]
+pub struct TelemetryConfig {
+    /// OTLP/HTTP receiver of the collector, nothing is exported without one
+    #[structopt(long)]
+    otlp_endpoint: Option<SocketAddr>,
+    /// Name the node reports its traces and metrics under
+    #[structopt(long, default_value = "dagchain")]
+    service_name: String,
+}
+
+impl TelemetryConfig {
+    pub fn new(otlp_endpoint: Option<SocketAddr>, service_name: impl Into<String>) -> Self {
+        Self {
+            otlp_endpoint,
+            service_name: service_name.into(),
+        }
+    }
+
+    pub fn otlp_endpoint(&self) -> Option<SocketAddr> {
+        self.otlp_endpoint
+    }
+
+    pub fn service_name(&self) -> &str {
+        &self.service_name
+    }
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self::new(None, DEFAULT_SERVICE_NAME)
+    }
+}
+
+/// Named sets of transport parameters suited to a kind of network
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TransportProfile {
+    /// Low latency, reliable links
+    Lan,
+    /// Long-haul links with high round-trip times
+    #[default]
+    Wan,
+    /// Links dropping a noticeable share of packets
+    Lossy,
+}
+
+impl FromStr for TransportProfile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "lan" => Ok(TransportProfile::Lan),
+            "wan" => Ok(TransportProfile::Wan),
+            "lossy" => Ok(TransportProfile::Lossy),
+            _ => Err(format!("Unknown transport profile: {}", s)),
+        }
+    }
+}
+
+/// Transport carrying the messages exchanged with peers
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub enum TransportKind {
+    /// QUIC over UDP, through quic-p2p
+    #[default]
+    Quic,
+    /// Length-prefixed frames over TCP, for networks blocking UDP
+    Tcp,
+}
+
+impl FromStr for TransportKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "quic" => Ok(TransportKind::Quic),
+            "tcp" => Ok(TransportKind::Tcp),
+            _ => Err(format!("Unknown transport: {}", s)),
+        }
+    }
+}
+
+/// Transport tuning of peer connections.
+///
+/// Parameters come from the selected profile unless overridden one by one.
+#[derive(Clone, Debug, Default, StructOpt)]
+#[structopt(rename_all = "kebab-case")]
+pub struct TransportConfig {
+    #[structopt(long = "transport", default_value = "quic")]
+    kind: TransportKind,
+    #[structopt(long = "transport-profile", default_value = "wan")]
+    profile: TransportProfile,
+    #[structopt(long)]
+    idle_timeout_msec: Option<u64>,
+    #[structopt(long)]
+    keep_alive_interval_msec: Option<u32>,
+}
+
+impl TransportConfig {
+    /// Initialize a TransportConfig from a profile
+    pub fn new(profile: TransportProfile) -> Self {
+        Self {
+            profile,
+            ..Default::default()
+        }
+    }
+
+    pub fn profile(&self) -> TransportProfile {
+        self.profile
+    }
+
+    pub fn kind(&self) -> TransportKind {
+        self.kind
+    }
+
+    pub fn set_kind(&mut self, kind: TransportKind) -> &mut Self {
+        self.kind = kind;
+        self
+    }
+
+    /// If we hear nothing from a peer for this long, it is declared offline
+    pub fn idle_timeout(&self) -> Duration {
+        let default = match self.profile {
+            TransportProfile::Lan => 10_000,
+            TransportProfile::Wan => 60_000,
+            TransportProfile::Lossy => 120_000,
+        };
+        Duration::from_millis(self.idle_timeout_msec.unwrap_or(default))
+    }
+
+    pub fn set_idle_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.idle_timeout_msec = Some(timeout.as_millis() as u64);
+        self
+    }
+
+    /// Interval of keep-alives sent over idle connections
+    pub fn keep_alive_interval(&self) -> Duration {
+        let default = match self.profile {
+            TransportProfile::Lan => 2_000,
+            TransportProfile::Wan => 15_000,
+            TransportProfile::Lossy => 5_000,
+        };
+        Duration::from_millis(self.keep_alive_interval_msec.unwrap_or(default) as u64)
+    }
+
+    pub fn set_keep_alive_interval(&mut self, interval: Duration) -> &mut Self {
+        self.keep_alive_interval_msec = Some(interval.as_millis() as u32);
+        self
+    }
+
+    /// Check that keep-alives are sent before a connection idles out
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let idle_timeout = self.idle_timeout();
+        let keep_alive = self.keep_alive_interval();
+        if !idle_timeout.is_zero() && keep_alive >= idle_timeout {
+            return Err(ConfigError::KeepAliveTooLong {
+                keep_alive,
+                idle_timeout,
+            });
+        }
+        Ok(())
+    }
+
+    /// Fill in the QUIC parameters left unset in `quic`
+    pub fn apply(&self, quic: &mut QuicConfig) {
+        if quic.idle_timeout_msec.is_none() {
+            quic.idle_timeout_msec = Some(self.idle_timeout().as_millis() as u64);
+        }
+        if quic.keep_alive_interval_msec.is_none() {
+            quic.keep_alive_interval_msec = Some(self.keep_alive_interval().as_millis() as u32);
+        }
+    }
+}
+
+#[test]
+fn test_transport_profiles() {
+    let lan = TransportConfig::new("LAN".parse().unwrap());
+    let wan = TransportConfig::default();
+    assert_eq!(wan.profile(), TransportProfile::Wan);
+    assert!(lan.idle_timeout() < wan.idle_timeout());
+    assert!("satellite".parse::<TransportProfile>().is_err());
+    assert_eq!(wan.kind(), TransportKind::Quic);
+    assert_eq!("TCP".parse(), Ok(TransportKind::Tcp));
+    assert!(wan.keep_alive_interval() < wan.idle_timeout());
+
+    let mut config = P2pConfig::default();
+    config.set_transport_config(lan);
+    let quic = config.get_quic_config();
+    assert_eq!(quic.idle_timeout_msec, Some(10_000));
+    assert_eq!(quic.keep_alive_interval_msec, Some(2_000));
+}
+
+#[test]
+fn test_p2p_config_validation() {
+    assert!(P2pConfig::builder().build().is_ok());
+    let testnet = Hash::new("testnet".as_bytes());
+    let config = P2pConfig::builder().network_id(testnet).build().unwrap();
+    assert_eq!(config.get_network_id(), testnet);
+    let unlimited = RateLimitConfig::new(0, 1, Duration::from_secs(1), 1);
+    assert_eq!(
+        P2pConfig::builder().rate_limits(unlimited).build().err(),
+        Some(ConfigError::ZeroRateLimit)
+    );
+    let unfragmented = FragmentConfig::new(0, Duration::from_secs(1), 1);
+    assert_eq!(
+        P2pConfig::builder().fragments(unfragmented).build().err(),
+        Some(ConfigError::ZeroFragmentSize)
+    );
+    assert_eq!(
+        P2pConfig::builder().max_message_size(0).build().err(),
+        Some(ConfigError::ZeroMaxMessageSize)
+    );
+
+    let mut transport = TransportConfig::new(TransportProfile::Lan);
+    transport.set_keep_alive_interval(Duration::from_secs(30));
+    assert!(matches!(
+        P2pConfig::builder().transport(transport).build(),
+        Err(ConfigError::KeepAliveTooLong { .. })
+    ));
+
+    assert_eq!(
+        P2pConfig::builder()
+            .diversity(DiversityConfig::new(0))
+            .build()
+            .err(),
+        Some(ConfigError::NoConnectionsPerSubnet)
+    );
+
+    assert_eq!(
+        P2pConfig::builder()
+            .outbox(OutboxConfig::new(0, "reroute".parse().unwrap()))
+            .build()
+            .err(),
+        Some(ConfigError::ZeroOutboxCapacity)
+    );
+
+    assert_eq!(
+        P2pConfig::builder()
+            .gossip(GossipConfig::new(4, 3))
+            .build()
+            .err(),
+        Some(ConfigError::InvalidFanout { min: 4, max: 3 })
+    );
+    let mut gossip = GossipConfig::default();
+    gossip.set_duplicate_ratios(0.5, 0.5);
+    assert!(matches!(
+        P2pConfig::builder().gossip(gossip).build(),
+        Err(ConfigError::InvalidDuplicateRatios { .. })
+    ));
+}
+
+#[test]
+fn test_hop_limits() {
+    let mut limits = HopLimits::default();
+    limits.set("UserMessage", 8);
+    assert_eq!(limits.get(&Message::UserMessage(vec![])), 8);
+    assert_eq!(limits.get(&Message::CompleteRound), DEFAULT_HOP_LIMIT);
+
+    limits.set("CompleteRound", 0);
+    assert_eq!(
+        P2pConfig::builder().hop_limits(limits).build().err(),
+        Some(ConfigError::ZeroHopLimit("CompleteRound".to_string()))
+    );
 }
@@ -1,22 +1,101 @@
-use super::{event::Event, message::Message};
-use crate::error::P2pError;
+use super::{
+    address_book::AddressBook,
+    admission::{self, Admission, AdmissionPolicy, IncomingPeerInfo},
+    bootstrap::{BootstrapReport, DialFailure, DialLog, DialOutcome},
+    capacity::CapacityAdvertisement,
+    codec::DEFAULT_MAX_MESSAGE_SIZE,
+    config::{DiversityConfig, NatConfig, RateLimitConfig, RoutingConfig},
+    convergence::Convergence,
+    event::Event,
+    identity::{Identity, PublicId},
+    message::Message,
+    nat::NatTraversal,
+    peers::ConsensusPeers,
+    protocol::{self, Features, Negotiated, ProtocolParams, PROTOCOL_VERSION},
+    ratelimit::{RateLimiter, RateVerdict},
+    tokens::UNTRACKED_TOKEN,
+};
+use crate::{error::P2pError, transport::Transport};
 use bytes::Bytes;
+use consensus::{
+    time::{Clock, SystemClock},
+    transaction::codec,
+    NodeId,
+};
 use crossbeam_channel::{self, Sender};
-use crypto::hash::Hash;
-use quic_p2p::{Peer, QuicP2p, QuicP2pError as QuicError};
+use crypto::{
+    hash::{Hash, HashDomain},
+    signature::{PrivateKey, PublicKey, Signature},
+};
+use metrics::Metrics;
+use quic_p2p::{Peer, QuicP2pError as QuicError};
 use serde::{Deserialize, Serialize};
 use std::collections::{hash_map::Entry, HashMap};
 use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 
 pub(super) const MAX_CONNECTION_LEN: usize = 5;
 
-pub type ConnectionMap = HashMap<SocketAddr, (Option<Hash>, ConnectionState)>;
+pub type ConnectionMap = HashMap<SocketAddr, (Option<NodeId>, ConnectionState)>;
+
+/// Domain of the identification signatures
+const IDENTIFICATION_DOMAIN: &[u8] = b"dagchain:identification";
+
+/// Span the handling of the connection to the peer at `peer_addr` runs in
+pub fn connection_span(peer_addr: &SocketAddr) -> tracing::Span {
+    tracing::info_span!("connection", peer = %peer_addr)
+}
 
 /// Manages the connections of a node
 pub struct Connection {
     entries: ConnectionMap,
-    active_connections: HashMap<Hash, SocketAddr>,
+    active_connections: HashMap<NodeId, SocketAddr>,
     routing_table: RoutingTable,
+    /// Copy of the routes, for monitoring
+    routing_monitor: RoutingMonitor,
+    /// How long routing takes to settle after peers join or leave
+    convergence: Convergence,
+    /// Our external address, and hole punches to peers behind NATs
+    nat: NatTraversal,
+    /// How long routes and tombstones are kept
+    routing: RoutingConfig,
+    address_book: AddressBook,
+    /// Dials attempted and their outcomes
+    dials: DialLog,
+    consensus_peers: ConsensusPeers,
+    /// Decides whether to go on with the handshake of incoming connections
+    admission: AdmissionPolicy,
+    max_connections_per_subnet: usize,
+    /// Largest message accepted from peers
+    max_message_size: u64,
+    /// Rates peers may send us messages at
+    rate_limiter: RateLimiter,
+    /// Hash of our protocol parameters, which peers must share
+    protocol: Hash,
+    /// Transaction encoding negotiated with each identified peer
+    tx_versions: HashMap<NodeId, u8>,
+    /// Network we take part in, which peers must take part in too
+    network_id: Hash,
+    /// Chain we follow, which peers must follow too
+    chain_id: Hash,
+    /// Optional features we support
+    features: Features,
+    /// Protocol version and features agreed on with each peer that greeted us
+    hellos: HashMap<SocketAddr, Negotiated>,
+    /// Our side of the handshakes with peers yet to identify
+    handshakes: HashMap<SocketAddr, Handshake>,
+    /// Peers refused or forgotten since the last
+    /// [`take_dropped`](Self::take_dropped)
+    dropped: Vec<SocketAddr>,
+    metrics: Arc<Metrics>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for Connection {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Connection {
@@ -25,6 +104,376 @@ impl Connection {
             entries: Default::default(),
             active_connections: Default::default(),
             routing_table: Default::default(),
+            routing_monitor: Default::default(),
+            convergence: Default::default(),
+            nat: Default::default(),
+            routing: Default::default(),
+            address_book: Default::default(),
+            dials: Default::default(),
+            consensus_peers: Default::default(),
+            admission: admission::open(),
+            max_connections_per_subnet: DiversityConfig::default().max_connections_per_subnet(),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            rate_limiter: Default::default(),
+            protocol: ProtocolParams::default().hash(),
+            tx_versions: Default::default(),
+            network_id: Hash::default(),
+            chain_id: Hash::default(),
+            features: Features::SUPPORTED,
+            hellos: Default::default(),
+            handshakes: Default::default(),
+            dropped: Default::default(),
+            metrics: Default::default(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Limit the connections to peers of a single subnet
+    pub fn set_max_connections_per_subnet(&mut self, max: usize) -> &mut Self {
+        self.max_connections_per_subnet = max;
+        self
+    }
+
+    /// Decide with `policy` whether to admit peers connecting to us
+    pub fn set_admission_policy(&mut self, policy: AdmissionPolicy) -> &mut Self {
+        self.admission = policy;
+        self
+    }
+
+    /// Refuse messages from peers larger than `max` bytes
+    pub fn set_max_message_size(&mut self, max: u64) -> &mut Self {
+        self.max_message_size = max;
+        self
+    }
+
+    /// Limit the rates peers may send us messages at. Peers keep their
+    /// current limits until they reconnect.
+    pub fn set_rate_limit_config(&mut self, config: &RateLimitConfig) -> &mut Self {
+        self.rate_limiter = RateLimiter::new(config);
+        self
+    }
+
+    /// Identify with the hash of `params`, and refuse peers identifying with
+    /// another
+    pub fn set_protocol_params(&mut self, params: &ProtocolParams) -> &mut Self {
+        self.protocol = params.hash();
+        self
+    }
+
+    /// Identify as taking part in the network `network_id`, refusing peers
+    /// and signed statements of other networks
+    pub fn set_network_id(&mut self, network_id: Hash) -> &mut Self {
+        self.network_id = network_id;
+        self
+    }
+
+    /// Greet peers as following the chain `chain_id`, and refuse peers
+    /// following another
+    pub fn set_chain_id(&mut self, chain_id: Hash) -> &mut Self {
+        self.chain_id = chain_id;
+        self
+    }
+
+    /// Offer peers only `features`, e.g. to turn some off
+    pub fn set_features(&mut self, features: Features) -> &mut Self {
+        self.features = features;
+        self
+    }
+
+    /// Protocol version and features agreed on with the peer `id`, for
+    /// higher layers to tell what they may send it
+    pub fn negotiated(&self, id: &NodeId) -> Option<Negotiated> {
+        let addr = self.active_connections.get(id)?;
+        self.hellos.get(addr).copied()
+    }
+
+    /// Transaction encoding to send the peer `id` transactions in, once it
+    /// identified
+    pub fn tx_version(&self, id: &NodeId) -> Option<u8> {
+        self.tx_versions.get(id).copied()
+    }
+
+    /// Count a message of `size` bytes received from the peer at
+    /// `peer_addr` against its rate limits, before decoding it. Messages of
+    /// peers over their limits are dropped, and the peers penalized, then
+    /// disconnected if they keep at it.
+    pub fn check_rate(
+        &mut self,
+        peer_addr: &SocketAddr,
+        size: usize,
+        transport: &mut dyn Transport,
+    ) -> Result<(), P2pError> {
+        let verdict = self.rate_limiter.check(*peer_addr, size, self.clock.now());
+        if verdict == RateVerdict::Allow {
+            return Ok(());
+        }
+        self.metrics.message_rate_limited();
+        let peer = match self.entries.get(peer_addr) {
+            Some((Some(peer), _)) => Some(*peer),
+            _ => None,
+        };
+        if verdict != RateVerdict::Dropped {
+            if let Some(peer) = &peer {
+                self.consensus_peers.penalize_flooding(peer);
+            }
+        }
+        match verdict {
+            RateVerdict::Throttled => {
+                log::warn!("Throttling {:?}: it is over its rate limits", peer_addr)
+            }
+            RateVerdict::Disconnect => {
+                log::warn!(
+                    "Disconnecting from {:?}: it kept going over its rate limits",
+                    peer_addr
+                );
+                let _ = self.forget(peer_addr);
+                transport.disconnect_from(*peer_addr);
+            }
+            _ => {}
+        }
+        Err(P2pError::RateLimited(*peer_addr))
+    }
+
+    /// Decode a message received from the peer at `peer_addr`. A peer
+    /// sending something we can't decode is penalized, harder if it was
+    /// oversized.
+    pub fn decode_message(
+        &self,
+        peer_addr: &SocketAddr,
+        bytes: &[u8],
+    ) -> Result<Message, P2pError> {
+        Message::decode(bytes, self.max_message_size).map_err(|e| {
+            log::warn!("Undecodable message from {:?}: {}", peer_addr, e);
+            if let Some((Some(peer), _)) = self.entries.get(peer_addr) {
+                self.consensus_peers.penalize_undecodable(peer, &e);
+            }
+            P2pError::from(e)
+        })
+    }
+
+    /// Peers refused or forgotten since the last call, so that what is
+    /// kept about them elsewhere, e.g. their incomplete messages, can be
+    /// dropped too
+    pub fn take_dropped(&mut self) -> Vec<SocketAddr> {
+        std::mem::take(&mut self.dropped)
+    }
+
+    /// Addresses of the peers we are connected to
+    pub fn address_book(&self) -> &AddressBook {
+        &self.address_book
+    }
+
+    /// Dials attempted so far, bootstrap contacts included, and their
+    /// outcomes
+    pub fn bootstrap_report(&self) -> &BootstrapReport {
+        self.dials.report()
+    }
+
+    /// Record the outcome of the dial to `addr`, reporting a bootstrap that
+    /// failed once no contact remains
+    fn resolve_dial(
+        &mut self,
+        addr: &SocketAddr,
+        outcome: DialOutcome,
+        node_tx: &Sender<Event>,
+    ) -> Result<(), P2pError> {
+        match self.dials.resolved(addr, outcome) {
+            Some(report) if report.has_failed() => node_tx
+                .send(Event::BootstrapFailed(report.clone()))
+                .map_err(P2pError::from),
+            _ => Ok(()),
+        }
+    }
+
+    /// Connected peers to sample for consensus queries, kept in sync as peers
+    /// connect and disconnect. The handle can be cloned and handed to a
+    /// consensus engine.
+    pub fn consensus_peers(&self) -> &ConsensusPeers {
+        &self.consensus_peers
+    }
+
+    /// Whether the subnet of `addr` already has its share of connections.
+    /// Loopback peers, as in local test networks, are never limited.
+    pub fn is_subnet_full(&self, addr: &SocketAddr) -> bool {
+        !addr.ip().is_loopback()
+            && self.address_book.subnet_count(addr) >= self.max_connections_per_subnet
+    }
+
+    /// Set the metrics updated by the connections
+    pub fn set_metrics(&mut self, metrics: Arc<Metrics>) -> &mut Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Time routing convergence and hole punches with `clock`
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) -> &mut Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Time the routing table must stay unchanged after a change of
+    /// topology for routing to be considered converged
+    pub fn set_convergence_settle_time(&mut self, settle_time: Duration) -> &mut Self {
+        self.convergence = Convergence::new(settle_time);
+        self
+    }
+
+    /// Check whether routing converged since peers last joined or left,
+    /// recording the time it took in the metrics
+    pub fn poll_convergence(&mut self) -> Option<Duration> {
+        let took = self
+            .convergence
+            .poll(&self.routing_table, self.clock.now())?;
+        log::debug!("Routing converged in {:?}", took);
+        self.metrics.routing_converged(took);
+        Some(took)
+    }
+
+    /// Set how we traverse NATs, and whether we relay for other peers
+    pub fn set_nat_config(&mut self, config: &NatConfig) -> &mut Self {
+        self.nat = NatTraversal::from_config(config);
+        self
+    }
+
+    /// Set how long routes are kept unless refreshed, and how long routes
+    /// to departed peers are ignored
+    pub fn set_routing_config(&mut self, config: &RoutingConfig) -> &mut Self {
+        self.routing = config.clone();
+        self
+    }
+
+    pub fn nat(&self) -> &NatTraversal {
+        &self.nat
+    }
+
+    /// Address peers see us at, once enough of them agree on it
+    pub fn external_addr(&self) -> Option<SocketAddr> {
+        self.nat.external_addr()
+    }
+
+    /// Record the address a peer sees us at
+    pub fn handle_observed_address(&mut self, peer: &Peer, addr: SocketAddr) {
+        if let Some((Some(id), _)) = self.entries.get(&peer.peer_addr()) {
+            log::trace!("Peer {:?} sees us at {:?}", id, addr);
+            self.nat.observed(*id, addr);
+        }
+    }
+
+    /// Ask the peer connected to `target` to coordinate a hole punch to it.
+    /// Returns false unless a neighbour is known to reach `target` directly.
+    pub fn request_hole_punch(&mut self, target: NodeId, transport: &mut dyn Transport) -> bool {
+        if self.active_connections.contains_key(&target) {
+            return false;
+        }
+        let rendezvous = match self.routing_table.known_route(&target) {
+            Some((next_hop, 2)) => next_hop,
+            _ => return false,
+        };
+        let socket = match self.active_connections.get(&rendezvous) {
+            Some(socket) => *socket,
+            None => return false,
+        };
+        if !self.nat.start_punch(target, rendezvous, self.clock.now()) {
+            return false;
+        }
+        log::debug!("Asking {:?} to punch a hole to {:?}", rendezvous, target);
+        send(transport, socket, &Message::PunchRequest { target });
+        true
+    }
+
+    /// As a rendezvous, tell a peer and the target of its punch request to
+    /// connect to each other
+    pub fn handle_punch_request(
+        &mut self,
+        peer: &Peer,
+        target: NodeId,
+        transport: &mut dyn Transport,
+    ) {
+        let source = match self.entries.get(&peer.peer_addr()) {
+            Some((Some(id), ConnectionState::Connected)) => *id,
+            _ => return,
+        };
+        let target_addr = self.active_connections.get(&target).copied();
+        for (socket, message) in self
+            .nat
+            .coordinate(source, peer.peer_addr(), target, target_addr)
+        {
+            send(transport, socket, &message);
+        }
+    }
+
+    /// Connect to `target` at the address the rendezvous `peer` sees it at
+    pub fn handle_punch(
+        &mut self,
+        peer: &Peer,
+        target: NodeId,
+        addr: SocketAddr,
+        transport: &mut dyn Transport,
+    ) {
+        let rendezvous = match self.entries.get(&peer.peer_addr()) {
+            Some((Some(id), ConnectionState::Connected)) => *id,
+            _ => return,
+        };
+        if self.active_connections.contains_key(&target) {
+            return;
+        }
+        let _ = self.nat.start_punch(target, rendezvous, self.clock.now());
+        log::debug!("Punching a hole to {:?} at {:?}", target, addr);
+        self.connect_to(
+            &ConnectionInfo {
+                hash: target,
+                socket_addr: addr,
+            },
+            transport,
+        );
+    }
+
+    /// Relay messages through the rendezvous to the peers hole punching
+    /// failed to connect us to in time, returning them
+    pub fn expire_hole_punches(&mut self) -> Vec<NodeId> {
+        self.nat
+            .expire_punches(&mut self.routing_table, self.clock.now())
+    }
+
+    /// Record the capacity a peer advertised, which must be signed by the
+    /// peer itself. Returns false if a later statement is known already.
+    pub fn handle_capacity_advertisement(
+        &mut self,
+        peer: &Peer,
+        advertisement: CapacityAdvertisement,
+    ) -> Result<bool, P2pError> {
+        let id = match self.entries.get(&peer.peer_addr()) {
+            Some((Some(id), ConnectionState::Connected)) => *id,
+            _ => return Ok(false),
+        };
+        if advertisement.network_id != self.network_id {
+            log::warn!(
+                "Peer {:?} advertised a capacity for network {}",
+                id,
+                advertisement.network_id
+            );
+            return Err(P2pError::NetworkMismatch {
+                ours: self.network_id,
+                theirs: advertisement.network_id,
+            });
+        }
+        if advertisement.verify()? != id {
+            log::warn!("Peer {:?} advertised a capacity signed by another", id);
+            return Err(P2pError::InvalidSignature);
+        }
+        log::trace!("Peer {:?} advertises {:?}", id, advertisement.capacity);
+        Ok(self.consensus_peers.advertised(id, advertisement))
+    }
+
+    /// Send our signed capacity to every connected peer
+    pub fn advertise_capacity(
+        &self,
+        advertisement: &CapacityAdvertisement,
+        transport: &mut dyn Transport,
+    ) {
+        let message = Message::CapacityAdvertisement(advertisement.clone());
+        for socket in self.active_connections.values() {
+            send(transport, *socket, &message);
         }
     }
 
@@ -36,6 +485,20 @@ impl Connection {
         &self.routing_table
     }
 
+    pub fn routing_table_mut(&mut self) -> &mut RoutingTable {
+        &mut self.routing_table
+    }
+
+    /// Copy of our routes, refreshed as they change. The handle can be
+    /// cloned and polled from another thread, e.g. by a monitoring agent.
+    pub fn routing_monitor(&self) -> &RoutingMonitor {
+        &self.routing_monitor
+    }
+
+    fn publish_routes(&self) {
+        self.routing_monitor.publish(&self.routing_table);
+    }
+
     pub fn our_connections(&self) -> &ConnectionMap {
         &self.entries
     }
@@ -43,181 +506,527 @@ impl Connection {
     pub fn update_routing_table(
         &mut self,
         peer_routing_table: SharedRoutingTable,
-        peer_id: Hash,
-        quic: &mut QuicP2p,
-        our_id: &Hash,
+        peer_id: NodeId,
+        transport: &mut dyn Transport,
+        our_id: &NodeId,
     ) {
+        let now = self.clock.now();
         let _ = peer_routing_table
             .entries()
             .keys()
-            .into_iter()
             .map(|entry| {
-                if !self.routing_table.has_node(entry) {
+                // Stale tables would bring departed peers back
+                if !self.routing_table.has_node(entry) && !self.routing_table.is_buried(entry, now)
+                {
                     self.routing_table.add_new_node(entry);
                 }
             })
             .collect::<Vec<_>>();
         let mut changed = false;
+        let mut refreshed = vec![];
         let _ = self
             .routing_table
             .entries_mut()
             .iter_mut()
             .map(|(dest, (hop_to, hop_count))| {
-                if let Some(new_hop_count) = peer_routing_table.get_routing_info(dest) {
-                    if new_hop_count + 1 < *hop_count {
+                let advertised = peer_routing_table
+                    .get_routing_info(dest)
+                    .filter(|hops| *hops != usize::MAX);
+                match advertised {
+                    Some(new_hop_count) if new_hop_count + 1 < *hop_count => {
                         changed = true;
                         let _ = std::mem::replace(hop_to, peer_id);
                         let _ = std::mem::replace(hop_count, new_hop_count + 1);
+                        refreshed.push(*dest);
                     }
+                    Some(_) if *hop_to == peer_id => refreshed.push(*dest),
+                    // The next hop no longer knows a route
+                    None if *hop_to == peer_id && *hop_count != 1 && *hop_count != usize::MAX => {
+                        changed = true;
+                        let _ = std::mem::replace(hop_count, usize::MAX);
+                    }
+                    _ => {}
                 }
             })
             .collect::<Vec<_>>();
+        for dest in &refreshed {
+            self.routing_table.refresh(dest, now);
+        }
         if changed {
             self.routing_table.increment_version();
-            self.share_routing_table(quic, our_id);
+            self.share_routing_table(transport, our_id);
+        }
+    }
+
+    /// Drop the routes whose next hop did not advertise them again within
+    /// the route TTL, along with expired tombstones, and share our table so
+    /// that peers refresh the routes going through us. Meant to be called
+    /// periodically, more often than the route TTL.
+    pub fn expire_routes(&mut self, transport: &mut dyn Transport, our_id: &NodeId) -> Vec<NodeId> {
+        let expired = self
+            .routing_table
+            .expire(self.clock.now(), self.routing.route_ttl());
+        if !expired.is_empty() {
+            log::debug!("Routes to {:?} aged out", expired);
+            self.routing_table.increment_version();
+        }
+        self.share_routing_table(transport, our_id);
+        expired
+    }
+
+    /// Let our peers know we no longer route to `peer`
+    pub fn announce_departure(
+        &mut self,
+        peer: &NodeId,
+        transport: &mut dyn Transport,
+        our_id: &NodeId,
+    ) {
+        let message = Message::Departed {
+            peer: *peer,
+            source: *our_id,
+        };
+        for socket in self.active_connections.values() {
+            send(transport, *socket, &message);
+        }
+    }
+
+    /// Handle `source` announcing it no longer routes to `peer`. Routes we
+    /// had through it are dropped and the announcement passed on, so that
+    /// it travels as far as the routes did. Returns whether we had one.
+    pub fn handle_departure(
+        &mut self,
+        peer: NodeId,
+        source: NodeId,
+        transport: &mut dyn Transport,
+        our_id: &NodeId,
+    ) -> bool {
+        if peer == *our_id || self.active_connections.contains_key(&peer) {
+            return false;
+        }
+        match self.routing_table.get_routing_info(&peer) {
+            Some((next_hop, _)) if *next_hop == source => {}
+            _ => return false,
         }
+        log::debug!("Peer {:?} departed, as announced by {:?}", peer, source);
+        let until = self.clock.now() + self.routing.tombstone_ttl();
+        let _ = self.routing_table.bury(&peer, until);
+        self.routing_table.increment_version();
+        self.convergence
+            .topology_changed(&self.routing_table, self.clock.now());
+        self.publish_routes();
+        self.announce_departure(&peer, transport, our_id);
+        true
     }
 
-    pub fn get_active_connections(&self) -> &HashMap<Hash, SocketAddr> {
+    pub fn get_active_connections(&self) -> &HashMap<NodeId, SocketAddr> {
         &self.active_connections
     }
 
-    pub fn bootstrap(&mut self, contacts: Vec<SocketAddr>, quic: &mut QuicP2p) {
+    pub fn bootstrap(&mut self, contacts: Vec<SocketAddr>, transport: &mut dyn Transport) {
         for node in contacts {
             if self.entries.len() == MAX_CONNECTION_LEN {
                 break;
             }
+            if self.is_subnet_full(&node) {
+                log::debug!("Skipping contact {:?}: too many peers in its subnet", node);
+                continue;
+            }
             if !self.entries.contains_key(&node) {
-                self.bootstrap_with(node, quic);
+                self.bootstrap_with(node, transport);
             }
         }
     }
 
-    pub fn bootstrap_with(&mut self, socket_addr: SocketAddr, quic: &mut QuicP2p) {
+    pub fn bootstrap_with(&mut self, socket_addr: SocketAddr, transport: &mut dyn Transport) {
         let _ = self
             .entries
             .insert(socket_addr, (None, ConnectionState::Connecting));
-        quic.connect_to(socket_addr);
+        self.dials.dialed(socket_addr, true);
+        transport.connect_to(socket_addr);
     }
 
-    pub fn connect_to(&mut self, conn_info: &ConnectionInfo, quic: &mut QuicP2p) {
+    pub fn connect_to(&mut self, conn_info: &ConnectionInfo, transport: &mut dyn Transport) {
+        let _span = connection_span(&conn_info.socket_addr).entered();
+        if self.is_subnet_full(&conn_info.socket_addr) {
+            log::debug!(
+                "Not connecting to {:?}: too many peers in its subnet",
+                conn_info
+            );
+            return;
+        }
         log::trace!("Connecting to: {:?}", conn_info);
         let _ = self.entries.insert(
             conn_info.socket_addr,
             (Some(conn_info.hash), ConnectionState::Connecting),
         );
-        quic.connect_to(conn_info.socket_addr);
+        self.dials.dialed(conn_info.socket_addr, false);
+        transport.connect_to(conn_info.socket_addr);
     }
 
+    /// Greet a peer we connected to or that connected to us, challenging it
+    /// to identify itself
     pub fn handle_successful_connection(
         &mut self,
         peer: &Peer,
-        our_id: &Hash,
-        node_tx: &Sender<Event>,
-        quic: &mut QuicP2p,
+        transport: &mut dyn Transport,
     ) -> Result<(), P2pError> {
         let socket_addr = peer.peer_addr();
-        let connection_entry = self.entries.get_mut(&socket_addr);
-        let mut connected = false;
-        if let Some((public_key, state)) = connection_entry {
-            quic.send(
-                Peer::Node(socket_addr),
-                Bytes::from(
-                    bincode::serialize(&Message::Identification(*our_id))
-                        .map_err(|e| P2pError::BincodeError(e))?,
-                ),
-                0,
-            );
-            if let Some(key) = public_key {
-                let _ = std::mem::replace(state, ConnectionState::Connected);
-                let _ = self.active_connections.insert(*key, socket_addr);
-                node_tx
-                    .send(Event::ConnectedTo(*key))
-                    .map_err(|e| P2pError::CrossbeamSenderError(e))?;
-                self.routing_table.add_direct_connection(key);
-                self.routing_table.increment_version();
-                connected = true;
-                log::debug!("Successfully connected with peer {:?}", socket_addr);
-                log::debug!("Our connections: {:?}", &self.entries);
-            } else {
-                log::debug!("Waiting for identification from peer: {:?}", &socket_addr);
+        let _span = connection_span(&socket_addr).entered();
+        if !self.entries.contains_key(&socket_addr) {
+            let admission = (self.admission)(&IncomingPeerInfo {
+                addr: socket_addr,
+                connections: self.entries.len(),
+                subnet_connections: self.address_book.subnet_count(&socket_addr),
+                now: self.clock.now(),
+            });
+            if admission == Admission::Reject {
+                log::info!("Admission policy rejected {:?}", &socket_addr);
+                transport.disconnect_from(socket_addr);
+                return Ok(());
             }
-        } else {
-            if self.entries.len() == MAX_CONNECTION_LEN {
+            if admission == Admission::Throttle
+                || self.entries.len() == MAX_CONNECTION_LEN
+                || self.is_subnet_full(&socket_addr)
+            {
                 let our_connections = self.entries.keys().cloned().collect::<Vec<_>>();
                 log::warn!(
-                    "Too many connections. Disconnecting from {:?}",
+                    "Throttled, too many connections, or too many in its subnet. \
+                     Disconnecting from {:?}",
                     &socket_addr
                 );
-                quic.send(
-                    Peer::Node(socket_addr),
+                transport.send(
+                    socket_addr,
                     Bytes::from(
                         bincode::serialize(&Message::Contacts(our_connections))
-                            .map_err(|e| P2pError::BincodeError(e))?,
+                            .map_err(P2pError::BincodeError)?,
                     ),
-                    1,
+                    UNTRACKED_TOKEN,
                 );
                 return Ok(());
             }
             let _ = self
                 .entries
                 .insert(socket_addr, (None, ConnectionState::Incoming));
-            quic.send(
-                Peer::Node(socket_addr),
-                Bytes::from(
-                    bincode::serialize(&Message::Identification(*our_id))
-                        .map_err(|e| P2pError::BincodeError(e))?,
-                ),
-                0,
-            );
         }
-        if connected {
-            self.share_routing_table(quic, our_id);
+        let handshake = Handshake::new();
+        let hello = Message::Hello {
+            protocol_version: PROTOCOL_VERSION,
+            features: self.features,
+            chain_id: self.chain_id,
+            challenge: handshake.challenge,
+            exchange_key: handshake.exchange_key.public_key(),
+        };
+        let _ = self.handshakes.insert(socket_addr, handshake);
+        transport.send(
+            socket_addr,
+            Bytes::from(bincode::serialize(&hello).map_err(P2pError::BincodeError)?),
+            UNTRACKED_TOKEN,
+        );
+        log::debug!("Waiting for identification from peer: {:?}", &socket_addr);
+        Ok(())
+    }
+
+    /// Handle a peer greeting us, refusing it if it follows another chain or
+    /// speaks no protocol version we do. Otherwise we identify ourselves,
+    /// answering its challenge over the key we exchanged with it.
+    #[allow(clippy::too_many_arguments)]
+    pub fn handle_hello(
+        &mut self,
+        identity: &Identity,
+        peer: &Peer,
+        protocol_version: u32,
+        features: Features,
+        chain_id: Hash,
+        challenge: Hash,
+        exchange_key: &PublicKey,
+        node_tx: &Sender<Event>,
+        transport: &mut dyn Transport,
+    ) -> Result<(), P2pError> {
+        let peer_addr = peer.peer_addr();
+        let _span = connection_span(&peer_addr).entered();
+        if chain_id != self.chain_id {
+            log::warn!(
+                "Refusing {:?}: it follows chain {}, we {}",
+                peer_addr,
+                chain_id,
+                self.chain_id
+            );
+            let failure = DialFailure::Handshake(format!("peer follows chain {}", chain_id));
+            self.refuse(peer_addr, failure, node_tx, transport)?;
+            return Err(P2pError::ChainMismatch {
+                ours: self.chain_id,
+                theirs: chain_id,
+            });
         }
+        let version = match protocol::negotiate_version(protocol_version) {
+            Some(version) => version,
+            None => {
+                log::warn!(
+                    "Refusing {:?}: it speaks protocol version {}",
+                    peer_addr,
+                    protocol_version
+                );
+                let failure = DialFailure::Handshake(format!(
+                    "peer speaks protocol version {}",
+                    protocol_version
+                ));
+                self.refuse(peer_addr, failure, node_tx, transport)?;
+                return Err(P2pError::IncompatibleProtocol {
+                    ours: PROTOCOL_VERSION,
+                    theirs: protocol_version,
+                });
+            }
+        };
+        // A relay swapping our ephemeral keys for its own ends up with
+        // another key on each side, so that the identifications it forwards
+        // fail
+        let channel = match self.handshakes.get_mut(&peer_addr) {
+            Some(handshake) => handshake.exchange(exchange_key),
+            None => None,
+        };
+        let channel = match channel {
+            Some(channel) => channel,
+            None => {
+                log::warn!("Refusing {:?}: the key exchange failed", peer_addr);
+                let failure = DialFailure::Handshake("key exchange failed".to_string());
+                self.refuse(peer_addr, failure, node_tx, transport)?;
+                return Err(P2pError::InvalidSignature);
+            }
+        };
+        let negotiated = Negotiated {
+            version,
+            features: self.features.intersection(features),
+        };
+        log::debug!(
+            "Peer {:?} greeted us, agreed on {:?}",
+            peer_addr,
+            negotiated
+        );
+        let _ = self.hellos.insert(peer_addr, negotiated);
+
+        let tx_versions = (codec::MIN_VERSION, codec::VERSION);
+        let bytes = identification_bytes(
+            &challenge,
+            &channel,
+            &self.network_id,
+            &self.protocol,
+            tx_versions,
+        )?;
+        let identification = Message::Identification {
+            public_id: identity.get_public_id(),
+            network_id: self.network_id,
+            protocol: self.protocol,
+            tx_versions,
+            signature: identity.sign_message(&bytes),
+        };
+        send(transport, peer_addr, &identification);
         Ok(())
     }
 
+    /// Handle a peer identifying itself, admitting it once it answered our
+    /// challenge over the key we exchanged, and agrees with us on the
+    /// network, protocol and encoding
+    #[allow(clippy::too_many_arguments)]
     pub fn handle_peer_identification(
         &mut self,
-        our_hash: Hash,
+        our_hash: NodeId,
         peer: &Peer,
-        peer_hash: Hash,
+        public_id: &PublicId,
+        network_id: Hash,
+        protocol: Hash,
+        tx_versions: (u8, u8),
+        signature: &Signature,
         node_tx: &Sender<Event>,
-        quic: &mut QuicP2p,
+        transport: &mut dyn Transport,
     ) -> Result<(), P2pError> {
+        let peer_addr = peer.peer_addr();
+        let _span = connection_span(&peer_addr).entered();
+        let peer_hash = Hash::serialize(&public_id.public_key)
+            .map(NodeId::from)
+            .map_err(P2pError::CryptoError)?;
         log::debug!(
             "Peer {:?} has identified itself as {:?}",
-            peer.peer_addr(),
+            peer_addr,
             &peer_hash
         );
+        if !self.hellos.contains_key(&peer_addr) {
+            log::warn!("Refusing {:?}: it identified before greeting us", peer_addr);
+            let failure = DialFailure::Handshake("peer identified before greeting us".to_string());
+            self.refuse(peer_addr, failure, node_tx, transport)?;
+            return Err(P2pError::MissingHello);
+        }
+        let answered = match self.handshakes.get(&peer_addr) {
+            Some(Handshake {
+                challenge,
+                channel: Some(channel),
+                ..
+            }) => {
+                let bytes =
+                    identification_bytes(challenge, channel, &network_id, &protocol, tx_versions)?;
+                signature.verify(&public_id.public_key, &bytes)
+            }
+            _ => false,
+        };
+        if !answered {
+            log::warn!(
+                "Refusing {:?}: it failed to answer our challenge as {:?}",
+                peer_addr,
+                peer_hash
+            );
+            let failure = DialFailure::Handshake("peer failed to answer our challenge".to_string());
+            self.refuse(peer_addr, failure, node_tx, transport)?;
+            return Err(P2pError::InvalidSignature);
+        }
+        if network_id != self.network_id {
+            log::warn!(
+                "Refusing {:?}: it takes part in network {}, we in {}",
+                peer_addr,
+                network_id,
+                self.network_id
+            );
+            let failure =
+                DialFailure::Handshake(format!("peer takes part in network {}", network_id));
+            self.refuse(peer_addr, failure, node_tx, transport)?;
+            return Err(P2pError::NetworkMismatch {
+                ours: self.network_id,
+                theirs: network_id,
+            });
+        }
+        if protocol != self.protocol {
+            log::warn!(
+                "Refusing {:?}: it runs protocol {}, we run {}",
+                peer_addr,
+                protocol,
+                self.protocol
+            );
+            let failure = DialFailure::Handshake(format!(
+                "peer runs protocol {}, we run {}",
+                protocol, self.protocol
+            ));
+            self.refuse(peer_addr, failure, node_tx, transport)?;
+            return Err(P2pError::ProtocolMismatch {
+                ours: self.protocol,
+                theirs: protocol,
+            });
+        }
+        let tx_version = match codec::negotiate(tx_versions.0, tx_versions.1) {
+            Some(version) => version,
+            None => {
+                log::warn!(
+                    "Refusing {:?}: it decodes transaction encodings {:?}, we {:?}",
+                    peer_addr,
+                    tx_versions,
+                    (codec::MIN_VERSION, codec::VERSION)
+                );
+                let failure = DialFailure::Handshake(format!(
+                    "peer decodes transaction encodings {:?}",
+                    tx_versions
+                ));
+                self.refuse(peer_addr, failure, node_tx, transport)?;
+                return Err(P2pError::IncompatibleEncoding {
+                    ours: (codec::MIN_VERSION, codec::VERSION),
+                    theirs: tx_versions,
+                });
+            }
+        };
         let mut connected = false;
-        if let Entry::Occupied(mut entry) = self.entries.entry(peer.peer_addr()) {
+        let mut expected = None;
+        if let Entry::Occupied(mut entry) = self.entries.entry(peer_addr) {
             let (key, state) = entry.get_mut();
-            if key.is_none() {
-                let _ = std::mem::replace(key, Some(peer_hash));
+            if key.is_some_and(|key| key != peer_hash) {
+                expected = *key;
+            } else if *state != ConnectionState::Connected {
+                let _ = key.replace(peer_hash);
                 let _ = std::mem::replace(state, ConnectionState::Connected);
                 node_tx
                     .send(Event::ConnectedTo(peer_hash))
-                    .map_err(|e| P2pError::CrossbeamSenderError(e))?;
-                let _ = self.active_connections.insert(peer_hash, peer.peer_addr());
+                    .map_err(P2pError::from)?;
+                let _ = self.active_connections.insert(peer_hash, peer_addr);
                 self.routing_table.add_direct_connection(&peer_hash);
                 self.routing_table.increment_version();
+                self.address_book.insert(peer_hash, peer_addr);
+                self.consensus_peers.connected(peer_hash, peer_addr);
+                self.metrics.connection_opened();
                 connected = true;
-                log::debug!("Successfully connected with peer {:?}", peer.peer_addr());
+                log::debug!("Successfully connected with peer {:?}", peer_addr);
                 log::debug!("Our connections: {:?}", &self.entries);
             }
         }
+        if let Some(expected) = expected {
+            log::warn!(
+                "Refusing {:?}: it identified as {:?}, we dialed {:?}",
+                peer_addr,
+                peer_hash,
+                expected
+            );
+            let failure = DialFailure::IdentityMismatch {
+                expected,
+                actual: peer_hash,
+            };
+            self.refuse(peer_addr, failure, node_tx, transport)?;
+            return Err(P2pError::IdentityMismatch {
+                expected,
+                actual: peer_hash,
+            });
+        }
+        let _ = self.handshakes.remove(&peer_addr);
+        let _ = self.tx_versions.insert(peer_hash, tx_version);
         if connected {
-            self.share_routing_table(quic, &our_hash);
+            self.connected(peer_addr, transport);
+            self.share_routing_table(transport, &our_hash);
+            self.resolve_dial(&peer_addr, DialOutcome::Connected(peer_hash), node_tx)?;
         }
         Ok(())
     }
 
-    pub fn share_routing_table(&mut self, quic: &mut QuicP2p, our_id: &Hash) {
+    /// Give up on the handshake with the peer at `peer_addr`, before it was
+    /// admitted
+    fn refuse(
+        &mut self,
+        peer_addr: SocketAddr,
+        failure: DialFailure,
+        node_tx: &Sender<Event>,
+        transport: &mut dyn Transport,
+    ) -> Result<(), P2pError> {
+        let _ = self.entries.remove(&peer_addr);
+        let _ = self.hellos.remove(&peer_addr);
+        let _ = self.handshakes.remove(&peer_addr);
+        self.rate_limiter.forget(&peer_addr);
+        self.dropped.push(peer_addr);
+        transport.disconnect_from(peer_addr);
+        self.resolve_dial(&peer_addr, DialOutcome::Failed(failure), node_tx)
+    }
+
+    /// Bookkeeping once the peer at `socket_addr` is connected and
+    /// identified, telling it the address we see it at
+    fn connected(&mut self, socket_addr: SocketAddr, transport: &mut dyn Transport) {
+        if let Some((Some(id), _)) = self.entries.get(&socket_addr) {
+            if self.nat.punched(id) {
+                log::info!("Punched a hole to {:?}", id);
+            }
+        }
+        self.convergence
+            .topology_changed(&self.routing_table, self.clock.now());
+        self.update_diversity_metrics();
+        send(
+            transport,
+            socket_addr,
+            &Message::ObservedAddress(socket_addr),
+        );
+    }
+
+    fn update_diversity_metrics(&self) {
+        let diversity = self.address_book.diversity();
+        self.metrics
+            .set_peer_diversity(diversity.subnets, diversity.largest);
+    }
+
+    pub fn share_routing_table(&mut self, transport: &mut dyn Transport, our_id: &NodeId) {
+        self.publish_routes();
         let routing_table = self.routing_table.clone();
         for socket in self.get_active_connections().values() {
-            quic.send(
-                Peer::Node(*socket),
+            transport.send(
+                *socket,
                 Bytes::from(
                     bincode::serialize(&Message::RoutingTable {
                         routing_table: routing_table.get_shared(),
@@ -225,52 +1034,189 @@ impl Connection {
                     })
                     .unwrap(),
                 ),
-                0,
+                UNTRACKED_TOKEN,
             );
         }
     }
 
+    /// Handle the connection to a peer failing, announcing its departure
+    /// to our other peers
     pub fn handle_connection_failure(
         &mut self,
         peer: Peer,
         error: QuicError,
+        node_tx: &Sender<Event>,
+        transport: &mut dyn Transport,
+        our_id: &NodeId,
     ) -> Result<(), P2pError> {
         let peer_addr = peer.peer_addr();
+        let _span = connection_span(&peer_addr).entered();
         log::info!(
             "Lost connection with Peer at {:?} due to {:?}",
             &peer_addr,
             &error
         );
-        if let Some((id, _)) = self.entries.remove(&peer_addr) {
-            log::info!("Disconnected from peer: {:?}", id);
-        } else {
-            log::warn!(
-                "We did not maintain the connection with peer at {:?}",
-                &peer_addr
-            );
+        if let Some(id) = self.forget(&peer_addr) {
+            self.announce_departure(&id, transport, our_id);
+        }
+        let failure = DialFailure::from_error(&error);
+        self.resolve_dial(&peer_addr, DialOutcome::Failed(failure), node_tx)
+    }
+
+    /// Handle a peer letting us know it is shutting down, announcing its
+    /// departure to our other peers
+    pub fn handle_peer_disconnecting(
+        &mut self,
+        peer: &Peer,
+        node_tx: &Sender<Event>,
+        transport: &mut dyn Transport,
+        our_id: &NodeId,
+    ) -> Result<(), P2pError> {
+        let _span = connection_span(&peer.peer_addr()).entered();
+        log::info!("Peer at {:?} is shutting down", peer.peer_addr());
+        match self.forget(&peer.peer_addr()) {
+            Some(id) => {
+                self.announce_departure(&id, transport, our_id);
+                node_tx
+                    .send(Event::DisconnectedFrom(id))
+                    .map_err(P2pError::from)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Let every peer know we are shutting down and forget about them
+    pub fn disconnect_all(&mut self, transport: &mut dyn Transport) {
+        let message = bincode::serialize(&Message::Disconnecting).unwrap();
+        let peers = self.entries.keys().copied().collect::<Vec<_>>();
+        for peer_addr in peers {
+            transport.send(peer_addr, Bytes::from(message.clone()), UNTRACKED_TOKEN);
+            let _ = self.forget(&peer_addr);
         }
-        Ok(())
+    }
+
+    /// Drop a peer from our connections, returning its ID if it had
+    /// identified itself
+    fn forget(&mut self, peer_addr: &SocketAddr) -> Option<NodeId> {
+        let _ = self.hellos.remove(peer_addr);
+        let _ = self.handshakes.remove(peer_addr);
+        self.rate_limiter.forget(peer_addr);
+        self.dropped.push(*peer_addr);
+        let (id, state) = match self.entries.remove(peer_addr) {
+            Some(entry) => entry,
+            None => {
+                log::warn!(
+                    "We did not maintain the connection with peer at {:?}",
+                    peer_addr
+                );
+                return None;
+            }
+        };
+        if state == ConnectionState::Connected {
+            self.metrics.connection_closed();
+        }
+        if let Some(id) = id {
+            let _ = self.active_connections.remove(&id);
+            let _ = self.tx_versions.remove(&id);
+            let _ = self.address_book.remove(&id);
+            self.consensus_peers.disconnected(&id);
+            self.nat.forget(&id);
+            let until = self.clock.now() + self.routing.tombstone_ttl();
+            let _ = self.routing_table.bury(&id, until);
+            self.routing_table.increment_version();
+            self.convergence
+                .topology_changed(&self.routing_table, self.clock.now());
+            self.update_diversity_metrics();
+            self.publish_routes();
+        }
+        log::info!("Disconnected from peer: {:?}", id);
+        id
+    }
+}
+
+/// Our side of the handshake with a peer: the challenge and ephemeral key
+/// we greeted it with, and the key we exchanged once it greeted us
+struct Handshake {
+    challenge: Hash,
+    /// Zeroed once dropped, when the peer identified or gave up
+    exchange_key: PrivateKey,
+    channel: Option<Hash>,
+}
+
+impl Handshake {
+    fn new() -> Self {
+        Self {
+            challenge: Hash::generate_random(),
+            exchange_key: PrivateKey::generate(),
+            channel: None,
+        }
+    }
+
+    /// Exchange our ephemeral key with the peer's, returning the key of
+    /// the channel, or None if `peer_key` shares nothing secret with ours
+    fn exchange(&mut self, peer_key: &PublicKey) -> Option<Hash> {
+        let shared = self.exchange_key.agree(peer_key).ok()?;
+        let channel = Hash::combine_in(
+            HashDomain::Custom("dagchain/channel"),
+            &[shared.expose_secret()],
+        );
+        self.channel = Some(channel);
+        Some(channel)
+    }
+}
+
+/// What a peer signs to identify, binding its answer to our challenge to
+/// the connection, through the key exchanged on it, and to the terms it
+/// identifies with
+fn identification_bytes(
+    challenge: &Hash,
+    channel: &Hash,
+    network_id: &Hash,
+    protocol: &Hash,
+    tx_versions: (u8, u8),
+) -> Result<Vec<u8>, P2pError> {
+    let mut bytes = IDENTIFICATION_DOMAIN.to_vec();
+    bytes.extend(
+        bincode::serialize(&(challenge, channel, network_id, protocol, tx_versions))
+            .map_err(P2pError::BincodeError)?,
+    );
+    Ok(bytes)
+}
+
+/// Send a message the sender isn't told the fate of
+fn send(transport: &mut dyn Transport, socket: SocketAddr, message: &Message) {
+    match bincode::serialize(message) {
+        Ok(bytes) => transport.send(socket, Bytes::from(bytes), UNTRACKED_TOKEN),
+        Err(e) => log::error!("Failed to serialize {:?}: {:?}", message, e),
     }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct RoutingTable {
-    entries: HashMap<Hash, (Hash, usize)>,
+    entries: HashMap<NodeId, (NodeId, usize)>,
     version: usize,
+    /// When the next hop of each route last advertised it, routes aging
+    /// out unless refreshed
+    #[serde(skip)]
+    refreshed: HashMap<NodeId, Instant>,
+    /// Departed peers, and until when shared tables listing them are
+    /// ignored
+    #[serde(skip)]
+    tombstones: HashMap<NodeId, Instant>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct SharedRoutingTable {
-    entries: HashMap<Hash, usize>,
+    entries: HashMap<NodeId, usize>,
 }
 
 impl SharedRoutingTable {
-    fn entries(&self) -> &HashMap<Hash, usize> {
+    fn entries(&self) -> &HashMap<NodeId, usize> {
         &self.entries
     }
 
-    fn get_routing_info(&self, node_id: &Hash) -> Option<usize> {
-        self.entries.get(node_id).map(|hops| *hops)
+    fn get_routing_info(&self, node_id: &NodeId) -> Option<usize> {
+        self.entries.get(node_id).copied()
     }
 }
 
@@ -279,6 +1225,8 @@ impl RoutingTable {
         Self {
             entries: HashMap::new(),
             version: 0,
+            refreshed: HashMap::new(),
+            tombstones: HashMap::new(),
         }
     }
 
@@ -287,34 +1235,162 @@ impl RoutingTable {
             .entries
             .iter()
             .map(|(node_id, (_intermediate, hops))| (*node_id, *hops))
-            .collect::<HashMap<Hash, usize>>();
+            .collect::<HashMap<NodeId, usize>>();
         SharedRoutingTable { entries }
     }
 
-    pub fn get_routing_info(&self, node_id: &Hash) -> Option<&(Hash, usize)> {
+    pub fn get_routing_info(&self, node_id: &NodeId) -> Option<&(NodeId, usize)> {
         self.entries.get(node_id)
     }
 
-    pub fn entries_mut(&mut self) -> &mut HashMap<Hash, (Hash, usize)> {
+    /// Next hop and hop count towards a node, if a route to it is known
+    pub fn known_route(&self, node_id: &NodeId) -> Option<(NodeId, usize)> {
+        self.entries
+            .get(node_id)
+            .filter(|(_, hops)| *hops != usize::MAX)
+            .copied()
+    }
+
+    /// Record a route to a node, unless a shorter one is already known.
+    /// Returns whether the route was taken.
+    pub fn update_route(&mut self, node_id: &NodeId, next_hop: &NodeId, hops: usize) -> bool {
+        match self.entries.get(node_id) {
+            Some((_, known)) if *known <= hops => false,
+            _ => {
+                let _ = self.entries.insert(*node_id, (*next_hop, hops));
+                let _ = self.tombstones.remove(node_id);
+                self.increment_version();
+                true
+            }
+        }
+    }
+
+    pub fn entries_mut(&mut self) -> &mut HashMap<NodeId, (NodeId, usize)> {
         &mut self.entries
     }
 
-    pub fn entries(&self) -> &HashMap<Hash, (Hash, usize)> {
+    pub fn entries(&self) -> &HashMap<NodeId, (NodeId, usize)> {
         &self.entries
     }
 
-    pub fn has_node(&self, node_id: &Hash) -> bool {
+    pub fn has_node(&self, node_id: &NodeId) -> bool {
         self.entries.contains_key(node_id)
     }
 
-    pub fn add_new_node(&mut self, node_id: &Hash) {
-        let _ = self
-            .entries
-            .insert(*node_id, (Hash::generate_random(), usize::MAX));
+    pub fn add_new_node(&mut self, node_id: &NodeId) {
+        let _ = self.entries.insert(
+            *node_id,
+            (NodeId::from(Hash::generate_random()), usize::MAX),
+        );
     }
 
-    pub fn add_direct_connection(&mut self, node_id: &Hash) {
+    pub fn add_direct_connection(&mut self, node_id: &NodeId) {
         let _ = self.entries.insert(*node_id, (*node_id, 1));
+        let _ = self.tombstones.remove(node_id);
+    }
+
+    /// Record the next hop towards `node_id` advertising it again
+    pub fn refresh(&mut self, node_id: &NodeId, now: Instant) {
+        let _ = self.refreshed.insert(*node_id, now);
+    }
+
+    /// Forget a departed node, ignoring it in shared tables until `until`.
+    /// Returns whether a route to it was known.
+    pub fn bury(&mut self, node_id: &NodeId, until: Instant) -> bool {
+        let _ = self.refreshed.remove(node_id);
+        let _ = self.tombstones.insert(*node_id, until);
+        self.entries.remove(node_id).is_some()
+    }
+
+    pub fn is_buried(&self, node_id: &NodeId, now: Instant) -> bool {
+        self.tombstones
+            .get(node_id)
+            .is_some_and(|until| *until > now)
+    }
+
+    /// Drop the routes not refreshed for `ttl`, returning the nodes they led
+    /// to. Routes to neighbours are kept as long as we stay connected, and
+    /// routes never refreshed age from the first time they are checked.
+    pub fn expire(&mut self, now: Instant, ttl: Duration) -> Vec<NodeId> {
+        self.tombstones.retain(|_, until| *until > now);
+        let refreshed = &mut self.refreshed;
+        let mut expired = vec![];
+        self.entries.retain(|node_id, (next_hop, hops)| {
+            if *hops == 1 && next_hop == node_id {
+                return true;
+            }
+            let seen = *refreshed.entry(*node_id).or_insert(now);
+            if now.duration_since(seen) < ttl {
+                return true;
+            }
+            expired.push(*node_id);
+            false
+        });
+        for node_id in &expired {
+            let _ = self.refreshed.remove(node_id);
+        }
+        expired
+    }
+
+    /// Nodes we are directly connected to
+    pub fn neighbours(&self) -> impl Iterator<Item = &NodeId> {
+        self.entries
+            .iter()
+            .filter(|(node_id, (next_hop, hops))| *hops == 1 && next_hop == *node_id)
+            .map(|(node_id, _)| node_id)
+    }
+
+    /// Known routes, copied out of the table. Nodes we know of but have no
+    /// route to are left out.
+    pub fn routes(&self) -> Vec<Route> {
+        let mut routes = self
+            .entries
+            .iter()
+            .filter(|(_, (_, hops))| *hops != usize::MAX)
+            .map(|(destination, (next_hop, hops))| Route {
+                destination: *destination,
+                next_hop: *next_hop,
+                hops: *hops,
+            })
+            .collect::<Vec<_>>();
+        routes.sort_by_key(|route| (route.hops, route.destination));
+        routes
+    }
+
+    /// Number of nodes a route is known to
+    pub fn reachable_count(&self) -> usize {
+        self.entries
+            .values()
+            .filter(|(_, hops)| *hops != usize::MAX)
+            .count()
+    }
+
+    /// Average hops of the known routes, if any is known
+    pub fn average_hops(&self) -> Option<f64> {
+        let (count, total) = self
+            .entries
+            .values()
+            .filter(|(_, hops)| *hops != usize::MAX)
+            .fold((0, 0), |(count, total), (_, hops)| {
+                (count + 1, total + hops)
+            });
+        (count > 0).then(|| total as f64 / count as f64)
+    }
+
+    /// Copy of the routes and their summary
+    pub fn snapshot(&self) -> RoutingSnapshot {
+        RoutingSnapshot {
+            version: self.version,
+            known: self.entries.len(),
+            reachable: self.reachable_count(),
+            average_hops: self.average_hops(),
+            routes: self.routes(),
+        }
+    }
+
+    /// Whether a route to every node we know of is known
+    pub fn is_fully_reachable(&self) -> bool {
+        self.entries.values().all(|(_, hops)| *hops != usize::MAX)
     }
 
     pub fn increment_version(&mut self) {
@@ -332,9 +1408,62 @@ impl Default for RoutingTable {
     }
 }
 
+/// Route to a node, as copied out of a [`RoutingTable`]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Route {
+    pub destination: NodeId,
+    pub next_hop: NodeId,
+    pub hops: usize,
+}
+
+/// Routes of a [`RoutingTable`] at one version of it
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct RoutingSnapshot {
+    pub version: usize,
+    /// Nodes we know of, routes to them known or not
+    pub known: usize,
+    pub reachable: usize,
+    pub average_hops: Option<f64>,
+    /// Known routes, the shortest first
+    pub routes: Vec<Route>,
+}
+
+/// Latest [`RoutingSnapshot`] of a node. Clones share the same snapshot,
+/// which readers copy out without touching the routing table.
+#[derive(Clone, Debug, Default)]
+pub struct RoutingMonitor {
+    snapshot: Arc<RwLock<Arc<RoutingSnapshot>>>,
+}
+
+impl RoutingMonitor {
+    /// Replace the snapshot, unless `routing_table` didn't change since
+    fn publish(&self, routing_table: &RoutingTable) {
+        let known = self.snapshot.read().unwrap().version;
+        if known != routing_table.version() || known == 0 {
+            *self.snapshot.write().unwrap() = Arc::new(routing_table.snapshot());
+        }
+    }
+
+    pub fn snapshot(&self) -> Arc<RoutingSnapshot> {
+        self.snapshot.read().unwrap().clone()
+    }
+
+    pub fn routes(&self) -> Vec<Route> {
+        self.snapshot().routes.clone()
+    }
+
+    pub fn reachable_count(&self) -> usize {
+        self.snapshot().reachable
+    }
+
+    pub fn average_hops(&self) -> Option<f64> {
+        self.snapshot().average_hops
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 pub struct ConnectionInfo {
-    pub hash: Hash,
+    pub hash: NodeId,
     pub socket_addr: SocketAddr,
 }
 
@@ -344,3 +1473,329 @@ pub enum ConnectionState {
     Incoming,
     Connected,
 }
+
+#[test]
+fn test_known_routes() {
+    let near = NodeId::from(Hash::new("near".as_bytes()));
+    let far = NodeId::from(Hash::new("far".as_bytes()));
+    let mut routing_table = RoutingTable::default();
+    routing_table.add_direct_connection(&near);
+    routing_table.add_new_node(&far);
+    assert_eq!(routing_table.known_route(&near), Some((near, 1)));
+    assert_eq!(routing_table.known_route(&far), None);
+
+    assert!(routing_table.update_route(&far, &near, 3));
+    assert!(!routing_table.update_route(&far, &NodeId::default(), 4));
+    assert_eq!(routing_table.known_route(&far), Some((near, 3)));
+}
+
+#[test]
+fn test_routes_age_out_and_departed_peers_stay_buried() {
+    let near = NodeId::from(Hash::new("near".as_bytes()));
+    let far = NodeId::from(Hash::new("far".as_bytes()));
+    let gone = NodeId::from(Hash::new("gone".as_bytes()));
+    let ttl = Duration::from_secs(60);
+    let start = Instant::now();
+    let mut routing_table = RoutingTable::default();
+    routing_table.add_direct_connection(&near);
+    assert!(routing_table.update_route(&far, &near, 2));
+    assert!(routing_table.update_route(&gone, &near, 2));
+    routing_table.refresh(&far, start);
+    routing_table.refresh(&gone, start);
+
+    // Refreshed routes are kept, the others age out
+    routing_table.refresh(&far, start + ttl / 2);
+    assert_eq!(routing_table.expire(start + ttl, ttl), vec![gone]);
+    assert!(routing_table.has_node(&far));
+    assert!(routing_table.expire(start + 2 * ttl, ttl).contains(&far));
+    assert!(routing_table.has_node(&near));
+
+    assert!(routing_table.bury(&near, start + 3 * ttl));
+    assert!(routing_table.is_buried(&near, start + 2 * ttl));
+    assert!(routing_table.neighbours().next().is_none());
+    let _ = routing_table.expire(start + 3 * ttl, ttl);
+    assert!(!routing_table.is_buried(&near, start + 3 * ttl));
+    // Connecting again lifts the tombstone right away
+    assert!(!routing_table.bury(&gone, start + 10 * ttl));
+    routing_table.add_direct_connection(&gone);
+    assert!(!routing_table.is_buried(&gone, start));
+}
+
+#[test]
+fn test_routes_are_copied_out_for_monitoring() {
+    let near = NodeId::from(Hash::new("near".as_bytes()));
+    let far = NodeId::from(Hash::new("far".as_bytes()));
+    let unknown = NodeId::from(Hash::new("unknown".as_bytes()));
+    let mut routing_table = RoutingTable::default();
+    routing_table.add_direct_connection(&near);
+    routing_table.add_new_node(&unknown);
+    assert!(routing_table.update_route(&far, &near, 2));
+
+    assert_eq!(routing_table.reachable_count(), 2);
+    assert_eq!(routing_table.average_hops(), Some(1.5));
+    assert_eq!(
+        routing_table.routes(),
+        vec![
+            Route {
+                destination: near,
+                next_hop: near,
+                hops: 1
+            },
+            Route {
+                destination: far,
+                next_hop: near,
+                hops: 2
+            },
+        ]
+    );
+    assert_eq!(RoutingTable::default().average_hops(), None);
+
+    let monitor = RoutingMonitor::default();
+    let agent = monitor.clone();
+    monitor.publish(&routing_table);
+    let snapshot = std::thread::spawn(move || agent.snapshot()).join().unwrap();
+    assert_eq!((snapshot.known, snapshot.reachable), (3, 2));
+    assert_eq!(
+        serde_json::from_str::<RoutingSnapshot>(&serde_json::to_string(&*snapshot).unwrap())
+            .unwrap(),
+        *snapshot
+    );
+}
+
+#[test]
+fn test_peers_greet_and_answer_challenges_to_identify() {
+    use super::config::TransportKind;
+
+    /// Transport recording what we send and whom we disconnect from
+    #[derive(Default)]
+    struct Recorder {
+        sent: Vec<(SocketAddr, Message)>,
+        disconnected: Vec<SocketAddr>,
+    }
+
+    impl Transport for Recorder {
+        fn kind(&self) -> TransportKind {
+            TransportKind::Tcp
+        }
+
+        fn our_addr(&mut self) -> Result<SocketAddr, P2pError> {
+            Ok(([127, 0, 0, 1], 9000).into())
+        }
+
+        fn connect_to(&mut self, _peer: SocketAddr) {}
+
+        fn disconnect_from(&mut self, peer: SocketAddr) {
+            self.disconnected.push(peer);
+        }
+
+        fn send(&mut self, peer: SocketAddr, msg: Bytes, _token: u64) {
+            self.sent.push((peer, bincode::deserialize(&msg).unwrap()));
+        }
+    }
+
+    /// A node, and the address its peers see it at
+    struct Node {
+        identity: Identity,
+        addr: SocketAddr,
+        connection: Connection,
+        transport: Recorder,
+    }
+
+    impl Node {
+        fn new(i: u8, chain_id: Hash) -> Self {
+            let mut connection = Connection::new();
+            let _ = connection.set_chain_id(chain_id);
+            Self {
+                identity: Identity::new(),
+                addr: ([10, 0, i, 1], 9000).into(),
+                connection,
+                transport: Recorder::default(),
+            }
+        }
+
+        fn id(&self) -> NodeId {
+            self.identity.get_our_hash().unwrap()
+        }
+
+        /// Messages sent so far, cleared
+        fn sent(&mut self) -> Vec<(SocketAddr, Message)> {
+            std::mem::take(&mut self.transport.sent)
+        }
+
+        /// Handle a handshake message from the node at `from`
+        fn receive(&mut self, from: SocketAddr, msg: Message) -> Result<(), P2pError> {
+            let (node_tx, _node_rx) = crossbeam_channel::unbounded();
+            let peer = Peer::Node(from);
+            match msg {
+                Message::Hello {
+                    protocol_version,
+                    features,
+                    chain_id,
+                    challenge,
+                    exchange_key,
+                } => self.connection.handle_hello(
+                    &self.identity,
+                    &peer,
+                    protocol_version,
+                    features,
+                    chain_id,
+                    challenge,
+                    &exchange_key,
+                    &node_tx,
+                    &mut self.transport,
+                ),
+                Message::Identification {
+                    public_id,
+                    network_id,
+                    protocol,
+                    tx_versions,
+                    signature,
+                } => self.connection.handle_peer_identification(
+                    self.id(),
+                    &peer,
+                    &public_id,
+                    network_id,
+                    protocol,
+                    tx_versions,
+                    &signature,
+                    &node_tx,
+                    &mut self.transport,
+                ),
+                msg => panic!("Unexpected {:?}", msg),
+            }
+        }
+    }
+
+    let chain_id = Hash::new("chain".as_bytes());
+    let mut a = Node::new(1, chain_id);
+    let mut b = Node::new(2, chain_id);
+    let _ = b
+        .connection
+        .set_features(Features::PIGGYBACK.union(Features::HOLE_PUNCHING));
+
+    // A dials B, and both greet each other before identifying
+    a.connection.connect_to(
+        &ConnectionInfo {
+            hash: b.id(),
+            socket_addr: b.addr,
+        },
+        &mut a.transport,
+    );
+    a.connection
+        .handle_successful_connection(&Peer::Node(b.addr), &mut a.transport)
+        .unwrap();
+    b.connection
+        .handle_successful_connection(&Peer::Node(a.addr), &mut b.transport)
+        .unwrap();
+    let (a_hello, b_hello) = (a.sent(), b.sent());
+    assert!(matches!(a_hello.as_slice(), [(_, Message::Hello { .. })]));
+    assert!(a.connection.get_active_connections().is_empty());
+    b.receive(a.addr, a_hello[0].1.clone()).unwrap();
+    a.receive(b.addr, b_hello[0].1.clone()).unwrap();
+    let (a_ident, b_ident) = (a.sent(), b.sent());
+    b.receive(a.addr, a_ident[0].1.clone()).unwrap();
+    a.receive(b.addr, b_ident[0].1.clone()).unwrap();
+    assert_eq!(
+        a.connection.get_active_connections().get(&b.id()),
+        Some(&b.addr)
+    );
+    assert_eq!(
+        b.connection.get_active_connections().get(&a.id()),
+        Some(&a.addr)
+    );
+    assert_eq!(
+        a.connection.negotiated(&b.id()),
+        Some(Negotiated {
+            version: PROTOCOL_VERSION,
+            features: Features::PIGGYBACK.union(Features::HOLE_PUNCHING),
+        })
+    );
+
+    assert!(matches!(b.sent()[0].1, Message::ObservedAddress(addr) if addr == a.addr));
+
+    // C claims to be A, replaying the identification A signed for B
+    let mut c = Node::new(3, chain_id);
+    b.connection
+        .handle_successful_connection(&Peer::Node(c.addr), &mut b.transport)
+        .unwrap();
+    c.connection
+        .handle_successful_connection(&Peer::Node(b.addr), &mut c.transport)
+        .unwrap();
+    let c_hello = c.sent();
+    b.receive(c.addr, c_hello[0].1.clone()).unwrap();
+    assert!(matches!(
+        b.receive(c.addr, a_ident[0].1.clone()),
+        Err(P2pError::InvalidSignature)
+    ));
+    assert!(!b.connection.our_connections().contains_key(&c.addr));
+    let _ = b.sent();
+
+    // D follows another chain, E identifies without greeting us
+    let mut d = Node::new(4, Hash::new("other chain".as_bytes()));
+    let mut e = Node::new(5, chain_id);
+    for peer in [&mut d, &mut e] {
+        peer.connection
+            .handle_successful_connection(&Peer::Node(b.addr), &mut peer.transport)
+            .unwrap();
+        b.connection
+            .handle_successful_connection(&Peer::Node(peer.addr), &mut b.transport)
+            .unwrap();
+    }
+    let d_hello = d.sent();
+    assert!(matches!(
+        b.receive(d.addr, d_hello[0].1.clone()),
+        Err(P2pError::ChainMismatch { .. })
+    ));
+    let (_, b_hello) = b.sent().pop().unwrap();
+    let _ = e.sent();
+    e.receive(b.addr, b_hello).unwrap();
+    let e_ident = e.sent();
+    assert!(matches!(
+        b.receive(e.addr, e_ident[0].1.clone()),
+        Err(P2pError::MissingHello)
+    ));
+
+    // M relays the handshake of F to B, exchanging keys with each of them
+    let mut f = Node::new(6, chain_id);
+    let m = Node::new(7, chain_id);
+    f.connection
+        .handle_successful_connection(&Peer::Node(m.addr), &mut f.transport)
+        .unwrap();
+    b.connection
+        .handle_successful_connection(&Peer::Node(m.addr), &mut b.transport)
+        .unwrap();
+    let relayed = |(_, hello): (SocketAddr, Message)| match hello {
+        Message::Hello {
+            protocol_version,
+            features,
+            chain_id,
+            challenge,
+            ..
+        } => Message::Hello {
+            protocol_version,
+            features,
+            chain_id,
+            challenge,
+            exchange_key: *m.identity.get_public_key(),
+        },
+        msg => panic!("Unexpected {:?}", msg),
+    };
+    let (f_hello, b_hello) = (f.sent().remove(0), b.sent().remove(0));
+    b.receive(m.addr, relayed(f_hello)).unwrap();
+    f.receive(m.addr, relayed(b_hello)).unwrap();
+    let f_ident = f.sent();
+    assert!(matches!(
+        b.receive(m.addr, f_ident[0].1.clone()),
+        Err(P2pError::InvalidSignature)
+    ));
+    assert_eq!(
+        b.transport.disconnected,
+        vec![c.addr, d.addr, e.addr, m.addr]
+    );
+    assert_eq!(
+        b.connection.take_dropped(),
+        vec![c.addr, d.addr, e.addr, m.addr]
+    );
+    assert!(b.connection.take_dropped().is_empty());
+    assert_eq!(b.connection.get_active_connections().len(), 1);
+}
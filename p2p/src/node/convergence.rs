@@ -0,0 +1,120 @@
+//! Time routing takes to converge after a change of topology.
+//!
+//! A peer joining, leaving or failing starts a measurement. Routing has
+//! converged once the version of the routing table stopped changing for a
+//! settle period and every node it knows of is reachable again. The time from
+//! the change of topology to the last version change is what gets recorded,
+//! the settle period only confirms it.
+
+use super::connection::RoutingTable;
+use std::time::{Duration, Instant};
+
+/// Time the routing table must stay unchanged to be considered settled
+pub const DEFAULT_SETTLE_TIME: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, Debug)]
+struct Measurement {
+    /// Earliest change of topology routing has not converged after yet
+    started: Instant,
+    version: usize,
+    version_changed: Instant,
+}
+
+/// Measures how long the routing table takes to settle
+#[derive(Clone, Debug)]
+pub struct Convergence {
+    settle_time: Duration,
+    measurement: Option<Measurement>,
+}
+
+impl Default for Convergence {
+    fn default() -> Self {
+        Self::new(DEFAULT_SETTLE_TIME)
+    }
+}
+
+impl Convergence {
+    pub fn new(settle_time: Duration) -> Self {
+        Self {
+            settle_time,
+            measurement: None,
+        }
+    }
+
+    pub fn is_converging(&self) -> bool {
+        self.measurement.is_some()
+    }
+
+    /// Start measuring, unless routing has not converged after an earlier
+    /// change yet
+    pub fn topology_changed(&mut self, routing_table: &RoutingTable, now: Instant) {
+        let measurement = self.measurement.get_or_insert(Measurement {
+            started: now,
+            version: routing_table.version(),
+            version_changed: now,
+        });
+        measurement.version = routing_table.version();
+        measurement.version_changed = now;
+    }
+
+    /// Check whether routing converged, returning the time it took if so
+    pub fn poll(&mut self, routing_table: &RoutingTable, now: Instant) -> Option<Duration> {
+        let measurement = self.measurement.as_mut()?;
+        if routing_table.version() != measurement.version {
+            measurement.version = routing_table.version();
+            measurement.version_changed = now;
+            return None;
+        }
+        let settled = now.saturating_duration_since(measurement.version_changed);
+        if settled < self.settle_time || !routing_table.is_fully_reachable() {
+            return None;
+        }
+        let took = measurement
+            .version_changed
+            .saturating_duration_since(measurement.started);
+        self.measurement = None;
+        Some(took)
+    }
+}
+
+#[test]
+fn test_convergence_is_measured_until_routes_settle() {
+    use consensus::NodeId;
+    use crypto::hash::Hash;
+
+    let secs = Duration::from_secs;
+    let start = Instant::now();
+    let near = NodeId::from(Hash::new("near".as_bytes()));
+    let far = NodeId::from(Hash::new("far".as_bytes()));
+    let mut routing_table = RoutingTable::default();
+    let mut convergence = Convergence::new(secs(5));
+    assert!(convergence.poll(&routing_table, start).is_none());
+
+    routing_table.add_direct_connection(&near);
+    routing_table.add_new_node(&far);
+    routing_table.increment_version();
+    convergence.topology_changed(&routing_table, start);
+    assert!(convergence.is_converging());
+
+    // Routes keep changing for a while
+    assert!(routing_table.update_route(&far, &near, 2));
+    assert!(convergence.poll(&routing_table, start + secs(2)).is_none());
+    assert!(convergence.poll(&routing_table, start + secs(6)).is_none());
+    assert_eq!(
+        convergence.poll(&routing_table, start + secs(7)),
+        Some(secs(2))
+    );
+    assert!(!convergence.is_converging());
+
+    // A node known without a route keeps routing from converging
+    let lost = NodeId::from(Hash::new("lost".as_bytes()));
+    routing_table.add_new_node(&lost);
+    convergence.topology_changed(&routing_table, start + secs(10));
+    assert!(convergence.poll(&routing_table, start + secs(20)).is_none());
+    assert!(routing_table.update_route(&lost, &near, 3));
+    assert!(convergence.poll(&routing_table, start + secs(21)).is_none());
+    assert_eq!(
+        convergence.poll(&routing_table, start + secs(26)),
+        Some(secs(11))
+    );
+}
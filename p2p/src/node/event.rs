@@ -1,35 +1,223 @@
-use consensus::{account::AccountStateChoice, transaction::Transaction};
+use super::{
+    admin::AdminCommand, benchmark::BenchmarkCommand, bootstrap::BootstrapReport,
+    config::OverflowPolicy, tokens::MessageClass,
+};
+use consensus::{
+    account::AccountStateChoice,
+    budget::Resource,
+    reconcile::{Claim, Resolution, StateDigest},
+    scheduler::Application,
+    transaction::Transaction,
+    NodeId, TxId,
+};
 use crypto::hash::Hash;
-use std::collections::HashSet;
+use std::net::SocketAddr;
 
 /// P2p Events
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Event {
-    ConnectedTo(Hash),
+    ConnectedTo(NodeId),
+    /// A peer left the network
+    DisconnectedFrom(NodeId),
     NewMessage(Vec<u8>),
     ConsensusRequest(AccountStateChoice),
     DagConsensusRequest {
-        sender: Hash,
+        sender: NodeId,
         data: AccountStateChoice,
-        tx: Transaction,
+        tx: Box<Transaction>,
         count: usize,
     },
     DagConsensusResponse {
-        hash: Hash,
-        sender: Hash,
+        hash: TxId,
+        sender: NodeId,
         accepted: bool,
     },
-    TransactionComplete(Hash),
-    InitBenchmarkingSignal(usize, u64),
+    TransactionComplete(TxId),
+    BenchmarkControl {
+        sender: NodeId,
+        /// Nonce the command was signed with, to check against replays
+        nonce: u64,
+        command: BenchmarkCommand,
+    },
+    /// A trusted operator sent us a command, checked against replays
+    AdminCommand {
+        operator: NodeId,
+        command: AdminCommand,
+    },
     CompleteRound,
-    BenchmarkStats(HashSet<u64>),
     BatchedConsensusRequest {
-        sender: Hash,
+        sender: NodeId,
         data: Vec<(AccountStateChoice, Transaction)>,
         count: usize,
     },
     BatchedConsensusResponse {
-        sender: Hash,
-        data: Vec<(Hash, bool)>,
+        sender: NodeId,
+        data: Vec<(TxId, bool)>,
+    },
+    /// A message could not be sent to `peer` within the policy of its class
+    SendFailed {
+        peer: SocketAddr,
+        class: MessageClass,
+    },
+    /// The outbox queue of a next hop overflowed and `policy` was applied
+    OutboxOverflow {
+        peer: NodeId,
+        policy: OverflowPolicy,
+    },
+    /// Too many messages to `peer` were waiting to be retried, one was
+    /// dropped
+    OutboxFull(SocketAddr),
+    /// In-flight state went over its memory budget, and work of `resource`
+    /// was shed
+    ResourceExhausted {
+        resource: Resource,
+        used: usize,
+        limit: usize,
+    },
+    StateDigest {
+        sender: NodeId,
+        digest: StateDigest,
+    },
+    FinalityClaims {
+        sender: NodeId,
+        claims: Vec<Claim>,
+    },
+    /// A transaction finalized on the other side of a partition replaced ours
+    TransactionReplaced {
+        account_state: Hash,
+        replaced: TxId,
+        tx_id: TxId,
     },
+    /// Both sides of a partition finalized different transactions and
+    /// neither prevails
+    IrreconcilableConflict {
+        account_state: Hash,
+        ours: TxId,
+        theirs: TxId,
+    },
+    /// None of our bootstrap contacts could be connected to
+    BootstrapFailed(BootstrapReport),
+    /// A finalized transaction was applied to the state, after its
+    /// dependencies
+    Applied(TxId),
+    /// A finalized transaction was refused by the state it was applied to
+    ApplicationRefused(TxId),
+    /// A finalized `CreateAccount` transaction created the account of this
+    /// ID
+    AccountCreated(Hash),
+}
+
+impl Event {
+    /// Name of the event type, as used to filter subscriptions
+    pub fn kind(&self) -> &'static str {
+        use Event::*;
+        match self {
+            ConnectedTo(_) => "ConnectedTo",
+            DisconnectedFrom(_) => "DisconnectedFrom",
+            NewMessage(_) => "NewMessage",
+            ConsensusRequest(_) => "ConsensusRequest",
+            DagConsensusRequest { .. } => "DagConsensusRequest",
+            DagConsensusResponse { .. } => "DagConsensusResponse",
+            TransactionComplete(_) => "TransactionComplete",
+            BenchmarkControl { .. } => "BenchmarkControl",
+            AdminCommand { .. } => "AdminCommand",
+            CompleteRound => "CompleteRound",
+            BatchedConsensusRequest { .. } => "BatchedConsensusRequest",
+            BatchedConsensusResponse { .. } => "BatchedConsensusResponse",
+            SendFailed { .. } => "SendFailed",
+            OutboxOverflow { .. } => "OutboxOverflow",
+            OutboxFull(_) => "OutboxFull",
+            ResourceExhausted { .. } => "ResourceExhausted",
+            StateDigest { .. } => "StateDigest",
+            FinalityClaims { .. } => "FinalityClaims",
+            TransactionReplaced { .. } => "TransactionReplaced",
+            IrreconcilableConflict { .. } => "IrreconcilableConflict",
+            BootstrapFailed(_) => "BootstrapFailed",
+            Applied(_) => "Applied",
+            ApplicationRefused(_) => "ApplicationRefused",
+            AccountCreated(_) => "AccountCreated",
+        }
+    }
+
+    /// Peer the event is about or was sent by, if known by its ID
+    pub fn peer(&self) -> Option<NodeId> {
+        use Event::*;
+        match self {
+            ConnectedTo(peer)
+            | DisconnectedFrom(peer)
+            | DagConsensusRequest { sender: peer, .. }
+            | DagConsensusResponse { sender: peer, .. }
+            | BenchmarkControl { sender: peer, .. }
+            | AdminCommand { operator: peer, .. }
+            | BatchedConsensusRequest { sender: peer, .. }
+            | BatchedConsensusResponse { sender: peer, .. }
+            | OutboxOverflow { peer, .. }
+            | StateDigest { sender: peer, .. }
+            | FinalityClaims { sender: peer, .. } => Some(*peer),
+            _ => None,
+        }
+    }
+
+    /// Transactions the event is about
+    pub fn tx_ids(&self) -> Vec<TxId> {
+        use Event::*;
+        match self {
+            DagConsensusRequest { tx, .. } => tx.try_get_tx_id().into_iter().collect(),
+            DagConsensusResponse { hash, .. } => vec![*hash],
+            TransactionComplete(tx_id) | Applied(tx_id) | ApplicationRefused(tx_id) => {
+                vec![*tx_id]
+            }
+            BatchedConsensusRequest { data, .. } => data
+                .iter()
+                .filter_map(|(_, tx)| tx.try_get_tx_id())
+                .collect(),
+            BatchedConsensusResponse { data, .. } => data.iter().map(|(tx_id, _)| *tx_id).collect(),
+            FinalityClaims { claims, .. } => claims
+                .iter()
+                .filter_map(|(_, tx, _)| tx.try_get_tx_id())
+                .collect(),
+            TransactionReplaced {
+                replaced, tx_id, ..
+            } => vec![*replaced, *tx_id],
+            IrreconcilableConflict { ours, theirs, .. } => vec![*ours, *theirs],
+            _ => vec![],
+        }
+    }
+
+    /// Event reporting the resolution of a diverging account state, if it
+    /// changed anything
+    pub fn from_resolution(resolution: Resolution) -> Option<Self> {
+        match resolution {
+            Resolution::Keep { .. } => None,
+            Resolution::Adopt {
+                account_state,
+                replaced,
+                tx_id,
+            } => Some(Event::TransactionReplaced {
+                account_state,
+                replaced,
+                tx_id,
+            }),
+            Resolution::Irreconcilable {
+                account_state,
+                ours,
+                theirs,
+            } => Some(Event::IrreconcilableConflict {
+                account_state,
+                ours,
+                theirs,
+            }),
+        }
+    }
+
+    /// Event reporting the application of a finalized transaction
+    pub fn from_application(application: Application) -> Self {
+        match application {
+            Application::Applied(tx_id) => Event::Applied(tx_id),
+            Application::Failed { tx_id, .. } => Event::ApplicationRefused(tx_id),
+            Application::AccountCreated { account_id, .. } => {
+                Event::AccountCreated(account_id.into())
+            }
+        }
+    }
 }
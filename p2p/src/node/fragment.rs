@@ -0,0 +1,232 @@
+//! Fragmentation of messages too large for a single send.
+//!
+//! QUIC puts a practical bound on the size of what is sent at once, so a
+//! large batch of consensus requests could fail to go through and never be
+//! reported. Messages serialized larger than the fragment size of the
+//! [`FragmentConfig`] are split into [`Fragment`]s instead, sent one by one,
+//! and put back together by the [`Reassembler`] of the receiver in whatever
+//! order they arrive. Messages whose fragments don't all arrive in time are
+//! dropped, and each peer may only have so many bytes of incomplete
+//! messages buffered.
+
+use super::config::FragmentConfig;
+use crate::error::DecodeError;
+use crypto::hash::Hash;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+/// Piece of a serialized message
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Fragment {
+    /// Shared by the fragments of a message
+    pub msg_id: Hash,
+    pub index: u32,
+    pub total: u32,
+    pub data: Vec<u8>,
+}
+
+/// Split `bytes` into fragments of at most `fragment_size` bytes
+pub fn fragment(bytes: &[u8], fragment_size: usize) -> Vec<Fragment> {
+    let msg_id = Hash::generate_random();
+    let chunks = bytes.chunks(fragment_size.max(1)).collect::<Vec<_>>();
+    let total = chunks.len() as u32;
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(index, data)| Fragment {
+            msg_id,
+            index: index as u32,
+            total,
+            data: data.to_vec(),
+        })
+        .collect()
+}
+
+/// Fragments received so far of a message
+#[derive(Clone, Debug)]
+struct Partial {
+    total: u32,
+    started: Instant,
+    fragments: BTreeMap<u32, Vec<u8>>,
+    size: u64,
+}
+
+/// Puts the fragments received from peers back together
+#[derive(Clone, Debug)]
+pub struct Reassembler {
+    timeout: Duration,
+    /// Bytes of incomplete messages buffered for each peer
+    max_size: u64,
+    partials: HashMap<(SocketAddr, Hash), Partial>,
+}
+
+impl Default for Reassembler {
+    fn default() -> Self {
+        Self::new(&FragmentConfig::default())
+    }
+}
+
+impl Reassembler {
+    pub fn new(config: &FragmentConfig) -> Self {
+        Self {
+            timeout: config.reassembly_timeout(),
+            max_size: config.max_reassembly_size(),
+            partials: HashMap::new(),
+        }
+    }
+
+    /// Take a fragment sent by `peer`, returning the bytes of its message
+    /// once all of its fragments arrived. Inconsistent fragments, and
+    /// fragments taking the peer over its buffer, drop the message they
+    /// belong to.
+    pub fn push(
+        &mut self,
+        peer: SocketAddr,
+        fragment: Fragment,
+        now: Instant,
+    ) -> Result<Option<Vec<u8>>, DecodeError> {
+        let key = (peer, fragment.msg_id);
+        if fragment.index >= fragment.total || fragment.data.is_empty() {
+            let _ = self.partials.remove(&key);
+            return Err(DecodeError::Malformed(format!(
+                "Fragment {} of {} with {} bytes",
+                fragment.index,
+                fragment.total,
+                fragment.data.len()
+            )));
+        }
+        let buffered = self.buffered(&peer) + fragment.data.len() as u64;
+        if buffered > self.max_size {
+            let _ = self.partials.remove(&key);
+            return Err(DecodeError::TooLarge {
+                size: buffered,
+                limit: self.max_size,
+            });
+        }
+
+        let partial = self.partials.entry(key).or_insert_with(|| Partial {
+            total: fragment.total,
+            started: now,
+            fragments: BTreeMap::new(),
+            size: 0,
+        });
+        if partial.total != fragment.total {
+            let expected = partial.total;
+            let _ = self.partials.remove(&key);
+            return Err(DecodeError::Malformed(format!(
+                "Fragment of {} expected, got one of {}",
+                expected, fragment.total
+            )));
+        }
+        if !partial.fragments.contains_key(&fragment.index) {
+            partial.size += fragment.data.len() as u64;
+            let _ = partial.fragments.insert(fragment.index, fragment.data);
+        }
+        if partial.fragments.len() < partial.total as usize {
+            return Ok(None);
+        }
+        Ok(self
+            .partials
+            .remove(&key)
+            .map(|partial| partial.fragments.into_values().flatten().collect()))
+    }
+
+    /// Bytes of the incomplete messages of `peer`
+    pub fn buffered(&self, peer: &SocketAddr) -> u64 {
+        self.partials
+            .iter()
+            .filter(|((addr, _), _)| addr == peer)
+            .map(|(_, partial)| partial.size)
+            .sum()
+    }
+
+    /// Incomplete messages
+    pub fn len(&self) -> usize {
+        self.partials.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.partials.is_empty()
+    }
+
+    /// Drop the messages whose fragments didn't all arrive in time,
+    /// returning how many were
+    pub fn expire(&mut self, now: Instant) -> usize {
+        let before = self.partials.len();
+        let timeout = self.timeout;
+        self.partials
+            .retain(|_, partial| now.saturating_duration_since(partial.started) < timeout);
+        before - self.partials.len()
+    }
+
+    /// Drop the incomplete messages of a peer we disconnected from
+    pub fn forget(&mut self, peer: &SocketAddr) {
+        self.partials.retain(|(addr, _), _| addr != peer);
+    }
+}
+
+#[test]
+fn test_fragments_are_reassembled() {
+    let peer: SocketAddr = ([10, 0, 0, 1], 9000).into();
+    let other: SocketAddr = ([10, 0, 0, 2], 9000).into();
+    let config = FragmentConfig::new(4, Duration::from_secs(30), 24);
+    let mut reassembler = Reassembler::new(&config);
+    let now = Instant::now();
+    let message = b"a message of 22 bytes.".to_vec();
+
+    // Out of order, with a duplicate
+    let mut fragments = fragment(&message, config.fragment_size());
+    assert_eq!(fragments.len(), 6);
+    assert!(fragments.iter().all(|fragment| fragment.data.len() <= 4));
+    fragments.reverse();
+    let first = fragments.pop().unwrap();
+    for fragment in fragments.iter().chain(&fragments[..1]) {
+        assert_eq!(reassembler.push(peer, fragment.clone(), now), Ok(None));
+    }
+    assert_eq!(reassembler.buffered(&peer), 18);
+    assert_eq!(
+        reassembler.push(peer, first, now),
+        Ok(Some(message.clone()))
+    );
+    assert!(reassembler.is_empty());
+
+    // Missing fragments are given up on
+    let short = fragment(&message[..8], config.fragment_size());
+    assert_eq!(reassembler.push(peer, short[0].clone(), now), Ok(None));
+    assert_eq!(reassembler.expire(now + Duration::from_secs(29)), 0);
+    assert_eq!(reassembler.expire(now + Duration::from_secs(30)), 1);
+    assert!(reassembler.is_empty());
+
+    // Each peer buffers at most 24 bytes
+    let long = fragment(&message, config.fragment_size());
+    for fragment in long[..5].iter().chain(&short[..1]) {
+        assert_eq!(reassembler.push(peer, fragment.clone(), now), Ok(None));
+    }
+    assert_eq!(
+        reassembler.push(peer, short[1].clone(), now),
+        Err(DecodeError::TooLarge {
+            size: 28,
+            limit: 24
+        })
+    );
+    assert_eq!(reassembler.buffered(&peer), 20);
+    assert_eq!(reassembler.push(other, short[0].clone(), now), Ok(None));
+    reassembler.forget(&other);
+    assert_eq!(reassembler.len(), 1);
+
+    // Inconsistent fragments drop their message
+    let mut inconsistent = long[5].clone();
+    inconsistent.total = 7;
+    assert!(matches!(
+        reassembler.push(peer, inconsistent.clone(), now),
+        Err(DecodeError::Malformed(_))
+    ));
+    assert!(reassembler.is_empty());
+    inconsistent.index = 7;
+    assert!(matches!(
+        reassembler.push(peer, inconsistent, now),
+        Err(DecodeError::Malformed(_))
+    ));
+}
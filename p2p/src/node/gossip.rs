@@ -0,0 +1,125 @@
+//! Adaptive fanout of gossip.
+//!
+//! Gossip reaches the whole network with high probability once every node
+//! forwards it to about ln(n) peers. The fanout starts from that estimate of
+//! the network size, then follows the share of gossip we receive twice: few
+//! duplicates hint that gossip dies out before covering the network, many
+//! that bandwidth is wasted.
+
+use super::config::GossipConfig;
+
+/// Gossip messages to receive before the fanout is nudged
+const SAMPLE_SIZE: u64 = 100;
+
+/// Number of neighbours gossip is forwarded to
+#[derive(Clone, Debug)]
+pub struct Fanout {
+    config: GossipConfig,
+    /// Correction of the size-based estimate learnt from duplicates
+    bias: isize,
+    received: u64,
+    duplicates: u64,
+    current: usize,
+}
+
+impl Default for Fanout {
+    fn default() -> Self {
+        Self::new(GossipConfig::default())
+    }
+}
+
+impl Fanout {
+    pub fn new(config: GossipConfig) -> Self {
+        Self {
+            current: config.min_fanout(),
+            config,
+            bias: 0,
+            received: 0,
+            duplicates: 0,
+        }
+    }
+
+    /// Fanout as of the latest adjustment
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    /// Record a gossip message received, and whether we had it already
+    pub fn record(&mut self, duplicate: bool) {
+        self.received += 1;
+        if duplicate {
+            self.duplicates += 1;
+        }
+    }
+
+    /// Share of the gossip received since the latest nudge that we had
+    /// already
+    pub fn duplicate_ratio(&self) -> Option<f64> {
+        match self.received {
+            0 => None,
+            received => Some(self.duplicates as f64 / received as f64),
+        }
+    }
+
+    /// Recompute the fanout for a network of `network_size` nodes, nudging
+    /// it once enough gossip was received to trust the duplicate ratio
+    pub fn adjust(&mut self, network_size: usize) -> usize {
+        let estimate = (network_size.max(1) as f64).ln().ceil() as isize + 1;
+        let (min, max) = (
+            self.config.min_fanout() as isize,
+            self.config.max_fanout() as isize,
+        );
+        if self.received >= SAMPLE_SIZE {
+            let ratio = self.duplicate_ratio().unwrap_or_default();
+            let (low, high) = self.config.duplicate_ratios();
+            if ratio < low {
+                self.bias += 1;
+            } else if ratio > high {
+                self.bias -= 1;
+            }
+            // Past the bounds, the bias would only grow without any effect
+            self.bias = self.bias.clamp(min - estimate, max - estimate);
+            self.received = 0;
+            self.duplicates = 0;
+        }
+        self.current = (estimate + self.bias).clamp(min, max) as usize;
+        self.current
+    }
+}
+
+#[test]
+fn test_fanout_adapts() {
+    let mut fanout = Fanout::new(GossipConfig::new(2, 8));
+    assert_eq!(fanout.adjust(10), 4);
+    assert_eq!(fanout.adjust(1_000), 8);
+    assert_eq!(fanout.adjust(1), 2);
+
+    // Hardly any duplicates, gossip may not be reaching everyone
+    for n in 0..SAMPLE_SIZE {
+        fanout.record(n == 0);
+    }
+    assert_eq!(fanout.adjust(10), 5);
+    assert!(fanout.duplicate_ratio().is_none());
+
+    // Mostly duplicates, the fanout shrinks back
+    for _ in 0..2 {
+        for n in 0..SAMPLE_SIZE {
+            fanout.record(n % 10 != 0);
+        }
+        let _ = fanout.adjust(10);
+    }
+    assert_eq!(fanout.current(), 3);
+
+    // The bias does not wind up past the bounds
+    for _ in 0..5 {
+        for _ in 0..SAMPLE_SIZE {
+            fanout.record(true);
+        }
+        let _ = fanout.adjust(10);
+    }
+    assert_eq!(fanout.current(), 2);
+    for _ in 0..SAMPLE_SIZE {
+        fanout.record(false);
+    }
+    assert_eq!(fanout.adjust(10), 3);
+}
@@ -0,0 +1,810 @@
+//! GraphQL queries over the explorer indexes.
+//!
+//! Served by the [`RpcServer`](super::rpc::RpcServer) on `POST /graphql`
+//! when the node exposes an [`ExplorerIndex`], under the same tokens as
+//! JSON-RPC. Queries are read-only and take the usual
+//! `{"query": ..., "variables": ...}` body.
+//!
+//! The subset of GraphQL understood covers what explorers send: a single
+//! query operation with variables, aliases and nested selections, but no
+//! fragments, directives or mutations. Lists are paginated as connections,
+//! e.g.
+//!
+//! ```text
+//! {
+//!   transactions(first: 10, origin: "<hex>", status: Accepted) {
+//!     nodes { id amount destination }
+//!     pageInfo { endCursor hasNextPage }
+//!   }
+//! }
+//! ```
+//!
+//! passing `endCursor` as `after` to fetch the next page.
+
+use consensus::{
+    account::Account,
+    transaction::{Transaction, TransactionStatus},
+    AccountId, Amount, TxId,
+};
+use crypto::hash::Hash;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+/// Items in a page unless `first` says otherwise
+pub const DEFAULT_PAGE_SIZE: usize = 20;
+/// Most items a page may hold
+pub const MAX_PAGE_SIZE: usize = 100;
+/// Deepest nesting of selections accepted
+const MAX_DEPTH: usize = 8;
+
+/// Node-side indexes queried through GraphQL. Lists are ordered by ID, and
+/// start right after `after` when it is given.
+pub trait ExplorerIndex: Send + Sync {
+    fn transaction(&self, tx_id: &TxId) -> Option<Transaction>;
+
+    /// At most `limit` transactions matching `filter`
+    fn transactions(
+        &self,
+        filter: &TransactionFilter,
+        after: Option<&TxId>,
+        limit: usize,
+    ) -> Vec<Transaction>;
+
+    fn account(&self, account_id: &AccountId) -> Option<Account>;
+
+    /// At most `limit` accounts matching `filter`
+    fn accounts(
+        &self,
+        filter: &AccountFilter,
+        after: Option<&AccountId>,
+        limit: usize,
+    ) -> Vec<Account>;
+}
+
+/// Conditions on the transactions listed, unset ones matching any
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TransactionFilter {
+    pub origin: Option<AccountId>,
+    pub destination: Option<AccountId>,
+    pub parent: Option<TxId>,
+    pub status: Option<TransactionStatus>,
+}
+
+impl TransactionFilter {
+    pub fn matches(&self, tx: &Transaction) -> bool {
+        self.origin.is_none_or(|origin| tx.origin == origin)
+            && self
+                .destination
+                .is_none_or(|destination| tx.destination == destination)
+            && self.parent.is_none_or(|parent| tx.parent() == parent)
+            && self
+                .status
+                .as_ref()
+                .is_none_or(|status| tx.status == *status)
+    }
+}
+
+/// Conditions on the accounts listed, unset ones matching any
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AccountFilter {
+    pub min_balance: Option<Amount>,
+}
+
+impl AccountFilter {
+    pub fn matches(&self, account: &Account) -> bool {
+        self.min_balance
+            .is_none_or(|min_balance| account.balance >= min_balance)
+    }
+}
+
+/// GraphQL error object
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct GraphqlError {
+    pub message: String,
+}
+
+impl GraphqlError {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct GraphqlRequest {
+    query: String,
+    #[serde(default)]
+    variables: Option<Map<String, Value>>,
+}
+
+/// Handle the body of a GraphQL request, returning the body of the response
+pub fn handle_request(index: &dyn ExplorerIndex, body: &str) -> String {
+    let outcome = serde_json::from_str::<GraphqlRequest>(body)
+        .map_err(|e| GraphqlError::new(e.to_string()))
+        .and_then(|request| {
+            let variables = request.variables.unwrap_or_default();
+            let selection = parse(&request.query, &variables)?;
+            execute(index, &selection)
+        });
+    let response = match outcome {
+        Ok(data) => json!({ "data": data }),
+        Err(error) => json!({ "data": null, "errors": [error] }),
+    };
+    response.to_string()
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Punct(char),
+    Name(String),
+    Str(String),
+    Int(i64),
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, GraphqlError> {
+    let mut tokens = vec![];
+    let mut chars = source.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() || c == ',' => {
+                let _ = chars.next();
+            }
+            '#' => while chars.next().is_some_and(|c| c != '\n') {},
+            '{' | '}' | '(' | ')' | '[' | ']' | ':' | '$' | '!' | '=' => {
+                tokens.push(Token::Punct(c));
+                let _ = chars.next();
+            }
+            '"' => {
+                let _ = chars.next();
+                let mut string = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => string.push('\n'),
+                            Some('t') => string.push('\t'),
+                            Some(c @ ('"' | '\\' | '/')) => string.push(c),
+                            _ => return Err(GraphqlError::new("Invalid escape in string")),
+                        },
+                        Some(c) => string.push(c),
+                        None => return Err(GraphqlError::new("Unterminated string")),
+                    }
+                }
+                tokens.push(Token::Str(string));
+            }
+            c if c == '-' || c.is_ascii_digit() => {
+                let mut number = String::new();
+                while let Some(c) = chars.next_if(|c| *c == '-' || c.is_ascii_digit()) {
+                    number.push(c);
+                }
+                let number = number
+                    .parse()
+                    .map_err(|_| GraphqlError::new(format!("Invalid number: {}", number)))?;
+                tokens.push(Token::Int(number));
+            }
+            c if c == '_' || c.is_ascii_alphabetic() => {
+                let mut name = String::new();
+                while let Some(c) = chars.next_if(|c| *c == '_' || c.is_ascii_alphanumeric()) {
+                    name.push(c);
+                }
+                tokens.push(Token::Name(name));
+            }
+            c => return Err(GraphqlError::new(format!("Unexpected character: {}", c))),
+        }
+    }
+    Ok(tokens)
+}
+
+/// Field selected by a query
+#[derive(Debug, PartialEq)]
+struct Field {
+    alias: Option<String>,
+    name: String,
+    arguments: Map<String, Value>,
+    selection: Vec<Field>,
+}
+
+impl Field {
+    /// Name the field takes in the response
+    fn key(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.name)
+    }
+
+    fn argument(&self, name: &str) -> Option<&Value> {
+        self.arguments.get(name).filter(|value| !value.is_null())
+    }
+}
+
+/// Parse the selection of a query operation, substituting `variables`
+fn parse(query: &str, variables: &Map<String, Value>) -> Result<Vec<Field>, GraphqlError> {
+    let mut parser = Parser {
+        tokens: tokenize(query)?,
+        position: 0,
+        variables,
+    };
+    let selection = parser.operation()?;
+    match parser.peek() {
+        None => Ok(selection),
+        Some(_) => Err(GraphqlError::new("Only a single query is supported")),
+    }
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    variables: &'a Map<String, Value>,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Result<Token, GraphqlError> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .cloned()
+            .ok_or_else(|| GraphqlError::new("Unexpected end of query"))?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    /// Consume `punct` if it comes next
+    fn eat(&mut self, punct: char) -> bool {
+        if self.peek() == Some(&Token::Punct(punct)) {
+            self.position += 1;
+            return true;
+        }
+        false
+    }
+
+    fn expect(&mut self, punct: char) -> Result<(), GraphqlError> {
+        if self.eat(punct) {
+            return Ok(());
+        }
+        Err(GraphqlError::new(format!("Expected '{}'", punct)))
+    }
+
+    fn name(&mut self) -> Result<String, GraphqlError> {
+        match self.next()? {
+            Token::Name(name) => Ok(name),
+            token => Err(GraphqlError::new(format!(
+                "Expected a name, got {:?}",
+                token
+            ))),
+        }
+    }
+
+    fn operation(&mut self) -> Result<Vec<Field>, GraphqlError> {
+        match self.peek() {
+            Some(Token::Name(keyword)) if keyword == "query" => {
+                self.position += 1;
+                if let Some(Token::Name(_)) = self.peek() {
+                    self.position += 1;
+                }
+                if self.eat('(') {
+                    self.skip_variable_definitions()?;
+                }
+            }
+            Some(Token::Name(keyword)) => {
+                return Err(GraphqlError::new(format!(
+                    "Unsupported operation: {}",
+                    keyword
+                )))
+            }
+            _ => {}
+        }
+        self.selection_set(0)
+    }
+
+    /// Variables are substituted as given, so their types and defaults are
+    /// not checked
+    fn skip_variable_definitions(&mut self) -> Result<(), GraphqlError> {
+        while !self.eat(')') {
+            let _ = self.next()?;
+        }
+        Ok(())
+    }
+
+    fn selection_set(&mut self, depth: usize) -> Result<Vec<Field>, GraphqlError> {
+        if depth >= MAX_DEPTH {
+            return Err(GraphqlError::new("Query is nested too deep"));
+        }
+        self.expect('{')?;
+        let mut fields = vec![];
+        while !self.eat('}') {
+            fields.push(self.field(depth)?);
+        }
+        Ok(fields)
+    }
+
+    fn field(&mut self, depth: usize) -> Result<Field, GraphqlError> {
+        let mut name = self.name()?;
+        let mut alias = None;
+        if self.eat(':') {
+            alias = Some(name);
+            name = self.name()?;
+        }
+        let mut arguments = Map::new();
+        if self.eat('(') {
+            while !self.eat(')') {
+                let argument = self.name()?;
+                self.expect(':')?;
+                let _ = arguments.insert(argument, self.value()?);
+            }
+        }
+        let selection = match self.peek() {
+            Some(Token::Punct('{')) => self.selection_set(depth + 1)?,
+            _ => vec![],
+        };
+        Ok(Field {
+            alias,
+            name,
+            arguments,
+            selection,
+        })
+    }
+
+    fn value(&mut self) -> Result<Value, GraphqlError> {
+        match self.next()? {
+            Token::Str(string) => Ok(json!(string)),
+            Token::Int(number) => Ok(json!(number)),
+            Token::Name(name) => Ok(match name.as_str() {
+                "true" => json!(true),
+                "false" => json!(false),
+                "null" => Value::Null,
+                // Enum values
+                _ => json!(name),
+            }),
+            Token::Punct('$') => {
+                let name = self.name()?;
+                Ok(self.variables.get(&name).cloned().unwrap_or(Value::Null))
+            }
+            token => Err(GraphqlError::new(format!(
+                "Expected a value, got {:?}",
+                token
+            ))),
+        }
+    }
+}
+
+fn execute(index: &dyn ExplorerIndex, selection: &[Field]) -> Result<Value, GraphqlError> {
+    select("Query", selection, |field| {
+        Ok(match field.name.as_str() {
+            "transaction" => {
+                let tx_id = TxId::from(required_hash(field, "id")?);
+                match index.transaction(&tx_id) {
+                    Some(tx) => Some(transaction_json(&tx, &field.selection)?),
+                    None => Some(Value::Null),
+                }
+            }
+            "transactions" => {
+                let (first, after) = page(field)?;
+                let txs = index.transactions(
+                    &transaction_filter(field)?,
+                    after.map(TxId::from).as_ref(),
+                    first + 1,
+                );
+                Some(connection(
+                    "TransactionConnection",
+                    txs,
+                    first,
+                    &field.selection,
+                    |tx| tx.get_tx_id().to_hex(),
+                    transaction_json,
+                )?)
+            }
+            // Each transaction is the child of one edge, to its parent
+            "edges" => {
+                let (first, after) = page(field)?;
+                let txs = index.transactions(
+                    &transaction_filter(field)?,
+                    after.map(TxId::from).as_ref(),
+                    first + 1,
+                );
+                Some(connection(
+                    "EdgeConnection",
+                    txs,
+                    first,
+                    &field.selection,
+                    |tx| tx.get_tx_id().to_hex(),
+                    edge_json,
+                )?)
+            }
+            "account" => {
+                let account_id = AccountId::from(required_hash(field, "id")?);
+                match index.account(&account_id) {
+                    Some(account) => Some(account_json(&account, &field.selection)?),
+                    None => Some(Value::Null),
+                }
+            }
+            "accounts" => {
+                let (first, after) = page(field)?;
+                let filter = AccountFilter {
+                    min_balance: amount(field, "minBalance")?,
+                };
+                let accounts =
+                    index.accounts(&filter, after.map(AccountId::from).as_ref(), first + 1);
+                Some(connection(
+                    "AccountConnection",
+                    accounts,
+                    first,
+                    &field.selection,
+                    |account| account.id.to_hex(),
+                    account_json,
+                )?)
+            }
+            _ => None,
+        })
+    })
+}
+
+/// Resolve the fields selected on an object of type `typename`. `resolve`
+/// returns `None` for fields the type doesn't have.
+fn select<F>(typename: &str, selection: &[Field], resolve: F) -> Result<Value, GraphqlError>
+where
+    F: Fn(&Field) -> Result<Option<Value>, GraphqlError>,
+{
+    if selection.is_empty() {
+        return Err(GraphqlError::new(format!(
+            "Fields of {} must be selected",
+            typename
+        )));
+    }
+    let mut object = Map::new();
+    for field in selection {
+        let value = match field.name.as_str() {
+            "__typename" => json!(typename),
+            name => resolve(field)?.ok_or_else(|| {
+                GraphqlError::new(format!("Unknown field {} on {}", name, typename))
+            })?,
+        };
+        let _ = object.insert(field.key().to_string(), value);
+    }
+    Ok(Value::Object(object))
+}
+
+/// Page of `items`, fetched with one more than `first` to tell whether
+/// another page follows
+fn connection<T, C, N>(
+    typename: &str,
+    mut items: Vec<T>,
+    first: usize,
+    selection: &[Field],
+    cursor: C,
+    node: N,
+) -> Result<Value, GraphqlError>
+where
+    C: Fn(&T) -> String,
+    N: Fn(&T, &[Field]) -> Result<Value, GraphqlError>,
+{
+    let has_next_page = items.len() > first;
+    items.truncate(first);
+    let end_cursor = items.last().map(&cursor);
+    select(typename, selection, |field| {
+        Ok(match field.name.as_str() {
+            "nodes" => Some(Value::Array(
+                items
+                    .iter()
+                    .map(|item| node(item, &field.selection))
+                    .collect::<Result<_, _>>()?,
+            )),
+            "pageInfo" => Some(select("PageInfo", &field.selection, |field| {
+                Ok(match field.name.as_str() {
+                    "endCursor" => Some(json!(end_cursor)),
+                    "hasNextPage" => Some(json!(has_next_page)),
+                    _ => None,
+                })
+            })?),
+            _ => None,
+        })
+    })
+}
+
+fn transaction_json(tx: &Transaction, selection: &[Field]) -> Result<Value, GraphqlError> {
+    select("Transaction", selection, |field| {
+        Ok(match field.name.as_str() {
+            "id" => Some(json!(tx.get_tx_id().to_hex())),
+            "parent" => Some(json!(tx.parent().to_hex())),
+            "parents" => Some(json!(tx
+                .parents()
+                .iter()
+                .map(TxId::to_hex)
+                .collect::<Vec<_>>())),
+            "children" => Some(json!(tx
+                .get_children()
+                .iter()
+                .map(TxId::to_hex)
+                .collect::<Vec<_>>())),
+            "origin" => Some(json!(tx.origin.to_hex())),
+            "destination" => Some(json!(tx.destination.to_hex())),
+            // u128 does not fit in a JSON number
+            "amount" => Some(json!(tx.amount.base_units().to_string())),
+            "fee" => Some(json!(tx.fee.base_units().to_string())),
+            "status" => Some(json!(tx.status)),
+            "type" => Some(json!(tx.tx_type)),
+            "sequence" => Some(json!(tx.sequence)),
+            "timestamp" => Some(json!(tx.timestamp.as_secs())),
+            "memo" => Some(json!(tx.memo())),
+            _ => None,
+        })
+    })
+}
+
+fn edge_json(tx: &Transaction, selection: &[Field]) -> Result<Value, GraphqlError> {
+    select("Edge", selection, |field| {
+        Ok(match field.name.as_str() {
+            "parent" => Some(json!(tx.parent().to_hex())),
+            "child" => Some(json!(tx.get_tx_id().to_hex())),
+            _ => None,
+        })
+    })
+}
+
+fn account_json(account: &Account, selection: &[Field]) -> Result<Value, GraphqlError> {
+    select("Account", selection, |field| {
+        Ok(match field.name.as_str() {
+            "id" => Some(json!(account.id.to_hex())),
+            "balance" => Some(json!(account.balance.base_units().to_string())),
+            "lastTxId" => Some(json!(account.last_tx_id.to_hex())),
+            "sequence" => Some(json!(account.sequence)),
+            "created" => Some(json!(account.created.as_secs())),
+            _ => None,
+        })
+    })
+}
+
+/// Size and cursor of the page requested
+fn page(field: &Field) -> Result<(usize, Option<Hash>), GraphqlError> {
+    let first = match field.argument("first") {
+        Some(first) => first
+            .as_u64()
+            .filter(|first| (1..=MAX_PAGE_SIZE as u64).contains(first))
+            .ok_or_else(|| {
+                GraphqlError::new(format!("first must be between 1 and {}", MAX_PAGE_SIZE))
+            })? as usize,
+        None => DEFAULT_PAGE_SIZE,
+    };
+    Ok((first, hash(field, "after")?))
+}
+
+fn transaction_filter(field: &Field) -> Result<TransactionFilter, GraphqlError> {
+    let status = field
+        .argument("status")
+        .map(|status| {
+            serde_json::from_value(status.clone())
+                .map_err(|_| GraphqlError::new(format!("Unknown status: {}", status)))
+        })
+        .transpose()?;
+    Ok(TransactionFilter {
+        origin: hash(field, "origin")?.map(AccountId::from),
+        destination: hash(field, "destination")?.map(AccountId::from),
+        parent: hash(field, "parent")?.map(TxId::from),
+        status,
+    })
+}
+
+fn hash(field: &Field, name: &str) -> Result<Option<Hash>, GraphqlError> {
+    field
+        .argument(name)
+        .map(|value| {
+            value
+                .as_str()
+                .and_then(|encoded| Hash::from_hex(encoded).ok())
+                .ok_or_else(|| GraphqlError::new(format!("{} must be a hex hash", name)))
+        })
+        .transpose()
+}
+
+fn required_hash(field: &Field, name: &str) -> Result<Hash, GraphqlError> {
+    hash(field, name)?.ok_or_else(|| GraphqlError::new(format!("Missing argument: {}", name)))
+}
+
+/// Amounts are passed as strings of base units, like they are returned
+fn amount(field: &Field, name: &str) -> Result<Option<Amount>, GraphqlError> {
+    field
+        .argument(name)
+        .map(|value| {
+            value
+                .as_str()
+                .and_then(|amount| amount.parse::<u128>().ok())
+                .map(Amount::new)
+                .ok_or_else(|| GraphqlError::new(format!("{} must be an amount string", name)))
+        })
+        .transpose()
+}
+
+#[cfg(test)]
+struct TestIndex {
+    transactions: std::collections::BTreeMap<TxId, Transaction>,
+    accounts: std::collections::BTreeMap<AccountId, Account>,
+}
+
+#[cfg(test)]
+impl ExplorerIndex for TestIndex {
+    fn transaction(&self, tx_id: &TxId) -> Option<Transaction> {
+        self.transactions.get(tx_id).cloned()
+    }
+
+    fn transactions(
+        &self,
+        filter: &TransactionFilter,
+        after: Option<&TxId>,
+        limit: usize,
+    ) -> Vec<Transaction> {
+        self.transactions
+            .iter()
+            .filter(|(tx_id, _)| after.is_none_or(|after| *tx_id > after))
+            .map(|(_, tx)| tx)
+            .filter(|tx| filter.matches(tx))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    fn account(&self, account_id: &AccountId) -> Option<Account> {
+        self.accounts.get(account_id).cloned()
+    }
+
+    fn accounts(
+        &self,
+        filter: &AccountFilter,
+        after: Option<&AccountId>,
+        limit: usize,
+    ) -> Vec<Account> {
+        self.accounts
+            .iter()
+            .filter(|(account_id, _)| after.is_none_or(|after| *account_id > after))
+            .map(|(_, account)| account)
+            .filter(|account| filter.matches(account))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+fn test_index() -> TestIndex {
+    use consensus::transaction::TransactionType;
+
+    let mut account = Account::create(
+        &Hash::new("origin".as_bytes()).into(),
+        &Hash::default().into(),
+    );
+    account.balance = Amount::new(100);
+    let mut transactions = std::collections::BTreeMap::new();
+    for amount in 1..=5 {
+        let mut tx = Transaction::new(
+            Hash::default().into(),
+            account.clone(),
+            Hash::new(format!("destination {}", amount % 2).as_bytes()).into(),
+            Amount::new(amount),
+            TransactionType::Transfer,
+            vec![],
+        );
+        tx.calculate_tx_id().unwrap();
+        let _ = transactions.insert(tx.get_tx_id(), tx);
+    }
+    TestIndex {
+        transactions,
+        accounts: [(account.id, account)].into_iter().collect(),
+    }
+}
+
+#[cfg(test)]
+fn query(index: &dyn ExplorerIndex, query: &str, variables: Value) -> Value {
+    let request = json!({ "query": query, "variables": variables });
+    serde_json::from_str(&handle_request(index, &request.to_string())).unwrap()
+}
+
+#[test]
+fn test_graphql_pagination() {
+    let index = test_index();
+    let page = |after: Value| {
+        query(
+            &index,
+            "query Page($after: String) {
+                transactions(first: 2, after: $after) {
+                    nodes { id }
+                    pageInfo { endCursor hasNextPage }
+                }
+            }",
+            json!({ "after": after }),
+        )["data"]["transactions"]
+            .clone()
+    };
+
+    let mut seen = vec![];
+    let mut after = Value::Null;
+    loop {
+        let page = page(after);
+        for node in page["nodes"].as_array().unwrap() {
+            seen.push(node["id"].as_str().unwrap().to_string());
+        }
+        if page["pageInfo"]["hasNextPage"] == json!(false) {
+            break;
+        }
+        after = page["pageInfo"]["endCursor"].clone();
+    }
+    let expected = index
+        .transactions
+        .keys()
+        .map(TxId::to_hex)
+        .collect::<Vec<_>>();
+    assert_eq!(seen, expected);
+}
+
+#[test]
+fn test_graphql_filters_and_aliases() {
+    let index = test_index();
+    let (account_id, account) = index.accounts.iter().next().unwrap();
+    let destination = Hash::new("destination 1".as_bytes()).to_hex();
+    let response = query(
+        &index,
+        &format!(
+            r#"{{
+                odd: transactions(destination: "{}", status: Pending) {{
+                    nodes {{ amount }}
+                }}
+                edges(first: 1) {{ __typename nodes {{ parent child }} }}
+                account(id: "{}") {{ balance sequence }}
+                rich: accounts(minBalance: "1000") {{ nodes {{ id }} }}
+            }}"#,
+            destination,
+            account_id.to_hex()
+        ),
+        Value::Null,
+    );
+    let data = &response["data"];
+    let mut amounts = data["odd"]["nodes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|node| node["amount"].as_str().unwrap().to_string())
+        .collect::<Vec<_>>();
+    amounts.sort();
+    assert_eq!(amounts, vec!["1", "3", "5"]);
+    assert_eq!(data["edges"]["__typename"], json!("EdgeConnection"));
+    assert_eq!(
+        data["edges"]["nodes"][0]["parent"],
+        json!(Hash::default().to_hex())
+    );
+    assert_eq!(
+        data["account"]["balance"],
+        json!(account.balance.base_units().to_string())
+    );
+    assert_eq!(data["rich"]["nodes"], json!([]));
+}
+
+#[test]
+fn test_graphql_errors() {
+    let index = test_index();
+    let error = |text: &str| {
+        let response = query(&index, text, Value::Null);
+        assert_eq!(response["data"], Value::Null);
+        response["errors"][0]["message"]
+            .as_str()
+            .unwrap()
+            .to_string()
+    };
+    assert_eq!(
+        error("{ transactions { nodes { size } } }"),
+        "Unknown field size on Transaction"
+    );
+    assert_eq!(
+        error("{ transactions(first: 1000) { nodes { id } } }"),
+        format!("first must be between 1 and {}", MAX_PAGE_SIZE)
+    );
+    assert_eq!(
+        error("{ accounts }"),
+        "Fields of AccountConnection must be selected"
+    );
+    assert_eq!(
+        error("mutation { transactions { nodes { id } } }"),
+        "Unsupported operation: mutation"
+    );
+    assert_eq!(error(&"{ a ".repeat(100)), "Query is nested too deep");
+    assert!(error("{ transactions { nodes { id }").contains("end of query"));
+}
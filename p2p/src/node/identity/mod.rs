@@ -1,6 +1,8 @@
 use crate::error::P2pError;
+use consensus::NodeId;
 use crypto::{
     hash::Hash,
+    secret::Secret,
     signature::{PrivateKey, PublicKey, Signature},
 };
 pub use public_id::PublicId;
@@ -50,19 +52,26 @@ impl Identity {
         }
     }
 
-    pub fn get_our_hash(&self) -> Result<Hash, P2pError> {
-        Ok(Hash::serialize(&self.public_key).map_err(|e| P2pError::CryptoError(e))?)
+    pub fn get_our_hash(&self) -> Result<NodeId, P2pError> {
+        Hash::serialize(&self.public_key)
+            .map(NodeId::from)
+            .map_err(P2pError::CryptoError)
     }
 
     pub fn decode(encoded_id: &str) -> Result<Self, P2pError> {
-        let (_base, bytes) =
-            multibase::decode(encoded_id).map_err(|e| P2pError::MultibaseError(e))?;
-        Ok(bincode::deserialize(&bytes).map_err(|e| P2pError::BincodeError(e))?)
+        let (_base, bytes) = multibase::decode(encoded_id).map_err(P2pError::MultibaseError)?;
+        let bytes = Secret::new(bytes);
+        bincode::deserialize(bytes.expose_secret()).map_err(P2pError::BincodeError)
     }
 
-    pub fn encode(&self) -> Result<String, P2pError> {
-        let buffer = bincode::serialize(self).map_err(|e| P2pError::BincodeError(e))?;
-        Ok(multibase::encode(multibase::Base::Base32Z, buffer))
+    /// Encode the identity, private key included. The encoding and the
+    /// buffers it goes through are zeroed once dropped.
+    pub fn encode(&self) -> Result<Secret<String>, P2pError> {
+        let buffer = Secret::new(bincode::serialize(self).map_err(P2pError::BincodeError)?);
+        Ok(Secret::new(multibase::encode(
+            multibase::Base::Base32Z,
+            buffer.expose_secret(),
+        )))
     }
 }
 
@@ -98,7 +107,7 @@ impl<'de> serde::Deserialize<'de> for Identity {
 fn test_encode_decode_identity() {
     let identity = Identity::new();
     let encoded_id = identity.encode().unwrap();
-    let recovered_id = Identity::decode(&encoded_id).unwrap();
+    let recovered_id = Identity::decode(encoded_id.expose_secret()).unwrap();
     assert_eq!(identity.get_private_key(), recovered_id.get_private_key());
 }
 
@@ -31,7 +31,7 @@ impl Serialize for PublicId {
     where
         S: Serializer,
     {
-        (&self.public_key).serialize(serializer)
+        self.public_key.serialize(serializer)
     }
 }
 
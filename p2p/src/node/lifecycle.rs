@@ -0,0 +1,231 @@
+//! Lifecycle of a running node.
+//!
+//! A node runs its QUIC event loop and services on background threads. Its
+//! [`NodeHandle`] owns them: shutting down runs the registered shutdown steps
+//! in order, e.g. draining the outbox, notifying peers and flushing storage,
+//! then signals the threads to stop and joins them. Dropping the handle shuts
+//! the node down, so that it never leaves threads running behind it.
+//!
+//! Consensus participation can be paused for a maintenance window, e.g. a
+//! storage migration: the node then neither answers nor sends consensus
+//! queries, but keeps routing messages and syncing state, so that peers
+//! don't take it for failed.
+
+use super::{connection::Connection, messaging::Messaging};
+use crate::{error::P2pError, transport::Transport};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+/// Step run when the node shuts down
+type ShutdownStep = Box<dyn FnOnce() -> Result<(), P2pError> + Send>;
+
+/// Tells background threads when to stop
+#[derive(Clone, Debug, Default)]
+pub struct ShutdownSignal(Arc<AtomicBool>);
+
+impl ShutdownSignal {
+    pub fn is_shutting_down(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    fn raise(&self) -> bool {
+        !self.0.swap(true, Ordering::SeqCst)
+    }
+}
+
+/// Whether a node takes part in consensus. Clones share the same state.
+#[derive(Clone, Debug, Default)]
+pub struct Participation(Arc<AtomicBool>);
+
+impl Participation {
+    /// Stop answering and sending consensus queries, returning false if
+    /// they already were
+    pub fn pause(&self) -> bool {
+        let paused = !self.0.swap(true, Ordering::SeqCst);
+        if paused {
+            log::info!("Consensus participation paused");
+        }
+        paused
+    }
+
+    /// Take part in consensus again, returning false if the node already
+    /// did
+    pub fn resume(&self) -> bool {
+        let resumed = self.0.swap(false, Ordering::SeqCst);
+        if resumed {
+            log::info!("Consensus participation resumed");
+        }
+        resumed
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Owner of the background threads of a node and of the steps to run when
+/// it shuts down
+#[derive(Default)]
+pub struct NodeHandle {
+    signal: ShutdownSignal,
+    participation: Participation,
+    steps: Vec<(&'static str, ShutdownStep)>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl NodeHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Signal raised once the node shuts down
+    pub fn signal(&self) -> ShutdownSignal {
+        self.signal.clone()
+    }
+
+    pub fn is_running(&self) -> bool {
+        !self.signal.is_shutting_down()
+    }
+
+    /// Participation of the node in consensus, to hand to messaging
+    pub fn participation(&self) -> Participation {
+        self.participation.clone()
+    }
+
+    /// Stop answering and sending consensus queries, while still routing
+    /// and syncing. Returns false if consensus was already paused.
+    pub fn pause_consensus(&self) -> bool {
+        self.participation.pause()
+    }
+
+    /// Answer and send consensus queries again. Returns false if consensus
+    /// was not paused.
+    pub fn resume_consensus(&self) -> bool {
+        self.participation.resume()
+    }
+
+    pub fn is_consensus_paused(&self) -> bool {
+        self.participation.is_paused()
+    }
+
+    /// Run `task` on a named background thread. It should return soon after
+    /// the signal it is given is raised.
+    pub fn spawn<F>(&mut self, name: &str, task: F) -> Result<&mut Self, P2pError>
+    where
+        F: FnOnce(ShutdownSignal) + Send + 'static,
+    {
+        let signal = self.signal();
+        let thread = std::thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || task(signal))
+            .map_err(P2pError::IoError)?;
+        self.threads.push(thread);
+        Ok(self)
+    }
+
+    /// Run `step` when the node shuts down, after the steps registered
+    /// before it and while the background threads still run
+    pub fn on_shutdown<F>(&mut self, name: &'static str, step: F) -> &mut Self
+    where
+        F: FnOnce() -> Result<(), P2pError> + Send + 'static,
+    {
+        self.steps.push((name, Box::new(step)));
+        self
+    }
+
+    /// Run the shutdown steps, then stop and join the background threads.
+    ///
+    /// Every step runs even if an earlier one fails, the first failure is
+    /// returned. Shutting down twice does nothing.
+    pub fn shutdown(&mut self) -> Result<(), P2pError> {
+        if !self.is_running() {
+            return Ok(());
+        }
+        let mut outcome = Ok(());
+        for (name, step) in self.steps.drain(..) {
+            log::debug!("Shutdown: {}", name);
+            if let Err(e) = step() {
+                log::error!("Shutdown step {} failed: {:?}", name, e);
+                outcome = outcome.and(Err(e));
+            }
+        }
+        let _ = self.signal.raise();
+        for thread in self.threads.drain(..) {
+            let name = thread.thread().name().unwrap_or_default().to_string();
+            if thread.join().is_err() {
+                log::error!("Thread {} panicked before shutting down", name);
+            }
+        }
+        log::info!("Node shut down");
+        outcome
+    }
+}
+
+impl Drop for NodeHandle {
+    fn drop(&mut self) {
+        if let Err(e) = self.shutdown() {
+            log::error!("Failed to shut down cleanly: {:?}", e);
+        }
+    }
+}
+
+/// Leave the network: send what is left in the outbox, then let every peer
+/// know we are shutting down. Returns the number of envelopes sent.
+pub fn leave_network(
+    connection: &mut Connection,
+    messaging: &mut Messaging,
+    transport: &mut dyn Transport,
+) -> usize {
+    let drained = messaging.drain_outbox(connection.get_active_connections(), transport);
+    connection.disconnect_all(transport);
+    drained
+}
+
+#[test]
+fn test_node_handle_shuts_down_in_order() {
+    use std::sync::Mutex;
+
+    let order = Arc::new(Mutex::new(Vec::new()));
+    let mut handle = NodeHandle::new();
+    let log = order.clone();
+    handle
+        .spawn("event-loop", move |signal| {
+            while !signal.is_shutting_down() {
+                std::thread::yield_now();
+            }
+            log.lock().unwrap().push("event-loop");
+        })
+        .unwrap();
+    for step in ["drain", "flush"] {
+        let log = order.clone();
+        let _ = handle.on_shutdown(step, move || {
+            log.lock().unwrap().push(step);
+            match step {
+                "drain" => Err(P2pError::CustomError("peer gone".to_string())),
+                _ => Ok(()),
+            }
+        });
+    }
+
+    assert!(handle.is_running());
+    assert!(matches!(handle.shutdown(), Err(P2pError::CustomError(_))));
+    assert!(!handle.is_running());
+    assert_eq!(*order.lock().unwrap(), vec!["drain", "flush", "event-loop"]);
+    handle.shutdown().unwrap();
+
+    // Dropping the handle stops its threads too
+    let stopped = Arc::new(AtomicBool::new(false));
+    let mut handle = NodeHandle::new();
+    let flag = stopped.clone();
+    let _ = handle
+        .spawn("service", move |signal| {
+            while !signal.is_shutting_down() {
+                std::thread::yield_now();
+            }
+            flag.store(true, Ordering::SeqCst);
+        })
+        .unwrap();
+    drop(handle);
+    assert!(stopped.load(Ordering::SeqCst));
+}
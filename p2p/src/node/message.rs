@@ -1,8 +1,20 @@
-use super::{connection::SharedRoutingTable, identity::PublicId};
-use consensus::{account::AccountStateChoice, transaction::Transaction};
-use crypto::hash::Hash;
+use super::{
+    admin::AdminRequest, benchmark::BenchmarkCommand, capacity::CapacityAdvertisement, codec,
+    connection::SharedRoutingTable, fragment::Fragment, identity::PublicId, protocol::Features,
+    telemetry::TraceContext,
+};
+use crate::error::DecodeError;
+use consensus::{
+    account::AccountStateChoice,
+    reconcile::{Claim, StateDigest},
+    transaction::Transaction,
+    NodeId, TxId,
+};
+use crypto::{
+    hash::Hash,
+    signature::{PublicKey, Signature},
+};
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
 use std::net::SocketAddr;
 
 #[derive(Clone, Deserialize, Serialize)]
@@ -18,41 +30,251 @@ pub enum Message {
         signature: Vec<u8>,
         sender: PublicId,
     },
-    Identification(Hash),
+    /// Who we are, and the hash of our [`ProtocolParams`](super::protocol::ProtocolParams),
+    /// signed over the challenge of the peer's [`Message::Hello`] and the
+    /// key exchanged through both greetings
+    Identification {
+        public_id: PublicId,
+        /// Network the sender takes part in
+        network_id: Hash,
+        protocol: Hash,
+        /// Oldest and latest transaction encodings the sender decodes, see
+        /// [`codec`](consensus::transaction::codec)
+        tx_versions: (u8, u8),
+        signature: Signature,
+    },
     Contacts(Vec<SocketAddr>),
+    /// What the sender is willing to handle, signed by it
+    CapacityAdvertisement(CapacityAdvertisement),
     AgentMessage {
-        payload: Vec<(Hash, Message, usize)>,
+        payload: Vec<Envelope>,
     },
     RoutingTable {
         routing_table: SharedRoutingTable,
-        source: Hash,
+        source: NodeId,
+    },
+    /// Sent by `source` to its peers once it no longer routes to `peer`,
+    /// which disconnected from it, or whose departure its next hop announced
+    Departed {
+        peer: NodeId,
+        source: NodeId,
     },
     ConsensusRequest {
         data: AccountStateChoice,
     },
     DagConsensusRequest {
-        sender: Hash,
+        sender: NodeId,
         data: AccountStateChoice,
-        tx: Transaction,
+        tx: Box<Transaction>,
         count: usize,
     },
     DagConsensusResponse {
-        sender: Hash,
-        hash: Hash,
+        sender: NodeId,
+        hash: TxId,
         strongly_preferred: bool,
     },
-    InitBenchmarking(usize, u64),
+    BenchmarkControl {
+        command: BenchmarkCommand,
+        /// Network the command is signed for
+        network_id: Hash,
+        /// Above the nonces of the commands the sender signed before
+        nonce: u64,
+        signature: Signature,
+        sender: PublicId,
+    },
+    /// Command signed by an operator for the target node
+    AdminRequest(AdminRequest),
     CompleteRound,
-    BenchmarkStats(HashSet<u64>),
     BatchedConsensusRequest {
-        sender: Hash,
+        sender: NodeId,
         data: Vec<(AccountStateChoice, Transaction)>,
         count: usize,
     },
     BatchedConsensusResponse {
-        sender: Hash,
-        data: Vec<(Hash, bool)>,
+        sender: NodeId,
+        data: Vec<(TxId, bool)>,
+    },
+    /// Flooded to neighbours to find a path from `origin` to `target`
+    RouteRequest {
+        id: Hash,
+        origin: NodeId,
+        target: NodeId,
+        /// Hops travelled from the origin so far
+        hops: usize,
+        hops_left: usize,
+    },
+    /// Sent back along the request path by a node knowing a route to `target`
+    RouteReply {
+        id: Hash,
+        origin: NodeId,
+        target: NodeId,
+        /// Hops from the sender of the reply to the target
+        hops: usize,
+    },
+    /// Transactions finalized by the sender, to detect divergence after a
+    /// partition
+    StateDigest {
+        sender: NodeId,
+        digest: StateDigest,
+    },
+    /// Transactions the sender finalized where our digest diverged from its
+    /// own, along with their certificates
+    FinalityClaims {
+        sender: NodeId,
+        claims: Vec<Claim>,
+    },
+    /// Forwarded to a few neighbours, which forward it in turn until the
+    /// whole network received it
+    Gossip(Vec<u8>),
+    /// Sent to every peer by a node shutting down
+    Disconnecting,
+    /// Address the receiver is seen connecting from, which behind a NAT is
+    /// its public address rather than the one it listens at
+    ObservedAddress(SocketAddr),
+    /// Asks a peer connected to `target` to coordinate a hole punch to it
+    PunchRequest {
+        target: NodeId,
     },
+    /// Sent by a rendezvous to both sides of a hole punch: connect to `peer`
+    /// at `addr`
+    Punch {
+        peer: NodeId,
+        addr: SocketAddr,
+    },
+    /// Sent first on every connection, before identifying. Added last so
+    /// that the other variants keep their encoding.
+    Hello {
+        protocol_version: u32,
+        features: Features,
+        /// Chain the sender follows, see
+        /// [`GenesisConfig::chain_id`](consensus::genesis::GenesisConfig::chain_id)
+        chain_id: Hash,
+        /// Random bytes the peer must sign to identify
+        challenge: Hash,
+        /// Ephemeral key of the sender, whose exchange with the peer's binds
+        /// the identifications to this connection
+        exchange_key: PublicKey,
+    },
+    /// Piece of a message too large to be sent at once
+    Fragment(Fragment),
+}
+
+impl Message {
+    /// Decode a message of at most `limit` bytes received from a peer
+    pub fn decode(bytes: &[u8], limit: u64) -> Result<Self, DecodeError> {
+        codec::decode(bytes, limit)
+    }
+
+    /// Transaction of the consensus round the message is sent for. Batches
+    /// carry several, which are left out.
+    pub fn tx_id(&self) -> Option<TxId> {
+        match self {
+            Message::ConsensusRequest { data } => Some(data.tx.get_tx_id()),
+            Message::DagConsensusRequest { tx, .. } => Some(tx.get_tx_id()),
+            Message::DagConsensusResponse { hash, .. } => Some(*hash),
+            Message::AgentMessage { payload } if payload.len() == 1 => payload[0].message.tx_id(),
+            _ => None,
+        }
+    }
+
+    /// Whether the message queries a peer in a consensus round or answers
+    /// such a query, as opposed to syncing state
+    pub fn is_consensus_query(&self) -> bool {
+        match self {
+            Message::ConsensusRequest { .. }
+            | Message::DagConsensusRequest { .. }
+            | Message::DagConsensusResponse { .. }
+            | Message::BatchedConsensusRequest { .. }
+            | Message::BatchedConsensusResponse { .. }
+            | Message::CompleteRound => true,
+            Message::AgentMessage { payload } => payload
+                .iter()
+                .any(|envelope| envelope.message.is_consensus_query()),
+            _ => false,
+        }
+    }
+
+    /// Name of the message type, as used to configure hop limits
+    pub fn kind(&self) -> &'static str {
+        use Message::*;
+        match self {
+            UserMessage(_) => "UserMessage",
+            EncryptedMessage(_) => "EncryptedMessage",
+            Hello { .. } => "Hello",
+            Identification { .. } => "Identification",
+            Contacts(_) => "Contacts",
+            CapacityAdvertisement(_) => "CapacityAdvertisement",
+            AuthenticatedMessage { .. } => "AuthenticatedMessage",
+            SignedMessage { .. } => "SignedMessage",
+            AgentMessage { .. } => "AgentMessage",
+            ConsensusRequest { .. } => "ConsensusRequest",
+            DagConsensusRequest { .. } => "DagConsensusRequest",
+            DagConsensusResponse { .. } => "DagConsensusResponse",
+            BenchmarkControl { .. } => "BenchmarkControl",
+            AdminRequest(_) => "AdminRequest",
+            CompleteRound => "CompleteRound",
+            BatchedConsensusRequest { .. } => "BatchedConsensusRequest",
+            BatchedConsensusResponse { .. } => "BatchedConsensusResponse",
+            RoutingTable { .. } => "RoutingTable",
+            Departed { .. } => "Departed",
+            RouteRequest { .. } => "RouteRequest",
+            RouteReply { .. } => "RouteReply",
+            StateDigest { .. } => "StateDigest",
+            FinalityClaims { .. } => "FinalityClaims",
+            Gossip(_) => "Gossip",
+            Disconnecting => "Disconnecting",
+            ObservedAddress(_) => "ObservedAddress",
+            PunchRequest { .. } => "PunchRequest",
+            Punch { .. } => "Punch",
+            Fragment(_) => "Fragment",
+        }
+    }
+}
+
+/// A message relayed hop by hop towards its target
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Envelope {
+    /// Unique ID, used to detect messages looping through the network
+    pub id: Hash,
+    pub target: NodeId,
+    pub message: Message,
+    pub hops_left: usize,
+    /// Span the message was sent from, for traces to follow it across nodes
+    pub trace: Option<TraceContext>,
+}
+
+impl Envelope {
+    /// Wrap a message for `target` with a fresh ID, in the span entered
+    pub fn new(target: NodeId, message: Message, hops_left: usize) -> Self {
+        Self {
+            id: Hash::generate_random(),
+            target,
+            message,
+            hops_left,
+            trace: TraceContext::current(),
+        }
+    }
+
+    /// Span the envelope is handled in on each hop, carrying its ID, the
+    /// tx id of the consensus round it is sent for, if any, and the span it
+    /// was sent from
+    pub fn span(&self) -> tracing::Span {
+        let trace_id = self.trace.map(|trace| trace.trace_id_hex());
+        let parent_span_id = self.trace.map(|trace| trace.span_id_hex());
+        let span = tracing::debug_span!(
+            "message",
+            msg_id = %self.id,
+            kind = self.message.kind(),
+            hops_left = self.hops_left,
+            tx_id = tracing::field::Empty,
+            trace_id = trace_id.as_deref(),
+            parent_span_id = parent_span_id.as_deref(),
+        );
+        if let Some(tx_id) = self.message.tx_id() {
+            let _ = span.record("tx_id", tracing::field::display(tx_id));
+        }
+        span
+    }
 }
 
 impl std::fmt::Debug for Message {
@@ -61,20 +283,45 @@ impl std::fmt::Debug for Message {
         match self {
             UserMessage(_) => write!(f, "UserMessage(..)",),
             EncryptedMessage(_) => write!(f, "EncryptedMessage(..)",),
-            Identification(_) => write!(f, "Identification(..)",),
+            Hello {
+                protocol_version,
+                features,
+                ..
+            } => write!(f, "Hello(v{}, {:?})", protocol_version, features),
+            Identification { .. } => write!(f, "Identification {{ .. }} "),
             Contacts(_) => write!(f, "Contacts(..)",),
+            CapacityAdvertisement(advertisement) => {
+                write!(f, "CapacityAdvertisement({:?})", advertisement.capacity)
+            }
             AuthenticatedMessage { .. } => write!(f, "AuthenticatedMessage {{ .. }} "),
             SignedMessage { .. } => write!(f, "SignedMessage {{ .. }} "),
             AgentMessage { .. } => write!(f, "AgentMessage {{ .. }} "),
             ConsensusRequest { .. } => write!(f, "ConsensusRequest {{ .. }} "),
             DagConsensusRequest { .. } => write!(f, "DagConsensusRequest {{ .. }} "),
             DagConsensusResponse { .. } => write!(f, "DagConsensusResponse {{ .. }} "),
-            InitBenchmarking { .. } => write!(f, "InitBenchmarking"),
-            CompleteRound { .. } => write!(f, "CompleteRound"),
-            BenchmarkStats { .. } => write!(f, "BenchmarkStats"),
+            BenchmarkControl { .. } => write!(f, "BenchmarkControl {{ .. }} "),
+            AdminRequest(request) => write!(f, "AdminRequest({:?})", request.command),
+            CompleteRound => write!(f, "CompleteRound"),
             BatchedConsensusRequest { .. } => write!(f, "BatchedConsensusRequest"),
             BatchedConsensusResponse { .. } => write!(f, "BatchedConsensusResponse"),
             RoutingTable { .. } => write!(f, "RoutingTable"),
+            Departed { peer, .. } => write!(f, "Departed({:?})", peer),
+            RouteRequest { .. } => write!(f, "RouteRequest"),
+            RouteReply { .. } => write!(f, "RouteReply"),
+            StateDigest { .. } => write!(f, "StateDigest"),
+            FinalityClaims { .. } => write!(f, "FinalityClaims"),
+            Gossip(_) => write!(f, "Gossip(..)"),
+            Disconnecting => write!(f, "Disconnecting"),
+            ObservedAddress(addr) => write!(f, "ObservedAddress({:?})", addr),
+            PunchRequest { target } => write!(f, "PunchRequest {{ target: {:?} }}", target),
+            Punch { peer, addr } => write!(f, "Punch {{ peer: {:?}, addr: {:?} }}", peer, addr),
+            Fragment(fragment) => write!(
+                f,
+                "Fragment({} of {} of {})",
+                fragment.index + 1,
+                fragment.total,
+                fragment.msg_id
+            ),
         }
     }
 }
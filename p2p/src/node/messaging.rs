@@ -1,17 +1,105 @@
-use super::{connection::RoutingTable, event::Event, identity::Identity, message::Message};
-use crate::error::P2pError;
+use super::{
+    address_book::shuffle,
+    admin::{AdminCommand, AdminGuard},
+    config::{FragmentConfig, GossipConfig, HopLimits, OutboxConfig, PiggybackConfig, SendConfig},
+    connection::{connection_span, RoutingTable},
+    event::Event,
+    fragment::{fragment, Fragment, Reassembler},
+    gossip::Fanout,
+    identity::Identity,
+    lifecycle::Participation,
+    message::{Envelope, Message},
+    middleware::{Direction, Middleware, Pipeline},
+    outbox::{Outbox, Queued, RetryQueue},
+    tokens::{MessageClass, TokenInfo, Tokens, Unsent},
+};
+use crate::{
+    error::{AuthError, DecodeError, P2pError},
+    transport::Transport,
+};
 use bytes::Bytes;
+use consensus::{
+    budget::{MemoryBudget, Resource},
+    time::{Clock, SystemClock},
+    NodeId,
+};
 use crossbeam_channel::Sender;
-use crypto::{hash::Hash, signature::Signature};
-use quic_p2p::{Peer, QuicP2p};
-use std::collections::{hash_map::Entry, HashMap};
+use crypto::{cache::VerificationCache, hash::Hash, signature::Signature};
+use metrics::Metrics;
+use quic_p2p::Peer;
+use std::collections::{hash_map::Entry, HashMap, HashSet, VecDeque};
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-const TTL: usize = 5;
+/// Number of message IDs remembered to detect loops
+const SEEN_CAPACITY: usize = 10_000;
+/// How long messages wait for a route to their target to be discovered
+const ROUTE_DISCOVERY_TIMEOUT: Duration = Duration::from_secs(5);
 
-pub(super) struct Messaging {
-    outbox: HashMap<Hash, Vec<(Hash, Message, usize)>>,
-    pending_messages: Vec<(Bytes, u64, SocketAddr)>,
+/// Bounded set of the IDs of messages we sent, relayed or received
+#[derive(Default)]
+struct SeenMessages {
+    ids: HashSet<Hash>,
+    order: VecDeque<Hash>,
+}
+
+impl SeenMessages {
+    /// Record a message ID, returning false if it was seen already
+    fn insert(&mut self, id: Hash) -> bool {
+        if !self.ids.insert(id) {
+            return false;
+        }
+        self.order.push_back(id);
+        if self.order.len() > SEEN_CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                let _ = self.ids.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+/// Routes messages between peers and turns the ones meant for us into events
+pub struct Messaging {
+    outbox: Outbox,
+    /// Messages QUIC failed to send, waiting to be retried
+    pending_messages: RetryQueue,
+    /// Tokens of the messages handed to QUIC
+    tokens: Tokens,
+    hop_limits: HopLimits,
+    seen: SeenMessages,
+    /// Messages held while discovering a route to their target
+    awaiting_route: HashMap<NodeId, (Instant, Vec<Message>)>,
+    /// Neighbours gossip is forwarded to
+    fanout: Fanout,
+    piggyback: PiggybackConfig,
+    /// Small consensus responses waiting for other traffic to their next hop
+    piggybacked: HashMap<NodeId, (Instant, Vec<Envelope>)>,
+    fragments: FragmentConfig,
+    /// Fragments of the large messages peers are sending us
+    reassembler: Reassembler,
+    middleware: Pipeline,
+    /// Where to report outbox overflows
+    events: Option<Sender<Event>>,
+    /// Checks the admin requests we receive, refused without one
+    admin: Option<AdminGuard>,
+    /// Shared with the rest of the in-flight state
+    budget: MemoryBudget,
+    /// Outcomes of the signatures verified so far
+    verification_cache: VerificationCache,
+    /// Consensus queries are neither answered nor sent while paused
+    participation: Participation,
+    /// Network we take part in, which signed commands must be meant for
+    network_id: Hash,
+    metrics: Arc<Metrics>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for Messaging {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Messaging {
@@ -19,57 +107,455 @@ impl Messaging {
         Self {
             outbox: Default::default(),
             pending_messages: Default::default(),
+            tokens: Default::default(),
+            hop_limits: Default::default(),
+            seen: Default::default(),
+            awaiting_route: Default::default(),
+            fanout: Default::default(),
+            piggyback: Default::default(),
+            piggybacked: Default::default(),
+            fragments: Default::default(),
+            reassembler: Default::default(),
+            middleware: Default::default(),
+            events: None,
+            admin: None,
+            budget: MemoryBudget::unlimited(),
+            verification_cache: Default::default(),
+            participation: Default::default(),
+            network_id: Hash::default(),
+            metrics: Default::default(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Count the outbox against `budget`, and shed application messages
+    /// while the in-flight state is over it
+    pub fn set_budget(&mut self, budget: MemoryBudget) -> &mut Self {
+        self.budget = budget;
+        self.outbox_changed();
+        self
+    }
+
+    /// Set the hop limits of the messages we send
+    pub fn set_hop_limits(&mut self, hop_limits: HopLimits) -> &mut Self {
+        self.hop_limits = hop_limits;
+        self
+    }
+
+    /// Bound the envelopes queued for each next hop. Envelopes already
+    /// queued are dropped.
+    pub fn set_outbox_config(&mut self, config: &OutboxConfig) -> &mut Self {
+        self.outbox = Outbox::new(config.outbox_capacity(), config.overflow_policy());
+        self.pending_messages = RetryQueue::new(config.outbox_capacity(), config.overflow_policy());
+        self.outbox_changed();
+        self
+    }
+
+    /// Set the retry and timeout policies of unsent messages. Messages
+    /// already sent keep being tracked under the previous policies.
+    pub fn set_send_config(&mut self, config: &SendConfig) -> &mut Self {
+        let in_flight = std::mem::replace(&mut self.tokens, Tokens::new(config.clone()));
+        if in_flight.in_flight() > 0 {
+            log::debug!(
+                "No longer tracking {} messages in flight",
+                in_flight.in_flight()
+            );
         }
+        self
+    }
+
+    /// Set the bounds of the gossip fanout
+    pub fn set_gossip_config(&mut self, config: &GossipConfig) -> &mut Self {
+        self.fanout = Fanout::new(config.clone());
+        self
+    }
+
+    /// Set how long small consensus responses may wait for other traffic.
+    /// Responses already waiting are sent along with the next message to
+    /// their hop, or once they expire under the new delay.
+    pub fn set_piggyback_config(&mut self, config: &PiggybackConfig) -> &mut Self {
+        self.piggyback = config.clone();
+        self
+    }
+
+    /// Set the size above which messages are sent in fragments, and the
+    /// bounds of their reassembly. Incomplete messages are dropped.
+    pub fn set_fragment_config(&mut self, config: &FragmentConfig) -> &mut Self {
+        self.fragments = config.clone();
+        self.reassembler = Reassembler::new(config);
+        self
+    }
+
+    /// Set the channel outbox overflows and failed sends are reported to
+    pub fn set_event_sender(&mut self, events: Sender<Event>) -> &mut Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Drop the signed commands meant for networks other than `network_id`
+    pub fn set_network_id(&mut self, network_id: Hash) -> &mut Self {
+        self.network_id = network_id;
+        self
+    }
+
+    /// Carry out the admin requests of the operators trusted by `guard`
+    pub fn set_admin_guard(&mut self, guard: AdminGuard) -> &mut Self {
+        self.admin = Some(guard);
+        self
+    }
+
+    /// Run `middleware` on every envelope received from peers
+    pub fn add_inbound_middleware(&mut self, middleware: Middleware) -> &mut Self {
+        let _ = self.middleware.add_inbound(middleware);
+        self
+    }
+
+    /// Run `middleware` on every envelope sent or relayed to peers
+    pub fn add_outbound_middleware(&mut self, middleware: Middleware) -> &mut Self {
+        let _ = self.middleware.add_outbound(middleware);
+        self
+    }
+
+    /// Verify signed messages through `cache`, e.g. to share it with
+    /// other components
+    pub fn set_verification_cache(&mut self, cache: VerificationCache) -> &mut Self {
+        self.verification_cache = cache;
+        self
     }
 
+    /// Stop answering and sending consensus queries while `participation`
+    /// is paused, e.g. to share it with the node handle. Paused and resumed
+    /// by admin commands too.
+    pub fn set_participation(&mut self, participation: Participation) -> &mut Self {
+        self.participation = participation;
+        self
+    }
+
+    /// Set the metrics updated by messaging
+    pub fn set_metrics(&mut self, metrics: Arc<Metrics>) -> &mut Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Time sends, piggybacked responses and route discoveries with `clock`
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) -> &mut Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Retry a message QUIC failed to send, unless the policy of its class
+    /// says to give up on it, in which case the failure is reported
     pub fn handle_unsent_message(
         &mut self,
         msg: Bytes,
         token: u64,
         addr: SocketAddr,
     ) -> Result<(), P2pError> {
-        self.pending_messages.push((msg, token, addr));
+        match self.tokens.unsent(token, self.clock.now()) {
+            Unsent::Retry | Unsent::Untracked => {
+                if let Some((_, dropped)) = self.pending_messages.push(addr, msg, token) {
+                    self.report_full_retry_queue(addr, dropped);
+                }
+            }
+            Unsent::Failed(info) => self.report_send_failure(info),
+        }
         Ok(())
     }
 
+    /// Stop tracking a message QUIC sent
+    pub fn handle_sent_message(&mut self, token: u64) {
+        let _ = self.tokens.sent(token);
+    }
+
+    /// Give up on the messages QUIC neither sent nor reported unsent
+    /// before their deadline
+    pub fn expire_sends(&mut self) {
+        for info in self.tokens.expire(self.clock.now()) {
+            self.report_send_failure(info);
+        }
+    }
+
+    fn report_send_failure(&mut self, info: TokenInfo) {
+        log::warn!(
+            "Gave up sending {:?} message to {:?} after {} retries",
+            info.class,
+            info.peer,
+            info.retries
+        );
+        self.metrics.message_dropped();
+        if let Some(events) = &self.events {
+            let _ = events.send(Event::SendFailed {
+                peer: info.peer,
+                class: info.class,
+            });
+        }
+    }
+
+    /// Drop a message whose peer has too many messages waiting to be
+    /// retried
+    fn report_full_retry_queue(&mut self, peer: SocketAddr, token: u64) {
+        log::warn!("Too many unsent messages to {:?}, dropped one", peer);
+        // No longer tracked, it won't be reported as failed either
+        let _ = self.tokens.sent(token);
+        self.metrics.message_dropped();
+        if let Some(events) = &self.events {
+            let _ = events.send(Event::OutboxFull(peer));
+        }
+    }
+
+    /// Hand a message to QUIC under a token tracking its class, in
+    /// fragments if it is too large to be sent at once
+    fn send(&mut self, transport: &mut dyn Transport, socket: SocketAddr, message: &Message) {
+        let class = MessageClass::of(message);
+        let fragment_size = self.fragments.fragment_size();
+        let sends = bincode::serialize(message).and_then(|bytes| {
+            if bytes.len() <= fragment_size {
+                return Ok(vec![bytes]);
+            }
+            fragment(&bytes, fragment_size)
+                .into_iter()
+                .map(|fragment| bincode::serialize(&Message::Fragment(fragment)))
+                .collect()
+        });
+        match sends {
+            Ok(sends) => {
+                for bytes in sends {
+                    let token = self.tokens.allocate(class, socket, self.clock.now());
+                    transport.send(socket, Bytes::from(bytes), token);
+                }
+            }
+            Err(e) => log::error!("Failed to serialize {:?}: {:?}", message, e),
+        }
+    }
+
+    /// Take a fragment sent by `peer`, returning its message once all of
+    /// its fragments arrived
+    pub fn handle_fragment(
+        &mut self,
+        peer: &Peer,
+        fragment: Fragment,
+    ) -> Result<Option<Message>, DecodeError> {
+        let msg_id = fragment.msg_id;
+        let bytes = match self
+            .reassembler
+            .push(peer.peer_addr(), fragment, self.clock.now())
+        {
+            Ok(Some(bytes)) => bytes,
+            Ok(None) => return Ok(None),
+            Err(e) => {
+                log::warn!("Dropped message {} of {:?}: {}", msg_id, peer, e);
+                self.metrics.message_dropped();
+                return Err(e);
+            }
+        };
+        match Message::decode(&bytes, self.fragments.max_reassembly_size())? {
+            Message::Fragment(_) => Err(DecodeError::Malformed(format!(
+                "Message {} is a fragment itself",
+                msg_id
+            ))),
+            message => Ok(Some(message)),
+        }
+    }
+
+    /// Drop the messages whose fragments didn't all arrive in time
+    pub fn expire_fragments(&mut self) {
+        for _ in 0..self.reassembler.expire(self.clock.now()) {
+            self.metrics.message_dropped();
+        }
+    }
+
+    /// Drop the incomplete messages of a peer we disconnected from
+    pub fn forget_fragments(&mut self, peer: &SocketAddr) {
+        self.reassembler.forget(peer);
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub fn handle_agent_message(
         &mut self,
         our_id: &Identity,
         peer: &Peer,
-        mut payload: Vec<(Hash, Message, usize)>,
-        active_connections: &HashMap<Hash, SocketAddr>,
-        quic: &mut QuicP2p,
+        mut payload: Vec<Envelope>,
+        active_connections: &HashMap<NodeId, SocketAddr>,
+        transport: &mut dyn Transport,
         node_tx: &Sender<Event>,
         routing_table: RoutingTable,
     ) {
+        let _span = connection_span(&peer.peer_addr()).entered();
         let our_hash = our_id.get_our_hash().unwrap();
-        while let Some((target, message, step)) = payload.pop() {
-            if target == our_hash {
-                self.handle_message(peer, message, our_id, node_tx)
-                    .unwrap_or_else(|err| {
-                        log::error!("Error: {:?}", err);
-                    });
-            } else {
-                if step >= 1 {
-                    let (next_hop, _) = routing_table.get_routing_info(&target).unwrap();
-                    match self.outbox.entry(*next_hop) {
-                        Entry::Occupied(mut entry) => {
-                            let messages = entry.get_mut();
-                            messages.push((target, message, step - 1));
-                        }
-                        Entry::Vacant(entry) => {
-                            let _ = entry.insert(vec![(target, message, step - 1)]);
-                        }
-                    }
+        let sender = active_connections
+            .iter()
+            .find(|(_, socket)| **socket == peer.peer_addr())
+            .map(|(node_id, _)| *node_id);
+        while let Some(envelope) = payload.pop() {
+            let _span = envelope.span().entered();
+            match self.route(envelope, &our_hash, &routing_table) {
+                Some(Message::Gossip(content)) => {
+                    self.handle_gossip(content, sender.as_ref(), &routing_table, node_tx)
                 }
+                Some(message) => self.handle_message(peer, message, our_id, node_tx),
+                None => Ok(()),
             }
-            let outbox = std::mem::replace(&mut self.outbox, HashMap::new());
-            for (target, payload) in outbox {
-                self.send_agent_message(active_connections, &target, quic, payload);
+            .unwrap_or_else(|err| {
+                log::error!("Error: {:?}", err);
+            });
+            for (target, payload) in self.outbox.take_all() {
+                self.send_agent_message(active_connections, &target, transport, payload);
             }
+            self.outbox_changed();
         }
     }
 
+    /// Route a received envelope: return its message if it is meant for us,
+    /// otherwise queue it towards its target, or drop it if it ran out of
+    /// hops or came back to us. Senders can't get a message relayed further
+    /// than the hop limit of its type, however many hops they grant it.
+    fn route(
+        &mut self,
+        envelope: Envelope,
+        our_hash: &NodeId,
+        routing_table: &RoutingTable,
+    ) -> Option<Message> {
+        if !self.seen.insert(envelope.id) {
+            log::debug!(
+                "{} message {:?} looped back to us. Dropped.",
+                envelope.message.kind(),
+                envelope.id
+            );
+            self.metrics.message_looped();
+            return None;
+        }
+        let envelope = self.filter(Direction::Inbound, envelope)?;
+        if envelope.target == *our_hash {
+            if self.is_paused_for(&envelope.message) {
+                return None;
+            }
+            return Some(envelope.message);
+        }
+        let hops_left = envelope
+            .hops_left
+            .min(self.hop_limits.get(&envelope.message));
+        if hops_left == 0 {
+            log::debug!(
+                "Message for {:?} ran out of hops. Dropped.",
+                envelope.target
+            );
+            self.metrics.message_dropped();
+            return None;
+        }
+        let (next_hop, _) = match routing_table.known_route(&envelope.target) {
+            Some(route) => route,
+            None => {
+                log::debug!("No route to {:?}. Dropped.", envelope.target);
+                self.metrics.message_dropped();
+                return None;
+            }
+        };
+        let envelope = self.filter(
+            Direction::Outbound,
+            Envelope {
+                hops_left: hops_left - 1,
+                ..envelope
+            },
+        )?;
+        if self.enqueue(next_hop, envelope, routing_table).is_some() {
+            self.metrics.message_routed();
+        }
+        None
+    }
+
+    /// Run the middleware of a direction on an envelope, counting the
+    /// envelopes dropped by it
+    fn filter(&self, direction: Direction, envelope: Envelope) -> Option<Envelope> {
+        if self.middleware.is_empty() {
+            return Some(envelope);
+        }
+        let id = envelope.id;
+        let filtered = self.middleware.run(direction, envelope);
+        if filtered.is_none() {
+            log::debug!("Middleware dropped {:?} message {:?}", direction, id);
+            self.metrics.message_dropped();
+        }
+        filtered
+    }
+
+    /// Whether `message` is a consensus query to leave out while consensus
+    /// is paused
+    fn is_paused_for(&self, message: &Message) -> bool {
+        let paused = self.participation.is_paused() && message.is_consensus_query();
+        if paused {
+            log::debug!("Consensus is paused, {} dropped", message.kind());
+        }
+        paused
+    }
+
+    fn outbox_changed(&self) {
+        self.metrics.set_outbox_depth(self.outbox.depth());
+        self.budget
+            .set_used(Resource::Outboxes, self.outbox.size_in_bytes());
+    }
+
+    /// Queue an envelope for a next hop, returning the hop it was queued
+    /// for, or `None` if it was dropped because the outbox overflowed or,
+    /// carrying an application message, the in-flight state is over its
+    /// memory budget
+    fn enqueue(
+        &mut self,
+        next_hop: NodeId,
+        envelope: Envelope,
+        routing_table: &RoutingTable,
+    ) -> Option<NodeId> {
+        if self.budget.is_exhausted() && MessageClass::of(&envelope.message) == MessageClass::User {
+            log::warn!("Over the memory budget, dropped message {:?}", envelope.id);
+            self.metrics.message_dropped();
+            if let Some(events) = &self.events {
+                let _ = events.send(Event::ResourceExhausted {
+                    resource: Resource::Outboxes,
+                    used: self.budget.used(),
+                    limit: self.budget.limit(),
+                });
+            }
+            return None;
+        }
+        let queued_for = match self.outbox.push(next_hop, envelope, routing_table) {
+            Queued::Accepted => {
+                self.outbox_changed();
+                return Some(next_hop);
+            }
+            Queued::DroppedOld(dropped) => {
+                log::debug!("Outbox overflow dropped message {:?}", dropped.id);
+                self.metrics.message_dropped();
+                Some(next_hop)
+            }
+            Queued::DroppedNew(dropped) => {
+                log::debug!("Outbox overflow dropped message {:?}", dropped.id);
+                self.metrics.message_dropped();
+                None
+            }
+            Queued::Rerouted(alternate) => Some(alternate),
+        };
+        let policy = self.outbox.policy();
+        log::warn!("Outbox for {:?} is full, applied {:?}", next_hop, policy);
+        self.metrics.outbox_overflowed();
+        self.outbox_changed();
+        if let Some(events) = &self.events {
+            let _ = events.send(Event::OutboxOverflow {
+                peer: next_hop,
+                policy,
+            });
+        }
+        queued_for
+    }
+
+    /// Wrap a message we originate, remembering its ID so that it is dropped
+    /// if it ever loops back to us. Returns `None` if outbound middleware
+    /// dropped it.
+    fn envelope(&mut self, target: NodeId, message: Message) -> Option<Envelope> {
+        let hops = self.hop_limits.get(&message);
+        let envelope = Envelope::new(target, message, hops);
+        let _ = self.seen.insert(envelope.id);
+        self.filter(Direction::Outbound, envelope)
+    }
+
     fn handle_message(
         &mut self,
         peer: &Peer,
@@ -82,7 +568,7 @@ impl Messaging {
                 log::trace!("Peer {:?} sent us: {:?}", peer.peer_addr(), &content[..4]);
                 node_tx
                     .send(Event::NewMessage(content))
-                    .map_err(|e| P2pError::CrossbeamSenderError(e))?;
+                    .map_err(P2pError::from)?;
                 Ok(())
             }
             Message::SignedMessage {
@@ -97,19 +583,24 @@ impl Messaging {
                 );
                 let signature = Signature::from_bytes(&signature)
                     .map_err(|e| P2pError::CustomError(e.to_string()))?;
-                if signature.verify(&sender.public_key, &message) {
+                let verification =
+                    self.verification_cache
+                        .verify(&signature, &sender.public_key, &message);
+                self.metrics.signature_verified(verification.cached);
+                if verification.valid {
                     node_tx
                         .send(Event::NewMessage(message))
-                        .map_err(|e| P2pError::CrossbeamSenderError(e))?;
+                        .map_err(P2pError::from)?;
                 } else {
-                    log::error!("Message has invalid signature! Dropped.")
+                    log::error!("Message has invalid signature! Dropped.");
+                    self.metrics.message_dropped();
                 }
                 Ok(())
             }
             Message::ConsensusRequest { data } => {
                 node_tx
                     .send(Event::ConsensusRequest(data))
-                    .map_err(|e| P2pError::CrossbeamSenderError(e))?;
+                    .map_err(P2pError::from)?;
                 Ok(())
             }
             Message::DagConsensusRequest {
@@ -124,10 +615,8 @@ impl Messaging {
                     sender,
                     count,
                 };
-                log::error!("Received: {:?}", event);
-                node_tx
-                    .send(event)
-                    .map_err(|e| P2pError::CrossbeamSenderError(e))?;
+                tracing::debug!(?event, "Received");
+                node_tx.send(event).map_err(P2pError::from)?;
                 Ok(())
             }
             Message::DagConsensusResponse {
@@ -140,28 +629,69 @@ impl Messaging {
                     sender,
                     accepted: strongly_preferred,
                 };
-                log::error!("Received: {:?}", event);
-                node_tx
-                    .send(event)
-                    .map_err(|e| P2pError::CrossbeamSenderError(e))?;
+                tracing::debug!(?event, "Received");
+                node_tx.send(event).map_err(P2pError::from)?;
                 Ok(())
             }
-            Message::InitBenchmarking(count, interval) => {
-                node_tx
-                    .send(Event::InitBenchmarkingSignal(count, interval))
-                    .map_err(|e| P2pError::CrossbeamSenderError(e))?;
+            Message::BenchmarkControl {
+                command,
+                network_id,
+                nonce,
+                signature,
+                sender,
+            } => {
+                if network_id != self.network_id {
+                    log::warn!("Benchmark command for network {} dropped", network_id);
+                    self.metrics.message_dropped();
+                    return Ok(());
+                }
+                match command.verify(&network_id, nonce, &signature, &sender) {
+                    Ok(sender) => node_tx
+                        .send(Event::BenchmarkControl {
+                            sender,
+                            nonce,
+                            command,
+                        })
+                        .map_err(P2pError::from)?,
+                    Err(_) => {
+                        log::error!("Benchmark command has invalid signature! Dropped.");
+                        self.metrics.message_dropped();
+                    }
+                }
                 Ok(())
             }
-            Message::CompleteRound => {
-                node_tx
-                    .send(Event::CompleteRound)
-                    .map_err(|e| P2pError::CrossbeamSenderError(e))?;
+            Message::AdminRequest(request) => {
+                let checked = match &self.admin {
+                    Some(guard) => guard.check(&request),
+                    None => Err(AuthError::UntrustedOperator.into()),
+                };
+                match checked {
+                    Ok(operator) => {
+                        match request.command {
+                            AdminCommand::PauseConsensus => {
+                                let _ = self.participation.pause();
+                            }
+                            AdminCommand::ResumeConsensus => {
+                                let _ = self.participation.resume();
+                            }
+                            _ => {}
+                        }
+                        node_tx
+                            .send(Event::AdminCommand {
+                                operator,
+                                command: request.command,
+                            })
+                            .map_err(P2pError::from)?
+                    }
+                    Err(e) => {
+                        log::error!("Admin request refused: {}. Dropped.", e);
+                        self.metrics.message_dropped();
+                    }
+                }
                 Ok(())
             }
-            Message::BenchmarkStats(txns) => {
-                node_tx
-                    .send(Event::BenchmarkStats(txns))
-                    .map_err(|e| P2pError::CrossbeamSenderError(e))?;
+            Message::CompleteRound => {
+                node_tx.send(Event::CompleteRound).map_err(P2pError::from)?;
                 Ok(())
             }
             Message::BatchedConsensusRequest {
@@ -175,80 +705,955 @@ impl Messaging {
                         data,
                         count,
                     })
-                    .map_err(|e| P2pError::CrossbeamSenderError(e))?;
+                    .map_err(P2pError::from)?;
                 Ok(())
             }
             Message::BatchedConsensusResponse { sender, data } => {
                 node_tx
                     .send(Event::BatchedConsensusResponse { sender, data })
-                    .map_err(|e| P2pError::CrossbeamSenderError(e))?;
+                    .map_err(P2pError::from)?;
+                Ok(())
+            }
+            Message::StateDigest { sender, digest } => {
+                if !digest.verify() {
+                    log::error!("State digest from {:?} is inconsistent! Dropped.", sender);
+                    self.metrics.message_dropped();
+                    return Ok(());
+                }
+                node_tx
+                    .send(Event::StateDigest { sender, digest })
+                    .map_err(P2pError::from)?;
+                Ok(())
+            }
+            Message::FinalityClaims { sender, claims } => {
+                node_tx
+                    .send(Event::FinalityClaims { sender, claims })
+                    .map_err(P2pError::from)?;
                 Ok(())
             }
             _ => {
                 log::error!("Unexpected message!!");
+                self.metrics.message_dropped();
                 Ok(())
             }
         }
     }
 
-    pub fn send_message(&mut self, dst_peer: &Hash, msg: &[u8], routing_table: &RoutingTable) {
-        let (next_hop, _) = routing_table.get_routing_info(&dst_peer).unwrap();
-        match self.outbox.entry(*next_hop) {
-            Entry::Occupied(mut entry) => {
-                let messages = entry.get_mut();
-                messages.push((*dst_peer, Message::UserMessage(msg.to_vec()), TTL));
-            }
-            Entry::Vacant(entry) => {
-                let _ = entry.insert(vec![(*dst_peer, Message::UserMessage(msg.to_vec()), TTL)]);
+    pub fn send_message(
+        &mut self,
+        dst_peer: &NodeId,
+        msg: &[u8],
+        routing_table: &RoutingTable,
+    ) -> Result<(), P2pError> {
+        let (next_hop, _) = routing_table
+            .known_route(dst_peer)
+            .ok_or(P2pError::NoRoute(*dst_peer))?;
+        if let Some(envelope) = self.envelope(*dst_peer, Message::UserMessage(msg.to_vec())) {
+            let _ = self.enqueue(next_hop, envelope, routing_table);
+        }
+        Ok(())
+    }
+
+    /// Gossip a message to the whole network, returning the number of
+    /// neighbours it was queued for
+    pub fn gossip(&mut self, msg: &[u8], routing_table: &RoutingTable) -> usize {
+        let _ = self.seen.insert(Hash::new(msg));
+        self.spread(msg.to_vec(), None, routing_table)
+    }
+
+    /// Pass on gossip we see for the first time, and count the duplicates
+    /// to adapt the fanout
+    fn handle_gossip(
+        &mut self,
+        content: Vec<u8>,
+        sender: Option<&NodeId>,
+        routing_table: &RoutingTable,
+        node_tx: &Sender<Event>,
+    ) -> Result<(), P2pError> {
+        let fresh = self.seen.insert(Hash::new(&content));
+        self.fanout.record(!fresh);
+        if !fresh {
+            log::trace!("Dropped duplicate gossip from {:?}", sender);
+            return Ok(());
+        }
+        let _ = self.spread(content.clone(), sender, routing_table);
+        node_tx
+            .send(Event::NewMessage(content))
+            .map_err(P2pError::from)
+    }
+
+    /// Queue gossip for a random subset of our neighbours, other than the
+    /// one we got it from
+    fn spread(
+        &mut self,
+        content: Vec<u8>,
+        sender: Option<&NodeId>,
+        routing_table: &RoutingTable,
+    ) -> usize {
+        let fanout = self.fanout.adjust(routing_table.entries().len() + 1);
+        let mut neighbours = routing_table
+            .neighbours()
+            .filter(|node_id| Some(*node_id) != sender)
+            .copied()
+            .collect::<Vec<_>>();
+        shuffle(&mut neighbours);
+        neighbours.truncate(fanout);
+        let mut queued = 0;
+        for neighbour in neighbours {
+            let envelope = match self.envelope(neighbour, Message::Gossip(content.clone())) {
+                Some(envelope) => envelope,
+                None => continue,
+            };
+            if self.enqueue(neighbour, envelope, routing_table).is_some() {
+                queued += 1;
             }
         }
+        queued
     }
 
+    /// Send a message towards a peer. If no route to it is known, the message
+    /// is held while a route is discovered through our neighbours.
+    #[allow(clippy::too_many_arguments)]
     pub fn push_to_outbox(
         &mut self,
-        dst_peer: Hash,
+        our_hash: &NodeId,
+        dst_peer: NodeId,
         message: Message,
         routing_table: &RoutingTable,
-        active_connections: &HashMap<Hash, SocketAddr>,
-        quic: &mut QuicP2p,
+        active_connections: &HashMap<NodeId, SocketAddr>,
+        transport: &mut dyn Transport,
+    ) -> Result<(), P2pError> {
+        tracing::trace!(?message, peer = %dst_peer, "Pushed to outbox");
+        if self.is_paused_for(&message) {
+            return Ok(());
+        }
+        let next_hop = match routing_table.known_route(&dst_peer) {
+            Some((next_hop, _)) => next_hop,
+            None => {
+                let requests =
+                    self.hold_for_route(our_hash, dst_peer, message, active_connections)?;
+                self.send_direct(transport, requests);
+                return Ok(());
+            }
+        };
+        let envelope = match self.envelope(dst_peer, message) {
+            Some(envelope) => envelope,
+            None => return Ok(()),
+        };
+        let envelope = match self.piggyback(next_hop, envelope) {
+            Some(envelope) => envelope,
+            None => return Ok(()),
+        };
+        if let Some(next_hop) = self.enqueue(next_hop, envelope, routing_table) {
+            let payload = self.outbox.take(&next_hop);
+            self.outbox_changed();
+            self.send_agent_message(active_connections, &next_hop, transport, payload);
+        }
+        Ok(())
+    }
+
+    /// Hold a small consensus response to be sent along with the next
+    /// message to `next_hop`. Returns the envelope back if it is to be sent
+    /// right away.
+    fn piggyback(&mut self, next_hop: NodeId, envelope: Envelope) -> Option<Envelope> {
+        if !self.piggyback.is_enabled() {
+            return Some(envelope);
+        }
+        let is_response = matches!(
+            envelope.message,
+            Message::DagConsensusResponse { .. } | Message::BatchedConsensusResponse { .. }
+        );
+        let is_small = bincode::serialized_size(&envelope)
+            .is_ok_and(|size| size <= self.piggyback.max_size() as u64);
+        if !is_response || !is_small {
+            return Some(envelope);
+        }
+        self.piggybacked
+            .entry(next_hop)
+            .or_insert_with(|| (self.clock.now(), vec![]))
+            .1
+            .push(envelope);
+        None
+    }
+
+    /// Responses held for `next_hop`, which no longer wait
+    fn take_piggybacked(&mut self, next_hop: &NodeId) -> Vec<Envelope> {
+        self.piggybacked
+            .remove(next_hop)
+            .map(|(_, held)| held)
+            .unwrap_or_default()
+    }
+
+    /// Responses that waited their whole delay without other traffic to
+    /// their next hop
+    fn expired_piggybacks(&mut self) -> Vec<(NodeId, Vec<Envelope>)> {
+        let (delay, now) = (self.piggyback.delay(), self.clock.now());
+        let expired = self
+            .piggybacked
+            .iter()
+            .filter(|(_, (since, _))| now.saturating_duration_since(*since) >= delay)
+            .map(|(next_hop, _)| *next_hop)
+            .collect::<Vec<_>>();
+        expired
+            .into_iter()
+            .map(|next_hop| {
+                let held = self.take_piggybacked(&next_hop);
+                (next_hop, held)
+            })
+            .collect()
+    }
+
+    /// Send on their own the responses that waited too long for other
+    /// traffic to carry them
+    pub fn flush_piggybacked(
+        &mut self,
+        active_connections: &HashMap<NodeId, SocketAddr>,
+        transport: &mut dyn Transport,
     ) {
-        log::error!("Pushed {:?} to outbox for {:?}", message, dst_peer);
-        let (next_hop, _) = routing_table.get_routing_info(&dst_peer).unwrap();
-        match self.outbox.entry(*next_hop) {
+        for (next_hop, held) in self.expired_piggybacks() {
+            self.send_agent_message(active_connections, &next_hop, transport, held);
+        }
+    }
+
+    /// Hold a message until a route to `target` is found, returning the route
+    /// requests to flood if no discovery is running for it yet
+    fn hold_for_route(
+        &mut self,
+        our_hash: &NodeId,
+        target: NodeId,
+        message: Message,
+        active_connections: &HashMap<NodeId, SocketAddr>,
+    ) -> Result<Vec<(SocketAddr, Message)>, P2pError> {
+        if active_connections.is_empty() {
+            return Err(P2pError::NoRoute(target));
+        }
+        match self.awaiting_route.entry(target) {
             Entry::Occupied(mut entry) => {
-                let messages = entry.get_mut();
-                messages.push((dst_peer, message, TTL));
+                entry.get_mut().1.push(message);
+                Ok(vec![])
             }
             Entry::Vacant(entry) => {
-                let _ = entry.insert(vec![(dst_peer, message, TTL)]);
+                let _ = entry.insert((self.clock.now(), vec![message]));
+                let id = Hash::generate_random();
+                let _ = self.seen.insert(id);
+                let request = Message::RouteRequest {
+                    id,
+                    origin: *our_hash,
+                    target,
+                    hops: 0,
+                    hops_left: self.hop_limits.get_kind("RouteRequest"),
+                };
+                log::debug!("Discovering a route to {:?}", target);
+                Ok(active_connections
+                    .values()
+                    .map(|socket| (*socket, request.clone()))
+                    .collect())
+            }
+        }
+    }
+
+    /// Handle a route request from a neighbour, replying if we know a route
+    /// to its target and flooding it further otherwise
+    #[allow(clippy::too_many_arguments)]
+    pub fn handle_route_request(
+        &mut self,
+        our_hash: &NodeId,
+        peer_hash: NodeId,
+        request: Message,
+        routing_table: &mut RoutingTable,
+        active_connections: &HashMap<NodeId, SocketAddr>,
+        transport: &mut dyn Transport,
+    ) {
+        let outgoing = self.route_request(
+            our_hash,
+            peer_hash,
+            request,
+            routing_table,
+            active_connections,
+        );
+        self.send_direct(transport, outgoing);
+    }
+
+    fn route_request(
+        &mut self,
+        our_hash: &NodeId,
+        peer_hash: NodeId,
+        request: Message,
+        routing_table: &mut RoutingTable,
+        active_connections: &HashMap<NodeId, SocketAddr>,
+    ) -> Vec<(SocketAddr, Message)> {
+        let (id, origin, target, hops, hops_left) = match request {
+            Message::RouteRequest {
+                id,
+                origin,
+                target,
+                hops,
+                hops_left,
+            } => (id, origin, target, hops, hops_left),
+            _ => return vec![],
+        };
+        if !self.seen.insert(id) {
+            self.metrics.message_looped();
+            return vec![];
+        }
+        let _ = routing_table.update_route(&origin, &peer_hash, hops + 1);
+        let distance = if target == *our_hash {
+            Some(0)
+        } else {
+            routing_table
+                .known_route(&target)
+                .filter(|(next_hop, _)| *next_hop != peer_hash)
+                .map(|(_, hops)| hops)
+        };
+        if let Some(hops) = distance {
+            return match active_connections.get(&peer_hash) {
+                Some(socket) => vec![(
+                    *socket,
+                    Message::RouteReply {
+                        id,
+                        origin,
+                        target,
+                        hops,
+                    },
+                )],
+                None => vec![],
+            };
+        }
+        let hops_left = hops_left.min(self.hop_limits.get_kind("RouteRequest"));
+        if hops_left == 0 {
+            self.metrics.message_dropped();
+            return vec![];
+        }
+        self.metrics.message_routed();
+        let request = Message::RouteRequest {
+            id,
+            origin,
+            target,
+            hops: hops + 1,
+            hops_left: hops_left - 1,
+        };
+        active_connections
+            .iter()
+            .filter(|(node, _)| **node != peer_hash)
+            .map(|(_, socket)| (*socket, request.clone()))
+            .collect()
+    }
+
+    /// Handle a route reply from a neighbour, sending the messages held for
+    /// its target if we requested the route, or passing it back towards the
+    /// origin otherwise
+    #[allow(clippy::too_many_arguments)]
+    pub fn handle_route_reply(
+        &mut self,
+        our_hash: &NodeId,
+        peer_hash: NodeId,
+        reply: Message,
+        routing_table: &mut RoutingTable,
+        active_connections: &HashMap<NodeId, SocketAddr>,
+        transport: &mut dyn Transport,
+    ) {
+        let outgoing = self.route_reply(
+            our_hash,
+            peer_hash,
+            reply,
+            routing_table,
+            active_connections,
+        );
+        self.send_direct(transport, outgoing);
+    }
+
+    fn route_reply(
+        &mut self,
+        our_hash: &NodeId,
+        peer_hash: NodeId,
+        reply: Message,
+        routing_table: &mut RoutingTable,
+        active_connections: &HashMap<NodeId, SocketAddr>,
+    ) -> Vec<(SocketAddr, Message)> {
+        let (id, origin, target, hops) = match reply {
+            Message::RouteReply {
+                id,
+                origin,
+                target,
+                hops,
+            } => (id, origin, target, hops),
+            _ => return vec![],
+        };
+        let _ = routing_table.update_route(&target, &peer_hash, hops + 1);
+        let (next_hop, message) = if origin == *our_hash {
+            let (_, held) = match self.awaiting_route.remove(&target) {
+                Some(held) => held,
+                None => return vec![],
+            };
+            log::debug!("Discovered a route to {:?}", target);
+            let payload = held
+                .into_iter()
+                .filter_map(|message| self.envelope(target, message))
+                .collect();
+            (peer_hash, Message::AgentMessage { payload })
+        } else {
+            match routing_table.known_route(&origin) {
+                Some((next_hop, _)) => (
+                    next_hop,
+                    Message::RouteReply {
+                        id,
+                        origin,
+                        target,
+                        hops: hops + 1,
+                    },
+                ),
+                None => {
+                    self.metrics.message_dropped();
+                    return vec![];
+                }
             }
+        };
+        match active_connections.get(&next_hop) {
+            Some(socket) => vec![(*socket, message)],
+            None => vec![],
         }
-        let payload = self.outbox.remove(next_hop).unwrap();
-        self.send_agent_message(active_connections, next_hop, quic, payload);
     }
 
-    fn send_pending_messages(&mut self, quic: &mut QuicP2p) {
+    /// Drop the messages whose route could not be discovered in time,
+    /// returning the targets found unreachable
+    pub fn expire_route_discoveries(&mut self) -> Vec<NodeId> {
+        let now = self.clock.now();
+        let expired = self
+            .awaiting_route
+            .iter()
+            .filter(|(_, (started, _))| {
+                now.saturating_duration_since(*started) >= ROUTE_DISCOVERY_TIMEOUT
+            })
+            .map(|(target, _)| *target)
+            .collect::<Vec<_>>();
+        for target in &expired {
+            if let Some((_, held)) = self.awaiting_route.remove(target) {
+                log::warn!(
+                    "No route to {:?} found. Dropped {} messages.",
+                    target,
+                    held.len()
+                );
+                held.iter().for_each(|_| self.metrics.message_dropped());
+            }
+        }
+        expired
+    }
+
+    /// Send every envelope still queued in the outbox, along with the
+    /// held responses and the messages waiting to be retried. Returns the
+    /// number of envelopes sent.
+    pub fn drain_outbox(
+        &mut self,
+        active_connections: &HashMap<NodeId, SocketAddr>,
+        transport: &mut dyn Transport,
+    ) -> usize {
+        let mut drained = 0;
+        for (next_hop, payload) in self.outbox.take_all() {
+            drained += payload.len();
+            self.send_agent_message(active_connections, &next_hop, transport, payload);
+        }
+        let held = self.piggybacked.drain().collect::<Vec<_>>();
+        for (next_hop, (_, payload)) in held {
+            drained += payload.len();
+            self.send_agent_message(active_connections, &next_hop, transport, payload);
+        }
+        self.send_pending_messages(transport);
+        self.outbox_changed();
+        drained
+    }
+
+    /// Send messages straight to neighbours
+    fn send_direct(&mut self, transport: &mut dyn Transport, outgoing: Vec<(SocketAddr, Message)>) {
+        for (socket, message) in outgoing {
+            self.send(transport, socket, &message);
+        }
+    }
+
+    fn send_pending_messages(&mut self, transport: &mut dyn Transport) {
         if self.pending_messages.is_empty() {
             return;
         }
-        while let Some((msg, token, addr)) = self.pending_messages.pop() {
-            quic.send(Peer::Node(addr), msg, token);
+        for (msg, token, addr) in self.pending_messages.drain() {
+            transport.send(addr, msg, token);
         }
     }
 
     pub fn send_agent_message(
         &mut self,
-        active_connections: &HashMap<Hash, SocketAddr>,
-        target: &Hash,
-        quic: &mut QuicP2p,
-        payload: Vec<(Hash, Message, usize)>,
+        active_connections: &HashMap<NodeId, SocketAddr>,
+        target: &NodeId,
+        transport: &mut dyn Transport,
+        mut payload: Vec<Envelope>,
     ) {
-        self.send_pending_messages(quic);
-        let socket = active_connections.get(target).unwrap();
-        quic.send(
-            Peer::Node(*socket),
-            Bytes::from(bincode::serialize(&Message::AgentMessage { payload }).unwrap()),
-            0,
-        );
+        self.send_pending_messages(transport);
+        let mut held = self.take_piggybacked(target);
+        if !held.is_empty() {
+            held.append(&mut payload);
+            payload = held;
+        }
+        match active_connections.get(target) {
+            Some(socket) => self.send(transport, *socket, &Message::AgentMessage { payload }),
+            None => {
+                log::warn!(
+                    "{:?} is no longer connected. Dropped {} messages.",
+                    target,
+                    payload.len()
+                );
+                payload.iter().for_each(|_| self.metrics.message_dropped());
+            }
+        }
+    }
+}
+
+#[test]
+fn test_relayed_message_loop_is_dropped() {
+    let our_hash = NodeId::from(Hash::new("us".as_bytes()));
+    let neighbour = NodeId::from(Hash::new("neighbour".as_bytes()));
+    let target = NodeId::from(Hash::new("target".as_bytes()));
+    let mut routing_table = RoutingTable::default();
+    routing_table.add_direct_connection(&neighbour);
+    let _ = routing_table.entries_mut().insert(target, (neighbour, 2));
+
+    let mut messaging = Messaging::new();
+    let envelope = Envelope::new(target, Message::CompleteRound, 3);
+    assert!(messaging
+        .route(envelope.clone(), &our_hash, &routing_table)
+        .is_none());
+    assert_eq!(messaging.outbox.get(&neighbour).unwrap()[0].hops_left, 2);
+
+    // The same message coming back through a cycle is dropped
+    assert!(messaging
+        .route(envelope, &our_hash, &routing_table)
+        .is_none());
+    assert_eq!(messaging.outbox.get(&neighbour).unwrap().len(), 1);
+
+    let expired = Envelope::new(target, Message::CompleteRound, 0);
+    assert!(messaging
+        .route(expired, &our_hash, &routing_table)
+        .is_none());
+
+    let ours = Envelope::new(our_hash, Message::CompleteRound, 0);
+    assert!(messaging.route(ours, &our_hash, &routing_table).is_some());
+
+    let snapshot = messaging.metrics.snapshot();
+    assert_eq!(snapshot.routed_messages, 1);
+    assert_eq!(snapshot.looped_messages, 1);
+    assert_eq!(snapshot.dropped_messages, 2);
+
+    // Hops granted beyond the limit of the message type are not honored
+    let _ = messaging.set_hop_limits(HopLimits::new(2));
+    let inflated = Envelope::new(target, Message::CompleteRound, 100);
+    assert!(messaging
+        .route(inflated, &our_hash, &routing_table)
+        .is_none());
+    assert_eq!(messaging.outbox.get(&neighbour).unwrap()[1].hops_left, 1);
+}
+
+#[test]
+fn test_route_discovery() {
+    let addr = |port| SocketAddr::from(([127, 0, 0, 1], port));
+    let (a, b, c) = (
+        NodeId::from(Hash::new("A".as_bytes())),
+        NodeId::from(Hash::new("B".as_bytes())),
+        NodeId::from(Hash::new("C".as_bytes())),
+    );
+    let unknown = NodeId::from(Hash::new("unknown".as_bytes()));
+
+    // A knows only B, which is connected to C
+    let mut a_messaging = Messaging::new();
+    let mut a_routes = RoutingTable::default();
+    a_routes.add_direct_connection(&b);
+    let a_connections = [(b, addr(2))].into_iter().collect::<HashMap<_, _>>();
+    assert!(matches!(
+        a_messaging.send_message(&c, &[], &a_routes),
+        Err(P2pError::NoRoute(node)) if node == c
+    ));
+    assert!(matches!(
+        a_messaging.hold_for_route(&a, unknown, Message::CompleteRound, &HashMap::new()),
+        Err(P2pError::NoRoute(_))
+    ));
+
+    let requests = a_messaging
+        .hold_for_route(&a, c, Message::CompleteRound, &a_connections)
+        .unwrap();
+    assert_eq!(requests.len(), 1);
+    assert!(a_messaging
+        .hold_for_route(&a, c, Message::CompleteRound, &a_connections)
+        .unwrap()
+        .is_empty());
+
+    let mut b_messaging = Messaging::new();
+    let mut b_routes = RoutingTable::default();
+    b_routes.add_direct_connection(&a);
+    b_routes.add_direct_connection(&c);
+    let b_connections = [(a, addr(1)), (c, addr(3))]
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+    let request = requests[0].1.clone();
+    let replies = b_messaging.route_request(&b, a, request.clone(), &mut b_routes, &b_connections);
+    assert_eq!(replies.len(), 1);
+    assert_eq!(replies[0].0, addr(1));
+
+    // The same request reaching B again through a cycle is dropped
+    assert!(b_messaging
+        .route_request(&b, c, request, &mut b_routes, &b_connections)
+        .is_empty());
+
+    let sent = a_messaging.route_reply(&a, b, replies[0].1.clone(), &mut a_routes, &a_connections);
+    assert_eq!(a_routes.known_route(&c), Some((b, 2)));
+    match &sent[..] {
+        [(socket, Message::AgentMessage { payload })] => {
+            assert_eq!(*socket, addr(2));
+            assert_eq!(payload.len(), 2);
+            assert!(payload.iter().all(|envelope| envelope.target == c));
+        }
+        _ => panic!("Held messages were not sent"),
+    }
+    assert!(a_messaging.expire_route_discoveries().is_empty());
+}
+
+#[test]
+fn test_middleware_filters_messages() {
+    use super::middleware::Action;
+
+    let our_hash = NodeId::from(Hash::new("us".as_bytes()));
+    let neighbour = NodeId::from(Hash::new("neighbour".as_bytes()));
+    let mut routing_table = RoutingTable::default();
+    routing_table.add_direct_connection(&neighbour);
+
+    let mut messaging = Messaging::new();
+    messaging
+        .add_inbound_middleware(Box::new(|ctx| match ctx.envelope.message {
+            Message::CompleteRound => Action::Drop,
+            _ => Action::Continue,
+        }))
+        .add_outbound_middleware(Box::new(|ctx| {
+            ctx.envelope.message = Message::UserMessage(b"redacted".to_vec());
+            Action::Mutate
+        }));
+
+    let dropped = Envelope::new(our_hash, Message::CompleteRound, 1);
+    assert!(messaging
+        .route(dropped, &our_hash, &routing_table)
+        .is_none());
+    let kept = Envelope::new(our_hash, Message::UserMessage(vec![]), 1);
+    assert!(messaging.route(kept, &our_hash, &routing_table).is_some());
+
+    messaging
+        .send_message(&neighbour, b"secret", &routing_table)
+        .unwrap();
+    match &messaging.outbox.get(&neighbour).unwrap()[0].message {
+        Message::UserMessage(content) => assert_eq!(content, b"redacted"),
+        other => panic!("Unexpected {:?}", other),
+    }
+    assert_eq!(messaging.metrics.snapshot().dropped_messages, 1);
+}
+
+#[test]
+fn test_outbox_overflow_is_reported() {
+    use super::config::OverflowPolicy;
+
+    let neighbour = NodeId::from(Hash::new("neighbour".as_bytes()));
+    let mut routing_table = RoutingTable::default();
+    routing_table.add_direct_connection(&neighbour);
+
+    let (events, node_rx) = crossbeam_channel::unbounded();
+    let mut messaging = Messaging::new();
+    messaging
+        .set_outbox_config(&OutboxConfig::new(2, OverflowPolicy::DropOld))
+        .set_event_sender(events);
+    for n in 0..3u8 {
+        messaging
+            .send_message(&neighbour, &[n], &routing_table)
+            .unwrap();
+    }
+
+    let queue = messaging.outbox.get(&neighbour).unwrap();
+    assert_eq!(queue.len(), 2);
+    assert!(matches!(&queue[0].message, Message::UserMessage(content) if content == &[1]));
+    assert_eq!(
+        node_rx.try_recv().unwrap(),
+        Event::OutboxOverflow {
+            peer: neighbour,
+            policy: OverflowPolicy::DropOld
+        }
+    );
+    let snapshot = messaging.metrics.snapshot();
+    assert_eq!(snapshot.outbox_depth, 2);
+    assert_eq!(snapshot.outbox_overflows, 1);
+    assert_eq!(snapshot.dropped_messages, 1);
+}
+
+#[test]
+fn test_user_messages_are_shed_over_memory_budget() {
+    let neighbour = NodeId::from(Hash::new("neighbour".as_bytes()));
+    let mut routing_table = RoutingTable::default();
+    routing_table.add_direct_connection(&neighbour);
+
+    let budget = MemoryBudget::new(1024);
+    let (events, node_rx) = crossbeam_channel::unbounded();
+    let mut messaging = Messaging::new();
+    messaging
+        .set_budget(budget.clone())
+        .set_event_sender(events);
+    messaging
+        .send_message(&neighbour, &[1], &routing_table)
+        .unwrap();
+    let queued = budget.used_by(Resource::Outboxes);
+    assert_eq!(queued, messaging.outbox.size_in_bytes());
+    assert!(queued > 0);
+
+    // Application messages are shed while other state is over the budget
+    budget.set_used(Resource::ConflictSets, 1024);
+    messaging
+        .send_message(&neighbour, &[2], &routing_table)
+        .unwrap();
+    assert_eq!(messaging.outbox.peer_depth(&neighbour), 1);
+    assert_eq!(
+        node_rx.try_recv().unwrap(),
+        Event::ResourceExhausted {
+            resource: Resource::Outboxes,
+            used: 1024 + queued,
+            limit: 1024
+        }
+    );
+    // Consensus traffic still goes through
+    let complete = messaging
+        .envelope(neighbour, Message::CompleteRound)
+        .unwrap();
+    assert!(messaging
+        .enqueue(neighbour, complete, &routing_table)
+        .is_some());
+
+    let _ = messaging.outbox.take_all();
+    messaging.outbox_changed();
+    assert_eq!(budget.used_by(Resource::Outboxes), 0);
+}
+
+#[test]
+fn test_unsent_message_failure_is_reported() {
+    use super::config::SendPolicy;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    let peer = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 5000);
+    let (events, node_rx) = crossbeam_channel::unbounded();
+    let mut config = SendConfig::default();
+    config.set(MessageClass::Consensus, SendPolicy::new(60_000, 1));
+    let mut messaging = Messaging::new();
+    messaging.set_send_config(&config).set_event_sender(events);
+
+    let token = messaging
+        .tokens
+        .allocate(MessageClass::Consensus, peer, Instant::now());
+    let msg = Bytes::from_static(b"round");
+    messaging
+        .handle_unsent_message(msg.clone(), token, peer)
+        .unwrap();
+    assert_eq!(messaging.pending_messages.len(), 1);
+    assert!(node_rx.try_recv().is_err());
+
+    messaging.handle_unsent_message(msg, token, peer).unwrap();
+    assert_eq!(messaging.pending_messages.len(), 1);
+    assert_eq!(
+        node_rx.try_recv().unwrap(),
+        Event::SendFailed {
+            peer,
+            class: MessageClass::Consensus
+        }
+    );
+    assert_eq!(messaging.metrics.snapshot().dropped_messages, 1);
+}
+
+#[test]
+fn test_gossip_is_spread_once() {
+    let neighbours = (0..10u8)
+        .map(|n| NodeId::from(Hash::new(&[n])))
+        .collect::<Vec<_>>();
+    let mut routing_table = RoutingTable::default();
+    for neighbour in &neighbours {
+        routing_table.add_direct_connection(neighbour);
+    }
+    let (node_tx, node_rx) = crossbeam_channel::unbounded();
+    let mut messaging = Messaging::new();
+    messaging.set_gossip_config(&GossipConfig::new(2, 3));
+
+    assert_eq!(messaging.gossip(b"block", &routing_table), 3);
+    assert_eq!(messaging.outbox.depth(), 3);
+
+    // Our own gossip coming back is a duplicate, not a new message
+    messaging
+        .handle_gossip(
+            b"block".to_vec(),
+            Some(&neighbours[0]),
+            &routing_table,
+            &node_tx,
+        )
+        .unwrap();
+    assert!(node_rx.try_recv().is_err());
+    assert_eq!(messaging.fanout.duplicate_ratio(), Some(1.0));
+
+    let _ = messaging.outbox.take_all();
+    messaging
+        .handle_gossip(
+            b"tx".to_vec(),
+            Some(&neighbours[0]),
+            &routing_table,
+            &node_tx,
+        )
+        .unwrap();
+    assert_eq!(
+        node_rx.try_recv().unwrap(),
+        Event::NewMessage(b"tx".to_vec())
+    );
+    assert_eq!(messaging.outbox.depth(), 3);
+    assert!(messaging.outbox.get(&neighbours[0]).is_none());
+}
+
+#[test]
+fn test_small_responses_are_piggybacked() {
+    use consensus::time::ManualClock;
+
+    let neighbour = NodeId::from(Hash::new("neighbour".as_bytes()));
+    let response = |sender| Message::DagConsensusResponse {
+        sender,
+        hash: Hash::new("tx".as_bytes()).into(),
+        strongly_preferred: true,
+    };
+    let envelope = |message| Envelope::new(neighbour, message, 1);
+
+    // Disabled by default
+    let mut messaging = Messaging::new();
+    assert!(messaging
+        .piggyback(neighbour, envelope(response(neighbour)))
+        .is_some());
+
+    messaging.set_piggyback_config(&PiggybackConfig::new(Duration::from_secs(60), 256));
+    assert!(messaging
+        .piggyback(neighbour, envelope(response(neighbour)))
+        .is_none());
+    assert!(messaging
+        .piggyback(neighbour, envelope(Message::CompleteRound))
+        .is_some());
+    let large = Message::BatchedConsensusResponse {
+        sender: neighbour,
+        data: vec![(Hash::default().into(), true); 64],
+    };
+    assert!(messaging.piggyback(neighbour, envelope(large)).is_some());
+    assert!(messaging.expired_piggybacks().is_empty());
+    assert_eq!(messaging.take_piggybacked(&neighbour).len(), 1);
+    assert!(messaging.take_piggybacked(&neighbour).is_empty());
+
+    // Responses without traffic to carry them go on their own past the delay
+    let clock = ManualClock::new();
+    messaging
+        .set_piggyback_config(&PiggybackConfig::new(Duration::from_millis(10), 256))
+        .set_clock(Arc::new(clock.clone()));
+    assert!(messaging
+        .piggyback(neighbour, envelope(response(neighbour)))
+        .is_none());
+    clock.advance(Duration::from_millis(9));
+    assert!(messaging.expired_piggybacks().is_empty());
+    clock.advance(Duration::from_millis(1));
+    match &messaging.expired_piggybacks()[..] {
+        [(next_hop, held)] => {
+            assert_eq!(*next_hop, neighbour);
+            assert_eq!(held.len(), 1);
+        }
+        other => panic!("Unexpected {:?}", other),
+    }
+    assert!(messaging.piggybacked.is_empty());
+}
+
+#[test]
+fn test_paused_node_leaves_consensus_queries_out() {
+    let our_hash = NodeId::from(Hash::new("us".as_bytes()));
+    let neighbour = NodeId::from(Hash::new("neighbour".as_bytes()));
+    let mut routing_table = RoutingTable::default();
+    routing_table.add_direct_connection(&neighbour);
+    let participation = Participation::default();
+    let mut messaging = Messaging::new();
+    let _ = messaging.set_participation(participation.clone());
+
+    assert!(participation.pause());
+    let query = Envelope::new(our_hash, Message::CompleteRound, 1);
+    assert!(messaging.route(query, &our_hash, &routing_table).is_none());
+    let user = Envelope::new(our_hash, Message::UserMessage(vec![]), 1);
+    assert!(messaging.route(user, &our_hash, &routing_table).is_some());
+    // Rounds of other nodes are still relayed
+    let relayed = Envelope::new(neighbour, Message::CompleteRound, 1);
+    assert!(messaging
+        .route(relayed, &our_hash, &routing_table)
+        .is_none());
+    assert_eq!(messaging.outbox.get(&neighbour).unwrap().len(), 1);
+
+    assert!(participation.resume());
+    let query = Envelope::new(our_hash, Message::CompleteRound, 1);
+    assert!(matches!(
+        messaging.route(query, &our_hash, &routing_table),
+        Some(Message::CompleteRound)
+    ));
+}
+
+#[test]
+fn test_large_messages_are_sent_in_fragments() {
+    use super::config::TransportKind;
+
+    /// Transport recording what we send
+    #[derive(Default)]
+    struct Recorder {
+        sent: Vec<(Bytes, u64)>,
+    }
+
+    impl Transport for Recorder {
+        fn kind(&self) -> TransportKind {
+            TransportKind::Tcp
+        }
+
+        fn our_addr(&mut self) -> Result<SocketAddr, P2pError> {
+            Ok(([127, 0, 0, 1], 9000).into())
+        }
+
+        fn connect_to(&mut self, _peer: SocketAddr) {}
+
+        fn disconnect_from(&mut self, _peer: SocketAddr) {}
+
+        fn send(&mut self, _peer: SocketAddr, msg: Bytes, token: u64) {
+            self.sent.push((msg, token));
+        }
+    }
+
+    let peer = SocketAddr::from(([127, 0, 0, 1], 5000));
+    let config = FragmentConfig::new(1024, Duration::from_secs(30), 1 << 20);
+    let mut sender = Messaging::new();
+    let _ = sender.set_fragment_config(&config);
+    let mut transport = Recorder::default();
+    let large = Message::UserMessage(vec![7; 4000]);
+    sender.send(&mut transport, peer, &large);
+    sender.send(&mut transport, peer, &Message::CompleteRound);
+    assert_eq!(transport.sent.len(), 5);
+    assert_eq!(sender.tokens.in_flight(), 5);
+
+    let mut receiver = Messaging::new();
+    let _ = receiver.set_fragment_config(&config);
+    let mut fragments = transport
+        .sent
+        .iter()
+        .filter_map(|(bytes, _)| match Message::decode(bytes, 2048).unwrap() {
+            Message::Fragment(fragment) => Some(fragment),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(fragments.len(), 4);
+    fragments.swap(0, 3);
+    let last = fragments.pop().unwrap();
+    for fragment in fragments {
+        assert!(receiver
+            .handle_fragment(&Peer::Node(peer), fragment)
+            .unwrap()
+            .is_none());
     }
+    assert!(matches!(
+        receiver.handle_fragment(&Peer::Node(peer), last).unwrap(),
+        Some(Message::UserMessage(content)) if content == vec![7; 4000]
+    ));
 }
@@ -0,0 +1,123 @@
+use super::message::Envelope;
+
+/// Way a message travels through the node
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Direction {
+    /// Received from a peer, whether for us or to be relayed
+    Inbound,
+    /// Sent by us or relayed to the next hop
+    Outbound,
+}
+
+/// Message as seen by middleware
+#[derive(Debug)]
+pub struct MessageCtx {
+    pub direction: Direction,
+    pub envelope: Envelope,
+}
+
+/// Outcome of running a middleware on a message
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Action {
+    /// Pass the message on unchanged
+    Continue,
+    /// Stop processing the message
+    Drop,
+    /// Pass the message on as changed by the middleware
+    Mutate,
+}
+
+/// Hook run on every message going through the pipeline
+pub type Middleware = Box<dyn Fn(&mut MessageCtx) -> Action + Send + Sync>;
+
+/// Inbound and outbound middleware, run in order of registration
+#[derive(Default)]
+pub struct Pipeline {
+    inbound: Vec<Middleware>,
+    outbound: Vec<Middleware>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_inbound(&mut self, middleware: Middleware) -> &mut Self {
+        self.inbound.push(middleware);
+        self
+    }
+
+    pub fn add_outbound(&mut self, middleware: Middleware) -> &mut Self {
+        self.outbound.push(middleware);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inbound.is_empty() && self.outbound.is_empty()
+    }
+
+    /// Run the middleware of a direction on an envelope, returning it as
+    /// possibly mutated, or `None` if a middleware dropped it
+    pub fn run(&self, direction: Direction, envelope: Envelope) -> Option<Envelope> {
+        let middleware = match direction {
+            Direction::Inbound => &self.inbound,
+            Direction::Outbound => &self.outbound,
+        };
+        let mut ctx = MessageCtx {
+            direction,
+            envelope,
+        };
+        for hook in middleware {
+            match hook(&mut ctx) {
+                Action::Continue => {}
+                Action::Mutate => log::trace!(
+                    "Middleware changed {:?} message {:?}",
+                    direction,
+                    ctx.envelope.id
+                ),
+                Action::Drop => return None,
+            }
+        }
+        Some(ctx.envelope)
+    }
+}
+
+impl std::fmt::Debug for Pipeline {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Pipeline")
+            .field("inbound", &self.inbound.len())
+            .field("outbound", &self.outbound.len())
+            .finish()
+    }
+}
+
+#[test]
+fn test_pipeline_runs_in_order() {
+    use super::message::Message;
+    use consensus::NodeId;
+
+    let mut pipeline = Pipeline::new();
+    pipeline
+        .add_inbound(Box::new(|ctx| {
+            ctx.envelope.hops_left = 1;
+            Action::Mutate
+        }))
+        .add_inbound(Box::new(|ctx| match ctx.envelope.message {
+            Message::CompleteRound => Action::Drop,
+            _ => Action::Continue,
+        }));
+
+    let envelope = Envelope::new(NodeId::default(), Message::UserMessage(vec![]), 5);
+    let passed = pipeline.run(Direction::Inbound, envelope.clone()).unwrap();
+    assert_eq!(passed.hops_left, 1);
+    assert_eq!(
+        pipeline
+            .run(Direction::Outbound, envelope)
+            .unwrap()
+            .hops_left,
+        5
+    );
+
+    let dropped = Envelope::new(NodeId::default(), Message::CompleteRound, 5);
+    assert!(pipeline.run(Direction::Inbound, dropped).is_none());
+}
@@ -1,6 +1,33 @@
+pub mod address_book;
+pub mod admin;
+pub mod admission;
+pub mod auth;
+pub mod benchmark;
+pub mod bootstrap;
+pub mod builder;
+pub mod capacity;
+pub mod codec;
 pub mod config;
 pub mod connection;
+pub mod convergence;
 pub mod event;
+pub mod fragment;
+pub mod gossip;
+#[cfg(feature = "rpc")]
+pub mod graphql;
 pub mod identity;
+pub mod lifecycle;
 pub mod message;
 pub mod messaging;
+pub mod middleware;
+pub mod nat;
+pub mod outbox;
+pub mod peers;
+pub mod protocol;
+pub mod ratelimit;
+#[cfg(feature = "rpc")]
+pub mod rpc;
+pub mod status;
+pub mod subscription;
+pub mod telemetry;
+pub mod tokens;
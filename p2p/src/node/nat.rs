@@ -0,0 +1,227 @@
+//! Traversal of NATs, for nodes unable to accept connections.
+//!
+//! Peers tell each other the address they see them connecting from. Behind a
+//! NAT, that is the public address the NAT maps the node to rather than the
+//! one it listens at, so once enough peers agree on it the node knows both
+//! its external address and that it is behind a NAT.
+//!
+//! Two nodes behind NATs can't connect to one another, but both can reach a
+//! public peer. On request, that rendezvous peer sends each of them the
+//! public address of the other, and both connect at once: each NAT sees the
+//! traffic of the other side as the reply to its own. Should punching fail,
+//! the rendezvous relays their messages instead, as the next hop of the
+//! agent message routing between them.
+
+use super::{config::NatConfig, connection::RoutingTable, message::Message};
+use consensus::NodeId;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+/// Peers that must report the same address before we take it as ours
+pub const MIN_CONFIRMATIONS: usize = 2;
+
+/// Discovery of our external address, and hole punches to peers behind NATs
+#[derive(Clone, Debug)]
+pub struct NatTraversal {
+    /// Whether we coordinate hole punches, and relay when they fail
+    relay: bool,
+    punch_timeout: Duration,
+    /// Address each peer sees us at
+    observed: HashMap<NodeId, SocketAddr>,
+    /// Peers being punched to, with the rendezvous coordinating it
+    punching: HashMap<NodeId, (NodeId, Instant)>,
+}
+
+impl Default for NatTraversal {
+    fn default() -> Self {
+        Self::from_config(&NatConfig::default())
+    }
+}
+
+impl NatTraversal {
+    pub fn new(relay: bool, punch_timeout: Duration) -> Self {
+        Self {
+            relay,
+            punch_timeout,
+            observed: HashMap::new(),
+            punching: HashMap::new(),
+        }
+    }
+
+    pub fn from_config(config: &NatConfig) -> Self {
+        Self::new(config.relay(), config.punch_timeout())
+    }
+
+    pub fn is_relay(&self) -> bool {
+        self.relay
+    }
+
+    /// Record the address `reporter` sees us at
+    pub fn observed(&mut self, reporter: NodeId, addr: SocketAddr) {
+        let _ = self.observed.insert(reporter, addr);
+    }
+
+    /// Forget what a peer disconnected from us reported
+    pub fn forget(&mut self, peer: &NodeId) {
+        let _ = self.observed.remove(peer);
+    }
+
+    /// Address most peers see us at, if at least [`MIN_CONFIRMATIONS`] of
+    /// them agree on it
+    pub fn external_addr(&self) -> Option<SocketAddr> {
+        let mut votes = HashMap::<SocketAddr, usize>::new();
+        for addr in self.observed.values() {
+            *votes.entry(*addr).or_default() += 1;
+        }
+        votes
+            .into_iter()
+            .filter(|(_, count)| *count >= MIN_CONFIRMATIONS)
+            .max_by_key(|(addr, count)| (*count, *addr))
+            .map(|(addr, _)| addr)
+    }
+
+    /// Whether peers reach us somewhere else than where we listen, unknown
+    /// until our external address is
+    pub fn is_behind_nat(&self, listen_addr: &SocketAddr) -> Option<bool> {
+        self.external_addr().map(|addr| addr != *listen_addr)
+    }
+
+    pub fn is_punching(&self, peer: &NodeId) -> bool {
+        self.punching.contains_key(peer)
+    }
+
+    /// Track a hole punch to `peer` coordinated by `rendezvous`. Returns
+    /// false if one is in progress already.
+    pub fn start_punch(&mut self, peer: NodeId, rendezvous: NodeId, now: Instant) -> bool {
+        if self.is_punching(&peer) {
+            return false;
+        }
+        let _ = self.punching.insert(peer, (rendezvous, now));
+        true
+    }
+
+    /// As a rendezvous, the messages telling `source` and `target` to punch
+    /// to each other at the addresses we see them at. Nothing is sent unless
+    /// we relay, or while `target` isn't connected to us.
+    pub fn coordinate(
+        &self,
+        source: NodeId,
+        source_addr: SocketAddr,
+        target: NodeId,
+        target_addr: Option<SocketAddr>,
+    ) -> Vec<(SocketAddr, Message)> {
+        match target_addr {
+            Some(target_addr) if self.relay && source != target => vec![
+                (
+                    source_addr,
+                    Message::Punch {
+                        peer: target,
+                        addr: target_addr,
+                    },
+                ),
+                (
+                    target_addr,
+                    Message::Punch {
+                        peer: source,
+                        addr: source_addr,
+                    },
+                ),
+            ],
+            _ => vec![],
+        }
+    }
+
+    /// Stop tracking a hole punch once we connected to the peer. Returns
+    /// whether one was in progress.
+    pub fn punched(&mut self, peer: &NodeId) -> bool {
+        self.punching.remove(peer).is_some()
+    }
+
+    /// Give up on the hole punches that took too long, routing messages to
+    /// their peers through the rendezvous instead. Returns the peers now
+    /// relayed.
+    pub fn expire_punches(
+        &mut self,
+        routing_table: &mut RoutingTable,
+        now: Instant,
+    ) -> Vec<NodeId> {
+        let timeout = self.punch_timeout;
+        let expired = self
+            .punching
+            .iter()
+            .filter(|(_, (_, started))| now.saturating_duration_since(*started) >= timeout)
+            .map(|(peer, (rendezvous, _))| (*peer, *rendezvous))
+            .collect::<Vec<_>>();
+        expired
+            .into_iter()
+            .map(|(peer, rendezvous)| {
+                let _ = self.punching.remove(&peer);
+                log::info!(
+                    "Hole punch to {:?} failed, relaying through {:?}",
+                    peer,
+                    rendezvous
+                );
+                let _ = routing_table.update_route(&peer, &rendezvous, 2);
+                peer
+            })
+            .collect()
+    }
+}
+
+#[test]
+fn test_nat_traversal() {
+    use crypto::hash::Hash;
+
+    let id = |name: &str| NodeId::from(Hash::new(name.as_bytes()));
+    let (a, b, c) = (id("a"), id("b"), id("c"));
+    let listen_addr = SocketAddr::from(([10, 0, 0, 2], 5000));
+    let public_addr = SocketAddr::from(([203, 0, 113, 7], 41000));
+
+    // A single report isn't trusted
+    let mut nat = NatTraversal::new(true, Duration::from_secs(5));
+    nat.observed(a, public_addr);
+    assert_eq!(nat.external_addr(), None);
+    assert_eq!(nat.is_behind_nat(&listen_addr), None);
+    nat.observed(b, public_addr);
+    nat.observed(c, listen_addr);
+    assert_eq!(nat.external_addr(), Some(public_addr));
+    assert_eq!(nat.is_behind_nat(&listen_addr), Some(true));
+    nat.forget(&a);
+    assert_eq!(nat.external_addr(), None);
+
+    // The rendezvous tells both sides where to punch to
+    let other_addr = SocketAddr::from(([198, 51, 100, 3], 52000));
+    let punches = nat.coordinate(a, public_addr, b, Some(other_addr));
+    assert!(matches!(
+        punches[..],
+        [
+            (to_a, Message::Punch { peer: p1, addr: addr1 }),
+            (to_b, Message::Punch { peer: p2, addr: addr2 }),
+        ] if to_a == public_addr && p1 == b && addr1 == other_addr
+            && to_b == other_addr && p2 == a && addr2 == public_addr
+    ));
+    assert!(nat.coordinate(a, public_addr, b, None).is_empty());
+    let not_relay = NatTraversal::new(false, Duration::from_secs(5));
+    assert!(not_relay
+        .coordinate(a, public_addr, b, Some(other_addr))
+        .is_empty());
+
+    // A failed punch falls back to relaying through the rendezvous
+    let start = Instant::now();
+    let mut routing_table = RoutingTable::default();
+    routing_table.add_direct_connection(&c);
+    assert!(nat.start_punch(a, c, start));
+    assert!(!nat.start_punch(a, c, start));
+    assert!(nat.start_punch(b, c, start));
+    assert!(nat.punched(&b));
+    assert!(nat
+        .expire_punches(&mut routing_table, start + Duration::from_secs(4))
+        .is_empty());
+    assert_eq!(
+        nat.expire_punches(&mut routing_table, start + Duration::from_secs(5)),
+        vec![a]
+    );
+    assert!(!nat.is_punching(&a));
+    assert_eq!(routing_table.known_route(&a), Some((c, 2)));
+}
@@ -0,0 +1,297 @@
+use super::{config::OverflowPolicy, connection::RoutingTable, message::Envelope};
+use bytes::Bytes;
+use consensus::NodeId;
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+
+/// Outcome of queueing an envelope in the outbox
+#[derive(Debug)]
+pub enum Queued {
+    /// Queued for the requested next hop
+    Accepted,
+    /// Queue was full, the envelope was dropped
+    DroppedNew(Envelope),
+    /// Queue was full, its oldest envelope was dropped to make room
+    DroppedOld(Envelope),
+    /// Queue was full, the envelope was queued for another neighbour
+    Rerouted(NodeId),
+}
+
+/// Envelopes waiting to be sent, in a bounded queue per next hop
+#[derive(Debug)]
+pub struct Outbox {
+    queues: HashMap<NodeId, VecDeque<Envelope>>,
+    capacity: usize,
+    policy: OverflowPolicy,
+    /// Estimated memory taken up by queued envelopes
+    bytes: usize,
+}
+
+fn size_of(envelope: &Envelope) -> usize {
+    bincode::serialized_size(envelope).unwrap_or_default() as usize
+}
+
+impl Default for Outbox {
+    fn default() -> Self {
+        Self::new(usize::MAX, OverflowPolicy::default())
+    }
+}
+
+impl Outbox {
+    pub fn new(capacity: usize, policy: OverflowPolicy) -> Self {
+        Self {
+            queues: HashMap::new(),
+            capacity: capacity.max(1),
+            policy,
+            bytes: 0,
+        }
+    }
+
+    pub fn policy(&self) -> OverflowPolicy {
+        self.policy
+    }
+
+    /// Queue an envelope for `next_hop`, applying the overflow policy if its
+    /// queue is full
+    pub fn push(
+        &mut self,
+        next_hop: NodeId,
+        envelope: Envelope,
+        routing_table: &RoutingTable,
+    ) -> Queued {
+        let size = size_of(&envelope);
+        let queued = self.queue(next_hop, envelope, routing_table);
+        self.bytes += size;
+        if let Queued::DroppedNew(dropped) | Queued::DroppedOld(dropped) = &queued {
+            self.bytes -= size_of(dropped);
+        }
+        queued
+    }
+
+    fn queue(
+        &mut self,
+        next_hop: NodeId,
+        envelope: Envelope,
+        routing_table: &RoutingTable,
+    ) -> Queued {
+        if !self.is_full(&next_hop) {
+            self.queues.entry(next_hop).or_default().push_back(envelope);
+            return Queued::Accepted;
+        }
+        match self.policy {
+            OverflowPolicy::DropNew => Queued::DroppedNew(envelope),
+            OverflowPolicy::DropOld => {
+                let queue = self.queues.entry(next_hop).or_default();
+                let oldest = queue.pop_front();
+                queue.push_back(envelope);
+                match oldest {
+                    Some(oldest) => Queued::DroppedOld(oldest),
+                    None => Queued::Accepted,
+                }
+            }
+            OverflowPolicy::Reroute => {
+                let alternate = routing_table
+                    .neighbours()
+                    .filter(|neighbour| **neighbour != next_hop && !self.is_full(neighbour))
+                    .min_by_key(|neighbour| self.peer_depth(neighbour))
+                    .copied();
+                match alternate {
+                    Some(alternate) => {
+                        self.queues
+                            .entry(alternate)
+                            .or_default()
+                            .push_back(envelope);
+                        Queued::Rerouted(alternate)
+                    }
+                    None => Queued::DroppedNew(envelope),
+                }
+            }
+        }
+    }
+
+    /// Take the envelopes queued for a next hop
+    pub fn take(&mut self, next_hop: &NodeId) -> Vec<Envelope> {
+        let taken = self
+            .queues
+            .remove(next_hop)
+            .map(Vec::from)
+            .unwrap_or_default();
+        self.bytes -= taken.iter().map(size_of).sum::<usize>();
+        taken
+    }
+
+    /// Take the envelopes queued for every next hop
+    pub fn take_all(&mut self) -> Vec<(NodeId, Vec<Envelope>)> {
+        self.bytes = 0;
+        self.queues
+            .drain()
+            .map(|(next_hop, queue)| (next_hop, Vec::from(queue)))
+            .collect()
+    }
+
+    pub fn get(&self, next_hop: &NodeId) -> Option<&VecDeque<Envelope>> {
+        self.queues.get(next_hop)
+    }
+
+    /// Envelopes queued for a next hop
+    pub fn peer_depth(&self, next_hop: &NodeId) -> usize {
+        self.queues.get(next_hop).map_or(0, VecDeque::len)
+    }
+
+    /// Envelopes queued for all next hops
+    pub fn depth(&self) -> usize {
+        self.queues.values().map(VecDeque::len).sum()
+    }
+
+    /// Estimated memory taken up by envelopes queued for all next hops
+    pub fn size_in_bytes(&self) -> usize {
+        self.bytes
+    }
+
+    fn is_full(&self, next_hop: &NodeId) -> bool {
+        self.peer_depth(next_hop) >= self.capacity
+    }
+}
+
+/// Serialized messages QUIC failed to send, waiting to be retried in a
+/// bounded queue per peer
+#[derive(Debug)]
+pub struct RetryQueue {
+    queues: HashMap<SocketAddr, VecDeque<(Bytes, u64)>>,
+    capacity: usize,
+    policy: OverflowPolicy,
+}
+
+impl Default for RetryQueue {
+    fn default() -> Self {
+        Self::new(usize::MAX, OverflowPolicy::default())
+    }
+}
+
+impl RetryQueue {
+    pub fn new(capacity: usize, policy: OverflowPolicy) -> Self {
+        Self {
+            queues: HashMap::new(),
+            capacity: capacity.max(1),
+            policy,
+        }
+    }
+
+    /// Queue a message to retry, returning the message dropped if the queue
+    /// of `peer` is full. Retries go to a given peer, so rerouting drops the
+    /// new message instead.
+    pub fn push(&mut self, peer: SocketAddr, msg: Bytes, token: u64) -> Option<(Bytes, u64)> {
+        let queue = self.queues.entry(peer).or_default();
+        if queue.len() < self.capacity {
+            queue.push_back((msg, token));
+            return None;
+        }
+        match self.policy {
+            OverflowPolicy::DropOld => {
+                let oldest = queue.pop_front();
+                queue.push_back((msg, token));
+                oldest
+            }
+            OverflowPolicy::DropNew | OverflowPolicy::Reroute => Some((msg, token)),
+        }
+    }
+
+    /// Take every message to retry, oldest first for each peer
+    pub fn drain(&mut self) -> Vec<(Bytes, u64, SocketAddr)> {
+        self.queues
+            .drain()
+            .flat_map(|(peer, queue)| {
+                queue
+                    .into_iter()
+                    .map(move |(msg, token)| (msg, token, peer))
+            })
+            .collect()
+    }
+
+    /// Messages waiting to be retried for all peers
+    pub fn len(&self) -> usize {
+        self.queues.values().map(VecDeque::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queues.values().all(VecDeque::is_empty)
+    }
+}
+
+#[test]
+fn test_outbox_overflow_policies() {
+    use super::message::Message;
+    use crypto::hash::Hash;
+
+    let full = NodeId::from(Hash::new("full".as_bytes()));
+    let other = NodeId::from(Hash::new("other".as_bytes()));
+    let mut routing_table = RoutingTable::default();
+    routing_table.add_direct_connection(&full);
+    routing_table.add_direct_connection(&other);
+    let first = Envelope::new(full, Message::CompleteRound, 1);
+    let second = Envelope::new(full, Message::CompleteRound, 1);
+
+    let mut outbox = Outbox::new(1, OverflowPolicy::DropNew);
+    let _ = outbox.push(full, first.clone(), &routing_table);
+    assert!(matches!(
+        outbox.push(full, second.clone(), &routing_table),
+        Queued::DroppedNew(dropped) if dropped.id == second.id
+    ));
+
+    let mut outbox = Outbox::new(1, OverflowPolicy::DropOld);
+    let _ = outbox.push(full, first.clone(), &routing_table);
+    assert!(matches!(
+        outbox.push(full, second.clone(), &routing_table),
+        Queued::DroppedOld(dropped) if dropped.id == first.id
+    ));
+    assert_eq!(outbox.get(&full).unwrap()[0].id, second.id);
+    // Dropped envelopes no longer take up memory
+    assert_eq!(outbox.size_in_bytes(), size_of(&second));
+    let _ = outbox.take(&full);
+    assert_eq!(outbox.size_in_bytes(), 0);
+
+    let mut outbox = Outbox::new(1, OverflowPolicy::Reroute);
+    assert!(matches!(
+        outbox.push(full, first.clone(), &routing_table),
+        Queued::Accepted
+    ));
+    assert!(matches!(
+        outbox.push(full, second.clone(), &routing_table),
+        Queued::Rerouted(alternate) if alternate == other
+    ));
+    assert!(matches!(
+        outbox.push(full, first, &routing_table),
+        Queued::DroppedNew(_)
+    ));
+    assert_eq!(outbox.depth(), 2);
+    assert_eq!(outbox.take(&full).len(), 1);
+    assert_eq!(outbox.depth(), 1);
+}
+
+#[test]
+fn test_retry_queue_is_bounded_per_peer() {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    let slow = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 5000);
+    let other = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 5001);
+    let mut retries = RetryQueue::new(2, OverflowPolicy::DropOld);
+    for token in 1..=2 {
+        assert!(retries.push(slow, Bytes::new(), token).is_none());
+    }
+    assert_eq!(retries.push(slow, Bytes::new(), 3).unwrap().1, 1);
+    assert!(retries.push(other, Bytes::new(), 4).is_none());
+    assert_eq!(retries.len(), 3);
+
+    let mut drained = retries.drain();
+    drained.sort_by_key(|(_, token, _)| *token);
+    let tokens = drained
+        .iter()
+        .map(|(_, token, _)| *token)
+        .collect::<Vec<_>>();
+    assert_eq!(tokens, vec![2, 3, 4]);
+    assert!(retries.is_empty());
+
+    let mut retries = RetryQueue::new(1, OverflowPolicy::Reroute);
+    let _ = retries.push(slow, Bytes::new(), 1);
+    assert_eq!(retries.push(slow, Bytes::new(), 2).unwrap().1, 2);
+}
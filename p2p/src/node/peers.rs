@@ -0,0 +1,313 @@
+use super::{
+    address_book::AddressBook,
+    capacity::{Capacity, CapacityAdvertisement},
+};
+use crate::error::DecodeError;
+use consensus::{network::CommonConsensusNetwork, sampling::VerifiableSample, NodeId, TxId};
+use crypto::signature::PrivateKey;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+
+const MAX_SCORE: i32 = 100;
+const MIN_SCORE: i32 = -100;
+/// Peers scoring below this are left out of consensus samples
+const EXCLUSION_SCORE: i32 = -20;
+const REWARD: i32 = 1;
+const PENALTY: i32 = 5;
+const OVERSIZED_PENALTY: i32 = 50;
+const FLOODING_PENALTY: i32 = 20;
+/// Transactions per second assumed of peers advertising no capacity
+const UNADVERTISED_TPS: u64 = 100;
+/// Most capacity a peer is sampled for, so that advertising a huge one
+/// doesn't draw every sample to it
+const MAX_SAMPLED_TPS: u64 = 10 * UNADVERTISED_TPS;
+
+#[derive(Debug, Default)]
+struct PeerSet {
+    book: AddressBook,
+    /// Kept across reconnections, so that misbehaving peers cannot reset
+    /// their score by reconnecting
+    scores: HashMap<NodeId, i32>,
+    /// Latest signed capacity of each peer, kept for audits
+    capacities: HashMap<NodeId, CapacityAdvertisement>,
+}
+
+/// Connected peers to sample for consensus queries, with a score per peer.
+///
+/// Clones share the same set, so a [`Connection`](super::connection::Connection)
+/// can keep it in sync with peers connecting and disconnecting while a
+/// consensus engine samples from it.
+#[derive(Clone, Debug, Default)]
+pub struct ConsensusPeers {
+    inner: Arc<RwLock<PeerSet>>,
+}
+
+impl ConsensusPeers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn connected(&self, peer: NodeId, addr: SocketAddr) {
+        let mut inner = self.inner.write().unwrap();
+        inner.book.insert(peer, addr);
+        let _ = inner.scores.entry(peer).or_insert(0);
+    }
+
+    pub fn disconnected(&self, peer: &NodeId) {
+        let _ = self.inner.write().unwrap().book.remove(peer);
+    }
+
+    pub fn contains(&self, peer: &NodeId) -> bool {
+        self.inner.read().unwrap().book.get(peer).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.read().unwrap().book.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.read().unwrap().book.is_empty()
+    }
+
+    pub fn score(&self, peer: &NodeId) -> Option<i32> {
+        self.inner.read().unwrap().scores.get(peer).copied()
+    }
+
+    /// Record a peer answering a consensus query in time
+    pub fn reward(&self, peer: &NodeId) {
+        self.adjust(peer, REWARD);
+    }
+
+    /// Record a peer failing to answer a consensus query, or answering it
+    /// with invalid data
+    pub fn penalize(&self, peer: &NodeId) {
+        self.adjust(peer, -PENALTY);
+    }
+
+    /// Record a peer sending data we could not decode, oversized data
+    /// costing it far more than malformed data
+    pub fn penalize_undecodable(&self, peer: &NodeId, error: &DecodeError) {
+        match error.is_oversized() {
+            true => self.adjust(peer, -OVERSIZED_PENALTY),
+            false => self.adjust(peer, -PENALTY),
+        }
+    }
+
+    /// Record a peer sending us messages faster than its rate limits allow
+    pub fn penalize_flooding(&self, peer: &NodeId) {
+        self.adjust(peer, -FLOODING_PENALTY);
+    }
+
+    /// Record the capacity `peer` advertised, verified to be signed by it.
+    /// Returns false if it isn't later than the one we have.
+    pub fn advertised(&self, peer: NodeId, advertisement: CapacityAdvertisement) -> bool {
+        let mut inner = self.inner.write().unwrap();
+        match inner.capacities.get(&peer) {
+            Some(latest) if latest.issued >= advertisement.issued => false,
+            _ => {
+                let _ = inner.capacities.insert(peer, advertisement);
+                true
+            }
+        }
+    }
+
+    pub fn capacity(&self, peer: &NodeId) -> Option<Capacity> {
+        self.inner
+            .read()
+            .unwrap()
+            .capacities
+            .get(peer)
+            .map(|advertisement| advertisement.capacity)
+    }
+
+    /// Signed statements of every peer that advertised a capacity
+    pub fn advertisements(&self) -> Vec<CapacityAdvertisement> {
+        self.inner
+            .read()
+            .unwrap()
+            .capacities
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    /// Largest batch to send `peer` at once, at most `max_batch_size` and
+    /// at most the transactions per second it validates
+    pub fn max_batch_size(&self, peer: &NodeId, max_batch_size: usize) -> usize {
+        match self.capacity(peer) {
+            Some(capacity) => max_batch_size.min(capacity.max_tps as usize).max(1),
+            None => max_batch_size,
+        }
+    }
+
+    fn adjust(&self, peer: &NodeId, delta: i32) {
+        let mut inner = self.inner.write().unwrap();
+        let score = inner.scores.entry(*peer).or_insert(0);
+        *score = (*score + delta).clamp(MIN_SCORE, MAX_SCORE);
+    }
+    /// Peers other than `node_id` that may be sampled, in no particular
+    /// order
+    pub fn candidates(&self, node_id: &NodeId) -> Vec<NodeId> {
+        let inner = self.inner.read().unwrap();
+        inner
+            .book
+            .peers()
+            .filter(|peer| inner.sampling_weight(peer, node_id) > 0)
+            .collect()
+    }
+
+    /// Draw `k` of the candidates for a round of queries of `tx_id`, from the
+    /// VRF output of the round under `private_key`, so that the peers
+    /// queried can check we didn't pick them. Unlike
+    /// [`get_nodes_except_one`](CommonConsensusNetwork::get_nodes_except_one),
+    /// peers are drawn regardless of their subnet and capacity, which the
+    /// peers checking the sample can't know of.
+    pub fn verifiable_sample(
+        &self,
+        private_key: &PrivateKey,
+        node_id: &NodeId,
+        tx_id: TxId,
+        attempt: u64,
+        k: usize,
+    ) -> VerifiableSample {
+        VerifiableSample::draw(private_key, tx_id, attempt, self.candidates(node_id), k)
+    }
+}
+
+impl PeerSet {
+    /// Weight of `peer` in samples drawn by `node_id`, in proportion to the
+    /// capacity it advertises, and zero if it scores too low
+    fn sampling_weight(&self, peer: &NodeId, node_id: &NodeId) -> u64 {
+        let eligible = peer != node_id
+            && self
+                .scores
+                .get(peer)
+                .is_none_or(|score| *score >= EXCLUSION_SCORE);
+        match self.capacities.get(peer) {
+            _ if !eligible => 0,
+            Some(advertisement) => advertisement.capacity.max_tps.min(MAX_SAMPLED_TPS),
+            None => UNADVERTISED_TPS,
+        }
+    }
+}
+
+impl CommonConsensusNetwork for ConsensusPeers {
+    /// Sample connected peers spread over subnets in proportion to the
+    /// capacity they advertise, leaving out the ones scoring too low
+    fn get_nodes_except_one(&self, k: u64, node_id: NodeId) -> Vec<NodeId> {
+        let inner = self.inner.read().unwrap();
+        inner
+            .book
+            .diverse_sample_weighted(k as usize, |peer| inner.sampling_weight(peer, &node_id))
+    }
+}
+
+#[test]
+fn test_consensus_peers_follow_connections_and_scores() {
+    use super::connection::Connection;
+    use crypto::hash::Hash;
+
+    let connection = Connection::new();
+    let peers = connection.consensus_peers().clone();
+    let peer = |i: u8| NodeId::from(Hash::new(&[i]));
+    for i in 0..3 {
+        connection
+            .consensus_peers()
+            .connected(peer(i), SocketAddr::from(([10, i, 0, 1], 5000)));
+    }
+    assert_eq!(peers.len(), 3);
+    assert_eq!(peers.get_nodes_except_one(10, peer(0)).len(), 2);
+
+    for _ in 0..5 {
+        peers.penalize(&peer(1));
+    }
+    assert_eq!(peers.score(&peer(1)), Some(-25));
+    assert_eq!(peers.get_nodes_except_one(10, peer(0)), vec![peer(2)]);
+
+    assert_eq!(peers.candidates(&peer(0)), vec![peer(2)]);
+
+    // Reconnecting does not reset the score
+    peers.disconnected(&peer(1));
+    assert!(!peers.contains(&peer(1)));
+    peers.connected(peer(1), SocketAddr::from(([10, 1, 0, 1], 5000)));
+    assert_eq!(peers.score(&peer(1)), Some(-25));
+    for _ in 0..5 {
+        peers.reward(&peer(1));
+    }
+    assert_eq!(peers.get_nodes_except_one(10, peer(0)).len(), 2);
+
+    peers.penalize_undecodable(&peer(2), &DecodeError::Malformed(String::new()));
+    assert_eq!(peers.score(&peer(2)), Some(-PENALTY));
+    peers.penalize_undecodable(&peer(2), &DecodeError::TooLarge { size: 1, limit: 0 });
+    assert_eq!(peers.score(&peer(2)), Some(-PENALTY - OVERSIZED_PENALTY));
+}
+
+#[test]
+fn test_sampling_follows_advertised_capacity() {
+    use super::{capacity::StorageMode, identity::Identity};
+    use crypto::hash::Hash;
+    use std::time::Duration;
+
+    let peers = ConsensusPeers::new();
+    let identities = (0..3).map(|_| Identity::new()).collect::<Vec<_>>();
+    let ids = identities
+        .iter()
+        .map(|identity| identity.get_our_hash().unwrap())
+        .collect::<Vec<_>>();
+    for (i, id) in ids.iter().enumerate() {
+        peers.connected(*id, SocketAddr::from(([10, 0, 0, i as u8], 5000)));
+    }
+    let advertise = |i: usize, max_tps: u64, issued: u64| {
+        let capacity = Capacity {
+            max_tps,
+            bandwidth: 1 << 20,
+            storage: StorageMode::Archive,
+        };
+        let advertisement = CapacityAdvertisement::sign_at(
+            capacity,
+            Hash::default(),
+            &identities[i],
+            Duration::from_secs(issued),
+        )
+        .unwrap();
+        peers.advertised(ids[i], advertisement)
+    };
+
+    // A node validating nothing is never sampled, and gets single batches
+    assert!(advertise(1, 0, 10));
+    assert!(!advertise(1, 500, 10));
+    assert_eq!(peers.max_batch_size(&ids[1], 40), 1);
+    assert_eq!(peers.max_batch_size(&ids[2], 40), 40);
+    for _ in 0..20 {
+        assert!(!peers
+            .get_nodes_except_one(3, NodeId::default())
+            .contains(&ids[1]));
+    }
+
+    // Later statements replace earlier ones
+    assert!(advertise(1, 20, 11));
+    assert_eq!(peers.max_batch_size(&ids[1], 40), 20);
+    assert_eq!(peers.get_nodes_except_one(3, NodeId::default()).len(), 3);
+    assert_eq!(peers.advertisements().len(), 1);
+}
+
+#[test]
+fn test_queried_peers_verify_their_sample() {
+    use crypto::hash::Hash;
+
+    let peers = ConsensusPeers::new();
+    let peer = |i: u8| NodeId::from(Hash::new(&[i]));
+    for i in 0..10 {
+        peers.connected(peer(i), SocketAddr::from(([10, i, 0, 1], 5000)));
+    }
+    peers.penalize_undecodable(&peer(9), &DecodeError::TooLarge { size: 1, limit: 0 });
+    let key = PrivateKey::generate();
+    let tx_id = TxId::from(Hash::new("tx".as_bytes()));
+
+    let sample = peers.verifiable_sample(&key, &peer(0), tx_id, 0, 4);
+    assert_eq!(sample.peers.len(), 4);
+    assert_eq!(sample.candidates.len(), 8);
+    assert!(!sample.contains(&peer(0)) && !sample.contains(&peer(9)));
+    assert!(sample.verify(&key.public_key()));
+}
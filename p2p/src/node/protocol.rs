@@ -0,0 +1,143 @@
+//! Constants peers must agree on, and the versions and features they
+//! negotiate.
+//!
+//! Nodes built with a different hop limit, message size limit or hash
+//! domain still decode each other's messages, then drop, reject or
+//! misverify some of them. Rather than desynchronizing in ways that are
+//! hard to trace back, peers exchange the hash of their [`ProtocolParams`]
+//! when identifying, and refuse to peer with nodes whose hash differs.
+//!
+//! Before that, peers greet each other with a
+//! [`Message::Hello`](super::message::Message::Hello) stating their protocol
+//! version, the optional [`Features`] they support and the chain they follow.
+//! Peers on another chain or too old a version are refused; otherwise both
+//! sides speak the older of their versions and the features they share.
+
+use super::{
+    codec::DEFAULT_MAX_MESSAGE_SIZE, config::DEFAULT_HOP_LIMIT, connection::MAX_CONNECTION_LEN,
+};
+use crate::transport::tcp::MAX_FRAME_LEN;
+use consensus::{config::ConsensusConfig, transaction::MAX_MEMO_LEN};
+use crypto::{
+    hash::Hash,
+    merkle::{LEAF_TAG, NODE_TAG, ROOT_TAG},
+};
+use serde::{Deserialize, Serialize};
+
+/// Version of the wire protocol, bumped on changes
+pub const PROTOCOL_VERSION: u32 = 1;
+/// Oldest version of the wire protocol we still speak
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+/// Domain of the hash of the parameters
+const PARAMS_DOMAIN: &[u8] = b"dagchain:protocol-params";
+
+/// Protocol-affecting constants of this build
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ProtocolParams {
+    /// Hops relayed messages travel unless configured otherwise
+    pub default_hop_limit: usize,
+    pub max_connections: usize,
+    /// Largest message accepted from a peer
+    pub max_message_size: u64,
+    /// Largest frame of the TCP transport
+    pub max_frame_len: usize,
+    /// Transactions queried together in a batch
+    pub max_batch_size: usize,
+    pub max_memo_len: usize,
+    /// Tags separating merkle leaves, inner nodes and roots
+    pub merkle_tags: (u8, u8, u8),
+}
+
+impl Default for ProtocolParams {
+    fn default() -> Self {
+        Self {
+            default_hop_limit: DEFAULT_HOP_LIMIT,
+            max_connections: MAX_CONNECTION_LEN,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            max_frame_len: MAX_FRAME_LEN,
+            max_batch_size: ConsensusConfig::default().max_batch_size(),
+            max_memo_len: MAX_MEMO_LEN,
+            merkle_tags: (LEAF_TAG, NODE_TAG, ROOT_TAG),
+        }
+    }
+}
+
+impl ProtocolParams {
+    /// Hash exchanged when identifying to peers
+    pub fn hash(&self) -> Hash {
+        let mut bytes = PARAMS_DOMAIN.to_vec();
+        bytes.extend(bincode::serialize(self).unwrap_or_default());
+        Hash::new(&bytes)
+    }
+}
+
+/// Version to speak with a peer speaking `theirs`, if we can
+pub fn negotiate_version(theirs: u32) -> Option<u32> {
+    (theirs >= MIN_PROTOCOL_VERSION).then(|| theirs.min(PROTOCOL_VERSION))
+}
+
+/// Optional capabilities of a node, as a set of flags
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct Features(u64);
+
+impl Features {
+    pub const NONE: Self = Self(0);
+    /// Consensus queries batched in a single message
+    pub const BATCHED_CONSENSUS: Self = Self(1);
+    /// Small messages piggybacked on others headed to the same peer
+    pub const PIGGYBACK: Self = Self(1 << 1);
+    /// Rendezvous of hole punches to peers behind NATs
+    pub const HOLE_PUNCHING: Self = Self(1 << 2);
+    /// Signed status reports gossiped for monitoring
+    pub const STATUS_REPORTS: Self = Self(1 << 3);
+    /// Features of this build
+    pub const SUPPORTED: Self = Self(0b1111);
+
+    pub const fn bits(self) -> u64 {
+        self.0
+    }
+
+    /// Whether every feature of `other` is in the set
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    /// Features both sets have, e.g. those two peers can use together
+    pub const fn intersection(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+}
+
+/// What we agreed on with a peer that greeted us
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Negotiated {
+    pub version: u32,
+    pub features: Features,
+}
+
+#[test]
+fn test_protocol_params_hash() {
+    let params = ProtocolParams::default();
+    assert_eq!(params.hash(), ProtocolParams::default().hash());
+
+    let mut larger_messages = params.clone();
+    larger_messages.max_message_size *= 2;
+    assert_ne!(larger_messages.hash(), params.hash());
+    let mut longer_memos = params.clone();
+    longer_memos.max_memo_len += 1;
+    assert_ne!(longer_memos.hash(), params.hash());
+
+    // Versions are negotiated instead
+    assert_eq!(
+        negotiate_version(PROTOCOL_VERSION + 1),
+        Some(PROTOCOL_VERSION)
+    );
+    assert_eq!(negotiate_version(MIN_PROTOCOL_VERSION - 1), None);
+    let shared = Features::SUPPORTED.intersection(Features::PIGGYBACK.union(Features(1 << 40)));
+    assert_eq!(shared, Features::PIGGYBACK);
+    assert!(Features::SUPPORTED.contains(shared) && !shared.contains(Features::HOLE_PUNCHING));
+}
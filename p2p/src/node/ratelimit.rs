@@ -0,0 +1,153 @@
+//! Rate limits of the messages each peer sends us.
+//!
+//! Every connection gets two token buckets, one counting messages and one
+//! counting bytes, refilled at the rates of the [`RateLimitConfig`] and
+//! holding one second worth. A peer sending faster empties them and is
+//! throttled: whatever it sends is dropped for a while. Peers throttled too
+//! often are disconnected, rather than left to flood the node and stall
+//! consensus.
+
+use super::config::RateLimitConfig;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+/// Tokens refilled at a steady rate, up to one second worth
+#[derive(Clone, Debug)]
+pub struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    /// Full bucket of `rate` tokens per second
+    pub fn new(rate: u64, now: Instant) -> Self {
+        Self {
+            rate: rate as f64,
+            tokens: rate as f64,
+            last: now,
+        }
+    }
+
+    /// Take `amount` tokens if the bucket holds as many. Amounts larger than
+    /// the bucket are taken from a full one, leaving it in debt, so that
+    /// they are let through without the rate being exceeded.
+    pub fn try_take(&mut self, amount: u64, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.last = now;
+        if self.tokens < (amount as f64).min(self.rate) {
+            return false;
+        }
+        self.tokens -= amount as f64;
+        true
+    }
+}
+
+/// What to do with a message received from a peer
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RateVerdict {
+    Allow,
+    /// Drop the message, the peer being throttled already
+    Dropped,
+    /// Drop the message, the peer going over its limits with it
+    Throttled,
+    /// Disconnect from the peer, throttled too many times
+    Disconnect,
+}
+
+#[derive(Clone, Debug)]
+struct PeerRate {
+    messages: TokenBucket,
+    bytes: TokenBucket,
+    throttled_until: Option<Instant>,
+    throttles: u32,
+}
+
+/// Rate limits of the peers we are connected to
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+    messages_per_sec: u64,
+    bytes_per_sec: u64,
+    throttle: Duration,
+    max_throttles: u32,
+    peers: HashMap<SocketAddr, PeerRate>,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(&RateLimitConfig::default())
+    }
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimitConfig) -> Self {
+        Self {
+            messages_per_sec: config.messages_per_sec(),
+            bytes_per_sec: config.bytes_per_sec(),
+            throttle: config.throttle(),
+            max_throttles: config.max_throttles(),
+            peers: HashMap::new(),
+        }
+    }
+
+    /// Count a message of `size` bytes received from `peer` at `now`
+    pub fn check(&mut self, peer: SocketAddr, size: usize, now: Instant) -> RateVerdict {
+        let (messages_per_sec, bytes_per_sec) = (self.messages_per_sec, self.bytes_per_sec);
+        let rate = self.peers.entry(peer).or_insert_with(|| PeerRate {
+            messages: TokenBucket::new(messages_per_sec, now),
+            bytes: TokenBucket::new(bytes_per_sec, now),
+            throttled_until: None,
+            throttles: 0,
+        });
+        if rate.throttled_until.is_some_and(|until| now < until) {
+            return RateVerdict::Dropped;
+        }
+        if rate.messages.try_take(1, now) && rate.bytes.try_take(size as u64, now) {
+            return RateVerdict::Allow;
+        }
+        rate.throttles += 1;
+        if rate.throttles > self.max_throttles {
+            return RateVerdict::Disconnect;
+        }
+        rate.throttled_until = Some(now + self.throttle);
+        RateVerdict::Throttled
+    }
+
+    /// Times `peer` was throttled since it connected
+    pub fn throttles(&self, peer: &SocketAddr) -> u32 {
+        self.peers.get(peer).map_or(0, |rate| rate.throttles)
+    }
+
+    /// Drop the limits of a peer we disconnected from
+    pub fn forget(&mut self, peer: &SocketAddr) {
+        let _ = self.peers.remove(peer);
+    }
+}
+
+#[test]
+fn test_flooding_peers_are_throttled_then_disconnected() {
+    let config = RateLimitConfig::new(10, 1_000, Duration::from_secs(5), 1);
+    let mut limiter = RateLimiter::new(&config);
+    let flooder: SocketAddr = ([10, 0, 0, 1], 9000).into();
+    let other: SocketAddr = ([10, 0, 0, 2], 9000).into();
+    let now = Instant::now();
+
+    for _ in 0..10 {
+        assert_eq!(limiter.check(flooder, 10, now), RateVerdict::Allow);
+    }
+    assert_eq!(limiter.check(flooder, 10, now), RateVerdict::Throttled);
+    assert_eq!(limiter.check(other, 10, now), RateVerdict::Allow);
+    let later = now + Duration::from_secs(4);
+    assert_eq!(limiter.check(flooder, 10, later), RateVerdict::Dropped);
+
+    // Refilled once the throttle is over, but bytes count too, and large
+    // messages are let through a full bucket
+    let later = now + Duration::from_secs(5);
+    assert_eq!(limiter.check(flooder, 4_000, later), RateVerdict::Allow);
+    assert_eq!(limiter.check(flooder, 10, later), RateVerdict::Disconnect);
+    assert_eq!(limiter.throttles(&flooder), 2);
+    limiter.forget(&flooder);
+    assert_eq!(limiter.throttles(&flooder), 0);
+}
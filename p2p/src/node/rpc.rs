@@ -0,0 +1,705 @@
+//! JSON-RPC endpoint for node control.
+//!
+//! Lets wallets and explorers talk to a running node over HTTP without
+//! linking against the crate. Hashes and transactions travel hex-encoded,
+//! transactions in their bincode form, as do the checkpoints, proofs and
+//! finality certificates served to light clients.
+//!
+//! A server started with an [`Authenticator`] requires a token in the
+//! `Authorization: Bearer <token>` header, and logs calls to the methods
+//! reserved to operators, which change the node state or inspect the
+//! conflict sets of the engine, under the `dagchain::audit` target. The same
+//! tokens grant read access to the [GraphQL](super::graphql) queries served
+//! on `POST /graphql`.
+
+use super::{
+    admin::AUDIT_TARGET,
+    auth::{Authenticator, Scope},
+    codec,
+    graphql::{self, ExplorerIndex},
+};
+use crate::error::{AuthError, P2pError};
+use consensus::{
+    account::Account,
+    checkpoint::CheckpointCertificate,
+    data::StoredData,
+    finality::FinalityCertificate,
+    inspect::ConflictReport,
+    receipt::Receipt,
+    state::BalanceProof,
+    transaction::{Transaction, TransactionStatus},
+    AccountId, NodeId, TxId,
+};
+use crypto::hash::Hash;
+use metrics::MetricsSnapshot;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::io::Read;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use tiny_http::{Header, Method, Response, Server};
+
+const JSONRPC_VERSION: &str = "2.0";
+const MAX_REQUEST_SIZE: u64 = 1024 * 1024;
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const SERVER_ERROR: i64 = -32000;
+pub const UNAUTHORIZED: i64 = -32001;
+pub const FORBIDDEN: i64 = -32003;
+
+/// Node-side implementation of the RPC methods
+pub trait RpcHandler: Send + Sync {
+    /// Submit a transaction for consensus, returning its ID
+    fn submit_transaction(&self, tx: Transaction) -> Result<TxId, RpcError>;
+
+    fn get_account(&self, account_id: &AccountId) -> Option<Account>;
+
+    /// Hashes of the peers the node is connected to
+    fn get_peers(&self) -> Vec<NodeId>;
+
+    fn get_transaction_status(&self, tx_id: &TxId) -> Option<TransactionStatus>;
+
+    /// Metrics served on `GET /metrics`, if the node exposes them
+    fn metrics(&self) -> Option<MetricsSnapshot> {
+        None
+    }
+
+    /// Certified checkpoints from `height` up to the latest, in order
+    fn get_checkpoints(&self, _height: u64) -> Vec<CheckpointCertificate> {
+        vec![]
+    }
+
+    /// Proof of the balance of an account at a checkpoint
+    fn get_balance_proof(
+        &self,
+        _account_id: &AccountId,
+        _checkpoint: &Hash,
+    ) -> Option<BalanceProof> {
+        None
+    }
+
+    /// Transaction along with the signatures certifying it
+    fn get_transaction(&self, _tx_id: &TxId) -> Option<Transaction> {
+        None
+    }
+
+    /// Fate of a transaction submitted to the node
+    fn get_receipt(&self, _tx_id: &TxId) -> Option<Receipt> {
+        None
+    }
+
+    /// Entry an account stored under `key`
+    fn get_data(&self, _account_id: &AccountId, _key: &str) -> Option<StoredData> {
+        None
+    }
+
+    /// Aggregated signatures of the validators on the finality of a
+    /// transaction
+    fn get_finality_certificate(&self, _tx_id: &TxId) -> Option<FinalityCertificate> {
+        None
+    }
+
+    /// Candidates competing for an account state, with the progress of
+    /// their rounds
+    fn get_conflicts(&self, _account_state: &Hash) -> Option<ConflictReport> {
+        None
+    }
+
+    /// Indexes queried on `POST /graphql`, if the node exposes them
+    fn explorer(&self) -> Option<&dyn ExplorerIndex> {
+        None
+    }
+}
+
+/// JSON-RPC error object
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    fn invalid_params(message: impl Into<String>) -> Self {
+        Self::new(INVALID_PARAMS, message)
+    }
+}
+
+impl From<AuthError> for RpcError {
+    #[inline]
+    fn from(e: AuthError) -> Self {
+        let code = match e {
+            AuthError::InsufficientScope { .. } => FORBIDDEN,
+            _ => UNAUTHORIZED,
+        };
+        RpcError::new(code, e.to_string())
+    }
+}
+
+#[derive(Deserialize)]
+struct RpcRequest {
+    jsonrpc: String,
+    method: String,
+    #[serde(default)]
+    params: Value,
+    #[serde(default)]
+    id: Value,
+}
+
+#[derive(Serialize)]
+struct RpcResponse {
+    jsonrpc: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<RpcError>,
+    id: Value,
+}
+
+impl RpcResponse {
+    fn new(id: Value, outcome: Result<Value, RpcError>) -> Self {
+        let (result, error) = match outcome {
+            Ok(result) => (Some(result), None),
+            Err(error) => (None, Some(error)),
+        };
+        Self {
+            jsonrpc: JSONRPC_VERSION,
+            result,
+            error,
+            id,
+        }
+    }
+}
+
+/// Handle the body of a JSON-RPC request, returning the body of the response
+pub fn handle_request(handler: &dyn RpcHandler, body: &str) -> String {
+    respond(body, |method, params| dispatch(handler, method, params))
+}
+
+/// Handle the body of a JSON-RPC request on behalf of the holder of `token`
+pub fn handle_authorized_request(
+    handler: &dyn RpcHandler,
+    auth: &Authenticator,
+    token: Option<&str>,
+    body: &str,
+) -> String {
+    respond(body, |method, params| {
+        let required = required_scope(method);
+        let grant = auth.authorize(token, required).map_err(|e| {
+            if required == Scope::Operator {
+                log::warn!(target: AUDIT_TARGET, "Refused call to {}: {}", method, e);
+            }
+            RpcError::from(e)
+        })?;
+        let outcome = dispatch(handler, method, params);
+        if required == Scope::Operator {
+            log::info!(
+                target: AUDIT_TARGET,
+                "{:?} called {}: {}",
+                grant.subject,
+                method,
+                if outcome.is_ok() { "ok" } else { "failed" }
+            );
+        }
+        outcome
+    })
+}
+
+/// Scope a token must grant to call `method`
+pub fn required_scope(method: &str) -> Scope {
+    match method {
+        "submit_transaction" | "get_conflicts" => Scope::Operator,
+        _ => Scope::ReadOnly,
+    }
+}
+
+fn respond<F>(body: &str, dispatch: F) -> String
+where
+    F: FnOnce(&str, &Value) -> Result<Value, RpcError>,
+{
+    let response = match serde_json::from_str::<RpcRequest>(body) {
+        Ok(request) if request.jsonrpc != JSONRPC_VERSION => RpcResponse::new(
+            request.id,
+            Err(RpcError::new(
+                INVALID_REQUEST,
+                "Unsupported JSON-RPC version",
+            )),
+        ),
+        Ok(request) => {
+            let outcome = dispatch(&request.method, &request.params);
+            RpcResponse::new(request.id, outcome)
+        }
+        Err(e) => RpcResponse::new(Value::Null, Err(RpcError::new(PARSE_ERROR, e.to_string()))),
+    };
+    serde_json::to_string(&response).unwrap()
+}
+
+fn dispatch(handler: &dyn RpcHandler, method: &str, params: &Value) -> Result<Value, RpcError> {
+    match method {
+        "submit_transaction" => {
+            let encoded = param(params, 0, "transaction")?
+                .as_str()
+                .ok_or_else(|| RpcError::invalid_params("transaction must be a hex string"))?;
+            let bytes =
+                hex::decode(encoded).map_err(|e| RpcError::invalid_params(e.to_string()))?;
+            let tx = codec::decode::<Transaction>(&bytes, MAX_REQUEST_SIZE)
+                .map_err(|e| RpcError::invalid_params(e.to_string()))?;
+            let tx_id = handler.submit_transaction(tx)?;
+            Ok(json!(tx_id.to_hex()))
+        }
+        "get_account" => {
+            let account_id = hash_param(params, "account_id")?;
+            Ok(handler
+                .get_account(&account_id.into())
+                .map_or(Value::Null, |account| account_json(&account)))
+        }
+        "get_peers" => Ok(json!(handler
+            .get_peers()
+            .iter()
+            .map(NodeId::to_hex)
+            .collect::<Vec<_>>())),
+        "get_transaction_status" => {
+            let tx_id = hash_param(params, "tx_id")?;
+            Ok(handler
+                .get_transaction_status(&tx_id.into())
+                .map_or(Value::Null, |status| json!(status)))
+        }
+        "get_metrics" => Ok(handler
+            .metrics()
+            .map_or(Value::Null, |metrics| json!(metrics))),
+        "get_checkpoints" => {
+            let height = param(params, 0, "height")?
+                .as_u64()
+                .ok_or_else(|| RpcError::invalid_params("height must be an integer"))?;
+            encoded(&handler.get_checkpoints(height))
+        }
+        "get_balance_proof" => {
+            let account_id = hash_param_at(params, 0, "account_id")?;
+            let checkpoint = hash_param_at(params, 1, "checkpoint")?;
+            handler
+                .get_balance_proof(&account_id.into(), &checkpoint)
+                .map_or(Ok(Value::Null), |proof| encoded(&proof))
+        }
+        "get_transaction" => {
+            let tx_id = hash_param(params, "tx_id")?;
+            handler
+                .get_transaction(&tx_id.into())
+                .map_or(Ok(Value::Null), |tx| encoded(&tx))
+        }
+        "get_receipt" => {
+            let tx_id = hash_param(params, "tx_id")?;
+            Ok(handler
+                .get_receipt(&tx_id.into())
+                .map_or(Value::Null, |receipt| receipt_json(&receipt)))
+        }
+        "get_data" => {
+            let account_id = hash_param_at(params, 0, "account_id")?;
+            let key = param(params, 1, "key")?
+                .as_str()
+                .ok_or_else(|| RpcError::invalid_params("key must be a string"))?;
+            Ok(handler
+                .get_data(&account_id.into(), key)
+                .map_or(Value::Null, |data| data_json(&data)))
+        }
+        "get_finality_certificate" => {
+            let tx_id = hash_param(params, "tx_id")?;
+            handler
+                .get_finality_certificate(&tx_id.into())
+                .map_or(Ok(Value::Null), |certificate| encoded(&certificate))
+        }
+        "get_conflicts" => {
+            let account_state = hash_param(params, "account_state")?;
+            Ok(handler
+                .get_conflicts(&account_state)
+                .map_or(Value::Null, |report| conflicts_json(&report)))
+        }
+        _ => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("Unknown method: {}", method),
+        )),
+    }
+}
+
+/// Retrieve a parameter passed either by position or by name
+fn param<'a>(params: &'a Value, position: usize, name: &str) -> Result<&'a Value, RpcError> {
+    match params {
+        Value::Array(values) => values.get(position),
+        Value::Object(values) => values.get(name),
+        _ => None,
+    }
+    .ok_or_else(|| RpcError::invalid_params(format!("Missing parameter: {}", name)))
+}
+
+fn hash_param(params: &Value, name: &str) -> Result<Hash, RpcError> {
+    hash_param_at(params, 0, name)
+}
+
+fn hash_param_at(params: &Value, position: usize, name: &str) -> Result<Hash, RpcError> {
+    let encoded = param(params, position, name)?
+        .as_str()
+        .ok_or_else(|| RpcError::invalid_params(format!("{} must be a hex string", name)))?;
+    Hash::from_hex(encoded).map_err(|e| RpcError::invalid_params(e.to_string()))
+}
+
+/// Hex encoding of the bincode form of a value
+fn encoded<T: Serialize>(value: &T) -> Result<Value, RpcError> {
+    bincode::serialize(value)
+        .map(|bytes| json!(hex::encode(bytes)))
+        .map_err(|e| RpcError::new(SERVER_ERROR, e.to_string()))
+}
+
+fn account_json(account: &Account) -> Value {
+    json!({
+        "id": account.id.to_hex(),
+        // u128 does not fit in a JSON number
+        "balance": account.balance.base_units().to_string(),
+        "last_tx_id": account.last_tx_id.to_hex(),
+        "created": account.created.as_secs(),
+        "sequence": account.sequence,
+    })
+}
+
+fn receipt_json(receipt: &Receipt) -> Value {
+    json!({
+        "tx_id": receipt.tx_id.to_hex(),
+        "status": receipt.status,
+        "round": receipt.round,
+        "timestamp": receipt.timestamp.as_secs(),
+        "aggregate_sig": receipt.aggregate_sig.as_ref().map(|sig| hex::encode(sig.as_bytes())),
+    })
+}
+
+fn data_json(data: &StoredData) -> Value {
+    json!({
+        "account_id": data.account_id.to_hex(),
+        "key": data.key,
+        "value": hex::encode(&data.value),
+        "tx_id": data.tx_id.to_hex(),
+    })
+}
+
+fn conflicts_json(report: &ConflictReport) -> Value {
+    json!({
+        "account_state": report.account_state.to_hex(),
+        "choice": report.choice.as_ref().map(TxId::to_hex),
+        "candidates": report
+            .candidates
+            .iter()
+            .map(|candidate| {
+                json!({
+                    "tx_id": candidate.tx_id.to_hex(),
+                    "confidence": candidate.confidence,
+                    "rounds": candidate.rounds,
+                    "attempts": candidate.attempts,
+                })
+            })
+            .collect::<Vec<_>>(),
+    })
+}
+
+/// HTTP server answering JSON-RPC requests on a background thread
+pub struct RpcServer {
+    server: Arc<Server>,
+    local_addr: SocketAddr,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl RpcServer {
+    /// Start serving requests on `addr` to anyone
+    pub fn start(addr: SocketAddr, handler: Arc<dyn RpcHandler>) -> Result<Self, P2pError> {
+        Self::spawn(addr, handler, None)
+    }
+
+    /// Start serving requests on `addr` to callers presenting a token
+    pub fn start_with_auth(
+        addr: SocketAddr,
+        handler: Arc<dyn RpcHandler>,
+        auth: Arc<Authenticator>,
+    ) -> Result<Self, P2pError> {
+        Self::spawn(addr, handler, Some(auth))
+    }
+
+    fn spawn(
+        addr: SocketAddr,
+        handler: Arc<dyn RpcHandler>,
+        auth: Option<Arc<Authenticator>>,
+    ) -> Result<Self, P2pError> {
+        let server =
+            Arc::new(Server::http(addr).map_err(|e| P2pError::CustomError(e.to_string()))?);
+        let local_addr = server.server_addr().to_ip().unwrap_or(addr);
+        let handle = {
+            let server = server.clone();
+            std::thread::spawn(move || serve(&server, handler.as_ref(), auth.as_deref()))
+        };
+        log::info!("JSON-RPC server listening on {:?}", local_addr);
+        Ok(Self {
+            server,
+            local_addr,
+            handle: Some(handle),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Drop for RpcServer {
+    fn drop(&mut self) {
+        self.server.unblock();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn serve(server: &Server, handler: &dyn RpcHandler, auth: Option<&Authenticator>) {
+    let content_type = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap();
+    for mut request in server.incoming_requests() {
+        let token = bearer_token(&request);
+        if *request.method() == Method::Get && request.url() == "/metrics" {
+            if let Some(Err(e)) = auth.map(|auth| auth.authorize(token.as_deref(), Scope::ReadOnly))
+            {
+                log::debug!("Refused metrics scrape: {}", e);
+                let _ = request.respond(Response::empty(401));
+                continue;
+            }
+            let response = match handler.metrics() {
+                Some(metrics) => Response::from_string(metrics.to_prometheus()),
+                None => Response::from_string("").with_status_code(404),
+            };
+            let _ = request.respond(response);
+            continue;
+        }
+        if *request.method() != Method::Post {
+            let _ = request.respond(Response::empty(405));
+            continue;
+        }
+        let mut body = String::new();
+        if let Err(e) = request
+            .as_reader()
+            .take(MAX_REQUEST_SIZE)
+            .read_to_string(&mut body)
+        {
+            log::warn!("Failed to read JSON-RPC request: {:?}", e);
+            let _ = request.respond(Response::empty(400));
+            continue;
+        }
+        if request.url() == "/graphql" {
+            if let Some(Err(e)) = auth.map(|auth| auth.authorize(token.as_deref(), Scope::ReadOnly))
+            {
+                log::debug!("Refused GraphQL query: {}", e);
+                let _ = request.respond(Response::empty(401));
+                continue;
+            }
+            let response = match handler.explorer() {
+                Some(index) => Response::from_string(graphql::handle_request(index, &body))
+                    .with_header(content_type.clone()),
+                None => Response::from_string("").with_status_code(404),
+            };
+            let _ = request.respond(response);
+            continue;
+        }
+        let body = match auth {
+            Some(auth) => handle_authorized_request(handler, auth, token.as_deref(), &body),
+            None => handle_request(handler, &body),
+        };
+        let response = Response::from_string(body).with_header(content_type.clone());
+        if let Err(e) = request.respond(response) {
+            log::warn!("Failed to answer JSON-RPC request: {:?}", e);
+        }
+    }
+}
+
+/// Token of an `Authorization: Bearer <token>` header
+fn bearer_token(request: &tiny_http::Request) -> Option<String> {
+    request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Authorization"))
+        .and_then(|header| header.value.as_str().strip_prefix("Bearer "))
+        .map(|token| token.trim().to_string())
+}
+
+#[cfg(test)]
+struct TestHandler {
+    account: Account,
+    peers: Vec<NodeId>,
+}
+
+#[cfg(test)]
+impl RpcHandler for TestHandler {
+    fn submit_transaction(&self, mut tx: Transaction) -> Result<TxId, RpcError> {
+        tx.calculate_tx_id()
+            .map_err(|e| RpcError::new(SERVER_ERROR, e.to_string()))?;
+        Ok(tx.get_tx_id())
+    }
+
+    fn get_account(&self, account_id: &AccountId) -> Option<Account> {
+        (*account_id == self.account.id).then(|| self.account.clone())
+    }
+
+    fn get_peers(&self) -> Vec<NodeId> {
+        self.peers.clone()
+    }
+
+    fn get_transaction_status(&self, _tx_id: &TxId) -> Option<TransactionStatus> {
+        Some(TransactionStatus::Pending)
+    }
+
+    fn get_receipt(&self, tx_id: &TxId) -> Option<Receipt> {
+        Some(Receipt::pending(*tx_id))
+    }
+
+    fn get_data(&self, account_id: &AccountId, key: &str) -> Option<StoredData> {
+        (*account_id == self.account.id && key == "doc").then(|| StoredData {
+            account_id: *account_id,
+            key: key.to_string(),
+            value: vec![0xab],
+            tx_id: Hash::default().into(),
+        })
+    }
+
+    fn get_finality_certificate(&self, tx_id: &TxId) -> Option<FinalityCertificate> {
+        Some(FinalityCertificate::new(*tx_id))
+    }
+}
+
+#[cfg(test)]
+fn test_handler() -> TestHandler {
+    TestHandler {
+        account: Account::create(
+            &Hash::new("account".as_bytes()).into(),
+            &Hash::default().into(),
+        ),
+        peers: vec![Hash::new("peer".as_bytes()).into()],
+    }
+}
+
+#[cfg(test)]
+fn call(handler: &dyn RpcHandler, method: &str, params: Value) -> Value {
+    let request = json!({"jsonrpc": "2.0", "method": method, "params": params, "id": 1});
+    serde_json::from_str(&handle_request(handler, &request.to_string())).unwrap()
+}
+
+#[test]
+fn test_rpc_queries() {
+    let handler = test_handler();
+    let peers = call(&handler, "get_peers", Value::Null);
+    assert_eq!(peers["result"], json!([handler.peers[0].to_hex()]));
+    assert_eq!(peers["id"], json!(1));
+
+    let account = call(
+        &handler,
+        "get_account",
+        json!([handler.account.id.to_hex()]),
+    );
+    assert_eq!(account["result"]["balance"], json!("0"));
+    let missing = call(
+        &handler,
+        "get_account",
+        json!({"account_id": Hash::default().to_hex()}),
+    );
+    assert_eq!(missing["result"], Value::Null);
+
+    let status = call(
+        &handler,
+        "get_transaction_status",
+        json!([Hash::default().to_hex()]),
+    );
+    assert_eq!(status["result"], json!("Pending"));
+    let receipt = call(&handler, "get_receipt", json!([Hash::default().to_hex()]));
+    assert_eq!(receipt["result"]["tx_id"], json!(Hash::default().to_hex()));
+    assert_eq!(receipt["result"]["status"], json!("Pending"));
+    assert_eq!(receipt["result"]["round"], Value::Null);
+    let account_id = handler.account.id.to_hex();
+    let data = call(&handler, "get_data", json!([account_id, "doc"]));
+    assert_eq!(data["result"]["value"], json!("ab"));
+    let missing = call(
+        &handler,
+        "get_data",
+        json!({"account_id": account_id, "key": "x"}),
+    );
+    assert_eq!(missing["result"], Value::Null);
+    let certificate = call(
+        &handler,
+        "get_finality_certificate",
+        json!([Hash::default().to_hex()]),
+    );
+    let certificate = hex::decode(certificate["result"].as_str().unwrap()).unwrap();
+    assert_eq!(
+        bincode::deserialize::<FinalityCertificate>(&certificate).unwrap(),
+        FinalityCertificate::new(Hash::default().into())
+    );
+
+    // The test node exposes no metrics
+    let metrics = call(&handler, "get_metrics", Value::Null);
+    assert_eq!(metrics["result"], Value::Null);
+}
+
+#[test]
+fn test_rpc_submit_transaction() {
+    use consensus::transaction::TransactionType;
+
+    let handler = test_handler();
+    let mut tx = Transaction::new(
+        Hash::default().into(),
+        handler.account.clone(),
+        Hash::new("destination".as_bytes()).into(),
+        consensus::Amount::new(10),
+        TransactionType::Transfer,
+        vec![],
+    );
+    let encoded = hex::encode(bincode::serialize(&tx).unwrap());
+    let response = call(&handler, "submit_transaction", json!([encoded]));
+    tx.calculate_tx_id().unwrap();
+    assert_eq!(response["result"], json!(tx.get_tx_id().to_hex()));
+}
+
+#[test]
+fn test_rpc_errors() {
+    let handler = test_handler();
+    let unknown = call(&handler, "get_everything", Value::Null);
+    assert_eq!(unknown["error"]["code"], json!(METHOD_NOT_FOUND));
+
+    let invalid = call(&handler, "get_account", json!(["not a hash"]));
+    assert_eq!(invalid["error"]["code"], json!(INVALID_PARAMS));
+
+    let garbage: Value = serde_json::from_str(&handle_request(&handler, "{")).unwrap();
+    assert_eq!(garbage["error"]["code"], json!(PARSE_ERROR));
+}
+
+#[test]
+fn test_rpc_authorization() {
+    let handler = test_handler();
+    let auth = Authenticator::new();
+    auth.add_static_token("reader", Scope::ReadOnly);
+    let call = |token: Option<&str>, method: &str, params: Value| -> Value {
+        let request = json!({"jsonrpc": "2.0", "method": method, "params": params, "id": 1});
+        let response = handle_authorized_request(&handler, &auth, token, &request.to_string());
+        serde_json::from_str(&response).unwrap()
+    };
+
+    let peers = call(Some("reader"), "get_peers", Value::Null);
+    assert_eq!(peers["result"], json!([handler.peers[0].to_hex()]));
+    let anonymous = call(None, "get_peers", Value::Null);
+    assert_eq!(anonymous["error"]["code"], json!(UNAUTHORIZED));
+    let submitted = call(Some("reader"), "submit_transaction", json!(["00"]));
+    assert_eq!(submitted["error"]["code"], json!(FORBIDDEN));
+    let conflicts = call(
+        Some("reader"),
+        "get_conflicts",
+        json!([Hash::default().to_hex()]),
+    );
+    assert_eq!(conflicts["error"]["code"], json!(FORBIDDEN));
+}
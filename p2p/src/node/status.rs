@@ -0,0 +1,311 @@
+//! Status documents nodes sign and gossip about their own view of consensus.
+//!
+//! No single node can tell whether the network agrees. Each node
+//! periodically gossips a [`StatusReport`] of what it finalized, the digest
+//! of its state, the conflicts it is still resolving, the account states it
+//! found diverging from its peers and its latest checkpoint. A
+//! [`StatusBoard`] collects the reports of the network, so that monitoring
+//! can spot the nodes whose view departs from the others'.
+
+use super::identity::{Identity, PublicId};
+use crate::error::P2pError;
+use consensus::{checkpoint::Checkpoint, reconcile::StateDigest, NodeId};
+use crypto::{hash::Hash, signature::Signature};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Domain of the signed reports, which also prefixes them in gossip
+const STATUS_DOMAIN: &[u8] = b"dagchain:status";
+
+/// A node's own view of the invariants consensus should keep
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct NodeStatus {
+    /// Account states the node finalized a transaction for
+    pub finalized: usize,
+    /// Digest of the finalized transactions, equal on nodes that agree
+    pub state_digest: Hash,
+    /// Account states the node holds a choice for, i.e. is still resolving
+    /// or resolved since the last pruning
+    pub choices: usize,
+    /// Account states the node found finalized differently by a peer
+    pub diverging: Vec<Hash>,
+    /// Height and ID of the latest checkpoint
+    pub last_checkpoint: Option<(u64, Hash)>,
+}
+
+impl NodeStatus {
+    pub fn new(
+        digest: &StateDigest,
+        choices: usize,
+        mut diverging: Vec<Hash>,
+        last_checkpoint: Option<&Checkpoint>,
+    ) -> Self {
+        diverging.sort();
+        diverging.dedup();
+        Self {
+            finalized: digest.finalized.len(),
+            state_digest: digest.digest,
+            choices,
+            diverging,
+            last_checkpoint: last_checkpoint.map(|checkpoint| (checkpoint.height, checkpoint.id)),
+        }
+    }
+}
+
+/// Status signed by the node reporting it
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct StatusReport {
+    pub status: NodeStatus,
+    /// Network the report is meant for
+    pub network_id: Hash,
+    pub sender: PublicId,
+    /// When the report was signed, since the Unix epoch. Later reports
+    /// replace earlier ones.
+    pub issued: Duration,
+    signature: Signature,
+}
+
+impl StatusReport {
+    /// Sign a report of `status` on `network_id` as of now
+    pub fn sign(
+        status: NodeStatus,
+        network_id: Hash,
+        identity: &Identity,
+    ) -> Result<Self, P2pError> {
+        let issued = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Self::sign_at(status, network_id, identity, issued)
+    }
+
+    pub fn sign_at(
+        status: NodeStatus,
+        network_id: Hash,
+        identity: &Identity,
+        issued: Duration,
+    ) -> Result<Self, P2pError> {
+        let signature = identity.sign_message(&signed_bytes(&status, &network_id, issued)?);
+        Ok(Self {
+            status,
+            network_id,
+            sender: identity.get_public_id(),
+            issued,
+            signature,
+        })
+    }
+
+    /// Check the signature, returning the node that signed the report
+    pub fn verify(&self) -> Result<NodeId, P2pError> {
+        let bytes = signed_bytes(&self.status, &self.network_id, self.issued)?;
+        if !self.signature.verify(&self.sender.public_key, &bytes) {
+            return Err(P2pError::InvalidSignature);
+        }
+        self.signer()
+    }
+
+    /// Node claiming to have signed the report
+    pub fn signer(&self) -> Result<NodeId, P2pError> {
+        Hash::serialize(&self.sender.public_key)
+            .map(NodeId::from)
+            .map_err(P2pError::CryptoError)
+    }
+
+    /// Content to gossip the report as, see [`Messaging::gossip`]
+    ///
+    /// [`Messaging::gossip`]: super::messaging::Messaging::gossip
+    pub fn to_gossip(&self) -> Result<Vec<u8>, P2pError> {
+        let mut bytes = STATUS_DOMAIN.to_vec();
+        bytes.extend(bincode::serialize(self).map_err(P2pError::BincodeError)?);
+        Ok(bytes)
+    }
+
+    /// Report gossiped as `content`, if it is one
+    pub fn from_gossip(content: &[u8]) -> Option<Self> {
+        let report = content.strip_prefix(STATUS_DOMAIN)?;
+        bincode::deserialize(report).ok()
+    }
+}
+
+fn signed_bytes(
+    status: &NodeStatus,
+    network_id: &Hash,
+    issued: Duration,
+) -> Result<Vec<u8>, P2pError> {
+    let mut bytes = STATUS_DOMAIN.to_vec();
+    bytes
+        .extend(bincode::serialize(&(status, network_id, issued)).map_err(P2pError::BincodeError)?);
+    Ok(bytes)
+}
+
+/// Tells when our status is due to be published again
+#[derive(Clone, Debug)]
+pub struct StatusSchedule {
+    interval: Duration,
+    last: Option<Instant>,
+}
+
+impl StatusSchedule {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last: None,
+        }
+    }
+
+    /// Whether to publish at `now`, in which case the next publication is
+    /// due an interval later
+    pub fn is_due(&mut self, now: Instant) -> bool {
+        if self
+            .last
+            .is_some_and(|last| now.saturating_duration_since(last) < self.interval)
+        {
+            return false;
+        }
+        self.last = Some(now);
+        true
+    }
+}
+
+/// Latest status reported by each node of a network
+#[derive(Clone, Debug)]
+pub struct StatusBoard {
+    network_id: Hash,
+    reports: HashMap<NodeId, StatusReport>,
+}
+
+impl StatusBoard {
+    pub fn new(network_id: Hash) -> Self {
+        Self {
+            network_id,
+            reports: HashMap::new(),
+        }
+    }
+
+    /// Record a report once checked that it is signed by its sender for our
+    /// network. Returns false if a later report of the sender is known
+    /// already.
+    pub fn record(&mut self, report: StatusReport) -> Result<bool, P2pError> {
+        if report.network_id != self.network_id {
+            return Err(P2pError::NetworkMismatch {
+                ours: self.network_id,
+                theirs: report.network_id,
+            });
+        }
+        let id = report.verify()?;
+        if self
+            .reports
+            .get(&id)
+            .is_some_and(|known| known.issued >= report.issued)
+        {
+            return Ok(false);
+        }
+        let _ = self.reports.insert(id, report);
+        Ok(true)
+    }
+
+    pub fn get(&self, node: &NodeId) -> Option<&NodeStatus> {
+        self.reports.get(node).map(|report| &report.status)
+    }
+
+    pub fn len(&self) -> usize {
+        self.reports.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.reports.is_empty()
+    }
+
+    /// Nodes reporting each state digest, the most reported first
+    pub fn digests(&self) -> Vec<(Hash, Vec<NodeId>)> {
+        let mut digests = HashMap::<Hash, Vec<NodeId>>::new();
+        for (id, report) in &self.reports {
+            digests
+                .entry(report.status.state_digest)
+                .or_default()
+                .push(*id);
+        }
+        let mut digests = digests.into_iter().collect::<Vec<_>>();
+        for (_, nodes) in &mut digests {
+            nodes.sort();
+        }
+        digests.sort_by(|(a, a_nodes), (b, b_nodes)| {
+            b_nodes.len().cmp(&a_nodes.len()).then_with(|| a.cmp(b))
+        });
+        digests
+    }
+
+    /// Nodes reporting divergence from one of their peers
+    pub fn alerts(&self) -> Vec<NodeId> {
+        let mut alerts = self
+            .reports
+            .iter()
+            .filter(|(_, report)| !report.status.diverging.is_empty())
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        alerts.sort();
+        alerts
+    }
+}
+
+#[test]
+fn test_status_reports_are_gossiped_and_compared() {
+    use consensus::TxId;
+    use std::collections::BTreeMap;
+
+    let account = Hash::new("account".as_bytes());
+    let digest = |tx: &str| {
+        let tx_id = TxId::from(Hash::new(tx.as_bytes()));
+        StateDigest::new(BTreeMap::from([(account, tx_id)])).unwrap()
+    };
+    let identities = (0..3).map(|_| Identity::new()).collect::<Vec<_>>();
+    let mut board = StatusBoard::new(Hash::default());
+    for (identity, tx) in identities.iter().zip(["a", "a", "b"]) {
+        let diverging = if tx == "b" {
+            vec![account, account]
+        } else {
+            vec![]
+        };
+        let status = NodeStatus::new(&digest(tx), 4, diverging, None);
+        let report =
+            StatusReport::sign_at(status, Hash::default(), identity, Duration::from_secs(10))
+                .unwrap();
+        let gossiped = StatusReport::from_gossip(&report.to_gossip().unwrap()).unwrap();
+        assert!(board.record(gossiped).unwrap());
+    }
+    assert!(StatusReport::from_gossip(b"other gossip").is_none());
+
+    let ids = identities
+        .iter()
+        .map(|identity| identity.get_our_hash().unwrap())
+        .collect::<Vec<_>>();
+    let digests = board.digests();
+    assert_eq!(digests[0].0, digest("a").digest);
+    assert_eq!(digests[0].1.len(), 2);
+    assert_eq!(digests[1].1, vec![ids[2]]);
+    assert_eq!(board.alerts(), vec![ids[2]]);
+    assert_eq!(board.get(&ids[2]).unwrap().diverging, vec![account]);
+
+    // Earlier and forged reports are ignored
+    let status = NodeStatus::new(&digest("b"), 0, vec![], None);
+    let sign = |network_id, issued| {
+        StatusReport::sign_at(status.clone(), network_id, &identities[0], issued).unwrap()
+    };
+    let earlier = sign(Hash::default(), Duration::from_secs(5));
+    assert!(!board.record(earlier.clone()).unwrap());
+    let testnet = sign(Hash::new("testnet".as_bytes()), Duration::from_secs(60));
+    assert!(matches!(
+        board.record(testnet),
+        Err(P2pError::NetworkMismatch { .. })
+    ));
+    let mut forged = earlier;
+    forged.issued = Duration::from_secs(60);
+    assert!(board.record(forged).is_err());
+    assert_eq!(board.get(&ids[0]).unwrap().state_digest, digest("a").digest);
+
+    let mut schedule = StatusSchedule::new(Duration::from_secs(30));
+    let now = Instant::now();
+    assert!(schedule.is_due(now));
+    assert!(!schedule.is_due(now + Duration::from_secs(29)));
+    assert!(schedule.is_due(now + Duration::from_secs(30)));
+}
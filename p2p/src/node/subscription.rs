@@ -0,0 +1,191 @@
+//! Subscriptions to the events of a node.
+//!
+//! Components report [`Event`]s on a single channel, which would force every
+//! consumer to see all of them. An [`EventBus`] fans them out instead to the
+//! subscribers interested, each picking events by kind, peer or transaction
+//! through an [`EventFilter`]. Every subscriber has its own bounded queue: a
+//! subscriber falling behind misses events rather than holding up the node
+//! or the other subscribers.
+
+use super::event::Event;
+use consensus::{NodeId, TxId};
+use crossbeam_channel::{Receiver, Sender, TrySendError};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+/// Events queued for a subscriber by default, before it misses new ones
+pub const DEFAULT_SUBSCRIBER_CAPACITY: usize = 1024;
+
+/// Events a subscriber is interested in: those matching every criterion set
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EventFilter {
+    kinds: Option<HashSet<&'static str>>,
+    peers: Option<HashSet<NodeId>>,
+    tx_ids: Option<HashSet<TxId>>,
+}
+
+impl EventFilter {
+    /// Filter letting every event through
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    /// Only events of these kinds, see [`Event::kind`]
+    pub fn kinds(mut self, kinds: impl IntoIterator<Item = &'static str>) -> Self {
+        self.kinds = Some(kinds.into_iter().collect());
+        self
+    }
+
+    /// Only events about or sent by these peers
+    pub fn peers(mut self, peers: impl IntoIterator<Item = NodeId>) -> Self {
+        self.peers = Some(peers.into_iter().collect());
+        self
+    }
+
+    /// Only events about these transactions
+    pub fn tx_ids(mut self, tx_ids: impl IntoIterator<Item = TxId>) -> Self {
+        self.tx_ids = Some(tx_ids.into_iter().collect());
+        self
+    }
+
+    pub fn matches(&self, event: &Event) -> bool {
+        self.kinds
+            .as_ref()
+            .is_none_or(|kinds| kinds.contains(event.kind()))
+            && self
+                .peers
+                .as_ref()
+                .is_none_or(|peers| event.peer().is_some_and(|peer| peers.contains(&peer)))
+            && self
+                .tx_ids
+                .as_ref()
+                .is_none_or(|tx_ids| event.tx_ids().iter().any(|tx_id| tx_ids.contains(tx_id)))
+    }
+}
+
+struct Subscriber {
+    filter: EventFilter,
+    events: Sender<Event>,
+}
+
+/// Registry of the subscribers to the events of a node. Clones share the
+/// subscribers.
+#[derive(Clone)]
+pub struct EventBus {
+    capacity: usize,
+    subscribers: Arc<Mutex<Vec<Subscriber>>>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new(DEFAULT_SUBSCRIBER_CAPACITY)
+    }
+}
+
+impl EventBus {
+    /// Bus queueing up to `capacity` events for each subscriber
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            subscribers: Default::default(),
+        }
+    }
+
+    /// Receive the events matching `filter` from now on. Dropping the
+    /// receiver ends the subscription, once the next event it matches is
+    /// published.
+    pub fn subscribe_events(&self, filter: EventFilter) -> Receiver<Event> {
+        let (events, receiver) = crossbeam_channel::bounded(self.capacity);
+        self.subscribers
+            .lock()
+            .unwrap()
+            .push(Subscriber { filter, events });
+        receiver
+    }
+
+    pub fn subscribers(&self) -> usize {
+        self.subscribers.lock().unwrap().len()
+    }
+
+    /// Deliver an event to the subscribers interested, returning how many
+    /// received it. Subscribers whose queue is full miss it.
+    pub fn publish(&self, event: &Event) -> usize {
+        let mut delivered = 0;
+        self.subscribers.lock().unwrap().retain(|subscriber| {
+            if !subscriber.filter.matches(event) {
+                return true;
+            }
+            match subscriber.events.try_send(event.clone()) {
+                Ok(()) => {
+                    delivered += 1;
+                    true
+                }
+                Err(TrySendError::Full(_)) => {
+                    log::debug!("Subscriber falling behind missed {}", event.kind());
+                    true
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            }
+        });
+        delivered
+    }
+
+    /// Deliver the events queued on `events`, the channel the components of
+    /// the node report to, returning how many there were
+    pub fn forward(&self, events: &Receiver<Event>) -> usize {
+        let mut forwarded = 0;
+        for event in events.try_iter() {
+            let _ = self.publish(&event);
+            forwarded += 1;
+        }
+        forwarded
+    }
+}
+
+#[test]
+fn test_subscribers_receive_the_events_they_filter() {
+    use crypto::hash::Hash;
+
+    let peer = NodeId::from(Hash::new("peer".as_bytes()));
+    let other = NodeId::from(Hash::new("other".as_bytes()));
+    let tx_id = TxId::from(Hash::new("tx".as_bytes()));
+    let bus = EventBus::new(2);
+    let everything = bus.subscribe_events(EventFilter::all());
+    let connections = bus.subscribe_events(EventFilter::all().kinds(["ConnectedTo"]));
+    let from_peer = bus.subscribe_events(EventFilter::all().peers([peer]));
+    let of_tx = bus.subscribe_events(EventFilter::all().tx_ids([tx_id]));
+    let decided = bus.subscribe_events(
+        EventFilter::all()
+            .kinds(["DagConsensusResponse"])
+            .tx_ids([tx_id]),
+    );
+
+    let (node_tx, node_rx) = crossbeam_channel::unbounded();
+    for event in [
+        Event::ConnectedTo(other),
+        Event::DagConsensusResponse {
+            hash: tx_id,
+            sender: peer,
+            accepted: true,
+        },
+        Event::Applied(tx_id),
+    ] {
+        node_tx.send(event).unwrap();
+    }
+    assert_eq!(bus.forward(&node_rx), 3);
+
+    // The queue of two events is full, the third is missed
+    assert_eq!(everything.try_iter().count(), 2);
+    assert_eq!(
+        connections.try_iter().collect::<Vec<_>>(),
+        vec![Event::ConnectedTo(other)]
+    );
+    assert_eq!(from_peer.try_iter().count(), 1);
+    assert_eq!(of_tx.try_iter().count(), 2);
+    assert_eq!(decided.try_iter().count(), 1);
+
+    // Dropped receivers are unsubscribed
+    drop(everything);
+    assert_eq!(bus.publish(&Event::ConnectedTo(peer)), 2);
+    assert_eq!(bus.subscribers(), 4);
+}
@@ -0,0 +1,514 @@
+//! Export of traces and metrics to OpenTelemetry collectors.
+//!
+//! An [`OtlpCollector`] installed as the `tracing` subscriber records the
+//! spans of the node, while still passing events on to the `log` crate.
+//! Envelopes carry the [`TraceContext`] of the span they were sent from, and
+//! the span handling them on the next hop adopts it as its parent, so that a
+//! consensus round is traced across every node it involves. An
+//! [`OtlpExporter`] sends the finished spans and the metrics of the node to
+//! a collector, as OTLP over HTTP in its JSON encoding.
+
+use super::config::TelemetryConfig;
+use crate::error::P2pError;
+use crypto::hash::Hash;
+use metrics::MetricsSnapshot;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{self, Write as _};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+
+/// Finished spans kept until exported, the oldest being dropped first
+const MAX_PENDING_SPANS: usize = 4096;
+/// Time an export may take
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Fields carrying the context of a span on another node
+const TRACE_ID_FIELD: &str = "trace_id";
+const PARENT_SPAN_ID_FIELD: &str = "parent_span_id";
+
+thread_local! {
+    /// Spans entered on this thread, innermost last
+    static ENTERED: RefCell<Vec<Id>> = const { RefCell::new(vec![]) };
+}
+
+/// Identifies a span across nodes, as in the W3C trace context
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct TraceContext {
+    pub trace_id: [u8; 16],
+    pub span_id: [u8; 8],
+}
+
+impl TraceContext {
+    /// Context of the span entered on this thread, if an [`OtlpCollector`]
+    /// records it
+    pub fn current() -> Option<Self> {
+        tracing::dispatcher::get_default(|dispatch| {
+            dispatch
+                .downcast_ref::<OtlpCollector>()
+                .and_then(OtlpCollector::current_context)
+        })
+    }
+
+    fn from_hex(trace_id: &str, span_id: &str) -> Option<Self> {
+        Some(Self {
+            trace_id: decode_hex(trace_id)?,
+            span_id: decode_hex(span_id)?,
+        })
+    }
+
+    pub fn trace_id_hex(&self) -> String {
+        encode_hex(&self.trace_id)
+    }
+
+    pub fn span_id_hex(&self) -> String {
+        encode_hex(&self.span_id)
+    }
+}
+
+/// Span recorded by an [`OtlpCollector`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpanRecord {
+    pub name: &'static str,
+    pub context: TraceContext,
+    pub parent_span_id: Option<[u8; 8]>,
+    pub start: SystemTime,
+    pub end: SystemTime,
+    pub attributes: Vec<(&'static str, String)>,
+}
+
+#[derive(Debug)]
+struct OpenSpan {
+    record: SpanRecord,
+    /// Handles to the span still alive
+    refs: usize,
+}
+
+#[derive(Debug, Default)]
+struct Spans {
+    open: HashMap<u64, OpenSpan>,
+    finished: VecDeque<SpanRecord>,
+}
+
+/// Subscriber recording spans for an [`OtlpExporter`]. Clones share the
+/// same spans, so one can be installed while another drains them.
+#[derive(Clone, Debug, Default)]
+pub struct OtlpCollector {
+    spans: Arc<Mutex<Spans>>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl OtlpCollector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take the spans finished since the latest call
+    pub fn finished_spans(&self) -> Vec<SpanRecord> {
+        self.spans.lock().unwrap().finished.drain(..).collect()
+    }
+
+    /// Send the finished spans to `exporter`, returning how many were sent.
+    /// Spans that failed to be sent are dropped.
+    pub fn export(&self, exporter: &OtlpExporter) -> Result<usize, P2pError> {
+        let spans = self.finished_spans();
+        if spans.is_empty() {
+            return Ok(0);
+        }
+        exporter.export_spans(&spans)?;
+        Ok(spans.len())
+    }
+
+    fn current_context(&self) -> Option<TraceContext> {
+        let id = ENTERED.with(|entered| entered.borrow().last().cloned())?;
+        let spans = self.spans.lock().unwrap();
+        spans
+            .open
+            .get(&id.into_u64())
+            .map(|span| span.record.context)
+    }
+}
+
+impl Subscriber for OtlpCollector {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.is_span() || log_level(metadata) <= log::max_level()
+    }
+
+    fn new_span(&self, attributes: &Attributes<'_>) -> Id {
+        let mut fields = Fields::default();
+        attributes.record(&mut fields);
+        let parent = if attributes.is_root() {
+            None
+        } else if let Some(parent) = attributes.parent() {
+            Some(parent.clone())
+        } else {
+            ENTERED.with(|entered| entered.borrow().last().cloned())
+        };
+
+        let mut spans = self.spans.lock().unwrap();
+        let local_parent = parent
+            .and_then(|parent| spans.open.get(&parent.into_u64()))
+            .map(|parent| parent.record.context);
+        // A context received from another node takes precedence
+        let parent = fields.remote.or(local_parent);
+        let context = TraceContext {
+            trace_id: parent.map_or_else(random_bytes, |parent| parent.trace_id),
+            span_id: random_bytes(),
+        };
+        let now = SystemTime::now();
+        let record = SpanRecord {
+            name: attributes.metadata().name(),
+            context,
+            parent_span_id: parent.map(|parent| parent.span_id),
+            start: now,
+            end: now,
+            attributes: fields.values,
+        };
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let _ = spans.open.insert(id, OpenSpan { record, refs: 1 });
+        Id::from_u64(id)
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        let mut fields = Fields::default();
+        values.record(&mut fields);
+        if let Some(span) = self.spans.lock().unwrap().open.get_mut(&span.into_u64()) {
+            span.record.attributes.extend(fields.values);
+        }
+    }
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let metadata = event.metadata();
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let mut text = fields.message.unwrap_or_default();
+        for (name, value) in &fields.values {
+            let _ = write!(text, " {}={}", name, value);
+        }
+        log::logger().log(
+            &log::Record::builder()
+                .args(format_args!("{}", text))
+                .level(log_level(metadata).to_level().unwrap_or(log::Level::Error))
+                .target(metadata.target())
+                .module_path(metadata.module_path())
+                .file(metadata.file())
+                .line(metadata.line())
+                .build(),
+        );
+    }
+
+    fn enter(&self, span: &Id) {
+        ENTERED.with(|entered| entered.borrow_mut().push(span.clone()));
+    }
+
+    fn exit(&self, span: &Id) {
+        ENTERED.with(|entered| {
+            let mut entered = entered.borrow_mut();
+            if let Some(position) = entered.iter().rposition(|id| id == span) {
+                let _ = entered.remove(position);
+            }
+        });
+    }
+
+    fn clone_span(&self, span: &Id) -> Id {
+        if let Some(span) = self.spans.lock().unwrap().open.get_mut(&span.into_u64()) {
+            span.refs += 1;
+        }
+        span.clone()
+    }
+
+    fn try_close(&self, span: Id) -> bool {
+        let mut spans = self.spans.lock().unwrap();
+        let id = span.into_u64();
+        match spans.open.get_mut(&id) {
+            Some(open) if open.refs > 1 => {
+                open.refs -= 1;
+                false
+            }
+            Some(_) => {
+                let mut record = spans.open.remove(&id).unwrap().record;
+                record.end = SystemTime::now();
+                if spans.finished.len() == MAX_PENDING_SPANS {
+                    let _ = spans.finished.pop_front();
+                }
+                spans.finished.push_back(record);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Fields of a span or an event
+#[derive(Default)]
+struct Fields {
+    message: Option<String>,
+    values: Vec<(&'static str, String)>,
+    remote: Option<TraceContext>,
+    remote_trace_id: Option<String>,
+}
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            TRACE_ID_FIELD => self.remote_trace_id = Some(value.to_string()),
+            PARENT_SPAN_ID_FIELD => {
+                self.remote = self
+                    .remote_trace_id
+                    .as_deref()
+                    .and_then(|trace_id| TraceContext::from_hex(trace_id, value));
+            }
+            _ => self.values.push((field.name(), value.to_string())),
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        match field.name() {
+            "message" => self.message = Some(format!("{:?}", value)),
+            name => self.values.push((name, format!("{:?}", value))),
+        }
+    }
+}
+
+/// Sends spans and metrics to an OpenTelemetry collector
+#[derive(Clone, Debug)]
+pub struct OtlpExporter {
+    /// Address of the OTLP/HTTP receiver of the collector
+    endpoint: SocketAddr,
+    service_name: String,
+}
+
+impl OtlpExporter {
+    pub fn new(endpoint: SocketAddr, service_name: impl Into<String>) -> Self {
+        Self {
+            endpoint,
+            service_name: service_name.into(),
+        }
+    }
+
+    /// Exporter to the configured collector, if any
+    pub fn from_config(config: &TelemetryConfig) -> Option<Self> {
+        config
+            .otlp_endpoint()
+            .map(|endpoint| Self::new(endpoint, config.service_name()))
+    }
+
+    pub fn endpoint(&self) -> SocketAddr {
+        self.endpoint
+    }
+
+    pub fn export_spans(&self, spans: &[SpanRecord]) -> Result<(), P2pError> {
+        let spans = spans.iter().map(span_json).collect::<Vec<_>>();
+        self.post(
+            "/v1/traces",
+            &json!({
+                "resourceSpans": [{
+                    "resource": self.resource(),
+                    "scopeSpans": [{ "scope": { "name": "dagchain" }, "spans": spans }],
+                }]
+            }),
+        )
+    }
+
+    pub fn export_metrics(&self, snapshot: &MetricsSnapshot) -> Result<(), P2pError> {
+        let now = unix_nanos(SystemTime::now());
+        let metrics = snapshot
+            .samples()
+            .into_iter()
+            .map(|(name, kind, help, value)| {
+                let points = json!({ "dataPoints": [{ "asDouble": value, "timeUnixNano": now }] });
+                let mut metric =
+                    json!({ "name": format!("dagchain.{}", name), "description": help });
+                match kind {
+                    "counter" => {
+                        metric["sum"] = points;
+                        // Cumulative since startup
+                        metric["sum"]["aggregationTemporality"] = json!(2);
+                        metric["sum"]["isMonotonic"] = json!(true);
+                    }
+                    _ => metric["gauge"] = points,
+                }
+                metric
+            })
+            .collect::<Vec<_>>();
+        self.post(
+            "/v1/metrics",
+            &json!({
+                "resourceMetrics": [{
+                    "resource": self.resource(),
+                    "scopeMetrics": [{ "scope": { "name": "dagchain" }, "metrics": metrics }],
+                }]
+            }),
+        )
+    }
+
+    fn resource(&self) -> Value {
+        json!({ "attributes": [attribute("service.name", &self.service_name)] })
+    }
+
+    fn post(&self, path: &str, body: &Value) -> Result<(), P2pError> {
+        let body = body.to_string();
+        let mut stream = TcpStream::connect_timeout(&self.endpoint, EXPORT_TIMEOUT)
+            .map_err(P2pError::IoError)?;
+        stream
+            .set_read_timeout(Some(EXPORT_TIMEOUT))
+            .and_then(|_| stream.set_write_timeout(Some(EXPORT_TIMEOUT)))
+            .map_err(P2pError::IoError)?;
+        let request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            path,
+            self.endpoint,
+            body.len(),
+            body
+        );
+        stream
+            .write_all(request.as_bytes())
+            .map_err(P2pError::IoError)?;
+        let mut status = String::new();
+        let _ = BufReader::new(stream)
+            .read_line(&mut status)
+            .map_err(P2pError::IoError)?;
+        match status.split_whitespace().nth(1) {
+            Some(code) if code.starts_with('2') => Ok(()),
+            _ => Err(P2pError::CustomError(format!(
+                "Collector refused the export: {}",
+                status.trim()
+            ))),
+        }
+    }
+}
+
+fn span_json(span: &SpanRecord) -> Value {
+    let mut value = json!({
+        "traceId": span.context.trace_id_hex(),
+        "spanId": span.context.span_id_hex(),
+        "name": span.name,
+        // Internal
+        "kind": 1,
+        "startTimeUnixNano": unix_nanos(span.start),
+        "endTimeUnixNano": unix_nanos(span.end),
+        "attributes": span
+            .attributes
+            .iter()
+            .map(|(key, value)| attribute(key, value))
+            .collect::<Vec<_>>(),
+    });
+    if let Some(parent) = span.parent_span_id {
+        value["parentSpanId"] = json!(encode_hex(&parent));
+    }
+    value
+}
+
+fn attribute(key: &str, value: &str) -> Value {
+    json!({ "key": key, "value": { "stringValue": value } })
+}
+
+/// Nanoseconds since the Unix epoch, which OTLP/JSON encodes as a string
+fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
+
+fn log_level(metadata: &Metadata<'_>) -> log::LevelFilter {
+    match *metadata.level() {
+        tracing::Level::ERROR => log::LevelFilter::Error,
+        tracing::Level::WARN => log::LevelFilter::Warn,
+        tracing::Level::INFO => log::LevelFilter::Info,
+        tracing::Level::DEBUG => log::LevelFilter::Debug,
+        tracing::Level::TRACE => log::LevelFilter::Trace,
+    }
+}
+
+fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0; N];
+    bytes.copy_from_slice(&Hash::generate_random().as_ref()[..N]);
+    bytes
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{:02x}", byte);
+        hex
+    })
+}
+
+fn decode_hex<const N: usize>(hex: &str) -> Option<[u8; N]> {
+    if hex.len() != 2 * N {
+        return None;
+    }
+    let mut bytes = [0; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(2 * i..2 * i + 2)?, 16).ok()?;
+    }
+    Some(bytes)
+}
+
+#[test]
+fn test_traces_stitch_across_nodes_and_export() {
+    use super::message::{Envelope, Message};
+    use consensus::NodeId;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    let collector = OtlpCollector::new();
+    let received = tracing::subscriber::with_default(collector.clone(), || {
+        let round = tracing::info_span!("consensus_round");
+        let _round = round.enter();
+        let envelope = Envelope::new(NodeId::default(), Message::CompleteRound, 3);
+        let context = envelope.trace.unwrap();
+        assert_eq!(TraceContext::current(), Some(context));
+
+        // The next hop handles the envelope under the round of the sender
+        let remote = OtlpCollector::new();
+        tracing::subscriber::with_default(remote.clone(), || drop(envelope.span()));
+        let handled = remote.finished_spans().pop().unwrap();
+        assert_eq!(handled.name, "message");
+        assert_eq!(handled.context.trace_id, context.trace_id);
+        assert_eq!(handled.parent_span_id, Some(context.span_id));
+        context
+    });
+    let round = collector.finished_spans().pop().unwrap();
+    assert_eq!(round.context, received);
+    assert_eq!(round.parent_span_id, None);
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let exporter = OtlpExporter::new(listener.local_addr().unwrap(), "test");
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut request = String::new();
+        let mut len = 0;
+        loop {
+            let mut line = String::new();
+            let _ = reader.read_line(&mut line).unwrap();
+            if let Some(value) = line.strip_prefix("Content-Length: ") {
+                len = value.trim().parse().unwrap();
+            }
+            request.push_str(&line);
+            if line == "\r\n" {
+                break;
+            }
+        }
+        let mut body = vec![0; len];
+        reader.read_exact(&mut body).unwrap();
+        stream.write_all(b"HTTP/1.1 200 OK\r\n\r\n").unwrap();
+        request + &String::from_utf8(body).unwrap()
+    });
+    exporter.export_spans(&[round]).unwrap();
+    let request = server.join().unwrap();
+    assert!(request.starts_with("POST /v1/traces HTTP/1.1"));
+    assert!(request.contains(&format!("\"traceId\":\"{}\"", received.trace_id_hex())));
+    assert!(request.contains("\"name\":\"consensus_round\""));
+}
@@ -0,0 +1,208 @@
+//! Tracking of the messages handed to QUIC.
+//!
+//! Every send is tagged with a token mapped to what was sent, so that when
+//! QUIC reports a message as unsent we know whether to retry it or give up on
+//! it, following the policy of its class.
+
+use super::{config::SendConfig, message::Message};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Instant;
+
+/// Token of sends that are not tracked, requeued blindly when unsent
+pub const UNTRACKED_TOKEN: u64 = 0;
+
+/// Classes of messages sharing a retry and timeout policy
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub enum MessageClass {
+    /// Identification, contacts and other connection housekeeping
+    Control,
+    /// Consensus queries and answers, useless once their round is over
+    Consensus,
+    /// Routing tables and route discovery
+    Routing,
+    /// Messages of the application
+    User,
+}
+
+impl MessageClass {
+    /// Class of a message. Relayed messages take the class of the first
+    /// envelope they carry. Fragments are sent under the class of the
+    /// message they are part of, rather than their own.
+    pub fn of(message: &Message) -> Self {
+        use Message::*;
+        match message {
+            Hello { .. }
+            | Identification { .. }
+            | Contacts(_)
+            | CapacityAdvertisement(_)
+            | Disconnecting
+            | ObservedAddress(_)
+            | PunchRequest { .. }
+            | Punch { .. }
+            | BenchmarkControl { .. }
+            | AdminRequest(_) => MessageClass::Control,
+            ConsensusRequest { .. }
+            | DagConsensusRequest { .. }
+            | DagConsensusResponse { .. }
+            | CompleteRound
+            | BatchedConsensusRequest { .. }
+            | BatchedConsensusResponse { .. }
+            | StateDigest { .. }
+            | FinalityClaims { .. } => MessageClass::Consensus,
+            RoutingTable { .. } | Departed { .. } | RouteRequest { .. } | RouteReply { .. } => {
+                MessageClass::Routing
+            }
+            AgentMessage { payload } => payload
+                .first()
+                .map_or(MessageClass::User, |envelope| Self::of(&envelope.message)),
+            UserMessage(_)
+            | Gossip(_)
+            | EncryptedMessage(_)
+            | AuthenticatedMessage { .. }
+            | SignedMessage { .. }
+            | Fragment(_) => MessageClass::User,
+        }
+    }
+}
+
+/// What was sent under a token
+#[derive(Clone, Debug, PartialEq)]
+pub struct TokenInfo {
+    pub class: MessageClass,
+    pub peer: SocketAddr,
+    /// Time after which the message is given up on
+    pub deadline: Instant,
+    pub retries: u32,
+}
+
+/// What to do with a message QUIC failed to send
+#[derive(Clone, Debug, PartialEq)]
+pub enum Unsent {
+    /// Send it again under the same token
+    Retry,
+    /// Out of retries or past its deadline, give up on it
+    Failed(TokenInfo),
+    /// Not sent under a token we allocated
+    Untracked,
+}
+
+/// Allocates the tokens of the messages handed to QUIC
+#[derive(Clone, Debug)]
+pub struct Tokens {
+    next: u64,
+    in_flight: HashMap<u64, TokenInfo>,
+    config: SendConfig,
+}
+
+impl Default for Tokens {
+    fn default() -> Self {
+        Self::new(SendConfig::default())
+    }
+}
+
+impl Tokens {
+    pub fn new(config: SendConfig) -> Self {
+        Self {
+            next: UNTRACKED_TOKEN + 1,
+            in_flight: HashMap::new(),
+            config,
+        }
+    }
+
+    /// Messages sent and not yet confirmed or given up on
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    pub fn get(&self, token: u64) -> Option<&TokenInfo> {
+        self.in_flight.get(&token)
+    }
+
+    /// Allocate the token of a message of `class` sent to `peer` at `now`
+    pub fn allocate(&mut self, class: MessageClass, peer: SocketAddr, now: Instant) -> u64 {
+        let token = self.next;
+        self.next = self.next.checked_add(1).unwrap_or(UNTRACKED_TOKEN + 1);
+        let _ = self.in_flight.insert(
+            token,
+            TokenInfo {
+                class,
+                peer,
+                deadline: now + self.config.get(class).timeout(),
+                retries: 0,
+            },
+        );
+        token
+    }
+
+    /// Release the token of a message QUIC sent
+    pub fn sent(&mut self, token: u64) -> Option<TokenInfo> {
+        self.in_flight.remove(&token)
+    }
+
+    /// Decide what to do with a message QUIC failed to send
+    pub fn unsent(&mut self, token: u64, now: Instant) -> Unsent {
+        let info = match self.in_flight.get_mut(&token) {
+            Some(info) => info,
+            None => return Unsent::Untracked,
+        };
+        if info.retries < self.config.get(info.class).max_retries() && now < info.deadline {
+            info.retries += 1;
+            return Unsent::Retry;
+        }
+        Unsent::Failed(self.in_flight.remove(&token).unwrap())
+    }
+
+    /// Give up on the messages past their deadline, QUIC never having
+    /// reported them as sent
+    pub fn expire(&mut self, now: Instant) -> Vec<TokenInfo> {
+        let expired = self
+            .in_flight
+            .iter()
+            .filter(|(_, info)| info.deadline <= now)
+            .map(|(token, _)| *token)
+            .collect::<Vec<_>>();
+        expired
+            .into_iter()
+            .filter_map(|token| self.in_flight.remove(&token))
+            .collect()
+    }
+}
+
+#[test]
+fn test_unsent_messages_follow_their_policy() {
+    use super::config::SendPolicy;
+    use std::time::Duration;
+
+    let peer = SocketAddr::from(([127, 0, 0, 1], 5000));
+    let mut config = SendConfig::default();
+    config.set(MessageClass::Consensus, SendPolicy::new(1_000, 2));
+    let mut tokens = Tokens::new(config);
+    let now = Instant::now();
+
+    let token = tokens.allocate(MessageClass::Consensus, peer, now);
+    assert_ne!(token, UNTRACKED_TOKEN);
+    assert_eq!(tokens.unsent(token, now), Unsent::Retry);
+    assert_eq!(tokens.unsent(token, now), Unsent::Retry);
+    assert!(matches!(
+        tokens.unsent(token, now),
+        Unsent::Failed(TokenInfo { retries: 2, .. })
+    ));
+    assert_eq!(tokens.unsent(token, now), Unsent::Untracked);
+
+    // Past its deadline, a message is not retried
+    let token = tokens.allocate(MessageClass::Consensus, peer, now);
+    let late = now + Duration::from_millis(1_000);
+    assert!(matches!(tokens.unsent(token, late), Unsent::Failed(_)));
+
+    let sent = tokens.allocate(MessageClass::Consensus, peer, now);
+    let stale = tokens.allocate(MessageClass::Consensus, peer, now);
+    let user = tokens.allocate(MessageClass::User, peer, now);
+    assert!(tokens.sent(sent).is_some());
+    let expired = tokens.expire(late);
+    assert_eq!(expired.len(), 1);
+    assert!(tokens.get(stale).is_none());
+    assert!(tokens.get(user).is_some());
+    assert_eq!(tokens.in_flight(), 1);
+}
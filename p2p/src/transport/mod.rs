@@ -0,0 +1,80 @@
+//! Transports carrying messages between peers.
+//!
+//! Connections and messaging only ever connect to, disconnect from and send
+//! to peers by address, so they do so through the [`Transport`] trait rather
+//! than quic-p2p itself. QUIC remains the default; [`tcp::TcpTransport`]
+//! reaches peers behind firewalls dropping UDP. Both report what happens on
+//! the wire as quic-p2p events, so the node handles them the same way
+//! whichever transport runs.
+
+pub mod tcp;
+
+use crate::{
+    error::P2pError,
+    node::config::{P2pConfig, TransportKind},
+};
+use bytes::Bytes;
+use quic_p2p::{EventSenders, Peer, QuicP2p};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use tcp::TcpTransport;
+
+/// Connections to peers, and messages sent over them
+pub trait Transport {
+    fn kind(&self) -> TransportKind;
+
+    /// Address peers reach us at
+    fn our_addr(&mut self) -> Result<SocketAddr, P2pError>;
+
+    /// Connect to a peer, reporting the outcome as an event
+    fn connect_to(&mut self, peer: SocketAddr);
+
+    fn disconnect_from(&mut self, peer: SocketAddr);
+
+    /// Send a message to a peer, reporting whether it was sent as an event
+    /// carrying `token`
+    fn send(&mut self, peer: SocketAddr, msg: Bytes, token: u64);
+}
+
+impl Transport for QuicP2p {
+    fn kind(&self) -> TransportKind {
+        TransportKind::Quic
+    }
+
+    fn our_addr(&mut self) -> Result<SocketAddr, P2pError> {
+        self.our_connection_info().map_err(P2pError::QuicP2pError)
+    }
+
+    fn connect_to(&mut self, peer: SocketAddr) {
+        QuicP2p::connect_to(self, peer)
+    }
+
+    fn disconnect_from(&mut self, peer: SocketAddr) {
+        QuicP2p::disconnect_from(self, peer)
+    }
+
+    fn send(&mut self, peer: SocketAddr, msg: Bytes, token: u64) {
+        QuicP2p::send(self, Peer::Node(peer), msg, token)
+    }
+}
+
+/// Start the transport selected in `config`, reporting its events to
+/// `events`
+pub fn open(config: &P2pConfig, events: EventSenders) -> Result<Box<dyn Transport>, P2pError> {
+    let quic = config.get_quic_config();
+    match config.get_transport_config().kind() {
+        TransportKind::Quic => {
+            let bootstrap_nodes = config.get_bootstrap_contacts().copied().collect();
+            let quic = QuicP2p::with_config(events, Some(quic), bootstrap_nodes, false)
+                .map_err(P2pError::QuicP2pError)?;
+            Ok(Box::new(quic))
+        }
+        TransportKind::Tcp => {
+            let ip = quic.ip.unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+            let addr = SocketAddr::new(ip, quic.port.unwrap_or_default());
+            let transport = TcpTransport::bind(addr, move |event| {
+                let _ = events.node_tx.send(event);
+            })?;
+            Ok(Box::new(transport))
+        }
+    }
+}
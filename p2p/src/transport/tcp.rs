@@ -0,0 +1,282 @@
+//! Transport of length-prefixed frames over TCP.
+//!
+//! Peers are known by the address they listen at, not the ephemeral port
+//! they connect from, so the first frame sent over every connection is the
+//! listening address of the side that opened it.
+
+use super::Transport;
+use crate::{
+    error::P2pError,
+    node::{codec, config::TransportKind},
+};
+use bytes::Bytes;
+use quic_p2p::{Event, Peer, QuicP2pError};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Largest frame accepted from a peer
+pub const MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Largest handshake, an encoded socket address
+const MAX_HANDSHAKE_LEN: u64 = 64;
+
+type Streams = Arc<Mutex<HashMap<SocketAddr, TcpStream>>>;
+/// Where events are reported
+type Report = Arc<dyn Fn(Event) + Send + Sync>;
+
+/// Connections to peers over TCP
+pub struct TcpTransport {
+    our_addr: SocketAddr,
+    streams: Streams,
+    report: Report,
+    stopped: Arc<AtomicBool>,
+}
+
+impl TcpTransport {
+    /// Listen for peers at `addr`, port 0 picking any free port, passing
+    /// the events of the transport to `report`
+    pub fn bind<F>(addr: SocketAddr, report: F) -> Result<Self, P2pError>
+    where
+        F: Fn(Event) + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind(addr).map_err(P2pError::IoError)?;
+        let transport = Self {
+            our_addr: listener.local_addr().map_err(P2pError::IoError)?,
+            streams: Default::default(),
+            report: Arc::new(report),
+            stopped: Default::default(),
+        };
+        let (streams, report, stopped) = (
+            transport.streams.clone(),
+            transport.report.clone(),
+            transport.stopped.clone(),
+        );
+        let _ = std::thread::Builder::new()
+            .name("tcp-listener".to_string())
+            .spawn(move || accept(listener, streams, report, stopped))
+            .map_err(P2pError::IoError)?;
+        Ok(transport)
+    }
+
+    fn report_failure(&self, peer: SocketAddr, error: io::Error) {
+        (self.report)(Event::ConnectionFailure {
+            peer: Peer::Node(peer),
+            err: QuicP2pError::Io(error),
+        });
+    }
+}
+
+impl Transport for TcpTransport {
+    fn kind(&self) -> TransportKind {
+        TransportKind::Tcp
+    }
+
+    fn our_addr(&mut self) -> Result<SocketAddr, P2pError> {
+        Ok(self.our_addr)
+    }
+
+    fn connect_to(&mut self, peer: SocketAddr) {
+        let connected =
+            TcpStream::connect_timeout(&peer, CONNECT_TIMEOUT).and_then(|mut stream| {
+                let handshake = bincode::serialize(&self.our_addr)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                write_frame(&mut stream, &handshake)?;
+                Ok(stream)
+            });
+        match connected {
+            Ok(stream) => {
+                if let Err(e) = register(stream, peer, &self.streams, &self.report, &self.stopped) {
+                    self.report_failure(peer, e);
+                }
+            }
+            Err(e) => self.report_failure(peer, e),
+        }
+    }
+
+    fn disconnect_from(&mut self, peer: SocketAddr) {
+        if let Some(stream) = self.streams.lock().unwrap().remove(&peer) {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+
+    fn send(&mut self, peer: SocketAddr, msg: Bytes, token: u64) {
+        let sent = match self.streams.lock().unwrap().get_mut(&peer) {
+            Some(stream) => write_frame(stream, &msg),
+            None => Err(io::ErrorKind::NotConnected.into()),
+        };
+        let event = match sent {
+            Ok(()) => Event::SentUserMessage {
+                peer: Peer::Node(peer),
+                msg,
+                token,
+            },
+            Err(e) => {
+                log::debug!("Failed to send to {:?} over TCP: {:?}", peer, e);
+                Event::UnsentUserMessage {
+                    peer: Peer::Node(peer),
+                    msg,
+                    token,
+                }
+            }
+        };
+        (self.report)(event);
+    }
+}
+
+impl Drop for TcpTransport {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        for (_, stream) in self.streams.lock().unwrap().drain() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+        // Wake the listener up so that it sees we stopped
+        let _ = TcpStream::connect_timeout(&self.our_addr, CONNECT_TIMEOUT);
+    }
+}
+
+/// Accept peers until the transport is dropped
+fn accept(listener: TcpListener, streams: Streams, report: Report, stopped: Arc<AtomicBool>) {
+    for stream in listener.incoming() {
+        if stopped.load(Ordering::SeqCst) {
+            break;
+        }
+        let accepted = stream.and_then(|mut stream| {
+            let handshake = read_frame(&mut stream)?;
+            let peer = codec::decode::<SocketAddr>(&handshake, MAX_HANDSHAKE_LEN)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            register(stream, peer, &streams, &report, &stopped)
+        });
+        if let Err(e) = accepted {
+            log::debug!("Failed to accept a TCP connection: {:?}", e);
+        }
+    }
+}
+
+/// Keep a connection to `peer` and read its frames on a thread of its own
+fn register(
+    stream: TcpStream,
+    peer: SocketAddr,
+    streams: &Streams,
+    report: &Report,
+    stopped: &Arc<AtomicBool>,
+) -> io::Result<()> {
+    let mut reader = stream.try_clone()?;
+    let _ = streams.lock().unwrap().insert(peer, stream);
+    let (streams, report, stopped) = (streams.clone(), report.clone(), stopped.clone());
+    report(Event::ConnectedTo {
+        peer: Peer::Node(peer),
+    });
+    let _ = std::thread::Builder::new()
+        .name(format!("tcp-{}", peer))
+        .spawn(move || loop {
+            match read_frame(&mut reader) {
+                Ok(msg) => {
+                    report(Event::NewMessage {
+                        peer: Peer::Node(peer),
+                        msg,
+                    });
+                }
+                Err(e) => {
+                    // Connections we closed ourselves were already forgotten
+                    let lost = streams.lock().unwrap().remove(&peer).is_some();
+                    if lost && !stopped.load(Ordering::SeqCst) {
+                        report(Event::ConnectionFailure {
+                            peer: Peer::Node(peer),
+                            err: QuicP2pError::Io(e),
+                        });
+                    }
+                    break;
+                }
+            }
+        })?;
+    Ok(())
+}
+
+fn write_frame(writer: &mut impl Write, frame: &[u8]) -> io::Result<()> {
+    if frame.len() > MAX_FRAME_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Frame of {} bytes is too large", frame.len()),
+        ));
+    }
+    writer.write_all(&(frame.len() as u32).to_be_bytes())?;
+    writer.write_all(frame)?;
+    writer.flush()
+}
+
+fn read_frame(reader: &mut impl Read) -> io::Result<Bytes> {
+    let mut len = [0; 4];
+    reader.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Frame of {} bytes is too large", len),
+        ));
+    }
+    let mut frame = vec![0; len];
+    reader.read_exact(&mut frame)?;
+    Ok(Bytes::from(frame))
+}
+
+#[test]
+fn test_tcp_transport_exchanges_frames() {
+    let localhost = SocketAddr::from(([127, 0, 0, 1], 0));
+    let (a_tx, a_rx) = crossbeam_channel::unbounded();
+    let (b_tx, b_rx) = crossbeam_channel::unbounded();
+    let mut a = TcpTransport::bind(localhost, move |event| a_tx.send(event).unwrap()).unwrap();
+    let mut b = TcpTransport::bind(localhost, move |event| b_tx.send(event).unwrap()).unwrap();
+    let (a_addr, b_addr) = (a.our_addr().unwrap(), b.our_addr().unwrap());
+    let timeout = Duration::from_secs(5);
+
+    a.connect_to(b_addr);
+    assert!(matches!(
+        a_rx.recv_timeout(timeout).unwrap(),
+        Event::ConnectedTo { peer } if peer.peer_addr() == b_addr
+    ));
+    // B knows A by its listening address
+    assert!(matches!(
+        b_rx.recv_timeout(timeout).unwrap(),
+        Event::ConnectedTo { peer } if peer.peer_addr() == a_addr
+    ));
+
+    a.send(b_addr, Bytes::from_static(b"ping"), 7);
+    assert!(matches!(
+        a_rx.recv_timeout(timeout).unwrap(),
+        Event::SentUserMessage { token: 7, .. }
+    ));
+    match b_rx.recv_timeout(timeout).unwrap() {
+        Event::NewMessage { peer, msg } => {
+            assert_eq!(peer.peer_addr(), a_addr);
+            assert_eq!(&msg[..], b"ping");
+        }
+        other => panic!("Unexpected {:?}", other),
+    }
+    b.send(a_addr, Bytes::from_static(b"pong"), 8);
+    assert!(matches!(
+        a_rx.recv_timeout(timeout).unwrap(),
+        Event::NewMessage { msg, .. } if &msg[..] == b"pong"
+    ));
+
+    a.disconnect_from(b_addr);
+    a.send(b_addr, Bytes::from_static(b"gone"), 9);
+    assert!(matches!(
+        a_rx.recv_timeout(timeout).unwrap(),
+        Event::UnsentUserMessage { token: 9, .. }
+    ));
+    assert!(matches!(
+        b_rx.recv_timeout(timeout).unwrap(),
+        Event::SentUserMessage { token: 8, .. }
+    ));
+    assert!(matches!(
+        b_rx.recv_timeout(timeout).unwrap(),
+        Event::ConnectionFailure { peer, .. } if peer.peer_addr() == a_addr
+    ));
+
+    let mut oversized = &(MAX_FRAME_LEN as u32 + 1).to_be_bytes()[..];
+    assert!(read_frame(&mut oversized).is_err());
+}
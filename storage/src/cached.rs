@@ -0,0 +1,181 @@
+//! Read cache over a storage.
+//!
+//! Accounts hot during consensus are read far more often than they change.
+//! A [`CachedStorage`] keeps the values read or written most recently in
+//! memory, evicting the least recently used one once it holds `capacity`
+//! of them. Writes go through to the storage before the cache is updated,
+//! so the storage never lags behind what the cache answered.
+
+use crate::{error::StorageError, Storage};
+use crypto::hash::Hash;
+use metrics::Metrics;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// Values cached by default
+pub const DEFAULT_CACHE_CAPACITY: usize = 4096;
+
+#[derive(Debug, Default)]
+struct Lru {
+    /// Cached values and when they were last used
+    values: HashMap<Hash, (Vec<u8>, u64)>,
+    /// Keys by when they were last used, the least recent first
+    recency: BTreeMap<u64, Hash>,
+    clock: u64,
+}
+
+impl Lru {
+    fn get(&mut self, key: &Hash) -> Option<Vec<u8>> {
+        self.clock += 1;
+        let (value, used) = self.values.get_mut(key)?;
+        let _ = self.recency.remove(used);
+        *used = self.clock;
+        let _ = self.recency.insert(self.clock, *key);
+        Some(value.clone())
+    }
+
+    fn put(&mut self, key: Hash, value: Vec<u8>, capacity: usize) {
+        self.clock += 1;
+        if let Some((_, used)) = self.values.insert(key, (value, self.clock)) {
+            let _ = self.recency.remove(&used);
+        }
+        let _ = self.recency.insert(self.clock, key);
+        while self.values.len() > capacity {
+            match self.recency.pop_first() {
+                Some((_, oldest)) => {
+                    let _ = self.values.remove(&oldest);
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+/// Storage whose values are cached in memory, least recently used first
+/// evicted
+pub struct CachedStorage<S: Storage> {
+    storage: S,
+    cache: Mutex<Lru>,
+    capacity: usize,
+    metrics: Arc<Metrics>,
+}
+
+impl<S: Storage> CachedStorage<S> {
+    /// Cache up to `capacity` values of `storage`
+    pub fn with_capacity(storage: S, capacity: usize) -> Self {
+        Self {
+            storage,
+            cache: Default::default(),
+            capacity,
+            metrics: Default::default(),
+        }
+    }
+
+    /// Count cache hits and misses in `metrics`
+    pub fn set_metrics(&mut self, metrics: Arc<Metrics>) -> &mut Self {
+        self.metrics = metrics;
+        self
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Number of values cached
+    pub fn cached(&self) -> usize {
+        self.cache.lock().unwrap().values.len()
+    }
+
+    pub fn storage(&self) -> &S {
+        &self.storage
+    }
+
+    /// Retrieve the underlying storage
+    pub fn into_inner(self) -> S {
+        self.storage
+    }
+}
+
+impl<S: Storage> Storage for CachedStorage<S> {
+    /// Create new storage, caching up to [`DEFAULT_CACHE_CAPACITY`] values
+    fn new(path: Option<&Path>) -> Result<Self, StorageError> {
+        Ok(Self::with_capacity(S::new(path)?, DEFAULT_CACHE_CAPACITY))
+    }
+
+    /// Open a keyspace of the underlying storage, with a cache of its own
+    fn open_tree(&self, name: &str) -> Result<Self, StorageError> {
+        let mut tree = Self::with_capacity(self.storage.open_tree(name)?, self.capacity);
+        let _ = tree.set_metrics(self.metrics.clone());
+        Ok(tree)
+    }
+
+    /// Insert data in the storage, then in the cache
+    fn insert(&mut self, key: Hash, value: Vec<u8>) -> Result<(), StorageError> {
+        self.storage.insert(key, value.clone())?;
+        if self.capacity > 0 {
+            self.cache.get_mut().unwrap().put(key, value, self.capacity);
+        }
+        Ok(())
+    }
+
+    /// Get data from the cache, or from the storage on a miss
+    fn get(&self, key: Hash) -> Result<Vec<u8>, StorageError> {
+        if let Some(value) = self.cache.lock().unwrap().get(&key) {
+            self.metrics.storage_cache_hit();
+            return Ok(value);
+        }
+        self.metrics.storage_cache_missed();
+        let value = self.storage.get(key)?;
+        if self.capacity > 0 {
+            self.cache
+                .lock()
+                .unwrap()
+                .put(key, value.clone(), self.capacity);
+        }
+        Ok(value)
+    }
+
+    /// Flush data
+    fn flush(&mut self) -> Result<(), StorageError> {
+        self.storage.flush()
+    }
+
+    /// Size of the stored data in bytes
+    fn size_on_disk(&self) -> Result<u64, StorageError> {
+        self.storage.size_on_disk()
+    }
+}
+
+#[test]
+fn test_cached_storage_evicts_least_recently_used() {
+    use crate::memory::MemoryStorage;
+
+    let metrics = Arc::new(Metrics::new());
+    let backend = MemoryStorage::new(None).unwrap();
+    let mut storage = CachedStorage::with_capacity(backend.open_tree("accounts").unwrap(), 2);
+    let _ = storage.set_metrics(metrics.clone());
+    let key = |name: &str| Hash::new(name.as_bytes());
+
+    storage.insert(key("a"), vec![1]).unwrap();
+    storage.insert(key("b"), vec![2]).unwrap();
+    // Written through
+    assert_eq!(
+        backend
+            .open_tree("accounts")
+            .unwrap()
+            .get(key("a"))
+            .unwrap(),
+        vec![1]
+    );
+    assert_eq!(storage.get(key("a")).unwrap(), vec![1]);
+    storage.insert(key("c"), vec![3]).unwrap();
+    assert_eq!(storage.cached(), 2);
+
+    // "b" was the least recently used, and is read from the storage again
+    assert_eq!(storage.get(key("b")).unwrap(), vec![2]);
+    assert!(storage.get(key("d")).is_err());
+    let snapshot = metrics.snapshot();
+    assert_eq!(snapshot.storage_cache_hits, 1);
+    assert_eq!(snapshot.storage_cache_misses, 2);
+}
@@ -8,6 +8,12 @@ pub enum StorageError {
     SledError(sled::Error),
     #[error("Option<None>: an error!")]
     NoneError,
+    #[error("Serialization error: {0}")]
+    SerializationError(String),
+    #[error("Value of schema version {found}, expected {expected}")]
+    SchemaMismatch { expected: u32, found: u32 },
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
 }
 
 impl From<sled::Error> for StorageError {
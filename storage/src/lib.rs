@@ -3,11 +3,17 @@
 use crypto::hash::Hash;
 use serde::{Deserialize, Serialize};
 
+pub mod cached;
 pub mod error;
 pub mod memory;
 pub mod sled;
+pub mod typed;
+pub mod wal;
 
+pub use cached::CachedStorage;
 pub use error::StorageError;
+pub use typed::TypedStore;
+pub use wal::{WalStorage, WriteBatch};
 
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub enum StorageType {
@@ -21,6 +27,13 @@ pub trait Storage: Send + Sync {
     where
         Self: std::marker::Sized;
 
+    /// Open the keyspace `name` of the storage, creating it if needed.
+    /// Its keys never collide with those of other keyspaces, nor with the
+    /// keys of the storage it was opened from.
+    fn open_tree(&self, name: &str) -> Result<Self, StorageError>
+    where
+        Self: std::marker::Sized;
+
     /// Insert data
     fn insert(&mut self, key: Hash, value: Vec<u8>) -> Result<(), StorageError>;
 
@@ -29,4 +42,7 @@ pub trait Storage: Send + Sync {
 
     /// Flush data
     fn flush(&mut self) -> Result<(), StorageError>;
+
+    /// Size of the stored data in bytes
+    fn size_on_disk(&self) -> Result<u64, StorageError>;
 }
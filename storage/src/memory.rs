@@ -1,30 +1,56 @@
 use crate::{error::StorageError, Storage};
 use crypto::hash::Hash;
 use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
 
+/// Keyspaces opened from the same storage share its map, each under the
+/// prefix naming it
 pub struct MemoryStorage {
-    storage: HashMap<Hash, Vec<u8>>,
+    storage: Arc<RwLock<HashMap<Vec<u8>, Vec<u8>>>>,
+    prefix: Vec<u8>,
+}
+
+impl MemoryStorage {
+    fn key(&self, key: &Hash) -> Vec<u8> {
+        let mut prefixed = self.prefix.clone();
+        prefixed.extend_from_slice(key.as_ref());
+        prefixed
+    }
 }
 
 impl Storage for MemoryStorage {
     /// Create new storage for DAGchain
     fn new(_p: Option<&std::path::Path>) -> Result<Self, StorageError> {
         Ok(MemoryStorage {
-            storage: HashMap::new(),
+            storage: Default::default(),
+            prefix: vec![],
+        })
+    }
+
+    /// Open a keyspace under the prefix of `name`
+    fn open_tree(&self, name: &str) -> Result<Self, StorageError> {
+        // Length-prefixed, so that no name is the prefix of another
+        let mut prefix = self.prefix.clone();
+        prefix.extend_from_slice(&(name.len() as u64).to_be_bytes());
+        prefix.extend_from_slice(name.as_bytes());
+        Ok(MemoryStorage {
+            storage: self.storage.clone(),
+            prefix,
         })
     }
 
     /// Insert data
     fn insert(&mut self, key: Hash, value: Vec<u8>) -> Result<(), StorageError> {
-        self.storage.insert(key, value);
+        let key = self.key(&key);
+        self.storage.write().unwrap().insert(key, value);
         Ok(())
     }
 
     /// Get data
     fn get(&self, key: Hash) -> Result<Vec<u8>, StorageError> {
-        match self.storage.get(&key) {
+        match self.storage.read().unwrap().get(&self.key(&key)) {
             Some(data) => Ok(data.to_vec()),
-            None => Err(StorageError::NoneError.into()),
+            None => Err(StorageError::NoneError),
         }
     }
 
@@ -32,4 +58,39 @@ impl Storage for MemoryStorage {
     fn flush(&mut self) -> Result<(), StorageError> {
         Ok(())
     }
+
+    /// Size of the stored data in bytes, keyspaces opened from it included
+    fn size_on_disk(&self) -> Result<u64, StorageError> {
+        Ok(self
+            .storage
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(key, _)| key.starts_with(&self.prefix))
+            .map(|(key, value)| (key.len() + value.len()) as u64)
+            .sum())
+    }
+}
+
+#[test]
+fn test_trees_are_isolated() {
+    let mut storage = MemoryStorage::new(None).unwrap();
+    let mut accounts = storage.open_tree("accounts").unwrap();
+    let mut routes = storage.open_tree("routes").unwrap();
+    let key = Hash::new("key".as_bytes());
+
+    accounts.insert(key, b"account".to_vec()).unwrap();
+    routes.insert(key, b"route".to_vec()).unwrap();
+    assert!(storage.get(key).is_err());
+    storage.insert(key, b"root".to_vec()).unwrap();
+    assert_eq!(accounts.get(key).unwrap(), b"account");
+    assert_eq!(routes.get(key).unwrap(), b"route");
+    assert_eq!(storage.get(key).unwrap(), b"root");
+
+    // Handles opened again share the same keyspace
+    let reopened = storage.open_tree("accounts").unwrap();
+    assert_eq!(reopened.get(key).unwrap(), b"account");
+    let nested = accounts.open_tree("routes").unwrap();
+    assert!(nested.get(key).is_err());
+    assert!(accounts.size_on_disk().unwrap() < storage.size_on_disk().unwrap());
 }
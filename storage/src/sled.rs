@@ -1,8 +1,12 @@
 use crate::{error::StorageError, Storage};
 use crypto::hash::Hash;
 
+/// Keyspaces are sled trees, those opened from a tree being named after it
 pub struct SledStorage {
-    storage: sled::Db,
+    db: sled::Db,
+    storage: sled::Tree,
+    /// Name of the tree, empty for the default one
+    name: String,
     sync: bool,
 }
 
@@ -10,17 +14,35 @@ impl Storage for SledStorage {
     /// Create new storage for DAGchain
     fn new(path: Option<&std::path::Path>) -> Result<Self, StorageError> {
         if path.is_none() {
-            return Err(StorageError::NoneError.into());
+            return Err(StorageError::NoneError);
         }
+        let db = sled::Config::new()
+            .path(path.unwrap())
+            .print_profile_on_drop(false)
+            .open()?;
         Ok(SledStorage {
-            storage: sled::Config::new()
-                .path(path.unwrap())
-                .print_profile_on_drop(false)
-                .open()?,
+            storage: (*db).clone(),
+            db,
+            name: String::new(),
             sync: false,
         })
     }
 
+    /// Open the sled tree of `name`
+    fn open_tree(&self, name: &str) -> Result<Self, StorageError> {
+        let name = if self.name.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}/{}", self.name, name)
+        };
+        Ok(SledStorage {
+            db: self.db.clone(),
+            storage: self.db.open_tree(&name)?,
+            name,
+            sync: self.sync,
+        })
+    }
+
     /// Insert data
     fn insert(&mut self, key: Hash, value: Vec<u8>) -> Result<(), StorageError> {
         self.storage.insert(key, value)?;
@@ -34,7 +56,7 @@ impl Storage for SledStorage {
     fn get(&self, key: Hash) -> Result<Vec<u8>, StorageError> {
         match self.storage.get(key)? {
             Some(data) => Ok(data.to_vec()),
-            None => Err(StorageError::NoneError.into()),
+            None => Err(StorageError::NoneError),
         }
     }
 
@@ -43,4 +65,9 @@ impl Storage for SledStorage {
         self.storage.flush()?;
         Ok(())
     }
+
+    /// Size of the stored data in bytes, every tree included
+    fn size_on_disk(&self) -> Result<u64, StorageError> {
+        Ok(self.db.size_on_disk()?)
+    }
 }
@@ -0,0 +1,130 @@
+//! Storage of typed values.
+//!
+//! A [`TypedStore`] serializes the values it is given with bincode, behind
+//! the version of their schema. Values stored under an older schema are not
+//! deserialized as the current one: they are read raw along with their
+//! version, for the caller to migrate them.
+
+use crate::{error::StorageError, Storage};
+use crypto::hash::Hash;
+use serde::{de::DeserializeOwned, Serialize};
+use std::marker::PhantomData;
+
+/// Bytes of the schema version prefixed to every value
+const VERSION_LEN: usize = std::mem::size_of::<u32>();
+
+/// Values of type `T` stored through a [`Storage`] under schema `version`
+pub struct TypedStore<S: Storage, T> {
+    storage: S,
+    version: u32,
+    _values: PhantomData<fn() -> T>,
+}
+
+impl<S: Storage, T: Serialize + DeserializeOwned> TypedStore<S, T> {
+    pub fn new(storage: S, version: u32) -> Self {
+        Self {
+            storage,
+            version,
+            _values: PhantomData,
+        }
+    }
+
+    /// Store the values in the keyspace `name` of `storage`
+    pub fn open(storage: &S, name: &str, version: u32) -> Result<Self, StorageError> {
+        Ok(Self::new(storage.open_tree(name)?, version))
+    }
+
+    /// Version of the schema values are stored under
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn put(&mut self, key: &Hash, value: &T) -> Result<(), StorageError> {
+        let mut bytes = self.version.to_be_bytes().to_vec();
+        bincode::serialize_into(&mut bytes, value)
+            .map_err(|e| StorageError::SerializationError(e.to_string()))?;
+        self.storage.insert(*key, bytes)
+    }
+
+    /// Value stored under `key`, if any. Values of another schema are
+    /// refused, see [`TypedStore::get_raw`].
+    pub fn get(&self, key: &Hash) -> Result<Option<T>, StorageError> {
+        let (version, bytes) = match self.get_raw(key)? {
+            Some(raw) => raw,
+            None => return Ok(None),
+        };
+        if version != self.version {
+            return Err(StorageError::SchemaMismatch {
+                expected: self.version,
+                found: version,
+            });
+        }
+        bincode::deserialize(&bytes)
+            .map(Some)
+            .map_err(|e| StorageError::SerializationError(e.to_string()))
+    }
+
+    /// Schema version and serialized form of the value stored under `key`
+    pub fn get_raw(&self, key: &Hash) -> Result<Option<(u32, Vec<u8>)>, StorageError> {
+        let mut bytes = match self.storage.get(*key) {
+            Ok(bytes) => bytes,
+            Err(StorageError::NoneError) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        if bytes.len() < VERSION_LEN {
+            return Err(StorageError::SerializationError(format!(
+                "Value of {} bytes has no schema version",
+                bytes.len()
+            )));
+        }
+        let payload = bytes.split_off(VERSION_LEN);
+        let version = u32::from_be_bytes(bytes.try_into().unwrap());
+        Ok(Some((version, payload)))
+    }
+
+    pub fn contains(&self, key: &Hash) -> Result<bool, StorageError> {
+        Ok(self.get_raw(key)?.is_some())
+    }
+
+    pub fn flush(&mut self) -> Result<(), StorageError> {
+        self.storage.flush()
+    }
+
+    pub fn storage(&self) -> &S {
+        &self.storage
+    }
+
+    /// Retrieve the underlying storage
+    pub fn into_inner(self) -> S {
+        self.storage
+    }
+}
+
+#[test]
+fn test_typed_store_versions_values() {
+    use crate::memory::MemoryStorage;
+
+    let storage = MemoryStorage::new(None).unwrap();
+    let mut balances = TypedStore::<_, (String, u128)>::open(&storage, "balances", 1).unwrap();
+    let key = Hash::new("alice".as_bytes());
+    assert_eq!(balances.get(&key).unwrap(), None);
+    balances.put(&key, &("alice".to_string(), 100)).unwrap();
+    assert_eq!(
+        balances.get(&key).unwrap(),
+        Some(("alice".to_string(), 100))
+    );
+
+    // Values written under the previous schema are left to migrate
+    let upgraded = TypedStore::<_, (String, u128, u64)>::open(&storage, "balances", 2).unwrap();
+    assert!(matches!(
+        upgraded.get(&key),
+        Err(StorageError::SchemaMismatch {
+            expected: 2,
+            found: 1
+        })
+    ));
+    let (version, bytes) = upgraded.get_raw(&key).unwrap().unwrap();
+    assert_eq!(version, 1);
+    let (name, balance): (String, u128) = bincode::deserialize(&bytes).unwrap();
+    assert_eq!((name.as_str(), balance), ("alice", 100));
+}
@@ -0,0 +1,206 @@
+//! Write-ahead log of the batches inserted into a storage.
+//!
+//! A consensus decision touches several keys, which a node killed midway
+//! through would leave partially written. [`WalStorage`] first appends the
+//! whole batch to a log file and syncs it, and only then applies it to the
+//! storage. Batches logged but not known to be flushed are applied again
+//! when the storage is reopened, and the log is truncated once the storage
+//! is flushed. A batch whose record was torn by the crash never reached the
+//! storage, and is dropped.
+
+use crate::{error::StorageError, Storage};
+use crypto::hash::Hash;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Bytes of the length and checksum heading every record
+const HEADER_LEN: usize = 4 + 32;
+
+/// Inserts applied together
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct WriteBatch {
+    entries: Vec<(Hash, Vec<u8>)>,
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, key: Hash, value: Vec<u8>) -> &mut Self {
+        self.entries.push((key, value));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Storage whose batches are logged before they are applied
+pub struct WalStorage<S: Storage> {
+    storage: S,
+    log: File,
+    path: PathBuf,
+}
+
+impl<S: Storage> WalStorage<S> {
+    /// Log the batches of `storage` in the file at `path`, first applying
+    /// the batches it holds from before a crash
+    pub fn open(storage: S, path: &Path) -> Result<Self, StorageError> {
+        let log = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let mut wal = Self {
+            storage,
+            log,
+            path: path.to_path_buf(),
+        };
+        wal.replay()?;
+        Ok(wal)
+    }
+
+    /// Log `batch`, then apply it
+    pub fn write_batch(&mut self, batch: WriteBatch) -> Result<(), StorageError> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        self.append(&batch)?;
+        self.apply(batch)
+    }
+
+    /// Insert a single value, logged as a batch of its own
+    pub fn insert(&mut self, key: Hash, value: Vec<u8>) -> Result<(), StorageError> {
+        let mut batch = WriteBatch::new();
+        let _ = batch.insert(key, value);
+        self.write_batch(batch)
+    }
+
+    pub fn get(&self, key: Hash) -> Result<Vec<u8>, StorageError> {
+        self.storage.get(key)
+    }
+
+    /// Flush the storage, after which the logged batches are no longer
+    /// needed
+    pub fn flush(&mut self) -> Result<(), StorageError> {
+        self.storage.flush()?;
+        self.log.set_len(0)?;
+        self.log.sync_all()?;
+        Ok(())
+    }
+
+    /// Bytes of batches logged since the latest flush
+    pub fn log_size(&self) -> Result<u64, StorageError> {
+        Ok(self.log.metadata()?.len())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn storage(&self) -> &S {
+        &self.storage
+    }
+
+    fn append(&mut self, batch: &WriteBatch) -> Result<(), StorageError> {
+        let payload = bincode::serialize(batch)
+            .map_err(|e| StorageError::SerializationError(e.to_string()))?;
+        let mut record = Vec::with_capacity(HEADER_LEN + payload.len());
+        record.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        record.extend_from_slice(Hash::new(&payload).as_ref());
+        record.extend_from_slice(&payload);
+        let _ = self.log.seek(SeekFrom::End(0))?;
+        self.log.write_all(&record)?;
+        self.log.sync_data()?;
+        Ok(())
+    }
+
+    fn apply(&mut self, batch: WriteBatch) -> Result<(), StorageError> {
+        for (key, value) in batch.entries {
+            self.storage.insert(key, value)?;
+        }
+        Ok(())
+    }
+
+    /// Apply the batches of the log, up to the first incomplete record
+    fn replay(&mut self) -> Result<(), StorageError> {
+        let mut bytes = vec![];
+        let _ = self.log.seek(SeekFrom::Start(0))?;
+        let _ = self.log.read_to_end(&mut bytes)?;
+        let mut rest = bytes.as_slice();
+        let mut replayed = 0;
+        while let Some((batch, next)) = read_record(rest) {
+            self.apply(batch)?;
+            replayed += 1;
+            rest = next;
+        }
+        if !rest.is_empty() {
+            log::warn!(
+                "Dropped a torn batch of {} bytes from {}",
+                rest.len(),
+                self.path.display()
+            );
+        }
+        if replayed > 0 {
+            log::info!("Replayed {} batches from {}", replayed, self.path.display());
+        }
+        self.flush()
+    }
+}
+
+/// Batch at the start of `bytes` and the bytes following it, if its record
+/// is complete and intact
+fn read_record(bytes: &[u8]) -> Option<(WriteBatch, &[u8])> {
+    let len = u32::from_be_bytes(bytes.get(..4)?.try_into().ok()?) as usize;
+    let checksum = bytes.get(4..HEADER_LEN)?;
+    let payload = bytes.get(HEADER_LEN..HEADER_LEN + len)?;
+    if Hash::new(payload).as_ref() != checksum {
+        return None;
+    }
+    let batch = bincode::deserialize(payload).ok()?;
+    Some((batch, &bytes[HEADER_LEN + len..]))
+}
+
+#[test]
+fn test_logged_batches_survive_a_crash() {
+    use crate::memory::MemoryStorage;
+
+    let path =
+        std::env::temp_dir().join(format!("dagchain-wal-{}", Hash::generate_random().to_hex()));
+    let key = |name: &str| Hash::new(name.as_bytes());
+    let storage = MemoryStorage::new(None).unwrap();
+    let mut wal = WalStorage::open(storage.open_tree("state").unwrap(), &path).unwrap();
+    let mut batch = WriteBatch::new();
+    let _ = batch.insert(key("a"), vec![1]).insert(key("b"), vec![2]);
+    wal.write_batch(batch.clone()).unwrap();
+    wal.flush().unwrap();
+    assert_eq!(wal.log_size().unwrap(), 0);
+
+    // Killed once the second batch is logged, before it is applied, and
+    // while a third is being logged
+    let mut decision = WriteBatch::new();
+    let _ = decision.insert(key("a"), vec![3]).insert(key("c"), vec![4]);
+    wal.append(&decision).unwrap();
+    let torn = std::fs::metadata(&path).unwrap().len();
+    wal.append(&batch).unwrap();
+    drop(wal);
+    let file = OpenOptions::new().write(true).open(&path).unwrap();
+    file.set_len(torn + HEADER_LEN as u64 + 2).unwrap();
+    assert!(storage.open_tree("state").unwrap().get(key("c")).is_err());
+
+    let wal = WalStorage::open(storage.open_tree("state").unwrap(), &path).unwrap();
+    assert_eq!(wal.get(key("a")).unwrap(), vec![3]);
+    assert_eq!(wal.get(key("b")).unwrap(), vec![2]);
+    assert_eq!(wal.get(key("c")).unwrap(), vec![4]);
+    assert_eq!(wal.log_size().unwrap(), 0);
+    std::fs::remove_file(&path).unwrap();
+}